@@ -0,0 +1,244 @@
+use std::time::{Duration, Instant};
+
+use async_std::task::block_on;
+use criterion::{black_box, criterion_group, Criterion};
+use flux_lsp::LspServer;
+use lspower::{lsp, LanguageServer};
+
+/// The number of pipelines in the large script.
+const PIPELINES: usize = 100;
+/// The number of files in the package.
+const PACKAGE_FILES: usize = 10;
+
+/// The median time a keystroke may take, i.e. changing the document and completing at
+/// the cursor, before the budget check fails.
+const SCRIPT_BUDGET: Duration = Duration::from_millis(250);
+const PACKAGE_BUDGET: Duration = Duration::from_millis(500);
+/// The number of keystrokes timed to find the median.
+const BUDGET_SAMPLES: usize = 21;
+
+const PIPELINE: &str = r#"
+errorCounts{} = from(bucket: "kube-infra/monthly")
+    |> range(start: -3d)
+    |> filter(fn: (r) => r._measurement == "query_log" and r.env == env)
+    |> group(columns: ["env", "error"])
+    |> aggregateWindow(every: 1h, fn: count)
+    |> map(fn: (r) => ({r with message: strings.toUpper(v: r.error)}))
+    |> yield(name: "errors{}")
+"#;
+
+/// A position in a document to complete at, with the text typed there.
+struct Cursor {
+    name: &'static str,
+    /// The text of the line being typed, which is appended to the document.
+    line: &'static str,
+    trigger_character: Option<&'static str>,
+}
+
+const CURSORS: &[Cursor] = &[
+    Cursor {
+        name: "identifier",
+        line: "err",
+        trigger_character: None,
+    },
+    Cursor {
+        name: "package member",
+        line: "strings.",
+        trigger_character: Some("."),
+    },
+    Cursor {
+        name: "call arguments",
+        line: "range(",
+        trigger_character: Some("("),
+    },
+    Cursor {
+        name: "pipeline stage",
+        line: "errorCounts0 |> filter(fn: (r) => r.",
+        trigger_character: Some("."),
+    },
+];
+
+/// A script of many pipelines, like dashboards and tasks grow into.
+fn large_script() -> String {
+    let mut script = String::from(
+        "import \"strings\"\n\nenv = \"prod01-us-west-2\"\n",
+    );
+    for index in 0..PIPELINES {
+        script.push_str(&PIPELINE.replace("{}", &index.to_string()));
+    }
+    script
+}
+
+fn url(name: &str) -> lsp::Url {
+    lsp::Url::parse(&format!("file:///home/user/package/{}", name))
+        .unwrap()
+}
+
+/// A server with the files of a package open, returning the url of the file being
+/// typed in and its contents.
+fn open_package(files: usize) -> (LspServer, lsp::Url, String) {
+    let server = LspServer::new(None);
+    let script = large_script();
+    for index in 1..files {
+        let params = lsp::DidOpenTextDocumentParams {
+            text_document: lsp::TextDocumentItem::new(
+                url(&format!("file{}.flux", index)),
+                "flux".to_string(),
+                1,
+                format!(
+                    "package main\n\n{}",
+                    script.replace(
+                        "errorCounts",
+                        &format!("file{}Counts", index)
+                    )
+                ),
+            ),
+        };
+        block_on(server.did_open(params));
+    }
+    let uri = url("file0.flux");
+    let text = format!("package main\n\n{}", script);
+    block_on(server.did_open(lsp::DidOpenTextDocumentParams {
+        text_document: lsp::TextDocumentItem::new(
+            uri.clone(),
+            "flux".to_string(),
+            1,
+            text.clone(),
+        ),
+    }));
+    (server, uri, text)
+}
+
+/// Type a line at the end of the document and complete at the end of it.
+///
+/// Every keystroke changes the document, so the package is analyzed again, like it
+/// is while typing in an editor.
+fn keystroke(
+    server: &LspServer,
+    uri: &lsp::Url,
+    text: &str,
+    cursor: &Cursor,
+    version: i32,
+) {
+    // Alternate the typed text, so that each keystroke is a change.
+    let line = if version % 2 == 0 {
+        cursor.line.to_string()
+    } else {
+        format!(" {}", cursor.line)
+    };
+    let contents = format!("{}\n{}", text, line);
+    block_on(server.did_change(lsp::DidChangeTextDocumentParams {
+        text_document: lsp::VersionedTextDocumentIdentifier {
+            uri: uri.clone(),
+            version,
+        },
+        content_changes: vec![lsp::TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: contents.clone(),
+        }],
+    }));
+
+    let params = lsp::CompletionParams {
+        text_document_position: lsp::TextDocumentPositionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: uri.clone(),
+            },
+            position: lsp::Position {
+                line: contents.lines().count() as u32 - 1,
+                character: line.len() as u32,
+            },
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+        context: Some(lsp::CompletionContext {
+            trigger_kind: match cursor.trigger_character {
+                Some(_) => {
+                    lsp::CompletionTriggerKind::TRIGGER_CHARACTER
+                }
+                None => lsp::CompletionTriggerKind::INVOKED,
+            },
+            trigger_character: cursor
+                .trigger_character
+                .map(String::from),
+        }),
+    };
+    let _ = block_on(black_box(server.completion(params)));
+}
+
+/// Benchmark completion at the cursors in a large script, and in a file of a package.
+fn completion(c: &mut Criterion) {
+    for (files, scenario) in
+        [(1, "script"), (PACKAGE_FILES, "package")]
+    {
+        let (server, uri, text) = open_package(files);
+        for cursor in CURSORS {
+            let mut version = 1;
+            c.bench_function(
+                &format!("completion {} {}", scenario, cursor.name),
+                |b| {
+                    b.iter(|| {
+                        version += 1;
+                        keystroke(
+                            &server, &uri, &text, cursor, version,
+                        );
+                    })
+                },
+            );
+        }
+    }
+}
+
+/// Fail when the median keystroke at any cursor is over budget.
+///
+/// Criterion reports regressions but doesn't fail on them, so this keeps `cargo bench`
+/// failing in CI when completion gets too slow to type with.
+fn check_budget() {
+    let mut over = vec![];
+    for (files, scenario, budget) in [
+        (1, "script", SCRIPT_BUDGET),
+        (PACKAGE_FILES, "package", PACKAGE_BUDGET),
+    ] {
+        let (server, uri, text) = open_package(files);
+        for cursor in CURSORS {
+            let mut samples: Vec<Duration> = (0..BUDGET_SAMPLES)
+                .map(|sample| {
+                    let start = Instant::now();
+                    keystroke(
+                        &server,
+                        &uri,
+                        &text,
+                        cursor,
+                        sample as i32 + 2,
+                    );
+                    start.elapsed()
+                })
+                .collect();
+            samples.sort_unstable();
+            let median = samples[samples.len() / 2];
+            if median > budget {
+                over.push(format!(
+                    "completion {} {}: {:?} (budget {:?})",
+                    scenario, cursor.name, median, budget
+                ));
+            }
+        }
+    }
+    assert!(
+        over.is_empty(),
+        "Completion is over budget:\n{}",
+        over.join("\n")
+    );
+}
+
+criterion_group!(benches, completion);
+
+fn main() {
+    benches();
+    Criterion::default().configure_from_args().final_summary();
+    check_budget();
+}
@@ -113,6 +113,9 @@ describe('LSP Server', () => {
         const diagnostics = [];
         const callback = jest.fn((message) => {
             const diagnosticMessage = JSON.parse(message);
+            if (diagnosticMessage.method !== 'textDocument/publishDiagnostics') {
+                return;
+            }
             diagnosticMessage.params.diagnostics.forEach(diagnostic => {
                 diagnostics.push(diagnostic);
             });
@@ -128,7 +131,7 @@ describe('LSP Server', () => {
 
         expect(callback).toHaveBeenCalled();
         expect(diagnostics.length).toBe(1);
-        expect(diagnostics[0]).toStrictEqual({"message": "undefined identifier x", "range": {"end": {"character": 14, "line": 0}, "start": {"character": 13, "line": 0}}, "severity": 1, "source": "flux"});
+        expect(diagnostics[0]).toStrictEqual({"message": "undefined identifier x", "range": {"end": {"character": 14, "line": 0}, "start": {"character": 13, "line": 0}}, "severity": 1, "code": "type-error", "source": "flux"});
     });
 });
 
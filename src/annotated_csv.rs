@@ -0,0 +1,190 @@
+/// Reading of annotated CSV, the format of query results and of `csv.from` test data
+///
+/// Only the shape of the tables is read: the columns with their types and whether
+/// they are part of the group key, along with the number of rows.
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Column {
+    pub name: String,
+    /// The flux type of the `#datatype` annotation of the column.
+    pub typ: &'static str,
+    pub group: bool,
+}
+
+/// The shape of the tables following a header row.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct TableSchema {
+    pub columns: Vec<Column>,
+    pub rows: usize,
+}
+
+/// Split a line of CSV into its fields.
+pub(crate) fn fields(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// The flux type of values of an annotated CSV datatype.
+fn flux_type(datatype: &str) -> &'static str {
+    match datatype.split(':').next() {
+        Some("long") => "int",
+        Some("unsignedLong") => "uint",
+        Some("double") => "float",
+        Some("boolean") => "bool",
+        Some("dateTime") => "time",
+        Some("duration") => "duration",
+        _ => "string",
+    }
+}
+
+/// The schema of every header row of annotated CSV, with the annotations preceding it.
+///
+/// Columns without a `#datatype` annotation are strings, and those without a `#group`
+/// annotation aren't part of the group key.
+pub(crate) fn table_schemas(csv: &str) -> Vec<TableSchema> {
+    let mut schemas = vec![];
+    let mut annotations: HashMap<String, Vec<String>> =
+        HashMap::new();
+    let mut current: Option<TableSchema> = None;
+    for line in csv.lines().map(|line| line.trim_end_matches('\r')) {
+        if line.trim().is_empty() {
+            schemas.extend(current.take());
+            annotations.clear();
+            continue;
+        }
+        let mut fields = fields(line);
+        if let Some(annotation) = fields[0].strip_prefix('#') {
+            schemas.extend(current.take());
+            annotations
+                .insert(annotation.into(), fields.split_off(1));
+            continue;
+        }
+        match &mut current {
+            Some(schema) => schema.rows += 1,
+            None => {
+                // The first column is the annotation column, which is empty outside
+                // of annotations.
+                if fields[0].is_empty() {
+                    fields.remove(0);
+                }
+                let annotation = |name: &str, index: usize| {
+                    annotations
+                        .get(name)
+                        .and_then(|values| values.get(index))
+                        .map(String::as_str)
+                };
+                current = Some(TableSchema {
+                    columns: fields
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, name)| Column {
+                            name,
+                            typ: flux_type(
+                                annotation("datatype", index)
+                                    .unwrap_or("string"),
+                            ),
+                            group: annotation("group", index)
+                                == Some("true"),
+                        })
+                        .collect(),
+                    rows: 0,
+                });
+            }
+        }
+    }
+    schemas.extend(current);
+    schemas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schemas_of_annotated_csv() {
+        let csv =
+            "#datatype,string,long,dateTime:RFC3339,double,string
+#group,false,false,false,false,true
+#default,_result,,,,
+,result,table,_time,_value,host
+,,0,2022-01-01T00:00:00Z,1.5,a
+,,1,2022-01-01T00:00:00Z,2.5,b
+
+#datatype,string,long,boolean
+,result,table,ok
+,,0,true
+";
+
+        assert_eq!(
+            vec![
+                TableSchema {
+                    columns: vec![
+                        Column {
+                            name: "result".into(),
+                            typ: "string",
+                            group: false,
+                        },
+                        Column {
+                            name: "table".into(),
+                            typ: "int",
+                            group: false,
+                        },
+                        Column {
+                            name: "_time".into(),
+                            typ: "time",
+                            group: false,
+                        },
+                        Column {
+                            name: "_value".into(),
+                            typ: "float",
+                            group: false,
+                        },
+                        Column {
+                            name: "host".into(),
+                            typ: "string",
+                            group: true,
+                        },
+                    ],
+                    rows: 2,
+                },
+                TableSchema {
+                    columns: vec![
+                        Column {
+                            name: "result".into(),
+                            typ: "string",
+                            group: false,
+                        },
+                        Column {
+                            name: "table".into(),
+                            typ: "int",
+                            group: false,
+                        },
+                        Column {
+                            name: "ok".into(),
+                            typ: "bool",
+                            group: false,
+                        },
+                    ],
+                    rows: 1,
+                },
+            ],
+            table_schemas(csv)
+        );
+    }
+}
@@ -1,20 +1,36 @@
 #![allow(clippy::unwrap_used)]
 use std::fs::OpenOptions;
+use std::io;
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::{Context, Poll};
 
 use clap::Parser;
-use lspower::{LspService, Server};
+use lspower::{Client, LspService, Server};
 use simplelog::{
     CombinedLogger, Config, LevelFilter, SimpleLogger, WriteLogger,
 };
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::{TcpListener, UnixListener};
 
-use flux_lsp::LspServer;
+use flux_lsp::{
+    Direction, DisabledCapabilities, Framer, LspServer, Normalized,
+    Trace,
+};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
     #[clap(long, short, help = "Path to write a debug log file")]
     log_file: Option<String>,
+    #[clap(
+        long,
+        help = "Path to append every message to and from clients to, as JSON lines"
+    )]
+    trace_file: Option<String>,
     #[clap(
         long,
         help = "I/O communication channel to use, stdin, tcp, unix (defaults to \"stdin\")"
@@ -22,14 +38,220 @@ struct Args {
     channel: Option<String>,
     #[clap(
         long,
-        help = "TCP address to bind when channel is \"tcp\" (defaults to :5001)"
+        help = "TCP address to bind when channel is \"tcp\" (defaults to 127.0.0.1:5001). Only loopback addresses are accepted without --allow-remote"
     )]
     addr: Option<String>,
+    #[clap(
+        long,
+        help = "Accept tcp addresses other than loopback ones. The server doesn't authenticate clients, so anyone able to connect gets a server of their own, which reads files and runs tests on this host"
+    )]
+    allow_remote: bool,
     #[clap(
         long,
         help = "Path to unix socket when channel is \"unix\" (defaults to /tmp/flux-lsp-sock.unix)"
     )]
     path: Option<String>,
+    #[clap(
+        long,
+        help = "Keep accepting connections on the tcp or unix channel, serving each client concurrently"
+    )]
+    daemon: bool,
+    #[clap(
+        long,
+        help = "Path to persist the index of workspace symbols to between sessions"
+    )]
+    symbol_index: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "The flux command line tool to run tests with (defaults to \"flux\"). Over stdin, clients may pick another with the fluxCommand setting, over tcp and unix sockets they can't"
+    )]
+    flux_command: Option<String>,
+    #[clap(long, help = "Disable folding ranges")]
+    disable_folding: bool,
+    #[clap(long, help = "Disable semantic tokens")]
+    disable_semantic_tokens: bool,
+    #[clap(long, help = "Disable code actions")]
+    disable_code_actions: bool,
+    #[clap(
+        long,
+        help = "Disable the execution of commands, along with the code lenses running them"
+    )]
+    disable_execute_commands: bool,
+}
+
+/// How the server of each client is set up, from the arguments.
+#[derive(Clone)]
+struct ServerConfig {
+    symbol_index: Option<PathBuf>,
+    disabled_capabilities: DisabledCapabilities,
+    /// The flux command tests are run with, which clients can't change when set.
+    flux_command: Option<String>,
+}
+
+impl ServerConfig {
+    fn server(self, client: Client) -> LspServer {
+        let mut server = LspServer::new(Some(client))
+            .with_disabled_capabilities(self.disabled_capabilities);
+        if let Some(command) = self.flux_command {
+            server = server.with_flux_command(command);
+        }
+        match self.symbol_index {
+            Some(path) => server.with_symbol_index(path),
+            None => server,
+        }
+    }
+}
+
+/// A reader or writer recording the messages passing through it in a trace.
+struct Traced<T> {
+    inner: T,
+    direction: Direction,
+    framer: Framer,
+    trace: Arc<Trace>,
+}
+
+impl<T> Traced<T> {
+    fn new(
+        inner: T,
+        direction: Direction,
+        trace: Arc<Trace>,
+    ) -> Self {
+        Self {
+            inner,
+            direction,
+            framer: Framer::default(),
+            trace,
+        }
+    }
+
+    fn observe(&mut self, bytes: &[u8]) {
+        for message in self.framer.push(bytes) {
+            self.trace.record(self.direction, &message);
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Traced<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            this.observe(&buf.filled()[filled..]);
+        }
+        poll
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Traced<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            this.observe(&buf[..written]);
+        }
+        poll
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Serve a single client over the provided reader and writer.
+///
+/// Each client gets its own `LspServer` instance. The flux stdlib and prelude are
+/// process-wide statics, so clients in the same process share them and only the
+/// first client pays the cost of loading them.
+///
+/// The messages of the client are reframed first, as some clients don't frame them
+/// quite as the protocol specifies. The messages exchanged are recorded when there is
+/// a trace.
+///
+/// The symbol index is loaded from and saved to its path when there is one. Clients of
+/// a daemon share it, each saving what it indexed last.
+///
+/// Returns true if the client requested a `shutdown` before the connection ended.
+async fn serve<I, O>(
+    read: I,
+    write: O,
+    trace: Option<Arc<Trace>>,
+    config: ServerConfig,
+) -> bool
+where
+    I: AsyncRead + Send + Unpin + 'static,
+    O: AsyncWrite + Send + Unpin + 'static,
+{
+    let mut shutdown_flag = None;
+    let (service, messages) = LspService::new(|client| {
+        let server = config.server(client);
+        shutdown_flag = Some(server.shutdown_flag());
+        server
+    });
+    match trace {
+        Some(trace) => {
+            Server::new(
+                Traced::new(
+                    Normalized::new(read),
+                    Direction::In,
+                    trace.clone(),
+                ),
+                Traced::new(write, Direction::Out, trace),
+            )
+            .interleave(messages)
+            .serve(service)
+            .await
+        }
+        None => {
+            Server::new(Normalized::new(read), write)
+                .interleave(messages)
+                .serve(service)
+                .await
+        }
+    }
+
+    shutdown_flag
+        .map(|flag| flag.load(Ordering::SeqCst))
+        .unwrap_or(false)
+}
+
+/// Whether a tcp address only resolves to loopback addresses, which only clients on
+/// this host can connect to.
+fn is_loopback(addr: &str) -> bool {
+    addr.to_socket_addrs().map_or(false, |mut addrs| {
+        addrs.all(|addr| addr.ip().is_loopback())
+    })
+}
+
+/// Exit the process once a single client is done.
+///
+/// Per the protocol, an `exit` notification (or the client going away) after a
+/// `shutdown` request exits with 0, and with 1 if no `shutdown` was received.
+fn exit(clean_shutdown: bool) -> ! {
+    if clean_shutdown {
+        log::debug!("Exiting after shutdown request");
+        std::process::exit(0);
+    }
+    log::error!("Exiting without a shutdown request");
+    std::process::exit(1);
 }
 
 #[tokio::main]
@@ -49,18 +271,53 @@ async fn main() {
         .unwrap();
     }
 
-    let (service, messages) =
-        LspService::new(|client| LspServer::new(Some(client)));
+    let trace = matches.trace_file.map(|trace_path| {
+        Arc::new(Trace::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(trace_path)
+                .unwrap(),
+        ))
+    });
+
+    let mut config = ServerConfig {
+        symbol_index: matches.symbol_index,
+        disabled_capabilities: DisabledCapabilities {
+            folding: matches.disable_folding,
+            semantic_tokens: matches.disable_semantic_tokens,
+            code_actions: matches.disable_code_actions,
+            execute_commands: matches.disable_execute_commands,
+        },
+        flux_command: matches.flux_command,
+    };
 
     let channel =
         matches.channel.unwrap_or_else(|| "stdio".to_string());
+    // Anyone able to connect to a socket could otherwise run any executable with
+    // the `fluxCommand` setting.
+    if channel != "stdio" {
+        config
+            .flux_command
+            .get_or_insert_with(|| "flux".to_string());
+    }
     match channel.as_str() {
+        #[allow(clippy::print_stderr)]
+        "stdio" if matches.daemon => {
+            eprintln!("Daemon mode requires the tcp or unix channel");
+            std::process::exit(1);
+        }
         "stdio" => {
             log::debug!("Communicating using stdin/stdout");
-            Server::new(tokio::io::stdin(), tokio::io::stdout())
-                .interleave(messages)
-                .serve(service)
-                .await;
+            exit(
+                serve(
+                    tokio::io::stdin(),
+                    tokio::io::stdout(),
+                    trace,
+                    config,
+                )
+                .await,
+            );
         }
         "tcp" => {
             SimpleLogger::init(LevelFilter::Debug, Config::default())
@@ -68,6 +325,26 @@ async fn main() {
             let addr = matches
                 .addr
                 .unwrap_or_else(|| "127.0.0.1:5001".to_string());
+            if !is_loopback(&addr) {
+                if !matches.allow_remote {
+                    #[allow(clippy::print_stderr)]
+                    {
+                        eprintln!(
+                            "Refusing to listen on {}, which isn't a \
+                             loopback address. Clients aren't \
+                             authenticated, pass --allow-remote to \
+                             listen on it anyway",
+                            addr
+                        );
+                    }
+                    std::process::exit(1);
+                }
+                log::warn!(
+                    "Listening on {}, clients of other hosts can \
+                     connect without authentication",
+                    addr
+                );
+            }
             log::debug!("Communicating on tcp socket {}", addr);
             let listener = match TcpListener::bind(&addr).await {
                 Ok(listener) => listener,
@@ -76,12 +353,37 @@ async fn main() {
                     std::process::exit(1);
                 }
             };
-            let (stream, _) = listener.accept().await.unwrap();
-            let (read, write) = tokio::io::split(stream);
-            Server::new(read, write)
-                .interleave(messages)
-                .serve(service)
-                .await;
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(err) => {
+                        log::error!(
+                            "Failed to accept client: {}",
+                            err
+                        );
+                        continue;
+                    }
+                };
+                log::debug!("Accepted client {}", peer);
+                let (read, write) = tokio::io::split(stream);
+                if !matches.daemon {
+                    exit(
+                        serve(
+                            read,
+                            write,
+                            trace.clone(),
+                            config.clone(),
+                        )
+                        .await,
+                    );
+                }
+                let trace = trace.clone();
+                let config = config.clone();
+                tokio::spawn(async move {
+                    serve(read, write, trace, config).await;
+                    log::debug!("Client {} disconnected", peer);
+                });
+            }
         }
         "unix" => {
             SimpleLogger::init(LevelFilter::Debug, Config::default())
@@ -97,12 +399,37 @@ async fn main() {
                     std::process::exit(1);
                 }
             };
-            let (stream, _) = listener.accept().await.unwrap();
-            let (read, write) = tokio::io::split(stream);
-            Server::new(read, write)
-                .interleave(messages)
-                .serve(service)
-                .await;
+            loop {
+                let (stream, _) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(err) => {
+                        log::error!(
+                            "Failed to accept client: {}",
+                            err
+                        );
+                        continue;
+                    }
+                };
+                log::debug!("Accepted client on {}", path);
+                let (read, write) = tokio::io::split(stream);
+                if !matches.daemon {
+                    exit(
+                        serve(
+                            read,
+                            write,
+                            trace.clone(),
+                            config.clone(),
+                        )
+                        .await,
+                    );
+                }
+                let trace = trace.clone();
+                let config = config.clone();
+                tokio::spawn(async move {
+                    serve(read, write, trace, config).await;
+                    log::debug!("Client disconnected");
+                });
+            }
         }
         #[allow(clippy::print_stderr)]
         _ => {
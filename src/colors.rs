@@ -0,0 +1,162 @@
+/// Colors of the hex color strings in records, for editors to show swatches of
+///
+/// Dashboards define the colors of maps and thresholds in records, under properties
+/// like `color`, `colors` or `thresholdColors`. String literals in the values of such
+/// properties, including in arrays and nested records, that are `#rgb`, `#rgba`,
+/// `#rrggbb` or `#rrggbbaa` colors are reported. Their range is the contents of the
+/// string, so that a color picked in the editor replaces it within the quotes.
+use flux::ast::{self, walk};
+use lspower::lsp;
+
+use crate::convert;
+use crate::visitors::ast::property_name;
+
+/// Whether the values of a property are colors, judging by its name.
+fn is_color_property(name: &str) -> bool {
+    name.to_lowercase().contains("color")
+}
+
+/// The color of a hex color string.
+fn parse_color(value: &str) -> Option<lsp::Color> {
+    let hex = value.strip_prefix('#')?;
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |digits: &str| {
+        u8::from_str_radix(digits, 16)
+            .ok()
+            .map(|channel| f32::from(channel) / 255.0)
+    };
+    let channels: Vec<f32> = match hex.len() {
+        3 | 4 => hex
+            .chars()
+            .map(|digit| channel(&format!("{}{}", digit, digit)))
+            .collect::<Option<_>>()?,
+        6 | 8 => (0..hex.len())
+            .step_by(2)
+            .map(|start| channel(&hex[start..start + 2]))
+            .collect::<Option<_>>()?,
+        _ => return None,
+    };
+    Some(lsp::Color {
+        red: channels[0],
+        green: channels[1],
+        blue: channels[2],
+        alpha: channels.get(3).copied().unwrap_or(1.0),
+    })
+}
+
+/// The hex color string of a color, without the alpha channel when it's opaque.
+pub(crate) fn presentation(color: &lsp::Color) -> String {
+    let channel =
+        |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let mut hex = format!(
+        "#{:02x}{:02x}{:02x}",
+        channel(color.red),
+        channel(color.green),
+        channel(color.blue)
+    );
+    if channel(color.alpha) != u8::MAX {
+        hex.push_str(&format!("{:02x}", channel(color.alpha)));
+    }
+    hex
+}
+
+/// Collect the color strings of the value of a color property.
+fn collect_colors(
+    expression: &ast::Expression,
+    colors: &mut Vec<lsp::ColorInformation>,
+) {
+    match expression {
+        ast::Expression::StringLit(lit) => {
+            // Only single line strings can be colors.
+            let range = match convert::location_to_inner_range(
+                &lit.base.location,
+            ) {
+                Some(range) => range,
+                None => return,
+            };
+            if let Some(color) = parse_color(&lit.value) {
+                colors.push(lsp::ColorInformation { range, color });
+            }
+        }
+        ast::Expression::Array(array) => {
+            for item in &array.elements {
+                collect_colors(&item.expression, colors);
+            }
+        }
+        ast::Expression::Object(object) => {
+            for property in &object.properties {
+                if let Some(value) = &property.value {
+                    collect_colors(value, colors);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[derive(Default)]
+struct ColorVisitor {
+    colors: Vec<lsp::ColorInformation>,
+}
+
+impl<'a> walk::Visitor<'a> for ColorVisitor {
+    fn visit(&mut self, node: walk::Node<'a>) -> bool {
+        if let walk::Node::Property(property) = node {
+            if is_color_property(property_name(&property.key)) {
+                if let Some(value) = &property.value {
+                    collect_colors(value, &mut self.colors);
+                }
+                // Colors in the value are collected already.
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The colors of a file, in the order they are written.
+pub(crate) fn document_colors(
+    file: &ast::File,
+) -> Vec<lsp::ColorInformation> {
+    let mut visitor = ColorVisitor::default();
+    walk::walk(&mut visitor, walk::Node::File(file));
+    visitor.colors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_of_color_properties() {
+        let fluxscript = r##"option dashboard = {
+    colors: ["#f00", "#22ADF6", "red"],
+    thresholds: [{value: 10, color: "#00ff0080"}],
+    label: "#abc",
+}
+"##;
+        let file = flux::parser::parse_string(
+            "dashboard.flux".into(),
+            fluxscript,
+        );
+
+        assert_eq!(
+            vec![
+                (1, 14, 18, "#ff0000".to_string()),
+                (1, 22, 29, "#22adf6".to_string()),
+                (2, 37, 46, "#00ff0080".to_string()),
+            ],
+            document_colors(&file)
+                .iter()
+                .map(|color| (
+                    color.range.start.line,
+                    color.range.start.character,
+                    color.range.end.character,
+                    presentation(&color.color)
+                ))
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,19 +1,23 @@
+#[cfg(feature = "docs")]
 use std::collections::BTreeMap;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
 
 use flux::ast::{Expression, PropertyKey};
 use flux::semantic::nodes::CallExpr;
 use flux::semantic::nodes::Expression as SemanticExpression;
 use flux::semantic::types::{
-    BuiltinType, CollectionType, MonoType, Record,
+    BuiltinType, CollectionType, Function, MonoType, Record,
 };
 use flux::semantic::walk::Visitor as SemanticVisitor;
 use lspower::lsp;
 
+use crate::convert;
 use crate::lang;
 use crate::visitors::semantic::{
     FunctionFinderVisitor, Import, ImportFinderVisitor,
-    ObjectFunctionFinderVisitor,
+    ObjectFunctionFinderVisitor, ScopedVariableFinderVisitor,
+    VariableScope,
 };
 
 pub fn get_imports(
@@ -26,6 +30,31 @@ pub fn get_imports(
     visitor.imports
 }
 
+/// Resolve the stdlib package an identifier refers to, e.g. the `s` in `s.toUpper`.
+///
+/// Imported packages are referred to by their alias, or by the last segment of their
+/// path (e.g. `statsmodels` for `contrib/anaisdg/statsmodels`). Packages that haven't
+/// been imported are resolved by name, preferring top level packages (e.g. `array`
+/// over `experimental/array`), unless they are imported under another alias.
+pub(crate) fn resolve_package(
+    imports: &[Import],
+    name: &str,
+) -> Option<lang::Package> {
+    if let Some(import) =
+        imports.iter().find(|import| import.name == name)
+    {
+        return lang::STDLIB.package(&import.path);
+    }
+    let not_aliased = |package: &lang::Package| {
+        !imports.iter().any(|import| import.path == package.path)
+    };
+    lang::STDLIB.package(name).filter(not_aliased).or_else(|| {
+        lang::STDLIB.packages().find(|package| {
+            package.name == name && not_aliased(package)
+        })
+    })
+}
+
 //Given a list of functions, filter the functions by name, and then flat map
 // the function parameters
 fn get_function_params<'a>(
@@ -64,6 +93,8 @@ pub(crate) fn walk_package(
                 MonoType::Fun(f) => {
                     list.push(Box::new(FunctionResult {
                         name: head.k.clone().to_string(),
+                        package: package.into(),
+                        function: f.as_ref().clone(),
                         signature: create_function_signature(f),
                     }));
                 }
@@ -92,6 +123,363 @@ pub(crate) trait Completable {
         &self,
         imports: &[Import],
     ) -> lsp::CompletionItem;
+
+    /// Documentation for the completion item, for clients that support markdown.
+    fn markdown_documentation(&self) -> lsp::MarkupContent;
+}
+
+#[cfg(feature = "docs")]
+const DOCS_URL: &str = "https://docs.influxdata.com/flux/v0.x/stdlib";
+
+/// Link to the documentation of a member of a stdlib package.
+#[cfg(feature = "docs")]
+pub(crate) fn docs_url(package: &str, name: &str) -> String {
+    format!("{}/{}/{}/", DOCS_URL, package, name.to_lowercase())
+}
+
+/// Link to the documentation of a stdlib package.
+#[cfg(feature = "docs")]
+pub(crate) fn package_docs_url(package: &str) -> String {
+    format!("{}/{}/", DOCS_URL, package)
+}
+
+/// Markdown documentation for a function, with its signature and parameters.
+///
+/// Functions that aren't part of a stdlib package (i.e. defined in the script itself)
+/// have no `package` and don't get a link to the docs. Builds without the `docs`
+/// feature only document the signature.
+pub(crate) fn function_markdown(
+    name: &str,
+    f: &Function,
+    package: Option<&str>,
+) -> lsp::MarkupContent {
+    let mut sections = vec![format!(
+        "```flux\n{}{}\n```",
+        name,
+        create_function_signature(f)
+    )];
+    sections.extend(function_docs(name, f, package));
+
+    lsp::MarkupContent {
+        kind: lsp::MarkupKind::Markdown,
+        value: sections.join("\n\n"),
+    }
+}
+
+/// The sections documenting a function after its signature: its parameters and where
+/// it comes from.
+#[cfg(feature = "docs")]
+fn function_docs(
+    name: &str,
+    f: &Function,
+    package: Option<&str>,
+) -> Vec<String> {
+    let mut sections = vec![];
+    let pipe = f
+        .pipe
+        .iter()
+        .map(|pipe| format!("- `{}` (pipe): `{}`", pipe.k, pipe.v));
+    let required = f
+        .req
+        .iter()
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .map(|(k, v)| format!("- `{}` (required): `{}`", k, v));
+    let optional =
+        f.opt.iter().collect::<BTreeMap<_, _>>().into_iter().map(
+            |(k, v)| format!("- `{}` (optional): `{}`", k, v.typ),
+        );
+    let parameters: Vec<String> =
+        pipe.chain(required).chain(optional).collect();
+    if !parameters.is_empty() {
+        sections.push(format!(
+            "**Parameters**\n\n{}",
+            parameters.join("\n")
+        ));
+    }
+
+    sections.push(package_markdown(name, package));
+    sections
+}
+
+#[cfg(not(feature = "docs"))]
+fn function_docs(
+    _name: &str,
+    _f: &Function,
+    _package: Option<&str>,
+) -> Vec<String> {
+    vec![]
+}
+
+/// Markdown documentation for a variable.
+pub(crate) fn variable_markdown(
+    name: &str,
+    detail: &str,
+    package: Option<&str>,
+) -> lsp::MarkupContent {
+    let mut sections =
+        vec![format!("```flux\n{}: {}\n```", name, detail)];
+    sections.extend(variable_docs(name, package));
+
+    lsp::MarkupContent {
+        kind: lsp::MarkupKind::Markdown,
+        value: sections.join("\n\n"),
+    }
+}
+
+/// The sections documenting a variable after its type: where it comes from.
+#[cfg(feature = "docs")]
+fn variable_docs(name: &str, package: Option<&str>) -> Vec<String> {
+    vec![package_markdown(name, package)]
+}
+
+#[cfg(not(feature = "docs"))]
+fn variable_docs(_name: &str, _package: Option<&str>) -> Vec<String> {
+    vec![]
+}
+
+#[cfg(feature = "docs")]
+fn package_markdown(name: &str, package: Option<&str>) -> String {
+    match package {
+        Some(package) => format!(
+            "from `{}`\n\n[View documentation]({})",
+            package,
+            docs_url(package, name)
+        ),
+        None => "from self".into(),
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref PRELUDE_ITEMS: Vec<CachedItem> = lang::UNIVERSE
+        .exports
+        .iter()
+        .filter_map(|(key, val)| prelude_item(&key.to_string(), &val.expr))
+        .collect();
+    /// The items of the stdlib packages completed so far, by path.
+    static ref STDLIB_ITEMS: RwLock<HashMap<String, Arc<[CachedItem]>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// A completion item of the prelude or of a stdlib package, built once.
+///
+/// Formatting the signature and documentation of an item is the same work on every
+/// request, so items are built the first time they are needed and cloned after that.
+pub(crate) struct CachedItem {
+    /// The name of the item, lowercased for matching.
+    name: String,
+    item: lsp::CompletionItem,
+    /// The documentation of the item for clients that support markdown.
+    markdown: lsp::MarkupContent,
+}
+
+impl CachedItem {
+    fn new(completable: &dyn Completable) -> Self {
+        let item = completable.completion_item(&[]);
+        Self {
+            name: item
+                .filter_text
+                .as_deref()
+                .unwrap_or(&item.label)
+                .to_lowercase(),
+            markdown: completable.markdown_documentation(),
+            item,
+        }
+    }
+
+    /// Whether the name of the item contains `needle`, ignoring case.
+    pub(crate) fn matches(&self, needle: &str) -> bool {
+        self.name.contains(needle.to_lowercase().as_str())
+    }
+
+    pub(crate) fn completion_item(
+        &self,
+        markdown: bool,
+    ) -> lsp::CompletionItem {
+        let mut item = self.item.clone();
+        if markdown {
+            item.documentation =
+                Some(lsp::Documentation::MarkupContent(
+                    self.markdown.clone(),
+                ));
+        }
+        item
+    }
+}
+
+/// The item of a member of the prelude, for the members that can be completed.
+fn prelude_item(name: &str, typ: &MonoType) -> Option<CachedItem> {
+    // Don't allow users to "discover" private-ish functionality.
+    if name.starts_with('_') {
+        return None;
+    }
+    let (item, markdown) = match typ {
+        MonoType::Fun(function) => (
+            lsp::CompletionItem {
+                label: name.into(),
+                detail: Some(create_function_signature(function)),
+                filter_text: Some(name.into()),
+                insert_text_format: Some(
+                    lsp::InsertTextFormat::SNIPPET,
+                ),
+                kind: Some(lsp::CompletionItemKind::FUNCTION),
+                sort_text: Some(name.into()),
+                commit_characters: Some(vec!["(".into()]),
+                tags: lang::replacement_of("universe", name).map(
+                    |_| vec![lsp::CompletionItemTag::DEPRECATED],
+                ),
+                ..lsp::CompletionItem::default()
+            },
+            function_markdown(name, function, Some("universe")),
+        ),
+        MonoType::Builtin(builtin) => {
+            let detail: String = match *builtin {
+                BuiltinType::String => "String".into(),
+                BuiltinType::Int => "Integer".into(),
+                BuiltinType::Float => "Float".into(),
+                BuiltinType::Bool => "Boolean".into(),
+                BuiltinType::Bytes => "Bytes".into(),
+                BuiltinType::Duration => "Duration".into(),
+                BuiltinType::Uint => "Uint".into(),
+                BuiltinType::Regexp => "Regular Expression".into(),
+                BuiltinType::Time => "Time".into(),
+            };
+            (
+                lsp::CompletionItem {
+                    label: format!("{} ({})", name, "prelude"),
+                    documentation: Some(lsp::Documentation::String(
+                        "from prelude".into(),
+                    )),
+                    detail: Some(detail.clone()),
+                    filter_text: Some(name.into()),
+                    insert_text: Some(name.into()),
+                    insert_text_format: Some(
+                        lsp::InsertTextFormat::PLAIN_TEXT,
+                    ),
+                    kind: Some(lsp::CompletionItemKind::VARIABLE),
+                    sort_text: Some(format!("{} prelude", name)),
+                    ..lsp::CompletionItem::default()
+                },
+                variable_markdown(name, &detail, Some("universe")),
+            )
+        }
+        _ => return None,
+    };
+    Some(CachedItem {
+        name: name.to_lowercase(),
+        item,
+        markdown,
+    })
+}
+
+/// The items of the prelude that can be completed.
+pub(crate) fn prelude_items() -> &'static [CachedItem] {
+    &PRELUDE_ITEMS
+}
+
+/// The items of the members of a stdlib package.
+///
+/// The items of a package are only built when it's first completed, so the few
+/// packages a script uses don't pay for the hundreds in the stdlib.
+pub(crate) fn package_items(
+    package: &lang::Package,
+) -> Arc<[CachedItem]> {
+    if let Some(items) = STDLIB_ITEMS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&package.path)
+    {
+        return items.clone();
+    }
+
+    let mut list: Vec<Box<dyn Completable>> = vec![];
+    walk_package(
+        &package.path,
+        &mut list,
+        &package.exports.typ().expr,
+    );
+    let items: Arc<[CachedItem]> = list
+        .iter()
+        .map(|completable| CachedItem::new(completable.as_ref()))
+        .collect();
+    STDLIB_ITEMS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(package.path.clone(), items.clone());
+    items
+}
+
+/// The items of the variables in scope at a position of a file whose name contains
+/// `needle`, ignoring case.
+///
+/// Parameters and variables of the functions the position is in are ranked first,
+/// then the variables of the file, then everything else. A variable shadowing another
+/// is offered in its stead.
+pub(crate) fn scoped_items(
+    sem_pkg: &flux::semantic::nodes::Package,
+    file: Option<String>,
+    position: lsp::Position,
+    needle: &str,
+) -> Vec<lsp::CompletionItem> {
+    let visitor = crate::walk_semantic_package!(
+        ScopedVariableFinderVisitor::new(position, file),
+        sem_pkg
+    );
+    let needle = needle.to_lowercase();
+    let mut items: Vec<lsp::CompletionItem> = vec![];
+    for (name, typ, scope) in visitor.variables.into_iter().rev() {
+        let name = name.to_string();
+        if !name.to_lowercase().contains(&needle)
+            || items.iter().any(|item| item.label == name)
+        {
+            continue;
+        }
+        let tier = match scope {
+            VariableScope::Local => 0,
+            VariableScope::Global => 1,
+        };
+        let (kind, detail, commit_characters) = match &typ {
+            Some(MonoType::Fun(function)) => (
+                lsp::CompletionItemKind::FUNCTION,
+                Some(create_function_signature(function)),
+                Some(vec!["(".into()]),
+            ),
+            Some(typ) => (
+                lsp::CompletionItemKind::VARIABLE,
+                Some(typ.to_string()),
+                None,
+            ),
+            None => (lsp::CompletionItemKind::VARIABLE, None, None),
+        };
+        items.push(lsp::CompletionItem {
+            label: name.clone(),
+            detail,
+            filter_text: Some(name.clone()),
+            insert_text: Some(name.clone()),
+            insert_text_format: Some(
+                lsp::InsertTextFormat::PLAIN_TEXT,
+            ),
+            kind: Some(kind),
+            // Identifiers can't start with a digit, so these rank before any other
+            // item.
+            sort_text: Some(format!("{}{}", tier, name)),
+            commit_characters,
+            ..lsp::CompletionItem::default()
+        });
+    }
+    items
+}
+
+/// Load the stdlib and build the completion items of every package ahead of time.
+///
+/// Everything is otherwise loaded on first use, which keeps startup fast but makes the
+/// first completions slow. Embedders with time to spare after startup (e.g. the wasm
+/// build in a browser) can call this to warm up.
+pub fn preload() {
+    prelude_items();
+    for package in lang::STDLIB.packages() {
+        package_items(&package);
+    }
 }
 
 impl Completable for FunctionResult {
@@ -99,10 +487,11 @@ impl Completable for FunctionResult {
         &self,
         _imports: &[Import],
     ) -> lsp::CompletionItem {
+        // Only the name is inserted, so typing the parenthesis of the call commits it.
         lsp::CompletionItem {
             label: self.name.clone(),
             additional_text_edits: None,
-            commit_characters: None,
+            commit_characters: Some(vec!["(".into()]),
             deprecated: None,
             detail: Some(self.signature.clone()),
             documentation: None,
@@ -116,9 +505,27 @@ impl Completable for FunctionResult {
             command: None,
             data: None,
             insert_text_mode: None,
-            tags: None,
+            tags: lang::replacement_of(&self.package, &self.name)
+                .map(|_| vec![lsp::CompletionItemTag::DEPRECATED]),
         }
     }
+
+    fn markdown_documentation(&self) -> lsp::MarkupContent {
+        let mut markdown = function_markdown(
+            &self.name,
+            &self.function,
+            Some(&self.package),
+        );
+        if let Some(replacement) =
+            lang::replacement_of(&self.package, &self.name)
+        {
+            markdown.value = format!(
+                "**Deprecated**: use `{}` instead.\n\n{}",
+                replacement, markdown.value
+            );
+        }
+        markdown
+    }
 }
 
 impl Completable for CompletionVarResult {
@@ -150,6 +557,10 @@ impl Completable for CompletionVarResult {
             tags: None,
         }
     }
+
+    fn markdown_documentation(&self) -> lsp::MarkupContent {
+        variable_markdown(&self.name, &self.detail(), None)
+    }
 }
 
 pub fn get_var_type(
@@ -190,6 +601,7 @@ fn create_function_result(
         if let MonoType::Fun(fun) = &f.typ {
             return Some(UserFunctionResult {
                 name: name.into(),
+                function: fun.as_ref().clone(),
                 required_args: fun
                     .req
                     .keys()
@@ -216,16 +628,19 @@ fn follow_function_pipes(c: &CallExpr) -> &MonoType {
     &c.typ
 }
 
+/// Finds the members of the records assigned to a variable before a position.
 pub(crate) struct CompletableObjectFinderVisitor<'a> {
     name: &'a str,
+    pos: lsp::Position,
     pub completables: Vec<Arc<dyn Completable>>,
 }
 
 impl<'a> CompletableObjectFinderVisitor<'a> {
-    pub fn new(name: &'a str) -> Self {
+    pub fn new(name: &'a str, pos: lsp::Position) -> Self {
         CompletableObjectFinderVisitor {
             completables: Vec::new(),
             name,
+            pos,
         }
     }
 }
@@ -237,6 +652,11 @@ impl<'a> SemanticVisitor<'a> for CompletableObjectFinderVisitor<'_> {
     ) -> bool {
         let name = self.name;
 
+        // Records assigned after the position are undefined at it.
+        if convert::starts_after(node.loc(), &self.pos) {
+            return false;
+        }
+
         match node {
             flux::semantic::walk::Node::ObjectExpr(obj) => {
                 if let Some(ident) = &obj.with {
@@ -440,6 +860,14 @@ impl Completable for VarResult {
             tags: None,
         }
     }
+
+    fn markdown_documentation(&self) -> lsp::MarkupContent {
+        variable_markdown(
+            &self.name,
+            &self.detail(),
+            Some(&self.package),
+        )
+    }
 }
 
 impl CompletionVarResult {
@@ -465,6 +893,8 @@ impl CompletionVarResult {
 #[derive(Clone)]
 struct FunctionResult {
     name: String,
+    package: String,
+    function: Function,
     signature: String,
 }
 #[derive(Clone)]
@@ -500,6 +930,7 @@ impl From<BuiltinType> for VarType {
 #[derive(Clone)]
 struct UserFunctionResult {
     name: String,
+    function: Function,
     required_args: Vec<String>,
     optional_args: Vec<String>,
     signature: String,
@@ -557,6 +988,10 @@ impl Completable for UserFunctionResult {
             tags: None,
         }
     }
+
+    fn markdown_documentation(&self) -> lsp::MarkupContent {
+        function_markdown(&self.name, &self.function, None)
+    }
 }
 
 pub fn complete_call_expr(
@@ -627,7 +1062,9 @@ pub fn complete_call_expr(
                 if let Expression::Identifier(ident) = &me.object {
                     let object_functions: Vec<CompletionFunction> = {
                         let visitor = crate::walk_semantic_package!(
-                            ObjectFunctionFinderVisitor::default(),
+                            ObjectFunctionFinderVisitor::new(
+                                position
+                            ),
                             sem_pkg
                         );
                         visitor
@@ -648,7 +1085,10 @@ pub fn complete_call_expr(
                     let initial_params: Vec<(
                         String,
                         Option<MonoType>,
-                    )> = match lang::STDLIB.package(&ident.name) {
+                    )> = match resolve_package(
+                        &get_imports(sem_pkg),
+                        &ident.name,
+                    ) {
                         Some(package) => {
                             match package.function(key) {
                                 Some(function) => function
@@ -735,6 +1175,115 @@ pub fn complete_call_expr(
         .collect()
 }
 
+/// Collect the calls of a file.
+#[derive(Default)]
+struct CallCollectorVisitor<'a> {
+    calls: Vec<&'a flux::ast::CallExpr>,
+}
+
+impl<'a> flux::ast::walk::Visitor<'a> for CallCollectorVisitor<'a> {
+    fn visit(&mut self, node: flux::ast::walk::Node<'a>) -> bool {
+        if let flux::ast::walk::Node::CallExpr(call) = node {
+            self.calls.push(call);
+        }
+        true
+    }
+}
+
+/// The brackets left open in a source, along with their offsets, innermost last.
+///
+/// Brackets in strings and comments don't count. Sources ending within a string have
+/// none, as what is written there isn't code.
+fn open_brackets(source: &str) -> Option<Vec<(usize, char)>> {
+    let mut open = vec![];
+    let mut chars = source.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => loop {
+                match chars.next() {
+                    Some((_, '\\')) => {
+                        chars.next();
+                    }
+                    Some((_, '"')) => break,
+                    Some(_) => {}
+                    None => return None,
+                }
+            },
+            '/' if matches!(chars.peek(), Some((_, '/'))) => {
+                for (_, c) in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '(' | '[' | '{' => open.push((index, c)),
+            ')' | ']' | '}' => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+    Some(open)
+}
+
+/// The call the cursor is within the arguments of, in sources too broken mid-edit for
+/// it to be found at the cursor, e.g. while typing the next argument of
+/// `filter(fn: (r) => r._measurement == "cpu", `.
+///
+/// The source up to the cursor is parsed again, with a trailing comma dropped and the
+/// brackets left open closed. The call is the one whose parenthesis is the innermost
+/// left open.
+pub(crate) fn recover_call(
+    source: &str,
+    position: lsp::Position,
+) -> Option<flux::ast::CallExpr> {
+    let line_start =
+        *convert::line_starts(source).get(position.line as usize)?;
+    let line = source.get(line_start..)?.split('\n').next()?;
+    let cursor = line_start
+        + line
+            .char_indices()
+            .nth(position.character as usize)
+            .map_or(line.len(), |(index, _)| index);
+    let prefix = source.get(..cursor)?;
+
+    let open = open_brackets(prefix)?;
+    if !open.iter().any(|(_, c)| *c == '(') {
+        return None;
+    }
+    let mut text =
+        prefix.trim_end().trim_end_matches(',').to_string();
+    text.extend(open.iter().rev().map(|(_, c)| match c {
+        '(' => ')',
+        '[' => ']',
+        _ => '}',
+    }));
+
+    let file = flux::parser::parse_string("".into(), &text);
+    let mut visitor = CallCollectorVisitor::default();
+    flux::ast::walk::walk(
+        &mut visitor,
+        flux::ast::walk::Node::File(&file),
+    );
+    open.iter()
+        .rev()
+        .filter(|(_, c)| *c == '(')
+        .find_map(|(offset, _)| {
+            let before = &prefix[..*offset];
+            let line_start =
+                before.rfind('\n').map_or(0, |index| index + 1);
+            let paren = flux::ast::Position {
+                line: before.matches('\n').count() as u32 + 1,
+                column: (offset - line_start) as u32 + 1,
+            };
+            visitor
+                .calls
+                .iter()
+                .find(|call| call.callee.base().location.end == paren)
+        })
+        .map(|call| (*call).clone())
+}
+
 #[derive(Clone)]
 pub struct CompletionFunction {
     pub name: String,
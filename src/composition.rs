@@ -505,11 +505,87 @@ impl PartialEq for CompositionStatementAnalyzer {
 }
 impl Eq for CompositionStatementAnalyzer {}
 
-type CompositionResult = Result<(), ()>;
+type CompositionResult = Result<(), EditError>;
+
+/// Why a composition couldn't be edited.
+#[derive(Debug, PartialEq, Eq)]
+pub enum EditError {
+    /// The field or tag value is already part of the composition.
+    AlreadyPresent,
+    /// The field or tag value isn't part of the composition.
+    NotPresent,
+}
+
+impl std::fmt::Display for EditError {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            EditError::AlreadyPresent => {
+                write!(f, "already part of the composition")
+            }
+            EditError::NotPresent => {
+                write!(f, "not part of the composition")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+/// Why a composition couldn't re-attach to a new ast.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ResolveError {
+    /// More than one statement matches the composition.
+    Ambiguous,
+    /// No statement matches the composition, e.g. while it is being edited.
+    NotFound,
+}
+
+impl std::fmt::Display for ResolveError {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            ResolveError::Ambiguous => write!(
+                f,
+                "more than one statement matches the composition"
+            ),
+            ResolveError::NotFound => {
+                write!(f, "no statement matches the composition")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
 
 /// Composition acts as the public entry point into the composition functionality.
+///
+/// A composition is a query built from a bucket, a measurement, fields and tag
+/// values, as the query builder of the editor does. It is part of the public API of
+/// the crate, so that services can generate the same queries without running a
+/// server:
+///
+/// ```
+/// use flux_lsp::Composition;
+///
+/// let file = flux::parser::parse_string("query.flux".into(), "");
+/// let mut composition =
+///     Composition::new(file, "telegraf".into(), None, vec![], vec![]);
+/// composition.set_measurement("cpu".into()).unwrap();
+/// composition.add_field("usage_user".into()).unwrap();
+/// assert!(composition.to_string().contains(r#"r._field == "usage_user""#));
+/// ```
+///
+/// The methods public here follow the semver of the crate: their signatures, and the
+/// statement of the query they generate for the same inputs, only change in a major
+/// release. The formatting of the generated source follows the flux formatter, and
+/// may change whenever the version of flux does.
 #[derive(Clone)]
-pub(crate) struct Composition {
+pub struct Composition {
     file: ast::File,
 
     statement_index: usize,
@@ -524,7 +600,9 @@ impl ToString for Composition {
 }
 
 impl Composition {
-    pub(crate) fn new(
+    /// Add a composition of the `bucket` to the file, before its first expression
+    /// statement, or at its end.
+    pub fn new(
         mut file: ast::File,
         bucket: String,
         measurement: Option<String>,
@@ -629,35 +707,36 @@ impl Composition {
     /// is complex, but the core of the work is the analyzer, which checks
     /// all the statements to find the matching one.
     ///
-    /// In the event the composition can't re-attach to the new AST, usually
-    /// through ambiguity, an error is returned. The Composition should then
-    /// be discarded.
+    /// In the event the composition can't re-attach to the new AST, an error is
+    /// returned and the composition is left untouched. An ambiguous composition
+    /// should be discarded, while a missing statement may reappear with later edits.
     pub fn resolve_with_ast(
         &mut self,
         file: ast::File,
-    ) -> Result<(), ()> {
-        self.file = file;
-        let matches = self.find_matches_in_file(&self.file);
+    ) -> Result<(), ResolveError> {
+        let matches = self.find_matches_in_file(&file);
         if matches.len() > 1 {
             log::error!(
                 "Too many matches for composition statement."
             );
-            Err(())
+            Err(ResolveError::Ambiguous)
         } else {
             match matches.last() {
                 Some((index, analyzer)) => {
                     self.statement_index = *index;
                     self.analyzer = analyzer.clone();
+                    self.file = file;
                     Ok(())
                 }
                 None => {
                     log::error!("Could not find matching composition statement.");
-                    Err(())
+                    Err(ResolveError::NotFound)
                 }
             }
         }
     }
 
+    /// Whether the statement of the composition is in the file.
     pub fn exists_in(&self, file: &ast::File) -> bool {
         let matches = self.find_matches_in_file(file);
         !matches.is_empty() && matches.last().is_some()
@@ -689,7 +768,9 @@ impl Composition {
             .collect::<Vec<(usize, CompositionStatementAnalyzer)>>()
     }
 
-    pub(crate) fn set_measurement(
+    /// Filter the composition by the measurement, replacing the measurement it was
+    /// filtered by.
+    pub fn set_measurement(
         &mut self,
         measurement: String,
     ) -> CompositionResult {
@@ -698,12 +779,10 @@ impl Composition {
         Ok(())
     }
 
-    pub(crate) fn add_field(
-        &mut self,
-        field: String,
-    ) -> CompositionResult {
+    /// Filter the composition by one more field.
+    pub fn add_field(&mut self, field: String) -> CompositionResult {
         if self.analyzer.fields.contains(&field) {
-            return Err(());
+            return Err(EditError::AlreadyPresent);
         } else {
             self.analyzer.fields.push(field);
         }
@@ -711,27 +790,29 @@ impl Composition {
         Ok(())
     }
 
-    pub(crate) fn remove_field(
+    /// Stop filtering the composition by the field.
+    pub fn remove_field(
         &mut self,
         field: String,
     ) -> CompositionResult {
         if self.analyzer.fields.contains(&field) {
             self.analyzer.fields.retain(|f| f != &field);
         } else {
-            return Err(());
+            return Err(EditError::NotPresent);
         }
         self.sync();
         Ok(())
     }
 
-    pub(crate) fn add_tag_value(
+    /// Filter the composition by one more value of a tag.
+    pub fn add_tag_value(
         &mut self,
         tag_key: String,
         tag_value: String,
     ) -> CompositionResult {
         let tag_pair = (tag_key, tag_value);
         if self.analyzer.tag_values.contains(&tag_pair) {
-            return Err(());
+            return Err(EditError::AlreadyPresent);
         } else {
             self.analyzer.tag_values.push(tag_pair);
         }
@@ -739,7 +820,8 @@ impl Composition {
         Ok(())
     }
 
-    pub(crate) fn remove_tag_value(
+    /// Stop filtering the composition by the value of a tag.
+    pub fn remove_tag_value(
         &mut self,
         tag_key: String,
         tag_value: String,
@@ -748,7 +830,7 @@ impl Composition {
         if self.analyzer.tag_values.contains(&tag_pair) {
             self.analyzer.tag_values.retain(|p| !p.eq(&tag_pair));
         } else {
-            return Err(());
+            return Err(EditError::NotPresent);
         }
         self.sync();
         Ok(())
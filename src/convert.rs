@@ -0,0 +1,335 @@
+/// Conversions between flux source locations and lsp types.
+///
+/// Flux locations are 1-based in both line and column, while the LSP spec
+/// uses 0-based lines and characters. Mixing the two has been the source of
+/// several off-by-one bugs, so every conversion between them should go
+/// through this module rather than doing the arithmetic inline.
+use flux::ast::{Position, SourceLocation};
+use lspower::lsp;
+
+/// Convert a 1-based flux position into a 0-based lsp position.
+///
+/// Nodes synthesized by the server (e.g. `ast::BaseNode::default()`) carry
+/// a 0:0 position. Those are clamped to the start of the document rather
+/// than underflowing.
+pub(crate) fn position_to_lsp(position: &Position) -> lsp::Position {
+    lsp::Position {
+        line: position.line.saturating_sub(1),
+        character: position.column.saturating_sub(1),
+    }
+}
+
+/// Convert a 0-based lsp position into a 1-based flux position.
+pub(crate) fn position_to_flux(position: &lsp::Position) -> Position {
+    Position {
+        line: position.line + 1,
+        column: position.character + 1,
+    }
+}
+
+/// Convert a flux source location into an lsp range.
+pub(crate) fn location_to_range(
+    location: &SourceLocation,
+) -> lsp::Range {
+    lsp::Range {
+        start: position_to_lsp(&location.start),
+        end: position_to_lsp(&location.end),
+    }
+}
+
+/// The lsp range of the contents of a node between one character delimiters, e.g. a
+/// string literal without its quotes. Only nodes on a single line have one.
+pub(crate) fn location_to_inner_range(
+    location: &SourceLocation,
+) -> Option<lsp::Range> {
+    let range = location_to_range(location);
+    if range.start.line != range.end.line {
+        return None;
+    }
+    Some(lsp::Range {
+        start: lsp::Position {
+            line: range.start.line,
+            character: range.start.character + 1,
+        },
+        end: lsp::Position {
+            line: range.end.line,
+            character: range.end.character.saturating_sub(1),
+        },
+    })
+}
+
+/// The 0-based index of the line of a flux position, among the lines of its source.
+pub(crate) fn line_index(position: &Position) -> usize {
+    position_to_lsp(position).line as usize
+}
+
+/// The byte offsets the lines of a source start at, for converting the positions of
+/// its nodes into offsets with `offset`.
+pub(crate) fn line_starts(source: &str) -> Vec<usize> {
+    source
+        .split('\n')
+        .scan(0, |start, line| {
+            let line_start = *start;
+            *start += line.len() + 1;
+            Some(line_start)
+        })
+        .collect()
+}
+
+/// The byte offset of a flux position in its source, from the offsets the lines of
+/// the source start at. Flux columns count bytes.
+pub(crate) fn offset(
+    line_starts: &[usize],
+    position: &Position,
+) -> usize {
+    let start = position_to_lsp(position);
+    line_starts
+        .get(start.line as usize)
+        .map_or(0, |line_start| line_start + start.character as usize)
+}
+
+/// The byte offset of a flux position in `source`.
+pub(crate) fn byte_offset(
+    source: &str,
+    position: &Position,
+) -> usize {
+    offset(&line_starts(source), position)
+}
+
+/// Return true if the lsp position falls within the flux source location.
+///
+/// Both ends of the location are inclusive, so a cursor placed right after
+/// the last character of a node is still considered inside of it.
+pub(crate) fn location_contains(
+    location: &SourceLocation,
+    position: &lsp::Position,
+) -> bool {
+    crate::lsp::position_in_range(
+        position,
+        &location_to_range(location),
+    )
+}
+
+/// Return true if the location was synthesized rather than parsed from source.
+///
+/// Flux positions are 1-based, so a location on line 0 (e.g. from
+/// `ast::BaseNode::default()`) doesn't point at anything in the document.
+pub(crate) fn is_synthesized(location: &SourceLocation) -> bool {
+    location.start.line == 0
+}
+
+/// Return true if the flux source location starts after the lsp position.
+pub(crate) fn starts_after(
+    location: &SourceLocation,
+    position: &lsp::Position,
+) -> bool {
+    position_to_lsp(&location.start) > *position
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX: u32 = 6;
+
+    fn flux_positions() -> impl Iterator<Item = Position> {
+        (1..=MAX).flat_map(|line| {
+            (1..=MAX).map(move |column| Position { line, column })
+        })
+    }
+
+    fn lsp_positions() -> impl Iterator<Item = lsp::Position> {
+        (0..MAX).flat_map(|line| {
+            (0..MAX).map(move |character| lsp::Position {
+                line,
+                character,
+            })
+        })
+    }
+
+    fn locations() -> impl Iterator<Item = SourceLocation> {
+        flux_positions().flat_map(|start| {
+            flux_positions()
+                .filter(move |end| {
+                    (end.line, end.column)
+                        >= (start.line, start.column)
+                })
+                .map(move |end| SourceLocation {
+                    file: None,
+                    start,
+                    end,
+                    source: None,
+                })
+        })
+    }
+
+    /// Converting to lsp and back is lossless for every valid flux position.
+    #[test]
+    fn position_round_trip() {
+        for position in flux_positions() {
+            let converted =
+                position_to_flux(&position_to_lsp(&position));
+
+            assert_eq!(position.line, converted.line);
+            assert_eq!(position.column, converted.column);
+        }
+        for position in lsp_positions() {
+            assert_eq!(
+                position,
+                position_to_lsp(&position_to_flux(&position))
+            );
+        }
+    }
+
+    /// The first flux position is the first lsp position.
+    #[test]
+    fn position_origin() {
+        assert_eq!(
+            lsp::Position {
+                line: 0,
+                character: 0
+            },
+            position_to_lsp(&Position { line: 1, column: 1 })
+        );
+    }
+
+    /// Synthesized nodes have 0:0 positions, which must not underflow.
+    #[test]
+    fn position_default_is_clamped() {
+        assert_eq!(
+            lsp::Position::default(),
+            position_to_lsp(&Position::default())
+        );
+    }
+
+    /// Conversion preserves ordering between any two positions.
+    #[test]
+    fn position_ordering_is_preserved() {
+        for a in flux_positions() {
+            for b in flux_positions() {
+                assert_eq!(
+                    (a.line, a.column).cmp(&(b.line, b.column)),
+                    position_to_lsp(&a).cmp(&position_to_lsp(&b)),
+                );
+            }
+        }
+    }
+
+    /// A range converted from a location starts and ends at the converted
+    /// positions, and never ends before it starts.
+    #[test]
+    fn location_to_range_bounds() {
+        for location in locations() {
+            let range = location_to_range(&location);
+
+            assert_eq!(position_to_lsp(&location.start), range.start);
+            assert_eq!(position_to_lsp(&location.end), range.end);
+            assert!(range.start <= range.end);
+        }
+    }
+
+    /// Containment agrees with a direct comparison in flux coordinates.
+    #[test]
+    fn location_contains_matches_flux_coordinates() {
+        for location in locations() {
+            for position in lsp_positions() {
+                let flux = position_to_flux(&position);
+                let expected =
+                    (location.start.line, location.start.column)
+                        <= (flux.line, flux.column)
+                        && (flux.line, flux.column)
+                            <= (
+                                location.end.line,
+                                location.end.column,
+                            );
+
+                assert_eq!(
+                    expected,
+                    location_contains(&location, &position),
+                    "{:?} in {:?}",
+                    position,
+                    location
+                );
+            }
+        }
+    }
+
+    /// Offsets count the bytes of the lines before a position, with their newlines,
+    /// and the bytes before it on its line.
+    #[test]
+    fn offsets_of_positions() {
+        let source = "a = 1\nbc = \"é\"\n\nd";
+        let starts = line_starts(source);
+
+        assert_eq!(vec![0, 6, 16, 17], starts);
+        assert_eq!(
+            0,
+            byte_offset(source, &Position { line: 1, column: 1 })
+        );
+        assert_eq!(
+            11,
+            byte_offset(source, &Position { line: 2, column: 6 })
+        );
+        assert_eq!(
+            17,
+            offset(&starts, &Position { line: 4, column: 1 })
+        );
+        assert_eq!(2, line_index(&Position { line: 3, column: 1 }));
+    }
+
+    /// The inner range of a node leaves out a character at each end, and nodes
+    /// spanning lines have none.
+    #[test]
+    fn inner_ranges() {
+        let location =
+            |start: Position, end: Position| SourceLocation {
+                file: None,
+                start,
+                end,
+                source: None,
+            };
+
+        assert_eq!(
+            Some(lsp::Range::new(
+                lsp::Position::new(1, 5),
+                lsp::Position::new(1, 8)
+            )),
+            location_to_inner_range(&location(
+                Position { line: 2, column: 5 },
+                Position {
+                    line: 2,
+                    column: 10
+                },
+            ))
+        );
+        assert_eq!(
+            None,
+            location_to_inner_range(&location(
+                Position { line: 2, column: 5 },
+                Position { line: 3, column: 2 },
+            ))
+        );
+    }
+
+    /// A location starts after a position exactly when the position is before
+    /// its start, and such a location never contains the position.
+    #[test]
+    fn starts_after_matches_flux_coordinates() {
+        for location in locations() {
+            for position in lsp_positions() {
+                let flux = position_to_flux(&position);
+                let expected =
+                    (location.start.line, location.start.column)
+                        > (flux.line, flux.column);
+
+                assert_eq!(
+                    expected,
+                    starts_after(&location, &position)
+                );
+                if expected {
+                    assert!(!location_contains(&location, &position));
+                }
+            }
+        }
+    }
+}
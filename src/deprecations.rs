@@ -0,0 +1,146 @@
+/// Deprecations of the functions of internal libraries
+///
+/// Organizations with large flux codebases share libraries of their own, whose
+/// functions get renamed or replaced like those of the stdlib. The
+/// `deprecatedFunctions` setting lists such functions by package and name, along with
+/// their replacement and a notice. Their calls are reported, and renamed to the
+/// replacement with a quick fix, so that the codebase can be migrated safely.
+use flux::semantic::nodes::{Expression, Package};
+use lspower::lsp;
+use serde::Deserialize;
+
+use crate::convert;
+use crate::versions::StdlibCallVisitor;
+
+/// The diagnostic code of calls of functions deprecated by the `deprecatedFunctions`
+/// setting.
+pub(crate) const DEPRECATED_FUNCTION: &str = "deprecated-function";
+
+/// A function deprecated by the `deprecatedFunctions` setting, e.g.
+/// `{"package": "myorg/alerts", "name": "notify", "replacement": "send"}`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Deprecation {
+    /// The path of the package of the function.
+    pub package: String,
+    pub name: String,
+    /// The function of the same package to call instead.
+    #[serde(default)]
+    pub replacement: Option<String>,
+    /// Why the function is deprecated, or how to migrate away from it.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// Calls of deprecated functions, along with their deprecation.
+///
+/// The range of each diagnostic is the name of the function, which the replacement is
+/// written over.
+pub(crate) fn deprecated_calls<'a>(
+    pkg: &Package,
+    deprecations: &'a [Deprecation],
+) -> Vec<(Option<String>, lsp::Diagnostic, &'a Deprecation)> {
+    if deprecations.is_empty() {
+        return vec![];
+    }
+    let visitor = crate::walk_semantic_package!(
+        StdlibCallVisitor::default(),
+        pkg
+    );
+    visitor
+        .calls
+        .into_iter()
+        .filter_map(|(path, name, call)| {
+            let deprecation =
+                deprecations.iter().find(|deprecation| {
+                    deprecation.package == path
+                        && deprecation.name == name
+                })?;
+            let member = match &call.callee {
+                Expression::Member(member) => member,
+                _ => return None,
+            };
+            // The name of the function ends the member expression.
+            let end = convert::location_to_range(&member.loc).end;
+            let range = lsp::Range {
+                start: lsp::Position {
+                    line: end.line,
+                    character: end
+                        .character
+                        .saturating_sub(name.chars().count() as u32),
+                },
+                end,
+            };
+            let package = path.rsplit('/').next().unwrap_or(&path);
+            let mut message = match &deprecation.replacement {
+                Some(replacement) => format!(
+                    "{}.{} is deprecated, use {}.{} instead.",
+                    package, name, package, replacement
+                ),
+                None => {
+                    format!("{}.{} is deprecated.", package, name)
+                }
+            };
+            if let Some(notice) = &deprecation.message {
+                message.push(' ');
+                message.push_str(notice);
+            }
+            Some((
+                call.loc.file.clone(),
+                lsp::Diagnostic {
+                    range,
+                    severity: Some(lsp::DiagnosticSeverity::WARNING),
+                    code: Some(lsp::NumberOrString::String(
+                        DEPRECATED_FUNCTION.into(),
+                    )),
+                    message,
+                    tags: Some(vec![lsp::DiagnosticTag::DEPRECATED]),
+                    ..lsp::Diagnostic::default()
+                },
+                deprecation,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::get_package;
+
+    #[test]
+    fn calls_of_deprecated_functions() {
+        let fluxscript = r#"import s "strings"
+
+s.title(v: "a")
+s.toUpper(v: "a")
+"#;
+        let package = get_package(fluxscript);
+        let deprecations = vec![Deprecation {
+            package: "strings".into(),
+            name: "title".into(),
+            replacement: Some("toTitle".into()),
+            message: Some("Titles are cased per word.".into()),
+        }];
+
+        let calls = deprecated_calls(&package, &deprecations);
+
+        assert_eq!(
+            vec![(
+                lsp::Range::new(
+                    lsp::Position::new(2, 2),
+                    lsp::Position::new(2, 7)
+                ),
+                "strings.title is deprecated, use strings.toTitle instead. Titles are cased per word.".to_string()
+            )],
+            calls
+                .into_iter()
+                .map(|(_, diagnostic, _)| (
+                    diagnostic.range,
+                    diagnostic.message
+                ))
+                .collect::<Vec<_>>()
+        );
+        assert!(deprecated_calls(&package, &[]).is_empty());
+    }
+}
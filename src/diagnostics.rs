@@ -1,15 +1,186 @@
 /// Diagnostics for flux code
 ///
 /// These diagnostics can range from informational lints to warnings and errors.
-use flux::semantic::nodes::Package;
+use std::collections::HashMap;
+
+use flux::ast;
+use flux::semantic::nodes::{
+    CallExpr, ExprStmt, Expression, Identifier, ImportDeclaration,
+    MemberExpr, Package, Statement,
+};
+use flux::semantic::types::{CollectionType, MonoType};
 use flux::semantic::walk::Node as WalkNode;
 use inflector::Inflector;
 use lspower::lsp;
+use serde::Serialize;
 
 use super::visitors::semantic::{
     ContribDiagnosticVisitor, ExperimentalDiagnosticVisitor,
-    InfluxDBIdentifierDiagnosticVisitor,
+    InfluxDBIdentifierDiagnosticVisitor, UnusedParameterVisitor,
 };
+use super::{convert, lang};
+
+/// The diagnostic codes of errors from flux, by kind of error.
+pub(crate) const SYNTAX_ERROR: &str = "syntax-error";
+pub(crate) const SEMANTIC_ERROR: &str = "semantic-error";
+pub(crate) const TYPE_ERROR: &str = "type-error";
+
+/// The diagnostic code of an error from flux.
+pub(crate) fn error_code(
+    error: &flux::semantic::Error,
+) -> &'static str {
+    match error.error {
+        flux::semantic::ErrorKind::InvalidAST(_) => SYNTAX_ERROR,
+        flux::semantic::ErrorKind::InvalidSemantic(_) => {
+            SEMANTIC_ERROR
+        }
+        flux::semantic::ErrorKind::Inference(_) => TYPE_ERROR,
+    }
+}
+
+/// The documentation of the flux language.
+#[cfg(feature = "docs")]
+const FLUX_DOCS_URL: &str = "https://docs.influxdata.com/flux/v0.x";
+
+/// The pages explaining common errors from flux, by a fragment of their message.
+///
+/// Type errors are only told apart by their message, as they are for narrowing missing
+/// labels. The first fragment found in a message picks its page.
+#[cfg(feature = "docs")]
+const ERROR_DOCS: &[(&str, &str)] = &[
+    ("is missing label", "/data-types/composite/record/"),
+    (
+        "missing required argument",
+        "/spec/expressions/#call-expressions",
+    ),
+    (
+        "found unexpected argument",
+        "/spec/expressions/#call-expressions",
+    ),
+    (" but found ", "/spec/types/"),
+];
+
+/// The link to the page explaining an error from flux, for clients to show along with
+/// its diagnostic.
+#[cfg(feature = "docs")]
+pub(crate) fn error_description(
+    message: &str,
+) -> Option<lsp::CodeDescription> {
+    let path = ERROR_DOCS
+        .iter()
+        .find(|(fragment, _)| message.contains(fragment))
+        .map(|(_, path)| path)?;
+    lsp::Url::parse(&format!("{}{}", FLUX_DOCS_URL, path))
+        .ok()
+        .map(|href| lsp::CodeDescription { href })
+}
+
+#[cfg(not(feature = "docs"))]
+pub(crate) fn error_description(
+    _message: &str,
+) -> Option<lsp::CodeDescription> {
+    None
+}
+
+/// The comment directive suppressing diagnostics on the line following it.
+///
+/// The directive is followed by the codes of the diagnostics to suppress, e.g.
+/// `// flux-lsp:ignore-next-line type-error, camel-case`. Without any codes, every
+/// diagnostic on the line is suppressed.
+const IGNORE_NEXT_LINE: &str = "flux-lsp:ignore-next-line";
+
+/// The text of a comment, without the leading `//`.
+fn comment_text(comment: &str) -> &str {
+    comment.trim().trim_start_matches("//").trim()
+}
+
+/// The codes suppressed by a comment, if it is an ignore directive.
+fn ignored_codes(comment: &str) -> Option<Vec<String>> {
+    let codes =
+        comment_text(comment).strip_prefix(IGNORE_NEXT_LINE)?;
+    if !codes.is_empty() && !codes.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(
+        codes
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|code| !code.is_empty())
+            .map(String::from)
+            .collect(),
+    )
+}
+
+/// Collect the comments of every node in an ast.
+#[derive(Default)]
+struct CommentVisitor {
+    comments: Vec<String>,
+}
+
+impl<'a> ast::walk::Visitor<'a> for CommentVisitor {
+    fn visit(&mut self, node: ast::walk::Node<'a>) -> bool {
+        self.comments.extend(
+            node.base().comments.iter().map(|comment| {
+                comment_text(&comment.text).to_string()
+            }),
+        );
+        true
+    }
+}
+
+/// The lines of a file with suppressed diagnostics, along with the codes suppressed
+/// on each line. An empty list of codes suppresses every diagnostic.
+///
+/// Ignore directives are found among the comments of the ast, so that text inside
+/// strings is never mistaken for a directive. The ast doesn't locate comments, though,
+/// so the directives are then located in the source of the file.
+pub(crate) fn suppressed_lines(
+    file: &ast::File,
+    source: &str,
+) -> HashMap<u32, Vec<String>> {
+    let mut visitor = CommentVisitor::default();
+    ast::walk::walk(&mut visitor, ast::walk::Node::File(file));
+    let directives: Vec<String> = visitor
+        .comments
+        .into_iter()
+        .filter(|comment| ignored_codes(comment).is_some())
+        .collect();
+    if directives.is_empty() {
+        return HashMap::new();
+    }
+
+    source
+        .lines()
+        .enumerate()
+        .filter_map(|(line, text)| {
+            let text = text.trim_start();
+            if !text.starts_with("//")
+                || !directives
+                    .iter()
+                    .any(|d| d.as_str() == comment_text(text))
+            {
+                return None;
+            }
+            Some((line as u32 + 1, ignored_codes(text)?))
+        })
+        .collect()
+}
+
+/// Whether a diagnostic with `code` on `line` is suppressed by an ignore directive.
+pub(crate) fn is_suppressed(
+    suppressed: &HashMap<u32, Vec<String>>,
+    line: u32,
+    code: &str,
+) -> bool {
+    match suppressed.get(&line) {
+        Some(codes) => {
+            codes.is_empty() || codes.iter().any(|c| c == code)
+        }
+        None => false,
+    }
+}
+
+/// The diagnostic code of calls into experimental packages.
+pub(crate) const EXPERIMENTAL: &str = "experimental";
 
 /// Provide info about the nature of experimental.
 ///
@@ -28,6 +199,9 @@ pub(crate) fn experimental_lint(
     visitor.diagnostics
 }
 
+/// The diagnostic code of calls into contrib packages.
+pub(crate) const CONTRIB: &str = "contrib";
+
 /// Provide info about the nature of contrib.
 ///
 /// The packages in contrib are provided by individual users, and don't carry the
@@ -43,6 +217,9 @@ pub(crate) fn contrib_lint(
     visitor.diagnostics
 }
 
+/// The diagnostic code of identifiers that InfluxDB may provide at runtime.
+pub(crate) const INFLUXDB_IDENTIFIER: &str = "influxdb-identifier";
+
 pub(crate) fn no_influxdb_identifiers(
     pkg: &Package,
 ) -> Vec<(Option<String>, lsp::Diagnostic)> {
@@ -53,6 +230,9 @@ pub(crate) fn no_influxdb_identifiers(
     visitor.diagnostics
 }
 
+/// The diagnostic code of identifiers that aren't camel case.
+pub(crate) const CAMEL_CASE: &str = "camel-case";
+
 /// Walk the ast and identifiers that are defined in the script and check that they are
 /// using camelCase rather than snake_case.
 #[derive(Default)]
@@ -67,8 +247,9 @@ impl<'a> flux::semantic::walk::Visitor<'a>
         if let WalkNode::VariableAssgn(assign) = node {
             if !assign.id.name.is_camel_case() {
                 self.diagnostics.push((assign.loc.file.clone(), lsp::Diagnostic {
-                    range: assign.id.loc.clone().into(),
+                    range: convert::location_to_range(&assign.id.loc),
                     severity: Some(lsp::DiagnosticSeverity::INFORMATION),
+                    code: Some(lsp::NumberOrString::String(CAMEL_CASE.into())),
                     message: format!("Idiomatic flux uses camel case for identifier names. Consider renaming this identifier `{}`", assign.id.name.to_camel_case()),
                     ..lsp::Diagnostic::default()
                 }))
@@ -89,21 +270,1057 @@ pub(crate) fn prefer_camel_case(
     visitor.diagnostics
 }
 
+/// The diagnostic code of assignments shadowing a prelude function.
+pub(crate) const PRELUDE_SHADOWING: &str = "prelude-shadowing";
+
+/// Find assignments to the names of functions from the prelude.
+#[derive(Default)]
+struct PreludeShadowingVisitor {
+    diagnostics: Vec<(Option<String>, lsp::Diagnostic)>,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a>
+    for PreludeShadowingVisitor
+{
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        if let WalkNode::VariableAssgn(assign) = node {
+            if let Some(function) =
+                lang::UNIVERSE.function(assign.id.name.as_str())
+            {
+                self.diagnostics.push((assign.loc.file.clone(), lsp::Diagnostic {
+                    range: convert::location_to_range(&assign.id.loc),
+                    severity: Some(lsp::DiagnosticSeverity::WARNING),
+                    code: Some(lsp::NumberOrString::String(PRELUDE_SHADOWING.into())),
+                    message: format!("`{}` shadows the prelude function `{}` with signature `{}`. Consider renaming this identifier.", function.name, function.name, function.signature()),
+                    ..lsp::Diagnostic::default()
+                }))
+            }
+        }
+        true
+    }
+}
+
+/// Assignments shadowing prelude functions, e.g. `sort = 5`.
+///
+/// Shadowing a function like `sort` or `filter` leads to confusing type errors wherever
+/// the function is called later on, far from the actual cause.
+pub(crate) fn prelude_shadowing(
+    pkg: &Package,
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    let visitor = crate::walk_semantic_package!(
+        PreludeShadowingVisitor::default(),
+        pkg
+    );
+    visitor.diagnostics
+}
+
+/// The diagnostic code of function parameters that are never used.
+pub(crate) const UNUSED_PARAMETER: &str = "unused-parameter";
+
+/// Parameters of functions that their body never uses, e.g. `x` in `f = (x, y) => y`.
+///
+/// Functions passed as arguments are left alone, as the function they're passed to
+/// decides their parameters.
+pub(crate) fn unused_parameters(
+    pkg: &Package,
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    let visitor = crate::walk_semantic_package!(
+        UnusedParameterVisitor::default(),
+        pkg
+    );
+    visitor
+        .unused
+        .iter()
+        .filter(|(assign, _)| {
+            !visitor.passed.contains(&assign.id.name.as_str())
+        })
+        .map(|(assign, param)| {
+            (
+                param.loc.file.clone(),
+                lsp::Diagnostic {
+                    range: convert::location_to_range(&param.key.loc),
+                    severity: Some(lsp::DiagnosticSeverity::HINT),
+                    code: Some(lsp::NumberOrString::String(
+                        UNUSED_PARAMETER.into(),
+                    )),
+                    message: format!(
+                        "The parameter `{}` of `{}` is never used.",
+                        param.key.name, assign.id.name
+                    ),
+                    tags: Some(vec![lsp::DiagnosticTag::UNNECESSARY]),
+                    ..lsp::Diagnostic::default()
+                },
+            )
+        })
+        .collect()
+}
+
+/// The diagnostic code of declarations shadowing a variable of an outer scope.
+pub(crate) const SHADOWED_VARIABLE: &str = "shadowed-variable";
+
+/// Find parameters and assignments in functions with the name of a variable of an
+/// outer scope, along with that variable.
+struct ShadowingVisitor<'a> {
+    /// The variables in scope, innermost last. Functions add a scope for their
+    /// parameters, and another for the variables of their body.
+    scopes: Vec<Vec<&'a Identifier>>,
+    shadowing: Vec<(&'a Identifier, &'a Identifier)>,
+}
+
+impl Default for ShadowingVisitor<'_> {
+    fn default() -> Self {
+        Self {
+            scopes: vec![vec![]],
+            shadowing: vec![],
+        }
+    }
+}
+
+impl<'a> ShadowingVisitor<'a> {
+    /// Declare a variable in the innermost scope, checking the scopes around it.
+    fn declare(&mut self, id: &'a Identifier) {
+        let (scope, outer) = match self.scopes.split_last_mut() {
+            Some(scopes) => scopes,
+            None => return,
+        };
+        if let Some(shadowed) = outer.iter().rev().find_map(|scope| {
+            scope.iter().find(|outer| outer.name == id.name).copied()
+        }) {
+            self.shadowing.push((id, shadowed));
+        }
+        scope.push(id);
+    }
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a> for ShadowingVisitor<'a> {
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        match node {
+            WalkNode::VariableAssgn(assign) => {
+                self.declare(&assign.id)
+            }
+            WalkNode::FunctionExpr(function) => {
+                self.scopes.push(vec![]);
+                for param in &function.params {
+                    self.declare(&param.key);
+                }
+                self.scopes.push(vec![]);
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn done(&mut self, node: WalkNode<'a>) {
+        if let WalkNode::FunctionExpr(_) = node {
+            self.scopes.truncate(self.scopes.len() - 2);
+        }
+    }
+}
+
+/// Parameters and variables of functions that shadow a variable of an outer scope.
+///
+/// Flux doesn't allow reassigning variables, but a function may declare a variable of
+/// the same name as one around it, which is easily mistaken for a reassignment. The
+/// shadowed variable is related information.
+pub(crate) fn shadowed_variables(
+    pkg: &Package,
+    urls: &[lsp::Url],
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    let visitor = crate::walk_semantic_package!(
+        ShadowingVisitor::default(),
+        pkg
+    );
+    visitor
+        .shadowing
+        .into_iter()
+        .map(|(id, shadowed)| {
+            let related_information = shadowed
+                .loc
+                .file
+                .as_ref()
+                .and_then(|filename| url_of_file(urls, filename))
+                .map(|uri| {
+                    let range =
+                        convert::location_to_range(&shadowed.loc);
+                    vec![lsp::DiagnosticRelatedInformation {
+                        location: lsp::Location {
+                            uri: uri.clone(),
+                            range,
+                        },
+                        message: format!(
+                            "The shadowed `{}`",
+                            shadowed.name
+                        ),
+                    }]
+                });
+            let diagnostic = lsp::Diagnostic {
+                range: convert::location_to_range(&id.loc),
+                severity: Some(lsp::DiagnosticSeverity::INFORMATION),
+                code: Some(lsp::NumberOrString::String(
+                    SHADOWED_VARIABLE.into(),
+                )),
+                message: format!(
+                    "`{}` shadows a variable of an outer scope. \
+                     Flux variables can't be reassigned, so this \
+                     declares a new variable.",
+                    id.name
+                ),
+                related_information,
+                ..lsp::Diagnostic::default()
+            };
+            (id.loc.file.clone(), diagnostic)
+        })
+        .collect()
+}
+
+/// Find the calls of a function by its name.
+struct CallsOfVisitor<'a> {
+    name: &'a str,
+    calls: Vec<&'a CallExpr>,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a> for CallsOfVisitor<'a> {
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        if let WalkNode::CallExpr(call) = node {
+            if matches!(&call.callee, Expression::Identifier(ident) if ident.name == self.name)
+            {
+                self.calls.push(call);
+            }
+        }
+        true
+    }
+}
+
+/// The calls of the function assigned to a variable, e.g. `f(x: 1)` of `f`.
+pub(crate) fn calls_of<'a>(
+    pkg: &'a Package,
+    name: &'a str,
+) -> Vec<&'a CallExpr> {
+    let visitor = crate::walk_semantic_package!(
+        CallsOfVisitor {
+            name,
+            calls: vec![]
+        },
+        pkg
+    );
+    visitor.calls
+}
+
+/// The diagnostic code of imports whose names collide.
+pub(crate) const IMPORT_COLLISION: &str = "import-collision";
+/// The diagnostic code of imports of a package already imported under the same name.
+pub(crate) const DUPLICATE_IMPORT: &str = "duplicate-import";
+
+/// The name an import is referred to by, i.e. its alias or the last segment of its path.
+pub(crate) fn import_name(import: &ImportDeclaration) -> String {
+    match &import.alias {
+        Some(alias) => alias.name.to_string(),
+        None => import
+            .path
+            .value
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+/// Imports whose name collides with the name of an earlier import in the same file.
+///
+/// This happens when two package paths end the same way (e.g. `influxdata/influxdb/schema`
+/// and `influxdata/influxdb/v1/schema`) or an alias is already taken. The error flux
+/// reports for this doesn't point at the cause, so the later import is flagged.
+/// When both import the same package, the later one is a duplicate, which is flagged
+/// as unnecessary instead.
+pub(crate) fn import_collisions(
+    pkg: &Package,
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    let mut diagnostics = vec![];
+    for file in pkg.files.iter() {
+        let mut names: Vec<(String, &str)> = vec![];
+        for import in file.imports.iter() {
+            let name = import_name(import);
+            match names.iter().find(|(taken, _)| taken == &name) {
+                Some((_, path)) if *path == import.path.value => diagnostics.push((import.loc.file.clone(), lsp::Diagnostic {
+                    range: convert::location_to_range(&import.loc),
+                    severity: Some(lsp::DiagnosticSeverity::WARNING),
+                    code: Some(lsp::NumberOrString::String(DUPLICATE_IMPORT.into())),
+                    message: format!("`{}` is already imported.", import.path.value),
+                    tags: Some(vec![lsp::DiagnosticTag::UNNECESSARY]),
+                    ..lsp::Diagnostic::default()
+                })),
+                Some((_, path)) => diagnostics.push((import.loc.file.clone(), lsp::Diagnostic {
+                    range: convert::location_to_range(&import.loc),
+                    severity: Some(lsp::DiagnosticSeverity::WARNING),
+                    code: Some(lsp::NumberOrString::String(IMPORT_COLLISION.into())),
+                    message: format!("The import of `{}` is named `{}`, like the import of `{}`. Add an alias to tell them apart.", import.path.value, name, path),
+                    ..lsp::Diagnostic::default()
+                })),
+                None => names.push((name, &import.path.value)),
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Suggest an alias for the import of `path` that isn't one of the `taken` names.
+///
+/// The alias includes the parent segment of the path, e.g. `v1Schema` for
+/// `influxdata/influxdb/v1/schema`.
+pub(crate) fn suggest_import_alias(
+    path: &str,
+    taken: &[String],
+) -> String {
+    let mut segments = path.rsplit('/');
+    let name = segments.next().unwrap_or_default();
+    let alias = match segments.next() {
+        Some(parent) => {
+            format!("{}_{}", parent, name).to_camel_case()
+        }
+        None => format!("{}_pkg", name).to_camel_case(),
+    };
+    if !taken.contains(&alias) {
+        return alias;
+    }
+    (2..)
+        .map(|i| format!("{}{}", alias, i))
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap_or(alias)
+}
+
+/// The diagnostic code of results without a name in a script with several results.
+pub(crate) const UNNAMED_RESULT: &str = "unnamed-result";
+
+/// The top level expression statements of a package that produce a result, i.e. a
+/// stream of tables.
+fn results(pkg: &Package) -> Vec<&ExprStmt> {
+    pkg.files
+        .iter()
+        .flat_map(|file| file.body.iter())
+        .filter_map(|statement| match statement {
+            Statement::Expr(statement) => {
+                match statement.expression.type_of() {
+                    MonoType::Collection(collection)
+                        if collection.collection
+                            == CollectionType::Stream =>
+                    {
+                        Some(statement)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The name of a result ending in a call to `yield`, which is `_result` unless the
+/// call names it.
+fn yield_name(expression: &Expression) -> Option<String> {
+    let call = match expression {
+        Expression::Call(call) => call,
+        _ => return None,
+    };
+    if !matches!(&call.callee, Expression::Identifier(ident) if ident.name.as_str() == "yield")
+    {
+        return None;
+    }
+    match call
+        .arguments
+        .iter()
+        .find(|argument| argument.key.name.as_str() == "name")
+        .map(|argument| &argument.value)
+    {
+        Some(Expression::StringLit(name)) => Some(name.value.clone()),
+        _ => Some("_result".into()),
+    }
+}
+
+/// The names to give the unnamed results of a package, along with the location of each
+/// result. Nothing needs a name unless there are several results.
+///
+/// The names are `result_1`, `result_2`, etc., skipping the names already yielded.
+pub(crate) fn unnamed_result_names(
+    pkg: &Package,
+) -> Vec<(&ast::SourceLocation, String)> {
+    let results = results(pkg);
+    if results.len() < 2 {
+        return vec![];
+    }
+    let taken: Vec<String> = results
+        .iter()
+        .filter_map(|statement| yield_name(&statement.expression))
+        .collect();
+    let mut names = (1..)
+        .map(|i| format!("result_{}", i))
+        .filter(|name| !taken.contains(name));
+    results
+        .into_iter()
+        .filter(|statement| {
+            yield_name(&statement.expression).is_none()
+        })
+        .filter_map(|statement| Some((&statement.loc, names.next()?)))
+        .collect()
+}
+
+/// Results without a name, in a script with several results.
+///
+/// Each result of a script without an explicit `yield(name: ...)` is named `_result`,
+/// so the results can't be told apart by the API.
+pub(crate) fn unnamed_results(
+    pkg: &Package,
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    let count = results(pkg).len();
+    unnamed_result_names(pkg)
+        .into_iter()
+        .map(|(location, name)| {
+            (location.file.clone(), lsp::Diagnostic {
+                range: convert::location_to_range(location),
+                severity: Some(lsp::DiagnosticSeverity::WARNING),
+                code: Some(lsp::NumberOrString::String(UNNAMED_RESULT.into())),
+                message: format!("This script has {} results, and this one has no name. Name it with `yield(name: \"{}\")` to tell it apart from the others.", count, name),
+                ..lsp::Diagnostic::default()
+            })
+        })
+        .collect()
+}
+
+/// The diagnostic code of `aggregateWindow` calls in tasks that don't set `createEmpty`.
+///
+/// This lint is opt-in, with the `optInLints` setting.
+pub(crate) const AGGREGATE_WINDOW_CREATE_EMPTY: &str =
+    "aggregate-window-create-empty";
+
+/// The name of the function called, e.g. `to` for both `to(...)` and `experimental.to(...)`.
+pub(crate) fn callee_name(call: &CallExpr) -> Option<&str> {
+    match &call.callee {
+        Expression::Identifier(ident) => Some(ident.name.as_str()),
+        Expression::Member(member) => Some(member.property.as_str()),
+        _ => None,
+    }
+}
+
+/// Find the `aggregateWindow` calls without `createEmpty` in a task writing to a bucket.
+#[derive(Default)]
+struct AggregateWindowVisitor<'a> {
+    task: bool,
+    writes: bool,
+    calls: Vec<&'a CallExpr>,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a>
+    for AggregateWindowVisitor<'a>
+{
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        match node {
+            WalkNode::OptionStmt(opt) => {
+                if let flux::semantic::nodes::Assignment::Variable(
+                    assign,
+                ) = &opt.assignment
+                {
+                    if assign.id.name == "task" {
+                        self.task = true;
+                    }
+                }
+            }
+            WalkNode::CallExpr(call) => match callee_name(call) {
+                Some("to") => self.writes = true,
+                Some("aggregateWindow")
+                    if !call.arguments.iter().any(|argument| {
+                        argument.key.name == "createEmpty"
+                    }) =>
+                {
+                    self.calls.push(call)
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        true
+    }
+}
+
+/// The `aggregateWindow` calls of a downsampling task that don't set `createEmpty`.
+pub(crate) fn aggregate_window_calls(
+    pkg: &Package,
+) -> Vec<&CallExpr> {
+    let visitor = crate::walk_semantic_package!(
+        AggregateWindowVisitor::default(),
+        pkg
+    );
+    if visitor.task && visitor.writes {
+        visitor.calls
+    } else {
+        vec![]
+    }
+}
+
+/// `aggregateWindow` calls that don't set `createEmpty`, in a task writing to a bucket.
+///
+/// By default, `aggregateWindow` creates a row for every window, with a null value for
+/// the windows without data. In a downsampling task, whether those rows should be
+/// written is a decision worth making explicitly.
+pub(crate) fn aggregate_window_create_empty(
+    pkg: &Package,
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    aggregate_window_calls(pkg)
+        .into_iter()
+        .map(|call| {
+            (call.loc.file.clone(), lsp::Diagnostic {
+                range: convert::location_to_range(&call.loc),
+                severity: Some(lsp::DiagnosticSeverity::INFORMATION),
+                code: Some(lsp::NumberOrString::String(AGGREGATE_WINDOW_CREATE_EMPTY.into())),
+                message: "`aggregateWindow` creates empty windows by default, with a null value. Consider whether this task should write them: `createEmpty: false` skips them, which leaves gaps in the downsampled data.".into(),
+                ..lsp::Diagnostic::default()
+            })
+        })
+        .collect()
+}
+
+/// Collect the names of identifiers that fall entirely within a range.
+struct IdentifiersInRangeVisitor {
+    range: lsp::Range,
+    names: Vec<String>,
+}
+
+impl<'a> ast::walk::Visitor<'a> for IdentifiersInRangeVisitor {
+    fn visit(&mut self, node: ast::walk::Node<'a>) -> bool {
+        let range = convert::location_to_range(&node.base().location);
+        if range.end < self.range.start
+            || range.start > self.range.end
+        {
+            return false;
+        }
+        if let ast::walk::Node::Identifier(ident) = node {
+            if self.range.start <= range.start
+                && range.end <= self.range.end
+                && !self.names.contains(&ident.name)
+            {
+                self.names.push(ident.name.clone());
+            }
+        }
+        true
+    }
+}
+
+/// The diagnostic code of pipelines writing back to the bucket they read from.
+pub(crate) const SAME_BUCKET_WRITE: &str = "same-bucket-write";
+
+/// The string literal passed as the `bucket` of a call, e.g. of `from` or `to`.
+fn bucket_argument(call: &CallExpr) -> Option<&str> {
+    call.arguments
+        .iter()
+        .find(|argument| argument.key.name == "bucket")
+        .and_then(|argument| match &argument.value {
+            Expression::StringLit(lit) => Some(lit.value.as_str()),
+            _ => None,
+        })
+}
+
+/// Find whether a stage sets the `_measurement` column, e.g. with
+/// `set(key: "_measurement", value: "cpu_5m")` or `map(fn: (r) => ({r with _measurement: "cpu_5m"}))`.
+struct MeasurementVisitor<'a> {
+    /// The stages before the one walked, which aren't walked along with it.
+    pipe: Option<&'a Expression>,
+    sets: bool,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a>
+    for MeasurementVisitor<'a>
+{
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        match node {
+            WalkNode::CallExpr(call) if matches!(self.pipe, Some(Expression::Call(pipe)) if std::ptr::eq(&**pipe, call)) => {
+                return false
+            }
+            WalkNode::StringLit(lit)
+                if lit.value == "_measurement" =>
+            {
+                self.sets = true
+            }
+            WalkNode::ObjectExpr(object)
+                if object.properties.iter().any(|property| {
+                    property.key.name == "_measurement"
+                }) =>
+            {
+                self.sets = true
+            }
+            _ => {}
+        }
+        !self.sets
+    }
+}
+
+/// Pipelines writing with `to` to the bucket their `from` reads, without changing the
+/// measurement of the data.
+///
+/// In a task, such a pipeline reads the data it wrote on its next run, and writes it
+/// again, along with what it derives from it. `filter` stages are skipped, as they
+/// read `_measurement` rather than set it.
+pub(crate) fn same_bucket_writes(
+    pkg: &Package,
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    crate::perf_lint::pipelines(pkg)
+        .into_iter()
+        .filter_map(|stages| {
+            let bucket = bucket_argument(stages[0])?;
+            let write = stages.iter().skip(1).find(|stage| {
+                callee_name(stage) == Some("to")
+                    && bucket_argument(stage) == Some(bucket)
+            })?;
+            let renamed = stages
+                .iter()
+                .skip(1)
+                .take_while(|stage| !std::ptr::eq(**stage, *write))
+                .filter(|stage| callee_name(stage) != Some("filter"))
+                .any(|stage| {
+                    let mut visitor = MeasurementVisitor {
+                        pipe: stage.pipe.as_ref(),
+                        sets: false,
+                    };
+                    flux::semantic::walk::walk(
+                        &mut visitor,
+                        WalkNode::CallExpr(stage),
+                    );
+                    visitor.sets
+                });
+            if renamed {
+                return None;
+            }
+            Some((write.loc.file.clone(), lsp::Diagnostic {
+                range: convert::location_to_range(&write.loc),
+                severity: Some(lsp::DiagnosticSeverity::WARNING),
+                code: Some(lsp::NumberOrString::String(SAME_BUCKET_WRITE.into())),
+                message: format!("This writes to \"{}\", the bucket it reads from, with the same measurement. A task running this reads back what it wrote, and writes it again on every run. Consider writing to another bucket, or setting another `_measurement`.", bucket),
+                ..lsp::Diagnostic::default()
+            }))
+        })
+        .collect()
+}
+
+/// A rule diagnostics are reported by, as listed by the `getLintRules` command.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LintRule {
+    /// The code of the diagnostics of the rule.
+    pub id: &'static str,
+    pub description: &'static str,
+    /// The severity reported unless configured otherwise, as named in the
+    /// `lintSeverities` setting.
+    pub default_severity: &'static str,
+    /// Whether the severity can be configured with the `lintSeverities` setting.
+    /// Errors from flux can't be.
+    pub configurable: bool,
+    /// Whether the rule only runs when listed in the `optInLints` setting.
+    pub opt_in: bool,
+    /// Whether code actions fixing the diagnostics are offered.
+    pub quickfix: bool,
+}
+
+const fn error_rule(
+    id: &'static str,
+    description: &'static str,
+    quickfix: bool,
+) -> LintRule {
+    LintRule {
+        id,
+        description,
+        default_severity: "error",
+        configurable: false,
+        opt_in: false,
+        quickfix,
+    }
+}
+
+const fn lint_rule(
+    id: &'static str,
+    description: &'static str,
+    default_severity: &'static str,
+    quickfix: bool,
+) -> LintRule {
+    LintRule {
+        id,
+        description,
+        default_severity,
+        configurable: true,
+        opt_in: false,
+        quickfix,
+    }
+}
+
+/// Every rule diagnostics are reported by, for settings UIs to list.
+pub(crate) const LINT_RULES: &[LintRule] = &[
+    error_rule(SYNTAX_ERROR, "Code that can't be parsed.", false),
+    error_rule(
+        SEMANTIC_ERROR,
+        "Code that can't be analyzed, e.g. an undefined identifier.",
+        true,
+    ),
+    error_rule(TYPE_ERROR, "Values of the wrong type.", false),
+    lint_rule(
+        EXPERIMENTAL,
+        "Calls into experimental packages, which can change or be removed.",
+        "hint",
+        false,
+    ),
+    lint_rule(
+        CONTRIB,
+        "Calls into contrib packages, which don't carry the compatibility guarantees of the stdlib.",
+        "hint",
+        false,
+    ),
+    lint_rule(
+        INFLUXDB_IDENTIFIER,
+        "Identifiers that InfluxDB may provide at runtime.",
+        "warning",
+        false,
+    ),
+    lint_rule(
+        CAMEL_CASE,
+        "Identifiers that aren't camel case.",
+        "information",
+        false,
+    ),
+    lint_rule(
+        PRELUDE_SHADOWING,
+        "Assignments shadowing a function of the prelude.",
+        "warning",
+        true,
+    ),
+    lint_rule(
+        UNUSED_PARAMETER,
+        "Function parameters that are never used.",
+        "hint",
+        true,
+    ),
+    lint_rule(
+        SHADOWED_VARIABLE,
+        "Declarations shadowing a variable of an outer scope.",
+        "information",
+        false,
+    ),
+    lint_rule(
+        IMPORT_COLLISION,
+        "Imports whose names collide.",
+        "warning",
+        true,
+    ),
+    lint_rule(
+        DUPLICATE_IMPORT,
+        "Imports of a package already imported under the same name.",
+        "warning",
+        true,
+    ),
+    lint_rule(
+        UNNAMED_RESULT,
+        "Results without a name in a script with several results.",
+        "warning",
+        true,
+    ),
+    LintRule {
+        id: AGGREGATE_WINDOW_CREATE_EMPTY,
+        description: "`aggregateWindow` calls in tasks that don't set `createEmpty`.",
+        default_severity: "information",
+        configurable: true,
+        opt_in: true,
+        quickfix: true,
+    },
+    lint_rule(
+        SAME_BUCKET_WRITE,
+        "Pipelines writing back to the bucket they read from.",
+        "warning",
+        false,
+    ),
+    lint_rule(
+        crate::perf_lint::FILTER_PUSHDOWN,
+        "Filters that could run earlier in a pipeline.",
+        "information",
+        false,
+    ),
+    lint_rule(
+        crate::perf_lint::PUSHDOWN_BLOCKED,
+        "Stages keeping the stages after them from being pushed down to storage.",
+        "information",
+        false,
+    ),
+    lint_rule(
+        crate::perf_lint::FILTER_NOT_PUSHABLE,
+        "Filters whose predicate can't be pushed down to storage.",
+        "information",
+        false,
+    ),
+    lint_rule(
+        crate::perf_lint::LITERAL_REGEX,
+        "Regular expressions only matching a literal string.",
+        "information",
+        true,
+    ),
+    lint_rule(
+        crate::ranges::EMPTY_RANGE,
+        "Ranges that can't contain any point.",
+        "warning",
+        false,
+    ),
+    lint_rule(
+        crate::ranges::UNBOUNDED_RANGE,
+        "Ranges starting at the beginning of time.",
+        "warning",
+        false,
+    ),
+    lint_rule(
+        crate::ranges::MISSING_RANGE,
+        "Pipelines reading from storage without a `range`.",
+        "warning",
+        false,
+    ),
+    lint_rule(
+        crate::timezones::UNKNOWN_TIMEZONE,
+        "Time zone names that aren't in the tz database.",
+        "warning",
+        false,
+    ),
+    lint_rule(
+        crate::secrets::UNKNOWN_SECRET_KEY,
+        "Secret keys that aren't among those pushed by the client.",
+        "warning",
+        false,
+    ),
+    lint_rule(
+        crate::http::INVALID_URL,
+        "Urls of HTTP functions that can't be parsed.",
+        "warning",
+        false,
+    ),
+    lint_rule(
+        crate::http::INSECURE_HTTP,
+        "Urls of HTTP functions sent unencrypted, with the `warnInsecureHttp` setting.",
+        "warning",
+        false,
+    ),
+    lint_rule(
+        crate::schema::UNKNOWN_SCHEMA_NAME,
+        "Buckets, measurements and fields that aren't in the schema.",
+        "warning",
+        false,
+    ),
+    lint_rule(
+        crate::versions::UNAVAILABLE_FUNCTION,
+        "Functions introduced after the flux of the `targetVersion` setting.",
+        "warning",
+        false,
+    ),
+    lint_rule(
+        crate::versions::UNSUPPORTED_ON_PLATFORM,
+        "Packages and arguments the platform of the `target` setting doesn't support.",
+        "warning",
+        false,
+    ),
+    lint_rule(
+        crate::deprecations::DEPRECATED_FUNCTION,
+        "Calls of functions deprecated by the `deprecatedFunctions` setting.",
+        "warning",
+        true,
+    ),
+    lint_rule(
+        crate::query_params::UNDECLARED_PARAM,
+        "Query parameters that aren't declared in the `queryParams` setting.",
+        "warning",
+        false,
+    ),
+];
+
+/// The severity of a lint configured with the `lintSeverities` setting, e.g. `"error"`,
+/// or `Some(None)` for `"off"`.
+pub(crate) fn parse_severity(
+    name: &str,
+) -> Option<Option<lsp::DiagnosticSeverity>> {
+    match name {
+        "error" => Some(Some(lsp::DiagnosticSeverity::ERROR)),
+        "warning" => Some(Some(lsp::DiagnosticSeverity::WARNING)),
+        "information" => {
+            Some(Some(lsp::DiagnosticSeverity::INFORMATION))
+        }
+        "hint" => Some(Some(lsp::DiagnosticSeverity::HINT)),
+        "off" => Some(None),
+        _ => None,
+    }
+}
+
+/// The url of the file named `filename`, among the urls of a package.
+pub(crate) fn url_of_file<'a>(
+    urls: &'a [lsp::Url],
+    filename: &str,
+) -> Option<&'a lsp::Url> {
+    urls.iter().find(|url| {
+        url.path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map_or(false, |segment| segment == filename)
+    })
+}
+
+/// Find the definitions in other files of a package that an error refers to.
+///
+/// An error in one file is frequently caused by a definition in another file of the
+/// same package, e.g. a type mismatch against a variable defined elsewhere. The
+/// top-level definitions of the other files that are referenced from within the
+/// error location are returned as related information, so the user can navigate to
+/// the root cause.
+pub(crate) fn related_definitions(
+    pkg: &ast::Package,
+    location: &ast::SourceLocation,
+    urls: &[lsp::Url],
+) -> Option<Vec<lsp::DiagnosticRelatedInformation>> {
+    let filename = location.file.as_ref()?;
+    let file =
+        pkg.files.iter().find(|file| &file.name == filename)?;
+
+    let mut visitor = IdentifiersInRangeVisitor {
+        range: convert::location_to_range(location),
+        names: vec![],
+    };
+    ast::walk::walk(&mut visitor, ast::walk::Node::File(file));
+
+    let definitions: HashMap<&str, (&ast::File, &ast::Identifier)> =
+        pkg.files
+            .iter()
+            .filter(|file| &file.name != filename)
+            .flat_map(|file| {
+                file.body.iter().filter_map(move |statement| {
+                    let id = match statement {
+                        ast::Statement::Variable(assignment) => {
+                            &assignment.id
+                        }
+                        ast::Statement::Option(option) => {
+                            match &option.assignment {
+                                ast::Assignment::Variable(
+                                    assignment,
+                                ) => &assignment.id,
+                                ast::Assignment::Member(_) => {
+                                    return None
+                                }
+                            }
+                        }
+                        _ => return None,
+                    };
+                    Some((id.name.as_str(), (file, id)))
+                })
+            })
+            .collect();
+
+    let related: Vec<lsp::DiagnosticRelatedInformation> = visitor
+        .names
+        .iter()
+        .filter_map(|name| {
+            let (file, id) = definitions.get(name.as_str())?;
+            let uri = url_of_file(urls, &file.name)?;
+            Some(lsp::DiagnosticRelatedInformation {
+                location: lsp::Location {
+                    uri: uri.clone(),
+                    range: convert::location_to_range(
+                        &id.base.location,
+                    ),
+                },
+                message: format!("`{}` is defined here", name),
+            })
+        })
+        .collect();
+
+    if related.is_empty() {
+        None
+    } else {
+        Some(related)
+    }
+}
+
+/// Find the first member expression accessing `property` within a range of a file.
+struct MemberAccessFinderVisitor<'a> {
+    file: Option<String>,
+    range: lsp::Range,
+    property: String,
+    member: Option<&'a MemberExpr>,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a>
+    for MemberAccessFinderVisitor<'a>
+{
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        if self.member.is_some() {
+            return false;
+        }
+        if let WalkNode::MemberExpr(member) = node {
+            let range = convert::location_to_range(&member.loc);
+            if member.property == self.property
+                && member.loc.file == self.file
+                && self.range.start <= range.start
+                && range.end <= self.range.end
+            {
+                self.member = Some(member);
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Extract the label from a "record is missing label X" error message.
+fn missing_label(message: &str) -> Option<&str> {
+    let (_, rest) = message.split_once("missing label ")?;
+    let label = rest
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .find(|part| !part.is_empty())?;
+    Some(label)
+}
+
+/// Narrow a "record is missing label" error down to the offending property access.
+///
+/// The flux type checker reports missing record labels against the expression where
+/// unification failed, which is frequently a whole `filter` lambda. Users care about
+/// the `r.X` that doesn't exist, and which labels they could have used instead, so
+/// return the range of the property access and a message listing the known labels.
+pub(crate) fn narrow_missing_label(
+    pkg: &Package,
+    location: &ast::SourceLocation,
+    message: &str,
+) -> Option<(lsp::Range, String)> {
+    let label = missing_label(message)?;
+
+    let visitor = crate::walk_semantic_package!(
+        MemberAccessFinderVisitor {
+            file: location.file.clone(),
+            range: convert::location_to_range(location),
+            property: label.into(),
+            member: None,
+        },
+        pkg
+    );
+    let member = visitor.member?;
+
+    let mut labels: Vec<String> = match member.object.type_of() {
+        MonoType::Record(record) => {
+            record.fields().map(|field| field.k.to_string()).collect()
+        }
+        _ => vec![],
+    };
+    labels.sort();
+    labels.dedup();
+
+    let message = if labels.is_empty() {
+        message.to_string()
+    } else {
+        format!(
+            "{} (available labels: {})",
+            message,
+            labels.join(", ")
+        )
+    };
+    Some((convert::location_to_range(&member.loc), message))
+}
+
 #[cfg(test)]
 #[allow(clippy::unwrap_used)]
 mod tests {
     use super::*;
-
-    fn get_package(source: &str) -> flux::semantic::nodes::Package {
-        let ast_pkg =
-            flux::parser::parse_string("script.flux".into(), &source);
-        let mut analyzer = flux::new_semantic_analyzer(
-            flux::semantic::AnalyzerConfig::default(),
-        )
-        .unwrap();
-        let (_, pkg) = analyzer.analyze_ast(&ast_pkg.into()).unwrap();
-        pkg
-    }
+    use crate::test_support::{get_package, get_salvaged_package};
 
     #[test]
     fn experimental_lint_check() {
@@ -128,6 +1345,7 @@ from(bucket: "my-bucket")
                 },
             },
             severity: Some(lsp::DiagnosticSeverity::HINT),
+            code: Some(lsp::NumberOrString::String(EXPERIMENTAL.into())),
             message: "experimental features can change often or be deleted/moved. Use with caution.".into(),
             ..lsp::Diagnostic::default()
         })], diagnostics);
@@ -156,6 +1374,7 @@ array.concat(
                 },
             },
             severity: Some(lsp::DiagnosticSeverity::HINT),
+            code: Some(lsp::NumberOrString::String(EXPERIMENTAL.into())),
             message: "experimental features can change often or be deleted/moved. Use with caution.".into(),
             ..lsp::Diagnostic::default()
         })], diagnostics);
@@ -191,6 +1410,7 @@ influxdb.select(
                 },
             },
             severity: Some(lsp::DiagnosticSeverity::HINT),
+            code: Some(lsp::NumberOrString::String(CONTRIB.into())),
             message: "contrib packages are user-contributed, and do not carry with them the same compatibility guarantees as the standard library. Use with caution.".into(),
             ..lsp::Diagnostic::default()
         })], diagnostics);
@@ -226,6 +1446,7 @@ influxdb2.select(
                 },
             },
             severity: Some(lsp::DiagnosticSeverity::HINT),
+            code: Some(lsp::NumberOrString::String(CONTRIB.into())),
             message: "contrib packages are user-contributed, and do not carry with them the same compatibility guarantees as the standard library. Use with caution.".into(),
             ..lsp::Diagnostic::default()
         })], diagnostics);
@@ -254,6 +1475,7 @@ from(bucket: "my-bucket")
                 },
             },
             severity: Some(lsp::DiagnosticSeverity::WARNING),
+            code: Some(lsp::NumberOrString::String(INFLUXDB_IDENTIFIER.into())),
             message: "Avoid using `v` as an identifier name. In some InfluxDB contexts, it may be provided at runtime.".into(),
             ..lsp::Diagnostic::default()
         })], diagnostics);
@@ -279,6 +1501,7 @@ task = "abc123"
                 },
             },
             severity: Some(lsp::DiagnosticSeverity::WARNING),
+            code: Some(lsp::NumberOrString::String(INFLUXDB_IDENTIFIER.into())),
             message: "Avoid using `task` as an identifier name. In some InfluxDB contexts, it may be provided at runtime.".into(),
             ..lsp::Diagnostic::default()
         })], diagnostics);
@@ -304,11 +1527,338 @@ params = "abc123"
                 },
             },
             severity: Some(lsp::DiagnosticSeverity::WARNING),
+            code: Some(lsp::NumberOrString::String(INFLUXDB_IDENTIFIER.into())),
             message: "Avoid using `params` as an identifier name. In some InfluxDB contexts, it may be provided at runtime.".into(),
             ..lsp::Diagnostic::default()
         })], diagnostics);
     }
 
+    #[test]
+    fn prelude_shadowing_assignments() {
+        let fluxscript = r#"sort = 5
+sorted = sort + 1"#;
+        let package = get_package(&fluxscript);
+
+        let diagnostics = prelude_shadowing(&package);
+
+        assert_eq!(1, diagnostics.len());
+        let (file, diagnostic) = &diagnostics[0];
+        assert_eq!(&Some("script.flux".to_string()), file);
+        assert_eq!(
+            lsp::Range {
+                start: lsp::Position {
+                    line: 0,
+                    character: 0
+                },
+                end: lsp::Position {
+                    line: 0,
+                    character: 4
+                },
+            },
+            diagnostic.range
+        );
+        assert_eq!(
+            Some(lsp::DiagnosticSeverity::WARNING),
+            diagnostic.severity
+        );
+        assert_eq!(
+            Some(lsp::NumberOrString::String(
+                PRELUDE_SHADOWING.into()
+            )),
+            diagnostic.code
+        );
+        assert!(
+            diagnostic.message.starts_with(
+                "`sort` shadows the prelude function `sort` with signature `(<-tables:"
+            ),
+            "{}",
+            diagnostic.message
+        );
+    }
+
+    #[test]
+    fn unused_function_parameters() {
+        let fluxscript = r#"scale = (x, factor, _offset) => x * 2
+keep = (r, column) => true
+passed = (tables=<-, fn) => tables
+double = (v, unused) => v * 2
+
+scale(x: 1, factor: 3)
+data = from(bucket: "a") |> range(start: -1h)
+passed(tables: data, fn: double)
+"#;
+        let package = get_package(&fluxscript);
+
+        let diagnostics = unused_parameters(&package);
+
+        assert_eq!(
+            vec![
+                (
+                    lsp::Range {
+                        start: lsp::Position::new(0, 12),
+                        end: lsp::Position::new(0, 18),
+                    },
+                    "The parameter `factor` of `scale` is never used."
+                        .to_string()
+                ),
+                (
+                    lsp::Range {
+                        start: lsp::Position::new(1, 11),
+                        end: lsp::Position::new(1, 17),
+                    },
+                    "The parameter `column` of `keep` is never used."
+                        .to_string()
+                ),
+                (
+                    lsp::Range {
+                        start: lsp::Position::new(2, 21),
+                        end: lsp::Position::new(2, 23),
+                    },
+                    "The parameter `fn` of `passed` is never used."
+                        .to_string()
+                ),
+            ],
+            diagnostics
+                .into_iter()
+                .map(|(_, diagnostic)| (
+                    diagnostic.range,
+                    diagnostic.message
+                ))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn shadowed_variables_in_functions() {
+        let fluxscript = r#"env = "prod"
+x = 1
+
+f = (env) => {
+    x = 2
+    y = 3
+    return env + string(v: x + y)
+}
+g = () => {
+    y = 4
+    return y
+}
+"#;
+        let package = get_package(&fluxscript);
+        let urls =
+            vec![lsp::Url::parse("file:///script.flux").unwrap()];
+
+        let diagnostics = shadowed_variables(&package, &urls);
+
+        assert_eq!(
+            vec![(3, 5, 0), (4, 4, 1)],
+            diagnostics
+                .iter()
+                .map(|(_, diagnostic)| {
+                    let related = diagnostic
+                        .related_information
+                        .as_ref()
+                        .unwrap();
+                    (
+                        diagnostic.range.start.line,
+                        diagnostic.range.start.character,
+                        related[0].location.range.start.line,
+                    )
+                })
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn writes_to_the_bucket_read() {
+        let fluxscript = r#"from(bucket: "telegraf")
+    |> range(start: -1h)
+    |> aggregateWindow(every: 5m, fn: mean)
+    |> to(bucket: "telegraf")
+from(bucket: "telegraf")
+    |> range(start: -1h)
+    |> filter(fn: (r) => r._measurement == "cpu")
+    |> set(key: "_measurement", value: "cpu_5m")
+    |> to(bucket: "telegraf")
+from(bucket: "telegraf")
+    |> range(start: -1h)
+    |> to(bucket: "downsampled")
+"#;
+        let package = get_package(&fluxscript);
+
+        let diagnostics = same_bucket_writes(&package);
+
+        assert_eq!(
+            vec![(
+                3,
+                Some(lsp::NumberOrString::String(
+                    SAME_BUCKET_WRITE.into()
+                ))
+            )],
+            diagnostics
+                .into_iter()
+                .map(|(_, diagnostic)| (
+                    diagnostic.range.start.line,
+                    diagnostic.code
+                ))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn import_collisions_in_file() {
+        let fluxscript = r#"import "influxdata/influxdb/schema"
+import "influxdata/influxdb/v1/schema"
+import strings "regexp"
+import "strings"
+import "array"
+"#;
+        // Flux may reject the colliding imports, so use whatever package it salvages.
+        let package = get_salvaged_package("script.flux", fluxscript);
+
+        let diagnostics = import_collisions(&package);
+
+        assert_eq!(
+            vec![1, 3],
+            diagnostics
+                .iter()
+                .map(|(_, diagnostic)| diagnostic.range.start.line)
+                .collect::<Vec<u32>>()
+        );
+        assert_eq!(
+            "The import of `influxdata/influxdb/v1/schema` is named `schema`, like the import of `influxdata/influxdb/schema`. Add an alias to tell them apart.",
+            diagnostics[0].1.message
+        );
+    }
+
+    #[test]
+    fn duplicate_imports_in_file() {
+        let fluxscript = r#"import "strings"
+import "array"
+import "strings"
+import str "strings"
+"#;
+        let package = get_salvaged_package("script.flux", fluxscript);
+
+        let diagnostics = import_collisions(&package);
+
+        // An alias makes a second import of a package distinct.
+        assert_eq!(
+            vec![(
+                2,
+                Some(lsp::NumberOrString::String(
+                    DUPLICATE_IMPORT.into()
+                )),
+                "`strings` is already imported."
+            )],
+            diagnostics
+                .iter()
+                .map(|(_, diagnostic)| (
+                    diagnostic.range.start.line,
+                    diagnostic.code.clone(),
+                    diagnostic.message.as_str()
+                ))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn suggest_import_alias_avoids_taken_names() {
+        assert_eq!(
+            "v1Schema",
+            suggest_import_alias(
+                "influxdata/influxdb/v1/schema",
+                &[]
+            )
+        );
+        assert_eq!(
+            "bonitooIoAlerta",
+            suggest_import_alias("contrib/bonitoo-io/alerta", &[])
+        );
+        assert_eq!(
+            "stringsPkg2",
+            suggest_import_alias("strings", &["stringsPkg".into()])
+        );
+    }
+
+    #[test]
+    fn unnamed_results_in_script() {
+        let fluxscript = r#"from(bucket: "a") |> range(start: -1h)
+from(bucket: "b") |> range(start: -1h) |> yield(name: "result_1")
+x = from(bucket: "c") |> range(start: -1h)
+from(bucket: "d")
+    |> range(start: -1h)
+"#;
+        let package = get_package(&fluxscript);
+
+        let diagnostics = unnamed_results(&package);
+
+        assert_eq!(
+            vec![(0, 0, 0, 38), (3, 0, 4, 24)],
+            diagnostics
+                .iter()
+                .map(|(_, diagnostic)| (
+                    diagnostic.range.start.line,
+                    diagnostic.range.start.character,
+                    diagnostic.range.end.line,
+                    diagnostic.range.end.character
+                ))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            "This script has 3 results, and this one has no name. Name it with `yield(name: \"result_3\")` to tell it apart from the others.",
+            diagnostics[1].1.message
+        );
+    }
+
+    #[test]
+    fn unnamed_results_single_result() {
+        let fluxscript = r#"from(bucket: "a") |> range(start: -1h)
+x = 1
+"#;
+        let package = get_package(&fluxscript);
+
+        assert!(unnamed_results(&package).is_empty());
+    }
+
+    #[test]
+    fn aggregate_window_create_empty_in_task() {
+        let fluxscript = r#"option task = {name: "downsample", every: 1h}
+
+from(bucket: "a")
+    |> range(start: -task.every)
+    |> aggregateWindow(every: 5m, fn: mean)
+    |> to(bucket: "b")
+
+from(bucket: "a")
+    |> range(start: -task.every)
+    |> aggregateWindow(every: 5m, fn: max, createEmpty: true)
+    |> to(bucket: "c")
+"#;
+        let package = get_package(&fluxscript);
+
+        let diagnostics = aggregate_window_create_empty(&package);
+
+        assert_eq!(
+            vec![4],
+            diagnostics
+                .iter()
+                .map(|(_, diagnostic)| diagnostic.range.start.line)
+                .collect::<Vec<u32>>()
+        );
+    }
+
+    #[test]
+    fn aggregate_window_create_empty_without_task() {
+        let fluxscript = r#"from(bucket: "a")
+    |> range(start: -1h)
+    |> aggregateWindow(every: 5m, fn: mean)
+    |> to(bucket: "b")
+"#;
+        let package = get_package(&fluxscript);
+
+        assert!(aggregate_window_create_empty(&package).is_empty());
+    }
+
     #[test]
     fn prefer_camel_case_in_identifiers() {
         let fluxscript = r#"my_snake_case = 10"#;
@@ -326,8 +1876,168 @@ params = "abc123"
                 },
             },
             severity: Some(lsp::DiagnosticSeverity::INFORMATION),
+            code: Some(lsp::NumberOrString::String(CAMEL_CASE.into())),
             message: "Idiomatic flux uses camel case for identifier names. Consider renaming this identifier `mySnakeCase`".into(),
             ..lsp::Diagnostic::default()
         })], diagnostics);
     }
+
+    #[test]
+    fn suppressed_lines_from_comments() {
+        let fluxscript = r#"// flux-lsp:ignore-next-line type-error, camel-case
+my_snake_case = x
+s = "// flux-lsp:ignore-next-line"
+    // flux-lsp:ignore-next-line
+y = z
+// flux-lsp:ignore-next-linefoo
+w = 1"#;
+        let file = flux::parser::parse_string(
+            "script.flux".into(),
+            fluxscript,
+        );
+
+        let suppressed = suppressed_lines(&file, fluxscript);
+
+        assert_eq!(
+            HashMap::from([
+                (
+                    1,
+                    vec![
+                        "type-error".to_string(),
+                        "camel-case".to_string()
+                    ]
+                ),
+                (4, vec![]),
+            ]),
+            suppressed
+        );
+        assert!(is_suppressed(&suppressed, 1, CAMEL_CASE));
+        assert!(!is_suppressed(&suppressed, 1, INFLUXDB_IDENTIFIER));
+        assert!(is_suppressed(&suppressed, 4, TYPE_ERROR));
+        assert!(!is_suppressed(&suppressed, 6, TYPE_ERROR));
+    }
+
+    #[cfg(feature = "docs")]
+    #[test]
+    fn error_descriptions() {
+        let href = |message| {
+            error_description(message)
+                .map(|description| description.href.to_string())
+        };
+
+        assert_eq!(
+            Some("https://docs.influxdata.com/flux/v0.x/data-types/composite/record/".to_string()),
+            href("record is missing label _value")
+        );
+        assert_eq!(
+            Some("https://docs.influxdata.com/flux/v0.x/spec/expressions/#call-expressions".to_string()),
+            href("missing required argument bucket (argument tables)")
+        );
+        assert_eq!(
+            Some(
+                "https://docs.influxdata.com/flux/v0.x/spec/types/"
+                    .to_string()
+            ),
+            href("expected int but found string")
+        );
+        assert_eq!(None, href("undefined identifier v"));
+    }
+
+    #[test]
+    fn missing_label_from_message() {
+        assert_eq!(
+            Some("_value"),
+            missing_label("record is missing label _value")
+        );
+        assert_eq!(None, missing_label("undefined identifier v"));
+    }
+
+    #[test]
+    fn related_definitions_in_other_files() {
+        let mut pkg: ast::Package = flux::parser::parse_string(
+            "script.flux".into(),
+            r#"x = a + 1"#,
+        )
+        .into();
+        let mut other: ast::Package = flux::parser::parse_string(
+            "vars.flux".into(),
+            r#"a = "b""#,
+        )
+        .into();
+        flux::merge_packages(&mut pkg, &mut other).unwrap();
+
+        let urls = vec![
+            lsp::Url::parse("file:///path/to/script.flux").unwrap(),
+            lsp::Url::parse("file:///path/to/vars.flux").unwrap(),
+        ];
+        let location = ast::SourceLocation {
+            file: Some("script.flux".into()),
+            start: ast::Position { line: 1, column: 5 },
+            end: ast::Position {
+                line: 1,
+                column: 10,
+            },
+            source: None,
+        };
+
+        let related = related_definitions(&pkg, &location, &urls);
+
+        assert_eq!(
+            Some(vec![lsp::DiagnosticRelatedInformation {
+                location: lsp::Location {
+                    uri: urls[1].clone(),
+                    range: lsp::Range {
+                        start: lsp::Position {
+                            line: 0,
+                            character: 0
+                        },
+                        end: lsp::Position {
+                            line: 0,
+                            character: 1
+                        },
+                    },
+                },
+                message: "`a` is defined here".into(),
+            }]),
+            related
+        );
+    }
+
+    #[test]
+    fn related_definitions_ignores_local_names() {
+        let pkg: ast::Package = flux::parser::parse_string(
+            "script.flux".into(),
+            r#"a = "b"
+x = a + 1"#,
+        )
+        .into();
+        let urls =
+            vec![lsp::Url::parse("file:///path/to/script.flux")
+                .unwrap()];
+        let location = ast::SourceLocation {
+            file: Some("script.flux".into()),
+            start: ast::Position { line: 2, column: 5 },
+            end: ast::Position {
+                line: 2,
+                column: 10,
+            },
+            source: None,
+        };
+
+        assert_eq!(None, related_definitions(&pkg, &location, &urls));
+    }
+
+    #[test]
+    fn lint_rules_are_distinct() {
+        let mut ids: Vec<&str> =
+            LINT_RULES.iter().map(|rule| rule.id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+
+        assert_eq!(LINT_RULES.len(), ids.len());
+        assert!(LINT_RULES.iter().all(|rule| matches!(
+            parse_severity(rule.default_severity),
+            Some(Some(_))
+        )));
+    }
 }
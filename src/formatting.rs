@@ -0,0 +1,503 @@
+/// Style options applied on top of the flux formatter
+///
+/// The flux formatter has no options of its own, so teams standardizing their style
+/// configure it in the server. Pipelines are broken into one stage per line, either
+/// always with the `formatStagePerLine` setting, or when their line is wider than the
+/// `formatMaxLineWidth` setting. Stages are then indented by four spaces from the line
+/// the pipeline starts on, as the formatter lays out pipelines already broken.
+///
+/// The same breaks are made in a single statement by the action toggling its pipelines
+/// between one line and one stage per line, which also joins them back.
+///
+/// The options of `textDocument/formatting` are applied last. Indentation is redone
+/// with tabs, or with the number of spaces editors are configured with (e.g. by an
+/// `.editorconfig`), and the final newlines are inserted or trimmed.
+use std::collections::HashSet;
+
+use flux::ast::{self, walk};
+use lspower::lsp;
+
+use crate::convert;
+
+/// The indentation of the stages of a pipeline, after that of its first line.
+const STAGE_INDENT: &str = "    ";
+
+/// The number of spaces the flux formatter indents each level by.
+const FLUX_INDENT_WIDTH: usize = 4;
+
+/// How formatted documents are styled, from the `formatStagePerLine` and
+/// `formatMaxLineWidth` settings.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct FormatStyle {
+    pub(crate) stage_per_line: bool,
+    pub(crate) max_line_width: Option<usize>,
+}
+
+/// Collect the outermost pipe expressions of a file, which hold whole pipelines.
+#[derive(Default)]
+struct PipelineVisitor<'a> {
+    pipelines: Vec<&'a ast::PipeExpr>,
+}
+
+impl<'a> walk::Visitor<'a> for PipelineVisitor<'a> {
+    fn visit(&mut self, node: walk::Node<'a>) -> bool {
+        if let walk::Node::PipeExpr(pipe) = node {
+            self.pipelines.push(pipe);
+            return false;
+        }
+        true
+    }
+}
+
+/// The text between the stages of a pipeline, i.e. the `|>` along with the whitespace
+/// and comments around it, by its start and end offsets.
+fn stage_gaps(
+    line_starts: &[usize],
+    pipeline: &ast::PipeExpr,
+) -> Vec<(usize, usize)> {
+    let mut gaps = vec![];
+    let mut pipe = pipeline;
+    loop {
+        gaps.push((
+            convert::offset(
+                line_starts,
+                &pipe.argument.base().location.end,
+            ),
+            convert::offset(
+                line_starts,
+                &pipe.call.base.location.start,
+            ),
+        ));
+        match &pipe.argument {
+            ast::Expression::PipeExpr(argument) => {
+                pipe = argument.as_ref()
+            }
+            _ => break,
+        }
+    }
+    gaps
+}
+
+/// The whitespace before each `|>` of a pipeline to replace with a line break, and the
+/// indentation following it.
+fn stage_breaks(
+    source: &str,
+    lines: &[&str],
+    line_starts: &[usize],
+    pipeline: &ast::PipeExpr,
+) -> Vec<(usize, usize, String)> {
+    let line = lines
+        .get(convert::line_index(&pipeline.base.location.start))
+        .unwrap_or(&"");
+    let indent = format!(
+        "{}{}",
+        &line[..line.len() - line.trim_start().len()],
+        STAGE_INDENT
+    );
+    stage_gaps(line_starts, pipeline)
+        .into_iter()
+        .filter_map(|(after, before)| {
+            let operator = source.get(after..before)?.find("|>")?;
+            Some((after, after + operator, indent.clone()))
+        })
+        .collect()
+}
+
+/// Copy a span of a source, with parts of it replaced.
+fn replace_spans(
+    source: &str,
+    (start, end): (usize, usize),
+    mut replacements: Vec<(usize, usize, String)>,
+) -> String {
+    replacements.sort_by_key(|(start, _, _)| *start);
+    let mut replaced = String::with_capacity(end - start);
+    let mut copied = start;
+    for (start, end, text) in replacements {
+        replaced.push_str(&source[copied..start]);
+        replaced.push_str(&text);
+        copied = end;
+    }
+    replaced.push_str(&source[copied..end]);
+    replaced
+}
+
+/// Break the pipelines of formatted source into one stage per line, as the style asks.
+///
+/// Only the pipelines written on a single line are broken, and nested pipelines, e.g.
+/// in the arguments of a stage, are left as they are.
+pub(crate) fn apply_style(
+    source: &str,
+    style: FormatStyle,
+) -> String {
+    if style == FormatStyle::default() {
+        return source.to_string();
+    }
+    let file = flux::parser::parse_string("".into(), source);
+    let mut visitor = PipelineVisitor::default();
+    walk::walk(&mut visitor, walk::Node::File(&file));
+
+    let lines: Vec<&str> = source.split('\n').collect();
+    let line_starts = convert::line_starts(source);
+    let mut breaks: Vec<(usize, usize, String)> = vec![];
+    for pipeline in visitor.pipelines {
+        let (start, end) = (
+            &pipeline.base.location.start,
+            &pipeline.base.location.end,
+        );
+        if start.line != end.line {
+            continue;
+        }
+        let line =
+            lines.get(convert::line_index(start)).unwrap_or(&"");
+        let too_wide = style
+            .max_line_width
+            .map_or(false, |width| line.chars().count() > width);
+        if !style.stage_per_line && !too_wide {
+            continue;
+        }
+        breaks.extend(stage_breaks(
+            source,
+            &lines,
+            &line_starts,
+            pipeline,
+        ));
+    }
+
+    replace_spans(
+        source,
+        (0, source.len()),
+        breaks
+            .into_iter()
+            .map(|(start, end, indent)| {
+                (start, end, format!("\n{}", indent))
+            })
+            .collect(),
+    )
+}
+
+/// A statement with its pipelines laid out the other way.
+pub(crate) struct ToggledPipelines {
+    /// Whether the pipelines are broken into one stage per line, rather than joined
+    /// onto one line.
+    pub(crate) broken: bool,
+    pub(crate) new_text: String,
+}
+
+/// Toggle the pipelines of a statement between one line and one stage per line.
+///
+/// Pipelines with a stage on a line of its own are joined onto the line they start on,
+/// and otherwise they are broken into one stage per line. Nested pipelines, e.g. in the
+/// arguments of a stage, are left as they are, and so are the lines within stages.
+/// Pipelines with comments between their stages can't be joined, as the comments would
+/// swallow the stages after them.
+pub(crate) fn toggle_pipelines(
+    source: &str,
+    statement: &ast::Statement,
+) -> Option<ToggledPipelines> {
+    let mut visitor = PipelineVisitor::default();
+    walk::walk(&mut visitor, walk::Node::from_stmt(statement));
+    if visitor.pipelines.is_empty() {
+        return None;
+    }
+
+    let lines: Vec<&str> = source.split('\n').collect();
+    let line_starts = convert::line_starts(source);
+    let location = &statement.base().location;
+    let span = (
+        convert::offset(&line_starts, &location.start),
+        convert::offset(&line_starts, &location.end),
+    );
+    let gaps: Vec<(usize, usize)> = visitor
+        .pipelines
+        .iter()
+        .flat_map(|pipeline| stage_gaps(&line_starts, pipeline))
+        .collect();
+    let gap_text = |(after, before): &(usize, usize)| {
+        source.get(*after..*before)
+    };
+
+    if gaps.iter().any(|gap| {
+        gap_text(gap).map_or(false, |text| text.contains('\n'))
+    }) {
+        if gaps.iter().any(|gap| {
+            gap_text(gap).map_or(true, |text| text.contains("//"))
+        }) {
+            return None;
+        }
+        let joins = gaps
+            .into_iter()
+            .map(|(after, before)| {
+                (after, before, " |> ".to_string())
+            })
+            .collect();
+        return Some(ToggledPipelines {
+            broken: false,
+            new_text: replace_spans(source, span, joins),
+        });
+    }
+
+    let breaks = visitor
+        .pipelines
+        .iter()
+        .flat_map(|pipeline| {
+            stage_breaks(source, &lines, &line_starts, pipeline)
+        })
+        .map(|(start, end, indent)| {
+            (start, end, format!("\n{}", indent))
+        })
+        .collect();
+    Some(ToggledPipelines {
+        broken: true,
+        new_text: replace_spans(source, span, breaks),
+    })
+}
+
+/// Collect the lines, as indexes, that continue a string, whose indentation is part of
+/// its value.
+#[derive(Default)]
+struct StringLinesVisitor {
+    lines: HashSet<u32>,
+}
+
+impl<'a> walk::Visitor<'a> for StringLinesVisitor {
+    fn visit(&mut self, node: walk::Node<'a>) -> bool {
+        let location = match node {
+            walk::Node::StringLit(lit) => &lit.base.location,
+            walk::Node::StringExpr(expr) => &expr.base.location,
+            _ => return true,
+        };
+        let range = convert::location_to_range(location);
+        self.lines.extend(range.start.line + 1..=range.end.line);
+        true
+    }
+}
+
+/// Indent formatted source with tabs, or with a number of spaces per level, rather
+/// than the four spaces of the flux formatter.
+///
+/// Clients always send a tab size, so a tab size of zero is taken as no preference
+/// and leaves the indentation as it is. Spaces that don't make up a whole level, e.g.
+/// aligning the continued conditions of a filter, are kept after the new indentation.
+fn reindent(
+    source: &str,
+    tab_size: u32,
+    insert_spaces: bool,
+) -> String {
+    if tab_size == 0
+        || (insert_spaces && tab_size as usize == FLUX_INDENT_WIDTH)
+    {
+        return source.to_string();
+    }
+    let level = if insert_spaces {
+        " ".repeat(tab_size as usize)
+    } else {
+        "\t".to_string()
+    };
+
+    let file = flux::parser::parse_string("".into(), source);
+    let mut visitor = StringLinesVisitor::default();
+    walk::walk(&mut visitor, walk::Node::File(&file));
+
+    source
+        .split('\n')
+        .enumerate()
+        .map(|(index, line)| {
+            if visitor.lines.contains(&(index as u32)) {
+                return line.to_string();
+            }
+            let text = line.trim_start_matches(' ');
+            let spaces = line.len() - text.len();
+            format!(
+                "{}{}{}",
+                level.repeat(spaces / FLUX_INDENT_WIDTH),
+                " ".repeat(spaces % FLUX_INDENT_WIDTH),
+                text
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Insert the final newline missing from source, or trim the newlines after its
+/// final one.
+fn final_newlines(source: &str, insert: bool, trim: bool) -> String {
+    let mut text = source.to_string();
+    if trim {
+        let end = text.trim_end_matches('\n').len();
+        if end < text.len() {
+            text.truncate(end + 1);
+        }
+    }
+    if insert && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text
+}
+
+/// Lay formatted source out as the options of `textDocument/formatting` ask.
+///
+/// The flux formatter always trims trailing whitespace, so whether it is asked to be
+/// trimmed makes no difference.
+pub(crate) fn apply_options(
+    source: &str,
+    options: &lsp::FormattingOptions,
+) -> String {
+    final_newlines(
+        &reindent(source, options.tab_size, options.insert_spaces),
+        options.insert_final_newline.unwrap_or(false),
+        options.trim_final_newlines.unwrap_or(false),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stage_per_line() {
+        let source = r#"data = from(bucket: "a") |> range(start: -1h) |> mean()
+x = 1
+"#;
+
+        assert_eq!(
+            r#"data = from(bucket: "a")
+    |> range(start: -1h)
+    |> mean()
+x = 1
+"#,
+            apply_style(
+                source,
+                FormatStyle {
+                    stage_per_line: true,
+                    max_line_width: None,
+                }
+            )
+        );
+        assert_eq!(
+            source,
+            apply_style(source, FormatStyle::default())
+        );
+    }
+
+    #[test]
+    fn max_line_width() {
+        let source = r#"from(bucket: "a") |> range(start: -1h)
+from(bucket: "telegraf") |> range(start: -1h) |> filter(fn: (r) => r._measurement == "cpu")
+"#;
+
+        assert_eq!(
+            r#"from(bucket: "a") |> range(start: -1h)
+from(bucket: "telegraf")
+    |> range(start: -1h)
+    |> filter(fn: (r) => r._measurement == "cpu")
+"#,
+            apply_style(
+                source,
+                FormatStyle {
+                    stage_per_line: false,
+                    max_line_width: Some(80),
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn toggled_pipelines() {
+        let toggle = |source: &str| {
+            let file = flux::parser::parse_string("".into(), source);
+            toggle_pipelines(source, &file.body[1])
+                .map(|toggled| (toggled.broken, toggled.new_text))
+        };
+
+        assert_eq!(
+            Some((
+                true,
+                r#"data = from(bucket: "a")
+    |> range(start: -1h)
+    |> mean()"#
+                    .to_string()
+            )),
+            toggle(
+                r#"x = 1
+data = from(bucket: "a") |> range(start: -1h) |> mean()
+y = 2
+"#
+            )
+        );
+        assert_eq!(
+            Some((
+                false,
+                r#"data = from(bucket: "a") |> range(start: -1h) |> mean()"#
+                    .to_string()
+            )),
+            toggle(
+                r#"x = 1
+data = from(bucket: "a")
+    |> range(start: -1h)
+    |> mean()
+"#
+            )
+        );
+        assert_eq!(
+            None,
+            toggle(
+                r#"x = 1
+data = from(bucket: "a")
+    // The last hour.
+    |> range(start: -1h)
+"#
+            )
+        );
+        assert_eq!(None, toggle("x = 1\ny = 2\n"));
+    }
+
+    #[test]
+    fn indentation_options() {
+        let source = r#"f = () => {
+    s = "a
+    b"
+
+    return from(bucket: "a")
+        |> filter(fn: (r) => r.a == 1
+          and r.b == 2)
+}
+"#;
+
+        assert_eq!(
+            "f = () => {\n\ts = \"a\n    b\"\n\n\treturn from(bucket: \"a\")\n\t\t|> filter(fn: (r) => r.a == 1\n\t\t  and r.b == 2)\n}\n",
+            reindent(source, 4, false)
+        );
+        assert_eq!(
+            r#"f = () => {
+  s = "a
+    b"
+
+  return from(bucket: "a")
+    |> filter(fn: (r) => r.a == 1
+      and r.b == 2)
+}
+"#,
+            reindent(source, 2, true)
+        );
+        assert_eq!(source, reindent(source, 4, true));
+        assert_eq!(source, reindent(source, 0, false));
+    }
+
+    #[test]
+    fn final_newline_options() {
+        assert_eq!("x = 1\n", final_newlines("x = 1", true, false));
+        assert_eq!("x = 1", final_newlines("x = 1", false, false));
+        assert_eq!(
+            "x = 1\n",
+            final_newlines("x = 1\n\n\n", false, true)
+        );
+        assert_eq!(
+            "x = 1\n",
+            final_newlines("x = 1\n\n", true, true)
+        );
+        assert_eq!(
+            "x = 1\n\n",
+            final_newlines("x = 1\n\n", true, false)
+        );
+    }
+}
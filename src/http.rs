@@ -0,0 +1,265 @@
+/// Completion and checks of the arguments of HTTP functions
+///
+/// Calls of the `http` and `http/requests` packages, like `http.post` and
+/// `requests.get`, have their `url` checked when it's a string literal, as invalid
+/// urls only fail when a query runs. Urls sent unencrypted over `http://` are reported
+/// too when the `warnInsecureHttp` setting is enabled. Their `headers` records are
+/// completed with common header names, whether they are records, like `http.post` takes,
+/// or dictionaries, like `requests.get` takes.
+use flux::ast;
+use flux::ast::walk::Node as AstNode;
+use flux::semantic::nodes::{
+    CallExpr, Expression, Package, StringLit,
+};
+use flux::semantic::walk::Node as WalkNode;
+use lspower::lsp;
+
+use crate::convert;
+use crate::visitors::ast::{property_name, NodeFinderNode};
+
+/// The diagnostic code of urls that can't be parsed.
+pub(crate) const INVALID_URL: &str = "invalid-url";
+/// The diagnostic code of urls sending requests unencrypted.
+pub(crate) const INSECURE_HTTP: &str = "insecure-http";
+
+/// The names packages making HTTP requests are imported as.
+const HTTP_PACKAGES: &[&str] = &["http", "requests"];
+
+/// Header names commonly sent with requests.
+pub(crate) const HEADERS: &[&str] = &[
+    "Accept",
+    "Accept-Encoding",
+    "Accept-Language",
+    "Authorization",
+    "Cache-Control",
+    "Content-Encoding",
+    "Content-Type",
+    "Cookie",
+    "If-Match",
+    "If-None-Match",
+    "Origin",
+    "User-Agent",
+    "X-Api-Key",
+    "X-Request-Id",
+];
+
+/// The `url` argument of a call of an HTTP function, when it's a string literal.
+fn url_argument(call: &CallExpr) -> Option<&StringLit> {
+    let is_http = match &call.callee {
+        Expression::Member(member) => {
+            matches!(&member.object, Expression::Identifier(ident) if HTTP_PACKAGES.contains(&ident.name.as_str()))
+        }
+        _ => false,
+    };
+    if !is_http {
+        return None;
+    }
+    call.arguments
+        .iter()
+        .find(|argument| argument.key.name == "url")
+        .and_then(|argument| match &argument.value {
+            Expression::StringLit(lit) => Some(lit),
+            _ => None,
+        })
+}
+
+#[derive(Default)]
+struct UrlVisitor<'a> {
+    urls: Vec<&'a StringLit>,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a> for UrlVisitor<'a> {
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        if let WalkNode::CallExpr(call) = node {
+            self.urls.extend(url_argument(call));
+        }
+        true
+    }
+}
+
+fn url_diagnostic(
+    lit: &StringLit,
+    code: &str,
+    message: String,
+) -> (Option<String>, lsp::Diagnostic) {
+    (
+        lit.loc.file.clone(),
+        lsp::Diagnostic {
+            range: convert::location_to_range(&lit.loc),
+            severity: Some(lsp::DiagnosticSeverity::WARNING),
+            code: Some(lsp::NumberOrString::String(code.into())),
+            message,
+            ..lsp::Diagnostic::default()
+        },
+    )
+}
+
+/// Urls of HTTP functions that can't be parsed, or that are insecure when asked to
+/// warn about them.
+pub(crate) fn url_diagnostics(
+    pkg: &Package,
+    warn_insecure: bool,
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    let visitor =
+        crate::walk_semantic_package!(UrlVisitor::default(), pkg);
+    visitor
+        .urls
+        .into_iter()
+        .filter_map(|lit| match lsp::Url::parse(&lit.value) {
+            Err(err) => Some(url_diagnostic(
+                lit,
+                INVALID_URL,
+                format!("\"{}\" is not a valid URL: {}.", lit.value, err),
+            )),
+            Ok(url) if warn_insecure && url.scheme() == "http" => {
+                Some(url_diagnostic(
+                    lit,
+                    INSECURE_HTTP,
+                    format!(
+                        "Requests to \"{}\" are sent unencrypted, use https instead.",
+                        lit.value
+                    ),
+                ))
+            }
+            Ok(_) => None,
+        })
+        .collect()
+}
+
+/// Whether a record or dictionary is the `headers` argument of a call of an HTTP
+/// function.
+fn is_headers(headers: &NodeFinderNode) -> bool {
+    if !matches!(
+        headers.node,
+        AstNode::ObjectExpr(_) | AstNode::DictExpr(_)
+    ) {
+        return false;
+    }
+    let property = match headers.parent.as_deref() {
+        Some(property) => property,
+        None => return false,
+    };
+    match &property.node {
+        AstNode::Property(property)
+            if property_name(&property.key) == "headers" => {}
+        _ => return false,
+    }
+    let call = match property
+        .parent
+        .as_deref()
+        .and_then(|arguments| arguments.parent.as_deref())
+        .map(|call| &call.node)
+    {
+        Some(AstNode::CallExpr(call)) => call,
+        _ => return false,
+    };
+    matches!(&call.callee, ast::Expression::Member(member)
+        if matches!(&member.object, ast::Expression::Identifier(ident) if HTTP_PACKAGES.contains(&ident.name.as_str())))
+}
+
+/// Whether the node being completed is in the headers of a call of an HTTP function:
+/// the headers themselves, or a header name being written as a string.
+pub(crate) fn is_completed_headers(node: &NodeFinderNode) -> bool {
+    let lit = match &node.node {
+        AstNode::ObjectExpr(_) | AstNode::DictExpr(_) => {
+            return is_headers(node)
+        }
+        AstNode::StringLit(lit) => lit,
+        _ => return false,
+    };
+    // The key of a record property, or of a dictionary item, which may be walked as
+    // a node of its own.
+    let is_key = |ancestor: &NodeFinderNode| {
+        match &ancestor.node {
+        AstNode::Property(property) => {
+            matches!(&property.key, ast::PropertyKey::StringLit(key) if key.base.location == lit.base.location)
+        }
+        AstNode::DictExpr(dict) => dict.elements.iter().any(|item| {
+            matches!(&item.key, ast::Expression::StringLit(key) if key.base.location == lit.base.location)
+        }),
+        _ => false,
+    }
+    };
+    let mut ancestor = node.parent.as_deref();
+    for _ in 0..2 {
+        match ancestor {
+            Some(parent) if is_key(parent) => {
+                return match parent.node {
+                    AstNode::DictExpr(_) => is_headers(parent),
+                    _ => parent
+                        .parent
+                        .as_deref()
+                        .map_or(false, is_headers),
+                };
+            }
+            Some(parent) => ancestor = parent.parent.as_deref(),
+            None => return false,
+        }
+    }
+    false
+}
+
+/// Completion items of header names, inserted as the keys of headers.
+pub(crate) fn header_items() -> Vec<lsp::CompletionItem> {
+    HEADERS
+        .iter()
+        .map(|header| lsp::CompletionItem {
+            label: header.to_string(),
+            detail: Some("header".into()),
+            insert_text: Some(format!("\"{}\": ", header)),
+            kind: Some(lsp::CompletionItemKind::PROPERTY),
+            ..lsp::CompletionItem::default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::get_package;
+
+    #[test]
+    fn urls_of_http_functions() {
+        let fluxscript = r#"import "http"
+import "http/requests"
+
+http.post(url: "https://example.com/alert", data: bytes(v: "alert"))
+requests.get(url: "not a url")
+requests.get(url: "http://example.com")
+"#;
+        let package = get_package(fluxscript);
+        let codes = |warn_insecure| {
+            url_diagnostics(&package, warn_insecure)
+                .into_iter()
+                .map(|(_, diagnostic)| {
+                    (diagnostic.range.start.line, diagnostic.code)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            vec![(
+                4,
+                Some(lsp::NumberOrString::String(INVALID_URL.into()))
+            )],
+            codes(false)
+        );
+        assert_eq!(
+            vec![
+                (
+                    4,
+                    Some(lsp::NumberOrString::String(
+                        INVALID_URL.into()
+                    ))
+                ),
+                (
+                    5,
+                    Some(lsp::NumberOrString::String(
+                        INSECURE_HTTP.into()
+                    ))
+                ),
+            ],
+            codes(true)
+        );
+    }
+}
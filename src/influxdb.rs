@@ -0,0 +1,371 @@
+/// Schema queries against an InfluxDB instance, for native builds
+///
+/// Desktop editors rarely have a client extension pushing the schema with
+/// `flux/updateSchema`. When the `influxdb` setting holds the url, org and token of
+/// an instance, the names offered for completion are queried from it with the
+/// `influxdata/influxdb/schema` package instead. Query results are cached for a
+/// while, so completing doesn't query the instance on every keystroke.
+///
+/// The token is never logged: it's wrapped in a `Secret` which is redacted when
+/// formatted, and scrubbed from the messages of failed queries. It can be read from
+/// an environment variable named with `tokenEnv` rather than be written in the settings.
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::annotated_csv::fields;
+use crate::schema::NameKind;
+
+/// How long query results are reused when the `cacheTtl` setting is missing.
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60);
+
+const REDACTED: &str = "[redacted]";
+
+/// A value that must not appear in logs or error messages.
+#[derive(Clone, Deserialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub(crate) struct Secret(String);
+
+impl Secret {
+    pub(crate) fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Scrub the secret from a message.
+    pub(crate) fn redact(&self, message: &str) -> String {
+        if self.0.is_empty() {
+            message.into()
+        } else {
+            message.replace(&self.0, REDACTED)
+        }
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+/// The `influxdb` setting.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Connection {
+    pub url: String,
+    pub org: String,
+    #[serde(default)]
+    pub token: Option<Secret>,
+    /// The name of the environment variable holding the token, used when `token`
+    /// is missing.
+    #[serde(default)]
+    pub token_env: Option<String>,
+    /// The number of seconds query results are reused for.
+    #[serde(default)]
+    pub cache_ttl: Option<u64>,
+}
+
+impl Connection {
+    /// The token to authenticate with, from the settings or the environment.
+    pub(crate) fn token(&self) -> Option<Secret> {
+        self.token.clone().or_else(|| {
+            self.token_env
+                .as_ref()
+                .and_then(|name| std::env::var(name).ok())
+                .map(Secret)
+        })
+    }
+}
+
+pub(crate) struct SchemaQuerier {
+    connection: Connection,
+    /// The token, resolved once so the environment is only read on connecting.
+    token: Option<Secret>,
+    client: reqwest::Client,
+    cache: Mutex<HashMap<String, (Instant, Vec<String>)>>,
+}
+
+impl SchemaQuerier {
+    pub(crate) fn new(connection: Connection) -> Self {
+        let token = connection.token();
+        if token.is_none() {
+            log::warn!(
+                "No token to query {} with, queries are sent unauthenticated",
+                connection.url
+            );
+        }
+        Self {
+            connection,
+            token,
+            client: reqwest::Client::new(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// The names of a kind in the instance, for the buckets and measurements a
+    /// script refers to.
+    ///
+    /// Nothing is known of measurements and fields until a bucket is referred to.
+    pub(crate) async fn names(
+        &self,
+        kind: NameKind,
+        buckets: &[String],
+        measurements: &[String],
+    ) -> Vec<String> {
+        let queries: Vec<String> = match kind {
+            NameKind::Bucket => vec![r#"buckets()
+    |> rename(columns: {name: "_value"})
+    |> keep(columns: ["_value"])"#
+                .into()],
+            NameKind::Measurement => buckets
+                .iter()
+                .map(|bucket| {
+                    format!(
+                        "import \"influxdata/influxdb/schema\"\n\nschema.measurements(bucket: {})",
+                        flux_string(bucket)
+                    )
+                })
+                .collect(),
+            NameKind::Tag if measurements.is_empty() => buckets
+                .iter()
+                .map(|bucket| {
+                    format!(
+                        "import \"influxdata/influxdb/schema\"\n\nschema.tagKeys(bucket: {})",
+                        flux_string(bucket)
+                    )
+                })
+                .collect(),
+            NameKind::Tag => buckets
+                .iter()
+                .flat_map(|bucket| {
+                    measurements.iter().map(move |measurement| {
+                        format!(
+                            "import \"influxdata/influxdb/schema\"\n\nschema.measurementTagKeys(bucket: {}, measurement: {})",
+                            flux_string(bucket),
+                            flux_string(measurement)
+                        )
+                    })
+                })
+                .collect(),
+            NameKind::Field if measurements.is_empty() => buckets
+                .iter()
+                .map(|bucket| {
+                    format!(
+                        "import \"influxdata/influxdb/schema\"\n\nschema.fieldKeys(bucket: {})",
+                        flux_string(bucket)
+                    )
+                })
+                .collect(),
+            NameKind::Field => buckets
+                .iter()
+                .flat_map(|bucket| {
+                    measurements.iter().map(move |measurement| {
+                        format!(
+                            "import \"influxdata/influxdb/schema\"\n\nschema.measurementFieldKeys(bucket: {}, measurement: {})",
+                            flux_string(bucket),
+                            flux_string(measurement)
+                        )
+                    })
+                })
+                .collect(),
+        };
+
+        let mut names = vec![];
+        for query in queries {
+            names.extend(self.query(query).await);
+        }
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// The `_value` column of the result of a query, from the cache while it's fresh.
+    ///
+    /// Failed queries are logged and treated as having no result; they aren't cached,
+    /// so they are retried on the next completion.
+    async fn query(&self, query: String) -> Vec<String> {
+        let ttl = self
+            .connection
+            .cache_ttl
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_CACHE_TTL);
+        if let Some((fetched, values)) = self
+            .cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&query)
+        {
+            if fetched.elapsed() < ttl {
+                return values.clone();
+            }
+        }
+
+        let mut request = self
+            .client
+            .post(format!(
+                "{}/api/v2/query",
+                self.connection.url.trim_end_matches('/')
+            ))
+            .query(&[("org", &self.connection.org)]);
+        if let Some(token) = &self.token {
+            request = request.header(
+                "Authorization",
+                format!("Token {}", token.expose()),
+            );
+        }
+        let response = request
+            .header("Content-Type", "application/vnd.flux")
+            .header("Accept", "application/csv")
+            .body(query.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        let body = match response {
+            Ok(response) => response.text().await,
+            Err(err) => Err(err),
+        };
+        match body {
+            Ok(body) => {
+                let values = csv_values(&body);
+                self.cache
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(query, (Instant::now(), values.clone()));
+                values
+            }
+            Err(err) => {
+                let message = format!(
+                    "Schema query against {} failed: {}",
+                    self.connection.url, err
+                );
+                log::warn!(
+                    "{}",
+                    match &self.token {
+                        Some(token) => token.redact(&message),
+                        None => message,
+                    }
+                );
+                vec![]
+            }
+        }
+    }
+}
+
+/// A flux string literal of a value.
+fn flux_string(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace("${", "\\${")
+    )
+}
+
+/// The `_value` column of every table in an annotated CSV response.
+///
+/// Each table starts with a header row, after annotation rows starting with `#`,
+/// and tables are separated by empty lines.
+fn csv_values(body: &str) -> Vec<String> {
+    let mut values = vec![];
+    let mut column: Option<Option<usize>> = None;
+    for line in body.lines().map(|line| line.trim_end_matches('\r')) {
+        if line.is_empty() {
+            column = None;
+        } else if line.starts_with('#') {
+            continue;
+        } else if let Some(column) = column {
+            if let Some(value) = column
+                .and_then(|index| fields(line).into_iter().nth(index))
+            {
+                values.push(value);
+            }
+        } else {
+            column = Some(
+                fields(line)
+                    .iter()
+                    .position(|field| field == "_value"),
+            );
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_of_annotated_csv() {
+        let body = "#datatype,string,long,string\r
+#group,false,false,false\r
+#default,_result,,\r
+,result,table,_value\r
+,,0,cpu\r
+,,0,\"disk,io\"\r
+\r
+#datatype,string,long,string\r
+,result,table,_value\r
+,,1,mem\r
+";
+
+        assert_eq!(vec!["cpu", "disk,io", "mem"], csv_values(body));
+    }
+
+    #[test]
+    fn token_is_redacted() {
+        let connection: Connection =
+            serde_json::from_value(serde_json::json!({
+                "url": "http://localhost:8086",
+                "org": "my-org",
+                "token": "s3cr3t",
+            }))
+            .unwrap();
+
+        assert!(!format!("{:?}", connection).contains("s3cr3t"));
+        assert_eq!(
+            "Token [redacted] is invalid",
+            connection
+                .token()
+                .unwrap()
+                .redact("Token s3cr3t is invalid")
+        );
+    }
+
+    #[test]
+    fn token_from_environment() {
+        std::env::set_var("FLUX_LSP_TEST_INFLUXDB_TOKEN", "s3cr3t");
+        let connection: Connection =
+            serde_json::from_value(serde_json::json!({
+                "url": "http://localhost:8086",
+                "org": "my-org",
+                "tokenEnv": "FLUX_LSP_TEST_INFLUXDB_TOKEN",
+            }))
+            .unwrap();
+
+        assert_eq!(
+            Some("s3cr3t"),
+            connection.token().as_ref().map(Secret::expose)
+        );
+    }
+
+    #[test]
+    fn flux_strings() {
+        assert_eq!(
+            r#""a \"b\" \${c}""#,
+            flux_string(r#"a "b" ${c}"#)
+        );
+    }
+}
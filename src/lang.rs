@@ -4,7 +4,8 @@
 /// The purpose of this module is to be the single source of truth for all
 /// things libflux. No other part of this library should
 use std::cmp::Ordering;
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
 
 use flux::semantic::types::MonoType;
 use lspower::lsp;
@@ -12,20 +13,50 @@ use lspower::lsp;
 use std::iter::Iterator;
 
 lazy_static::lazy_static! {
-    pub static ref STDLIB: Stdlib = Stdlib(flux::imports().expect("Could not initialize stdlib."));
+    pub static ref STDLIB: Stdlib = Stdlib::new(flux::imports().expect("Could not initialize stdlib."));
     pub static ref UNIVERSE: Package = Package::new("builtin", Arc::new(flux::prelude().expect("Could not initialize prelude")));
 }
 
+/// The number of needles whose fuzzy matches are remembered.
+const FUZZY_MATCH_CACHE_SIZE: usize = 32;
+
 /// Stdlib serves as the API for querying the flux stdlib.
 ///
 /// The flux stdlib is a collection of packages, and this interface
 /// provides a method for querying those packages.
-pub struct Stdlib(flux::semantic::import::Packages);
+pub struct Stdlib {
+    packages: flux::semantic::import::Packages,
+    /// The lowercase name of every package, along with its path.
+    names: Vec<(String, String)>,
+    /// The paths of the packages matching the most recent needles, least recently
+    /// used first.
+    fuzzy_matches: Mutex<VecDeque<(String, Arc<[String]>)>>,
+}
 
 impl Stdlib {
+    fn new(packages: flux::semantic::import::Packages) -> Self {
+        let names = packages
+            .iter()
+            .map(|(path, _)| {
+                (
+                    path.rsplit('/')
+                        .next()
+                        .unwrap_or(path)
+                        .to_lowercase(),
+                    path.to_string(),
+                )
+            })
+            .collect();
+        Self {
+            packages,
+            names,
+            fuzzy_matches: Mutex::new(VecDeque::new()),
+        }
+    }
+
     /// Get all packages from the stdlib.
     pub fn packages(&self) -> impl Iterator<Item = Package> + '_ {
-        self.0.iter().map(|(path, package)| {
+        self.packages.iter().map(|(path, package)| {
             Package::new(path, package.clone())
         })
     }
@@ -36,15 +67,50 @@ impl Stdlib {
     }
 
     /// Get all packages that fuzzy match on the needle.
+    ///
+    /// This runs on every keystroke of an identifier being completed, which repeats
+    /// the same needles, so the matches of recent needles are remembered.
     pub fn fuzzy_matches<'a>(
         &'a self,
         needle: &'a str,
     ) -> impl Iterator<Item = Package> + '_ {
-        self.packages().filter(|package| {
-            package
-                .name
-                .to_lowercase()
-                .contains(needle.to_lowercase().as_str())
+        let needle = needle.to_lowercase();
+        let paths = {
+            let mut cache = self
+                .fuzzy_matches
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let entry = match cache
+                .iter()
+                .position(|(cached, _)| cached == &needle)
+                .and_then(|index| cache.remove(index))
+            {
+                Some(entry) => entry,
+                None => {
+                    let paths: Arc<[String]> = self
+                        .names
+                        .iter()
+                        .filter(|(name, _)| {
+                            name.contains(needle.as_str())
+                        })
+                        .map(|(_, path)| path.clone())
+                        .collect();
+                    if cache.len() >= FUZZY_MATCH_CACHE_SIZE {
+                        cache.pop_front();
+                    }
+                    (needle, paths)
+                }
+            };
+            let paths = entry.1.clone();
+            // The needle goes to the back, as the most recently used.
+            cache.push_back(entry);
+            paths
+        };
+        (0..paths.len()).filter_map(move |index| {
+            let path = &paths[index];
+            self.packages
+                .get(path)
+                .map(|package| Package::new(path, package.clone()))
         })
     }
 }
@@ -106,6 +172,11 @@ impl Package {
         }
     }
 
+    /// Whether the package exports a function or value by that name.
+    pub fn exports_name(&self, name: &str) -> bool {
+        self.exports.iter().any(|(key, _)| key.to_string() == name)
+    }
+
     /// Get a function by name from the package.
     pub fn function(&self, name: &str) -> Option<Function> {
         self.functions()
@@ -207,6 +278,11 @@ impl Function {
             .collect()
     }
 
+    /// The signature of the function, e.g. `(<-tables:stream[A], columns:[string]) -> stream[A]`.
+    pub fn signature(&self) -> String {
+        crate::completion::create_function_signature(&self.expr)
+    }
+
     pub fn parameters(&self) -> Vec<(String, MonoType)> {
         self.expr
             .req
@@ -218,10 +294,356 @@ impl Function {
     }
 }
 
+/// A version of flux, e.g. `v0.173`.
+///
+/// Patch releases don't add functions, so they are left out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FluxVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl FluxVersion {
+    const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+}
+
+impl std::fmt::Display for FluxVersion {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        write!(f, "v{}.{}", self.major, self.minor)
+    }
+}
+
+/// The versions of flux stdlib functions were introduced in, by package path and
+/// function name. Functions of the prelude are under `universe`.
+///
+/// Only functions introduced since InfluxDB OSS 2.0 are listed, as everything older
+/// is available on every target.
+const INTRODUCED_IN: &[(&str, &str, FluxVersion)] = &[
+    ("array", "concat", FluxVersion::new(0, 173)),
+    ("array", "filter", FluxVersion::new(0, 173)),
+    ("array", "from", FluxVersion::new(0, 103)),
+    ("array", "map", FluxVersion::new(0, 173)),
+    ("array", "toBool", FluxVersion::new(0, 184)),
+    ("array", "toDuration", FluxVersion::new(0, 184)),
+    ("array", "toFloat", FluxVersion::new(0, 184)),
+    ("array", "toInt", FluxVersion::new(0, 184)),
+    ("array", "toString", FluxVersion::new(0, 184)),
+    ("array", "toTime", FluxVersion::new(0, 184)),
+    ("array", "toUInt", FluxVersion::new(0, 184)),
+    ("date", "add", FluxVersion::new(0, 162)),
+    ("date", "sub", FluxVersion::new(0, 162)),
+    ("dict", "fromList", FluxVersion::new(0, 97)),
+    ("dict", "get", FluxVersion::new(0, 97)),
+    ("dict", "insert", FluxVersion::new(0, 97)),
+    ("dict", "remove", FluxVersion::new(0, 97)),
+    ("experimental/polyline", "rdp", FluxVersion::new(0, 181)),
+    ("http/requests", "do", FluxVersion::new(0, 152)),
+    ("http/requests", "get", FluxVersion::new(0, 152)),
+    ("http/requests", "peek", FluxVersion::new(0, 154)),
+    ("http/requests", "post", FluxVersion::new(0, 152)),
+    (
+        "influxdata/influxdb/sample",
+        "data",
+        FluxVersion::new(0, 123),
+    ),
+    (
+        "influxdata/influxdb/sample",
+        "list",
+        FluxVersion::new(0, 123),
+    ),
+    ("join", "full", FluxVersion::new(0, 172)),
+    ("join", "inner", FluxVersion::new(0, 172)),
+    ("join", "left", FluxVersion::new(0, 172)),
+    ("join", "right", FluxVersion::new(0, 172)),
+    ("join", "tables", FluxVersion::new(0, 172)),
+    ("join", "time", FluxVersion::new(0, 172)),
+    ("sampledata", "bool", FluxVersion::new(0, 128)),
+    ("sampledata", "float", FluxVersion::new(0, 128)),
+    ("sampledata", "int", FluxVersion::new(0, 128)),
+    ("sampledata", "numericBool", FluxVersion::new(0, 128)),
+    ("sampledata", "string", FluxVersion::new(0, 128)),
+    ("sampledata", "uint", FluxVersion::new(0, 128)),
+    ("testing", "assertEqualValues", FluxVersion::new(0, 141)),
+    ("timezone", "location", FluxVersion::new(0, 134)),
+    ("types", "isType", FluxVersion::new(0, 140)),
+    ("universe", "die", FluxVersion::new(0, 82)),
+    ("universe", "display", FluxVersion::new(0, 154)),
+    ("universe", "timeWeightedAvg", FluxVersion::new(0, 83)),
+];
+
+/// The version of flux shipped with the first release of each minor version of
+/// InfluxDB OSS.
+const INFLUXDB_OSS: &[(&str, FluxVersion)] = &[
+    ("2.0", FluxVersion::new(0, 94)),
+    ("2.1", FluxVersion::new(0, 139)),
+    ("2.2", FluxVersion::new(0, 150)),
+    ("2.3", FluxVersion::new(0, 167)),
+    ("2.4", FluxVersion::new(0, 179)),
+    ("2.5", FluxVersion::new(0, 186)),
+    ("2.6", FluxVersion::new(0, 188)),
+    ("2.7", FluxVersion::new(0, 191)),
+];
+
+/// The version of flux a function of a package was introduced in, if it is recent
+/// enough to be missing from some targets.
+pub fn introduced_in(
+    package: &str,
+    function: &str,
+) -> Option<FluxVersion> {
+    INTRODUCED_IN
+        .iter()
+        .find(|(path, name, _)| *path == package && *name == function)
+        .map(|(_, _, version)| *version)
+}
+
+/// Deprecated stdlib functions, by package path and function name, along with the
+/// function replacing them.
+const DEPRECATED: &[(&str, &str, &str)] = &[
+    ("experimental", "addDuration", "date.add"),
+    ("experimental", "subDuration", "date.sub"),
+    ("experimental/http", "get", "requests.get"),
+    ("influxdata/influxdb/v1", "fieldKeys", "schema.fieldKeys"),
+    (
+        "influxdata/influxdb/v1",
+        "fieldsAsCols",
+        "schema.fieldsAsCols",
+    ),
+    (
+        "influxdata/influxdb/v1",
+        "measurementFieldKeys",
+        "schema.measurementFieldKeys",
+    ),
+    (
+        "influxdata/influxdb/v1",
+        "measurementTagKeys",
+        "schema.measurementTagKeys",
+    ),
+    (
+        "influxdata/influxdb/v1",
+        "measurementTagValues",
+        "schema.measurementTagValues",
+    ),
+    (
+        "influxdata/influxdb/v1",
+        "measurements",
+        "schema.measurements",
+    ),
+    ("influxdata/influxdb/v1", "tagKeys", "schema.tagKeys"),
+    ("influxdata/influxdb/v1", "tagValues", "schema.tagValues"),
+];
+
+/// The function replacing a deprecated function of a package, if it is deprecated.
+pub fn replacement_of(
+    package: &str,
+    function: &str,
+) -> Option<&'static str> {
+    DEPRECATED
+        .iter()
+        .find(|(path, name, _)| *path == package && *name == function)
+        .map(|(_, _, replacement)| *replacement)
+}
+
+/// The version of flux available on a target, as configured with the
+/// `targetVersion` setting.
+///
+/// Targets are versions of InfluxDB OSS, e.g. `2.4` or `oss-2.4.1`, or `cloud`, which
+/// always runs the latest flux and so is `None` like targets that aren't known.
+pub fn target_flux_version(target: &str) -> Option<FluxVersion> {
+    let target = target.trim().to_lowercase();
+    let version = target
+        .strip_prefix("oss-")
+        .unwrap_or(&target)
+        .trim_start_matches('v');
+    let minor_version =
+        version.splitn(3, '.').take(2).collect::<Vec<_>>().join(".");
+    INFLUXDB_OSS
+        .iter()
+        .find(|(oss, _)| *oss == minor_version)
+        .map(|(_, flux)| *flux)
+}
+
+/// A platform scripts run on, as configured with the `target` setting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    Oss,
+    Cloud,
+    Serverless,
+}
+
+impl Platform {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "oss" => Some(Platform::Oss),
+            "cloud" => Some(Platform::Cloud),
+            "serverless" => Some(Platform::Serverless),
+            _ => None,
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Platform::Oss => "InfluxDB OSS",
+            Platform::Cloud => "InfluxDB Cloud",
+            Platform::Serverless => "InfluxDB Cloud Serverless",
+        }
+    }
+}
+
+/// Packages that can't be used on some platforms, including the packages under them.
+const UNAVAILABLE_PACKAGES: &[(&str, &[Platform])] = &[
+    // Only InfluxDB IOx has anything to read.
+    ("experimental/iox", &[Platform::Oss, Platform::Cloud]),
+    // Hosted platforms don't allow connections to raw sockets.
+    ("socket", &[Platform::Cloud, Platform::Serverless]),
+];
+
+/// Arguments of stdlib functions that can't be used on some platforms, by package
+/// path, function and argument name, along with the only value that can't be used,
+/// if the argument can be used otherwise.
+const UNSUPPORTED_ARGUMENTS: &[(
+    &str,
+    &str,
+    &str,
+    Option<&str>,
+    &[Platform],
+)] = &[
+    // Hosted platforms have no file system to read from.
+    (
+        "csv",
+        "from",
+        "file",
+        None,
+        &[Platform::Cloud, Platform::Serverless],
+    ),
+    (
+        "sql",
+        "from",
+        "driverName",
+        Some("sqlite3"),
+        &[Platform::Cloud, Platform::Serverless],
+    ),
+    (
+        "sql",
+        "to",
+        "driverName",
+        Some("sqlite3"),
+        &[Platform::Cloud, Platform::Serverless],
+    ),
+];
+
+/// Whether a package can be used on a platform. Every package can be used when the
+/// platform isn't known.
+pub fn is_available(path: &str, platform: Option<Platform>) -> bool {
+    let platform = match platform {
+        Some(platform) => platform,
+        None => return true,
+    };
+    !UNAVAILABLE_PACKAGES.iter().any(|(unavailable, platforms)| {
+        platforms.contains(&platform)
+            && (path == *unavailable
+                || path
+                    .strip_prefix(unavailable)
+                    .map_or(false, |rest| rest.starts_with('/')))
+    })
+}
+
+/// Whether an argument of a function of a package can't be given `value` on a
+/// platform.
+pub fn is_unsupported_argument(
+    package: &str,
+    function: &str,
+    argument: &str,
+    value: Option<&str>,
+    platform: Platform,
+) -> bool {
+    UNSUPPORTED_ARGUMENTS.iter().any(
+        |(path, name, key, unsupported, platforms)| {
+            *path == package
+                && *name == function
+                && *key == argument
+                && platforms.contains(&platform)
+                && unsupported.map_or(true, |unsupported| {
+                    value == Some(unsupported)
+                })
+        },
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Every function with a version is in the stdlib, so that typos in the table
+    /// don't go unnoticed.
+    /// Every deprecated function is in the stdlib.
+    #[test]
+    fn deprecated_functions_exist() {
+        for (path, name, _) in DEPRECATED {
+            assert!(
+                STDLIB
+                    .package(path)
+                    .and_then(|package| package.function(name))
+                    .is_some(),
+                "{}.{} is not in the stdlib",
+                path,
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn introduced_functions_exist() {
+        for (path, name, _) in INTRODUCED_IN {
+            let package = match *path {
+                "universe" => Some(UNIVERSE.clone()),
+                path => STDLIB.package(path),
+            };
+            assert!(
+                package
+                    .and_then(|package| package.function(name))
+                    .is_some(),
+                "{}.{} is not in the stdlib",
+                path,
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn available_packages() {
+        assert!(is_available("experimental/iox", None));
+        assert!(is_available(
+            "experimental/iox",
+            Some(Platform::Serverless)
+        ));
+        assert!(!is_available(
+            "experimental/iox",
+            Some(Platform::Oss)
+        ));
+        assert!(is_available("socket", Some(Platform::Oss)));
+        assert!(!is_available("socket", Some(Platform::Cloud)));
+        assert!(is_available("socketry", Some(Platform::Cloud)));
+    }
+
+    #[test]
+    fn target_versions() {
+        assert_eq!(
+            Some(FluxVersion::new(0, 179)),
+            target_flux_version("2.4")
+        );
+        assert_eq!(
+            Some(FluxVersion::new(0, 94)),
+            target_flux_version("OSS-2.0.9")
+        );
+        assert_eq!(None, target_flux_version("cloud"));
+        assert_eq!("v0.173", FluxVersion::new(0, 173).to_string());
+    }
+
     /// All stdlib packages are fetched.
     ///
     /// There is some logic that makes assumptions about flux packages,
@@ -484,6 +906,33 @@ mod tests {
         );
     }
 
+    /// Fuzzy matches ignore case, and remembered matches are the same as fresh ones.
+    #[test]
+    fn fuzzy_matches() {
+        let stdlib = Stdlib::new(
+            flux::imports().expect("Could not initialize stdlib."),
+        );
+        let matches = |needle: &str| {
+            stdlib
+                .fuzzy_matches(needle)
+                .map(|package| package.path)
+                .collect::<Vec<String>>()
+        };
+
+        let fresh = matches("StRiNg");
+        assert!(fresh.contains(&"strings".to_string()));
+        assert_eq!(fresh, matches("string"));
+
+        for needle in 0..FUZZY_MATCH_CACHE_SIZE * 2 {
+            matches(&needle.to_string());
+        }
+        assert_eq!(
+            FUZZY_MATCH_CACHE_SIZE,
+            stdlib.fuzzy_matches.lock().unwrap().len()
+        );
+        assert_eq!(fresh, matches("string"));
+    }
+
     #[test]
     fn function_parameters() {
         let from =
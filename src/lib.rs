@@ -5,12 +5,39 @@
     clippy::unwrap_used,
     clippy::wildcard_imports
 )]
+mod annotated_csv;
+mod colors;
 mod completion;
 mod composition;
+mod convert;
+mod deprecations;
 mod diagnostics;
+mod formatting;
+mod http;
+#[cfg(feature = "native-queries")]
+mod influxdb;
 mod lang;
 mod lsp;
+mod perf_lint;
+mod query_params;
+mod ranges;
+mod schema;
+mod secrets;
 mod server;
+mod snippets;
+mod sticky;
+mod tasks;
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test_support;
+mod testing;
+#[cfg(any(test, feature = "testkit"))]
+pub mod testkit;
+mod timezones;
+mod trace;
+#[cfg(feature = "cmd")]
+mod transport;
+mod versions;
 mod visitors;
 #[cfg(feature = "wasm")]
 mod wasm;
@@ -19,7 +46,17 @@ mod wasm;
 #[macro_use]
 extern crate pretty_assertions;
 
-pub use server::LspServer;
+pub use completion::preload;
+pub use composition::{Composition, EditError, ResolveError};
+pub use server::{
+    DisabledCapabilities, DocumentObserver, DocumentStore, LspServer,
+    MemoryStore,
+};
+#[cfg(feature = "cmd")]
+pub use trace::replay;
+pub use trace::{Direction, Framer, Trace, TraceEntry};
+#[cfg(feature = "cmd")]
+pub use transport::Normalized;
 
 #[macro_export]
 macro_rules! walk_ast_package {
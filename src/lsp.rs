@@ -14,6 +14,78 @@ pub fn position_in_range(
     (range.start..=range.end).contains(position)
 }
 
+/// Return true if range `a` spans no more of the document than range `b`.
+///
+/// Ranges are compared by the number of lines they span, and then by the number of
+/// characters. For nested ranges, this means the inner range is always narrower.
+pub fn range_is_narrower_or_equal(
+    a: &lsp::Range,
+    b: &lsp::Range,
+) -> bool {
+    let span = |range: &lsp::Range| {
+        (
+            range.end.line.saturating_sub(range.start.line),
+            i64::from(range.end.character)
+                - i64::from(range.start.character),
+        )
+    };
+    span(a) <= span(b)
+}
+
+/// Sort text edits in reverse document order, dropping duplicates.
+///
+/// Edits of the same document are applied to the document as it was before any of
+/// them, but some clients apply them one after the other, shifting the positions of
+/// every edit after the first. In reverse document order, each edit only shifts the
+/// text after the edits still to apply. Edits at the same position keep their order.
+pub fn sort_edits(edits: &mut Vec<lsp::TextEdit>) {
+    edits.sort_by(|a, b| {
+        (b.range.start, b.range.end)
+            .cmp(&(a.range.start, a.range.end))
+    });
+    edits.dedup();
+}
+
+/// Sort every list of text edits of a workspace edit with `sort_edits`.
+pub fn sorted_workspace_edit(
+    mut edit: lsp::WorkspaceEdit,
+) -> lsp::WorkspaceEdit {
+    if let Some(changes) = &mut edit.changes {
+        changes.values_mut().for_each(sort_edits);
+    }
+    let sort_document_edits =
+        |document: &mut lsp::TextDocumentEdit| {
+            let range = |edit: &lsp::OneOf<
+                lsp::TextEdit,
+                lsp::AnnotatedTextEdit,
+            >| match edit {
+                lsp::OneOf::Left(edit) => edit.range,
+                lsp::OneOf::Right(edit) => edit.text_edit.range,
+            };
+            document.edits.sort_by(|a, b| {
+                let (a, b) = (range(a), range(b));
+                (b.start, b.end).cmp(&(a.start, a.end))
+            });
+            document.edits.dedup();
+        };
+    match &mut edit.document_changes {
+        Some(lsp::DocumentChanges::Edits(documents)) => {
+            documents.iter_mut().for_each(sort_document_edits)
+        }
+        Some(lsp::DocumentChanges::Operations(operations)) => {
+            operations.iter_mut().for_each(|operation| {
+                if let lsp::DocumentChangeOperation::Edit(document) =
+                    operation
+                {
+                    sort_document_edits(document)
+                }
+            })
+        }
+        None => {}
+    }
+    edit
+}
+
 #[cfg(test)]
 mod test {
     use lspower::lsp;
@@ -135,4 +207,87 @@ mod test {
         assert!(position_in_range(&range.start, &range));
         assert!(position_in_range(&range.end, &range));
     }
+
+    #[test]
+    fn range_is_narrower_or_equal_nested() {
+        let outer = lsp::Range {
+            start: lsp::Position {
+                line: 2,
+                character: 4,
+            },
+            end: lsp::Position {
+                line: 2,
+                character: 20,
+            },
+        };
+        let inner = lsp::Range {
+            start: lsp::Position {
+                line: 2,
+                character: 8,
+            },
+            end: lsp::Position {
+                line: 2,
+                character: 12,
+            },
+        };
+        assert!(range_is_narrower_or_equal(&inner, &outer));
+        assert!(!range_is_narrower_or_equal(&outer, &inner));
+        assert!(range_is_narrower_or_equal(&outer, &outer));
+    }
+
+    #[test]
+    fn range_is_narrower_or_equal_multiline() {
+        let outer = lsp::Range {
+            start: lsp::Position {
+                line: 2,
+                character: 4,
+            },
+            end: lsp::Position {
+                line: 5,
+                character: 1,
+            },
+        };
+        let inner = lsp::Range {
+            start: lsp::Position {
+                line: 3,
+                character: 0,
+            },
+            end: lsp::Position {
+                line: 3,
+                character: 80,
+            },
+        };
+        assert!(range_is_narrower_or_equal(&inner, &outer));
+        assert!(!range_is_narrower_or_equal(&outer, &inner));
+    }
+
+    #[test]
+    fn sort_edits_in_reverse_document_order() {
+        let edit = |line, character, text: &str| lsp::TextEdit {
+            range: lsp::Range {
+                start: lsp::Position { line, character },
+                end: lsp::Position { line, character },
+            },
+            new_text: text.into(),
+        };
+        let mut edits = vec![
+            edit(0, 4, "a"),
+            edit(2, 0, "b"),
+            edit(0, 4, "a"),
+            edit(1, 2, "c"),
+            edit(1, 2, "d"),
+        ];
+
+        sort_edits(&mut edits);
+
+        assert_eq!(
+            vec![
+                edit(2, 0, "b"),
+                edit(1, 2, "c"),
+                edit(1, 2, "d"),
+                edit(0, 4, "a"),
+            ],
+            edits
+        );
+    }
 }
@@ -0,0 +1,817 @@
+/// Performance lints for flux code
+///
+/// InfluxDB can push the first stages of a pipeline down to storage: `from`, `range`
+/// and `filter`, followed by one of `group`, `window`, `aggregateWindow` or a few
+/// aggregates. Storage then does the work, rather than sending every row to be
+/// processed in memory. Once a stage can't be pushed down, none of the stages after
+/// it can either, which is the most common reason for a query to be slow.
+use flux::ast::Operator;
+use flux::semantic::nodes::{
+    BinaryExpr, CallExpr, Expression, FunctionExpr, Package,
+};
+use flux::semantic::walk::Node as WalkNode;
+use lspower::lsp;
+
+use crate::convert;
+use crate::diagnostics::{callee_name, url_of_file};
+
+/// The diagnostic code of filters that could come before the stage preceding them.
+pub(crate) const FILTER_PUSHDOWN: &str = "filter-pushdown";
+
+/// The diagnostic code of stages that keep the stages after them from being pushed
+/// down to storage.
+pub(crate) const PUSHDOWN_BLOCKED: &str = "pushdown-blocked";
+
+/// The diagnostic code of filters whose predicate can't be pushed down to storage.
+pub(crate) const FILTER_NOT_PUSHABLE: &str = "filter-not-pushable";
+
+/// The diagnostic code of regular expressions in predicates that only match a literal.
+pub(crate) const LITERAL_REGEX: &str = "literal-regex";
+
+/// Aggregates and selectors, which keep the columns of the group key.
+const AGGREGATES: &[&str] = &[
+    "aggregateWindow",
+    "count",
+    "distinct",
+    "first",
+    "integral",
+    "last",
+    "max",
+    "mean",
+    "median",
+    "min",
+    "mode",
+    "quantile",
+    "spread",
+    "stddev",
+    "sum",
+];
+
+/// The stages that end what storage can do, once pushed down along with the `range`
+/// and `filter` stages before them.
+const FINAL_PUSHABLE_STAGES: &[&str] = &[
+    "aggregateWindow",
+    "count",
+    "first",
+    "group",
+    "last",
+    "max",
+    "mean",
+    "min",
+    "sum",
+    "window",
+];
+
+/// The call piped into a call, i.e. the previous stage of a pipeline.
+fn piped_call(call: &CallExpr) -> Option<&CallExpr> {
+    match &call.pipe {
+        Some(Expression::Call(stage)) => {
+            let stage: &CallExpr = stage;
+            Some(stage)
+        }
+        _ => None,
+    }
+}
+
+/// The predicate function of a `filter` call.
+fn predicate(call: &CallExpr) -> Option<&FunctionExpr> {
+    match &call
+        .arguments
+        .iter()
+        .find(|argument| argument.key.name == "fn")?
+        .value
+    {
+        Expression::Function(function) => {
+            let function: &FunctionExpr = function;
+            Some(function)
+        }
+        _ => None,
+    }
+}
+
+/// The columns that a heavy stage leaves untouched, or `None` if it leaves all of them
+/// untouched. Filtering on these columns is the same before or after the stage.
+///
+/// Aggregates keep the group key, which is at least `_measurement`, `_field`, `_start`
+/// and `_stop` for data from `from` and `range`. `pivot` turns fields into columns, and
+/// there is no telling those apart from tags.
+fn kept_columns(
+    stage: &str,
+) -> Option<Option<&'static [&'static str]>> {
+    match stage {
+        "group" => Some(None),
+        "pivot" => Some(Some(&["_measurement", "_start", "_stop"])),
+        _ if AGGREGATES.contains(&stage) => {
+            Some(Some(&["_measurement", "_field", "_start", "_stop"]))
+        }
+        _ => None,
+    }
+}
+
+/// Collect the columns a predicate reads from its row parameter, and count the uses of
+/// the parameter.
+struct PredicateColumnsVisitor<'a> {
+    row: &'a str,
+    columns: Vec<&'a str>,
+    uses: usize,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a>
+    for PredicateColumnsVisitor<'a>
+{
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        match node {
+            WalkNode::MemberExpr(member) => {
+                if let Expression::Identifier(ident) = &member.object
+                {
+                    if ident.name == self.row {
+                        self.columns.push(member.property.as_str());
+                    }
+                }
+            }
+            WalkNode::IdentifierExpr(ident)
+                if ident.name == self.row =>
+            {
+                self.uses += 1
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+/// The columns read by the predicate of a `filter` call, or `None` if the row is used
+/// in other ways, e.g. passed to a function.
+fn predicate_columns(call: &CallExpr) -> Option<Vec<&str>> {
+    let function = predicate(call)?;
+    let row = function.params.first()?.key.name.as_str();
+    let mut visitor = PredicateColumnsVisitor {
+        row,
+        columns: vec![],
+        uses: 0,
+    };
+    flux::semantic::walk::walk(
+        &mut visitor,
+        WalkNode::Block(&function.body),
+    );
+    if visitor.uses == visitor.columns.len() {
+        Some(visitor.columns)
+    } else {
+        None
+    }
+}
+
+/// Find `filter` stages that could come before the stage preceding them.
+#[derive(Default)]
+struct FilterPushdownVisitor<'a> {
+    filters: Vec<(&'a CallExpr, &'a CallExpr, &'a str)>,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a>
+    for FilterPushdownVisitor<'a>
+{
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        if let WalkNode::CallExpr(call) = node {
+            if callee_name(call) != Some("filter") {
+                return true;
+            }
+            let stage = match piped_call(call) {
+                Some(stage) => stage,
+                None => return true,
+            };
+            let name = match callee_name(stage) {
+                Some(name) => name,
+                None => return true,
+            };
+            let kept = match kept_columns(name) {
+                Some(kept) => kept,
+                None => return true,
+            };
+            let pushable = match (predicate_columns(call), kept) {
+                (Some(_), None) => true,
+                (Some(columns), Some(kept)) => {
+                    columns.iter().all(|column| kept.contains(column))
+                }
+                (None, _) => false,
+            };
+            if pushable {
+                self.filters.push((call, stage, name));
+            }
+        }
+        true
+    }
+}
+
+/// `filter` stages following `pivot`, `group` or an aggregate, that only use columns
+/// the earlier stage leaves untouched.
+///
+/// Such a filter could come first, which means less data to transform, and lets the
+/// filter be pushed down to the storage. The earlier stage is related information.
+pub(crate) fn filter_pushdown(
+    pkg: &Package,
+    urls: &[lsp::Url],
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    let visitor = crate::walk_semantic_package!(
+        FilterPushdownVisitor::default(),
+        pkg
+    );
+    visitor
+        .filters
+        .into_iter()
+        .map(|(call, stage, name)| {
+            let related_information = stage
+                .loc
+                .file
+                .as_ref()
+                .and_then(|filename| url_of_file(urls, filename))
+                .map(|uri| {
+                    vec![lsp::DiagnosticRelatedInformation {
+                        location: lsp::Location {
+                            uri: uri.clone(),
+                            range: convert::location_to_range(&stage.loc),
+                        },
+                        message: format!("The earlier `{}` stage", name),
+                    }]
+                });
+            (call.loc.file.clone(), lsp::Diagnostic {
+                range: convert::location_to_range(&call.loc),
+                severity: Some(lsp::DiagnosticSeverity::INFORMATION),
+                code: Some(lsp::NumberOrString::String(FILTER_PUSHDOWN.into())),
+                message: format!("This `filter` only uses columns that `{}` leaves untouched. Consider filtering before `{}`, so there is less data to process and the filter can be pushed down to the storage.", name, name),
+                related_information,
+                ..lsp::Diagnostic::default()
+            })
+        })
+        .collect()
+}
+
+/// Collect every call in a package.
+#[derive(Default)]
+struct CallVisitor<'a> {
+    calls: Vec<&'a CallExpr>,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a> for CallVisitor<'a> {
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        if let WalkNode::CallExpr(call) = node {
+            self.calls.push(call);
+        }
+        true
+    }
+}
+
+/// The pipelines of a package reading from storage, i.e. starting with `from`. Each
+/// pipeline is the list of its stages, in order.
+pub(crate) fn pipelines(pkg: &Package) -> Vec<Vec<&CallExpr>> {
+    let visitor =
+        crate::walk_semantic_package!(CallVisitor::default(), pkg);
+    let piped: Vec<&CallExpr> = visitor
+        .calls
+        .iter()
+        .filter_map(|call| piped_call(call))
+        .collect();
+
+    visitor
+        .calls
+        .iter()
+        // Only the last stage of a pipeline isn't piped into another.
+        .filter(|call| {
+            !piped.iter().any(|stage| std::ptr::eq(*stage, **call))
+        })
+        .filter_map(|call| stages(call))
+        .collect()
+}
+
+/// The stages of the pipeline ending in `call`, in order, if it reads from storage.
+fn stages(call: &CallExpr) -> Option<Vec<&CallExpr>> {
+    let mut stages = vec![call];
+    let mut stage = call;
+    while let Some(previous) = piped_call(stage) {
+        stages.push(previous);
+        stage = previous;
+    }
+    stages.reverse();
+    (callee_name(stages[0]) == Some("from")).then_some(stages)
+}
+
+/// Whether an expression reads the `row` parameter of a predicate.
+fn references_row(expression: &Expression, row: &str) -> bool {
+    match expression {
+        Expression::Identifier(ident) => ident.name == row,
+        Expression::Member(member) => {
+            references_row(&member.object, row)
+        }
+        Expression::Index(index) => {
+            references_row(&index.array, row)
+                || references_row(&index.index, row)
+        }
+        Expression::Unary(unary) => {
+            references_row(&unary.argument, row)
+        }
+        Expression::Binary(binary) => {
+            references_row(&binary.left, row)
+                || references_row(&binary.right, row)
+        }
+        Expression::Logical(logical) => {
+            references_row(&logical.left, row)
+                || references_row(&logical.right, row)
+        }
+        Expression::Call(call) => {
+            call.arguments
+                .iter()
+                .any(|argument| references_row(&argument.value, row))
+                || call
+                    .pipe
+                    .as_ref()
+                    .map_or(false, |pipe| references_row(pipe, row))
+        }
+        _ => false,
+    }
+}
+
+/// Why a predicate expression can't be pushed down to storage, if it can't.
+///
+/// Storage evaluates comparisons of columns against values. A predicate calling a
+/// function with a column, or comparing two columns, is evaluated in memory instead.
+fn unpushable_reason(
+    expression: &Expression,
+    row: &str,
+) -> Option<&'static str> {
+    match expression {
+        Expression::Logical(logical) => {
+            unpushable_reason(&logical.left, row)
+                .or_else(|| unpushable_reason(&logical.right, row))
+        }
+        Expression::Binary(binary) => {
+            if references_row(&binary.left, row)
+                && references_row(&binary.right, row)
+            {
+                Some("compares two columns")
+            } else {
+                unpushable_reason(&binary.left, row)
+                    .or_else(|| unpushable_reason(&binary.right, row))
+            }
+        }
+        Expression::Unary(unary) => {
+            unpushable_reason(&unary.argument, row)
+        }
+        Expression::Call(_) if references_row(expression, row) => {
+            Some("calls a function with a column")
+        }
+        _ => None,
+    }
+}
+
+/// Why the predicate of a `filter` call can't be pushed down to storage, if it can't.
+fn unpushable_predicate(call: &CallExpr) -> Option<&'static str> {
+    let function = predicate(call)?;
+    let row = function.params.first()?.key.name.as_str();
+    match &function.body {
+        flux::semantic::nodes::Block::Return(statement) => {
+            unpushable_reason(&statement.argument, row)
+        }
+        _ => None,
+    }
+}
+
+/// The string a regular expression matches, if it only matches that string, e.g.
+/// `cpu` for `/^cpu$/`.
+fn literal_of_regex(pattern: &str) -> Option<String> {
+    let pattern = pattern.strip_prefix('^')?.strip_suffix('$')?;
+    let mut literal = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            // Escaped punctuation is literal, unlike classes like `\d`.
+            '\\' => match chars.next() {
+                Some(escaped) if escaped.is_ascii_punctuation() => {
+                    literal.push(escaped)
+                }
+                _ => return None,
+            },
+            '.' | '*' | '+' | '?' | '(' | ')' | '[' | ']' | '{'
+            | '}' | '|' | '^' | '$' => return None,
+            c => literal.push(c),
+        }
+    }
+    Some(literal)
+}
+
+/// Collect the comparisons of a column with a regular expression only matching a
+/// literal, in the predicates of `filter` calls.
+#[derive(Default)]
+struct LiteralRegexVisitor<'a> {
+    comparisons: Vec<(&'a BinaryExpr, String)>,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a>
+    for LiteralRegexVisitor<'a>
+{
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        if let WalkNode::CallExpr(call) = node {
+            if callee_name(call) != Some("filter") {
+                return true;
+            }
+            let function = match predicate(call) {
+                Some(function) => function,
+                None => return true,
+            };
+            let row = match function.params.first() {
+                Some(param) => param.key.name.as_str(),
+                None => return true,
+            };
+            let mut visitor = BinaryVisitor::default();
+            flux::semantic::walk::walk(
+                &mut visitor,
+                WalkNode::Block(&function.body),
+            );
+            self.comparisons.extend(
+                visitor.binaries.into_iter().filter_map(|binary| {
+                    if binary.operator
+                        != Operator::RegexpMatchOperator
+                        || !references_row(&binary.left, row)
+                    {
+                        return None;
+                    }
+                    match &binary.right {
+                        Expression::Regexp(regexp) => Some((
+                            binary,
+                            literal_of_regex(&regexp.value)?,
+                        )),
+                        _ => None,
+                    }
+                }),
+            );
+        }
+        true
+    }
+}
+
+/// Collect every binary expression.
+#[derive(Default)]
+struct BinaryVisitor<'a> {
+    binaries: Vec<&'a BinaryExpr>,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a> for BinaryVisitor<'a> {
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        if let WalkNode::BinaryExpr(binary) = node {
+            self.binaries.push(binary);
+        }
+        true
+    }
+}
+
+/// Comparisons of a column with a regular expression only matching a literal in
+/// `filter` predicates, along with that literal.
+pub(crate) fn literal_regex_comparisons(
+    pkg: &Package,
+) -> Vec<(&BinaryExpr, String)> {
+    let visitor = crate::walk_semantic_package!(
+        LiteralRegexVisitor::default(),
+        pkg
+    );
+    visitor.comparisons
+}
+
+/// The comparison replacing a regular expression only matching a literal, e.g.
+/// ` == "cpu"` after `r._measurement`.
+pub(crate) fn equality_of_literal(literal: &str) -> String {
+    format!(
+        " == \"{}\"",
+        literal
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace("${", "\\${")
+    )
+}
+
+/// Regular expressions in `filter` predicates that only match a literal, e.g.
+/// `r._measurement =~ /^cpu$/`.
+///
+/// Storage can push down comparisons with regular expressions, but an equality is a
+/// cheaper lookup of the series than matching every value against the expression.
+pub(crate) fn literal_regexes(
+    pkg: &Package,
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    literal_regex_comparisons(pkg)
+        .into_iter()
+        .map(|(binary, literal)| {
+            (binary.loc.file.clone(), lsp::Diagnostic {
+                range: convert::location_to_range(&binary.loc),
+                severity: Some(lsp::DiagnosticSeverity::INFORMATION),
+                code: Some(lsp::NumberOrString::String(LITERAL_REGEX.into())),
+                message: format!("This regular expression only matches \"{}\". Consider comparing with `==` instead, which storage can look up faster.", literal),
+                ..lsp::Diagnostic::default()
+            })
+        })
+        .collect()
+}
+
+/// How many stages, from the start of a pipeline, are pushed down to storage.
+///
+/// `range` and filters with pushable predicates are pushed down, and so is a single
+/// stage of `FINAL_PUSHABLE_STAGES` right after them. Everything after runs in memory.
+fn pushed_down(stages: &[&CallExpr]) -> usize {
+    for (i, stage) in stages.iter().enumerate().skip(1) {
+        match callee_name(stage).unwrap_or_default() {
+            "range" => continue,
+            "filter" if unpushable_predicate(stage).is_none() => {
+                continue
+            }
+            name if FINAL_PUSHABLE_STAGES.contains(&name) => {
+                return i + 1
+            }
+            _ => return i,
+        }
+    }
+    stages.len()
+}
+
+/// The stages of the pipeline an expression evaluates, if it reads from storage, along
+/// with whether each of them is estimated to be pushed down to storage.
+pub(crate) fn pushdown_boundary(
+    expression: &Expression,
+) -> Option<Vec<(&str, bool)>> {
+    let stages = match expression {
+        Expression::Call(call) => stages(call)?,
+        _ => return None,
+    };
+    let pushed = pushed_down(&stages);
+    Some(
+        stages
+            .iter()
+            .enumerate()
+            .map(|(i, stage)| {
+                (callee_name(stage).unwrap_or_default(), i < pushed)
+            })
+            .collect(),
+    )
+}
+
+/// Stages of pipelines reading from storage that keep later stages from being pushed
+/// down to storage, e.g. a `map` before an `aggregateWindow`.
+///
+/// Only the first such stage of a pipeline is reported, as it is the one to fix first.
+pub(crate) fn pushdown_blockers(
+    pkg: &Package,
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    let mut diagnostics = vec![];
+    for stages in pipelines(pkg) {
+        for (i, stage) in stages.iter().enumerate().skip(1) {
+            let name = callee_name(stage).unwrap_or_default();
+            if FINAL_PUSHABLE_STAGES.contains(&name) {
+                break;
+            }
+            if name == "range" {
+                continue;
+            }
+            if name == "filter" {
+                match unpushable_predicate(stage) {
+                    None => continue,
+                    Some(reason) => {
+                        diagnostics.push((stage.loc.file.clone(), lsp::Diagnostic {
+                            range: convert::location_to_range(&stage.loc),
+                            severity: Some(lsp::DiagnosticSeverity::INFORMATION),
+                            code: Some(lsp::NumberOrString::String(FILTER_NOT_PUSHABLE.into())),
+                            message: format!("The predicate of this `filter` {}, so it can't be pushed down to storage. Every row read is filtered in memory instead, along with the stages after it.", reason),
+                            ..lsp::Diagnostic::default()
+                        }));
+                        break;
+                    }
+                }
+            }
+            // Nothing after this stage is pushed down, which only matters if something
+            // after it could have been.
+            if let Some(later) = stages[i + 1..]
+                .iter()
+                .filter_map(|stage| callee_name(stage))
+                .find(|later| {
+                    *later == "filter"
+                        || FINAL_PUSHABLE_STAGES.contains(later)
+                })
+            {
+                diagnostics.push((stage.loc.file.clone(), lsp::Diagnostic {
+                    range: convert::location_to_range(&stage.loc),
+                    severity: Some(lsp::DiagnosticSeverity::INFORMATION),
+                    code: Some(lsp::NumberOrString::String(PUSHDOWN_BLOCKED.into())),
+                    message: format!("`{}` can't be pushed down to storage, so the `{}` after it can't be either. Consider moving `{}` after `{}`, if the results are the same.", name, later, name, later),
+                    ..lsp::Diagnostic::default()
+                }));
+            }
+            break;
+        }
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::test_support::get_package;
+
+    fn lines(
+        diagnostics: &[(Option<String>, lsp::Diagnostic)],
+    ) -> Vec<(u32, String)> {
+        diagnostics
+            .iter()
+            .map(|(_, diagnostic)| {
+                (
+                    diagnostic.range.start.line,
+                    match &diagnostic.code {
+                        Some(lsp::NumberOrString::String(code)) => {
+                            code.clone()
+                        }
+                        _ => String::new(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn map_before_aggregate_window() {
+        let fluxscript = r#"from(bucket: "a")
+    |> range(start: -1h)
+    |> filter(fn: (r) => r._measurement == "cpu")
+    |> map(fn: (r) => ({r with _value: r._value * 2.0}))
+    |> aggregateWindow(every: 5m, fn: mean)
+"#;
+        let package = get_package(fluxscript);
+
+        let diagnostics = pushdown_blockers(&package);
+
+        assert_eq!(
+            vec![(3, PUSHDOWN_BLOCKED.to_string())],
+            lines(&diagnostics)
+        );
+        assert_eq!(
+            "`map` can't be pushed down to storage, so the `aggregateWindow` after it can't be either. Consider moving `map` after `aggregateWindow`, if the results are the same.",
+            diagnostics[0].1.message
+        );
+    }
+
+    #[test]
+    fn map_after_aggregate_window() {
+        let fluxscript = r#"from(bucket: "a")
+    |> range(start: -1h)
+    |> aggregateWindow(every: 5m, fn: mean)
+    |> map(fn: (r) => ({r with _value: r._value * 2.0}))
+"#;
+        let package = get_package(fluxscript);
+
+        assert!(pushdown_blockers(&package).is_empty());
+    }
+
+    #[test]
+    fn unpushable_filter_predicates() {
+        let fluxscript = r#"import "strings"
+
+from(bucket: "a")
+    |> range(start: -1h)
+    |> filter(fn: (r) => r._measurement == "cpu" and strings.hasPrefix(v: r.host, prefix: "web"))
+
+from(bucket: "a")
+    |> range(start: -1h)
+    |> filter(fn: (r) => r._value > r.threshold)
+
+from(bucket: "a")
+    |> range(start: -1h)
+    |> filter(fn: (r) => r._measurement == "cpu" and r.host =~ /^web/)
+"#;
+        let package = get_package(fluxscript);
+
+        let diagnostics = pushdown_blockers(&package);
+
+        assert_eq!(
+            vec![
+                (4, FILTER_NOT_PUSHABLE.to_string()),
+                (8, FILTER_NOT_PUSHABLE.to_string()),
+            ],
+            lines(&diagnostics)
+        );
+        assert!(diagnostics[0]
+            .1
+            .message
+            .contains("calls a function with a column"));
+        assert!(diagnostics[1]
+            .1
+            .message
+            .contains("compares two columns"));
+    }
+
+    #[test]
+    fn filter_pushdown_after_heavy_stages() {
+        let fluxscript = r#"from(bucket: "a")
+    |> range(start: -1h)
+    |> pivot(rowKey: ["_time"], columnKey: ["_field"], valueColumn: "_value")
+    |> filter(fn: (r) => r._measurement == "cpu")
+    |> group(columns: ["host"])
+    |> filter(fn: (r) => r.host == "a" or r._value > 2.0)
+    |> mean()
+    |> filter(fn: (r) => r._value > 2.0)
+"#;
+        let package = get_package(&fluxscript);
+        let urls =
+            vec![lsp::Url::parse("file:///path/to/script.flux")
+                .unwrap()];
+
+        let diagnostics = filter_pushdown(&package, &urls);
+
+        assert_eq!(
+            vec![3, 5],
+            diagnostics
+                .iter()
+                .map(|(_, diagnostic)| diagnostic.range.start.line)
+                .collect::<Vec<u32>>()
+        );
+        assert_eq!(
+            Some(vec![lsp::DiagnosticRelatedInformation {
+                location: lsp::Location {
+                    uri: urls[0].clone(),
+                    range: lsp::Range {
+                        start: lsp::Position {
+                            line: 2,
+                            character: 7,
+                        },
+                        end: lsp::Position {
+                            line: 2,
+                            character: 77,
+                        },
+                    },
+                },
+                message: "The earlier `pivot` stage".into(),
+            }]),
+            diagnostics[0].1.related_information
+        );
+    }
+
+    #[test]
+    fn regexes_matching_a_literal() {
+        assert_eq!(
+            Some("cpu".to_string()),
+            literal_of_regex("^cpu$")
+        );
+        assert_eq!(
+            Some("host.example".to_string()),
+            literal_of_regex(r"^host\.example$")
+        );
+        assert_eq!(None, literal_of_regex("cpu"));
+        assert_eq!(None, literal_of_regex("^cpu.*$"));
+        assert_eq!(None, literal_of_regex(r"^cpu\d$"));
+        assert_eq!(None, literal_of_regex("^cpu|mem$"));
+    }
+
+    #[test]
+    fn literal_regexes_in_filters() {
+        let fluxscript = r#"from(bucket: "a")
+    |> range(start: -1h)
+    |> filter(fn: (r) => r._measurement =~ /^cpu$/ and r.host =~ /^server-\d+$/)
+    |> filter(fn: (r) => r._field !~ /^usage$/)
+"#;
+        let package = get_package(fluxscript);
+
+        let diagnostics = literal_regexes(&package);
+
+        assert_eq!(
+            vec![(2, LITERAL_REGEX.to_string())],
+            lines(&diagnostics)
+        );
+        assert_eq!(
+            lsp::Range {
+                start: lsp::Position::new(2, 25),
+                end: lsp::Position::new(2, 50),
+            },
+            diagnostics[0].1.range
+        );
+    }
+
+    #[test]
+    fn pushdown_boundary_of_assignment() {
+        let fluxscript = r#"data = from(bucket: "a")
+    |> range(start: -1h)
+    |> filter(fn: (r) => r._measurement == "cpu")
+    |> aggregateWindow(every: 5m, fn: mean)
+    |> map(fn: (r) => ({r with _value: r._value * 2.0}))
+"#;
+        let package = get_package(fluxscript);
+        let init = match &package.files[0].body[0] {
+            flux::semantic::nodes::Statement::Variable(var) => {
+                &var.init
+            }
+            _ => unreachable!(),
+        };
+
+        assert_eq!(
+            Some(vec![
+                ("from", true),
+                ("range", true),
+                ("filter", true),
+                ("aggregateWindow", true),
+                ("map", false),
+            ]),
+            pushdown_boundary(init)
+        );
+    }
+}
@@ -0,0 +1,161 @@
+/// Support of the parameters of InfluxDB parameterized queries
+///
+/// Queries sent to InfluxDB with `params` refer to them as `params.<name>`, e.g.
+/// `range(start: params.start)`. Flux knows nothing of `params`, so such scripts only
+/// had undefined identifier errors. Clients declare the params their queries are sent
+/// with, along with their types, with the `queryParams` setting. Once they do, `params`
+/// is no longer reported as undefined, its members are completed, and members that
+/// aren't declared are reported instead.
+use std::collections::BTreeMap;
+
+use flux::semantic::nodes::{
+    ErrorKind, Expression, MemberExpr, Package, Statement,
+};
+use flux::semantic::walk::Node as WalkNode;
+use lspower::lsp;
+
+use crate::convert;
+
+/// The diagnostic code of params that aren't declared in the `queryParams` setting.
+pub(crate) const UNDECLARED_PARAM: &str = "undeclared-param";
+
+/// The name parameterized queries refer to their params by.
+pub(crate) const PARAMS: &str = "params";
+
+/// Whether a package defines `params` itself, in which case it isn't the params of
+/// the query.
+fn defines_params(pkg: &Package) -> bool {
+    pkg.files.iter().flat_map(|file| file.body.iter()).any(
+        |statement| {
+            matches!(statement, Statement::Variable(assignment) if assignment.id.name == PARAMS)
+        },
+    )
+}
+
+/// Whether an error is `params` being undefined, which it is to flux.
+pub(crate) fn is_params_error(error: &flux::semantic::Error) -> bool {
+    matches!(
+        &error.error,
+        flux::semantic::ErrorKind::Inference(ErrorKind::UndefinedIdentifier(name))
+            if name == PARAMS
+    )
+}
+
+#[derive(Default)]
+struct ParamsVisitor<'a> {
+    members: Vec<&'a MemberExpr>,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a> for ParamsVisitor<'a> {
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        if let WalkNode::MemberExpr(member) = node {
+            if matches!(&member.object, Expression::Identifier(ident) if ident.name == PARAMS)
+            {
+                self.members.push(member);
+            }
+        }
+        true
+    }
+}
+
+/// Members of `params` that aren't among the declared params.
+///
+/// Nothing is reported until params are declared, or when the package defines
+/// `params` itself.
+pub(crate) fn undeclared_params(
+    pkg: &Package,
+    params: Option<&BTreeMap<String, String>>,
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    let params = match params {
+        Some(params) if !defines_params(pkg) => params,
+        _ => return vec![],
+    };
+
+    let visitor =
+        crate::walk_semantic_package!(ParamsVisitor::default(), pkg);
+    visitor
+        .members
+        .into_iter()
+        .filter(|member| {
+            !params.contains_key(member.property.as_str())
+        })
+        .map(|member| {
+            (
+                member.loc.file.clone(),
+                lsp::Diagnostic {
+                    range: convert::location_to_range(&member.loc),
+                    severity: Some(lsp::DiagnosticSeverity::WARNING),
+                    code: Some(lsp::NumberOrString::String(
+                        UNDECLARED_PARAM.into(),
+                    )),
+                    message: format!(
+                        "There is no query parameter named \"{}\".",
+                        member.property
+                    ),
+                    ..lsp::Diagnostic::default()
+                },
+            )
+        })
+        .collect()
+}
+
+/// Completion items of the declared params, along with their types.
+pub(crate) fn param_items(
+    params: &BTreeMap<String, String>,
+) -> Vec<lsp::CompletionItem> {
+    params
+        .iter()
+        .map(|(name, typ)| lsp::CompletionItem {
+            label: name.clone(),
+            detail: Some(format!("query parameter: {}", typ)),
+            kind: Some(lsp::CompletionItemKind::FIELD),
+            ..lsp::CompletionItem::default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::get_salvaged_package;
+
+    #[test]
+    fn undeclared_members_of_params() {
+        let fluxscript = r#"from(bucket: params.bucket)
+    |> range(start: params.start)
+    |> filter(fn: (r) => r.host == params.hots)
+"#;
+        let package = get_salvaged_package("script.flux", fluxscript);
+        let params = BTreeMap::from([
+            ("bucket".to_string(), "string".to_string()),
+            ("start".to_string(), "time".to_string()),
+            ("host".to_string(), "string".to_string()),
+        ]);
+        let messages = |params| {
+            undeclared_params(&package, params)
+                .into_iter()
+                .map(|(_, diagnostic)| {
+                    (diagnostic.range.start.line, diagnostic.message)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            vec![(
+                2,
+                "There is no query parameter named \"hots\"."
+                    .to_string()
+            )],
+            messages(Some(&params))
+        );
+        assert!(messages(None).is_empty());
+        assert!(undeclared_params(
+            &get_salvaged_package(
+                "script.flux",
+                "params = {a: 1}\nparams.b\n"
+            ),
+            Some(&params)
+        )
+        .is_empty());
+    }
+}
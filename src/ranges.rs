@@ -0,0 +1,250 @@
+/// Checks of the time bounds of `range` calls
+///
+/// A `range` starting at `now()`, or with a start after its stop, returns nothing,
+/// and one starting at `0` reads every point since 1970. Both are easily written by
+/// mistake and only noticed once a query runs. Reading from storage without a `range`
+/// at all fails outright, which is the most common mistake of newcomers to flux.
+use flux::ast;
+use flux::semantic::nodes::{
+    CallExpr, DateTimeLit, Expression, Package,
+};
+use flux::semantic::walk::Node as WalkNode;
+use lspower::lsp;
+
+use crate::convert;
+use crate::diagnostics::callee_name;
+use crate::perf_lint::pipelines;
+
+/// The diagnostic code of ranges that can't contain any point.
+pub(crate) const EMPTY_RANGE: &str = "empty-range";
+/// The diagnostic code of ranges starting at the beginning of time.
+pub(crate) const UNBOUNDED_RANGE: &str = "unbounded-range";
+/// The diagnostic code of pipelines reading from storage without a `range`.
+pub(crate) const MISSING_RANGE: &str = "missing-range";
+
+/// The nanoseconds of a month, which is close enough to compare durations.
+const MONTH: i128 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+/// A bound of a range, when it's written as a literal.
+enum Bound<'a> {
+    /// The nanoseconds from now, e.g. `-1h` or `now()`.
+    Relative(i128),
+    Absolute(&'a DateTimeLit),
+}
+
+fn bound(expression: &Expression) -> Option<Bound> {
+    match expression {
+        Expression::Duration(lit) => {
+            let nanoseconds = i128::from(lit.value.months) * MONTH
+                + i128::from(lit.value.nanoseconds);
+            Some(Bound::Relative(if lit.value.negative {
+                -nanoseconds
+            } else {
+                nanoseconds
+            }))
+        }
+        Expression::Unary(unary)
+            if unary.operator
+                == ast::Operator::SubtractionOperator =>
+        {
+            match bound(&unary.argument)? {
+                Bound::Relative(nanoseconds) => {
+                    Some(Bound::Relative(-nanoseconds))
+                }
+                Bound::Absolute(_) => None,
+            }
+        }
+        Expression::DateTime(lit) => Some(Bound::Absolute(lit)),
+        Expression::Call(call)
+            if callee_name(call) == Some("now")
+                && call.arguments.is_empty() =>
+        {
+            Some(Bound::Relative(0))
+        }
+        _ => None,
+    }
+}
+
+/// Whether a range between two bounds can't contain any point, if it can be told.
+fn is_empty(start: &Bound, stop: &Bound) -> bool {
+    match (start, stop) {
+        (Bound::Relative(start), Bound::Relative(stop)) => {
+            start >= stop
+        }
+        (Bound::Absolute(start), Bound::Absolute(stop)) => {
+            start.value >= stop.value
+        }
+        _ => false,
+    }
+}
+
+#[derive(Default)]
+struct RangeVisitor<'a> {
+    calls: Vec<&'a CallExpr>,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a> for RangeVisitor<'a> {
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        if let WalkNode::CallExpr(call) = node {
+            if matches!(&call.callee, Expression::Identifier(ident) if ident.name == "range")
+            {
+                self.calls.push(call);
+            }
+        }
+        true
+    }
+}
+
+fn range_diagnostic(
+    loc: &ast::SourceLocation,
+    code: &str,
+    message: String,
+) -> (Option<String>, lsp::Diagnostic) {
+    (
+        loc.file.clone(),
+        lsp::Diagnostic {
+            range: convert::location_to_range(loc),
+            severity: Some(lsp::DiagnosticSeverity::WARNING),
+            code: Some(lsp::NumberOrString::String(code.into())),
+            message,
+            ..lsp::Diagnostic::default()
+        },
+    )
+}
+
+/// `range` calls whose literal bounds can't contain any point, or start at `0`.
+pub(crate) fn range_bounds(
+    pkg: &Package,
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    let visitor =
+        crate::walk_semantic_package!(RangeVisitor::default(), pkg);
+    visitor
+        .calls
+        .into_iter()
+        .filter_map(|call| {
+            let argument = |name| {
+                call.arguments
+                    .iter()
+                    .find(|argument| argument.key.name == name)
+            };
+            let start = argument("start")?;
+            if matches!(&start.value, Expression::Integer(lit) if lit.value == 0)
+            {
+                return Some(range_diagnostic(
+                    &start.loc,
+                    UNBOUNDED_RANGE,
+                    "This range starts in 1970, so every point ever written is read. Consider a relative start, like `-1h`.".into(),
+                ));
+            }
+            // The stop defaults to now.
+            let stop = match argument("stop") {
+                Some(stop) => bound(&stop.value)?,
+                None => Bound::Relative(0),
+            };
+            if is_empty(&bound(&start.value)?, &stop) {
+                return Some(range_diagnostic(
+                    &call.loc,
+                    EMPTY_RANGE,
+                    "This range starts at or after its stop, so it never contains any point.".into(),
+                ));
+            }
+            None
+        })
+        .collect()
+}
+
+/// Pipelines reading from storage without a `range` before their first stage other
+/// than `filter`, e.g. `from(bucket: "a") |> mean()`.
+///
+/// InfluxDB refuses to read from storage without bounds, so these queries fail.
+pub(crate) fn missing_ranges(
+    pkg: &Package,
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    pipelines(pkg)
+        .into_iter()
+        // A lone `from` may be piped into a `range` later on, e.g. through a variable.
+        .filter(|stages| stages.len() > 1)
+        .filter_map(|stages| {
+            let message = match stages[1..]
+                .iter()
+                .filter_map(|stage| callee_name(stage))
+                .find(|name| *name != "filter")
+            {
+                Some("range") => return None,
+                Some(name) => format!(
+                    "This data is read without a `range` before `{}`. Reading from storage requires bounds, so add a `range` right after `from`.",
+                    name
+                ),
+                None => "This data is read without a `range`. Reading from storage requires bounds, so add a `range` right after `from`.".into(),
+            };
+            Some(range_diagnostic(&stages[0].loc, MISSING_RANGE, message))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::get_package;
+
+    fn codes(
+        diagnostics: Vec<(Option<String>, lsp::Diagnostic)>,
+    ) -> Vec<(u32, String)> {
+        diagnostics
+            .into_iter()
+            .map(|(_, diagnostic)| {
+                (
+                    diagnostic.range.start.line,
+                    match diagnostic.code {
+                        Some(lsp::NumberOrString::String(code)) => {
+                            code
+                        }
+                        _ => String::new(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ranges_with_literal_bounds() {
+        let fluxscript = r#"from(bucket: "a") |> range(start: 0)
+from(bucket: "a") |> range(start: now())
+from(bucket: "a") |> range(start: -1h, stop: -2h)
+from(bucket: "a") |> range(start: 2023-01-02T00:00:00Z, stop: 2023-01-01T00:00:00Z)
+from(bucket: "a") |> range(start: -2h, stop: -1h)
+from(bucket: "a") |> range(start: 2023-01-01T00:00:00Z)
+from(bucket: "a") |> range(start: -1mo, stop: now())
+"#;
+        let package = get_package(fluxscript);
+
+        assert_eq!(
+            vec![
+                (0, UNBOUNDED_RANGE.to_string()),
+                (1, EMPTY_RANGE.to_string()),
+                (2, EMPTY_RANGE.to_string()),
+                (3, EMPTY_RANGE.to_string()),
+            ],
+            codes(range_bounds(&package))
+        );
+    }
+
+    #[test]
+    fn pipelines_without_range() {
+        let fluxscript = r#"from(bucket: "a") |> mean()
+from(bucket: "a") |> filter(fn: (r) => r._measurement == "cpu") |> range(start: -1h)
+from(bucket: "a") |> filter(fn: (r) => r._measurement == "cpu")
+data = from(bucket: "a")
+data |> range(start: -1h)
+"#;
+        let package = get_package(fluxscript);
+
+        assert_eq!(
+            vec![
+                (0, MISSING_RANGE.to_string()),
+                (2, MISSING_RANGE.to_string()),
+            ],
+            codes(missing_ranges(&package))
+        );
+    }
+}
@@ -0,0 +1,790 @@
+/// Schema aware analysis of flux code
+///
+/// Clients push the schema of the data they can query (buckets, and the measurements,
+/// fields and tags in them) with the `flux/updateSchema` notification. Bucket,
+/// measurement and field names written in a query are then completed, described on
+/// hover and checked against that schema. So are the names passed to the functions of
+/// the `influxdata/influxdb/schema` package, e.g. `schema.measurementTagKeys`.
+///
+/// Within a document, the other places a measurement name is written are highlighted
+/// along with the one under the cursor, whether it is filtered on or set before `to`.
+use flux::ast;
+use flux::ast::walk::Node as AstNode;
+use flux::semantic::nodes::{Expression, Package, StringLit};
+use flux::semantic::walk::Node as WalkNode;
+use lspower::lsp;
+
+use crate::convert;
+use crate::diagnostics::callee_name;
+use crate::server::protocol_ext::Schema;
+use crate::visitors::ast::{property_name, NodeFinderNode};
+
+/// The diagnostic code of names that aren't in the schema pushed by the client.
+pub(crate) const UNKNOWN_SCHEMA_NAME: &str = "unknown-schema-name";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum NameKind {
+    Bucket,
+    Measurement,
+    Field,
+    Tag,
+}
+
+/// The functions of the `influxdata/influxdb/schema` package exploring the schema,
+/// whose `bucket`, `measurement` and `tag` arguments are schema names.
+const SCHEMA_FUNCTIONS: &[&str] = &[
+    "fieldKeys",
+    "measurementFieldKeys",
+    "measurementTagKeys",
+    "measurementTagValues",
+    "measurements",
+    "tagKeys",
+    "tagValues",
+];
+
+impl NameKind {
+    fn column(column: &str) -> Option<Self> {
+        match column {
+            "_measurement" => Some(NameKind::Measurement),
+            "_field" => Some(NameKind::Field),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn describe(&self) -> &'static str {
+        match self {
+            NameKind::Bucket => "bucket",
+            NameKind::Measurement => "measurement",
+            NameKind::Field => "field",
+            NameKind::Tag => "tag",
+        }
+    }
+}
+
+/// The names of a kind known from the schema, along with the bucket names from the
+/// `buckets` setting.
+///
+/// Measurements and fields are those of every bucket, as the bucket a pipeline reads
+/// from isn't always known.
+pub(crate) fn known_names<'a>(
+    schema: &'a Schema,
+    buckets: &'a [String],
+    kind: NameKind,
+) -> Vec<&'a str> {
+    let mut names: Vec<&str> = match kind {
+        NameKind::Bucket => schema
+            .buckets
+            .keys()
+            .chain(buckets.iter())
+            .map(String::as_str)
+            .collect(),
+        NameKind::Measurement => schema
+            .buckets
+            .values()
+            .flat_map(|bucket| bucket.measurements.keys())
+            .map(String::as_str)
+            .collect(),
+        NameKind::Field => schema
+            .buckets
+            .values()
+            .flat_map(|bucket| bucket.measurements.values())
+            .flat_map(|measurement| measurement.fields.iter())
+            .map(String::as_str)
+            .collect(),
+        NameKind::Tag => schema
+            .buckets
+            .values()
+            .flat_map(|bucket| bucket.measurements.values())
+            .flat_map(|measurement| measurement.tags.iter())
+            .map(String::as_str)
+            .collect(),
+    };
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+/// What the schema says about a name, as titled lists of names, e.g. the fields and
+/// tags of a measurement.
+pub(crate) fn describe_name(
+    schema: &Schema,
+    kind: NameKind,
+    name: &str,
+) -> Vec<(&'static str, Vec<String>)> {
+    match kind {
+        NameKind::Bucket => schema
+            .buckets
+            .get(name)
+            .map(|bucket| {
+                vec![(
+                    "Measurements",
+                    bucket.measurements.keys().cloned().collect(),
+                )]
+            })
+            .unwrap_or_default(),
+        NameKind::Measurement => {
+            let mut fields: Vec<String> = vec![];
+            let mut tags: Vec<String> = vec![];
+            for measurement in schema
+                .buckets
+                .values()
+                .filter_map(|bucket| bucket.measurements.get(name))
+            {
+                fields.extend(measurement.fields.iter().cloned());
+                tags.extend(measurement.tags.iter().cloned());
+            }
+            fields.sort_unstable();
+            fields.dedup();
+            tags.sort_unstable();
+            tags.dedup();
+            if fields.is_empty() && tags.is_empty() {
+                vec![]
+            } else {
+                vec![("Fields", fields), ("Tags", tags)]
+            }
+        }
+        NameKind::Field | NameKind::Tag => {
+            let mut measurements: Vec<String> = schema
+                .buckets
+                .values()
+                .flat_map(|bucket| bucket.measurements.iter())
+                .filter(|(_, measurement)| {
+                    let columns = match kind {
+                        NameKind::Tag => &measurement.tags,
+                        _ => &measurement.fields,
+                    };
+                    columns.iter().any(|column| column == name)
+                })
+                .map(|(measurement, _)| measurement.clone())
+                .collect();
+            measurements.sort_unstable();
+            measurements.dedup();
+            if measurements.is_empty() {
+                vec![]
+            } else {
+                vec![("Measurements", measurements)]
+            }
+        }
+    }
+}
+
+/// The names of a kind in the buckets and measurements a call of a schema function
+/// is narrowed to, e.g. the tags of `cpu` in
+/// `schema.measurementTagKeys(bucket: "telegraf", measurement: "cpu")`.
+///
+/// Buckets and measurements that aren't in the schema don't narrow the names down.
+pub(crate) fn names_in_scope<'a>(
+    schema: &'a Schema,
+    kind: NameKind,
+    bucket: Option<&str>,
+    measurement: Option<&str>,
+) -> Vec<&'a str> {
+    let buckets = schema
+        .buckets
+        .iter()
+        .filter(|(name, _)| {
+            bucket.map_or(true, |bucket| {
+                !schema.buckets.contains_key(bucket)
+                    || name.as_str() == bucket
+            })
+        })
+        .map(|(_, bucket)| bucket);
+    let mut names: Vec<&str> = match kind {
+        NameKind::Bucket => {
+            schema.buckets.keys().map(String::as_str).collect()
+        }
+        NameKind::Measurement => buckets
+            .flat_map(|bucket| bucket.measurements.keys())
+            .map(String::as_str)
+            .collect(),
+        NameKind::Field | NameKind::Tag => {
+            let measurements: Vec<_> = buckets
+                .flat_map(|bucket| bucket.measurements.iter())
+                .collect();
+            let known = measurement.map_or(false, |measurement| {
+                measurements
+                    .iter()
+                    .any(|(name, _)| *name == measurement)
+            });
+            measurements
+                .into_iter()
+                .filter(|(name, _)| {
+                    !known || Some(name.as_str()) == measurement
+                })
+                .flat_map(|(_, measurement)| match kind {
+                    NameKind::Tag => measurement.tags.iter(),
+                    _ => measurement.fields.iter(),
+                })
+                .map(String::as_str)
+                .collect()
+        }
+    };
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+/// The schema names written as string literals in a node, i.e. the bucket of a
+/// `from` call or the measurement or field compared with `r._measurement` or
+/// `r._field`.
+pub(crate) fn schema_names<'a>(
+    node: &WalkNode<'a>,
+) -> Vec<(NameKind, &'a StringLit)> {
+    match node {
+        WalkNode::CallExpr(call)
+            if callee_name(call) == Some("from") =>
+        {
+            call.arguments
+                .iter()
+                .filter(|argument| argument.key.name == "bucket")
+                .filter_map(|argument| match &argument.value {
+                    Expression::StringLit(lit) => {
+                        Some((NameKind::Bucket, lit))
+                    }
+                    _ => None,
+                })
+                .collect()
+        }
+        WalkNode::BinaryExpr(binary)
+            if matches!(
+                binary.operator,
+                ast::Operator::EqualOperator
+                    | ast::Operator::NotEqualOperator
+            ) =>
+        {
+            match (&binary.left, &binary.right) {
+                (
+                    Expression::Member(member),
+                    Expression::StringLit(lit),
+                )
+                | (
+                    Expression::StringLit(lit),
+                    Expression::Member(member),
+                ) => NameKind::column(&member.property)
+                    .map(|kind| vec![(kind, lit)])
+                    .unwrap_or_default(),
+                _ => vec![],
+            }
+        }
+        _ => vec![],
+    }
+}
+
+#[derive(Default)]
+struct SchemaNameVisitor<'a> {
+    names: Vec<(NameKind, &'a StringLit)>,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a> for SchemaNameVisitor<'a> {
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        self.names.extend(schema_names(&node));
+        true
+    }
+}
+
+/// The schema names written in a package, e.g. the buckets a script reads from.
+#[cfg(feature = "native-queries")]
+pub(crate) fn referenced_names(
+    pkg: &Package,
+) -> Vec<(NameKind, String)> {
+    let visitor = crate::walk_semantic_package!(
+        SchemaNameVisitor::default(),
+        pkg
+    );
+    visitor
+        .names
+        .into_iter()
+        .map(|(kind, lit)| (kind, lit.value.clone()))
+        .collect()
+}
+
+/// Bucket, measurement and field names that aren't in the schema, which are most
+/// likely typos.
+///
+/// Nothing is reported until the client has pushed a schema.
+pub(crate) fn unknown_schema_names(
+    pkg: &Package,
+    schema: &Schema,
+    buckets: &[String],
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    if schema.buckets.is_empty() {
+        return vec![];
+    }
+
+    let visitor = crate::walk_semantic_package!(
+        SchemaNameVisitor::default(),
+        pkg
+    );
+    visitor
+        .names
+        .into_iter()
+        .filter(|(kind, lit)| {
+            !known_names(schema, buckets, *kind)
+                .contains(&lit.value.as_str())
+        })
+        .map(|(kind, lit)| {
+            (
+                lit.loc.file.clone(),
+                lsp::Diagnostic {
+                    range: convert::location_to_range(&lit.loc),
+                    severity: Some(lsp::DiagnosticSeverity::WARNING),
+                    code: Some(lsp::NumberOrString::String(
+                        UNKNOWN_SCHEMA_NAME.into(),
+                    )),
+                    message: format!(
+                        "There is no {} named \"{}\" in the schema.",
+                        kind.describe(),
+                        lit.value
+                    ),
+                    ..lsp::Diagnostic::default()
+                },
+            )
+        })
+        .collect()
+}
+
+/// Finds the `bucket` arguments of `from` and `to` calls naming a bucket.
+struct BucketReferenceVisitor<'a> {
+    bucket: &'a str,
+    ranges: Vec<lsp::Range>,
+}
+
+impl<'a> ast::walk::Visitor<'a> for BucketReferenceVisitor<'_> {
+    fn visit(&mut self, node: AstNode<'a>) -> bool {
+        let call = match node {
+            AstNode::CallExpr(call) => call,
+            _ => return true,
+        };
+        let callee = match &call.callee {
+            ast::Expression::Identifier(ident) => ident.name.as_str(),
+            ast::Expression::Member(member) => {
+                property_name(&member.property)
+            }
+            _ => return true,
+        };
+        if callee != "from" && callee != "to" {
+            return true;
+        }
+        for argument in &call.arguments {
+            let object = match argument {
+                ast::Expression::Object(object) => object,
+                _ => continue,
+            };
+            for property in &object.properties {
+                match &property.value {
+                    Some(ast::Expression::StringLit(lit))
+                        if property_name(&property.key)
+                            == "bucket"
+                            && lit.value == self.bucket =>
+                    {
+                        self.ranges.push(convert::location_to_range(
+                            &lit.base.location,
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        true
+    }
+}
+
+/// The ranges of a file where a bucket is read with `from` or written with `to`.
+pub(crate) fn bucket_references(
+    file: &ast::File,
+    bucket: &str,
+) -> Vec<lsp::Range> {
+    let mut visitor = BucketReferenceVisitor {
+        bucket,
+        ranges: vec![],
+    };
+    ast::walk::walk(&mut visitor, AstNode::File(file));
+    visitor.ranges
+}
+
+/// Finds the string literals naming a measurement: those compared with
+/// `r._measurement`, the `_measurement` of records, e.g. `({r with _measurement: "cpu"})`
+/// before `to`, the value of `set(key: "_measurement", ...)` and the `measurement`
+/// arguments of schema functions.
+#[derive(Default)]
+struct MeasurementLiteralVisitor<'a> {
+    literals: Vec<&'a ast::StringLit>,
+}
+
+impl<'a> ast::walk::Visitor<'a> for MeasurementLiteralVisitor<'a> {
+    fn visit(&mut self, node: AstNode<'a>) -> bool {
+        match node {
+            AstNode::BinaryExpr(binary)
+                if matches!(
+                    binary.operator,
+                    ast::Operator::EqualOperator
+                        | ast::Operator::NotEqualOperator
+                ) =>
+            {
+                match (&binary.left, &binary.right) {
+                    (
+                        ast::Expression::Member(member),
+                        ast::Expression::StringLit(lit),
+                    )
+                    | (
+                        ast::Expression::StringLit(lit),
+                        ast::Expression::Member(member),
+                    ) if property_name(&member.property)
+                        == "_measurement" =>
+                    {
+                        self.literals.push(lit)
+                    }
+                    _ => {}
+                }
+            }
+            AstNode::Property(property)
+                if property_name(&property.key) == "_measurement" =>
+            {
+                if let Some(ast::Expression::StringLit(lit)) =
+                    &property.value
+                {
+                    self.literals.push(lit)
+                }
+            }
+            AstNode::CallExpr(call) => {
+                let set = matches!(&call.callee, ast::Expression::Identifier(ident) if ident.name == "set");
+                let schema = is_schema_function(call);
+                for argument in &call.arguments {
+                    let object = match argument {
+                        ast::Expression::Object(object) => object,
+                        _ => continue,
+                    };
+                    let argument = |name: &str| {
+                        object.properties.iter().find_map(
+                            |property| match &property.value {
+                                Some(ast::Expression::StringLit(
+                                    lit,
+                                )) if property_name(
+                                    &property.key,
+                                ) == name =>
+                                {
+                                    Some(lit)
+                                }
+                                _ => None,
+                            },
+                        )
+                    };
+                    if schema {
+                        self.literals.extend(argument("measurement"));
+                    } else if set
+                        && argument("key")
+                            .map(|key| key.value.as_str())
+                            == Some("_measurement")
+                    {
+                        self.literals.extend(argument("value"));
+                    }
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+/// The ranges of a file where the measurement named by the string literal at a
+/// position is written, or nothing if there is no measurement name at the position.
+pub(crate) fn measurement_references(
+    file: &ast::File,
+    position: lsp::Position,
+) -> Option<Vec<lsp::Range>> {
+    let mut visitor = MeasurementLiteralVisitor::default();
+    ast::walk::walk(&mut visitor, AstNode::File(file));
+    let measurement = visitor.literals.iter().find(|lit| {
+        convert::location_contains(&lit.base.location, &position)
+    })?;
+    Some(
+        visitor
+            .literals
+            .iter()
+            .filter(|lit| lit.value == measurement.value)
+            .map(|lit| convert::location_to_range(&lit.base.location))
+            .collect(),
+    )
+}
+
+/// Whether a call is of a function of the `influxdata/influxdb/schema` package.
+fn is_schema_function(call: &ast::CallExpr) -> bool {
+    match &call.callee {
+        ast::Expression::Member(member) => {
+            matches!(&member.object, ast::Expression::Identifier(ident) if ident.name == "schema")
+                && SCHEMA_FUNCTIONS
+                    .contains(&property_name(&member.property))
+        }
+        _ => false,
+    }
+}
+
+/// The `bucket` and `measurement` string arguments of the call of a schema function
+/// a string literal being completed is an argument of, e.g. the bucket `telegraf` when
+/// completing the measurement of `schema.measurementTagKeys(bucket: "telegraf", measurement: "")`.
+pub(crate) fn completed_name_scope(
+    node: &NodeFinderNode,
+) -> (Option<String>, Option<String>) {
+    let call = match node
+        .parent
+        .as_deref()
+        .and_then(|property| property.parent.as_deref())
+        .and_then(|object| object.parent.as_deref())
+        .map(|call| &call.node)
+    {
+        Some(AstNode::CallExpr(call)) if is_schema_function(call) => {
+            call
+        }
+        _ => return (None, None),
+    };
+    let argument = |name: &str| {
+        call.arguments.iter().find_map(|arguments| match arguments {
+            ast::Expression::Object(object) => object
+                .properties
+                .iter()
+                .find_map(|property| match &property.value {
+                    Some(ast::Expression::StringLit(lit))
+                        if property_name(&property.key) == name =>
+                    {
+                        Some(lit.value.clone())
+                    }
+                    _ => None,
+                }),
+            _ => None,
+        })
+    };
+    (argument("bucket"), argument("measurement"))
+}
+
+/// The kind of schema name a string literal being completed is, from the nodes
+/// enclosing it.
+pub(crate) fn completed_name_kind(
+    node: &NodeFinderNode,
+) -> Option<NameKind> {
+    let parent = node.parent.as_deref()?;
+    match &parent.node {
+        // `bucket: "..."` in a call of `from`
+        AstNode::Property(property) => {
+            let key = match &property.key {
+                ast::PropertyKey::Identifier(ident) => {
+                    ident.name.as_str()
+                }
+                ast::PropertyKey::StringLit(lit) => {
+                    lit.value.as_str()
+                }
+            };
+            let object = parent.parent.as_deref()?;
+            let call = match &object.parent.as_deref()?.node {
+                AstNode::CallExpr(call) => call,
+                _ => return None,
+            };
+            if is_schema_function(call) {
+                return match key {
+                    "bucket" => Some(NameKind::Bucket),
+                    "measurement" => Some(NameKind::Measurement),
+                    "tag" => Some(NameKind::Tag),
+                    _ => None,
+                };
+            }
+            let from = match &call.callee {
+                ast::Expression::Identifier(ident) => {
+                    ident.name == "from"
+                }
+                ast::Expression::Member(member) => {
+                    matches!(&member.property, ast::PropertyKey::Identifier(ident) if ident.name == "from")
+                }
+                _ => false,
+            };
+            (from && key == "bucket").then_some(NameKind::Bucket)
+        }
+        // `r._measurement == "..."` or `r._field == "..."`
+        AstNode::BinaryExpr(binary) => {
+            let column =
+                |expression: &ast::Expression| match expression {
+                    ast::Expression::Member(member) => {
+                        match &member.property {
+                            ast::PropertyKey::Identifier(ident) => {
+                                NameKind::column(&ident.name)
+                            }
+                            ast::PropertyKey::StringLit(lit) => {
+                                NameKind::column(&lit.value)
+                            }
+                        }
+                    }
+                    _ => None,
+                };
+            column(&binary.left).or_else(|| column(&binary.right))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::protocol_ext::{
+        BucketSchema, MeasurementSchema,
+    };
+    use crate::test_support::get_package;
+
+    fn schema() -> Schema {
+        Schema {
+            buckets: [(
+                "telegraf".to_string(),
+                BucketSchema {
+                    measurements: [(
+                        "cpu".to_string(),
+                        MeasurementSchema {
+                            fields: vec!["usage_user".into()],
+                            tags: vec!["host".into()],
+                        },
+                    )]
+                    .into(),
+                },
+            )]
+            .into(),
+        }
+    }
+
+    #[test]
+    fn unknown_names() {
+        let fluxscript = r#"from(bucket: "telegraf")
+    |> range(start: -1h)
+    |> filter(fn: (r) => r._measurement == "cpu" and r._field == "usage_system")
+
+from(bucket: "other")
+    |> range(start: -1h)
+"#;
+        let package = get_package(fluxscript);
+
+        let diagnostics =
+            unknown_schema_names(&package, &schema(), &[]);
+
+        assert_eq!(
+            vec![
+                "There is no field named \"usage_system\" in the schema.",
+                "There is no bucket named \"other\" in the schema.",
+            ],
+            diagnostics
+                .iter()
+                .map(|(_, diagnostic)| diagnostic.message.as_str())
+                .collect::<Vec<&str>>()
+        );
+
+        // Buckets from the settings are known too.
+        assert_eq!(
+            1,
+            unknown_schema_names(
+                &package,
+                &schema(),
+                &["other".into()]
+            )
+            .len()
+        );
+    }
+
+    #[test]
+    fn names_in_scope_of_schema_functions() {
+        let mut schema = schema();
+        schema.buckets.insert(
+            "system".into(),
+            BucketSchema {
+                measurements: [(
+                    "disk".to_string(),
+                    MeasurementSchema {
+                        fields: vec!["free".into()],
+                        tags: vec!["device".into()],
+                    },
+                )]
+                .into(),
+            },
+        );
+
+        assert_eq!(
+            vec!["cpu"],
+            names_in_scope(
+                &schema,
+                NameKind::Measurement,
+                Some("telegraf"),
+                None
+            )
+        );
+        assert_eq!(
+            vec!["device"],
+            names_in_scope(
+                &schema,
+                NameKind::Tag,
+                None,
+                Some("disk")
+            )
+        );
+        // Names that aren't in the schema don't narrow anything down.
+        assert_eq!(
+            vec!["device", "host"],
+            names_in_scope(
+                &schema,
+                NameKind::Tag,
+                Some("other"),
+                Some("other")
+            )
+        );
+    }
+
+    #[test]
+    fn measurement_references_in_file() {
+        let fluxscript = r#"import "influxdata/influxdb/schema"
+
+from(bucket: "telegraf")
+    |> filter(fn: (r) => r._measurement == "cpu")
+    |> set(key: "_measurement", value: "cpu")
+    |> to(bucket: "downsampled")
+
+from(bucket: "telegraf")
+    |> filter(fn: (r) => "mem" == r._measurement or r["_measurement"] != "cpu")
+    |> map(fn: (r) => ({r with _measurement: "cpu", host: "cpu"}))
+
+schema.measurementTagKeys(bucket: "telegraf", measurement: "cpu")
+"#;
+        let file = flux::parser::parse_string(
+            "script.flux".into(),
+            fluxscript,
+        );
+        let lines = |line, character| {
+            measurement_references(
+                &file,
+                lsp::Position { line, character },
+            )
+            .map(|ranges| {
+                ranges
+                    .into_iter()
+                    .map(|range| {
+                        (range.start.line, range.start.character)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(
+            Some(vec![(3, 43), (4, 39), (8, 73), (9, 45), (11, 59)]),
+            lines(3, 45)
+        );
+        assert_eq!(Some(vec![(8, 25)]), lines(8, 26));
+        // Neither the bucket nor a tag value is a measurement.
+        assert_eq!(None, lines(2, 15));
+        assert_eq!(None, lines(9, 58));
+    }
+
+    #[test]
+    fn unknown_names_without_schema() {
+        let fluxscript =
+            r#"from(bucket: "other") |> range(start: -1h)"#;
+        let package = get_package(fluxscript);
+
+        assert!(unknown_schema_names(
+            &package,
+            &Schema::default(),
+            &[]
+        )
+        .is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+/// Completion and checks of the keys of secrets read with `secrets.get`
+///
+/// Clients push the keys of the secrets they store with the `flux/updateSecrets`
+/// notification. The `key` argument of `secrets.get` is then completed with those
+/// keys, and keys that aren't among them are reported.
+use flux::ast;
+use flux::ast::walk::Node as AstNode;
+use flux::semantic::nodes::{
+    CallExpr, Expression, Package, StringLit,
+};
+use flux::semantic::walk::Node as WalkNode;
+use lspower::lsp;
+
+use crate::convert;
+use crate::visitors::ast::NodeFinderNode;
+
+/// The diagnostic code of secret keys that aren't in the keys pushed by the client.
+pub(crate) const UNKNOWN_SECRET_KEY: &str = "unknown-secret-key";
+
+/// The key of a `secrets.get` call, when it's a string literal.
+fn secret_key(call: &CallExpr) -> Option<&StringLit> {
+    let is_get = match &call.callee {
+        Expression::Member(member) => {
+            member.property.as_str() == "get"
+                && matches!(&member.object, Expression::Identifier(ident) if ident.name == "secrets")
+        }
+        _ => false,
+    };
+    if !is_get {
+        return None;
+    }
+    call.arguments
+        .iter()
+        .find(|argument| argument.key.name == "key")
+        .and_then(|argument| match &argument.value {
+            Expression::StringLit(lit) => Some(lit),
+            _ => None,
+        })
+}
+
+#[derive(Default)]
+struct SecretKeyVisitor<'a> {
+    keys: Vec<&'a StringLit>,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a> for SecretKeyVisitor<'a> {
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        if let WalkNode::CallExpr(call) = node {
+            self.keys.extend(secret_key(call));
+        }
+        true
+    }
+}
+
+/// Secret keys that aren't among the keys pushed by the client.
+///
+/// Nothing is reported until the client has pushed its keys.
+pub(crate) fn unknown_secret_keys(
+    pkg: &Package,
+    keys: Option<&[String]>,
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    let keys = match keys {
+        Some(keys) => keys,
+        None => return vec![],
+    };
+
+    let visitor = crate::walk_semantic_package!(
+        SecretKeyVisitor::default(),
+        pkg
+    );
+    visitor
+        .keys
+        .into_iter()
+        .filter(|lit| !keys.contains(&lit.value))
+        .map(|lit| {
+            (
+                lit.loc.file.clone(),
+                lsp::Diagnostic {
+                    range: convert::location_to_range(&lit.loc),
+                    severity: Some(lsp::DiagnosticSeverity::WARNING),
+                    code: Some(lsp::NumberOrString::String(
+                        UNKNOWN_SECRET_KEY.into(),
+                    )),
+                    message: format!(
+                        "There is no secret with the key \"{}\".",
+                        lit.value
+                    ),
+                    ..lsp::Diagnostic::default()
+                },
+            )
+        })
+        .collect()
+}
+
+/// Whether a string literal being completed is the `key` argument of `secrets.get`.
+pub(crate) fn is_completed_key(node: &NodeFinderNode) -> bool {
+    let property = match node.parent.as_deref() {
+        Some(parent) => parent,
+        None => return false,
+    };
+    let key = match &property.node {
+        AstNode::Property(property) => match &property.key {
+            ast::PropertyKey::Identifier(ident) => {
+                ident.name.as_str()
+            }
+            ast::PropertyKey::StringLit(lit) => lit.value.as_str(),
+        },
+        _ => return false,
+    };
+    let call = match property
+        .parent
+        .as_deref()
+        .and_then(|object| object.parent.as_deref())
+        .map(|call| &call.node)
+    {
+        Some(AstNode::CallExpr(call)) => call,
+        _ => return false,
+    };
+    key == "key"
+        && matches!(&call.callee, ast::Expression::Member(member)
+            if matches!(&member.object, ast::Expression::Identifier(ident) if ident.name == "secrets")
+                && matches!(&member.property, ast::PropertyKey::Identifier(ident) if ident.name == "get"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::get_package;
+
+    #[test]
+    fn unknown_keys() {
+        let fluxscript = r#"import "influxdata/influxdb/secrets"
+
+token = secrets.get(key: "TOKEN")
+password = secrets.get(key: "PASWORD")
+"#;
+        let package = get_package(fluxscript);
+
+        let diagnostics = unknown_secret_keys(
+            &package,
+            Some(&["TOKEN".into(), "PASSWORD".into()]),
+        );
+
+        assert_eq!(
+            vec!["There is no secret with the key \"PASWORD\"."],
+            diagnostics
+                .iter()
+                .map(|(_, diagnostic)| diagnostic.message.as_str())
+                .collect::<Vec<&str>>()
+        );
+
+        // Nothing is known of keys until they are pushed.
+        assert!(unknown_secret_keys(&package, None).is_empty());
+    }
+}
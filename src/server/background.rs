@@ -0,0 +1,138 @@
+/// Work the server does in the background
+///
+/// Some work started by a notification, like analyzing the package of a document that
+/// was opened, takes long enough that the client would wait on it before its next
+/// messages are handled. Such work is spawned on the runtime of the host instead: the
+/// tokio runtime of the binary, or the event loop of the browser for wasm. Embedders
+/// without a runtime to spawn on, e.g. the tests, run it to completion right away.
+///
+/// The tasks still running are tracked, so that `shutdown` waits for them to finish.
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+/// Run a task in the background, or right away without a runtime to spawn it on.
+async fn spawn<F>(task: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    // The event loop of the browser is only there on wasm targets, tests of the
+    // wasm feature run natively.
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    wasm_bindgen_futures::spawn_local(task);
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    {
+        #[cfg(feature = "cmd")]
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(task);
+            return;
+        }
+        task.await;
+    }
+}
+
+/// Run a task in the background without tracking it, for tasks waiting on the client,
+/// e.g. for the answer to a request, which shutting down shouldn't wait for.
+pub(crate) async fn detach<F>(task: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    spawn(task).await
+}
+
+#[derive(Default)]
+struct Running {
+    count: usize,
+    /// The wakers of those waiting for no task to be running.
+    waiting: Vec<Waker>,
+}
+
+/// The tasks running in the background. Clones track the same tasks.
+#[derive(Clone, Default)]
+pub(crate) struct Tasks {
+    running: Arc<Mutex<Running>>,
+}
+
+impl Tasks {
+    /// Acquire the running tasks. Every update is a single change of the count or of
+    /// the waiting wakers, so poisoning is ignored.
+    fn running(&self) -> MutexGuard<'_, Running> {
+        self.running.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Record that a task started. It is done once the returned guard is dropped,
+    /// which it is even if the task panics.
+    fn start(&self) -> Started {
+        self.running().count += 1;
+        Started(self.clone())
+    }
+
+    /// Run a task in the background, tracked until it is done.
+    pub(crate) async fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let started = self.start();
+        spawn(async move {
+            task.await;
+            drop(started);
+        })
+        .await
+    }
+
+    /// Wait for no task to be running.
+    pub(crate) fn idle(&self) -> Idle {
+        Idle(self.clone())
+    }
+}
+
+struct Started(Tasks);
+
+impl Drop for Started {
+    fn drop(&mut self) {
+        let mut running = self.0.running();
+        running.count -= 1;
+        if running.count == 0 {
+            running.waiting.drain(..).for_each(Waker::wake);
+        }
+    }
+}
+
+/// A future ready once no task is running.
+pub(crate) struct Idle(Tasks);
+
+impl Future for Idle {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut running = self.0.running();
+        if running.count == 0 {
+            return Poll::Ready(());
+        }
+        running.waiting.push(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_once_tasks_are_done() {
+        let tasks = Tasks::default();
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut tasks.idle()).poll(&mut cx).is_ready());
+
+        let first = tasks.start();
+        let second = tasks.start();
+        let mut idle = tasks.idle();
+        assert!(Pin::new(&mut idle).poll(&mut cx).is_pending());
+        drop(first);
+        assert!(Pin::new(&mut idle).poll(&mut cx).is_pending());
+        drop(second);
+        assert!(Pin::new(&mut idle).poll(&mut cx).is_ready());
+    }
+}
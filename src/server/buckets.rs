@@ -0,0 +1,101 @@
+/// The commands finding and renaming a bucket
+///
+/// Buckets are named by the `bucket` argument of `from` and `to` calls, which are
+/// looked for in every document of the store.
+use std::collections::HashMap;
+
+use lspower::lsp;
+
+use super::types::LspError;
+use super::{escape_string_literal, protocol_ext, LspServer};
+
+impl LspServer {
+    /// The locations of the `from` and `to` calls naming a bucket, in every document
+    /// of the store.
+    pub(super) fn find_bucket_references(
+        &self,
+        bucket: &str,
+    ) -> Vec<lsp::Location> {
+        self.store
+            .get_urls()
+            .into_iter()
+            .filter_map(|url| {
+                let file = self.store.get_ast_file(&url).ok()?;
+                Some(
+                    crate::schema::bucket_references(&file, bucket)
+                        .into_iter()
+                        .map(move |range| {
+                            lsp::Location::new(url.clone(), range)
+                        }),
+                )
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// An edit renaming a bucket in the `from` and `to` calls of every document of the
+    /// store.
+    ///
+    /// Edits are annotated as needing confirmation, so that clients let the user review
+    /// each of them before the rename is applied.
+    pub(super) fn rename_bucket(
+        &self,
+        params: protocol_ext::RenameBucketParams,
+    ) -> Result<lsp::WorkspaceEdit, LspError> {
+        if params.new_name.is_empty() {
+            return Err(LspError::InvalidArguments(vec![
+                serde_json::Value::String(params.new_name),
+            ]));
+        }
+        let annotation = String::from("renameBucket");
+        let new_text = format!(
+            "\"{}\"",
+            escape_string_literal(&params.new_name)
+        );
+
+        let mut changes: Vec<lsp::TextDocumentEdit> = vec![];
+        for location in self.find_bucket_references(&params.bucket) {
+            let edit = lsp::OneOf::Right(lsp::AnnotatedTextEdit {
+                text_edit: lsp::TextEdit {
+                    range: location.range,
+                    new_text: new_text.clone(),
+                },
+                annotation_id: annotation.clone(),
+            });
+            match changes.last_mut() {
+                Some(change)
+                    if change.text_document.uri == location.uri =>
+                {
+                    change.edits.push(edit)
+                }
+                _ => changes.push(lsp::TextDocumentEdit {
+                    text_document:
+                        lsp::OptionalVersionedTextDocumentIdentifier {
+                            uri: location.uri,
+                            version: None,
+                        },
+                    edits: vec![edit],
+                }),
+            }
+        }
+
+        Ok(lsp::WorkspaceEdit {
+            changes: None,
+            document_changes: Some(lsp::DocumentChanges::Edits(changes)),
+            change_annotations: Some(HashMap::from([(
+                annotation,
+                lsp::ChangeAnnotation {
+                    label: format!(
+                        "Rename bucket \"{}\" to \"{}\"",
+                        params.bucket, params.new_name
+                    ),
+                    needs_confirmation: Some(true),
+                    description: Some(
+                        "Queries and tasks reading from or writing to the bucket"
+                            .into(),
+                    ),
+                },
+            )])),
+        })
+    }
+}
@@ -0,0 +1,142 @@
+/// The command setting an argument of a call to a literal value
+///
+/// Only literal values are set, so that the edit can't change the meaning of the rest
+/// of the query.
+use std::collections::HashMap;
+
+use flux::ast::{self, Expression as AstExpression};
+use lspower::lsp;
+
+use super::protocol_ext::SetCallArgumentParams;
+use super::types::LspError;
+use super::{call_at, is_literal, pipeline_at, LspServer};
+use crate::convert;
+
+impl LspServer {
+    /// Set an argument of a call to a literal value, replacing the value it has or
+    /// adding it after the other arguments.
+    pub(super) fn set_call_argument(
+        &self,
+        params: SetCallArgumentParams,
+    ) -> Result<lsp::WorkspaceEdit, LspError> {
+        let uri = &params.text_document.uri;
+        let file = self.store.get_ast_file(uri)?;
+
+        let value_file = flux::parser::parse_string(
+            "".into(),
+            &format!("x = {}", params.value),
+        );
+        let is_valid = match value_file.body.as_slice() {
+            [ast::Statement::Variable(assignment)] => {
+                is_literal(&assignment.init)
+            }
+            _ => false,
+        };
+        let is_identifier = params
+            .name
+            .chars()
+            .next()
+            .map_or(false, |c| c.is_alphabetic() || c == '_')
+            && params
+                .name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_');
+        if !is_valid || !is_identifier {
+            return Err(LspError::InvalidArguments(vec![
+                serde_json::json!({"name": params.name, "value": params.value}),
+            ]));
+        }
+
+        let call = match params.stage {
+            None => call_at(&file, params.position),
+            Some(stage) => pipeline_at(&file, params.position)
+                .and_then(|pipes| match stage {
+                    0 => match &pipes[0].argument {
+                        AstExpression::Call(call) => {
+                            Some(call.as_ref())
+                        }
+                        _ => None,
+                    },
+                    stage => {
+                        pipes.get(stage - 1).map(|pipe| &pipe.call)
+                    }
+                }),
+        }
+        .ok_or_else(|| {
+            LspError::InvalidArguments(vec![serde_json::json!({
+                "position": params.position,
+                "stage": params.stage,
+            })])
+        })?;
+
+        let object = match call.arguments.first() {
+            Some(AstExpression::Object(object)) => Some(object),
+            _ => None,
+        };
+        let existing = object.and_then(|object| {
+            object.properties.iter().find(|property| match &property
+                .key
+            {
+                ast::PropertyKey::Identifier(ident) => {
+                    ident.name == params.name
+                }
+                ast::PropertyKey::StringLit(lit) => {
+                    lit.value == params.name
+                }
+            })
+        });
+        let edit = match (
+            existing,
+            object.and_then(|o| o.properties.last()),
+        ) {
+            (Some(property), _) => match &property.value {
+                Some(value) => lsp::TextEdit {
+                    range: convert::location_to_range(
+                        &value.base().location,
+                    ),
+                    new_text: params.value,
+                },
+                // A shorthand property, e.g. `bucket` in `from(bucket)`.
+                None => lsp::TextEdit {
+                    range: convert::location_to_range(
+                        &property.base.location,
+                    ),
+                    new_text: format!(
+                        "{}: {}",
+                        params.name, params.value
+                    ),
+                },
+            },
+            (None, Some(last)) => {
+                let end =
+                    convert::position_to_lsp(&last.base.location.end);
+                lsp::TextEdit {
+                    range: lsp::Range { start: end, end },
+                    new_text: format!(
+                        ", {}: {}",
+                        params.name, params.value
+                    ),
+                }
+            }
+            (None, None) => {
+                // Right before the closing parenthesis.
+                let mut end =
+                    convert::position_to_lsp(&call.base.location.end);
+                end.character = end.character.saturating_sub(1);
+                lsp::TextEdit {
+                    range: lsp::Range { start: end, end },
+                    new_text: format!(
+                        "{}: {}",
+                        params.name, params.value
+                    ),
+                }
+            }
+        };
+
+        Ok(lsp::WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+            document_changes: None,
+            change_annotations: None,
+        })
+    }
+}
@@ -0,0 +1,521 @@
+/// JSON schemas of the params of commands, derived from their serde implementations
+///
+/// Clients found out what the params of a command are by trial and error, as serde
+/// stops at the first mismatch, and ignores fields it doesn't know of. The params of
+/// commands are checked against their schema first, so that every missing and unknown
+/// field is reported at once, and clients can fetch the schemas with the
+/// `getCommandSchemas` command.
+///
+/// Schemas are traced from the `Deserialize` implementation of the params, so they
+/// can't drift from what the server accepts: the params are deserialized from a
+/// tracer, which records the fields and types it is asked for and makes up a value of
+/// each. A field is required when the params can't be deserialized without it.
+use serde::de::{
+    self, value::Error, DeserializeOwned, DeserializeSeed,
+    Deserializer, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde_json::{json, Map, Value};
+
+/// The value of every traced string. Some params parse their strings, e.g. as urls,
+/// so it's a valid url as well.
+const TRACED_STRING: &str = "file:///";
+
+/// A field of a struct, by the name of the struct and of the field.
+type Field = (&'static str, &'static str);
+
+#[derive(Default)]
+struct Trace {
+    /// The fields of the structs traced.
+    fields: Vec<Field>,
+    /// The fields found to be required, written in the schemas of their structs.
+    required: Vec<Field>,
+    /// A field left out of its struct, to find out whether it is required.
+    omitted: Option<Field>,
+}
+
+/// Stands in for the JSON params of a command, writing the schema of the value
+/// deserialized from it to `schema`.
+struct Tracer<'a> {
+    schema: &'a mut Value,
+    trace: &'a mut Trace,
+}
+
+macro_rules! trace_primitives {
+    ($($method:ident => $typ:literal, $visit:ident($value:expr);)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(
+                self,
+                visitor: V,
+            ) -> Result<V::Value, Error> {
+                *self.schema = json!({ "type": $typ });
+                visitor.$visit($value)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> Deserializer<'de> for Tracer<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(
+        self,
+        _visitor: V,
+    ) -> Result<V::Value, Error> {
+        Err(de::Error::custom(
+            "params can't be traced without knowing their type",
+        ))
+    }
+
+    trace_primitives! {
+        deserialize_bool => "boolean", visit_bool(false);
+        deserialize_i8 => "integer", visit_i8(0);
+        deserialize_i16 => "integer", visit_i16(0);
+        deserialize_i32 => "integer", visit_i32(0);
+        deserialize_i64 => "integer", visit_i64(0);
+        deserialize_u8 => "integer", visit_u8(0);
+        deserialize_u16 => "integer", visit_u16(0);
+        deserialize_u32 => "integer", visit_u32(0);
+        deserialize_u64 => "integer", visit_u64(0);
+        deserialize_f32 => "number", visit_f32(0.0);
+        deserialize_f64 => "number", visit_f64(0.0);
+        deserialize_char => "string", visit_char('a');
+        deserialize_str => "string", visit_str(TRACED_STRING);
+        deserialize_string => "string", visit_str(TRACED_STRING);
+        deserialize_identifier => "string", visit_str(TRACED_STRING);
+        deserialize_bytes => "string", visit_bytes(TRACED_STRING.as_bytes());
+        deserialize_byte_buf => "string", visit_bytes(TRACED_STRING.as_bytes());
+        deserialize_unit => "null", visit_unit();
+        deserialize_ignored_any => "null", visit_unit();
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let mut items = Value::Null;
+        let value = visitor.visit_seq(TraceElements {
+            slots: vec![&mut items].into_iter(),
+            trace: &mut *self.trace,
+        })?;
+        *self.schema = json!({ "type": "array", "items": items });
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let mut items = vec![Value::Null; len];
+        let value = visitor.visit_seq(TraceElements {
+            slots: items.iter_mut().collect::<Vec<_>>().into_iter(),
+            trace: &mut *self.trace,
+        })?;
+        *self.schema = json!({
+            "type": "array",
+            "prefixItems": items,
+            "minItems": len,
+            "maxItems": len,
+        });
+        Ok(value)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        *self.schema = json!({ "type": "object" });
+        let fields: &'static [&'static str] = &[];
+        visitor.visit_map(TraceFields {
+            name: "",
+            fields: fields.iter(),
+            current: "",
+            properties: &mut Map::new(),
+            trace: self.trace,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.trace
+            .fields
+            .extend(fields.iter().map(|field| (name, *field)));
+        let mut properties = Map::new();
+        let value = visitor.visit_map(TraceFields {
+            name,
+            fields: fields.iter(),
+            current: "",
+            properties: &mut properties,
+            trace: &mut *self.trace,
+        })?;
+        let required: Vec<&str> = fields
+            .iter()
+            .copied()
+            .filter(|field| {
+                self.trace.required.contains(&(name, *field))
+            })
+            .collect();
+        *self.schema = json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+        });
+        Ok(value)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let variant = variants.first().copied().ok_or_else(|| {
+            de::Error::custom("enum without variants")
+        })?;
+        *self.schema = json!({ "enum": variants });
+        visitor.visit_enum(TraceVariant {
+            variant,
+            trace: self.trace,
+        })
+    }
+}
+
+/// The fields of a struct, leaving out the omitted field.
+struct TraceFields<'a> {
+    name: &'static str,
+    fields: std::slice::Iter<'static, &'static str>,
+    /// The field whose value is deserialized next.
+    current: &'static str,
+    properties: &'a mut Map<String, Value>,
+    trace: &'a mut Trace,
+}
+
+impl<'de, 'a> MapAccess<'de> for TraceFields<'a> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let (name, omitted) = (self.name, self.trace.omitted);
+        match self
+            .fields
+            .find(|field| omitted != Some((name, **field)))
+        {
+            Some(field) => {
+                self.current = *field;
+                seed.deserialize(
+                    IntoDeserializer::<Error>::into_deserializer(
+                        *field,
+                    ),
+                )
+                .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Error> {
+        let schema = self
+            .properties
+            .entry(self.current)
+            .or_insert(Value::Null);
+        seed.deserialize(Tracer {
+            schema,
+            trace: &mut *self.trace,
+        })
+    }
+}
+
+/// The elements of a sequence, one for each slot of their schemas.
+struct TraceElements<'a> {
+    slots: std::vec::IntoIter<&'a mut Value>,
+    trace: &'a mut Trace,
+}
+
+impl<'de, 'a> SeqAccess<'de> for TraceElements<'a> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        match self.slots.next() {
+            Some(schema) => seed
+                .deserialize(Tracer {
+                    schema,
+                    trace: &mut *self.trace,
+                })
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The first variant of an enum.
+struct TraceVariant<'a> {
+    variant: &'static str,
+    trace: &'a mut Trace,
+}
+
+impl<'de, 'a> EnumAccess<'de> for TraceVariant<'a> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), Error> {
+        let variant = seed.deserialize(
+            IntoDeserializer::<Error>::into_deserializer(
+                self.variant,
+            ),
+        )?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, 'a> VariantAccess<'de> for TraceVariant<'a> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, Error> {
+        seed.deserialize(Tracer {
+            schema: &mut Value::Null,
+            trace: self.trace,
+        })
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        Tracer {
+            schema: &mut Value::Null,
+            trace: self.trace,
+        }
+        .deserialize_tuple(len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        Tracer {
+            schema: &mut Value::Null,
+            trace: self.trace,
+        }
+        .deserialize_struct(self.variant, fields, visitor)
+    }
+}
+
+/// The JSON schema of params of type `T`.
+pub fn params_schema<T: DeserializeOwned>() -> Value {
+    let mut trace = Trace::default();
+    if let Err(err) = T::deserialize(Tracer {
+        schema: &mut Value::Null,
+        trace: &mut trace,
+    }) {
+        log::warn!("Could not trace the schema of params: {}", err);
+        return Value::Null;
+    }
+    trace.fields.sort_unstable();
+    trace.fields.dedup();
+
+    trace.required = trace
+        .fields
+        .iter()
+        .copied()
+        .filter(|field| {
+            T::deserialize(Tracer {
+                schema: &mut Value::Null,
+                trace: &mut Trace {
+                    omitted: Some(*field),
+                    ..Trace::default()
+                },
+            })
+            .is_err()
+        })
+        .collect();
+    let mut schema = Value::Null;
+    let _ = T::deserialize(Tracer {
+        schema: &mut schema,
+        trace: &mut trace,
+    });
+    schema
+}
+
+/// The required fields missing from params, and the fields of params that aren't in
+/// their schema, by their path, e.g. `textDocument.uri`.
+pub fn mismatched_fields(
+    schema: &Value,
+    params: &Value,
+) -> (Vec<String>, Vec<String>) {
+    let mut missing = vec![];
+    let mut unknown = vec![];
+    collect_mismatched_fields(
+        schema,
+        params,
+        "",
+        &mut missing,
+        &mut unknown,
+    );
+    missing.sort();
+    unknown.sort();
+    (missing, unknown)
+}
+
+fn collect_mismatched_fields(
+    schema: &Value,
+    params: &Value,
+    prefix: &str,
+    missing: &mut Vec<String>,
+    unknown: &mut Vec<String>,
+) {
+    let (properties, params) = match (
+        schema.get("properties").and_then(Value::as_object),
+        params.as_object(),
+    ) {
+        (Some(properties), Some(params)) => (properties, params),
+        _ => return,
+    };
+    missing.extend(
+        schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .filter(|field| !params.contains_key(*field))
+            .map(|field| format!("{}{}", prefix, field)),
+    );
+    for (field, value) in params {
+        match properties.get(field) {
+            Some(schema) => collect_mismatched_fields(
+                schema,
+                value,
+                &format!("{}{}.", prefix, field),
+                missing,
+                unknown,
+            ),
+            None => unknown.push(format!("{}{}", prefix, field)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    #[serde(rename_all = "camelCase")]
+    struct Params {
+        text_document: lspower::lsp::TextDocumentIdentifier,
+        stage: Option<usize>,
+        #[serde(default)]
+        names: Vec<String>,
+        direction: crate::server::protocol_ext::StageDirection,
+    }
+
+    #[test]
+    fn schema_of_params() {
+        assert_eq!(
+            json!({
+                "type": "object",
+                "properties": {
+                    "textDocument": {
+                        "type": "object",
+                        "properties": {"uri": {"type": "string"}},
+                        "required": ["uri"],
+                    },
+                    "stage": {"type": "integer"},
+                    "names": {"type": "array", "items": {"type": "string"}},
+                    "direction": {"enum": ["up", "down"]},
+                },
+                "required": ["textDocument", "direction"],
+            }),
+            params_schema::<Params>()
+        );
+        assert_eq!(json!({"type": "null"}), params_schema::<()>());
+    }
+
+    #[test]
+    fn mismatched_fields_of_params() {
+        let schema = params_schema::<Params>();
+
+        assert_eq!(
+            (
+                vec![
+                    "direction".to_string(),
+                    "textDocument.uri".into()
+                ],
+                vec![
+                    "stages".to_string(),
+                    "textDocument.version".into()
+                ]
+            ),
+            mismatched_fields(
+                &schema,
+                &json!({"textDocument": {"version": 1}, "stages": 1})
+            )
+        );
+        assert_eq!(
+            (vec![], vec![]),
+            mismatched_fields(
+                &schema,
+                &json!({"textDocument": {"uri": "file:///a.flux"}, "direction": "up"})
+            )
+        );
+    }
+}
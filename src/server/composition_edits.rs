@@ -0,0 +1,72 @@
+/// Applying the edits of composition commands in the client
+///
+/// The client may refuse an edit, in which case the composition is reverted so that
+/// it matches the contents the client still has.
+use lspower::{jsonrpc::Result as RpcResult, lsp};
+
+use super::protocol_ext::LspClientCommand;
+use super::types::LspError;
+use super::{send_client_command, LspServer};
+use crate::composition;
+
+impl LspServer {
+    /// Apply an edit of the composition of a document in the client, or return it
+    /// when the client applies it itself, as asked with `apply: false`.
+    ///
+    /// A client that fails or refuses to apply the edit still has the contents the
+    /// composition was changed from, so the composition is reverted to `previous`, or
+    /// dropped when there was none, and the client is told with an
+    /// `executeCommandFailed` message, the way other composition commands report their
+    /// failures.
+    pub(super) async fn apply_composition_edit(
+        &self,
+        uri: &lsp::Url,
+        edit: lsp::WorkspaceEdit,
+        previous: Option<composition::Composition>,
+        apply: bool,
+    ) -> RpcResult<Option<serde_json::Value>> {
+        let edit = crate::lsp::sorted_workspace_edit(edit);
+        if !apply {
+            return match serde_json::to_value(edit) {
+                Ok(value) => Ok(Some(value)),
+                Err(err) => {
+                    Err(LspError::InternalError(err.to_string())
+                        .into())
+                }
+            };
+        }
+        let client = match self.get_client() {
+            Some(client) => client,
+            None => return Ok(None),
+        };
+        let reason = match client.apply_edit(edit, None).await {
+            Ok(response) if response.applied => return Ok(None),
+            Ok(response) => response
+                .failure_reason
+                .unwrap_or_else(|| "the edit was rejected".into()),
+            Err(err) => err.to_string(),
+        };
+        log::warn!(
+            "The composition of {} was not updated: {}.",
+            uri,
+            reason
+        );
+        {
+            let mut state = self.write_state();
+            match previous {
+                Some(composition) => {
+                    state.set_composition(uri.clone(), composition)
+                }
+                None => state.drop_composition(uri),
+            }
+        }
+        let params = lsp::ShowMessageRequestParams {
+            typ: lsp::MessageType::ERROR,
+            message: LspClientCommand::ExecuteCommandFailed
+                .to_string(),
+            actions: None,
+        };
+        send_client_command(client, params).await;
+        Ok(None)
+    }
+}
@@ -0,0 +1,105 @@
+/// The edits importing packages into a file
+///
+/// Imports are inserted in order among the existing imports, and never inside the
+/// header comments at the top of a file.
+use flux::ast;
+use lspower::lsp;
+
+use crate::convert;
+
+/// The edit importing a package into a file.
+///
+/// The import is kept in order among the imports of the file, along with the comments
+/// above them. Without imports, it goes after the package clause, or else after the
+/// shebang and the header comments at the top of the file, which are separated from
+/// the code by a blank line. Comments right above the first statement document it,
+/// so the import goes above them. The package is imported as `alias` when given.
+pub(super) fn import_edit(
+    file: &ast::File,
+    source: &str,
+    path: &str,
+    alias: Option<&str>,
+) -> lsp::TextEdit {
+    let import = match alias {
+        Some(alias) => format!("import {} \"{}\"", alias, path),
+        None => format!("import \"{}\"", path),
+    };
+    let lines: Vec<&str> = source.lines().collect();
+    let is_comment = |line: usize| {
+        lines
+            .get(line)
+            .map_or(false, |text| text.trim_start().starts_with("//"))
+    };
+    let is_blank = |line: usize| {
+        lines.get(line).map_or(false, |text| text.trim().is_empty())
+    };
+    // The edit inserting the import at the start of a line, which may be past the
+    // end of a file without a final newline.
+    let insert_at = |line: usize| {
+        if line < lines.len()
+            || source.ends_with('\n')
+            || source.is_empty()
+        {
+            let position = lsp::Position::new(line as u32, 0);
+            lsp::TextEdit {
+                range: lsp::Range::new(position, position),
+                new_text: format!("{}\n", import),
+            }
+        } else {
+            let last = lines.len() - 1;
+            let position = lsp::Position::new(
+                last as u32,
+                lines[last].encode_utf16().count() as u32,
+            );
+            lsp::TextEdit {
+                range: lsp::Range::new(position, position),
+                new_text: format!("\n{}", import),
+            }
+        }
+    };
+    let line_after = |location: &ast::SourceLocation| {
+        convert::line_index(&location.end) + 1
+    };
+
+    if let Some(last) = file.imports.last() {
+        let sorted = file
+            .imports
+            .windows(2)
+            .all(|pair| pair[0].path.value <= pair[1].path.value);
+        let next = file
+            .imports
+            .iter()
+            .find(|import| import.path.value.as_str() > path);
+        return match next {
+            Some(next) if sorted => {
+                let mut line =
+                    convert::line_index(&next.base.location.start);
+                while line > 0 && is_comment(line - 1) {
+                    line -= 1;
+                }
+                insert_at(line)
+            }
+            _ => insert_at(line_after(&last.base.location)),
+        };
+    }
+    if let Some(package) = &file.package {
+        return insert_at(line_after(&package.base.location));
+    }
+
+    let start = match lines.first() {
+        Some(line) if line.starts_with("#!") => 1,
+        _ => 0,
+    };
+    let mut end = start;
+    while is_comment(end) {
+        end += 1;
+    }
+    if end > start && (end >= lines.len() || is_blank(end)) {
+        while is_blank(end) {
+            end += 1;
+        }
+        insert_at(end)
+    } else {
+        insert_at(start)
+    }
+}
@@ -1,19 +1,38 @@
-mod commands;
+mod background;
+mod buckets;
+mod call_arguments;
+mod command_schema;
+mod composition_edits;
+mod imports;
+mod observer;
+mod pipeline_stages;
+pub(crate) mod protocol_ext;
 mod store;
+mod symbol_index;
+mod test_runner;
 mod types;
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex, RwLock};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{
+    Arc, Mutex, PoisonError, RwLock, RwLockReadGuard,
+    RwLockWriteGuard,
+};
 
 use flux::ast::walk::Node as AstNode;
 use flux::ast::{self, Expression as AstExpression};
 use flux::semantic::nodes::{
-    ErrorKind as SemanticNodeErrorKind, Package as SemanticPackage,
+    ErrorKind as SemanticNodeErrorKind,
+    Expression as SemanticExpression, ObjectExpr,
+    Package as SemanticPackage,
 };
 use flux::semantic::sub::{Substitutable, Substituter};
 use flux::semantic::types::{
-    BoundTvar, BoundTvarKinds, BuiltinType, CollectionType, MonoType,
-    PolyType, Tvar,
+    BoundTvar, BoundTvarKinds, CollectionType, MonoType, PolyType,
+    Record, Tvar,
 };
 use flux::semantic::{walk, ErrorKind};
 use lspower::{
@@ -21,19 +40,36 @@ use lspower::{
 };
 use strum::IntoEnumIterator;
 
-use crate::{completion, composition, lang, visitors::semantic};
+use crate::{
+    completion, composition, convert, lang, snippets,
+    visitors::semantic,
+};
 
-use self::commands::{
-    ClientCommandNotification, CompositionInitializeParams,
-    LspClientCommand, LspMessageActionItem, LspServerCommand,
-    TagValueFilterParams, ValueFilterParams,
+pub use self::observer::DocumentObserver;
+pub use self::protocol_ext::DisabledCapabilities;
+use self::protocol_ext::{
+    AnalysisStatusNotification, AnalysisStatusParams,
+    InitializationOptions, InlineValueParams, InlineValueRequest,
+    InlineValueVariableLookup, LspClientCommand,
+    LspMessageActionItem, LspServerCommand, PipelineHeader,
+    PipelineHeadersParams, PipelineHeadersRequest,
+    ProtocolCapabilities, Schema, SecretKeys, ServerCommand,
+    UpdateSchemaNotification, UpdateSecretsNotification,
 };
+pub use self::store::{DocumentStore, MemoryStore};
 use self::types::LspError;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 type Diagnostic =
     fn(&SemanticPackage) -> Vec<(Option<String>, lsp::Diagnostic)>;
+/// A lint relating its diagnostics to other locations of the package, which it
+/// needs the urls of the package for.
+type RelatedDiagnostic = fn(
+    &SemanticPackage,
+    &[lsp::Url],
+)
+    -> Vec<(Option<String>, lsp::Diagnostic)>;
 
 /// Convert a flux::semantic::walk::Node to a lsp::Location
 /// https://microsoft.github.io/language-server-protocol/specification#location
@@ -43,7 +79,75 @@ fn node_to_location(
 ) -> lsp::Location {
     lsp::Location {
         uri,
-        range: node.loc().clone().into(),
+        range: convert::location_to_range(node.loc()),
+    }
+}
+
+/// Parse the params of a command, which are passed as its only argument.
+///
+/// The params are checked against the schema of their type first, so that every
+/// missing and unknown field is reported, rather than the first one serde runs into.
+fn command_params<C: ServerCommand>(
+    arguments: &[serde_json::Value],
+) -> Result<C::Params, LspError> {
+    let params = arguments.first().cloned().unwrap_or_default();
+    let (missing, unknown) = command_schema::mismatched_fields(
+        &C::COMMAND.params_schema(),
+        &params,
+    );
+    if !missing.is_empty() || !unknown.is_empty() {
+        return Err(LspError::InvalidCommandParams {
+            command: C::COMMAND.into(),
+            missing,
+            unknown,
+            reason: None,
+        });
+    }
+    serde_json::value::from_value(params).map_err(|err| {
+        LspError::InvalidCommandParams {
+            command: C::COMMAND.into(),
+            missing: vec![],
+            unknown: vec![],
+            reason: Some(err.to_string()),
+        }
+    })
+}
+
+/// Find the object literal an expression evaluates to, following variables and member
+/// access, e.g. `o.inner` in `o = {inner: {x: 1}}`.
+fn find_object_literal<'a>(
+    pkg: &'a SemanticPackage,
+    expr: &'a SemanticExpression,
+) -> Option<&'a ObjectExpr> {
+    match expr {
+        SemanticExpression::Object(object) => {
+            let object: &ObjectExpr = object;
+            Some(object)
+        }
+        SemanticExpression::Identifier(ident) => {
+            let visitor = crate::walk_semantic_package!(
+                semantic::DefinitionFinderVisitor::new(
+                    ident.name.clone()
+                ),
+                pkg
+            );
+            match visitor.node? {
+                walk::Node::VariableAssgn(assignment) => {
+                    find_object_literal(pkg, &assignment.init)
+                }
+                _ => None,
+            }
+        }
+        SemanticExpression::Member(member) => {
+            let property = find_object_literal(pkg, &member.object)?
+                .properties
+                .iter()
+                .find(|property| {
+                    property.key.name.as_str() == member.property
+                })?;
+            find_object_literal(pkg, &property.value)
+        }
+        _ => None,
     }
 }
 
@@ -104,16 +208,905 @@ fn find_references<'a>(
     }
 }
 
+/// The url of a file in the package of the document at `uri`.
+///
+/// The files of a package share a directory, so only the file name differs.
+fn package_file_url(uri: &lsp::Url, file: Option<&str>) -> lsp::Url {
+    file.and_then(|name| uri.join(name).ok())
+        .unwrap_or_else(|| uri.clone())
+}
+
+/// Find the references of the identifier at a position in every file of its package.
+///
+/// Definitions at the top level of a file are visible in the other files of the
+/// package, so their references are searched in all of them, except in the functions
+/// shadowing them. Identifiers defined by an enclosing function are only searched in
+/// that function, and others in their own file, like `find_references` does.
+fn find_package_references<'a>(
+    uri: &lsp::Url,
+    pkg: &'a SemanticPackage,
+    node: Option<flux::semantic::walk::Node<'a>>,
+    path: Vec<flux::semantic::walk::Node<'a>>,
+) -> Vec<lsp::Location> {
+    let name = match node {
+        Some(walk::Node::Identifier(ident)) => &ident.name,
+        Some(walk::Node::IdentifierExpr(ident)) => &ident.name,
+        _ => return vec![],
+    };
+    // The innermost function defining the name is where the identifier is defined.
+    let shadowing = path.iter().rev().find(|node| match node {
+        walk::Node::FunctionExpr(function) => {
+            semantic::defines_name(function, name)
+        }
+        _ => false,
+    });
+    if let Some(function) = shadowing {
+        let mut visitor =
+            semantic::IdentFinderVisitor::new(name.clone());
+        walk::walk(&mut visitor, *function);
+        return visitor
+            .identifiers
+            .iter()
+            .map(|node| node_to_location(node, uri.clone()))
+            .collect();
+    }
+    let is_top_level = pkg.files.iter().any(|file| {
+        file.body.iter().any(|statement| match statement {
+            flux::semantic::nodes::Statement::Variable(
+                assignment,
+            ) => &assignment.id.name == name,
+            flux::semantic::nodes::Statement::Builtin(builtin) => {
+                &builtin.id.name == name
+            }
+            _ => false,
+        })
+    });
+    if !is_top_level {
+        return find_references(uri, node, path);
+    }
+
+    let mut visitor =
+        semantic::IdentFinderVisitor::unshadowed(name.clone());
+    walk::walk(&mut visitor, walk::Node::Package(pkg));
+    visitor
+        .identifiers
+        .iter()
+        .map(|node| {
+            node_to_location(
+                node,
+                package_file_url(uri, node.loc().file.as_deref()),
+            )
+        })
+        .collect()
+}
+
+/// Find the accesses of the record property at `position`.
+///
+/// The property may be accessed as `r.name` or `r["name"]`. Records aren't tracked
+/// through the script, so every access of the same property on an identifier of the
+/// same name is a reference, e.g. `r._value` in each `filter` of a pipeline.
+fn find_property_references(
+    file: &ast::File,
+    position: lsp::Position,
+) -> Vec<&ast::MemberExpr> {
+    let mut visitor =
+        crate::visitors::ast::NodeFinderVisitor::new(position);
+    ast::walk::walk(&mut visitor, AstNode::File(file));
+    let node = match visitor.node {
+        Some(node) => node,
+        None => return vec![],
+    };
+    let member = match (
+        &node.node,
+        node.parent.as_deref().map(|parent| &parent.node),
+    ) {
+        (
+            AstNode::Identifier(_) | AstNode::StringLit(_),
+            Some(AstNode::MemberExpr(member)),
+        ) if member.property.base().location.start
+            == node.node.base().location.start =>
+        {
+            *member
+        }
+        _ => return vec![],
+    };
+    let object = match &member.object {
+        AstExpression::Identifier(ident) => ident.name.clone(),
+        _ => return vec![],
+    };
+
+    let mut visitor =
+        crate::visitors::ast::PropertyFinderVisitor::new(
+            object,
+            crate::visitors::ast::property_name(&member.property)
+                .into(),
+        );
+    ast::walk::walk(&mut visitor, AstNode::File(file));
+    visitor.members
+}
+
+const KEYWORDS: &[&str] = &[
+    "and", "builtin", "else", "empty", "exists", "if", "import",
+    "not", "option", "or", "package", "return", "testcase", "then",
+];
+
+/// Whether `name` can be used as an identifier, e.g. in `r.name`.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_alphanumeric() || c == '_')
+                && !KEYWORDS.contains(&name)
+        }
+        _ => false,
+    }
+}
+
+/// Escape a value to be written within the quotes of a flux string literal, where a
+/// `${` would otherwise start an interpolation.
+fn escape_string_literal(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${")
+}
+
+/// The edit renaming the property accessed by `member` to `name`.
+///
+/// A name that isn't a valid identifier can only be accessed with a string, so
+/// `r.name` becomes `r["new name"]` in that case.
+fn rename_property(
+    member: &ast::MemberExpr,
+    name: &str,
+) -> lsp::TextEdit {
+    let literal = format!("\"{}\"", escape_string_literal(name));
+    match &member.property {
+        ast::PropertyKey::StringLit(lit) => lsp::TextEdit {
+            range: convert::location_to_range(&lit.base.location),
+            new_text: literal,
+        },
+        ast::PropertyKey::Identifier(ident)
+            if is_identifier(name) =>
+        {
+            lsp::TextEdit {
+                range: convert::location_to_range(
+                    &ident.base.location,
+                ),
+                new_text: name.into(),
+            }
+        }
+        ast::PropertyKey::Identifier(ident) => lsp::TextEdit {
+            range: lsp::Range {
+                start: convert::position_to_lsp(
+                    &member.object.base().location.end,
+                ),
+                end: convert::position_to_lsp(
+                    &ident.base.location.end,
+                ),
+            },
+            new_text: format!("[{}]", literal),
+        },
+    }
+}
+
+/// Find the locations of the accesses of the record property at `position`.
+fn property_reference_locations(
+    uri: &lsp::Url,
+    file: &ast::File,
+    position: lsp::Position,
+) -> Vec<lsp::Location> {
+    find_property_references(file, position)
+        .iter()
+        .map(|member| lsp::Location {
+            uri: uri.clone(),
+            range: convert::location_to_range(
+                &member.property.base().location,
+            ),
+        })
+        .collect()
+}
+
+/// Find the stages calling the function called at `position`, within its pipeline.
+///
+/// Functions like `filter` are usually called by many pipelines in a script, but only
+/// the stages of the pipeline under the cursor are relevant. Returns `None` if the
+/// position isn't on the name of a function called by a pipeline stage.
+fn pipeline_call_sites(
+    file: &ast::File,
+    position: lsp::Position,
+) -> Option<Vec<lsp::Range>> {
+    let mut visitor =
+        crate::visitors::ast::NodeFinderVisitor::new(position);
+    ast::walk::walk(&mut visitor, AstNode::File(file));
+    let node = visitor.node?;
+    let name = match &node.node {
+        AstNode::Identifier(ident) => &ident.name,
+        _ => return None,
+    };
+    let call = node.parent.as_deref()?;
+    match &call.node {
+        AstNode::CallExpr(call)
+            if call.callee.base().location
+                == node.node.base().location => {}
+        _ => return None,
+    }
+
+    // Climb to the outermost pipe expression, which holds the whole pipeline.
+    let mut pipeline = None;
+    let mut parent = call.parent.as_deref();
+    while let Some(AstNode::PipeExpr(pipe)) =
+        parent.map(|node| &node.node)
+    {
+        pipeline = Some(*pipe);
+        parent = parent.and_then(|node| node.parent.as_deref());
+    }
+
+    let mut ranges = vec![];
+    let mut push_call_site = |call: &ast::CallExpr| {
+        if let AstExpression::Identifier(callee) = &call.callee {
+            if &callee.name == name {
+                ranges.push(convert::location_to_range(
+                    &callee.base.location,
+                ));
+            }
+        }
+    };
+    let mut pipe = pipeline?;
+    loop {
+        push_call_site(&pipe.call);
+        match &pipe.argument {
+            AstExpression::PipeExpr(argument) => {
+                pipe = argument.as_ref()
+            }
+            AstExpression::Call(head) => {
+                push_call_site(head);
+                break;
+            }
+            _ => break,
+        }
+    }
+    ranges.reverse();
+    Some(ranges)
+}
+
+/// The pipe expressions of the pipeline containing `position`, from the first stage
+/// piped into to the last. The head of the pipeline is the argument of the first.
+fn pipeline_at(
+    file: &ast::File,
+    position: lsp::Position,
+) -> Option<Vec<&ast::PipeExpr>> {
+    let mut visitor =
+        crate::visitors::ast::NodeFinderVisitor::new(position);
+    ast::walk::walk(&mut visitor, AstNode::File(file));
+    let node = visitor.node?;
+
+    // Climb to the outermost pipe expression, which holds the whole pipeline.
+    let mut pipeline = None;
+    let mut current = Some(&node);
+    while let Some(node) = current {
+        match node.node {
+            AstNode::PipeExpr(pipe) => pipeline = Some(pipe),
+            _ if pipeline.is_some() => break,
+            _ => (),
+        }
+        current = node.parent.as_deref();
+    }
+
+    let mut pipes = vec![pipeline?];
+    while let AstExpression::PipeExpr(argument) =
+        &pipes[pipes.len() - 1].argument
+    {
+        pipes.push(argument.as_ref());
+    }
+    pipes.reverse();
+    Some(pipes)
+}
+
+/// The innermost call containing `position`.
+fn call_at(
+    file: &ast::File,
+    position: lsp::Position,
+) -> Option<&ast::CallExpr> {
+    let mut visitor =
+        crate::visitors::ast::NodeFinderVisitor::new(position);
+    ast::walk::walk(&mut visitor, AstNode::File(file));
+    let node = visitor.node?;
+
+    let mut current = Some(&node);
+    while let Some(node) = current {
+        if let AstNode::CallExpr(call) = node.node {
+            return Some(call);
+        }
+        current = node.parent.as_deref();
+    }
+    None
+}
+
+/// Whether `expression` is a literal value, e.g. `5m`, `-1h`, `"cpu"` or `["a", "b"]`.
+fn is_literal(expression: &AstExpression) -> bool {
+    match expression {
+        AstExpression::Integer(_)
+        | AstExpression::Uint(_)
+        | AstExpression::Float(_)
+        | AstExpression::StringLit(_)
+        | AstExpression::Duration(_)
+        | AstExpression::DateTime(_)
+        | AstExpression::Boolean(_)
+        | AstExpression::Regexp(_) => true,
+        AstExpression::Identifier(ident) => {
+            ident.name == "true" || ident.name == "false"
+        }
+        AstExpression::Unary(unary) => is_literal(&unary.argument),
+        AstExpression::Array(array) => array
+            .elements
+            .iter()
+            .all(|element| is_literal(&element.expression)),
+        _ => false,
+    }
+}
+
+/// Code actions with the edits of each sorted with `sorted_workspace_edit`.
+fn sorted_actions(
+    actions: Vec<lsp::CodeActionOrCommand>,
+) -> Vec<lsp::CodeActionOrCommand> {
+    actions
+        .into_iter()
+        .map(|action| match action {
+            lsp::CodeActionOrCommand::CodeAction(mut action) => {
+                action.edit = action
+                    .edit
+                    .map(crate::lsp::sorted_workspace_edit);
+                action.into()
+            }
+            command => command,
+        })
+        .collect()
+}
+
+/// The names imports and package level variables of a file are referred to by, which
+/// a package imported into it can't be referred to by as well.
+fn taken_names(file: &ast::File) -> Vec<String> {
+    let imports =
+        file.imports.iter().map(|import| match &import.alias {
+            Some(alias) => alias.name.clone(),
+            None => import
+                .path
+                .value
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        });
+    let variables =
+        file.body.iter().filter_map(|statement| match statement {
+            ast::Statement::Variable(assignment) => {
+                Some(assignment.id.name.clone())
+            }
+            ast::Statement::Option(option) => {
+                match &option.assignment {
+                    ast::Assignment::Variable(assignment) => {
+                        Some(assignment.id.name.clone())
+                    }
+                    ast::Assignment::Member(_) => None,
+                }
+            }
+            _ => None,
+        });
+    imports.chain(variables).collect()
+}
+
+/// The alias and path of the import an identifier at the end of `path` refers to: the
+/// alias in the import itself, or the root of a member expression like `s.trim`.
+fn import_alias<'a>(
+    path: &[walk::Node<'a>],
+) -> Option<(&'a str, &'a str)> {
+    let name = match path {
+        [.., walk::Node::ImportDeclaration(_), walk::Node::Identifier(ident)] => {
+            ident.name.as_str()
+        }
+        [.., walk::Node::MemberExpr(member), walk::Node::IdentifierExpr(ident)]
+            if matches!(&member.object, SemanticExpression::Identifier(object) if std::ptr::eq(object, *ident)) =>
+        {
+            // Parameters of enclosing functions shadow the import.
+            let shadowed = path.iter().any(|node| match node {
+                walk::Node::FunctionExpr(func) => func
+                    .params
+                    .iter()
+                    .any(|param| param.key.name == ident.name),
+                _ => false,
+            });
+            if shadowed {
+                return None;
+            }
+            ident.name.as_str()
+        }
+        _ => return None,
+    };
+    let file = path.iter().find_map(|node| match node {
+        walk::Node::File(file) => Some(*file),
+        _ => None,
+    })?;
+    file.imports.iter().find_map(|import| match &import.alias {
+        Some(alias) if alias.name == name => {
+            Some((alias.name.as_str(), import.path.value.as_str()))
+        }
+        _ => None,
+    })
+}
+
+/// Find the constant value of the package level variable at the end of `path`, if any.
+///
+/// Both the definition of a variable and references to it outside of functions are
+/// considered. Inside of functions, the name may be shadowed by a parameter or a
+/// local variable, so no value is reported.
+fn constant_value(
+    pkg: &SemanticPackage,
+    path: &[walk::Node<'_>],
+) -> Option<semantic::ConstantValue> {
+    let name = match path {
+        [.., walk::Node::File(_), walk::Node::VariableAssgn(_), walk::Node::Identifier(ident)] => {
+            &ident.name
+        }
+        [.., walk::Node::IdentifierExpr(ident)]
+            if !path.iter().any(|node| {
+                matches!(node, walk::Node::FunctionExpr(_))
+            }) =>
+        {
+            &ident.name
+        }
+        _ => return None,
+    };
+
+    let visitor = crate::walk_semantic_package!(
+        semantic::ConstantEvaluatorVisitor::default(),
+        pkg
+    );
+    visitor.constants.get(name.as_str()).cloned()
+}
+
+/// The columns of the records flowing into a pipeline stage.
+struct PipeSchema {
+    columns: Vec<(String, MonoType)>,
+    /// Whether the records may have columns other than those listed.
+    open: bool,
+}
+
+impl PipeSchema {
+    /// Whether both schemas have the same columns, of the same types.
+    ///
+    /// Type variables are numbered anew by every analysis, so any two of them are
+    /// considered the same type.
+    fn same_columns(&self, other: &PipeSchema) -> bool {
+        let is_var = |typ: &MonoType| {
+            matches!(typ, MonoType::Var(_) | MonoType::BoundVar(_))
+        };
+        self.open == other.open
+            && self.columns.len() == other.columns.len()
+            && self.columns.iter().all(|(name, typ)| {
+                other.columns.iter().any(|(other_name, other_typ)| {
+                    name == other_name
+                        && ((is_var(typ) && is_var(other_typ))
+                            || typ.to_string()
+                                == other_typ.to_string())
+                })
+            })
+    }
+}
+
+/// Find the schema of the records flowing into the pipeline stage at `position`.
+///
+/// The position may either be on the `|>` operator itself, or on the name of the
+/// function called by the stage.
+fn pipe_input_schema(
+    ast_pkg: &ast::Package,
+    sem_pkg: &SemanticPackage,
+    position: lsp::Position,
+) -> Option<PipeSchema> {
+    let visitor = crate::walk_ast_package!(
+        crate::visitors::ast::NodeFinderVisitor::new(position),
+        ast_pkg
+    );
+    let node = visitor.node?;
+    let call = match &node.node {
+        AstNode::PipeExpr(pipe) => &pipe.call,
+        AstNode::Identifier(_) => {
+            // Climb from the function name up to the call, which must be the
+            // call of a pipe expression.
+            let mut parent = node.parent.as_deref()?;
+            while let AstNode::MemberExpr(_) = parent.node {
+                parent = parent.parent.as_deref()?;
+            }
+            match (
+                &parent.node,
+                parent.parent.as_deref().map(|node| &node.node),
+            ) {
+                (
+                    AstNode::CallExpr(call),
+                    Some(AstNode::PipeExpr(pipe)),
+                ) if std::ptr::eq(*call, &pipe.call) => &pipe.call,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    let visitor = crate::walk_semantic_package!(
+        semantic::PipedCallFinderVisitor::new(&call.base.location),
+        sem_pkg
+    );
+    stream_schema(visitor.call?.pipe.as_ref()?.type_of())
+}
+
+/// The columns of the records of a stream type.
+fn stream_schema(typ: MonoType) -> Option<PipeSchema> {
+    let mut row = match typ {
+        MonoType::Collection(collection)
+            if collection.collection == CollectionType::Stream =>
+        {
+            collection.arg.clone()
+        }
+        _ => return None,
+    };
+
+    let mut columns: Vec<(String, MonoType)> = vec![];
+    let open = loop {
+        match row {
+            MonoType::Record(record) => match record.as_ref() {
+                Record::Empty => break false,
+                Record::Extension { head, tail } => {
+                    let name = head.k.to_string();
+                    if !columns
+                        .iter()
+                        .any(|(column, _)| column == &name)
+                    {
+                        columns.push((name, head.v.clone()));
+                    }
+                    row = tail.clone();
+                }
+            },
+            _ => break true,
+        }
+    };
+    Some(PipeSchema { columns, open })
+}
+
+/// The argument of a call a column name is being completed for, along with the call.
+///
+/// Column names are completed in strings, and in arrays of them.
+fn completed_column_argument<'a>(
+    node: &crate::visitors::ast::NodeFinderNode<'a>,
+) -> Option<(&'a str, &'a ast::CallExpr)> {
+    if !matches!(
+        node.node,
+        AstNode::ArrayExpr(_) | AstNode::StringLit(_)
+    ) {
+        return None;
+    }
+    // Climb from a string through its array, and the item of the array it may be
+    // walked as, up to the argument.
+    let mut ancestor = node.parent.as_deref()?;
+    for _ in 0..2 {
+        if let AstNode::Property(_) = ancestor.node {
+            break;
+        }
+        ancestor = ancestor.parent.as_deref()?;
+    }
+    let argument = match ancestor.node {
+        AstNode::Property(property) => {
+            crate::visitors::ast::property_name(&property.key)
+        }
+        _ => return None,
+    };
+    match ancestor
+        .parent
+        .as_deref()
+        .and_then(|arguments| arguments.parent.as_deref())
+        .map(|call| &call.node)
+    {
+        Some(AstNode::CallExpr(call)) => Some((argument, *call)),
+        _ => None,
+    }
+}
+
+/// Stdlib functions taking column names, along with the name of the argument.
+const COLUMN_ARGUMENTS: &[(&str, &str)] = &[
+    ("distinct", "column"),
+    ("drop", "columns"),
+    ("duplicate", "column"),
+    ("fill", "column"),
+    ("group", "columns"),
+    ("keep", "columns"),
+    ("sort", "columns"),
+    ("unique", "column"),
+];
+
+/// The columns of the tables read from InfluxDB.
+const INFLUXDB_COLUMNS: &[&str] = &[
+    "_field",
+    "_measurement",
+    "_start",
+    "_stop",
+    "_time",
+    "_value",
+];
+
+/// The columns offered when completing a column name in an argument of a call.
+///
+/// The `on` columns of a join are completed with the columns of its inputs, and the
+/// columns of `pivot` with the columns flowing into it. So are the columns of functions
+/// like `group` and `keep`, along with the columns of InfluxDB and the tags of the
+/// schema when the columns flowing into them aren't all known.
+fn argument_columns(
+    sem_pkg: &SemanticPackage,
+    node: &crate::visitors::ast::NodeFinderNode,
+    schema: &Schema,
+) -> Option<Vec<String>> {
+    let (argument, call) = completed_column_argument(node)?;
+    let callee = |name: &str| match &call.callee {
+        AstExpression::Identifier(ident) => ident.name == name,
+        _ => false,
+    };
+    match argument {
+        "on" if callee("join")
+            || matches!(&call.callee, AstExpression::Member(member)
+                if matches!(&member.object, AstExpression::Identifier(ident) if ident.name == "join")) =>
+        {
+            join_columns(sem_pkg, call)
+        }
+        "rowKey" | "columnKey" | "valueColumn" if callee("pivot") => {
+            let visitor = crate::walk_semantic_package!(
+                semantic::PipedCallFinderVisitor::new(
+                    &call.base.location
+                ),
+                sem_pkg
+            );
+            let schema = stream_schema(
+                visitor.call?.pipe.as_ref()?.type_of(),
+            )?;
+            Some(
+                schema
+                    .columns
+                    .into_iter()
+                    .map(|(column, _)| column)
+                    .collect(),
+            )
+        }
+        argument
+            if COLUMN_ARGUMENTS.iter().any(|(function, key)| {
+                *key == argument && callee(function)
+            }) =>
+        {
+            let visitor = crate::walk_semantic_package!(
+                semantic::PipedCallFinderVisitor::new(
+                    &call.base.location
+                ),
+                sem_pkg
+            );
+            let piped = visitor
+                .call
+                .and_then(|call| call.pipe.as_ref())
+                .and_then(|pipe| stream_schema(pipe.type_of()));
+            let mut columns: Vec<String> = match &piped {
+                Some(piped) => piped
+                    .columns
+                    .iter()
+                    .map(|(column, _)| column.clone())
+                    .collect(),
+                None => vec![],
+            };
+            if piped.map_or(true, |piped| piped.open) {
+                columns.extend(
+                    INFLUXDB_COLUMNS
+                        .iter()
+                        .map(|column| column.to_string()),
+                );
+                columns.extend(
+                    crate::schema::known_names(
+                        schema,
+                        &[],
+                        crate::schema::NameKind::Tag,
+                    )
+                    .into_iter()
+                    .map(String::from),
+                );
+            }
+            // Columns already listed, other than the one being completed, aren't
+            // offered again.
+            let completed = match node.node {
+                AstNode::StringLit(lit) => Some(&lit.base.location),
+                _ => None,
+            };
+            let array = std::iter::successors(Some(node), |node| {
+                node.parent.as_deref()
+            })
+            .take(3)
+            .find_map(|node| match node.node {
+                AstNode::ArrayExpr(array) => Some(array),
+                _ => None,
+            });
+            if let Some(array) = array {
+                columns.retain(|column| {
+                    !array.elements.iter().any(|item| matches!(&item.expression, AstExpression::StringLit(lit) if &lit.value == column && Some(&lit.base.location) != completed))
+                });
+            }
+            columns.sort();
+            columns.dedup();
+            Some(columns)
+        }
+        _ => None,
+    }
+}
+
+/// The columns that may be joined on.
+///
+/// Columns are those of every input of the join, which are the `tables` of `join`, or
+/// the `left` and `right` arguments of joins taking them. Inputs whose records may have
+/// other columns don't rule any column out.
+fn join_columns(
+    sem_pkg: &SemanticPackage,
+    call: &ast::CallExpr,
+) -> Option<Vec<String>> {
+    let visitor = crate::walk_semantic_package!(
+        semantic::CallFinderVisitor::new(&call.base.location),
+        sem_pkg
+    );
+    let call = visitor.call?;
+    let inputs: Vec<MonoType> = call
+        .arguments
+        .iter()
+        .flat_map(|argument| {
+            match (argument.key.name.as_str(), &argument.value) {
+                ("tables", SemanticExpression::Object(tables)) => {
+                    tables
+                        .properties
+                        .iter()
+                        .map(|property| property.value.type_of())
+                        .collect()
+                }
+                ("left" | "right", value) => vec![value.type_of()],
+                _ => vec![],
+            }
+        })
+        .collect();
+    let schemas: Vec<PipeSchema> = inputs
+        .into_iter()
+        .map(stream_schema)
+        .collect::<Option<_>>()?;
+
+    let mut columns: Vec<String> = vec![];
+    for schema in &schemas {
+        for (column, _) in &schema.columns {
+            let joinable = schemas.iter().all(|other| {
+                other.open
+                    || other
+                        .columns
+                        .iter()
+                        .any(|(name, _)| name == column)
+            });
+            if joinable && !columns.contains(column) {
+                columns.push(column.clone());
+            }
+        }
+    }
+    Some(columns)
+}
+
+/// Counts the errors the parser attached to the nodes of a file.
 #[derive(Default)]
+struct ParseErrorVisitor {
+    errors: usize,
+}
+
+impl<'a> ast::walk::Visitor<'a> for ParseErrorVisitor {
+    fn visit(&mut self, node: AstNode<'a>) -> bool {
+        self.errors += node.base().errors.len();
+        true
+    }
+}
+
+/// The number of diagnostics published for a single file, unless configured otherwise
+/// with the `maxDiagnosticsPerFile` setting.
+const DEFAULT_MAX_DIAGNOSTICS_PER_FILE: usize = 500;
+
 struct LspServerState {
     buckets: Vec<String>,
     compositions: HashMap<lsp::Url, composition::Composition>,
+    published_diagnostics: HashMap<lsp::Url, Vec<lsp::Diagnostic>>,
+    /// The latest version of each open document.
+    document_versions: HashMap<lsp::Url, i32>,
+    /// The latest version of each document whose composition is waiting to be resolved.
+    pending_compositions: HashMap<lsp::Url, i32>,
+    /// The documents whose composition a background task is resolving.
+    resolving_compositions: HashSet<lsp::Url>,
+    /// The number of consecutive changes each composition couldn't be resolved for.
+    unresolved_compositions: HashMap<lsp::Url, usize>,
+    max_diagnostics_per_file: usize,
+    strict_analysis: bool,
+    /// Whether `http://` urls are reported, from the `warnInsecureHttp` setting.
+    warn_insecure_http: bool,
+    /// The InfluxDB scripts are deployed to, from the `targetVersion` setting.
+    target_version: Option<String>,
+    /// The platform scripts run on, from the `target` setting.
+    platform: Option<lang::Platform>,
+    /// The functions of internal libraries deprecated by the `deprecatedFunctions`
+    /// setting.
+    deprecated_functions: Vec<crate::deprecations::Deprecation>,
+    /// The params of parameterized queries by name, along with their types, from the
+    /// `queryParams` setting.
+    query_params: Option<BTreeMap<String, String>>,
+    /// Whether colors are provided, from the `documentColors` initialization option.
+    document_colors: bool,
+    /// The capabilities turned off by the embedder, and by the client with the
+    /// `disabledCapabilities` initialization option.
+    disabled_capabilities: DisabledCapabilities,
+    /// The codes of the opt-in lints enabled with the `optInLints` setting.
+    opt_in_lints: Vec<String>,
+    /// The severities of lints by code, from the `lintSeverities` setting. Lints
+    /// turned off have no severity.
+    lint_severities: HashMap<String, Option<lsp::DiagnosticSeverity>>,
+    /// How documents are formatted, from the `formatStagePerLine` and
+    /// `formatMaxLineWidth` settings.
+    format_style: crate::formatting::FormatStyle,
+    /// Whether documents are formatted before they are saved, from the `formatOnSave`
+    /// setting.
+    format_on_save: bool,
+    /// The schema pushed by the client with the `flux/updateSchema` notification.
+    schema: Schema,
+    /// The secret keys pushed by the client with the `flux/updateSecrets` notification,
+    /// if it did.
+    secret_keys: Option<Vec<String>>,
+    /// The symbols of the documents of the workspace, for `workspace/symbol`.
+    symbol_index: symbol_index::SymbolIndex,
+    /// The `flux` command line tool tests are run with, from the `fluxCommand` setting.
+    #[cfg(feature = "cmd")]
+    flux_command: String,
+    /// Whether the flux command was chosen by the embedder, in which case the
+    /// `fluxCommand` setting is ignored.
+    #[cfg(feature = "cmd")]
+    flux_command_fixed: bool,
+    /// The instance schema names are queried from, from the `influxdb` setting.
+    #[cfg(feature = "native-queries")]
+    influxdb: Option<Arc<crate::influxdb::SchemaQuerier>>,
+}
+
+impl Default for LspServerState {
+    fn default() -> Self {
+        Self {
+            buckets: Vec::new(),
+            compositions: HashMap::new(),
+            published_diagnostics: HashMap::new(),
+            document_versions: HashMap::new(),
+            pending_compositions: HashMap::new(),
+            resolving_compositions: HashSet::new(),
+            unresolved_compositions: HashMap::new(),
+            max_diagnostics_per_file:
+                DEFAULT_MAX_DIAGNOSTICS_PER_FILE,
+            strict_analysis: true,
+            warn_insecure_http: false,
+            target_version: None,
+            platform: None,
+            deprecated_functions: Vec::new(),
+            query_params: None,
+            document_colors: false,
+            disabled_capabilities: DisabledCapabilities::default(),
+            opt_in_lints: Vec::new(),
+            lint_severities: HashMap::new(),
+            format_style: crate::formatting::FormatStyle::default(),
+            format_on_save: false,
+            schema: Schema::default(),
+            secret_keys: None,
+            symbol_index: symbol_index::SymbolIndex::default(),
+            #[cfg(feature = "cmd")]
+            flux_command: "flux".into(),
+            #[cfg(feature = "cmd")]
+            flux_command_fixed: false,
+            #[cfg(feature = "native-queries")]
+            influxdb: None,
+        }
+    }
 }
 
 impl LspServerState {
-    // XXX: rockstar (21 Jun 2022) - This `allow` pragma is temporary, until we can add
-    // bucket completion, which is blocked on the completion refactor.
-    #[allow(dead_code)]
     pub fn buckets(&self) -> &Vec<String> {
         &self.buckets
     }
@@ -137,166 +1130,2368 @@ impl LspServerState {
         uri: lsp::Url,
         composition: composition::Composition,
     ) {
+        self.unresolved_compositions.remove(&uri);
         self.compositions.insert(uri, composition);
     }
 
     pub fn drop_composition(&mut self, uri: &lsp::Url) {
         self.compositions.remove(uri);
+        self.pending_compositions.remove(uri);
+        self.unresolved_compositions.remove(uri);
     }
-}
 
-pub struct LspServer {
-    client: Arc<Mutex<Option<Client>>>,
-    diagnostics: Vec<Diagnostic>,
-    store: store::Store,
-    state: Mutex<LspServerState>,
-    client_capabilities: RwLock<lsp::ClientCapabilities>,
-}
+    pub fn document_version(&self, uri: &lsp::Url) -> Option<i32> {
+        self.document_versions.get(uri).copied()
+    }
 
-impl LspServer {
-    pub fn new(client: Option<Client>) -> Self {
-        Self {
-            client: Arc::new(Mutex::new(client)),
-            diagnostics: vec![
-                super::diagnostics::contrib_lint,
-                super::diagnostics::experimental_lint,
-                super::diagnostics::no_influxdb_identifiers,
-                super::diagnostics::prefer_camel_case,
-            ],
-            store: store::Store::default(),
-            state: Mutex::new(LspServerState::default()),
-            client_capabilities: RwLock::new(
-                lsp::ClientCapabilities::default(),
-            ),
-        }
+    /// Record the version of a document.
+    ///
+    /// Changes may be handled out of order, so the highest version is kept.
+    pub fn set_document_version(
+        &mut self,
+        uri: &lsp::Url,
+        version: i32,
+    ) {
+        let latest = self
+            .document_versions
+            .entry(uri.clone())
+            .or_insert(version);
+        *latest = (*latest).max(version);
     }
 
-    // Get the client from out of its arc and mutex.
-    // Note the lspower::Client has a cheap clone method to make it easy
-    // to pass around many instances of the client.
-    //
-    // We leverage that here so we do not have to keep a lock or
-    // an extra reference to the client.
-    fn get_client(&self) -> Option<Client> {
-        match self.client.lock() {
-            Ok(client) => (*client).clone(),
-            Err(err) => {
-                log::error!("failed to get lock on client: {}", err);
-                None
-            }
-        }
+    pub fn drop_document_version(&mut self, uri: &lsp::Url) {
+        self.document_versions.remove(uri);
     }
 
-    fn get_document(&self, key: &lsp::Url) -> RpcResult<String> {
+    /// Record that a change of a document needs its composition resolved.
+    ///
+    /// Changes may be handled out of order, so the highest version is kept. Returns
+    /// whether a task needs to be started to resolve the composition, as none is
+    /// resolving it already.
+    pub fn queue_composition_resolution(
+        &mut self,
+        uri: &lsp::Url,
+        version: i32,
+    ) -> bool {
+        let latest = self
+            .pending_compositions
+            .entry(uri.clone())
+            .or_insert(version);
+        *latest = (*latest).max(version);
+        self.resolving_compositions.insert(uri.clone())
+    }
+
+    /// Take the changes queued for resolution off the queue, returning whether there
+    /// were any.
+    ///
+    /// Once there are none, the task resolving the composition is done, and the next
+    /// change starts another.
+    pub fn take_composition_resolution(
+        &mut self,
+        uri: &lsp::Url,
+    ) -> bool {
+        let queued = self.pending_compositions.remove(uri).is_some();
+        if !queued {
+            self.resolving_compositions.remove(uri);
+        }
+        queued
+    }
+
+    pub fn composition_resolved(&mut self, uri: &lsp::Url) {
+        self.unresolved_compositions.remove(uri);
+    }
+
+    /// Record a failure to resolve a composition, returning the number of
+    /// consecutive failures so far.
+    pub fn composition_unresolved(
+        &mut self,
+        uri: &lsp::Url,
+    ) -> usize {
+        let failures = self
+            .unresolved_compositions
+            .entry(uri.clone())
+            .or_insert(0);
+        *failures += 1;
+        *failures
+    }
+
+    /// Record the diagnostics about to be published for a url.
+    ///
+    /// Returns false if they are identical to the diagnostics last published for
+    /// that url, in which case there is no need to publish them again.
+    pub fn set_published_diagnostics(
+        &mut self,
+        uri: &lsp::Url,
+        diagnostics: &[lsp::Diagnostic],
+    ) -> bool {
+        match self.published_diagnostics.get(uri) {
+            Some(previous) if previous.as_slice() == diagnostics => {
+                false
+            }
+            _ => {
+                self.published_diagnostics
+                    .insert(uri.clone(), diagnostics.to_vec());
+                true
+            }
+        }
+    }
+
+    pub fn drop_published_diagnostics(&mut self, uri: &lsp::Url) {
+        self.published_diagnostics.remove(uri);
+    }
+
+    pub fn max_diagnostics_per_file(&self) -> usize {
+        self.max_diagnostics_per_file
+    }
+
+    pub fn set_max_diagnostics_per_file(&mut self, max: usize) {
+        self.max_diagnostics_per_file = max;
+    }
+
+    pub fn strict_analysis(&self) -> bool {
+        self.strict_analysis
+    }
+
+    pub fn set_strict_analysis(&mut self, strict: bool) {
+        self.strict_analysis = strict;
+    }
+
+    pub fn warn_insecure_http(&self) -> bool {
+        self.warn_insecure_http
+    }
+
+    pub fn set_warn_insecure_http(&mut self, warn: bool) {
+        self.warn_insecure_http = warn;
+    }
+
+    pub fn target_version(&self) -> Option<&str> {
+        self.target_version.as_deref()
+    }
+
+    pub fn set_target_version(&mut self, target: Option<String>) {
+        self.target_version = target;
+    }
+
+    pub fn platform(&self) -> Option<lang::Platform> {
+        self.platform
+    }
+
+    pub fn set_platform(&mut self, platform: Option<lang::Platform>) {
+        self.platform = platform;
+    }
+
+    pub fn deprecated_functions(
+        &self,
+    ) -> &Vec<crate::deprecations::Deprecation> {
+        &self.deprecated_functions
+    }
+
+    pub fn set_deprecated_functions(
+        &mut self,
+        deprecations: Vec<crate::deprecations::Deprecation>,
+    ) {
+        self.deprecated_functions = deprecations;
+    }
+
+    pub fn query_params(&self) -> Option<&BTreeMap<String, String>> {
+        self.query_params.as_ref()
+    }
+
+    pub fn set_query_params(
+        &mut self,
+        params: Option<BTreeMap<String, String>>,
+    ) {
+        self.query_params = params;
+    }
+
+    pub fn lint_severities(
+        &self,
+    ) -> &HashMap<String, Option<lsp::DiagnosticSeverity>> {
+        &self.lint_severities
+    }
+
+    pub fn set_lint_severities(
+        &mut self,
+        severities: HashMap<String, Option<lsp::DiagnosticSeverity>>,
+    ) {
+        self.lint_severities = severities;
+    }
+
+    pub fn format_style(&self) -> crate::formatting::FormatStyle {
+        self.format_style
+    }
+
+    pub fn set_format_style(
+        &mut self,
+        style: crate::formatting::FormatStyle,
+    ) {
+        self.format_style = style;
+    }
+
+    pub fn format_on_save(&self) -> bool {
+        self.format_on_save
+    }
+
+    pub fn set_format_on_save(&mut self, format_on_save: bool) {
+        self.format_on_save = format_on_save;
+    }
+
+    pub fn document_colors(&self) -> bool {
+        self.document_colors
+    }
+
+    pub fn set_document_colors(&mut self, enabled: bool) {
+        self.document_colors = enabled;
+    }
+
+    pub fn disabled_capabilities(&self) -> DisabledCapabilities {
+        self.disabled_capabilities
+    }
+
+    pub fn set_disabled_capabilities(
+        &mut self,
+        disabled: DisabledCapabilities,
+    ) {
+        self.disabled_capabilities = disabled;
+    }
+
+    pub fn opt_in_lints(&self) -> &Vec<String> {
+        &self.opt_in_lints
+    }
+
+    pub fn set_opt_in_lints(&mut self, lints: Vec<String>) {
+        self.opt_in_lints = lints;
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    pub fn set_schema(&mut self, schema: Schema) {
+        self.schema = schema;
+    }
+
+    pub fn secret_keys(&self) -> Option<&Vec<String>> {
+        self.secret_keys.as_ref()
+    }
+
+    pub fn set_secret_keys(&mut self, keys: Vec<String>) {
+        self.secret_keys = Some(keys);
+    }
+
+    #[cfg(feature = "cmd")]
+    pub fn flux_command(&self) -> &str {
+        &self.flux_command
+    }
+
+    #[cfg(feature = "cmd")]
+    pub fn set_flux_command(&mut self, command: String) {
+        self.flux_command = command;
+    }
+
+    #[cfg(feature = "cmd")]
+    pub fn flux_command_fixed(&self) -> bool {
+        self.flux_command_fixed
+    }
+
+    #[cfg(feature = "cmd")]
+    pub fn fix_flux_command(&mut self, command: String) {
+        self.flux_command = command;
+        self.flux_command_fixed = true;
+    }
+
+    #[cfg(feature = "native-queries")]
+    pub fn influxdb(
+        &self,
+    ) -> Option<Arc<crate::influxdb::SchemaQuerier>> {
+        self.influxdb.clone()
+    }
+
+    /// Query schema names from another instance, or stop querying on `None`.
+    ///
+    /// The querier, and with it the cache, is kept while the connection doesn't change.
+    #[cfg(feature = "native-queries")]
+    pub fn set_influxdb(
+        &mut self,
+        connection: Option<crate::influxdb::Connection>,
+    ) {
+        if self.influxdb.as_ref().map(|querier| querier.connection())
+            != connection.as_ref()
+        {
+            self.influxdb = connection.map(|connection| {
+                Arc::new(crate::influxdb::SchemaQuerier::new(
+                    connection,
+                ))
+            });
+        }
+    }
+}
+
+/// The number of consecutive changes without the composition statement, after which
+/// the composition is dropped.
+const MAX_UNRESOLVED_COMPOSITION_CHANGES: usize = 3;
+
+/// The notification telling the client about the updated range and state of a
+/// composition.
+fn composition_update_params(
+    composition: &composition::Composition,
+) -> Option<lsp::ShowMessageRequestParams> {
+    let position = composition.get_stmt_position()?;
+    let range_action_item = lsp::MessageActionItem {
+        title: LspMessageActionItem::CompositionRange.to_string(),
+        properties: HashMap::from([(
+            "range".to_string(),
+            lsp::MessageActionItemProperty::Object(
+                serde_json::to_value(HashMap::from([
+                    ("start", position.start),
+                    ("end", position.end),
+                ]))
+                .ok()?,
+            ),
+        )]),
+    };
+    let composition_state_action_item = lsp::MessageActionItem {
+        title: LspMessageActionItem::CompositionState.to_string(),
+        properties: HashMap::from([(
+            "state".to_string(),
+            lsp::MessageActionItemProperty::Object(
+                composition
+                    .get_serialized_composition_state()
+                    .ok()?,
+            ),
+        )]),
+    };
+    Some(lsp::ShowMessageRequestParams {
+        typ: lsp::MessageType::INFO,
+        message: LspClientCommand::UpdateComposition.to_string(),
+        actions: Some(vec![
+            range_action_item,
+            composition_state_action_item,
+        ]),
+    })
+}
+
+/// Tell the client about the composition of a document with a
+/// `window/showMessageRequest`, whose message is an `LspClientCommand`.
+///
+/// Clients may only answer once the user dismisses the message, so the answer is
+/// waited for in the background, rather than holding up the command or change.
+async fn send_client_command(
+    client: Client,
+    params: lsp::ShowMessageRequestParams,
+) {
+    background::detach(async move {
+        if let Err(err) = client
+            .show_message_request(
+                params.typ,
+                params.message,
+                params.actions,
+            )
+            .await
+        {
+            log::error!(
+                "Failed to send a composition message: {}",
+                err
+            );
+        }
+    })
+    .await
+}
+
+/// Limit a file's diagnostics to `max`, noting how many were left out.
+///
+/// Large generated scripts can produce thousands of errors, which overwhelms some
+/// clients. The note is an informational diagnostic at the start of the file.
+fn truncate_diagnostics(
+    diagnostics: &mut Vec<lsp::Diagnostic>,
+    max: usize,
+) {
+    if diagnostics.len() <= max {
+        return;
+    }
+    let total = diagnostics.len();
+    diagnostics.truncate(max);
+    diagnostics.push(lsp::Diagnostic {
+        range: lsp::Range::default(),
+        severity: Some(lsp::DiagnosticSeverity::INFORMATION),
+        source: Some("flux".to_string()),
+        message: format!(
+            "Showing {} of {} diagnostics for this file",
+            max, total
+        ),
+        ..lsp::Diagnostic::default()
+    });
+}
+
+/// The flux language server.
+///
+/// Clones share the documents and state of the server, which is how work is handed
+/// to the background.
+#[derive(Clone)]
+pub struct LspServer {
+    client: Arc<Mutex<Option<Client>>>,
+    diagnostics: Vec<Diagnostic>,
+    /// Lints that are only run when their code is listed in the `optInLints` setting.
+    opt_in_diagnostics: Vec<(&'static str, Diagnostic)>,
+    related_diagnostics: Vec<RelatedDiagnostic>,
+    store: store::Store,
+    state: Arc<RwLock<LspServerState>>,
+    client_capabilities: Arc<RwLock<lsp::ClientCapabilities>>,
+    shutdown_requested: Arc<AtomicBool>,
+    observers: Vec<Arc<dyn DocumentObserver>>,
+    /// Where the symbol index is persisted between sessions, if it is.
+    symbol_index_path: Option<PathBuf>,
+    /// The work running in the background, which shutting down waits for.
+    background: background::Tasks,
+}
+
+impl LspServer {
+    pub fn new(client: Option<Client>) -> Self {
+        Self {
+            client: Arc::new(Mutex::new(client)),
+            diagnostics: vec![
+                super::diagnostics::contrib_lint,
+                super::diagnostics::experimental_lint,
+                super::diagnostics::no_influxdb_identifiers,
+                super::diagnostics::prefer_camel_case,
+                super::diagnostics::prelude_shadowing,
+                super::diagnostics::import_collisions,
+                super::diagnostics::unnamed_results,
+                super::diagnostics::unused_parameters,
+                super::perf_lint::pushdown_blockers,
+                super::perf_lint::literal_regexes,
+                super::ranges::range_bounds,
+                super::ranges::missing_ranges,
+                super::diagnostics::same_bucket_writes,
+            ],
+            opt_in_diagnostics: vec![(
+                super::diagnostics::AGGREGATE_WINDOW_CREATE_EMPTY,
+                super::diagnostics::aggregate_window_create_empty
+                    as Diagnostic,
+            )],
+            related_diagnostics: vec![
+                super::perf_lint::filter_pushdown,
+                super::diagnostics::shadowed_variables,
+            ],
+            store: store::Store::default(),
+            state: Arc::new(RwLock::new(LspServerState::default())),
+            client_capabilities: Arc::new(RwLock::new(
+                lsp::ClientCapabilities::default(),
+            )),
+            shutdown_requested: Arc::new(AtomicBool::new(false)),
+            observers: vec![],
+            symbol_index_path: None,
+            background: background::Tasks::default(),
+        }
+    }
+
+    /// Keep documents in a store of the embedder's own, rather than in memory.
+    ///
+    /// Documents already stored by the server are left behind, so this is meant to
+    /// be called right after `new`.
+    pub fn with_store(
+        mut self,
+        documents: Arc<dyn DocumentStore>,
+    ) -> Self {
+        self.store = store::Store::new(documents);
+        self
+    }
+
+    /// Register an observer of the documents opened, changed and closed.
+    pub fn with_observer(
+        mut self,
+        observer: Arc<dyn DocumentObserver>,
+    ) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Turn capabilities off, for clients whose own implementation of them conflicts
+    /// with the server's. Clients may turn off more of them when they initialize.
+    pub fn with_disabled_capabilities(
+        self,
+        disabled: DisabledCapabilities,
+    ) -> Self {
+        self.write_state().set_disabled_capabilities(disabled);
+        self
+    }
+
+    /// Run tests with a `flux` command chosen by the embedder, e.g. with the
+    /// `--flux-command` flag of the binary, rather than by the `fluxCommand` setting.
+    ///
+    /// Settings come from the client, so a server any client can connect to must not
+    /// take the executable it runs from them.
+    #[cfg(feature = "cmd")]
+    pub fn with_flux_command(
+        self,
+        command: impl Into<String>,
+    ) -> Self {
+        self.write_state().fix_flux_command(command.into());
+        self
+    }
+
+    /// Persist the symbol index at a path between sessions, so that `workspace/symbol`
+    /// finds the symbols of documents indexed in earlier sessions right away, rather
+    /// than once each is opened again.
+    ///
+    /// The index persisted by an earlier session is loaded, without the documents that
+    /// changed on disk since. It is saved as documents are closed and on shutdown.
+    pub fn with_symbol_index(
+        mut self,
+        path: impl Into<PathBuf>,
+    ) -> Self {
+        let path = path.into();
+        match symbol_index::SymbolIndex::load(&path) {
+            Ok(index) => self.write_state().symbol_index = index,
+            Err(err) => log::warn!(
+                "Could not load the symbol index at {}: {}",
+                path.display(),
+                err
+            ),
+        }
+        self.symbol_index_path = Some(path);
+        self
+    }
+
+    /// Index the symbols of a document for `workspace/symbol`, unless its contents
+    /// are indexed already.
+    fn index_symbols(&self, uri: &lsp::Url) {
+        let contents = match self.store.get(uri) {
+            Ok(contents) => contents,
+            Err(err) => {
+                log::error!("{:?}", err);
+                return;
+            }
+        };
+        if self.read_state().symbol_index.is_current(uri, &contents) {
+            return;
+        }
+        let pkg = match self.store.get_semantic_package(uri) {
+            Ok(pkg) => pkg,
+            Err(err) => {
+                log::debug!("Could not index {}: {:?}", uri, err);
+                return;
+            }
+        };
+        let filename = uri
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(String::from);
+        let symbols = pkg
+            .files
+            .iter()
+            .find(|file| file.loc.file == filename)
+            .map(semantic::document_symbols)
+            .unwrap_or_default();
+        self.write_state()
+            .symbol_index
+            .update(uri, &contents, &symbols);
+    }
+
+    /// Persist the symbol index, if it is persisted.
+    fn save_symbol_index(&self) {
+        if let Some(path) = &self.symbol_index_path {
+            if let Err(err) =
+                self.read_state().symbol_index.save(path)
+            {
+                log::error!(
+                    "Could not save the symbol index at {}: {}",
+                    path.display(),
+                    err
+                );
+            }
+        }
+    }
+
+    /// Call the observers with the AST of a document, which is only parsed when
+    /// there are any.
+    fn notify_observers(
+        &self,
+        uri: &lsp::Url,
+        notify: impl Fn(&dyn DocumentObserver, &ast::File),
+    ) {
+        if self.observers.is_empty() {
+            return;
+        }
+        match self.store.get_ast_file(uri) {
+            Ok(file) => {
+                for observer in &self.observers {
+                    notify(observer.as_ref(), &file);
+                }
+            }
+            Err(err) => log::error!("{:?}", err),
+        }
+    }
+
+    /// Get a handle to the flag recording whether `shutdown` was requested.
+    ///
+    /// The protocol requires the process to exit with code 0 when `exit` follows
+    /// a `shutdown` request, and with code 1 otherwise. The server itself is owned
+    /// by the transport, so hosts keep this handle to decide on the exit code once
+    /// the transport has stopped.
+    pub fn shutdown_flag(&self) -> Arc<AtomicBool> {
+        self.shutdown_requested.clone()
+    }
+
+    // Get the client from out of its arc and mutex.
+    // Note the lspower::Client has a cheap clone method to make it easy
+    // to pass around many instances of the client.
+    //
+    // We leverage that here so we do not have to keep a lock or
+    // an extra reference to the client.
+    fn get_client(&self) -> Option<Client> {
+        match self.client.lock() {
+            Ok(client) => (*client).clone(),
+            Err(err) => {
+                log::error!("failed to get lock on client: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Reject a composition command made against an older version of its document
+    /// than the latest, as its edit would replace contents the client no longer has.
+    fn check_document_version(
+        &self,
+        uri: &lsp::Url,
+        version: Option<i32>,
+    ) -> Result<(), LspError> {
+        match (version, self.read_state().document_version(uri)) {
+            (Some(version), Some(latest)) if version < latest => {
+                Err(LspError::StaleDocumentVersion {
+                    uri: uri.clone(),
+                    version,
+                    latest,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Acquire the server state for reading.
+    ///
+    /// Any number of requests can read the state at once. A panic while the state was
+    /// locked doesn't leave it inconsistent (every update is a single insert or
+    /// removal), so poisoning is ignored rather than failing every later request.
+    fn read_state(&self) -> RwLockReadGuard<'_, LspServerState> {
+        self.state.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Acquire the server state for writing.
+    ///
+    /// See `read_state` regarding poisoning.
+    fn write_state(&self) -> RwLockWriteGuard<'_, LspServerState> {
+        self.state.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn get_document(&self, key: &lsp::Url) -> RpcResult<String> {
         match self.store.get(key) {
             Ok(contents) => Ok(contents),
             Err(err) => Err(err.into()),
         }
     }
 
-    /// Publish any diagnostics to the client
-    async fn publish_diagnostics(&self, key: &lsp::Url) {
-        // If we have a client back to the editor report any diagnostics found in the document
-        if let Some(client) = &self.get_client() {
-            for (key, diagnostics) in
-                self.compute_diagnostics(key).into_iter()
-            {
-                client
-                    .publish_diagnostics(key, diagnostics, None)
-                    .await;
+    /// Publish any diagnostics to the client
+    async fn publish_diagnostics(&self, key: &lsp::Url) {
+        // If we have a client back to the editor report any diagnostics found in the document
+        if let Some(client) = &self.get_client() {
+            for (key, diagnostics) in self
+                .changed_diagnostics(self.compute_diagnostics(key))
+                .into_iter()
+            {
+                client
+                    .publish_diagnostics(key, diagnostics, None)
+                    .await;
+            }
+        }
+    }
+
+    async fn send_analysis_status(
+        &self,
+        key: &lsp::Url,
+        ready: bool,
+    ) {
+        if let Some(client) = &self.get_client() {
+            client
+                .send_custom_notification::<AnalysisStatusNotification>(
+                    AnalysisStatusParams {
+                        text_document: lsp::TextDocumentIdentifier {
+                            uri: key.clone(),
+                        },
+                        ready,
+                    },
+                )
+                .await;
+        }
+    }
+
+    /// Resolve the composition of a document, for as long as changes of the document
+    /// are queued for resolution.
+    ///
+    /// This runs in the background, so that typing isn't held up by it. A single task
+    /// resolves the composition of a document at a time, and the changes made while it
+    /// does are resolved together once it is done, against the latest contents.
+    async fn resolve_compositions(&self, key: &lsp::Url) {
+        loop {
+            let queued =
+                self.write_state().take_composition_resolution(key);
+            if !queued {
+                return;
+            }
+            self.resolve_composition(key).await;
+        }
+    }
+
+    /// Resolve the composition of a document against its contents, and tell the
+    /// client about the result.
+    async fn resolve_composition(&self, key: &lsp::Url) {
+        let strict = self.read_state().strict_analysis();
+        // The package was analyzed to publish its diagnostics, so whether it has
+        // errors is known without analyzing it again. Nothing slow happens while
+        // the state is locked, so requests reading it aren't held up.
+        if self.store.has_package_errors(key, strict) {
+            return;
+        }
+        let file = self.store.get_ast_file(key);
+        let composition_state = {
+            let mut state = self.write_state();
+            let resolved =
+                match (state.get_mut_composition(key), file) {
+                    (Some(composition), Ok(file)) => Some(
+                        composition
+                            .resolve_with_ast(file)
+                            .map(|_| composition.clone()),
+                    ),
+                    _ => None,
+                };
+            match resolved {
+                Some(Ok(composition)) => {
+                    state.composition_resolved(key);
+                    Ok(composition)
+                }
+                // The composition statement may be mid-edit, so give it a few
+                // changes to reappear before dropping the composition.
+                Some(Err(composition::ResolveError::NotFound))
+                    if state.composition_unresolved(key)
+                        < MAX_UNRESOLVED_COMPOSITION_CHANGES =>
+                {
+                    return
+                }
+                Some(Err(_)) => {
+                    state.drop_composition(key);
+                    Err(LspClientCommand::CompositionDropped)
+                }
+                None => Err(LspClientCommand::CompositionNotFound),
+            }
+        };
+
+        if let Some(client) = self.get_client() {
+            let params = match composition_state {
+                Ok(composition) => {
+                    match composition_update_params(&composition) {
+                        Some(params) => params,
+                        None => {
+                            log::error!("Could not serialize the composition of {}", key);
+                            return;
+                        }
+                    }
+                }
+                Err(error_type) => lsp::ShowMessageRequestParams {
+                    typ: lsp::MessageType::INFO,
+                    message: error_type.to_string(),
+                    actions: None,
+                },
+            };
+            send_client_command(client, params).await;
+        } else {
+            log::error!("Failed to acquire client.");
+        };
+    }
+
+    /// Filter computed diagnostics down to the urls whose diagnostics changed.
+    ///
+    /// `compute_diagnostics` produces a list for every file in the package, even
+    /// when nothing about that file changed. Re-publishing identical (often empty)
+    /// lists causes some clients to flicker, so only the urls whose diagnostics
+    /// differ from what was last published are kept.
+    fn changed_diagnostics(
+        &self,
+        diagnostics: HashMap<lsp::Url, Vec<lsp::Diagnostic>>,
+    ) -> HashMap<lsp::Url, Vec<lsp::Diagnostic>> {
+        let mut state = self.write_state();
+        diagnostics
+            .into_iter()
+            .filter(|(url, diagnostics)| {
+                state.set_published_diagnostics(url, diagnostics)
+            })
+            .collect()
+    }
+
+    /// Compute diagnostics for a package
+    ///
+    /// This function will compute all diagnostics for the same package simultaneously. This
+    /// includes files that don't have any diagnostic messages (an empty list is generated),
+    /// as this is the way the server will signal that previous diagnostic messages have cleared.
+    pub(crate) fn compute_diagnostics(
+        &self,
+        key: &lsp::Url,
+    ) -> HashMap<lsp::Url, Vec<lsp::Diagnostic>> {
+        let mut diagnostic_map: HashMap<
+            lsp::Url,
+            Vec<lsp::Diagnostic>,
+        > = self
+            .store
+            .get_package_urls(key)
+            .into_iter()
+            .map(|url| (url, Vec::new()))
+            .collect();
+
+        let (
+            max,
+            strict,
+            opt_in_lints,
+            schema,
+            buckets,
+            secret_keys,
+            warn_insecure_http,
+            target_version,
+            platform,
+            deprecated_functions,
+            query_params,
+            lint_severities,
+        ) = {
+            let state = self.read_state();
+            (
+                state.max_diagnostics_per_file(),
+                state.strict_analysis(),
+                state.opt_in_lints().clone(),
+                state.schema().clone(),
+                state.buckets().clone(),
+                state.secret_keys().cloned(),
+                state.warn_insecure_http(),
+                state.target_version().map(String::from),
+                state.platform(),
+                state.deprecated_functions().clone(),
+                state.query_params().cloned(),
+                state.lint_severities().clone(),
+            )
+        };
+        // Diagnostics suppressed by `flux-lsp:ignore-next-line` comments, by filename.
+        let suppressed: HashMap<String, HashMap<u32, Vec<String>>> =
+            diagnostic_map
+                .keys()
+                .filter_map(|url| {
+                    let file = self.store.get_ast_file(url).ok()?;
+                    let source = self.store.get(url).ok()?;
+                    let lines = crate::diagnostics::suppressed_lines(
+                        &file, &source,
+                    );
+                    Some((file.name, lines))
+                })
+                .filter(|(_, lines)| !lines.is_empty())
+                .collect();
+        let is_suppressed =
+            |filename: &Option<String>, line: u32, code: &str| {
+                filename
+                    .as_ref()
+                    .and_then(|filename| suppressed.get(filename))
+                    .map_or(false, |lines| {
+                        crate::diagnostics::is_suppressed(
+                            lines, line, code,
+                        )
+                    })
+            };
+        let reported = |error: &&flux::semantic::Error| {
+            // `params` is defined once the params of queries are declared.
+            !(query_params.is_some()
+                && crate::query_params::is_params_error(error))
+                && !is_suppressed(
+                    &error.location.file,
+                    convert::location_to_range(&error.location)
+                        .start
+                        .line,
+                    crate::diagnostics::error_code(error),
+                )
+        };
+
+        let diagnostics: Vec<(Option<String>, lsp::Diagnostic)> =
+            match self.store.get_package_errors(key, strict).filter(
+                |errors| {
+                    errors
+                        .diagnostics
+                        .errors
+                        .iter()
+                        .any(|error| reported(&error))
+                },
+            ) {
+                None => {
+                    // If there are no semantic package errors, we can check for other
+                    // diagnostics.
+                    //
+                    // Note: it is important, if no diagnostics exist, that we return an empty
+                    // diagnostic list, as that will signal to the client that the diagnostics
+                    // have been cleared.
+                    if let Ok(package) =
+                        self.store.get_semantic_package(key)
+                    {
+                        // Lints relating to other locations need the urls of the package.
+                        let urls: Vec<lsp::Url> =
+                            diagnostic_map.keys().cloned().collect();
+                        let opted_in = self
+                            .opt_in_diagnostics
+                            .iter()
+                            .filter_map(|(code, func)| {
+                                opt_in_lints
+                                    .iter()
+                                    .any(|lint| lint == code)
+                                    .then_some(func)
+                            });
+                        let mut diagnostics: Vec<(
+                            Option<String>,
+                            lsp::Diagnostic,
+                        )> = self
+                            .diagnostics
+                            .iter()
+                            .chain(opted_in)
+                            .flat_map(|func| func(&package))
+                            .collect();
+                        diagnostics.extend(
+                            self.related_diagnostics.iter().flat_map(
+                                |func| func(&package, &urls),
+                            ),
+                        );
+                        diagnostics.extend(
+                            crate::schema::unknown_schema_names(
+                                &package, &schema, &buckets,
+                            ),
+                        );
+                        diagnostics.extend(
+                            crate::secrets::unknown_secret_keys(
+                                &package,
+                                secret_keys.as_deref(),
+                            ),
+                        );
+                        diagnostics.extend(
+                            crate::timezones::unknown_timezones(
+                                &package,
+                            ),
+                        );
+                        diagnostics.extend(
+                            crate::http::url_diagnostics(
+                                &package,
+                                warn_insecure_http,
+                            ),
+                        );
+                        diagnostics.extend(
+                            crate::versions::unavailable_functions(
+                                &package,
+                                target_version.as_deref(),
+                            ),
+                        );
+                        diagnostics.extend(
+                            crate::versions::unsupported_on_platform(
+                                &package, platform,
+                            ),
+                        );
+                        diagnostics.extend(
+                            crate::deprecations::deprecated_calls(
+                                &package,
+                                &deprecated_functions,
+                            )
+                            .into_iter()
+                            .map(
+                                |(file, diagnostic, _)| {
+                                    (file, diagnostic)
+                                },
+                            ),
+                        );
+                        diagnostics.extend(
+                            crate::query_params::undeclared_params(
+                                &package,
+                                query_params.as_ref(),
+                            ),
+                        );
+                        diagnostics
+                    } else {
+                        vec![]
+                    }
+                }
+                Some(errors) => {
+                    let ast_pkg =
+                        self.store.get_ast_package(key).ok();
+                    let sem_pkg =
+                        self.store.get_semantic_package(key).ok();
+                    let urls: Vec<lsp::Url> =
+                        diagnostic_map.keys().cloned().collect();
+                    errors
+                        .diagnostics
+                        .errors
+                        .iter()
+                        .filter(reported)
+                        .filter(|error| {
+                            // We will never have two files with the same name in a package, so we can
+                            // key off filename to determine whether the error exists in this file or
+                            // elsewhere in the package.
+                            if let Some(file) = &error.location.file {
+                                if let Some(segments) =
+                                    key.path_segments()
+                                {
+                                    if let Some(filename) =
+                                        segments.last()
+                                    {
+                                        return file == filename;
+                                    }
+                                }
+                            }
+                            false
+                        })
+                        .map(|e| {
+                            let message = e.error.to_string();
+                            let code_description =
+                                crate::diagnostics::error_description(&message);
+                            let (range, message) = sem_pkg
+                                .as_ref()
+                                .and_then(|pkg| {
+                                    crate::diagnostics::narrow_missing_label(
+                                        pkg,
+                                        &e.location,
+                                        &message,
+                                    )
+                                })
+                                .unwrap_or_else(|| {
+                                    (convert::location_to_range(&e.location), message)
+                                });
+                            (e.location.file.clone(), lsp::Diagnostic {
+                    range,
+                    severity: Some(lsp::DiagnosticSeverity::ERROR),
+                    code: Some(lsp::NumberOrString::String(crate::diagnostics::error_code(e).into())),
+                    code_description,
+                    source: Some("flux".to_string()),
+                    message,
+                    related_information: ast_pkg.as_ref().and_then(|pkg| {
+                        crate::diagnostics::related_definitions(pkg, &e.location, &urls)
+                    }),
+                    ..lsp::Diagnostic::default()
+                })
+                        })
+                        // Shadowed prelude functions and colliding imports are common
+                        // causes of confusing errors, so they are reported alongside them.
+                        .chain(sem_pkg.iter().flat_map(|pkg| {
+                            crate::diagnostics::prelude_shadowing(pkg)
+                                .into_iter()
+                                .chain(crate::diagnostics::import_collisions(pkg))
+                        }))
+                        .collect()
+                }
+            };
+        diagnostics
+            .into_iter()
+            .filter(|(filename, diagnostic)| {
+                let code = match &diagnostic.code {
+                    Some(lsp::NumberOrString::String(code)) => {
+                        code.as_str()
+                    }
+                    _ => "",
+                };
+                !is_suppressed(
+                    filename,
+                    diagnostic.range.start.line,
+                    code,
+                )
+            })
+            // Lints have the severity configured with `lintSeverities`, if any, and
+            // are dropped when turned off. Errors from flux keep theirs.
+            .filter_map(|(filename, mut diagnostic)| {
+                if let (
+                    None,
+                    Some(lsp::NumberOrString::String(code)),
+                ) = (&diagnostic.source, &diagnostic.code)
+                {
+                    if let Some(severity) = lint_severities.get(code)
+                    {
+                        diagnostic.severity = Some((*severity)?);
+                    }
+                }
+                Some((filename, diagnostic))
+            })
+            .for_each(|(filename, diagnostic)| {
+                // XXX: rockstar (5 June 2022) - Can this _ever_ be None? Is a blind unwrap safe?
+                if let Some(filename) = filename {
+                    diagnostic_map
+                        .iter_mut()
+                        .filter(|(url, _)| {
+                            url.to_string().ends_with(&filename)
+                        })
+                        .for_each(|(_, diagnostics)| {
+                            diagnostics.push(diagnostic.clone())
+                        });
+                }
+            });
+
+        diagnostic_map.values_mut().for_each(|diagnostics| {
+            truncate_diagnostics(diagnostics, max)
+        });
+
+        diagnostic_map
+    }
+
+    /// Quick fixes renaming assignments that shadow a prelude function.
+    ///
+    /// The assignment and all of its references are renamed, e.g. `sort` to `mySort`.
+    fn prelude_shadowing_actions(
+        &self,
+        params: &lsp::CodeActionParams,
+    ) -> Vec<lsp::CodeActionOrCommand> {
+        let shadowing: Vec<&lsp::Diagnostic> = params
+            .context
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| {
+                diagnostic.code
+                    == Some(lsp::NumberOrString::String(
+                        crate::diagnostics::PRELUDE_SHADOWING.into(),
+                    ))
+            })
+            .collect();
+        if shadowing.is_empty() {
+            return vec![];
+        }
+        let pkg = match self
+            .store
+            .get_semantic_package(&params.text_document.uri)
+        {
+            Ok(pkg) => pkg,
+            Err(err) => {
+                log::error!("{:?}", err);
+                return vec![];
+            }
+        };
+
+        shadowing
+            .into_iter()
+            .filter_map(|diagnostic| {
+                let visitor = crate::walk_semantic_package!(
+                    semantic::NodeFinderVisitor::new(
+                        diagnostic.range.start
+                    ),
+                    pkg
+                );
+                let name = match visitor.node? {
+                    walk::Node::Identifier(ident) => {
+                        ident.name.to_string()
+                    }
+                    _ => return None,
+                };
+                let new_name = format!(
+                    "my{}{}",
+                    name.get(..1)?.to_uppercase(),
+                    name.get(1..)?
+                );
+                let edits = find_references(
+                    &params.text_document.uri,
+                    visitor.node,
+                    visitor.path,
+                )
+                .into_iter()
+                .map(|location| lsp::TextEdit {
+                    range: location.range,
+                    new_text: new_name.clone(),
+                })
+                .collect();
+                Some(
+                    lsp::CodeAction {
+                        title: format!(
+                            "Rename `{}` to `{}`",
+                            name, new_name
+                        ),
+                        kind: Some(lsp::CodeActionKind::QUICKFIX),
+                        diagnostics: Some(vec![diagnostic.clone()]),
+                        edit: Some(lsp::WorkspaceEdit {
+                            changes: Some(HashMap::from([(
+                                params.text_document.uri.clone(),
+                                edits,
+                            )])),
+                            document_changes: None,
+                            change_annotations: None,
+                        }),
+                        command: None,
+                        is_preferred: Some(true),
+                        disabled: None,
+                        data: None,
+                    }
+                    .into(),
+                )
+            })
+            .collect()
+    }
+
+    /// Quick fixes renaming calls of deprecated functions to their replacement.
+    fn deprecated_function_actions(
+        &self,
+        params: &lsp::CodeActionParams,
+    ) -> Vec<lsp::CodeActionOrCommand> {
+        let deprecated: Vec<&lsp::Diagnostic> = params
+            .context
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| {
+                diagnostic.code
+                    == Some(lsp::NumberOrString::String(
+                        crate::deprecations::DEPRECATED_FUNCTION
+                            .into(),
+                    ))
+            })
+            .collect();
+        if deprecated.is_empty() {
+            return vec![];
+        }
+        let pkg = match self
+            .store
+            .get_semantic_package(&params.text_document.uri)
+        {
+            Ok(pkg) => pkg,
+            Err(err) => {
+                log::error!("{:?}", err);
+                return vec![];
+            }
+        };
+        let deprecations =
+            self.read_state().deprecated_functions().clone();
+        let calls = crate::deprecations::deprecated_calls(
+            &pkg,
+            &deprecations,
+        );
+
+        deprecated
+            .into_iter()
+            .filter_map(|diagnostic| {
+                let (_, _, deprecation) =
+                    calls.iter().find(|(_, call, _)| {
+                        call.range == diagnostic.range
+                    })?;
+                let replacement = deprecation.replacement.as_ref()?;
+                Some(
+                    lsp::CodeAction {
+                        title: format!(
+                            "Replace `{}` with `{}`",
+                            deprecation.name, replacement
+                        ),
+                        kind: Some(lsp::CodeActionKind::QUICKFIX),
+                        diagnostics: Some(vec![diagnostic.clone()]),
+                        edit: Some(lsp::WorkspaceEdit {
+                            changes: Some(HashMap::from([(
+                                params.text_document.uri.clone(),
+                                vec![lsp::TextEdit {
+                                    range: diagnostic.range,
+                                    new_text: replacement.clone(),
+                                }],
+                            )])),
+                            document_changes: None,
+                            change_annotations: None,
+                        }),
+                        command: None,
+                        is_preferred: Some(true),
+                        disabled: None,
+                        data: None,
+                    }
+                    .into(),
+                )
+            })
+            .collect()
+    }
+
+    /// Quick fixes adding an alias to (or renaming the alias of) colliding imports.
+    fn import_collision_actions(
+        &self,
+        params: &lsp::CodeActionParams,
+    ) -> Vec<lsp::CodeActionOrCommand> {
+        let collisions: Vec<&lsp::Diagnostic> = params
+            .context
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| {
+                diagnostic.code
+                    == Some(lsp::NumberOrString::String(
+                        crate::diagnostics::IMPORT_COLLISION.into(),
+                    ))
+            })
+            .collect();
+        if collisions.is_empty() {
+            return vec![];
+        }
+        let pkg = match self
+            .store
+            .get_semantic_package(&params.text_document.uri)
+        {
+            Ok(pkg) => pkg,
+            Err(err) => {
+                log::error!("{:?}", err);
+                return vec![];
+            }
+        };
+        let filename = params
+            .text_document
+            .uri
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(String::from);
+        let file = match pkg
+            .files
+            .iter()
+            .find(|file| file.loc.file == filename)
+        {
+            Some(file) => file,
+            None => return vec![],
+        };
+        let taken: Vec<String> = file
+            .imports
+            .iter()
+            .map(crate::diagnostics::import_name)
+            .collect();
+
+        collisions
+            .into_iter()
+            .filter_map(|diagnostic| {
+                let import = file.imports.iter().find(|import| {
+                    convert::location_to_range(&import.loc)
+                        == diagnostic.range
+                })?;
+                let alias = crate::diagnostics::suggest_import_alias(
+                    &import.path.value,
+                    &taken,
+                );
+                let edit = match &import.alias {
+                    Some(existing) => lsp::TextEdit {
+                        range: convert::location_to_range(
+                            &existing.loc,
+                        ),
+                        new_text: alias.clone(),
+                    },
+                    None => {
+                        let start = convert::position_to_lsp(
+                            &import.path.loc.start,
+                        );
+                        lsp::TextEdit {
+                            range: lsp::Range { start, end: start },
+                            new_text: format!("{} ", alias),
+                        }
+                    }
+                };
+                Some(
+                    lsp::CodeAction {
+                        title: format!(
+                            "Import `{}` as `{}`",
+                            import.path.value, alias
+                        ),
+                        kind: Some(lsp::CodeActionKind::QUICKFIX),
+                        diagnostics: Some(vec![diagnostic.clone()]),
+                        edit: Some(lsp::WorkspaceEdit {
+                            changes: Some(HashMap::from([(
+                                params.text_document.uri.clone(),
+                                vec![edit],
+                            )])),
+                            document_changes: None,
+                            change_annotations: None,
+                        }),
+                        command: None,
+                        is_preferred: Some(true),
+                        disabled: None,
+                        data: None,
+                    }
+                    .into(),
+                )
+            })
+            .collect()
+    }
+
+    /// Quick fixes removing duplicate imports, along with the lines they are on.
+    fn duplicate_import_actions(
+        &self,
+        params: &lsp::CodeActionParams,
+    ) -> Vec<lsp::CodeActionOrCommand> {
+        let duplicates: Vec<&lsp::Diagnostic> = params
+            .context
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| {
+                diagnostic.code
+                    == Some(lsp::NumberOrString::String(
+                        crate::diagnostics::DUPLICATE_IMPORT.into(),
+                    ))
+            })
+            .collect();
+        if duplicates.is_empty() {
+            return vec![];
+        }
+        let file = match self
+            .store
+            .get_ast_file(&params.text_document.uri)
+        {
+            Ok(file) => file,
+            Err(err) => {
+                log::error!("{:?}", err);
+                return vec![];
+            }
+        };
+
+        duplicates
+            .into_iter()
+            .filter_map(|diagnostic| {
+                let import = file.imports.iter().find(|import| {
+                    convert::location_to_range(&import.base.location)
+                        == diagnostic.range
+                })?;
+                let range = lsp::Range {
+                    start: lsp::Position {
+                        line: diagnostic.range.start.line,
+                        character: 0,
+                    },
+                    end: lsp::Position {
+                        line: diagnostic.range.end.line + 1,
+                        character: 0,
+                    },
+                };
+                Some(
+                    lsp::CodeAction {
+                        title: format!(
+                            "Remove the duplicate import of `{}`",
+                            import.path.value
+                        ),
+                        kind: Some(lsp::CodeActionKind::QUICKFIX),
+                        diagnostics: Some(vec![diagnostic.clone()]),
+                        edit: Some(lsp::WorkspaceEdit {
+                            changes: Some(HashMap::from([(
+                                params.text_document.uri.clone(),
+                                vec![lsp::TextEdit {
+                                    range,
+                                    new_text: "".into(),
+                                }],
+                            )])),
+                            document_changes: None,
+                            change_annotations: None,
+                        }),
+                        command: None,
+                        is_preferred: Some(true),
+                        disabled: None,
+                        data: None,
+                    }
+                    .into(),
+                )
+            })
+            .collect()
+    }
+
+    /// Quick fixes naming unnamed results with a `yield`.
+    fn unnamed_result_actions(
+        &self,
+        params: &lsp::CodeActionParams,
+    ) -> Vec<lsp::CodeActionOrCommand> {
+        let unnamed: Vec<&lsp::Diagnostic> = params
+            .context
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| {
+                diagnostic.code
+                    == Some(lsp::NumberOrString::String(
+                        crate::diagnostics::UNNAMED_RESULT.into(),
+                    ))
+            })
+            .collect();
+        if unnamed.is_empty() {
+            return vec![];
+        }
+        let pkg = match self
+            .store
+            .get_semantic_package(&params.text_document.uri)
+        {
+            Ok(pkg) => pkg,
+            Err(err) => {
+                log::error!("{:?}", err);
+                return vec![];
+            }
+        };
+        let filename = params
+            .text_document
+            .uri
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(String::from);
+        let names = crate::diagnostics::unnamed_result_names(&pkg);
+
+        unnamed
+            .into_iter()
+            .filter_map(|diagnostic| {
+                let (location, name) =
+                    names.iter().find(|(location, _)| {
+                        location.file == filename
+                            && convert::location_to_range(location)
+                                == diagnostic.range
+                    })?;
+                let end = convert::position_to_lsp(&location.end);
+                Some(
+                    lsp::CodeAction {
+                        title: format!("Name the result `{}`", name),
+                        kind: Some(lsp::CodeActionKind::QUICKFIX),
+                        diagnostics: Some(vec![diagnostic.clone()]),
+                        edit: Some(lsp::WorkspaceEdit {
+                            changes: Some(HashMap::from([(
+                                params.text_document.uri.clone(),
+                                vec![lsp::TextEdit {
+                                    range: lsp::Range { start: end, end },
+                                    new_text: format!(
+                                        "\n    |> yield(name: \"{}\")",
+                                        name
+                                    ),
+                                }],
+                            )])),
+                            document_changes: None,
+                            change_annotations: None,
+                        }),
+                        command: None,
+                        is_preferred: Some(true),
+                        disabled: None,
+                        data: None,
+                    }
+                    .into(),
+                )
+            })
+            .collect()
+    }
+
+    /// Quick fixes setting `createEmpty: false` on `aggregateWindow` calls.
+    /// Regular expressions only matching a literal are replaced with an equality.
+    fn literal_regex_actions(
+        &self,
+        params: &lsp::CodeActionParams,
+    ) -> Vec<lsp::CodeActionOrCommand> {
+        let flagged: Vec<&lsp::Diagnostic> = params
+            .context
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| {
+                diagnostic.code
+                    == Some(lsp::NumberOrString::String(
+                        crate::perf_lint::LITERAL_REGEX.into(),
+                    ))
+            })
+            .collect();
+        if flagged.is_empty() {
+            return vec![];
+        }
+        let pkg = match self
+            .store
+            .get_semantic_package(&params.text_document.uri)
+        {
+            Ok(pkg) => pkg,
+            Err(err) => {
+                log::error!("{:?}", err);
+                return vec![];
+            }
+        };
+        let filename = params
+            .text_document
+            .uri
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(String::from);
+        let comparisons =
+            crate::perf_lint::literal_regex_comparisons(&pkg);
+
+        flagged
+            .into_iter()
+            .filter_map(|diagnostic| {
+                let (binary, literal) =
+                    comparisons.iter().find(|(binary, _)| {
+                        binary.loc.file == filename
+                            && convert::location_to_range(&binary.loc)
+                                == diagnostic.range
+                    })?;
+                let equality =
+                    crate::perf_lint::equality_of_literal(literal);
+                Some(
+                    lsp::CodeAction {
+                        title: format!("Replace with `{}`", equality.trim_start()),
+                        kind: Some(lsp::CodeActionKind::QUICKFIX),
+                        diagnostics: Some(vec![diagnostic.clone()]),
+                        edit: Some(lsp::WorkspaceEdit {
+                            changes: Some(HashMap::from([(
+                                params.text_document.uri.clone(),
+                                vec![lsp::TextEdit {
+                                    range: lsp::Range {
+                                        start: convert::location_to_range(
+                                            binary.left.loc(),
+                                        )
+                                        .end,
+                                        end: convert::location_to_range(
+                                            binary.right.loc(),
+                                        )
+                                        .end,
+                                    },
+                                    new_text: equality,
+                                }],
+                            )])),
+                            document_changes: None,
+                            change_annotations: None,
+                        }),
+                        command: None,
+                        is_preferred: Some(true),
+                        disabled: None,
+                        data: None,
+                    }
+                    .into(),
+                )
+            })
+            .collect()
+    }
+
+    /// Unused parameters can be removed, along with the arguments passed as them, or
+    /// prefixed with `_` to mark them as unused on purpose.
+    fn unused_parameter_actions(
+        &self,
+        params: &lsp::CodeActionParams,
+    ) -> Vec<lsp::CodeActionOrCommand> {
+        let unused: Vec<&lsp::Diagnostic> = params
+            .context
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| {
+                diagnostic.code
+                    == Some(lsp::NumberOrString::String(
+                        crate::diagnostics::UNUSED_PARAMETER.into(),
+                    ))
+            })
+            .collect();
+        if unused.is_empty() {
+            return vec![];
+        }
+        let pkg = match self
+            .store
+            .get_semantic_package(&params.text_document.uri)
+        {
+            Ok(pkg) => pkg,
+            Err(err) => {
+                log::error!("{:?}", err);
+                return vec![];
+            }
+        };
+
+        // The range removing an item of a list along with its separator.
+        let removal = |items: &[lsp::Range], index: usize| {
+            if index > 0 {
+                lsp::Range {
+                    start: items[index - 1].end,
+                    end: items[index].end,
+                }
+            } else if let Some(next) = items.get(1) {
+                lsp::Range {
+                    start: items[0].start,
+                    end: next.start,
+                }
+            } else {
+                items[0]
+            }
+        };
+        let action = |title: String,
+                      diagnostic: &lsp::Diagnostic,
+                      edits: Vec<lsp::TextEdit>|
+         -> lsp::CodeActionOrCommand {
+            lsp::CodeAction {
+                title,
+                kind: Some(lsp::CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                edit: Some(lsp::WorkspaceEdit {
+                    changes: Some(HashMap::from([(
+                        params.text_document.uri.clone(),
+                        edits,
+                    )])),
+                    document_changes: None,
+                    change_annotations: None,
+                }),
+                command: None,
+                is_preferred: None,
+                disabled: None,
+                data: None,
+            }
+            .into()
+        };
+
+        unused
+            .into_iter()
+            .filter_map(|diagnostic| {
+                let visitor = crate::walk_semantic_package!(
+                    semantic::NodeFinderVisitor::new(
+                        diagnostic.range.start
+                    ),
+                    pkg
+                );
+                let name = match visitor.node? {
+                    walk::Node::Identifier(ident) => {
+                        ident.name.as_str()
+                    }
+                    _ => return None,
+                };
+                let mut ancestors = visitor.path.iter().rev();
+                let function =
+                    ancestors.find_map(|node| match node {
+                        walk::Node::FunctionExpr(function) => {
+                            Some(function)
+                        }
+                        _ => None,
+                    })?;
+                let assign =
+                    ancestors.find_map(|node| match node {
+                        walk::Node::VariableAssgn(assign) => {
+                            Some(assign)
+                        }
+                        _ => None,
+                    })?;
+                let index = function
+                    .params
+                    .iter()
+                    .position(|param| param.key.name == name)?;
+                let calls = crate::diagnostics::calls_of(
+                    &pkg,
+                    assign.id.name.as_str(),
+                );
+                // The arguments passed as the parameter, with the ranges of every
+                // argument of their call.
+                let arguments: Vec<(Vec<lsp::Range>, usize)> = calls
+                    .iter()
+                    .filter_map(|call| {
+                        let index = call.arguments.iter().position(
+                            |argument| argument.key.name == name,
+                        )?;
+                        Some((
+                            call.arguments
+                                .iter()
+                                .map(|argument| {
+                                    convert::location_to_range(
+                                        &argument.loc,
+                                    )
+                                })
+                                .collect(),
+                            index,
+                        ))
+                    })
+                    .collect();
+                let param_ranges: Vec<lsp::Range> = function
+                    .params
+                    .iter()
+                    .map(|param| {
+                        convert::location_to_range(&param.loc)
+                    })
+                    .collect();
+
+                let removals =
+                    std::iter::once(removal(&param_ranges, index))
+                        .chain(arguments.iter().map(
+                            |(ranges, index)| removal(ranges, *index),
+                        ))
+                        .map(|range| lsp::TextEdit {
+                            range,
+                            new_text: "".into(),
+                        })
+                        .collect();
+                let prefixes =
+                    std::iter::once(param_ranges[index].start)
+                        .chain(arguments.iter().map(
+                            |(ranges, index)| ranges[*index].start,
+                        ))
+                        .map(|start| lsp::TextEdit {
+                            range: lsp::Range { start, end: start },
+                            new_text: "_".into(),
+                        })
+                        .collect();
+                Some(vec![
+                    action(
+                        format!("Remove the parameter `{}`", name),
+                        diagnostic,
+                        removals,
+                    ),
+                    action(
+                        format!(
+                            "Rename the parameter `{}` to `_{}`",
+                            name, name
+                        ),
+                        diagnostic,
+                        prefixes,
+                    ),
+                ])
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// The refactoring toggling the pipelines of the statement at the cursor between
+    /// one line and one stage per line, leaving the rest of the document as it is.
+    fn pipeline_actions(
+        &self,
+        params: &lsp::CodeActionParams,
+    ) -> Vec<lsp::CodeActionOrCommand> {
+        if let Some(only) = &params.context.only {
+            if !only.iter().any(|kind| {
+                lsp::CodeActionKind::REFACTOR_REWRITE
+                    .as_str()
+                    .starts_with(kind.as_str())
+            }) {
+                return vec![];
+            }
+        }
+        let (file, source) = match (
+            self.store.get_ast_file(&params.text_document.uri),
+            self.store.get(&params.text_document.uri),
+        ) {
+            (Ok(file), Ok(source)) => (file, source),
+            _ => return vec![],
+        };
+        let statement = match file.body.iter().find(|statement| {
+            convert::location_contains(
+                &statement.base().location,
+                &params.range.start,
+            )
+        }) {
+            Some(statement) => statement,
+            None => return vec![],
+        };
+        let toggled = match crate::formatting::toggle_pipelines(
+            &source, statement,
+        ) {
+            Some(toggled) => toggled,
+            None => return vec![],
+        };
+
+        vec![lsp::CodeAction {
+            title: if toggled.broken {
+                "Put each stage of the pipeline on its own line"
+            } else {
+                "Join the stages of the pipeline onto one line"
+            }
+            .into(),
+            kind: Some(lsp::CodeActionKind::REFACTOR_REWRITE),
+            diagnostics: None,
+            edit: Some(lsp::WorkspaceEdit {
+                changes: Some(HashMap::from([(
+                    params.text_document.uri.clone(),
+                    vec![lsp::TextEdit {
+                        range: convert::location_to_range(
+                            &statement.base().location,
+                        ),
+                        new_text: toggled.new_text,
+                    }],
+                )])),
+                document_changes: None,
+                change_annotations: None,
+            }),
+            command: None,
+            is_preferred: None,
+            disabled: None,
+            data: None,
+        }
+        .into()]
+    }
+
+    fn aggregate_window_actions(
+        &self,
+        params: &lsp::CodeActionParams,
+    ) -> Vec<lsp::CodeActionOrCommand> {
+        let flagged: Vec<&lsp::Diagnostic> = params
+            .context
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| {
+                diagnostic.code
+                    == Some(lsp::NumberOrString::String(
+                        crate::diagnostics::AGGREGATE_WINDOW_CREATE_EMPTY
+                            .into(),
+                    ))
+            })
+            .collect();
+        if flagged.is_empty() {
+            return vec![];
+        }
+        let pkg = match self
+            .store
+            .get_semantic_package(&params.text_document.uri)
+        {
+            Ok(pkg) => pkg,
+            Err(err) => {
+                log::error!("{:?}", err);
+                return vec![];
+            }
+        };
+        let filename = params
+            .text_document
+            .uri
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(String::from);
+        let calls = crate::diagnostics::aggregate_window_calls(&pkg);
+
+        flagged
+            .into_iter()
+            .filter_map(|diagnostic| {
+                let call = calls.iter().find(|call| {
+                    call.loc.file == filename
+                        && convert::location_to_range(&call.loc)
+                            == diagnostic.range
+                })?;
+                let end = convert::position_to_lsp(
+                    &call.arguments.last()?.loc.end,
+                );
+                Some(
+                    lsp::CodeAction {
+                        title: "Set `createEmpty: false`".into(),
+                        kind: Some(lsp::CodeActionKind::QUICKFIX),
+                        diagnostics: Some(vec![diagnostic.clone()]),
+                        edit: Some(lsp::WorkspaceEdit {
+                            changes: Some(HashMap::from([(
+                                params.text_document.uri.clone(),
+                                vec![lsp::TextEdit {
+                                    range: lsp::Range {
+                                        start: end,
+                                        end,
+                                    },
+                                    new_text: ", createEmpty: false"
+                                        .into(),
+                                }],
+                            )])),
+                            document_changes: None,
+                            change_annotations: None,
+                        }),
+                        command: None,
+                        is_preferred: Some(true),
+                        disabled: None,
+                        data: None,
+                    }
+                    .into(),
+                )
+            })
+            .collect()
+    }
+
+    /// Whether the client can show document symbols as a hierarchy.
+    fn supports_hierarchical_symbols(&self) -> bool {
+        match self.client_capabilities.read() {
+            Ok(client_capabilities) => client_capabilities
+                .text_document
+                .as_ref()
+                .and_then(|text_document| {
+                    text_document.document_symbol.as_ref()
+                })
+                .and_then(|document_symbol| {
+                    document_symbol
+                        .hierarchical_document_symbol_support
+                })
+                .unwrap_or(false),
+            Err(err) => {
+                log::error!("{}", err);
+                false
+            }
+        }
+    }
+
+    /// Whether the client can render markdown in hovers.
+    fn supports_markdown_hover(&self) -> bool {
+        match self.client_capabilities.read() {
+            Ok(client_capabilities) => client_capabilities
+                .text_document
+                .as_ref()
+                .and_then(|text_document| {
+                    text_document.hover.as_ref()
+                })
+                .and_then(|hover| hover.content_format.as_ref())
+                .map_or(false, |formats| {
+                    formats.contains(&lsp::MarkupKind::Markdown)
+                }),
+            Err(err) => {
+                log::error!("{}", err);
+                false
+            }
+        }
+    }
+
+    /// The variables holding values in the visible range of a document, up to where
+    /// the debugger stopped, for it to show their values inline.
+    fn inline_values(
+        &self,
+        params: InlineValueParams,
+    ) -> Result<Vec<InlineValueVariableLookup>, LspError> {
+        let uri = &params.text_document.uri;
+        let pkg = self.store.get_semantic_package(uri)?;
+        let file = uri
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(String::from);
+        let end =
+            params.range.end.min(params.context.stopped_location.end);
+
+        let visitor = crate::walk_semantic_package!(
+            semantic::VariableFinderVisitor::new(file),
+            pkg
+        );
+        let mut values: Vec<InlineValueVariableLookup> = visitor
+            .variables
+            .into_iter()
+            .map(|(name, loc)| InlineValueVariableLookup {
+                range: convert::location_to_range(loc),
+                variable_name: Some(name.to_string()),
+                case_sensitive_lookup: true,
+            })
+            .filter(|value| {
+                params.range.start <= value.range.start
+                    && value.range.end <= end
+            })
+            .collect();
+        values.sort_by_key(|value| value.range.start);
+        Ok(values)
+    }
+
+    /// What the server knows of a document, for the `debugDocumentState` command.
+    fn document_state(
+        &self,
+        uri: &lsp::Url,
+    ) -> Result<protocol_ext::DocumentState, LspError> {
+        let contents = self.store.get(uri)?;
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+
+        let mut parse_errors = ParseErrorVisitor::default();
+        ast::walk::walk(
+            &mut parse_errors,
+            AstNode::File(&self.store.get_ast_file(uri)?),
+        );
+        // Checking for errors analyzes the package, so whether it was cached is
+        // looked up first.
+        let analysis_cached = self.store.is_analyzed(uri);
+        let strict = self.read_state().strict_analysis();
+        let semantic_errors = self
+            .store
+            .get_package_errors(uri, strict)
+            .map_or(0, |errors| errors.diagnostics.errors.len());
+
+        let state = self.read_state();
+        let mut package_files = self.store.get_package_urls(uri);
+        package_files.sort();
+        Ok(protocol_ext::DocumentState {
+            contents_hash: format!("{:016x}", hasher.finish()),
+            version: state.document_version(uri),
+            parse_errors: parse_errors.errors,
+            semantic_errors,
+            package_files,
+            analysis_cached,
+            composition: state.compositions.get(uri).and_then(
+                |composition| {
+                    composition
+                        .get_serialized_composition_state()
+                        .ok()
+                },
+            ),
+            last_analysis_millis: self
+                .store
+                .last_analysis_duration(uri),
+        })
+    }
+
+    /// Schema names of a kind queried from the instance of the `influxdb` setting,
+    /// for the buckets and measurements the package refers to.
+    #[cfg(feature = "native-queries")]
+    async fn query_names(
+        &self,
+        kind: crate::schema::NameKind,
+        pkg: &SemanticPackage,
+    ) -> Vec<String> {
+        let querier = match self.read_state().influxdb() {
+            Some(querier) => querier,
+            None => return vec![],
+        };
+        let referenced = crate::schema::referenced_names(pkg);
+        let of_kind = |of: crate::schema::NameKind| -> Vec<String> {
+            referenced
+                .iter()
+                .filter(|(kind, _)| *kind == of)
+                .map(|(_, name)| name.clone())
+                .collect()
+        };
+        querier
+            .names(
+                kind,
+                &of_kind(crate::schema::NameKind::Bucket),
+                &of_kind(crate::schema::NameKind::Measurement),
+            )
+            .await
+    }
+
+    /// Render the type of a hovered node and/or the schema flowing into a pipeline stage.
+    fn hover_contents(
+        &self,
+        typ: Option<String>,
+        schema: Option<PipeSchema>,
+        pushdown: Option<Vec<(&str, bool)>>,
+        names: Vec<(&str, Vec<String>)>,
+        tables: Vec<crate::annotated_csv::TableSchema>,
+        task_field: Option<crate::tasks::TaskField>,
+    ) -> lsp::HoverContents {
+        let markdown = self.supports_markdown_hover();
+
+        let mut sections: Vec<String> = vec![];
+        if let Some(typ) = typ {
+            sections.push(if markdown {
+                format!("```flux\n{}\n```", typ)
+            } else {
+                typ
+            });
+        }
+        if let Some(field) = task_field {
+            let status = match &field.invalid {
+                Some(reason) => format!("Invalid: {}.", reason),
+                None => "Valid.".to_string(),
+            };
+            sections.push(if markdown {
+                format!(
+                    "**Task option `{}`**\n\n{}\n\n*{}*",
+                    field.name, field.meaning, status
+                )
+            } else {
+                format!(
+                    "Task option {}:\n{}\n{}",
+                    field.name, field.meaning, status
+                )
+            });
+        }
+        if let Some(schema) = schema {
+            let mut columns: Vec<String> = schema
+                .columns
+                .iter()
+                .map(|(name, typ)| {
+                    if markdown {
+                        format!("- `{}`: `{}`", name, typ)
+                    } else {
+                        format!("  {}: {}", name, typ)
+                    }
+                })
+                .collect();
+            if schema.open {
+                columns.push(if markdown {
+                    "- *possibly more columns*".into()
+                } else {
+                    "  ...".into()
+                });
+            }
+            sections.push(if markdown {
+                format!("**Input columns**\n\n{}", columns.join("\n"))
+            } else {
+                format!("Input columns:\n{}", columns.join("\n"))
+            });
+        }
+        if let Some(stages) = pushdown {
+            let runs_in = |pushed: bool| {
+                if pushed {
+                    "storage"
+                } else {
+                    "memory"
+                }
+            };
+            sections.push(if markdown {
+                let rows: Vec<String> = stages
+                    .iter()
+                    .map(|(name, pushed)| {
+                        format!("| `{}` | {} |", name, runs_in(*pushed))
+                    })
+                    .collect();
+                format!(
+                    "**Estimated pushdown**\n\n| Stage | Runs in |\n| --- | --- |\n{}",
+                    rows.join("\n")
+                )
+            } else {
+                let rows: Vec<String> = stages
+                    .iter()
+                    .map(|(name, pushed)| {
+                        format!("  {}: {}", name, runs_in(*pushed))
+                    })
+                    .collect();
+                format!("Estimated pushdown:\n{}", rows.join("\n"))
+            });
+        }
+        for (title, names) in names {
+            if names.is_empty() {
+                continue;
+            }
+            sections.push(if markdown {
+                let rows: Vec<String> = names
+                    .iter()
+                    .map(|name| format!("- `{}`", name))
+                    .collect();
+                format!("**{}**\n\n{}", title, rows.join("\n"))
+            } else {
+                let rows: Vec<String> = names
+                    .iter()
+                    .map(|name| format!("  {}", name))
+                    .collect();
+                format!("{}:\n{}", title, rows.join("\n"))
+            });
+        }
+        let numbered = tables.len() > 1;
+        for (i, table) in tables.iter().enumerate() {
+            let title = if numbered {
+                format!("CSV table {}", i + 1)
+            } else {
+                "CSV table".into()
+            };
+            let rows = match table.rows {
+                1 => "1 row".to_string(),
+                rows => format!("{} rows", rows),
+            };
+            sections.push(if markdown {
+                let columns: Vec<String> = table
+                    .columns
+                    .iter()
+                    .map(|column| {
+                        format!(
+                            "| `{}` | `{}` | {} |",
+                            column.name,
+                            column.typ,
+                            if column.group { "yes" } else { "" }
+                        )
+                    })
+                    .collect();
+                format!(
+                    "**{}** ({})\n\n| Column | Type | Group key |\n| --- | --- | --- |\n{}",
+                    title,
+                    rows,
+                    columns.join("\n")
+                )
+            } else {
+                let columns: Vec<String> = table
+                    .columns
+                    .iter()
+                    .map(|column| {
+                        format!(
+                            "  {}: {}{}",
+                            column.name,
+                            column.typ,
+                            if column.group { " (group key)" } else { "" }
+                        )
+                    })
+                    .collect();
+                format!("{} ({}):\n{}", title, rows, columns.join("\n"))
+            });
+        }
+
+        if markdown {
+            lsp::HoverContents::Markup(lsp::MarkupContent {
+                kind: lsp::MarkupKind::Markdown,
+                value: sections.join("\n\n"),
+            })
+        } else {
+            lsp::HoverContents::Scalar(lsp::MarkedString::String(
+                sections.join("\n\n"),
+            ))
+        }
+    }
+
+    /// The hover of an import alias, describing the package it refers to.
+    fn import_alias_contents(
+        &self,
+        alias: &str,
+        package: &lang::Package,
+    ) -> lsp::HoverContents {
+        let functions = match package.functions().len() {
+            1 => "1 function".to_string(),
+            count => format!("{} functions", count),
+        };
+        if self.supports_markdown_hover() {
+            let mut sections = vec![
+                format!(
+                    "```flux\nimport {} \"{}\"\n```",
+                    alias, package.path
+                ),
+                format!(
+                    "alias for `{}` \u{2014} contains {}",
+                    package.path, functions
+                ),
+            ];
+            #[cfg(feature = "docs")]
+            sections.push(format!(
+                "[View documentation]({})",
+                completion::package_docs_url(&package.path)
+            ));
+            lsp::HoverContents::Markup(lsp::MarkupContent {
+                kind: lsp::MarkupKind::Markdown,
+                value: sections.join("\n\n"),
+            })
+        } else {
+            lsp::HoverContents::Scalar(lsp::MarkedString::String(
+                format!(
+                    "alias for {} \u{2014} contains {}",
+                    package.path, functions
+                ),
+            ))
+        }
+    }
+
+    /// Whether the client can render markdown in completion item documentation.
+    fn supports_markdown_completion(&self) -> bool {
+        match self.client_capabilities.read() {
+            Ok(client_capabilities) => client_capabilities
+                .text_document
+                .as_ref()
+                .and_then(|text_document| {
+                    text_document.completion.as_ref()
+                })
+                .and_then(|completion| {
+                    completion.completion_item.as_ref()
+                })
+                .and_then(|item| item.documentation_format.as_ref())
+                .map_or(false, |formats| {
+                    formats.contains(&lsp::MarkupKind::Markdown)
+                }),
+            Err(err) => {
+                log::error!("{}", err);
+                false
+            }
+        }
+    }
+
+    /// Whether the client can show completion items as deprecated with a tag.
+    fn supports_deprecated_completion_tag(&self) -> bool {
+        match self.client_capabilities.read() {
+            Ok(client_capabilities) => client_capabilities
+                .text_document
+                .as_ref()
+                .and_then(|text_document| {
+                    text_document.completion.as_ref()
+                })
+                .and_then(|completion| {
+                    completion.completion_item.as_ref()
+                })
+                .and_then(|item| item.tag_support.as_ref())
+                .map_or(false, |tags| {
+                    tags.value_set
+                        .contains(&lsp::CompletionItemTag::DEPRECATED)
+                }),
+            Err(err) => {
+                log::error!("{}", err);
+                false
             }
         }
     }
 
-    /// Compute diagnostics for a package
-    ///
-    /// This function will compute all diagnostics for the same package simultaneously. This
-    /// includes files that don't have any diagnostic messages (an empty list is generated),
-    /// as this is the way the server will signal that previous diagnostic messages have cleared.
-    fn compute_diagnostics(
+    /// Complete the parameters of the call the cursor is within the arguments of, in
+    /// sources too broken mid-edit for the call to be found at the cursor.
+    fn complete_recovered_call(
         &self,
-        key: &lsp::Url,
-    ) -> HashMap<lsp::Url, Vec<lsp::Diagnostic>> {
-        let mut diagnostic_map: HashMap<
-            lsp::Url,
-            Vec<lsp::Diagnostic>,
-        > = self
+        params: &lsp::CompletionParams,
+        sem_pkg: &flux::semantic::nodes::Package,
+    ) -> Option<Vec<lsp::CompletionItem>> {
+        let source = self
             .store
-            .get_package_urls(key)
-            .into_iter()
-            .map(|url| (url, Vec::new()))
-            .collect();
-
-        let diagnostics: Vec<(Option<String>, lsp::Diagnostic)> =
-            match self.store.get_package_errors(key) {
-                None => {
-                    // If there are no semantic package errors, we can check for other
-                    // diagnostics.
-                    //
-                    // Note: it is important, if no diagnostics exist, that we return an empty
-                    // diagnostic list, as that will signal to the client that the diagnostics
-                    // have been cleared.
-                    if let Ok(package) =
-                        self.store.get_semantic_package(key)
-                    {
-                        self
-                        .diagnostics
-                        .iter()
-                        .flat_map(|func| func(&package))
-                        .collect::<Vec<(Option<String>, lsp::Diagnostic)>>()
-                    } else {
-                        vec![]
-                    }
-                }
-                Some(errors) => {
-                    errors
-                        .diagnostics
-                        .errors
-                        .iter()
-                        .filter(|error| {
-                            // We will never have two files with the same name in a package, so we can
-                            // key off filename to determine whether the error exists in this file or
-                            // elsewhere in the package.
-                            if let Some(file) = &error.location.file {
-                                if let Some(segments) =
-                                    key.path_segments()
-                                {
-                                    if let Some(filename) =
-                                        segments.last()
-                                    {
-                                        return file == filename;
-                                    }
-                                }
-                            }
-                            false
-                        })
-                        .map(|e| {
-                            (e.location.file.clone(), lsp::Diagnostic {
-                    range: e.location.clone().into(),
-                    severity: Some(lsp::DiagnosticSeverity::ERROR),
-                    source: Some("flux".to_string()),
-                    message: e.error.to_string(),
-                    ..lsp::Diagnostic::default()
-                })
-                        })
-                        .collect()
-                }
-            };
-        diagnostics.into_iter().for_each(|(filename, diagnostic)| {
-            // XXX: rockstar (5 June 2022) - Can this _ever_ be None? Is a blind unwrap safe?
-            if let Some(filename) = filename {
-                diagnostic_map
-                    .iter_mut()
-                    .filter(|(url, _)| {
-                        url.to_string().ends_with(&filename)
-                    })
-                    .for_each(|(_, diagnostics)| {
-                        diagnostics.push(diagnostic.clone())
-                    });
-            }
-        });
-
-        diagnostic_map
+            .get(&params.text_document_position.text_document.uri)
+            .ok()?;
+        let call = completion::recover_call(
+            &source,
+            params.text_document_position.position,
+        )?;
+        Some(completion::complete_call_expr(params, sem_pkg, &call))
     }
 
     fn complete_member_expression(
@@ -309,54 +3504,72 @@ impl LspServer {
                 // XXX: rockstar (6 Jul 2022) - This is the last holdout from the previous
                 // completion code. There is a bit of indirection/cruft here that can be cleaned
                 // up when recursive support for member expressions is implemented.
-                let mut list: Vec<Box<dyn completion::Completable>> =
-                    vec![];
-                if let Some(import) = completion::get_imports(sem_pkg)
-                    .iter()
-                    .find(|x| x.name == identifier.name)
-                {
-                    for package in lang::STDLIB.packages() {
-                        if package.path == import.path {
-                            completion::walk_package(
-                                &package.path,
-                                &mut list,
-                                &package.exports.typ().expr,
-                            );
-                        }
-                    }
-                } else {
-                    for package in lang::STDLIB.packages() {
-                        if package.name == identifier.name {
-                            completion::walk_package(
-                                &package.path,
-                                &mut list,
-                                &package.exports.typ().expr,
-                            );
-                        }
+                let imports = completion::get_imports(sem_pkg);
+                let package_items = match completion::resolve_package(
+                    &imports,
+                    &identifier.name,
+                ) {
+                    Some(package) => {
+                        completion::package_items(&package)
                     }
-                }
+                    None => Arc::from(vec![]),
+                };
 
                 let visitor = crate::walk_semantic_package!(
                     completion::CompletableObjectFinderVisitor::new(
-                        &identifier.name
+                        &identifier.name,
+                        convert::position_to_lsp(
+                            &member.base.location.start
+                        ),
                     ),
                     sem_pkg
                 );
-                let imports = completion::get_imports(sem_pkg);
+                // The params of parameterized queries, unless the script defines
+                // `params` itself.
+                let param_items = match self
+                    .read_state()
+                    .query_params()
+                {
+                    Some(params)
+                        if identifier.name
+                            == crate::query_params::PARAMS
+                            && visitor.completables.is_empty() =>
+                    {
+                        crate::query_params::param_items(params)
+                    }
+                    _ => vec![],
+                };
+                let markdown = self.supports_markdown_completion();
+                let completion_item =
+                    |completable: &dyn completion::Completable| {
+                        let mut item =
+                            completable.completion_item(&imports);
+                        if markdown {
+                            item.documentation = Some(
+                                lsp::Documentation::MarkupContent(
+                                    completable
+                                        .markdown_documentation(),
+                                ),
+                            );
+                        }
+                        item
+                    };
                 Some(
                     vec![
                         visitor
                             .completables
                             .iter()
                             .map(|completable| {
-                                completable.completion_item(&imports)
+                                completion_item(completable.as_ref())
                             })
                             .collect::<Vec<lsp::CompletionItem>>(),
-                        list.iter()
-                            .map(|completable| {
-                                completable.completion_item(&imports)
+                        package_items
+                            .iter()
+                            .map(|item| {
+                                item.completion_item(markdown)
                             })
                             .collect(),
+                        param_items,
                     ]
                     .into_iter()
                     .flatten()
@@ -374,16 +3587,35 @@ impl LanguageServer for LspServer {
         &self,
         params: lsp::InitializeParams,
     ) -> RpcResult<lsp::InitializeResult> {
+        let mut options: InitializationOptions = params
+            .initialization_options
+            .and_then(|options| serde_json::from_value(options).ok())
+            .unwrap_or_default();
         match self.client_capabilities.write() {
             Ok(mut client_capabilities) => {
                 *client_capabilities = params.capabilities;
             }
             Err(err) => log::error!("{}", err),
         }
+        let disabled = {
+            let mut state = self.write_state();
+            state.set_document_colors(options.document_colors);
+            let disabled = state
+                .disabled_capabilities()
+                .union(options.disabled_capabilities);
+            state.set_disabled_capabilities(disabled);
+            disabled
+        };
+        options.disabled_capabilities = disabled;
 
         Ok(lsp::InitializeResult {
             capabilities: lsp::ServerCapabilities {
-                code_action_provider: Some(lsp::CodeActionProviderCapability::Simple(true)),
+                code_action_provider: (!disabled.code_actions).then_some(lsp::CodeActionProviderCapability::Simple(true)),
+                // Lenses run tests, which only native builds can do, with a command.
+                code_lens_provider: (cfg!(feature = "cmd") && !disabled.execute_commands).then_some(lsp::CodeLensOptions {
+                    resolve_provider: Some(false),
+                }),
+                color_provider: options.document_colors.then_some(lsp::ColorProviderCapability::Simple(true)),
                 completion_provider: Some(lsp::CompletionOptions {
                     resolve_provider: None,
                     trigger_characters: Some(vec![
@@ -409,21 +3641,25 @@ impl LanguageServer for LspServer {
                 document_symbol_provider: Some(lsp::OneOf::Left(
                     true,
                 )),
-                execute_command_provider: Some(lsp::ExecuteCommandOptions {
-                    commands: commands::LspServerCommand::iter().map(|command| command.into()).collect::<Vec<String>>(),
+                workspace_symbol_provider: Some(lsp::OneOf::Left(
+                    true,
+                )),
+                execute_command_provider: (!disabled.execute_commands).then_some(lsp::ExecuteCommandOptions {
+                    commands: LspServerCommand::iter().map(|command| command.into()).collect::<Vec<String>>(),
                     work_done_progress_options: lsp::WorkDoneProgressOptions {
                         work_done_progress: None,
                     }
                 }),
-                folding_range_provider: Some(
+                folding_range_provider: (!disabled.folding).then_some(
                     lsp::FoldingRangeProviderCapability::Simple(true),
                 ),
                 hover_provider: Some(
                     lsp::HoverProviderCapability::Simple(true),
                 ),
+                moniker_provider: Some(lsp::OneOf::Left(true)),
                 references_provider: Some(lsp::OneOf::Left(true)),
                 rename_provider: Some(lsp::OneOf::Left(true)),
-                semantic_tokens_provider: Some(lsp::SemanticTokensServerCapabilities::SemanticTokensOptions(lsp::SemanticTokensOptions{
+                semantic_tokens_provider: (!disabled.semantic_tokens).then_some(lsp::SemanticTokensServerCapabilities::SemanticTokensOptions(lsp::SemanticTokensOptions{
                     work_done_progress_options: lsp::WorkDoneProgressOptions {
                         work_done_progress: None
                     },
@@ -453,10 +3689,14 @@ impl LanguageServer for LspServer {
                         lsp::TextDocumentSyncOptions {
                             open_close: Some(true),
                             change: Some(lsp::TextDocumentSyncKind::FULL),
+                            will_save_wait_until: Some(true),
                             ..Default::default()
                         }
                     ),
                 ),
+                experimental: Some(serde_json::json!({
+                    "fluxProtocol": ProtocolCapabilities::new(&options),
+                })),
                 ..Default::default()
             },
             server_info: Some(lsp::ServerInfo {
@@ -467,6 +3707,12 @@ impl LanguageServer for LspServer {
     }
 
     async fn shutdown(&self) -> RpcResult<()> {
+        // Analyses and composition resolutions running in the background are
+        // waited for, so that none is cut short once the shutdown is acknowledged.
+        self.background.idle().await;
+        self.shutdown_requested.store(true, Ordering::SeqCst);
+        self.save_symbol_index();
+
         // XXX: rockstar (19 May 2022) - This chunk of code will no longer be needed,
         // when tower-lsp is added again.
         let mut client = match self.client.lock() {
@@ -491,8 +3737,42 @@ impl LanguageServer for LspServer {
         let key = params.text_document.uri;
         let value = params.text_document.text;
         self.store.put(&key, &value);
+        self.write_state()
+            .set_document_version(&key, params.text_document.version);
+        self.notify_observers(&key, |observer, file| {
+            observer.opened(&key, file)
+        });
 
-        self.publish_diagnostics(&key).await;
+        // Analyze the package as soon as it is opened, rather than on the first
+        // hover or completion request, in the background so that the messages
+        // following the open aren't held up. The store keeps the analysis until a
+        // file in the package changes, and the client is told when it is ready.
+        self.send_analysis_status(&key, false).await;
+        let server = self.clone();
+        let version = params.text_document.version;
+        self.background
+            .spawn(async move {
+                // A change arriving in the meantime publishes its own diagnostics,
+                // which those of the opened contents would replace.
+                let changed =
+                    server.read_state().document_version(&key)
+                        != Some(version);
+                if !changed {
+                    server.publish_diagnostics(&key).await;
+                }
+                if let Err(err) =
+                    server.store.get_semantic_package(&key)
+                {
+                    log::debug!(
+                        "Could not analyze {}: {:?}",
+                        key,
+                        err
+                    );
+                }
+                server.index_symbols(&key);
+                server.send_analysis_status(&key, true).await;
+            })
+            .await;
     }
 
     async fn did_change(
@@ -500,6 +3780,7 @@ impl LanguageServer for LspServer {
         params: lsp::DidChangeTextDocumentParams,
     ) -> () {
         let key = params.text_document.uri;
+        let version = params.text_document.version;
 
         match self.store.get(&key) {
             Ok(value) => {
@@ -512,79 +3793,29 @@ impl LanguageServer for LspServer {
                     .iter()
                     .fold(value, |_acc, change| change.text.clone());
                 self.store.put(&key, &new_contents.clone());
-                self.publish_diagnostics(&key).await;
+                self.notify_observers(&key, |observer, file| {
+                    observer.changed(&key, file)
+                });
 
-                // let mut composition_position = None;
-                if self.store.get_package_errors(&key).is_none() {
-                    let composition_state = match self.state.lock() {
-                        Ok(mut state) => {
-                            if let Some(composition) =
-                                state.get_mut_composition(&key)
-                            {
-                                match self.store.get_ast_file(&key) {
-                                    Ok(file) => {
-                                        let result = composition
-                                            .resolve_with_ast(file);
-                                        if result.is_err() {
-                                            state.drop_composition(
-                                                &key,
-                                            );
-                                            Err(LspClientCommand::CompositionDropped)
-                                        } else {
-                                            Ok(composition.clone())
-                                        }
-                                    }
-                                    Err(_) => Err(LspClientCommand::CompositionNotFound),
-                                }
-                            } else {
-                                Err(LspClientCommand::CompositionNotFound)
-                            }
-                        }
-                        Err(err) => panic!("{}", err),
-                    };
+                // The composition is resolved in the background. Changes arriving while
+                // it is being resolved for an earlier one are queued, rather than each
+                // resolving it in turn against contents that were already replaced.
+                let resolve = {
+                    let mut state = self.write_state();
+                    state.set_document_version(&key, version);
+                    state.queue_composition_resolution(&key, version)
+                };
+                self.publish_diagnostics(&key).await;
+                self.index_symbols(&key);
 
-                    if let Some(client) = self.get_client() {
-                        match composition_state {
-                            Ok(composition) => {
-                                let position = composition
-                                    .get_stmt_position()
-                                    .expect("Bad stmt position.");
-                                let range_action_item = lsp::MessageActionItem {
-                                    title: LspMessageActionItem::CompositionRange.to_string(),
-                                    properties: HashMap::from([("range".to_string(), lsp::MessageActionItemProperty::Object(
-                                        serde_json::to_value(HashMap::from([
-                                            ("start", position.start),
-                                            ("end", position.end)
-                                        ])).expect("Bad stmt position")
-                                    ))])
-                                };
-                                let composition_state_action_item = lsp::MessageActionItem {
-                                    title: LspMessageActionItem::CompositionState.to_string(),
-                                    properties: HashMap::from([
-                                        ("state".to_string(), lsp::MessageActionItemProperty::Object(
-                                        composition.get_serialized_composition_state().expect("Bad composition state")))])
-                                };
-                                let params = lsp::ShowMessageRequestParams {
-                                    typ: lsp::MessageType::INFO,
-                                    message: LspClientCommand::UpdateComposition.to_string(),
-                                    actions: Some(vec![range_action_item, composition_state_action_item]),
-                                };
-                                client.send_custom_notification::<ClientCommandNotification>(params).await;
-                            }
-                            Err(error_type) => {
-                                let params =
-                                    lsp::ShowMessageRequestParams {
-                                        typ: lsp::MessageType::INFO,
-                                        message: error_type
-                                            .to_string(),
-                                        actions: None,
-                                    };
-                                client.send_custom_notification::<ClientCommandNotification>(params).await;
-                            }
-                        };
-                    } else {
-                        log::error!("Failed to acquire client.");
-                    };
+                if resolve {
+                    let server = self.clone();
+                    let key = key.clone();
+                    self.background
+                        .spawn(async move {
+                            server.resolve_compositions(&key).await
+                        })
+                        .await;
                 }
             }
             Err(err) => log::error!(
@@ -600,12 +3831,23 @@ impl LanguageServer for LspServer {
         params: lsp::DidCloseTextDocumentParams,
     ) -> () {
         self.store.remove(&params.text_document.uri);
-        match self.state.lock() {
-            Ok(mut state) => {
-                state.drop_composition(&params.text_document.uri)
+        for observer in &self.observers {
+            observer.closed(&params.text_document.uri);
+        }
+        {
+            let mut state = self.write_state();
+            state.drop_composition(&params.text_document.uri);
+            state.drop_document_version(&params.text_document.uri);
+            state.drop_published_diagnostics(
+                &params.text_document.uri,
+            );
+            // The symbols of closed files stay indexed, but documents that aren't
+            // files, e.g. untitled ones, are gone once closed.
+            if params.text_document.uri.scheme() != "file" {
+                state.symbol_index.remove(&params.text_document.uri);
             }
-            Err(err) => panic!("{}", err),
         }
+        self.save_symbol_index();
     }
 
     async fn did_change_configuration(
@@ -619,26 +3861,180 @@ impl LanguageServer for LspServer {
                     buckets,
                 )) = settings.get("buckets")
                 {
-                    match self.state.lock() {
-                        Ok(mut state) => {
-                            state.set_buckets(
-                                buckets
-                                    .iter()
-                                    .filter(|bucket| {
-                                        bucket.is_string()
-                                    })
-                                    .map(|bucket| {
-                                        #[allow(clippy::unwrap_used)]
-                                        String::from(
-                                            bucket.as_str().unwrap(),
-                                        )
-                                    })
-                                    .collect::<Vec<String>>(),
-                            );
+                    self.write_state().set_buckets(
+                        buckets
+                            .iter()
+                            .filter(|bucket| bucket.is_string())
+                            .map(|bucket| {
+                                #[allow(clippy::unwrap_used)]
+                                String::from(bucket.as_str().unwrap())
+                            })
+                            .collect::<Vec<String>>(),
+                    );
+                }
+                if let Some(max) = settings
+                    .get("maxDiagnosticsPerFile")
+                    .and_then(|max| max.as_u64())
+                {
+                    self.write_state().set_max_diagnostics_per_file(
+                        usize::try_from(max).unwrap_or(usize::MAX),
+                    );
+                }
+                if let Some(strict) = settings
+                    .get("strictAnalysis")
+                    .and_then(|strict| strict.as_bool())
+                {
+                    self.write_state().set_strict_analysis(strict);
+                }
+                if let Some(warn) = settings
+                    .get("warnInsecureHttp")
+                    .and_then(|warn| warn.as_bool())
+                {
+                    self.write_state().set_warn_insecure_http(warn);
+                }
+                if let Some(target) = settings.get("target") {
+                    let platform = target
+                        .as_str()
+                        .and_then(lang::Platform::parse);
+                    if platform.is_none() && !target.is_null() {
+                        log::warn!(
+                            "Unknown target platform: {}",
+                            target
+                        );
+                    }
+                    self.write_state().set_platform(platform);
+                }
+                if let Some(target) = settings.get("targetVersion") {
+                    // `null` goes back to not having a target.
+                    self.write_state().set_target_version(
+                        target.as_str().map(String::from),
+                    );
+                }
+                if let Some(params) = settings.get("queryParams") {
+                    // `null` goes back to not having params.
+                    match serde_json::from_value(params.clone()) {
+                        Ok(params) => self
+                            .write_state()
+                            .set_query_params(params),
+                        Err(err) => {
+                            log::warn!("Invalid queryParams: {}", err)
                         }
-                        Err(err) => log::error!("{}", err),
                     }
                 }
+                if let Some(deprecations) =
+                    settings.get("deprecatedFunctions")
+                {
+                    match serde_json::from_value(deprecations.clone())
+                    {
+                        Ok(deprecations) => self
+                            .write_state()
+                            .set_deprecated_functions(deprecations),
+                        Err(err) => log::warn!(
+                            "Invalid deprecatedFunctions: {}",
+                            err
+                        ),
+                    }
+                }
+                if let Some(serde_json::value::Value::Array(lints)) =
+                    settings.get("optInLints")
+                {
+                    self.write_state().set_opt_in_lints(
+                        lints
+                            .iter()
+                            .filter_map(|lint| lint.as_str())
+                            .map(String::from)
+                            .collect(),
+                    );
+                }
+                if let Some(stage_per_line) =
+                    settings.get("formatStagePerLine").and_then(
+                        |stage_per_line| stage_per_line.as_bool(),
+                    )
+                {
+                    let mut state = self.write_state();
+                    let style = state.format_style();
+                    state.set_format_style(
+                        crate::formatting::FormatStyle {
+                            stage_per_line,
+                            ..style
+                        },
+                    );
+                }
+                if let Some(format_on_save) =
+                    settings.get("formatOnSave").and_then(
+                        |format_on_save| format_on_save.as_bool(),
+                    )
+                {
+                    self.write_state()
+                        .set_format_on_save(format_on_save);
+                }
+                if let Some(width) =
+                    settings.get("formatMaxLineWidth")
+                {
+                    // `null` goes back to lines of any width.
+                    let mut state = self.write_state();
+                    let style = state.format_style();
+                    state.set_format_style(
+                        crate::formatting::FormatStyle {
+                            max_line_width: width.as_u64().and_then(
+                                |width| usize::try_from(width).ok(),
+                            ),
+                            ..style
+                        },
+                    );
+                }
+                if let Some(serde_json::value::Value::Object(
+                    severities,
+                )) = settings.get("lintSeverities")
+                {
+                    self.write_state().set_lint_severities(
+                        severities
+                            .iter()
+                            .filter_map(|(code, severity)| {
+                                let parsed = severity.as_str().and_then(
+                                    crate::diagnostics::parse_severity,
+                                );
+                                if parsed.is_none() {
+                                    log::warn!(
+                                        "Unknown severity of {}: {}",
+                                        code,
+                                        severity
+                                    );
+                                }
+                                Some((code.clone(), parsed?))
+                            })
+                            .collect(),
+                    );
+                }
+                #[cfg(feature = "cmd")]
+                if let Some(command) = settings
+                    .get("fluxCommand")
+                    .and_then(|command| command.as_str())
+                {
+                    let mut state = self.write_state();
+                    if state.flux_command_fixed() {
+                        log::warn!(
+                            "Ignoring the fluxCommand setting, the flux \
+                             command is set by the server"
+                        );
+                    } else {
+                        state.set_flux_command(command.into());
+                    }
+                }
+                #[cfg(feature = "native-queries")]
+                if let Some(influxdb) = settings.get("influxdb") {
+                    self.write_state().set_influxdb(
+                        serde_json::from_value(influxdb.clone()).ok(),
+                    );
+                }
+
+                // Settings like `strictAnalysis`, `targetVersion` and `lintSeverities`
+                // change what is reported, so open documents are checked again rather
+                // than keeping their diagnostics until their next change. Only those
+                // whose diagnostics changed are published.
+                for url in self.store.get_urls() {
+                    self.publish_diagnostics(&url).await;
+                }
             }
         }
     }
@@ -671,7 +4067,7 @@ impl LanguageServer for LspServer {
                 if let flux::semantic::nodes::Expression::Member(member) = callee.clone() {
                     let name = member.property.clone();
                     if let flux::semantic::nodes::Expression::Identifier(ident) = member.object.clone() {
-                        match lang::STDLIB.package(&ident.name) {
+                        match completion::resolve_package(&completion::get_imports(&pkg), &ident.name) {
                             None => return Ok(None),
                             Some(package) => match package.function(&name) {
                                 None => return Ok(None),
@@ -719,8 +4115,12 @@ impl LanguageServer for LspServer {
         let key = params.text_document.uri;
 
         let contents = self.get_document(&key)?;
-        let mut formatted = match flux::formatter::format(&contents) {
-            Ok(value) => value,
+        let style = self.read_state().format_style();
+        let formatted = match flux::formatter::format(&contents) {
+            Ok(value) => crate::formatting::apply_options(
+                &crate::formatting::apply_style(&value, style),
+                &params.options,
+            ),
             Err(err) => {
                 return Err(lspower::jsonrpc::Error {
                     code: lspower::jsonrpc::ErrorCode::InternalError,
@@ -732,31 +4132,6 @@ impl LanguageServer for LspServer {
                 })
             }
         };
-        if let Some(trim_trailing_whitespace) =
-            params.options.trim_trailing_whitespace
-        {
-            if trim_trailing_whitespace {
-                log::info!("textDocument/formatting requested trimming trailing whitespace, but the flux formatter will always trim trailing whitespace");
-            }
-        }
-        if let Some(insert_final_newline) =
-            params.options.insert_final_newline
-        {
-            if insert_final_newline
-                && formatted.chars().last().unwrap_or(' ') != '\n'
-            {
-                formatted.push('\n');
-            }
-        }
-        if let Some(trim_final_newlines) =
-            params.options.trim_final_newlines
-        {
-            if trim_final_newlines
-                && formatted.chars().last().unwrap_or(' ') != '\n'
-            {
-                log::info!("textDocument/formatting requested trimming final newlines, but the flux formatter will always trim trailing whitespace");
-            }
-        }
 
         // The new text shows the range of the previously replaced section,
         // not the range of the new section.
@@ -780,10 +4155,122 @@ impl LanguageServer for LspServer {
         Ok(Some(vec![edit]))
     }
 
+    async fn will_save_wait_until(
+        &self,
+        params: lsp::WillSaveTextDocumentParams,
+    ) -> RpcResult<Option<Vec<lsp::TextEdit>>> {
+        // Saves after a delay or losing focus happen while typing, where moving the
+        // text around would get in the way.
+        if !self.read_state().format_on_save()
+            || params.reason != lsp::TextDocumentSaveReason::MANUAL
+        {
+            return Ok(None);
+        }
+        let params = lsp::DocumentFormattingParams {
+            text_document: params.text_document,
+            options: lsp::FormattingOptions::default(),
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+        };
+        // A document that can't be formatted is still saved as it is.
+        match self.formatting(params).await {
+            Ok(edits) => Ok(edits),
+            Err(err) => {
+                log::debug!(
+                    "Could not format on save: {}",
+                    err.message
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    async fn code_lens(
+        &self,
+        params: lsp::CodeLensParams,
+    ) -> RpcResult<Option<Vec<lsp::CodeLens>>> {
+        if self.read_state().disabled_capabilities().execute_commands
+        {
+            return Ok(None);
+        }
+        let uri = params.text_document.uri;
+        let file = match self.store.get_ast_file(&uri) {
+            Ok(file) => file,
+            Err(err) => return Err(err.into()),
+        };
+
+        // Every test can be run, or run verbosely to see what it outputs.
+        let lenses: Vec<lsp::CodeLens> = crate::testing::tests(&file)
+            .into_iter()
+            .flat_map(|test| {
+                let uri = uri.clone();
+                [("Run test", false), ("Debug output", true)]
+                    .into_iter()
+                    .map(move |(title, verbose)| lsp::CodeLens {
+                        range: test.range,
+                        command: Some(lsp::Command {
+                            title: title.into(),
+                            command: LspServerCommand::RunTests
+                                .into(),
+                            arguments: Some(vec![
+                                serde_json::json!({
+                                    "textDocument": {"uri": uri},
+                                    "names": [test.name],
+                                    "verbose": verbose,
+                                }),
+                            ]),
+                        }),
+                        data: None,
+                    })
+            })
+            .collect();
+        if lenses.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(lenses))
+        }
+    }
+
+    async fn document_color(
+        &self,
+        params: lsp::DocumentColorParams,
+    ) -> RpcResult<Vec<lsp::ColorInformation>> {
+        if !self.read_state().document_colors() {
+            return Ok(vec![]);
+        }
+        let file = match self
+            .store
+            .get_ast_file(&params.text_document.uri)
+        {
+            Ok(file) => file,
+            Err(err) => return Err(err.into()),
+        };
+        Ok(crate::colors::document_colors(&file))
+    }
+
+    async fn color_presentation(
+        &self,
+        params: lsp::ColorPresentationParams,
+    ) -> RpcResult<Vec<lsp::ColorPresentation>> {
+        let label = crate::colors::presentation(&params.color);
+        Ok(vec![lsp::ColorPresentation {
+            text_edit: Some(lsp::TextEdit {
+                range: params.range,
+                new_text: label.clone(),
+            }),
+            label,
+            additional_text_edits: None,
+        }])
+    }
+
     async fn folding_range(
         &self,
         params: lsp::FoldingRangeParams,
     ) -> RpcResult<Option<Vec<lsp::FoldingRange>>> {
+        if self.read_state().disabled_capabilities().folding {
+            return Ok(None);
+        }
         let key = params.text_document.uri;
         let pkg = match self.store.get_semantic_package(&key) {
             Ok(pkg) => pkg,
@@ -797,12 +4284,15 @@ impl LanguageServer for LspServer {
         let results: Vec<lsp::FoldingRange> = visitor
             .nodes
             .into_iter()
-            .map(|node| lsp::FoldingRange {
-                start_line: node.loc().start.line,
-                start_character: Some(node.loc().start.column),
-                end_line: node.loc().end.line,
-                end_character: Some(node.loc().end.column),
-                kind: Some(lsp::FoldingRangeKind::Region),
+            .map(|node| {
+                let range = convert::location_to_range(node.loc());
+                lsp::FoldingRange {
+                    start_line: range.start.line,
+                    start_character: Some(range.start.character),
+                    end_line: range.end.line,
+                    end_character: Some(range.end.character),
+                    kind: Some(lsp::FoldingRangeKind::Region),
+                }
             })
             .collect();
 
@@ -823,6 +4313,24 @@ impl LanguageServer for LspServer {
             Err(err) => return Err(err.into()),
         };
 
+        if self.supports_hierarchical_symbols() {
+            let filename = key
+                .path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .map(String::from);
+            let symbols = pkg
+                .files
+                .iter()
+                .find(|file| file.loc.file == filename)
+                .map(semantic::document_symbols)
+                .unwrap_or_default();
+            return Ok(if symbols.is_empty() {
+                None
+            } else {
+                Some(lsp::DocumentSymbolResponse::Nested(symbols))
+            });
+        }
+
         let visitor = crate::walk_semantic_package!(
             semantic::SymbolsVisitor::new(key),
             pkg
@@ -849,6 +4357,19 @@ impl LanguageServer for LspServer {
         Ok(response)
     }
 
+    async fn symbol(
+        &self,
+        params: lsp::WorkspaceSymbolParams,
+    ) -> RpcResult<Option<Vec<lsp::SymbolInformation>>> {
+        let symbols =
+            self.read_state().symbol_index.search(&params.query);
+        Ok(if symbols.is_empty() {
+            None
+        } else {
+            Some(symbols)
+        })
+    }
+
     async fn goto_definition(
         &self,
         params: lsp::GotoDefinitionParams,
@@ -870,6 +4391,30 @@ impl LanguageServer for LspServer {
             let node_name = match node {
                 walk::Node::Identifier(ident) => &ident.name,
                 walk::Node::IdentifierExpr(ident) => &ident.name,
+                walk::Node::MemberExpr(member) => {
+                    // Member properties aren't nodes of their own, so the member
+                    // expression is the innermost node when on the property.
+                    return Ok(find_object_literal(
+                        &pkg,
+                        &member.object,
+                    )
+                    .and_then(|object| {
+                        object.properties.iter().find(|property| {
+                            property.key.name.as_str()
+                                == member.property
+                        })
+                    })
+                    .map(|property| {
+                        lsp::GotoDefinitionResponse::from(
+                            node_to_location(
+                                &walk::Node::Identifier(
+                                    &property.key,
+                                ),
+                                key,
+                            ),
+                        )
+                    }));
+                }
                 _ => return Ok(None),
             };
 
@@ -899,27 +4444,64 @@ impl LanguageServer for LspServer {
             Err(err) => return Err(err.into()),
         };
 
-        let visitor = crate::walk_semantic_package!(
-            semantic::NodeFinderVisitor::new(
-                params.text_document_position.position
-            ),
-            pkg
+        // Only the renamed file is searched for the symbol, as the other files of the
+        // package have nodes at the same positions.
+        let filename = key
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(String::from);
+        let mut visitor = semantic::NodeFinderVisitor::new(
+            params.text_document_position.position,
+        );
+        if let Some(file) =
+            pkg.files.iter().find(|file| file.loc.file == filename)
+        {
+            walk::walk(&mut visitor, walk::Node::File(file));
+        }
+        let locations = find_package_references(
+            &key,
+            &pkg,
+            visitor.node,
+            visitor.path,
         );
-        let locations =
-            find_references(&key, visitor.node, visitor.path);
-        let edits = locations
-            .iter()
-            .map(|location| lsp::TextEdit {
-                range: location.range,
-                new_text: params.new_name.clone(),
-            })
-            .collect::<Vec<lsp::TextEdit>>();
 
-        Ok(Some(lsp::WorkspaceEdit {
-            changes: Some(HashMap::from([(key, edits)])),
-            document_changes: None,
-            change_annotations: None,
-        }))
+        let mut changes: HashMap<lsp::Url, Vec<lsp::TextEdit>> =
+            HashMap::new();
+        if locations.is_empty() {
+            let file = match self.store.get_ast_file(&key) {
+                Ok(file) => file,
+                Err(err) => return Err(err.into()),
+            };
+            changes.insert(
+                key.clone(),
+                find_property_references(
+                    &file,
+                    params.text_document_position.position,
+                )
+                .iter()
+                .map(|member| {
+                    rename_property(member, &params.new_name)
+                })
+                .collect(),
+            );
+        } else {
+            for location in locations {
+                changes.entry(location.uri).or_default().push(
+                    lsp::TextEdit {
+                        range: location.range,
+                        new_text: params.new_name.clone(),
+                    },
+                );
+            }
+        }
+
+        Ok(Some(crate::lsp::sorted_workspace_edit(
+            lsp::WorkspaceEdit {
+                changes: Some(changes),
+                document_changes: None,
+                change_annotations: None,
+            },
+        )))
     }
 
     async fn document_highlight(
@@ -928,6 +4510,28 @@ impl LanguageServer for LspServer {
     ) -> RpcResult<Option<Vec<lsp::DocumentHighlight>>> {
         let key =
             params.text_document_position_params.text_document.uri;
+        if let Some(ranges) =
+            self.store.get_ast_file(&key).ok().and_then(|file| {
+                let position =
+                    params.text_document_position_params.position;
+                pipeline_call_sites(&file, position).or_else(|| {
+                    crate::schema::measurement_references(
+                        &file, position,
+                    )
+                })
+            })
+        {
+            return Ok(Some(
+                ranges
+                    .into_iter()
+                    .map(|range| lsp::DocumentHighlight {
+                        kind: Some(lsp::DocumentHighlightKind::TEXT),
+                        range,
+                    })
+                    .collect(),
+            ));
+        }
+
         let pkg = match self.store.get_semantic_package(&key) {
             Ok(pkg) => pkg,
             Err(err) => return Err(err.into()),
@@ -939,7 +4543,17 @@ impl LanguageServer for LspServer {
             ),
             pkg
         );
-        let refs = find_references(&key, visitor.node, visitor.path);
+        let mut refs =
+            find_references(&key, visitor.node, visitor.path);
+        if refs.is_empty() {
+            if let Ok(file) = self.store.get_ast_file(&key) {
+                refs = property_reference_locations(
+                    &key,
+                    &file,
+                    params.text_document_position_params.position,
+                );
+            }
+        }
         Ok(Some(
             refs.iter()
                 .map(|r| lsp::DocumentHighlight {
@@ -967,8 +4581,19 @@ impl LanguageServer for LspServer {
             ),
             pkg
         );
-        let references =
+        let mut references =
             find_references(&key, visitor.node, visitor.path);
+        if references.is_empty() {
+            let file = match self.store.get_ast_file(&key) {
+                Ok(file) => file,
+                Err(err) => return Err(err.into()),
+            };
+            references = property_reference_locations(
+                &key,
+                &file,
+                params.text_document_position.position,
+            );
+        }
         Ok(if references.is_empty() {
             None
         } else {
@@ -976,6 +4601,82 @@ impl LanguageServer for LspServer {
         })
     }
 
+    /// Monikers of references to stdlib functions and values, e.g.
+    /// `flux:stdlib/universe#aggregateWindow`, which stay the same whichever script
+    /// they are referenced from.
+    async fn moniker(
+        &self,
+        params: lsp::MonikerParams,
+    ) -> RpcResult<Option<Vec<lsp::Moniker>>> {
+        let key =
+            params.text_document_position_params.text_document.uri;
+        let pkg = match self.store.get_semantic_package(&key) {
+            Ok(pkg) => pkg,
+            Err(err) => return Err(err.into()),
+        };
+
+        let visitor = crate::walk_semantic_package!(
+            semantic::NodeFinderVisitor::new(
+                params.text_document_position_params.position
+            ),
+            pkg
+        );
+        let symbol = match visitor.node {
+            // Names of the prelude, unless the script defines its own.
+            Some(walk::Node::IdentifierExpr(ident)) => {
+                let definition = crate::walk_semantic_package!(
+                    semantic::DefinitionFinderVisitor::new(
+                        ident.name.clone()
+                    ),
+                    pkg
+                );
+                (definition.node.is_none()
+                    && lang::UNIVERSE
+                        .exports_name(ident.name.as_str()))
+                .then(|| {
+                    ("universe".to_string(), ident.name.to_string())
+                })
+            }
+            // Members of imported packages, e.g. `strings.title`.
+            Some(walk::Node::MemberExpr(member)) => {
+                match &member.object {
+                    SemanticExpression::Identifier(object) => {
+                        completion::get_imports(&pkg)
+                            .into_iter()
+                            .find(|import| {
+                                import.name == object.name.as_str()
+                            })
+                            .and_then(|import| {
+                                lang::STDLIB.package(&import.path)
+                            })
+                            .filter(|package| {
+                                package.exports_name(
+                                    member.property.as_str(),
+                                )
+                            })
+                            .map(|package| {
+                                (
+                                    package.path,
+                                    member.property.to_string(),
+                                )
+                            })
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        Ok(symbol.map(|(path, name)| {
+            vec![lsp::Moniker {
+                scheme: "flux".into(),
+                identifier: format!("stdlib/{}#{}", path, name),
+                unique: lsp::UniquenessLevel::Scheme,
+                kind: Some(lsp::MonikerKind::Import),
+            }]
+        }))
+    }
+
     async fn hover(
         &self,
         params: lsp::HoverParams,
@@ -987,6 +4688,16 @@ impl LanguageServer for LspServer {
             Err(err) => return Err(err.into()),
         };
 
+        let schema = self.store.get_ast_package(&key).ok().and_then(
+            |ast_pkg| {
+                pipe_input_schema(
+                    &ast_pkg,
+                    &pkg,
+                    params.text_document_position_params.position,
+                )
+            },
+        );
+
         let visitor = crate::walk_semantic_package!(
             semantic::NodeFinderVisitor::new(
                 params.text_document_position_params.position
@@ -995,6 +4706,57 @@ impl LanguageServer for LspServer {
         );
         if let Some(node) = visitor.node {
             let path = &visitor.path;
+            // Hovering an import alias describes the package, rather than the type of
+            // the record of its members.
+            if let Some((alias, package)) = import_alias(path)
+                .and_then(|(alias, path)| {
+                    lang::STDLIB
+                        .package(path)
+                        .map(|package| (alias, package))
+                })
+            {
+                return Ok(Some(lsp::Hover {
+                    contents: self
+                        .import_alias_contents(alias, &package),
+                    range: None,
+                }));
+            }
+            // Hovering the name of a pipeline assignment shows which of its stages
+            // are estimated to run in storage.
+            let pushdown = match (&node, path.len().checked_sub(2)) {
+                (walk::Node::Identifier(_), Some(i)) => match path[i]
+                {
+                    walk::Node::VariableAssgn(var) => {
+                        crate::perf_lint::pushdown_boundary(&var.init)
+                    }
+                    _ => None,
+                },
+                _ => None,
+            };
+            // Names of buckets, measurements and fields are described with the schema
+            // pushed by the client.
+            let names = match &node {
+                walk::Node::StringLit(lit) => path
+                    .iter()
+                    .rev()
+                    .skip(1)
+                    .take(2)
+                    .flat_map(crate::schema::schema_names)
+                    .find(|(_, name)| std::ptr::eq(*name, *lit))
+                    .map(|(kind, name)| {
+                        crate::schema::describe_name(
+                            self.read_state().schema(),
+                            kind,
+                            &name.value,
+                        )
+                    })
+                    .unwrap_or_default(),
+                _ => vec![],
+            };
+            // The tables of the test data of `csv.from` calls are previewed.
+            let tables = csv_table_schemas(path);
+            // Fields of the task option are explained, and their values checked.
+            let task_field = crate::tasks::hovered_field(path);
             let hover_type = node
                 .type_of()
                 .map(|t| include_constraints(path, t).to_string())
@@ -1043,69 +4805,40 @@ impl LanguageServer for LspServer {
                     _ => None,
                 });
             if let Some(typ) = hover_type {
-                let supports_markdown = match self
-                    .client_capabilities
-                    .read()
-                {
-                    Ok(client_capabilities) => {
-                        if let Some(text_document) =
-                            (client_capabilities)
-                                .text_document
-                                .as_ref()
-                        {
-                            if let Some(hover) =
-                                text_document.hover.as_ref()
-                            {
-                                hover.content_format.as_ref().map_or(false, |formats| formats.contains(&lsp::MarkupKind::Markdown))
-                            } else {
-                                false
-                            }
-                        } else {
-                            false
-                        }
-                    }
-                    Err(err) => {
-                        log::error!("{}", err);
-                        false
-                    }
+                let typ = match constant_value(&pkg, path) {
+                    Some(value) => format!("{} = {}", typ, value),
+                    None => typ,
                 };
-                let hover_contents: lsp::HoverContents =
-                    match supports_markdown {
-                        true => lsp::HoverContents::Markup(
-                            lsp::MarkupContent {
-                                kind: lsp::MarkupKind::Markdown,
-                                value: format!(
-                                    "```flux\n{}\n```",
-                                    typ
-                                ),
-                            },
-                        ),
-                        false => lsp::HoverContents::Scalar(
-                            lsp::MarkedString::String(typ),
-                        ),
-                    };
-
                 return Ok(Some(lsp::Hover {
-                    contents: hover_contents,
+                    contents: self.hover_contents(
+                        Some(typ),
+                        schema,
+                        pushdown,
+                        names,
+                        tables,
+                        task_field,
+                    ),
                     range: None,
                 }));
             }
         }
-        Ok(None)
+        Ok(schema.map(|schema| lsp::Hover {
+            contents: self.hover_contents(
+                None,
+                Some(schema),
+                None,
+                vec![],
+                vec![],
+                None,
+            ),
+            range: None,
+        }))
     }
 
     async fn completion(
         &self,
         params: lsp::CompletionParams,
     ) -> RpcResult<Option<lsp::CompletionResponse>> {
-        // This is the rules for matching whether a string should be part of
-        // the completion matching.
-        let fuzzy_match = |haystack: &str, needle: &str| -> bool {
-            return haystack
-                .to_lowercase()
-                .contains(needle.to_lowercase().as_str());
-        };
-
         let ast_pkg = match self.store.get_ast_package(
             &params.text_document_position.text_document.uri,
         ) {
@@ -1127,7 +4860,15 @@ impl LanguageServer for LspServer {
             ),
             ast_pkg
         );
-        let items = match visitor.node {
+        let columns = visitor.node.as_ref().and_then(|node| {
+            argument_columns(
+                &sem_pkg,
+                node,
+                self.read_state().schema(),
+            )
+        });
+        let platform = self.read_state().platform();
+        let mut items: Vec<lsp::CompletionItem> = match visitor.node {
             Some(walk_node) => match walk_node.node {
                 AstNode::CallExpr(call) => {
                     completion::complete_call_expr(
@@ -1163,6 +4904,12 @@ impl LanguageServer for LspServer {
                                 lsp::CompletionItem,
                             > = lang::STDLIB
                                 .fuzzy_matches(&identifier.name)
+                                .filter(|package| {
+                                    lang::is_available(
+                                        &package.path,
+                                        platform,
+                                    )
+                                })
                                 .map(|package| {
                                     lsp::CompletionItem {
                                 label: package.path.clone(),
@@ -1190,63 +4937,65 @@ impl LanguageServer for LspServer {
                                 })
                                 .collect();
 
+                            // Variables in scope rank before everything else.
+                            let uri = &params
+                                .text_document_position
+                                .text_document
+                                .uri;
+                            let scoped_completions =
+                                completion::scoped_items(
+                                    &sem_pkg,
+                                    uri.path_segments()
+                                        .and_then(|mut segments| {
+                                            segments.next_back()
+                                        })
+                                        .map(String::from),
+                                    params
+                                        .text_document_position
+                                        .position,
+                                    &identifier.name,
+                                );
+
+                            let markdown =
+                                self.supports_markdown_completion();
                             let builtin_completions: Vec<
-                        lsp::CompletionItem,
-                    > = lang::UNIVERSE.exports.iter().filter(|(key, val)| {
-                            // Don't allow users to "discover" private-ish functionality.
-                            // Filter out irrelevent items that won't match.
-                            // Only pass expressions that have completion support.
-                            !key.starts_with('_') && fuzzy_match(key, &identifier.name) &&
-                            match &val.expr {
-                                MonoType::Fun(_) | MonoType::Builtin(_) => true,
-                                MonoType::Collection(collection) => collection.collection == CollectionType::Array,
-                                _ => false
-                            }
-                        }).map(|(key, val)| {
-                            match &val.expr {
-                                MonoType::Fun(function) => {
-                                    lsp::CompletionItem {
-                                        label: key.to_string(),
-                                        detail: Some(completion::create_function_signature(function)),
-                                        filter_text: Some(key.to_string()),
-                                        insert_text_format: Some(lsp::InsertTextFormat::SNIPPET),
-                                        kind: Some(lsp::CompletionItemKind::FUNCTION),
-                                        sort_text: Some(key.to_string()),
-                                        ..lsp::CompletionItem::default()
-                                    }
-                                }
-                                MonoType::Builtin(builtin) => {
-                                    lsp::CompletionItem {
-                                        label: format!("{} ({})", key, "prelude"),
-                                        detail: Some(match *builtin {
-                                            BuiltinType::String => "String".into(),
-                                            BuiltinType::Int => "Integer".into(),
-                                            BuiltinType::Float => "Float".into(),
-                                            BuiltinType::Bool => "Boolean".into(),
-                                            BuiltinType::Bytes => "Bytes".into(),
-                                            BuiltinType::Duration => "Duration".into(),
-                                            BuiltinType::Uint => "Uint".into(),
-                                            BuiltinType::Regexp => "Regular Expression".into(),
-                                            BuiltinType::Time => "Time".into(),
-                                        }),
-                                        documentation: Some(lsp::Documentation::String("from prelude".into())),
-                                        filter_text: Some(key.to_string()),
-                                        insert_text: Some(key.to_string()),
-                                        insert_text_format: Some(
-                                            lsp::InsertTextFormat::PLAIN_TEXT
-                                        ),
-                                        kind: Some(lsp::CompletionItemKind::VARIABLE),
-                                        sort_text: Some(format!("{} prelude", key)),
-                                        ..lsp::CompletionItem::default()
-                                    }
+                                lsp::CompletionItem,
+                            > = completion::prelude_items()
+                                .iter()
+                                .filter(|item| {
+                                    item.matches(&identifier.name)
+                                })
+                                .map(|item| {
+                                    item.completion_item(markdown)
+                                })
+                                .collect();
+
+                            // Snippets expand to whole statements, so they are only
+                            // offered for identifiers that are statements of their own.
+                            let snippet_completions: Vec<
+                                lsp::CompletionItem,
+                            > = match walk_node
+                                .parent
+                                .as_ref()
+                                .map(|node| &node.node)
+                            {
+                                Some(AstNode::ExprStmt(_)) => {
+                                    snippets::matching(
+                                        &identifier.name,
+                                    )
+                                    .map(|snippet| {
+                                        snippet.completion_item()
+                                    })
+                                    .collect()
                                 }
-                                _ => unreachable!("Previous filter on expression value failed. Got: {}", val.expr)
-                            }
-                        }).collect();
+                                _ => vec![],
+                            };
 
                             vec![
+                                scoped_completions,
                                 stdlib_completions,
                                 builtin_completions,
+                                snippet_completions,
                             ]
                             .into_iter()
                             .flatten()
@@ -1273,10 +5022,49 @@ impl LanguageServer for LspServer {
                                 &params, &sem_pkg, call,
                             )
                         }
+                        // Header names of the headers of HTTP requests.
+                        Some(_)
+                            if crate::http::is_completed_headers(
+                                &walk_node,
+                            ) =>
+                        {
+                            crate::http::header_items()
+                        }
                         Some(_) | None => return Ok(None),
                     }
                 }
-                AstNode::StringLit(_) => {
+                // Header names of the headers of HTTP requests.
+                AstNode::DictExpr(_)
+                    if crate::http::is_completed_headers(
+                        &walk_node,
+                    ) =>
+                {
+                    crate::http::header_items()
+                }
+                // Columns of the arguments of joins and pivots, from their inputs.
+                AstNode::ArrayExpr(_) | AstNode::StringLit(_)
+                    if columns.is_some() =>
+                {
+                    let range = match walk_node.node {
+                        AstNode::StringLit(lit) => {
+                            convert::location_to_range(
+                                &lit.base.location,
+                            )
+                        }
+                        _ => lsp::Range::new(
+                            params.text_document_position.position,
+                            params.text_document_position.position,
+                        ),
+                    };
+                    columns
+                        .iter()
+                        .flatten()
+                        .map(|column| {
+                            quoted_value_item(column, "column", range)
+                        })
+                        .collect()
+                }
+                AstNode::StringLit(lit) => {
                     let parent = walk_node
                         .parent
                         .as_ref()
@@ -1288,6 +5076,7 @@ impl LanguageServer for LspServer {
 
                             lang::STDLIB.packages().filter(|package| {
                                 !&imports.iter().any(|x| x.path == package.path)
+                                    && lang::is_available(&package.path, platform)
                             }).map(|package| {
                                 let trigger = if let Some(context) = & params.context {
                                     context.trigger_character.as_deref()
@@ -1308,14 +5097,149 @@ impl LanguageServer for LspServer {
                                 }
                             }).collect()
                         }
-                        // This is where bucket/measurement/field/tag completion will occur.
-                        Some(_) | None => return Ok(None),
+                        // Header names being written as strings in the headers of
+                        // HTTP requests.
+                        Some(_)
+                            if crate::http::is_completed_headers(
+                                &walk_node,
+                            ) =>
+                        {
+                            let range = convert::location_to_range(
+                                &lit.base.location,
+                            );
+                            crate::http::HEADERS
+                                .iter()
+                                .map(|header| {
+                                    quoted_value_item(
+                                        header, "header", range,
+                                    )
+                                })
+                                .collect()
+                        }
+                        // Time zone names, from the tz database.
+                        Some(_)
+                            if crate::timezones::is_completed_name(
+                                &walk_node,
+                            ) =>
+                        {
+                            let range = convert::location_to_range(
+                                &lit.base.location,
+                            );
+                            crate::timezones::TIMEZONES
+                                .iter()
+                                .map(|name| {
+                                    quoted_value_item(
+                                        name,
+                                        "time zone",
+                                        range,
+                                    )
+                                })
+                                .collect()
+                        }
+                        // Secret keys, from the keys pushed by the client.
+                        Some(_)
+                            if crate::secrets::is_completed_key(
+                                &walk_node,
+                            ) =>
+                        {
+                            let range = convert::location_to_range(
+                                &lit.base.location,
+                            );
+                            self.read_state()
+                                .secret_keys()
+                                .into_iter()
+                                .flatten()
+                                .map(|key| {
+                                    quoted_value_item(
+                                        key, "secret", range,
+                                    )
+                                })
+                                .collect()
+                        }
+                        // Bucket, measurement and field names, from the schema pushed
+                        // by the client.
+                        Some(_) | None => {
+                            let kind =
+                                match crate::schema::completed_name_kind(
+                                    &walk_node,
+                                ) {
+                                    Some(kind) => kind,
+                                    None => return Ok(None),
+                                };
+                            let range = convert::location_to_range(
+                                &lit.base.location,
+                            );
+                            #[cfg(feature = "native-queries")]
+                            let queried = self
+                                .query_names(kind, &sem_pkg)
+                                .await;
+                            #[cfg(not(feature = "native-queries"))]
+                            let queried: Vec<String> = vec![];
+                            let state = self.read_state();
+                            // Names passed to schema functions are narrowed down to
+                            // the bucket and measurement passed along with them.
+                            let mut names = match crate::schema::completed_name_scope(
+                                &walk_node,
+                            ) {
+                                (None, None) => crate::schema::known_names(
+                                    state.schema(),
+                                    state.buckets(),
+                                    kind,
+                                ),
+                                _ if kind == crate::schema::NameKind::Bucket => {
+                                    crate::schema::known_names(
+                                        state.schema(),
+                                        state.buckets(),
+                                        kind,
+                                    )
+                                }
+                                (bucket, measurement) => {
+                                    crate::schema::names_in_scope(
+                                        state.schema(),
+                                        kind,
+                                        bucket.as_deref(),
+                                        measurement.as_deref(),
+                                    )
+                                }
+                            };
+                            names.extend(
+                                queried.iter().map(String::as_str),
+                            );
+                            names.sort_unstable();
+                            names.dedup();
+                            names
+                                .into_iter()
+                                .map(|name| {
+                                    quoted_value_item(
+                                        name,
+                                        kind.describe(),
+                                        range,
+                                    )
+                                })
+                                .collect()
+                        }
                     }
                 }
-                _ => return Ok(None),
+                _ => match self
+                    .complete_recovered_call(&params, &sem_pkg)
+                {
+                    Some(items) => items,
+                    None => return Ok(None),
+                },
+            },
+            None => match self
+                .complete_recovered_call(&params, &sem_pkg)
+            {
+                Some(items) => items,
+                None => return Ok(None),
             },
-            None => return Ok(None),
         };
+        // Clients not knowing the tag may show it as something else.
+        if !self.supports_deprecated_completion_tag() {
+            for item in &mut items {
+                item.tags = None;
+            }
+        }
         if items.is_empty() {
             Ok(None)
         } else {
@@ -1334,6 +5258,9 @@ impl LanguageServer for LspServer {
         &self,
         params: lsp::SemanticTokensParams,
     ) -> RpcResult<Option<lsp::SemanticTokensResult>> {
+        if self.read_state().disabled_capabilities().semantic_tokens {
+            return Ok(None);
+        }
         let pkg = match self
             .store
             .get_ast_package(&params.text_document.uri)
@@ -1358,20 +5285,41 @@ impl LanguageServer for LspServer {
         &self,
         params: lsp::CodeActionParams,
     ) -> RpcResult<Option<lsp::CodeActionResponse>> {
-        // Our code actions should all be connected with a diagnostic. The
-        // client user experience can vary when not directly connected to
-        // a diagnostic, which is sorta the client's fault, but we also
-        // don't have a need for trying to support any other flows.
-        if params.context.diagnostics.is_empty() {
+        if self.read_state().disabled_capabilities().code_actions {
             return Ok(None);
         }
+        // Our code actions should all be connected with a diagnostic, but for
+        // refactorings of the statement at the cursor. The client user experience
+        // can vary when not directly connected to a diagnostic, which is sorta the
+        // client's fault, but we also don't have a need for trying to support any
+        // other flows.
+        let refactor_actions = self.pipeline_actions(&params);
+        if params.context.diagnostics.is_empty() {
+            if refactor_actions.is_empty() {
+                return Ok(None);
+            }
+            return Ok(Some(refactor_actions));
+        }
 
+        let mut lint_actions = refactor_actions;
+        lint_actions.extend(self.prelude_shadowing_actions(&params));
+        lint_actions.extend(self.import_collision_actions(&params));
+        lint_actions.extend(self.duplicate_import_actions(&params));
+        lint_actions.extend(self.unnamed_result_actions(&params));
+        lint_actions.extend(self.aggregate_window_actions(&params));
+        lint_actions.extend(self.unused_parameter_actions(&params));
+        lint_actions.extend(self.literal_regex_actions(&params));
+        lint_actions
+            .extend(self.deprecated_function_actions(&params));
+
+        let strict = self.read_state().strict_analysis();
         let errors = match self
             .store
-            .get_package_errors(&params.text_document.uri)
+            .get_package_errors(&params.text_document.uri, strict)
         {
             Some(errors) => errors,
-            None => return Ok(None),
+            None if lint_actions.is_empty() => return Ok(None),
+            None => return Ok(Some(sorted_actions(lint_actions))),
         };
 
         let relevant: Vec<&flux::semantic::Error> = errors
@@ -1381,61 +5329,88 @@ impl LanguageServer for LspServer {
             .filter(|error| {
                 crate::lsp::ranges_overlap(
                     &params.range,
-                    &error.location.clone().into(),
+                    &convert::location_to_range(&error.location),
                 )
             })
             .collect();
         if relevant.is_empty() {
-            return Ok(None);
+            if lint_actions.is_empty() {
+                return Ok(None);
+            }
+            return Ok(Some(sorted_actions(lint_actions)));
         }
 
-        let pkg = match self
-            .store
-            .get_semantic_package(&params.text_document.uri)
-        {
-            Ok(pkg) => pkg,
-            Err(err) => unreachable!("{:?}", err),
+        let file = self.store.get_ast_file(&params.text_document.uri);
+        let source = self.store.get(&params.text_document.uri);
+        // Packages already imported, or offered for import by an earlier error, aren't
+        // offered again, so that applying several fixes doesn't import a package twice.
+        let mut imported: Vec<String> = match &file {
+            Ok(file) => file
+                .imports
+                .iter()
+                .map(|import| import.path.value.clone())
+                .collect(),
+            Err(_) => vec![],
         };
-
-        let visitor = crate::walk_semantic_package!(
-            semantic::PackageNodeFinderVisitor::default(),
-            pkg
-        );
-        let import_position = match visitor.location {
-            Some(location) => lsp::Position {
-                line: location.start.line + 1,
-                character: 0,
-            },
-            None => lsp::Position::default(),
+        // Packages whose name is already taken are imported with an alias, which the
+        // undefined identifier is then renamed to.
+        let taken: Vec<String> = match &file {
+            Ok(file) => taken_names(file),
+            Err(_) => vec![],
         };
+        let platform = self.read_state().platform();
 
-        let actions: Vec<lsp::CodeActionOrCommand> = relevant.iter().map(|error| {
+        let mut actions: Vec<lsp::CodeActionOrCommand> = relevant.iter().map(|error| {
             if let ErrorKind::Inference(kind) = &error.error {
                 match kind {
                     SemanticNodeErrorKind::UndefinedIdentifier(identifier) => {
                         // When encountering undefined identifiers, check to see if they match any corresponding
                         // packages available for import.
-                        let potential_imports: Vec<lang::Package> = lang::STDLIB.fuzzy_matches(identifier).collect();
+                        let potential_imports: Vec<lang::Package> = lang::STDLIB.fuzzy_matches(identifier).filter(|package| {
+                            if imported.contains(&package.path) || !lang::is_available(&package.path, platform) {
+                                return false;
+                            }
+                            imported.push(package.path.clone());
+                            true
+                        }).collect();
                         if potential_imports.is_empty() {
                             return None;
                         }
 
                         let inner_actions: Vec<lsp::CodeActionOrCommand> = potential_imports.iter().map(|package| {
+                            let alias = if taken.contains(&package.name) {
+                                Some(crate::diagnostics::suggest_import_alias(&package.path, &taken))
+                            } else {
+                                None
+                            };
+                            let mut edits = vec![
+                                match (&file, &source) {
+                                    (Ok(file), Ok(source)) => imports::import_edit(file, source, &package.path, alias.as_deref()),
+                                    _ => lsp::TextEdit {
+                                        range: lsp::Range::default(),
+                                        new_text: match &alias {
+                                            Some(alias) => format!("import {} \"{}\"\n", alias, package.path),
+                                            None => format!("import \"{}\"\n", package.path),
+                                        },
+                                    },
+                                }
+                            ];
+                            if let Some(alias) = &alias {
+                                edits.push(lsp::TextEdit {
+                                    range: convert::location_to_range(&error.location),
+                                    new_text: alias.clone(),
+                                });
+                            }
                             lsp::CodeAction {
-                                title: format!("Import `{}`", package.path),
+                                title: match &alias {
+                                    Some(alias) => format!("Import `{}` as `{}`", package.path, alias),
+                                    None => format!("Import `{}`", package.path),
+                                },
                                 kind: Some(lsp::CodeActionKind::QUICKFIX),
                                 diagnostics: None,
                                 edit: Some(lsp::WorkspaceEdit {
                                     changes: Some(HashMap::from([
-                                        (params.text_document.uri.clone(), vec![
-                                            lsp::TextEdit {
-                                                range: lsp::Range {
-                                                    start: import_position,
-                                                    end: import_position,
-                                                },
-                                                new_text: format!("import \"{}\"\n", package.path),
-                                            }
-                                        ])
+                                        (params.text_document.uri.clone(), edits)
                                     ])),
                                     document_changes: None,
                                     change_annotations: None,
@@ -1455,14 +5430,21 @@ impl LanguageServer for LspServer {
         }).filter(|action| action.is_some()).flat_map(|action| {
             action.expect("Previous .filter() call failed.")
         }).collect();
+        actions.extend(lint_actions);
 
-        return Ok(Some(actions));
+        return Ok(Some(sorted_actions(actions)));
     }
 
     async fn execute_command(
         &self,
         params: lsp::ExecuteCommandParams,
     ) -> RpcResult<Option<serde_json::Value>> {
+        if self.read_state().disabled_capabilities().execute_commands
+        {
+            return Err(
+                LspError::InvalidCommand(params.command).into()
+            );
+        }
         if params.arguments.len() > 1
             || (params.arguments.len() == 1
                 && !params.arguments[0].is_object())
@@ -1476,18 +5458,14 @@ impl LanguageServer for LspServer {
         }
         match LspServerCommand::try_from(params.command.clone()) {
             Ok(LspServerCommand::CompositionInitialize) => {
-                let command_params: CompositionInitializeParams =
-                    match serde_json::value::from_value(
-                        params.arguments[0].clone(),
-                    ) {
-                        Ok(value) => value,
-                        Err(err) => {
-                            return Err(LspError::InternalError(
-                                format!("{:?}", err),
-                            )
-                            .into())
-                        }
-                    };
+                let command_params =
+                    command_params::<
+                        protocol_ext::CompositionInitialize,
+                    >(&params.arguments)?;
+                self.check_document_version(
+                    &command_params.text_document.uri,
+                    command_params.version,
+                )?;
 
                 let file = self.store.get_ast_file(
                     &command_params.text_document.uri,
@@ -1529,7 +5507,7 @@ impl LanguageServer for LspServer {
                         actions: Some(vec![range_action_item]),
                     };
                     if let Some(client) = self.get_client() {
-                        client.send_custom_notification::<ClientCommandNotification>(params).await;
+                        send_client_command(client, params).await;
                     };
                 } else {
                     let edit = lsp::WorkspaceEdit {
@@ -1544,78 +5522,79 @@ impl LanguageServer for LspServer {
                                                 .text_document
                                                 .uri,
                                         )?;
-                                    file.base.location.into()
+                                    convert::location_to_range(
+                                        &file.base.location,
+                                    )
                                 },
                             }],
                         )])),
                         document_changes: None,
                         change_annotations: None,
                     };
-                    if let Some(client) = self.get_client() {
-                        let edit_applied =
-                            client.apply_edit(edit, None).await;
-                        if edit_applied.is_err() {
-                            let params =
-                                    lsp::ShowMessageRequestParams {
-                                        typ: lsp::MessageType::ERROR,
-                                        message: LspClientCommand::ExecuteCommandFailed.to_string(),
-                                        actions: None,
-                                    };
-                            client.send_custom_notification::<ClientCommandNotification>(params).await;
-                        }
+                    let previous = {
+                        let mut state = self.write_state();
+                        let previous = state
+                            .get_mut_composition(
+                                &command_params.text_document.uri,
+                            )
+                            .cloned();
+                        state.set_composition(
+                            command_params.text_document.uri.clone(),
+                            composition,
+                        );
+                        previous
                     };
+                    return self
+                        .apply_composition_edit(
+                            &command_params.text_document.uri,
+                            edit,
+                            previous,
+                            command_params.apply,
+                        )
+                        .await;
                 }
 
-                match self.state.lock() {
-                    Ok(mut state) => state.set_composition(
-                        command_params.text_document.uri,
-                        composition,
-                    ),
-                    Err(err) => panic!("{}", err),
-                }
-                Ok(None)
-            }
-            Ok(LspServerCommand::SetMeasurementFilter) => {
-                let command_params: ValueFilterParams =
-                    match serde_json::value::from_value(
-                        params.arguments[0].clone(),
-                    ) {
-                        Ok(value) => value,
-                        Err(err) => {
-                            return Err(LspError::InternalError(
-                                format!("{:?}", err),
-                            )
-                            .into())
-                        }
-                    };
+                self.write_state().set_composition(
+                    command_params.text_document.uri,
+                    composition,
+                );
+                Ok(None)
+            }
+            Ok(LspServerCommand::SetMeasurementFilter) => {
+                let command_params =
+                    command_params::<
+                        protocol_ext::SetMeasurementFilter,
+                    >(&params.arguments)?;
+                self.check_document_version(
+                    &command_params.text_document.uri,
+                    command_params.version,
+                )?;
 
-                let composition_text = match self.state.lock() {
-                    Ok(mut state) => match state.get_mut_composition(
+                let (previous, composition_text) = match self
+                    .write_state()
+                    .get_mut_composition(
                         &command_params.text_document.uri,
                     ) {
-                        Some(composition) => {
-                            if composition
-                                .set_measurement(command_params.value)
-                                .is_err()
-                            {
-                                return Err(LspError::InternalError(
+                    Some(composition) => {
+                        let previous = composition.clone();
+                        if composition
+                            .set_measurement(command_params.value)
+                            .is_err()
+                        {
+                            return Err(LspError::InternalError(
                                     "Failed to set measurement to composition."
                                         .to_string(),
                                 )
                                 .into());
-                            }
-                            composition.to_string()
-                        }
-                        None => {
-                            return Err(
-                                LspError::CompositionNotFound(
-                                    command_params.text_document.uri,
-                                )
-                                .into(),
-                            )
                         }
-                    },
-                    Err(err) => panic!("{}", err),
+                        (previous, composition.to_string())
+                    }
+                    None => {
+                        return Err(LspError::CompositionNotFound(
+                            command_params.text_document.uri,
+                        )
+                        .into())
+                    }
                 };
 
                 let edit = lsp::WorkspaceEdit {
@@ -1629,7 +5608,9 @@ impl LanguageServer for LspServer {
                                 let file = self.store.get_ast_file(
                                     &command_params.text_document.uri,
                                 )?;
-                                file.base.location.into()
+                                convert::location_to_range(
+                                    &file.base.location,
+                                )
                             },
                         }],
                     )])),
@@ -1637,63 +5618,49 @@ impl LanguageServer for LspServer {
                     change_annotations: None,
                 };
 
-                if let Some(client) = self.get_client() {
-                    let edit_applied =
-                        client.apply_edit(edit, None).await;
-                    if edit_applied.is_err() {
-                        let params = lsp::ShowMessageRequestParams {
-                            typ: lsp::MessageType::ERROR,
-                            message:
-                                LspClientCommand::ExecuteCommandFailed
-                                    .to_string(),
-                            actions: None,
-                        };
-                        client.send_custom_notification::<ClientCommandNotification>(params).await;
-                    }
-                };
-                Ok(None)
+                self.apply_composition_edit(
+                    &command_params.text_document.uri,
+                    edit,
+                    Some(previous),
+                    command_params.apply,
+                )
+                .await
             }
             Ok(LspServerCommand::AddFieldFilter) => {
-                let command_params: ValueFilterParams =
-                    match serde_json::value::from_value(
-                        params.arguments[0].clone(),
-                    ) {
-                        Ok(value) => value,
-                        Err(err) => {
-                            return Err(LspError::InternalError(
-                                format!("{:?}", err),
-                            )
-                            .into())
-                        }
-                    };
+                let command_params =
+                    command_params::<protocol_ext::AddFieldFilter>(
+                        &params.arguments,
+                    )?;
+                self.check_document_version(
+                    &command_params.text_document.uri,
+                    command_params.version,
+                )?;
 
-                let composition_text = match self.state.lock() {
-                    Ok(mut state) => match state.get_mut_composition(
+                let (previous, composition_text) = match self
+                    .write_state()
+                    .get_mut_composition(
                         &command_params.text_document.uri,
                     ) {
-                        Some(composition) => {
-                            if composition
-                                .add_field(command_params.value)
-                                .is_err()
-                            {
-                                return Err(LspError::InternalError(
-                        "Failed to add field to composition."
-                            .to_string(),
-                    )
-                    .into());
-                            }
-                            composition.to_string().clone()
-                        }
-                        None => {
-                            return Err(
-                                LspError::CompositionNotFound(
-                                    command_params.text_document.uri,
-                                )
-                                .into(),
+                    Some(composition) => {
+                        let previous = composition.clone();
+                        if composition
+                            .add_field(command_params.value)
+                            .is_err()
+                        {
+                            return Err(LspError::InternalError(
+                                "Failed to add field to composition."
+                                    .to_string(),
                             )
+                            .into());
                         }
-                    },
-                    Err(err) => panic!("{}", err),
+                        (previous, composition.to_string())
+                    }
+                    None => {
+                        return Err(LspError::CompositionNotFound(
+                            command_params.text_document.uri,
+                        )
+                        .into())
+                    }
                 };
 
                 let edit = lsp::WorkspaceEdit {
@@ -1707,7 +5674,9 @@ impl LanguageServer for LspServer {
                                 let file = self.store.get_ast_file(
                                     &command_params.text_document.uri,
                                 )?;
-                                file.base.location.into()
+                                convert::location_to_range(
+                                    &file.base.location,
+                                )
                             },
                         }],
                     )])),
@@ -1715,63 +5684,50 @@ impl LanguageServer for LspServer {
                     change_annotations: None,
                 };
 
-                if let Some(client) = self.get_client() {
-                    let edit_applied =
-                        client.apply_edit(edit, None).await;
-                    if edit_applied.is_err() {
-                        let params = lsp::ShowMessageRequestParams {
-                            typ: lsp::MessageType::ERROR,
-                            message:
-                                LspClientCommand::ExecuteCommandFailed
-                                    .to_string(),
-                            actions: None,
-                        };
-                        client.send_custom_notification::<ClientCommandNotification>(params).await;
-                    }
-                };
-                Ok(None)
+                self.apply_composition_edit(
+                    &command_params.text_document.uri,
+                    edit,
+                    Some(previous),
+                    command_params.apply,
+                )
+                .await
             }
             Ok(LspServerCommand::RemoveFieldFilter) => {
-                let command_params: ValueFilterParams =
-                    match serde_json::value::from_value(
-                        params.arguments[0].clone(),
-                    ) {
-                        Ok(value) => value,
-                        Err(err) => {
-                            return Err(LspError::InternalError(
-                                format!("{:?}", err),
-                            )
-                            .into())
-                        }
-                    };
+                let command_params = command_params::<
+                    protocol_ext::RemoveFieldFilter,
+                >(
+                    &params.arguments
+                )?;
+                self.check_document_version(
+                    &command_params.text_document.uri,
+                    command_params.version,
+                )?;
 
-                let composition_text = match self.state.lock() {
-                    Ok(mut state) => match state.get_mut_composition(
+                let (previous, composition_text) = match self
+                    .write_state()
+                    .get_mut_composition(
                         &command_params.text_document.uri,
                     ) {
-                        Some(composition) => {
-                            if composition
-                                .remove_field(command_params.value)
-                                .is_err()
-                            {
-                                return Err(LspError::InternalError(
+                    Some(composition) => {
+                        let previous = composition.clone();
+                        if composition
+                            .remove_field(command_params.value)
+                            .is_err()
+                        {
+                            return Err(LspError::InternalError(
                         "Failed to remove field from composition."
                             .to_string(),
                     )
                     .into());
-                            }
-                            composition.to_string().clone()
-                        }
-                        None => {
-                            return Err(
-                                LspError::CompositionNotFound(
-                                    command_params.text_document.uri,
-                                )
-                                .into(),
-                            )
                         }
-                    },
-                    Err(err) => panic!("{}", err),
+                        (previous, composition.to_string())
+                    }
+                    None => {
+                        return Err(LspError::CompositionNotFound(
+                            command_params.text_document.uri,
+                        )
+                        .into())
+                    }
                 };
 
                 let edit = lsp::WorkspaceEdit {
@@ -1785,7 +5741,9 @@ impl LanguageServer for LspServer {
                                 let file = self.store.get_ast_file(
                                     &command_params.text_document.uri,
                                 )?;
-                                file.base.location.into()
+                                convert::location_to_range(
+                                    &file.base.location,
+                                )
                             },
                         }],
                     )])),
@@ -1793,66 +5751,53 @@ impl LanguageServer for LspServer {
                     change_annotations: None,
                 };
 
-                if let Some(client) = self.get_client() {
-                    let edit_applied =
-                        client.apply_edit(edit, None).await;
-                    if edit_applied.is_err() {
-                        let params = lsp::ShowMessageRequestParams {
-                            typ: lsp::MessageType::ERROR,
-                            message:
-                                LspClientCommand::ExecuteCommandFailed
-                                    .to_string(),
-                            actions: None,
-                        };
-                        client.send_custom_notification::<ClientCommandNotification>(params).await;
-                    }
-                };
-                Ok(None)
+                self.apply_composition_edit(
+                    &command_params.text_document.uri,
+                    edit,
+                    Some(previous),
+                    command_params.apply,
+                )
+                .await
             }
             Ok(LspServerCommand::AddTagValueFilter) => {
-                let command_params: TagValueFilterParams =
-                    match serde_json::value::from_value(
-                        params.arguments[0].clone(),
-                    ) {
-                        Ok(value) => value,
-                        Err(err) => {
-                            return Err(LspError::InternalError(
-                                format!("{:?}", err),
-                            )
-                            .into())
-                        }
-                    };
+                let command_params = command_params::<
+                    protocol_ext::AddTagValueFilter,
+                >(
+                    &params.arguments
+                )?;
+                self.check_document_version(
+                    &command_params.text_document.uri,
+                    command_params.version,
+                )?;
 
-                let composition_text = match self.state.lock() {
-                    Ok(mut state) => match state.get_mut_composition(
+                let (previous, composition_text) = match self
+                    .write_state()
+                    .get_mut_composition(
                         &command_params.text_document.uri,
                     ) {
-                        Some(composition) => {
-                            if composition
-                                .add_tag_value(
-                                    command_params.tag,
-                                    command_params.value,
-                                )
-                                .is_err()
-                            {
-                                return Err(LspError::InternalError(
+                    Some(composition) => {
+                        let previous = composition.clone();
+                        if composition
+                            .add_tag_value(
+                                command_params.tag,
+                                command_params.value,
+                            )
+                            .is_err()
+                        {
+                            return Err(LspError::InternalError(
                         "Failed to add tagValue to composition."
                             .to_string(),
                     )
                     .into());
-                            }
-                            composition.to_string().clone()
-                        }
-                        None => {
-                            return Err(
-                                LspError::CompositionNotFound(
-                                    command_params.text_document.uri,
-                                )
-                                .into(),
-                            )
                         }
-                    },
-                    Err(err) => panic!("{}", err),
+                        (previous, composition.to_string())
+                    }
+                    None => {
+                        return Err(LspError::CompositionNotFound(
+                            command_params.text_document.uri,
+                        )
+                        .into())
+                    }
                 };
 
                 let edit = lsp::WorkspaceEdit {
@@ -1866,7 +5811,9 @@ impl LanguageServer for LspServer {
                                 let file = self.store.get_ast_file(
                                     &command_params.text_document.uri,
                                 )?;
-                                file.base.location.into()
+                                convert::location_to_range(
+                                    &file.base.location,
+                                )
                             },
                         }],
                     )])),
@@ -1874,66 +5821,52 @@ impl LanguageServer for LspServer {
                     change_annotations: None,
                 };
 
-                if let Some(client) = self.get_client() {
-                    let edit_applied =
-                        client.apply_edit(edit, None).await;
-                    if edit_applied.is_err() {
-                        let params = lsp::ShowMessageRequestParams {
-                            typ: lsp::MessageType::ERROR,
-                            message:
-                                LspClientCommand::ExecuteCommandFailed
-                                    .to_string(),
-                            actions: None,
-                        };
-                        client.send_custom_notification::<ClientCommandNotification>(params).await;
-                    }
-                };
-                Ok(None)
+                self.apply_composition_edit(
+                    &command_params.text_document.uri,
+                    edit,
+                    Some(previous),
+                    command_params.apply,
+                )
+                .await
             }
             Ok(LspServerCommand::RemoveTagValueFilter) => {
-                let command_params: TagValueFilterParams =
-                    match serde_json::value::from_value(
-                        params.arguments[0].clone(),
-                    ) {
-                        Ok(value) => value,
-                        Err(err) => {
-                            return Err(LspError::InternalError(
-                                format!("{:?}", err),
-                            )
-                            .into())
-                        }
-                    };
+                let command_params =
+                    command_params::<
+                        protocol_ext::RemoveTagValueFilter,
+                    >(&params.arguments)?;
+                self.check_document_version(
+                    &command_params.text_document.uri,
+                    command_params.version,
+                )?;
 
-                let composition_text = match self.state.lock() {
-                    Ok(mut state) => match state.get_mut_composition(
+                let (previous, composition_text) = match self
+                    .write_state()
+                    .get_mut_composition(
                         &command_params.text_document.uri,
                     ) {
-                        Some(composition) => {
-                            if composition
-                                .remove_tag_value(
-                                    command_params.tag,
-                                    command_params.value,
-                                )
-                                .is_err()
-                            {
-                                return Err(LspError::InternalError(
+                    Some(composition) => {
+                        let previous = composition.clone();
+                        if composition
+                            .remove_tag_value(
+                                command_params.tag,
+                                command_params.value,
+                            )
+                            .is_err()
+                        {
+                            return Err(LspError::InternalError(
                                     "Failed to remove tagValue from composition."
                                         .to_string(),
                                 )
                                 .into());
-                            }
-                            composition.to_string().clone()
-                        }
-                        None => {
-                            return Err(
-                                LspError::CompositionNotFound(
-                                    command_params.text_document.uri,
-                                )
-                                .into(),
-                            )
                         }
-                    },
-                    Err(err) => panic!("{}", err),
+                        (previous, composition.to_string())
+                    }
+                    None => {
+                        return Err(LspError::CompositionNotFound(
+                            command_params.text_document.uri,
+                        )
+                        .into())
+                    }
                 };
 
                 let edit = lsp::WorkspaceEdit {
@@ -1947,7 +5880,9 @@ impl LanguageServer for LspServer {
                                 let file = self.store.get_ast_file(
                                     &command_params.text_document.uri,
                                 )?;
-                                file.base.location.into()
+                                convert::location_to_range(
+                                    &file.base.location,
+                                )
                             },
                         }],
                     )])),
@@ -1955,21 +5890,13 @@ impl LanguageServer for LspServer {
                     change_annotations: None,
                 };
 
-                if let Some(client) = self.get_client() {
-                    let edit_applied =
-                        client.apply_edit(edit, None).await;
-                    if edit_applied.is_err() {
-                        let params = lsp::ShowMessageRequestParams {
-                            typ: lsp::MessageType::ERROR,
-                            message:
-                                LspClientCommand::ExecuteCommandFailed
-                                    .to_string(),
-                            actions: None,
-                        };
-                        client.send_custom_notification::<ClientCommandNotification>(params).await;
-                    }
-                };
-                Ok(None)
+                self.apply_composition_edit(
+                    &command_params.text_document.uri,
+                    edit,
+                    Some(previous),
+                    command_params.apply,
+                )
+                .await
             }
             Ok(LspServerCommand::GetFunctionList) => Ok(Some(
                 lang::UNIVERSE
@@ -1978,6 +5905,180 @@ impl LanguageServer for LspServer {
                     .map(|function| function.name.clone())
                     .collect(),
             )),
+            Ok(LspServerCommand::GetSnippets) => {
+                match serde_json::to_value(snippets::SNIPPETS) {
+                    Ok(value) => Ok(Some(value)),
+                    Err(err) => {
+                        Err(LspError::InternalError(err.to_string())
+                            .into())
+                    }
+                }
+            }
+            Ok(LspServerCommand::GetLintRules) => {
+                match serde_json::to_value(
+                    crate::diagnostics::LINT_RULES,
+                ) {
+                    Ok(value) => Ok(Some(value)),
+                    Err(err) => {
+                        Err(LspError::InternalError(err.to_string())
+                            .into())
+                    }
+                }
+            }
+            Ok(LspServerCommand::GetCommandSchemas) => {
+                let schemas: std::collections::BTreeMap<
+                    String,
+                    serde_json::Value,
+                > =
+                    LspServerCommand::iter()
+                        .map(|command| {
+                            (command.into(), command.params_schema())
+                        })
+                        .collect();
+                match serde_json::to_value(schemas) {
+                    Ok(value) => Ok(Some(value)),
+                    Err(err) => {
+                        Err(LspError::InternalError(err.to_string())
+                            .into())
+                    }
+                }
+            }
+            Ok(LspServerCommand::MovePipelineStage) => {
+                let command_params = command_params::<
+                    protocol_ext::MovePipelineStage,
+                >(
+                    &params.arguments
+                )?;
+
+                let edit =
+                    self.move_pipeline_stage(command_params)?;
+                match serde_json::to_value(crate::lsp::sorted_workspace_edit(edit)) {
+                    Ok(value) => Ok(Some(value)),
+                    Err(err) => {
+                        Err(LspError::InternalError(err.to_string())
+                            .into())
+                    }
+                }
+            }
+            Ok(LspServerCommand::RemovePipelineStage) => {
+                let command_params =
+                    command_params::<
+                        protocol_ext::RemovePipelineStage,
+                    >(&params.arguments)?;
+
+                let edit =
+                    self.remove_pipeline_stage(command_params)?;
+                match serde_json::to_value(crate::lsp::sorted_workspace_edit(edit)) {
+                    Ok(value) => Ok(Some(value)),
+                    Err(err) => {
+                        Err(LspError::InternalError(err.to_string())
+                            .into())
+                    }
+                }
+            }
+            Ok(LspServerCommand::SetCallArgument) => {
+                let command_params =
+                    command_params::<protocol_ext::SetCallArgument>(
+                        &params.arguments,
+                    )?;
+
+                let edit = self.set_call_argument(command_params)?;
+                match serde_json::to_value(crate::lsp::sorted_workspace_edit(edit)) {
+                    Ok(value) => Ok(Some(value)),
+                    Err(err) => {
+                        Err(LspError::InternalError(err.to_string())
+                            .into())
+                    }
+                }
+            }
+            Ok(LspServerCommand::DiscoverTests) => {
+                let command_params =
+                    command_params::<protocol_ext::DiscoverTests>(
+                        &params.arguments,
+                    )?;
+
+                let tests = self
+                    .discover_tests(&command_params.text_document.uri);
+                match serde_json::to_value(tests) {
+                    Ok(value) => Ok(Some(value)),
+                    Err(err) => {
+                        Err(LspError::InternalError(err.to_string())
+                            .into())
+                    }
+                }
+            }
+            #[cfg(feature = "cmd")]
+            Ok(LspServerCommand::RunTests) => {
+                let command_params =
+                    command_params::<protocol_ext::RunTests>(
+                        &params.arguments,
+                    )?;
+
+                let results = self.run_tests(command_params).await?;
+                match serde_json::to_value(results) {
+                    Ok(value) => Ok(Some(value)),
+                    Err(err) => {
+                        Err(LspError::InternalError(err.to_string())
+                            .into())
+                    }
+                }
+            }
+            #[cfg(not(feature = "cmd"))]
+            Ok(LspServerCommand::RunTests) => Err(LspError::InternalError(
+                "Tests can only be run by native builds of the server"
+                    .into(),
+            )
+            .into()),
+            Ok(LspServerCommand::FindBucketReferences) => {
+                let command_params = command_params::<
+                    protocol_ext::FindBucketReferences,
+                >(
+                    &params.arguments
+                )?;
+
+                let locations = self
+                    .find_bucket_references(&command_params.bucket);
+                match serde_json::to_value(locations) {
+                    Ok(value) => Ok(Some(value)),
+                    Err(err) => {
+                        Err(LspError::InternalError(err.to_string())
+                            .into())
+                    }
+                }
+            }
+            Ok(LspServerCommand::DebugDocumentState) => {
+                let command_params = command_params::<
+                    protocol_ext::DebugDocumentState,
+                >(
+                    &params.arguments
+                )?;
+
+                let state = self.document_state(
+                    &command_params.text_document.uri,
+                )?;
+                match serde_json::to_value(state) {
+                    Ok(value) => Ok(Some(value)),
+                    Err(err) => {
+                        Err(LspError::InternalError(err.to_string())
+                            .into())
+                    }
+                }
+            }
+            Ok(LspServerCommand::RenameBucket) => {
+                let command_params =
+                    command_params::<protocol_ext::RenameBucket>(
+                        &params.arguments,
+                    )?;
+
+                let edit = self.rename_bucket(command_params)?;
+                match serde_json::to_value(crate::lsp::sorted_workspace_edit(edit)) {
+                    Ok(value) => Ok(Some(value)),
+                    Err(err) => {
+                        Err(LspError::InternalError(err.to_string())
+                            .into())
+                    }
+                }
+            }
             Err(_err) => {
                 return Err(
                     LspError::InvalidCommand(params.command).into()
@@ -1985,6 +6086,135 @@ impl LanguageServer for LspServer {
             }
         }
     }
+
+    /// Handle the requests and notifications of the protocol extension sent by the
+    /// client.
+    async fn request_else(
+        &self,
+        method: &str,
+        params: Option<serde_json::Value>,
+    ) -> RpcResult<Option<serde_json::Value>> {
+        match method {
+            UpdateSchemaNotification::METHOD => {
+                let schema: Schema = serde_json::from_value(
+                    params.unwrap_or_default(),
+                )
+                .map_err(|err| {
+                    LspError::InternalError(format!("{:?}", err))
+                })?;
+                self.write_state().set_schema(schema);
+                Ok(None)
+            }
+            UpdateSecretsNotification::METHOD => {
+                let secrets: SecretKeys = serde_json::from_value(
+                    params.unwrap_or_default(),
+                )
+                .map_err(|err| {
+                    LspError::InternalError(format!("{:?}", err))
+                })?;
+                self.write_state().set_secret_keys(secrets.keys);
+                Ok(None)
+            }
+            InlineValueRequest::METHOD => {
+                let params: InlineValueParams =
+                    serde_json::from_value(
+                        params.unwrap_or_default(),
+                    )
+                    .map_err(|err| {
+                        LspError::InternalError(format!("{:?}", err))
+                    })?;
+                let values = self.inline_values(params)?;
+                match serde_json::to_value(values) {
+                    Ok(value) => Ok(Some(value)),
+                    Err(err) => {
+                        Err(LspError::InternalError(err.to_string())
+                            .into())
+                    }
+                }
+            }
+            PipelineHeadersRequest::METHOD => {
+                let params: PipelineHeadersParams =
+                    serde_json::from_value(
+                        params.unwrap_or_default(),
+                    )
+                    .map_err(|err| {
+                        LspError::InternalError(format!("{:?}", err))
+                    })?;
+                let file = self
+                    .store
+                    .get_ast_file(&params.text_document.uri)?;
+                let headers: Vec<PipelineHeader> =
+                    crate::sticky::pipeline_headers(&file)
+                        .into_iter()
+                        .map(|(range, header)| PipelineHeader {
+                            range,
+                            header,
+                        })
+                        .collect();
+                match serde_json::to_value(headers) {
+                    Ok(value) => Ok(Some(value)),
+                    Err(err) => {
+                        Err(LspError::InternalError(err.to_string())
+                            .into())
+                    }
+                }
+            }
+            _ => Err(lspower::jsonrpc::Error::method_not_found()),
+        }
+    }
+}
+
+/// The schema of the annotated CSV of the innermost `csv.from` call enclosing a node.
+fn csv_table_schemas(
+    path: &[walk::Node<'_>],
+) -> Vec<crate::annotated_csv::TableSchema> {
+    path.iter()
+        .rev()
+        .find_map(|node| match node {
+            walk::Node::CallExpr(call) => match &call.callee {
+                SemanticExpression::Member(member)
+                    if member.property.as_str() == "from"
+                        && matches!(&member.object, SemanticExpression::Identifier(ident) if ident.name == "csv") =>
+                {
+                    call.arguments
+                        .iter()
+                        .find(|argument| argument.key.name == "csv")
+                        .and_then(|argument| match &argument.value {
+                            SemanticExpression::StringLit(lit) => Some(
+                                crate::annotated_csv::table_schemas(
+                                    &lit.value,
+                                ),
+                            ),
+                            _ => None,
+                        })
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+/// A completion item replacing the string literal at `range` with a quoted value.
+fn quoted_value_item(
+    value: &str,
+    detail: &str,
+    range: lsp::Range,
+) -> lsp::CompletionItem {
+    let text = format!("\"{}\"", escape_string_literal(value));
+    lsp::CompletionItem {
+        label: value.to_string(),
+        detail: Some(detail.into()),
+        filter_text: Some(text.clone()),
+        text_edit: Some(lsp::CompletionTextEdit::Edit(
+            lsp::TextEdit {
+                range,
+                new_text: text,
+            },
+        )),
+        kind: Some(lsp::CompletionItemKind::VALUE),
+        ..lsp::CompletionItem::default()
+    }
 }
 
 // `MonoType`'s extracted from a `Node` in a semantic graph do not contain the constraints directly
@@ -0,0 +1,19 @@
+/// Hooks for embedders to observe the documents of a server
+///
+/// Tooling built on top of this crate (e.g. query catalogs) registers a
+/// `DocumentObserver` with `LspServer::with_observer` instead of tracking and parsing
+/// documents itself. Observers are called once the server has stored the new
+/// contents of a document, with the AST of its file.
+use flux::ast;
+use lspower::lsp;
+
+pub trait DocumentObserver: Send + Sync {
+    /// A document was opened.
+    fn opened(&self, _uri: &lsp::Url, _file: &ast::File) {}
+
+    /// The contents of a document changed.
+    fn changed(&self, _uri: &lsp::Url, _file: &ast::File) {}
+
+    /// A document was closed.
+    fn closed(&self, _uri: &lsp::Url) {}
+}
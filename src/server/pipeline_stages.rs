@@ -0,0 +1,239 @@
+/// The commands editing the stages of a pipeline
+///
+/// Stages are counted from the head of the pipeline, which is stage 0 and is never
+/// moved or removed.
+use std::collections::HashMap;
+
+use flux::ast::{self, Expression as AstExpression};
+use flux::semantic::nodes::Package as SemanticPackage;
+use lspower::lsp;
+
+use super::protocol_ext::{
+    MovePipelineStageParams, RemovePipelineStageParams,
+    StageDirection,
+};
+use super::types::LspError;
+use super::{pipeline_at, stream_schema, LspServer};
+use crate::{convert, visitors::semantic};
+
+impl LspServer {
+    /// Swap a stage of a pipeline with the stage before or after it, if that is safe.
+    ///
+    /// The analyzer decides whether a move is safe: the package must still analyze
+    /// without errors, and the records coming out of the pipeline must have the same
+    /// columns, of the same types. Stages writing data (`to`, `yield`) are never moved.
+    /// Changes to the values of columns, e.g. from moving a `filter` across a `map`,
+    /// are not detected.
+    pub(super) fn move_pipeline_stage(
+        &self,
+        params: MovePipelineStageParams,
+    ) -> Result<lsp::WorkspaceEdit, LspError> {
+        let uri = &params.text_document.uri;
+        let source = self.store.get(uri)?;
+        let file = self.store.get_ast_file(uri)?;
+        let pipes =
+            pipeline_at(&file, params.position).ok_or_else(|| {
+                LspError::UnsafeStageMove(
+                    "there is no pipeline at the given position"
+                        .into(),
+                )
+            })?;
+
+        let (first, second, direction) = match params.direction {
+            StageDirection::Up => {
+                (params.stage.saturating_sub(1), params.stage, "up")
+            }
+            StageDirection::Down => {
+                (params.stage, params.stage + 1, "down")
+            }
+        };
+        if first == 0 || second > pipes.len() {
+            return Err(LspError::UnsafeStageMove(format!(
+                "stage {} can't be moved {}",
+                params.stage, direction
+            )));
+        }
+        let calls = [&pipes[first - 1].call, &pipes[second - 1].call];
+        for call in calls {
+            if let AstExpression::Identifier(callee) = &call.callee {
+                if callee.name == "to" || callee.name == "yield" {
+                    return Err(LspError::UnsafeStageMove(format!(
+                        "`{}` writes data, so it stays where it is",
+                        callee.name
+                    )));
+                }
+            }
+        }
+
+        let ranges = calls.map(|call| {
+            convert::byte_offset(&source, &call.base.location.start)
+                ..convert::byte_offset(
+                    &source,
+                    &call.base.location.end,
+                )
+        });
+        let texts = match (
+            source.get(ranges[0].clone()),
+            source.get(ranges[1].clone()),
+        ) {
+            (Some(first), Some(second)) => [first, second],
+            _ => {
+                return Err(LspError::InternalError(
+                    "Pipeline stages out of bounds.".into(),
+                ))
+            }
+        };
+        let moved = format!(
+            "{}{}{}{}{}",
+            &source[..ranges[0].start],
+            texts[1],
+            &source[ranges[0].end..ranges[1].start],
+            texts[0],
+            &source[ranges[1].end..]
+        );
+
+        // The head of the pipeline doesn't move, so the same pipeline is found from it
+        // after the move.
+        let schema_of =
+            |pkg: &SemanticPackage, pipe: &ast::PipeExpr| {
+                let visitor = crate::walk_semantic_package!(
+                    semantic::PipedCallFinderVisitor::new(
+                        &pipe.call.base.location
+                    ),
+                    pkg
+                );
+                stream_schema(visitor.call?.typ.clone())
+            };
+        let head = convert::position_to_lsp(
+            &pipes[0].argument.base().location.start,
+        );
+        let before = schema_of(
+            &self.store.get_semantic_package(uri)?,
+            pipes[pipes.len() - 1],
+        );
+        let after_pkg =
+            self.store.analyze_with(uri, &moved).map_err(|_| {
+                LspError::UnsafeStageMove(
+                    "the package wouldn't analyze without errors"
+                        .into(),
+                )
+            })?;
+        let after_file =
+            flux::parser::parse_string(file.name.clone(), &moved);
+        let after =
+            pipeline_at(&after_file, head).and_then(|pipes| {
+                schema_of(&after_pkg, pipes[pipes.len() - 1])
+            });
+        match (before, after) {
+            (Some(before), Some(after))
+                if before.same_columns(&after) => {}
+            (None, None) => {}
+            _ => {
+                return Err(LspError::UnsafeStageMove(
+                    "the pipeline would return different columns"
+                        .into(),
+                ))
+            }
+        }
+
+        Ok(lsp::WorkspaceEdit {
+            changes: Some(HashMap::from([(
+                uri.clone(),
+                vec![
+                    lsp::TextEdit {
+                        range: convert::location_to_range(
+                            &calls[0].base.location,
+                        ),
+                        new_text: texts[1].to_string(),
+                    },
+                    lsp::TextEdit {
+                        range: convert::location_to_range(
+                            &calls[1].base.location,
+                        ),
+                        new_text: texts[0].to_string(),
+                    },
+                ],
+            )])),
+            document_changes: None,
+            change_annotations: None,
+        })
+    }
+
+    /// Remove a stage of a pipeline, along with its pipe operator and the whitespace
+    /// and comments before it. A comment trailing the stage on its line goes too.
+    pub(super) fn remove_pipeline_stage(
+        &self,
+        params: RemovePipelineStageParams,
+    ) -> Result<lsp::WorkspaceEdit, LspError> {
+        let uri = &params.text_document.uri;
+        let source = self.store.get(uri)?;
+        let file = self.store.get_ast_file(uri)?;
+        let not_found = || {
+            LspError::InvalidArguments(vec![serde_json::json!({
+                "range": params.range,
+                "position": params.position,
+                "stage": params.stage,
+            })])
+        };
+
+        let (pipes, stage) =
+            match (params.range, params.position, params.stage) {
+                (Some(range), _, _) => {
+                    let pipes = pipeline_at(&file, range.start)
+                        .ok_or_else(not_found)?;
+                    let stage = pipes
+                        .iter()
+                        .position(|pipe| {
+                            convert::location_contains(
+                                &pipe.call.base.location,
+                                &range.start,
+                            )
+                        })
+                        .ok_or_else(not_found)?;
+                    (pipes, stage + 1)
+                }
+                (None, Some(position), Some(stage)) => (
+                    pipeline_at(&file, position)
+                        .ok_or_else(not_found)?,
+                    stage,
+                ),
+                _ => return Err(not_found()),
+            };
+        // The head of the pipeline is what the other stages read from.
+        if stage == 0 || stage > pipes.len() {
+            return Err(not_found());
+        }
+        let pipe = pipes[stage - 1];
+
+        // Everything from the end of the previous stage to the end of this one goes.
+        let start = convert::position_to_lsp(
+            &pipe.argument.base().location.end,
+        );
+        let mut end =
+            convert::position_to_lsp(&pipe.call.base.location.end);
+        let rest = source
+            .get(
+                convert::byte_offset(
+                    &source,
+                    &pipe.call.base.location.end,
+                )..,
+            )
+            .unwrap_or_default();
+        let rest = rest.split('\n').next().unwrap_or_default();
+        if rest.trim_start().starts_with("//") {
+            end.character += rest.trim_end().chars().count() as u32;
+        }
+
+        Ok(lsp::WorkspaceEdit {
+            changes: Some(HashMap::from([(
+                uri.clone(),
+                vec![lsp::TextEdit {
+                    range: lsp::Range { start, end },
+                    new_text: String::new(),
+                }],
+            )])),
+            document_changes: None,
+            change_annotations: None,
+        })
+    }
+}
@@ -0,0 +1,640 @@
+/// The extensions to the language server protocol specific to flux.
+///
+/// Every custom command and notification, along with the types of their params, is
+/// declared here, so that clients (the InfluxDB UI, editor plugins) have a single
+/// place to track them. The extension is versioned with `PROTOCOL_VERSION`, which
+/// clients negotiate through the `protocolVersion` initialization option.
+use std::collections::BTreeMap;
+
+use lspower::lsp::{
+    self, notification::Notification, request::Request,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use strum_macros::{Display, EnumIter};
+
+/// The version of the protocol extension. It is bumped whenever a command, a
+/// notification or their params change in a way that isn't backwards compatible.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The options clients may send in `initializationOptions`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializationOptions {
+    /// The newest version of the protocol extension the client understands.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<u32>,
+    /// Whether to provide the colors of hex color strings, for editors to show
+    /// swatches of.
+    #[serde(default)]
+    pub document_colors: bool,
+    /// The capabilities the client implements itself, which the server turns off.
+    #[serde(default)]
+    pub disabled_capabilities: DisabledCapabilities,
+}
+
+/// Capabilities of the server that can be turned off, for clients whose own
+/// implementation of them conflicts with the server's.
+///
+/// They are turned off by the `--disable-*` flags of the binary, by embedders with
+/// `LspServer::with_disabled_capabilities`, or by clients with the
+/// `disabledCapabilities` initialization option. A capability turned off by any of
+/// them is off.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct DisabledCapabilities {
+    #[serde(default)]
+    pub folding: bool,
+    #[serde(default)]
+    pub semantic_tokens: bool,
+    #[serde(default)]
+    pub code_actions: bool,
+    /// Commands, along with the code lenses running them.
+    #[serde(default)]
+    pub execute_commands: bool,
+}
+
+impl DisabledCapabilities {
+    /// The capabilities turned off by either.
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            folding: self.folding || other.folding,
+            semantic_tokens: self.semantic_tokens
+                || other.semantic_tokens,
+            code_actions: self.code_actions || other.code_actions,
+            execute_commands: self.execute_commands
+                || other.execute_commands,
+        }
+    }
+}
+
+impl InitializationOptions {
+    /// The version of the protocol extension both the client and server understand.
+    pub fn negotiated_version(&self) -> u32 {
+        self.protocol_version.map_or(PROTOCOL_VERSION, |version| {
+            version.min(PROTOCOL_VERSION)
+        })
+    }
+}
+
+/// The protocol extension spoken by the server, sent to clients under
+/// `experimental.fluxProtocol` in the server capabilities.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProtocolCapabilities {
+    pub version: u32,
+    pub commands: Vec<String>,
+    pub notifications: Vec<String>,
+    /// Requests of the protocol the server handles, but can't advertise in its
+    /// capabilities, as they are newer than the protocol types it is built with.
+    #[serde(default)]
+    pub requests: Vec<String>,
+}
+
+impl ProtocolCapabilities {
+    pub fn new(options: &InitializationOptions) -> Self {
+        use strum::IntoEnumIterator;
+
+        Self {
+            version: options.negotiated_version(),
+            commands: LspServerCommand::iter()
+                // Tests are run with the `flux` command line tool, which only native
+                // builds can spawn.
+                .filter(|command| {
+                    cfg!(feature = "cmd")
+                        || *command != LspServerCommand::RunTests
+                })
+                .filter(|_| {
+                    !options.disabled_capabilities.execute_commands
+                })
+                .map(|command| command.into())
+                .collect(),
+            notifications: [
+                AnalysisStatusNotification::METHOD,
+                UpdateSchemaNotification::METHOD,
+                UpdateSecretsNotification::METHOD,
+            ]
+            .into_iter()
+            // Only the wasm build catches panics to tell the client about.
+            .chain(
+                cfg!(feature = "wasm")
+                    .then(|| CrashedNotification::METHOD),
+            )
+            .map(String::from)
+            .collect(),
+            requests: vec![
+                InlineValueRequest::METHOD.into(),
+                PipelineHeadersRequest::METHOD.into(),
+            ],
+        }
+    }
+}
+
+/// A command executed through `workspace/executeCommand`, tied to the type of its
+/// params like `Notification` is for notifications.
+pub trait ServerCommand {
+    type Params: DeserializeOwned + Serialize;
+    const COMMAND: LspServerCommand;
+}
+
+/// Declare the commands executed by the server, along with their names and the types
+/// of their params.
+///
+/// Declaring all three in one place keeps them in sync: `LspServerCommand` and its
+/// conversions to and from names are generated, and params can only be parsed as the
+/// type declared for the command, through the type of the same name.
+macro_rules! server_commands {
+    ($($command:ident($params:ty) = $name:literal;)*) => {
+        #[derive(Clone, Copy, Debug, EnumIter, Eq, PartialEq)]
+        pub enum LspServerCommand {
+            $($command,)*
+        }
+
+        impl TryFrom<String> for LspServerCommand {
+            type Error = String;
+
+            fn try_from(value: String) -> Result<Self, Self::Error> {
+                match value.as_str() {
+                    $($name => Ok(LspServerCommand::$command),)*
+                    _ => Err(format!(
+                        "Received unknown value for LspServerCommand: {}",
+                        value
+                    )),
+                }
+            }
+        }
+
+        impl LspServerCommand {
+            /// The JSON schema of the params of the command.
+            pub fn params_schema(self) -> serde_json::Value {
+                match self {
+                    $(LspServerCommand::$command => {
+                        super::command_schema::params_schema::<$params>()
+                    })*
+                }
+            }
+        }
+
+        impl From<LspServerCommand> for String {
+            fn from(value: LspServerCommand) -> Self {
+                match value {
+                    $(LspServerCommand::$command => $name.into(),)*
+                }
+            }
+        }
+
+        $(
+            /// Implements `ServerCommand` for the command of the same name.
+            // Commands without params don't need theirs parsed.
+            #[allow(dead_code)]
+            pub struct $command;
+
+            impl ServerCommand for $command {
+                type Params = $params;
+                const COMMAND: LspServerCommand = LspServerCommand::$command;
+            }
+        )*
+    };
+}
+
+server_commands! {
+    CompositionInitialize(CompositionInitializeParams) = "fluxComposition/initialize";
+    SetMeasurementFilter(ValueFilterParams) = "fluxComposition/setMeasurementFilter";
+    AddFieldFilter(ValueFilterParams) = "fluxComposition/addFieldFilter";
+    RemoveFieldFilter(ValueFilterParams) = "fluxComposition/removeFieldFilter";
+    AddTagValueFilter(TagValueFilterParams) = "fluxComposition/addTagValueFilter";
+    RemoveTagValueFilter(TagValueFilterParams) = "fluxComposition/removeTagValueFilter";
+    GetFunctionList(()) = "getFunctionList";
+    GetSnippets(()) = "getSnippets";
+    GetLintRules(()) = "getLintRules";
+    GetCommandSchemas(()) = "getCommandSchemas";
+    MovePipelineStage(MovePipelineStageParams) = "movePipelineStage";
+    RemovePipelineStage(RemovePipelineStageParams) = "removePipelineStage";
+    SetCallArgument(SetCallArgumentParams) = "setCallArgument";
+    DiscoverTests(DiscoverTestsParams) = "discoverTests";
+    RunTests(RunTestsParams) = "runTests";
+    FindBucketReferences(FindBucketReferencesParams) = "findBucketReferences";
+    RenameBucket(RenameBucketParams) = "renameBucket";
+    DebugDocumentState(DebugDocumentStateParams) = "debugDocumentState";
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscoverTestsParams {
+    pub text_document: lsp::TextDocumentIdentifier,
+}
+
+/// A test of the package of a document, as found by the `discoverTests` command.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestCase {
+    pub name: String,
+    pub uri: lsp::Url,
+    pub range: lsp::Range,
+    /// The package a `testcase` extends the tests of.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunTestsParams {
+    pub text_document: lsp::TextDocumentIdentifier,
+    /// The tests to run, by name. Every test of the package is run when empty.
+    #[serde(default)]
+    pub names: Vec<String>,
+    /// Whether to keep the verbose output of the runs, e.g. to show the tables a
+    /// test produced.
+    #[serde(default)]
+    pub verbose: bool,
+}
+
+/// The outcome of a test run by the `runTests` command.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestResult {
+    pub name: String,
+    pub uri: lsp::Url,
+    pub passed: bool,
+    /// Why the test failed, located at the test.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diagnostic: Option<lsp::Diagnostic>,
+    /// The output of the run, when it was verbose.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FindBucketReferencesParams {
+    /// The name of the bucket, as written in `from` and `to` calls.
+    pub bucket: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameBucketParams {
+    pub bucket: String,
+    pub new_name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugDocumentStateParams {
+    pub text_document: lsp::TextDocumentIdentifier,
+}
+
+/// What the server knows of a document, as returned by the `debugDocumentState`
+/// command for triaging bug reports.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentState {
+    /// A hash of the contents the server has, to compare with those of the client.
+    pub contents_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>,
+    pub parse_errors: usize,
+    /// The errors of the analysis of the package, including parse errors.
+    pub semantic_errors: usize,
+    /// The documents of the package of the document.
+    pub package_files: Vec<lsp::Url>,
+    /// Whether the analysis of the package was cached, before this command.
+    pub analysis_cached: bool,
+    /// The bucket, measurement, fields and tag values of the composition of the
+    /// document, if it has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub composition: Option<serde_json::Value>,
+    /// How long the last analysis of the package took, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_analysis_millis: Option<u64>,
+}
+
+/// Tells the client whether the package of a document has been analyzed.
+///
+/// Sent with `ready: false` when analysis of a newly opened document starts, and
+/// with `ready: true` once it is done, at which point hover, completion, etc.
+/// are served without waiting on analysis.
+pub struct AnalysisStatusNotification;
+
+impl Notification for AnalysisStatusNotification {
+    type Params = AnalysisStatusParams;
+    const METHOD: &'static str = "flux/analysisStatus";
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalysisStatusParams {
+    pub text_document: lsp::TextDocumentIdentifier,
+    pub ready: bool,
+}
+
+/// Sent to the client when the server panics, after which it should be restarted.
+///
+/// Requests being handled when the server panicked are answered with internal
+/// errors, but notifications have no response to carry the error.
+pub struct CrashedNotification;
+
+impl Notification for CrashedNotification {
+    type Params = CrashedParams;
+    const METHOD: &'static str = "flux/crashed";
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrashedParams {
+    /// The message of the panic.
+    pub message: String,
+}
+
+/// Sent by the client with the schema of the data it can query, so that bucket,
+/// measurement and field names can be completed, described on hover and checked.
+///
+/// Every notification replaces the schema sent before it.
+pub struct UpdateSchemaNotification;
+
+impl Notification for UpdateSchemaNotification {
+    type Params = Schema;
+    const METHOD: &'static str = "flux/updateSchema";
+}
+
+/// The buckets the client can query, by name.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Schema {
+    #[serde(default)]
+    pub buckets: BTreeMap<String, BucketSchema>,
+}
+
+/// The measurements of a bucket, by name.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BucketSchema {
+    #[serde(default)]
+    pub measurements: BTreeMap<String, MeasurementSchema>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeasurementSchema {
+    #[serde(default)]
+    pub fields: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Sent by the client with the keys of the secrets it stores, so that the `key`
+/// argument of `secrets.get` can be completed and checked.
+///
+/// Every notification replaces the keys sent before it.
+pub struct UpdateSecretsNotification;
+
+impl Notification for UpdateSecretsNotification {
+    type Params = SecretKeys;
+    const METHOD: &'static str = "flux/updateSecrets";
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretKeys {
+    #[serde(default)]
+    pub keys: Vec<String>,
+}
+
+/// `textDocument/inlineValue`, from version 3.17 of the protocol.
+///
+/// The values of variables are shown inline by debuggers, which look them up by the
+/// names returned here.
+pub struct InlineValueRequest;
+
+impl Request for InlineValueRequest {
+    type Params = InlineValueParams;
+    type Result = Option<Vec<InlineValueVariableLookup>>;
+    const METHOD: &'static str = "textDocument/inlineValue";
+}
+
+/// Sent by the client for the pipelines of a document spanning several lines, along
+/// with the line each starts with, for editors to pin while scrolling through them.
+pub struct PipelineHeadersRequest;
+
+impl Request for PipelineHeadersRequest {
+    type Params = PipelineHeadersParams;
+    type Result = Vec<PipelineHeader>;
+    const METHOD: &'static str = "flux/pipelineHeaders";
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PipelineHeadersParams {
+    pub text_document: lsp::TextDocumentIdentifier,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PipelineHeader {
+    /// The statement of the pipeline.
+    pub range: lsp::Range,
+    /// The statement up to the end of the head of the pipeline, e.g.
+    /// `data = from(bucket: "a")`.
+    pub header: lsp::Range,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineValueParams {
+    pub text_document: lsp::TextDocumentIdentifier,
+    /// The range of the document visible in the editor.
+    pub range: lsp::Range,
+    pub context: InlineValueContext,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineValueContext {
+    /// The stack frame the debugger stopped in.
+    pub frame_id: i64,
+    /// Where the debugger stopped, past which values aren't computed yet.
+    pub stopped_location: lsp::Range,
+}
+
+/// An inline value looked up by the name of a variable, which is the only kind of
+/// inline value the server provides.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineValueVariableLookup {
+    pub range: lsp::Range,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variable_name: Option<String>,
+    pub case_sensitive_lookup: bool,
+}
+
+/// The messages of the `window/showMessageRequest` requests telling the client about
+/// the composition of a document.
+#[derive(Debug, Display)]
+pub enum LspClientCommand {
+    #[strum(serialize = "fluxComposition/compositionState")]
+    UpdateComposition,
+    #[strum(serialize = "fluxComposition/compositionEnded")]
+    CompositionDropped,
+    #[strum(serialize = "fluxComposition/compositionNotFound")]
+    CompositionNotFound,
+    #[strum(serialize = "fluxComposition/executeCommandFailed")]
+    ExecuteCommandFailed,
+    #[strum(serialize = "fluxComposition/alreadyInitialized")]
+    AlreadyExists,
+}
+
+#[derive(Debug, Display)]
+pub enum LspMessageActionItem {
+    CompositionRange,
+    CompositionState,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompositionInitializeParams {
+    pub text_document: lsp::TextDocumentIdentifier,
+    pub bucket: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub measurement: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<String>>,
+    #[deprecated(
+        since = "0.8.36",
+        note = "tag filters are no longer supported"
+    )]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag_values: Option<Vec<(String, String)>>,
+    /// The version of the document the command is made against. Commands against an
+    /// older version than the server has are rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>,
+    /// Whether the server applies the edit of the composition in the client. When
+    /// false, the edit is returned instead, for clients managing their own buffers.
+    #[serde(default = "apply_by_default")]
+    pub apply: bool,
+}
+
+fn apply_by_default() -> bool {
+    true
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValueFilterParams {
+    pub text_document: lsp::TextDocumentIdentifier,
+    pub value: String,
+    /// The version of the document the command is made against.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>,
+    /// Whether the server applies the edit of the composition in the client.
+    #[serde(default = "apply_by_default")]
+    pub apply: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagValueFilterParams {
+    pub text_document: lsp::TextDocumentIdentifier,
+    pub tag: String,
+    pub value: String,
+    /// The version of the document the command is made against.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>,
+    /// Whether the server applies the edit of the composition in the client.
+    #[serde(default = "apply_by_default")]
+    pub apply: bool,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StageDirection {
+    /// Towards the head of the pipeline, i.e. swap with the stage before.
+    Up,
+    /// Towards the end of the pipeline, i.e. swap with the stage after.
+    Down,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MovePipelineStageParams {
+    pub text_document: lsp::TextDocumentIdentifier,
+    /// Any position inside of the pipeline.
+    pub position: lsp::Position,
+    /// The index of the stage to move. The head of the pipeline, e.g. `from`, is
+    /// stage 0 and can't be moved.
+    pub stage: usize,
+    pub direction: StageDirection,
+}
+
+/// Identifies the stage to remove either by its `range`, or by the `position` of its
+/// pipeline along with its `stage` index, like `MovePipelineStageParams`.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemovePipelineStageParams {
+    pub text_document: lsp::TextDocumentIdentifier,
+    /// The range of the stage, or any range inside of its call.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<lsp::Range>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<lsp::Position>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stage: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetCallArgumentParams {
+    pub text_document: lsp::TextDocumentIdentifier,
+    /// A position inside of the call, or inside of its pipeline if `stage` is given.
+    pub position: lsp::Position,
+    /// The index of the pipeline stage to set the argument of, the head being 0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stage: Option<usize>,
+    pub name: String,
+    /// The flux source of a literal, e.g. `5m` or `"cpu"`.
+    pub value: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use strum::IntoEnumIterator;
+
+    use super::*;
+
+    #[test]
+    fn command_names_round_trip() {
+        for command in LspServerCommand::iter() {
+            assert_eq!(
+                Ok(command),
+                LspServerCommand::try_from(String::from(command))
+            );
+        }
+    }
+
+    #[test]
+    fn negotiated_version() {
+        assert_eq!(
+            PROTOCOL_VERSION,
+            InitializationOptions::default().negotiated_version()
+        );
+        assert_eq!(
+            PROTOCOL_VERSION,
+            InitializationOptions {
+                protocol_version: Some(PROTOCOL_VERSION + 1),
+                ..InitializationOptions::default()
+            }
+            .negotiated_version()
+        );
+        assert_eq!(
+            0,
+            InitializationOptions {
+                protocol_version: Some(0),
+                ..InitializationOptions::default()
+            }
+            .negotiated_version()
+        );
+    }
+}
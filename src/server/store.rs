@@ -3,7 +3,9 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::sync::{
+    Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
 
 use lspower::lsp;
 
@@ -21,7 +23,13 @@ fn url_to_key_val(url: &lsp::Url) -> (String, String) {
     (parent, filename.into())
 }
 
-fn get_analyzer() -> Result<
+/// An analyzer of flux packages.
+///
+/// Flux's AST and semantic checks (e.g. for reassigned options) only run in `strict`
+/// analysis. Hover, completion, etc. don't report errors, so they skip them.
+fn get_analyzer(
+    strict: bool,
+) -> Result<
     flux::semantic::Analyzer<
         'static,
         &'static flux::semantic::import::Packages,
@@ -29,141 +37,371 @@ fn get_analyzer() -> Result<
     LspError,
 > {
     match flux::new_semantic_analyzer(
-        flux::semantic::AnalyzerConfig::default(),
+        flux::semantic::AnalyzerConfig {
+            skip_checks: !strict,
+            ..flux::semantic::AnalyzerConfig::default()
+        },
     ) {
         Ok(analyzer) => Ok(analyzer),
         Err(err) => Err(LspError::InternalError(format!("{}", err))),
     }
 }
 
-/// Store acts as the in-memory storage backend for the LSP server.
+/// The storage of the documents of the server.
+///
+/// `MemoryStore` keeps documents in memory, which is what editors need, as they send
+/// the contents of every open document. Embedders may back documents with their own
+/// persistence instead (e.g. notebooks stored in a database), and register their
+/// store with `LspServer::with_store`.
+///
+/// Documents are grouped in packages by directory, like flux does on disk. The server
+/// caches the analysis of packages, and invalidates it when documents are put or
+/// removed through it, so documents shouldn't change behind its back.
+pub trait DocumentStore: Send + Sync {
+    /// Store the contents of a document, replacing any it had.
+    fn put(&self, url: &lsp::Url, contents: &str);
+
+    fn remove(&self, url: &lsp::Url);
+
+    fn get(&self, url: &lsp::Url) -> Option<String>;
+
+    /// The documents of the package of a document, including itself, along with
+    /// their contents.
+    fn package_files(
+        &self,
+        url: &lsp::Url,
+    ) -> Vec<(lsp::Url, String)>;
+
+    /// The urls of every document in the store.
+    ///
+    /// Workspace wide requests, like finding the usages of a bucket, only look at the
+    /// documents listed here. Stores that can't list their documents cheaply may keep
+    /// the default, leaving them out of such requests.
+    fn urls(&self) -> Vec<lsp::Url> {
+        vec![]
+    }
+}
+
+/// The default `DocumentStore`, keeping documents in memory.
 ///
 /// The spec talks specifically about setting versions for files, but isn't
 /// clear on how those versions are surfaced to the client, if ever. This
 /// type could be extended to keep track of versions of files, but simplicity
 /// is preferred at this point.
-pub(crate) struct Store {
-    #[allow(clippy::type_complexity)]
-    backend: Arc<
-        RwLock<HashMap<String, HashMap<String, (String, lsp::Url)>>>,
-    >,
+#[derive(Default)]
+pub struct MemoryStore {
+    backend: RwLock<Backend>,
 }
 
-impl Default for Store {
-    fn default() -> Self {
-        Store {
-            backend: Arc::new(RwLock::new(HashMap::new())),
-        }
+type Backend = HashMap<String, HashMap<String, (String, lsp::Url)>>;
+/// Semantic packages analyzed, keyed by directory and file name.
+#[derive(Default)]
+struct Analyzed {
+    packages: HashMap<String, HashMap<String, AnalyzedPackage>>,
+    /// How many times the documents of each package changed, keyed by directory.
+    ///
+    /// An analysis started before a document of its package changed is of contents
+    /// that are gone, so it is only kept if the generation of its package is still
+    /// the one from before its documents were read.
+    generations: HashMap<String, u64>,
+}
+
+/// A semantic package analyzed for a document, along with whether the analysis had
+/// errors.
+#[derive(Clone)]
+struct AnalyzedPackage {
+    pkg: flux::semantic::nodes::Package,
+    has_errors: bool,
+}
+
+impl MemoryStore {
+    /// Acquire the backend for reading.
+    ///
+    /// Readers don't block each other, so read-heavy requests (hover, completion,
+    /// etc.) proceed in parallel. Every write is a single insert or removal, so a
+    /// panic while the lock was held can't leave the backend inconsistent, and
+    /// poisoning is ignored.
+    fn read(&self) -> RwLockReadGuard<'_, Backend> {
+        self.backend.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Acquire the backend for writing. See `read` regarding poisoning.
+    fn write(&self) -> RwLockWriteGuard<'_, Backend> {
+        self.backend.write().unwrap_or_else(PoisonError::into_inner)
     }
 }
 
-impl Store {
-    pub fn put(&self, url: &lsp::Url, contents: &str) {
+impl DocumentStore for MemoryStore {
+    fn put(&self, url: &lsp::Url, contents: &str) {
         let (key, val) = url_to_key_val(url);
 
-        match self.backend.write() {
-            Ok(mut store) => match store.entry(key) {
-                Entry::Vacant(entry) => {
-                    entry.insert(HashMap::from([(
-                        val,
-                        (contents.into(), url.clone()),
-                    )]));
-                }
-                Entry::Occupied(mut entry) => {
-                    let map = entry.get_mut();
-                    map.insert(val, (contents.into(), url.clone()));
-                }
-            },
-            Err(error) => {
-                log::error!(
-                    "Could not acquire store lock. Error: {}",
-                    error
-                );
+        match self.write().entry(key) {
+            Entry::Vacant(entry) => {
+                entry.insert(HashMap::from([(
+                    val,
+                    (contents.into(), url.clone()),
+                )]));
+            }
+            Entry::Occupied(mut entry) => {
+                let map = entry.get_mut();
+                map.insert(val, (contents.into(), url.clone()));
             }
         }
     }
 
-    pub fn remove(&self, url: &lsp::Url) {
+    fn remove(&self, url: &lsp::Url) {
         let (key, val) = url_to_key_val(url);
 
-        match self.backend.write() {
-            Ok(mut store) => match store.entry(key) {
-                Entry::Vacant(_) => {
-                    log::warn!(
-                        "remove called on non-existent file: {}",
-                        url
-                    )
-                }
-                Entry::Occupied(mut entry) => {
-                    let map = entry.get_mut();
-                    map.remove(&val);
-                }
-            },
-            Err(error) => {
-                log::error!(
-                    "Could not acquire store lock. Error: {}",
-                    error
-                );
+        match self.write().entry(key) {
+            Entry::Vacant(_) => {
+                log::warn!(
+                    "remove called on non-existent file: {}",
+                    url
+                )
+            }
+            Entry::Occupied(mut entry) => {
+                let map = entry.get_mut();
+                map.remove(&val);
             }
         }
     }
 
-    pub fn get(&self, url: &lsp::Url) -> Result<String, LspError> {
+    fn get(&self, url: &lsp::Url) -> Option<String> {
         let (key, val) = url_to_key_val(url);
 
-        match self.backend.read() {
-            Ok(store) => match store.get(&key) {
-                None => Err(LspError::FileNotFound(url.to_string())),
-                Some(entry) => match entry.get(&val) {
-                    Some(value) => Ok(value.0.clone()),
-                    None => {
-                        Err(LspError::FileNotFound(url.to_string()))
-                    }
-                },
-            },
-            Err(_) => Err(LspError::LockNotAcquired),
+        self.read()
+            .get(&key)
+            .and_then(|entry| entry.get(&val))
+            .map(|value| value.0.clone())
+    }
+
+    fn package_files(
+        &self,
+        url: &lsp::Url,
+    ) -> Vec<(lsp::Url, String)> {
+        let (key, _) = url_to_key_val(url);
+        match self.read().get(&key) {
+            None => vec![],
+            Some(files) => files
+                .values()
+                .map(|(contents, url)| {
+                    (url.clone(), contents.clone())
+                })
+                .collect(),
         }
     }
 
-    /// Get urls for all files in a specified file's package.
-    pub fn get_package_urls(&self, url: &lsp::Url) -> Vec<lsp::Url> {
+    fn urls(&self) -> Vec<lsp::Url> {
+        self.read()
+            .values()
+            .flat_map(|files| {
+                files.values().map(|(_, url)| url.clone())
+            })
+            .collect()
+    }
+}
+
+/// Store gives the server the documents of a `DocumentStore`, parsed and analyzed.
+///
+/// Clones share their documents and analyses.
+#[derive(Clone)]
+pub(crate) struct Store {
+    documents: Arc<dyn DocumentStore>,
+    /// Semantic packages already analyzed.
+    ///
+    /// A package is only analyzed once until one of its files changes, so
+    /// requests following the first analysis of a package don't pay for it again.
+    analyzed: Arc<RwLock<Analyzed>>,
+    /// How long the last analysis of each package took in milliseconds, keyed by
+    /// directory.
+    analysis_durations: Arc<RwLock<HashMap<String, u64>>>,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Store::new(Arc::new(MemoryStore::default()))
+    }
+}
+
+impl Store {
+    pub fn new(documents: Arc<dyn DocumentStore>) -> Self {
+        Store {
+            documents,
+            analyzed: Arc::new(RwLock::new(Analyzed::default())),
+            analysis_durations: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// The generation of the package of `url`, to be read before its documents are
+    /// read for analysis.
+    pub(crate) fn generation(&self, url: &lsp::Url) -> u64 {
         let (key, _) = url_to_key_val(url);
-        match self.backend.read() {
-            Ok(store) => match store.get(&key) {
-                None => vec![],
-                Some(files) => files
-                    .iter()
-                    .map(|(_file, (_contents, url))| url.clone())
-                    .collect(),
+        self.analyzed
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .generations
+            .get(&key)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Remember the semantic package analyzed for `url`, and whether its analysis
+    /// had errors, unless a document of the package changed since `generation`.
+    fn set_analyzed(
+        &self,
+        url: &lsp::Url,
+        generation: u64,
+        pkg: &flux::semantic::nodes::Package,
+        has_errors: bool,
+    ) {
+        let (key, val) = url_to_key_val(url);
+        let mut analyzed = self
+            .analyzed
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if analyzed.generations.get(&key).copied().unwrap_or_default()
+            != generation
+        {
+            return;
+        }
+        analyzed.packages.entry(key).or_default().insert(
+            val,
+            AnalyzedPackage {
+                pkg: pkg.clone(),
+                has_errors,
             },
-            Err(_) => vec![],
+        );
+    }
+
+    fn get_analyzed(
+        &self,
+        url: &lsp::Url,
+    ) -> Option<AnalyzedPackage> {
+        let (key, val) = url_to_key_val(url);
+        self.analyzed
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .packages
+            .get(&key)
+            .and_then(|files| files.get(&val))
+            .cloned()
+    }
+
+    /// Whether the semantic package of `url` is analyzed already.
+    pub fn is_analyzed(&self, url: &lsp::Url) -> bool {
+        self.get_analyzed(url).is_some()
+    }
+
+    /// Whether the analysis of the package of `url` has errors.
+    ///
+    /// This is the same as `get_package_errors(url, strict).is_some()`, but reuses
+    /// the analysis of the package when there is one, e.g. right after its
+    /// diagnostics were published, however strict it was.
+    pub fn has_package_errors(
+        &self,
+        url: &lsp::Url,
+        strict: bool,
+    ) -> bool {
+        match self.get_analyzed(url) {
+            Some(analyzed) => analyzed.has_errors,
+            None => self.get_package_errors(url, strict).is_some(),
         }
     }
 
+    /// Remember how long the analysis of the package of `url`, started at `started`,
+    /// took.
+    fn record_analysis(&self, url: &lsp::Url, started: u64) {
+        let (key, _) = url_to_key_val(url);
+        self.analysis_durations
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key, crate::trace::now().saturating_sub(started));
+    }
+
+    /// How long the last analysis of the package of `url` took, in milliseconds.
+    pub fn last_analysis_duration(
+        &self,
+        url: &lsp::Url,
+    ) -> Option<u64> {
+        let (key, _) = url_to_key_val(url);
+        self.analysis_durations
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key)
+            .copied()
+    }
+
+    /// Forget every analyzed package in the directory of `url`, as any file in
+    /// a package can change the analysis of the others, and move the package to its
+    /// next generation, so that analyses already underway aren't kept either.
+    ///
+    /// This is called once the document changed, as an analysis could otherwise read
+    /// the previous contents after the package moved to its next generation.
+    fn invalidate_analyzed(&self, url: &lsp::Url) {
+        let (key, _) = url_to_key_val(url);
+        let mut analyzed = self
+            .analyzed
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        analyzed.packages.remove(&key);
+        *analyzed.generations.entry(key).or_default() += 1;
+    }
+
+    /// Store the contents of a document.
+    ///
+    /// Flux infers the types of a package as a whole, and its analyzer can't re-infer
+    /// a single statement against a previous analysis, so any change to a document
+    /// invalidates the analysis of its package. Contents identical to those stored,
+    /// e.g. from a document opened again or a client syncing a document it didn't
+    /// change, keep their analysis.
+    pub fn put(&self, url: &lsp::Url, contents: &str) {
+        if self.documents.get(url).as_deref() == Some(contents) {
+            return;
+        }
+        self.documents.put(url, contents);
+        self.invalidate_analyzed(url);
+    }
+
+    pub fn remove(&self, url: &lsp::Url) {
+        self.documents.remove(url);
+        self.invalidate_analyzed(url);
+    }
+
+    pub fn get(&self, url: &lsp::Url) -> Result<String, LspError> {
+        self.documents
+            .get(url)
+            .ok_or_else(|| LspError::FileNotFound(url.to_string()))
+    }
+
+    /// Get urls for all files in a specified file's package.
+    pub fn get_package_urls(&self, url: &lsp::Url) -> Vec<lsp::Url> {
+        self.documents
+            .package_files(url)
+            .into_iter()
+            .map(|(url, _contents)| url)
+            .collect()
+    }
+
+    /// Get urls for all files in the store, sorted.
+    pub fn get_urls(&self) -> Vec<lsp::Url> {
+        let mut urls = self.documents.urls();
+        urls.sort();
+        urls
+    }
+
+    /// Get the file names and contents of all files in a specified file's package.
     fn get_files(
         &self,
-        path: String,
+        url: &lsp::Url,
     ) -> Result<Vec<(String, String)>, LspError> {
-        match self.backend.read() {
-            Ok(store) => match store.get(&path) {
-                None => Err(LspError::FileNotFound(path)),
-                Some(entry) => {
-                    Ok(entry
-                        .keys()
-                        .map(|key| {
-                            (
-                                key.clone(),
-                                // Unwrap is okay here, as the key is retrieved from
-                                // map.keys()
-                                #[allow(clippy::unwrap_used)]
-                                entry.get(key).unwrap().0.clone(),
-                            )
-                        })
-                        .collect())
-                }
-            },
-            Err(_) => Err(LspError::LockNotAcquired),
+        let files = self.documents.package_files(url);
+        if files.is_empty() {
+            return Err(LspError::FileNotFound(url.to_string()));
         }
+        Ok(files
+            .into_iter()
+            .map(|(url, contents)| (url_to_key_val(&url).1, contents))
+            .collect())
     }
 
     pub fn get_ast_file(
@@ -187,19 +425,30 @@ impl Store {
         &self,
         url: &lsp::Url,
     ) -> Result<flux::ast::Package, LspError> {
-        let (key, val) = url_to_key_val(url);
-        let files = self.get_files(key)?;
+        self.get_ast_package_with(url, None)
+    }
+
+    /// Get the AST package for a file's package, with the contents of the file
+    /// replaced by `contents` if given.
+    fn get_ast_package_with(
+        &self,
+        url: &lsp::Url,
+        contents: Option<&str>,
+    ) -> Result<flux::ast::Package, LspError> {
+        let (_, val) = url_to_key_val(url);
+        let files = self.get_files(url)?;
 
         // Grab the AST Package corresponding to currently requested package. Merge all
         // other packages with it that one as root.
         let mut pkgs: Vec<flux::ast::Package> = files
             .iter()
             .map(|source| {
-                flux::parser::parse_string(
-                    source.0.clone(),
-                    &source.1,
-                )
-                .into()
+                let text = match contents {
+                    Some(contents) if source.0 == val => contents,
+                    _ => &source.1,
+                };
+                flux::parser::parse_string(source.0.clone(), text)
+                    .into()
             })
             .collect();
         let mut ast_pkg = match pkgs
@@ -231,35 +480,78 @@ impl Store {
         Ok(ast_pkg)
     }
 
+    /// Get the semantic package for a file's package.
+    ///
+    /// This is the relaxed path used by hover, completion, etc. Flux's checks are
+    /// skipped, and analysis errors are ignored as long as flux can salvage a semantic
+    /// graph; they are reported via `get_package_errors` instead.
+    ///
+    /// The package is analyzed once and then served from memory until a file in
+    /// the package changes.
     pub fn get_semantic_package(
         &self,
 
         url: &lsp::Url,
     ) -> Result<flux::semantic::nodes::Package, LspError> {
+        if let Some(analyzed) = self.get_analyzed(url) {
+            return Ok(analyzed.pkg);
+        }
+
+        let generation = self.generation(url);
         let ast_pkg = self.get_ast_package(url)?;
 
-        let mut analyzer = get_analyzer()?;
-        match analyzer.analyze_ast(&ast_pkg) {
-            Ok((_, pkg)) => Ok(pkg),
+        let mut analyzer = get_analyzer(false)?;
+        let started = crate::trace::now();
+        let analyzed = analyzer.analyze_ast(&ast_pkg);
+        self.record_analysis(url, started);
+        let (pkg, has_errors) = match analyzed {
+            Ok((_, pkg)) => (pkg, false),
             Err(e) => {
                 let error_string = format!("{}", e);
                 if e.value.is_none() {
                     log::debug!("Unable to parse source: {}", e);
                 }
                 match e.value.map(|(_, sem_pkg)| sem_pkg) {
-                    Some(value) => Ok(value),
+                    Some(value) => (value, true),
                     None => {
-                        Err(LspError::InternalError(error_string))
+                        return Err(LspError::InternalError(
+                            error_string,
+                        ))
                     }
                 }
             }
+        };
+        self.set_analyzed(url, generation, &pkg, has_errors);
+        Ok(pkg)
+    }
+
+    /// Analyze a file's package as if the file had `contents`, without storing them.
+    ///
+    /// Unlike `get_semantic_package`, any analysis error is an error, as this is used
+    /// to check that a change to the file is sound before suggesting it.
+    pub fn analyze_with(
+        &self,
+        url: &lsp::Url,
+        contents: &str,
+    ) -> Result<flux::semantic::nodes::Package, LspError> {
+        let ast_pkg =
+            self.get_ast_package_with(url, Some(contents))?;
+
+        let mut analyzer = get_analyzer(true)?;
+        match analyzer.analyze_ast(&ast_pkg) {
+            Ok((_, pkg)) => Ok(pkg),
+            Err(e) => Err(LspError::InternalError(format!("{}", e))),
         }
     }
 
+    /// The errors of the analysis of a file's package, if any. Errors from flux's
+    /// checks are only found in `strict` analysis.
     pub fn get_package_errors(
         &self,
         url: &lsp::Url,
+        strict: bool,
     ) -> Option<flux::semantic::FileErrors> {
+        let generation = self.generation(url);
         let ast_pkg = match self.get_ast_package(url) {
             Ok(pkg) => pkg,
             Err(err) => {
@@ -268,16 +560,29 @@ impl Store {
             }
         };
 
-        let mut analyzer = match get_analyzer() {
+        let mut analyzer = match get_analyzer(strict) {
             Ok(analyzer) => analyzer,
             Err(err) => {
                 log::error!("{:?}", err);
                 return None;
             }
         };
-        match analyzer.analyze_ast(&ast_pkg) {
-            Ok(_) => None,
-            Err(errors) => Some(errors.error),
+        // The analysis is done anyway, so keep the semantic package around for
+        // `get_semantic_package`.
+        let started = crate::trace::now();
+        let analyzed = analyzer.analyze_ast(&ast_pkg);
+        self.record_analysis(url, started);
+        match analyzed {
+            Ok((_, pkg)) => {
+                self.set_analyzed(url, generation, &pkg, false);
+                None
+            }
+            Err(errors) => {
+                if let Some((_, pkg)) = &errors.value {
+                    self.set_analyzed(url, generation, pkg, true);
+                }
+                Some(errors.error)
+            }
         }
     }
 }
@@ -289,7 +594,8 @@ mod test {
 
     #[test]
     fn put() {
-        let store = Store::default();
+        let memory = Arc::new(MemoryStore::default());
+        let store = Store::new(memory.clone());
         let url = lsp::Url::parse("file:///a/b/c").unwrap();
         let contents = "import \"foo\"";
         store.put(&url, contents);
@@ -297,7 +603,7 @@ mod test {
         let (key, val) = url_to_key_val(&url);
 
         {
-            let mut backend = store
+            let mut backend = memory
                 .backend
                 .write()
                 .expect("Could not acquire lock");
@@ -316,7 +622,8 @@ mod test {
 
     #[test]
     fn get() {
-        let store = Store::default();
+        let memory = Arc::new(MemoryStore::default());
+        let store = Store::new(memory.clone());
         let url = lsp::Url::parse("file:///a/b/c").unwrap();
         let contents = "import \"foo\"";
         let (key, val) = url_to_key_val(&url);
@@ -324,7 +631,7 @@ mod test {
         {
             let mut map = HashMap::new();
             map.insert(val, (contents.into(), url.clone()));
-            let mut backend = store
+            let mut backend = memory
                 .backend
                 .write()
                 .expect("Could not acquire lock");
@@ -392,16 +699,28 @@ mod test {
     }
 
     #[test]
-    fn remove() {
+    fn get_urls() {
         let store = Store::default();
         let url = lsp::Url::parse("file:///a/b/c").unwrap();
+        let url2 = lsp::Url::parse("file:///a/c/c").unwrap();
+        store.put(&url2, "");
+        store.put(&url, "");
+
+        assert_eq!(vec![url, url2], store.get_urls());
+    }
+
+    #[test]
+    fn remove() {
+        let memory = Arc::new(MemoryStore::default());
+        let store = Store::new(memory.clone());
+        let url = lsp::Url::parse("file:///a/b/c").unwrap();
         let contents = "import \"foo\"";
         let (key, val) = url_to_key_val(&url);
 
         {
             let mut map = HashMap::new();
             map.insert(val, (contents.into(), url.clone()));
-            let mut backend = store
+            let mut backend = memory
                 .backend
                 .write()
                 .expect("Could not acquire lock");
@@ -451,6 +770,64 @@ from(bucket: "bucket")
         assert_eq!(2, result.files.len());
     }
 
+    /// Changing any file of a package invalidates the analysis of the others.
+    #[test]
+    fn get_semantic_package_after_put() {
+        let store = Store::default();
+        let key = lsp::Url::parse("file:///a/b/c").unwrap();
+        store.put(&key, r#"x = 1"#);
+        assert_eq!(
+            1,
+            store.get_semantic_package(&key).unwrap().files.len()
+        );
+
+        store.put(
+            &lsp::Url::parse("file:///a/b/a").unwrap(),
+            r#"v = {a: "b"}"#,
+        );
+        assert_eq!(
+            2,
+            store.get_semantic_package(&key).unwrap().files.len()
+        );
+
+        store.remove(&lsp::Url::parse("file:///a/b/a").unwrap());
+        assert_eq!(
+            1,
+            store.get_semantic_package(&key).unwrap().files.len()
+        );
+    }
+
+    #[test]
+    fn put_unchanged_contents() {
+        let store = Store::default();
+        let key = lsp::Url::parse("file:///a/b/c").unwrap();
+        store.put(&key, r#"x = 1"#);
+        store.get_semantic_package(&key).unwrap();
+
+        store.put(&key, r#"x = 1"#);
+        assert!(store.is_analyzed(&key));
+
+        store.put(&key, r#"x = 2"#);
+        assert!(!store.is_analyzed(&key));
+    }
+
+    #[test]
+    fn analysis_of_changed_package_not_kept() {
+        let store = Store::default();
+        let key = lsp::Url::parse("file:///a/b/c").unwrap();
+        store.put(&key, r#"x = 1"#);
+
+        // An analysis that read the documents before they changed finishes after.
+        let generation = store.generation(&key);
+        let pkg = store.get_semantic_package(&key).unwrap();
+        store.put(&key, r#"x = 2"#);
+        store.set_analyzed(&key, generation, &pkg, false);
+        assert!(!store.is_analyzed(&key));
+
+        store.get_semantic_package(&key).unwrap();
+        assert!(store.is_analyzed(&key));
+    }
+
     #[test]
     fn get_package_multi_file_separate_packages() {
         let store = Store::default();
@@ -482,7 +859,7 @@ from(bucket: "bucket")
 |> filter(fn: (r) => r.tag == "anTag")"#;
         store.put(&key, contents);
 
-        let result = store.get_package_errors(&key);
+        let result = store.get_package_errors(&key, true);
 
         assert!(result.is_none());
     }
@@ -498,11 +875,38 @@ from(bucket: "bucket")
 |> filter(fn: (r) => r.tag == "anTag")"#;
         store.put(&key, contents);
 
-        let result = store.get_package_errors(&key);
+        let result = store.get_package_errors(&key, true);
 
         // XXX: rockstar (29 Apr 2022) - fluxcore::errors is private, so asserting
         // information _about_ the errors is difficult. Asserting that there _are_ errors
         // is enough, for now.
         assert!(result.is_some());
+        // The analysis is kept, errors included.
+        assert!(store.is_analyzed(&key));
+        assert!(store.has_package_errors(&key, true));
+    }
+
+    /// Errors from flux's checks, e.g. a reassigned variable, are only found in strict
+    /// analysis.
+    #[test]
+    fn get_package_errors_strict() {
+        let store = Store::default();
+        let key = lsp::Url::parse("file:///a/b/c").unwrap();
+        store.put(&key, "a = 1\na = 2\n");
+
+        assert!(store.get_package_errors(&key, true).is_some());
+        assert!(store.get_package_errors(&key, false).is_none());
+    }
+
+    #[test]
+    fn analyze_with() {
+        let store = Store::default();
+        let key = lsp::Url::parse("file:///a/b/c").unwrap();
+        store.put(&key, "x = 1\n");
+
+        assert!(store.analyze_with(&key, "x = 1 + 1\n").is_ok());
+        assert!(store.analyze_with(&key, "x = 1 + \"a\"\n").is_err());
+        // The stored contents are left alone.
+        assert_eq!("x = 1\n", store.get(&key).unwrap());
     }
 }
@@ -0,0 +1,312 @@
+#![allow(deprecated)]
+/// An index of the symbols of the documents of a workspace, for `workspace/symbol`
+///
+/// Documents are indexed as they are opened and changed, and stay in the index once
+/// they are closed. The server only knows of the documents the client opens, so for
+/// large workspaces the index is persisted between sessions with
+/// `LspServer::with_symbol_index`, and the symbols of documents indexed in earlier
+/// sessions are found right away on startup rather than once each is opened again.
+///
+/// Each document is kept along with the hash of its contents and the modification
+/// time of its file. When the index is loaded, documents whose file was modified since
+/// are checked against the hash of their contents, and dropped if they changed.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use lspower::lsp;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexedSymbol {
+    name: String,
+    kind: lsp::SymbolKind,
+    range: lsp::Range,
+    container_name: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexedDocument {
+    /// The hash of the contents the symbols were found in.
+    hash: u64,
+    /// When the file of the document was last modified, in milliseconds since the
+    /// epoch. Documents that aren't files have none, and aren't kept between sessions.
+    modified: Option<u64>,
+    symbols: Vec<IndexedSymbol>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SymbolIndex {
+    /// The version of the server that wrote the index. Other versions may find other
+    /// symbols in the same contents, so their index is discarded.
+    version: String,
+    documents: BTreeMap<lsp::Url, IndexedDocument>,
+}
+
+impl Default for SymbolIndex {
+    fn default() -> Self {
+        Self {
+            version: super::VERSION.into(),
+            documents: BTreeMap::new(),
+        }
+    }
+}
+
+// The hash is only compared with hashes of the same build of the server, as the index
+// of other versions is discarded.
+fn hash(contents: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// When the file of a document was last modified, if it is a file.
+fn modified(url: &lsp::Url) -> Option<u64> {
+    let path = url.to_file_path().ok()?;
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_millis() as u64)
+}
+
+fn flatten(
+    symbols: &[lsp::DocumentSymbol],
+    container_name: Option<&str>,
+    indexed: &mut Vec<IndexedSymbol>,
+) {
+    for symbol in symbols {
+        indexed.push(IndexedSymbol {
+            name: symbol.name.clone(),
+            kind: symbol.kind,
+            range: symbol.range,
+            container_name: container_name.map(String::from),
+        });
+        if let Some(children) = &symbol.children {
+            flatten(children, Some(&symbol.name), indexed);
+        }
+    }
+}
+
+/// Whether the characters of a query appear in a name in the same order, ignoring
+/// case, e.g. `mAvg` matches `movingAverage`.
+fn matches(name: &str, query: &str) -> bool {
+    let mut name = name.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|c| name.any(|n| n == c))
+}
+
+impl SymbolIndex {
+    /// Whether the symbols of a document with these contents are indexed already.
+    pub(crate) fn is_current(
+        &self,
+        url: &lsp::Url,
+        contents: &str,
+    ) -> bool {
+        self.documents
+            .get(url)
+            .map_or(false, |document| document.hash == hash(contents))
+    }
+
+    /// Index the symbols of a document, as found in its contents.
+    pub(crate) fn update(
+        &mut self,
+        url: &lsp::Url,
+        contents: &str,
+        symbols: &[lsp::DocumentSymbol],
+    ) {
+        let mut indexed = vec![];
+        flatten(symbols, None, &mut indexed);
+        self.documents.insert(
+            url.clone(),
+            IndexedDocument {
+                hash: hash(contents),
+                modified: modified(url),
+                symbols: indexed,
+            },
+        );
+    }
+
+    pub(crate) fn remove(&mut self, url: &lsp::Url) {
+        self.documents.remove(url);
+    }
+
+    /// The symbols of every indexed document whose name matches the query. Every
+    /// symbol matches an empty query.
+    pub(crate) fn search(
+        &self,
+        query: &str,
+    ) -> Vec<lsp::SymbolInformation> {
+        self.documents
+            .iter()
+            .flat_map(|(url, document)| {
+                document
+                    .symbols
+                    .iter()
+                    .filter(|symbol| matches(&symbol.name, query))
+                    .map(move |symbol| lsp::SymbolInformation {
+                        name: symbol.name.clone(),
+                        kind: symbol.kind,
+                        location: lsp::Location {
+                            uri: url.clone(),
+                            range: symbol.range,
+                        },
+                        tags: None,
+                        deprecated: None,
+                        container_name: symbol.container_name.clone(),
+                    })
+            })
+            .collect()
+    }
+
+    /// Load the index persisted at a path, without the documents that changed on disk
+    /// since. A missing index is empty.
+    pub(crate) fn load(path: &Path) -> io::Result<Self> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Self::default())
+            }
+            Err(err) => return Err(err),
+        };
+        let mut index: Self = serde_json::from_slice(&contents)?;
+        if index.version != super::VERSION {
+            return Ok(Self::default());
+        }
+        index.documents.retain(|url, document| {
+            let current = match modified(url) {
+                Some(current) => current,
+                None => return false,
+            };
+            if document.modified == Some(current) {
+                return true;
+            }
+            let unchanged = url
+                .to_file_path()
+                .ok()
+                .and_then(|path| fs::read_to_string(path).ok())
+                .map_or(false, |contents| {
+                    document.hash == hash(&contents)
+                });
+            document.modified = Some(current);
+            unchanged
+        });
+        Ok(index)
+    }
+
+    /// Persist the index at a path. It is written next to it first, so that an index
+    /// is never left half written.
+    pub(crate) fn save(&self, path: &Path) -> io::Result<()> {
+        let written = path.with_extension("tmp");
+        fs::write(&written, serde_json::to_vec(self)?)?;
+        fs::rename(written, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(
+        name: &str,
+        kind: lsp::SymbolKind,
+        line: u32,
+        children: Option<Vec<lsp::DocumentSymbol>>,
+    ) -> lsp::DocumentSymbol {
+        let range = lsp::Range::new(
+            lsp::Position::new(line, 0),
+            lsp::Position::new(line, 10),
+        );
+        lsp::DocumentSymbol {
+            name: name.into(),
+            detail: None,
+            kind,
+            tags: None,
+            deprecated: None,
+            range,
+            selection_range: range,
+            children,
+        }
+    }
+
+    #[test]
+    fn persisted_between_sessions() {
+        let dir = std::env::temp_dir().join(format!(
+            "flux-lsp-symbol-index-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let kept = dir.join("kept.flux");
+        let changed = dir.join("changed.flux");
+        let contents = "movingAverage = (tables=<-) => tables\n";
+        fs::write(&kept, contents).unwrap();
+        fs::write(&changed, contents).unwrap();
+
+        let symbols = vec![symbol(
+            "movingAverage",
+            lsp::SymbolKind::FUNCTION,
+            0,
+            Some(vec![symbol(
+                "tables",
+                lsp::SymbolKind::VARIABLE,
+                0,
+                None,
+            )]),
+        )];
+        let mut index = SymbolIndex::default();
+        for path in [&kept, &changed] {
+            let url = lsp::Url::from_file_path(path).unwrap();
+            index.update(&url, contents, &symbols);
+        }
+        index.update(
+            &lsp::Url::parse("untitled:Untitled-1").unwrap(),
+            contents,
+            &symbols,
+        );
+
+        let found = index.search("mAvg");
+        assert_eq!(3, found.len());
+        assert!(found
+            .iter()
+            .all(|symbol| symbol.name == "movingAverage"));
+        assert_eq!(
+            Some("movingAverage".to_string()),
+            index.search("tables")[0].container_name
+        );
+        assert_eq!(6, index.search("").len());
+        assert!(index.search("avm").is_empty());
+
+        // Both files are modified after being indexed, but only one of them changes.
+        fs::write(&changed, "x = 1\n").unwrap();
+        for document in index.documents.values_mut() {
+            document.modified = document.modified.map(|_| 0);
+        }
+        let saved = dir.join("index.json");
+        index.save(&saved).unwrap();
+        let loaded = SymbolIndex::load(&saved).unwrap();
+
+        let kept = lsp::Url::from_file_path(&kept).unwrap();
+        assert_eq!(
+            vec![kept.clone(), kept.clone()],
+            loaded
+                .search("")
+                .into_iter()
+                .map(|symbol| symbol.location.uri)
+                .collect::<Vec<_>>()
+        );
+        assert!(loaded.is_current(&kept, contents));
+        assert!(!loaded.is_current(&kept, "x = 1\n"));
+        assert_eq!(
+            SymbolIndex::default(),
+            SymbolIndex::load(&dir.join("missing.json")).unwrap()
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
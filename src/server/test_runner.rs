@@ -0,0 +1,97 @@
+/// The commands discovering and running the flux tests of a package
+///
+/// Tests are found in every document of the package of a document, and run with the
+/// `flux` command line tool, see `crate::testing`.
+use lspower::lsp;
+
+#[cfg(feature = "cmd")]
+use super::types::LspError;
+use super::{protocol_ext, LspServer};
+
+impl LspServer {
+    /// The tests of the package of a document, file by file.
+    pub(super) fn discover_tests(
+        &self,
+        uri: &lsp::Url,
+    ) -> Vec<protocol_ext::TestCase> {
+        let mut urls = self.store.get_package_urls(uri);
+        urls.sort();
+        urls.into_iter()
+            .filter_map(|url| {
+                let file = self.store.get_ast_file(&url).ok()?;
+                Some(crate::testing::tests(&file).into_iter().map(
+                    move |test| protocol_ext::TestCase {
+                        name: test.name,
+                        uri: url.clone(),
+                        range: test.range,
+                        extends: test.extends,
+                    },
+                ))
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// Run tests of the package of a document one by one, with the `flux` command
+    /// line tool, in the directory of the file of each test.
+    #[cfg(feature = "cmd")]
+    pub(super) async fn run_tests(
+        &self,
+        params: protocol_ext::RunTestsParams,
+    ) -> Result<Vec<protocol_ext::TestResult>, LspError> {
+        let flux = self.read_state().flux_command().to_string();
+        let tests = self
+            .discover_tests(&params.text_document.uri)
+            .into_iter()
+            .filter(|test| {
+                params.names.is_empty()
+                    || params.names.contains(&test.name)
+            });
+
+        let mut results = vec![];
+        for test in tests {
+            let directory = test
+                .uri
+                .to_file_path()
+                .ok()
+                .and_then(|path| {
+                    path.parent().map(std::path::Path::to_path_buf)
+                })
+                .ok_or_else(|| {
+                    LspError::InternalError(format!(
+                        "Tests of {} can't be run, as it isn't a local file",
+                        test.uri
+                    ))
+                })?;
+            let (flux, name) = (flux.clone(), test.name.clone());
+            let verbose = params.verbose;
+            let (passed, output) =
+                tokio::task::spawn_blocking(move || {
+                    crate::testing::run(
+                        &flux, &directory, &name, verbose,
+                    )
+                })
+                .await
+                .map_err(|err| {
+                    LspError::InternalError(err.to_string())
+                })?;
+            results.push(protocol_ext::TestResult {
+                passed,
+                diagnostic: (!passed).then(|| lsp::Diagnostic {
+                    range: test.range,
+                    severity: Some(lsp::DiagnosticSeverity::ERROR),
+                    message: if output.is_empty() {
+                        format!("Test {} failed", test.name)
+                    } else {
+                        output.clone()
+                    },
+                    ..lsp::Diagnostic::default()
+                }),
+                output: verbose.then_some(output),
+                name: test.name,
+                uri: test.uri,
+            });
+        }
+        Ok(results)
+    }
+}
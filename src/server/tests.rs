@@ -88,6 +88,114 @@ async fn test_initialized() {
     );
 }
 
+/// Clients are told which version of the protocol extension to speak, and which
+/// commands and notifications it has.
+#[test]
+async fn test_initialize_protocol_version() {
+    let server = create_server();
+
+    let params = lsp::InitializeParams {
+        capabilities: lsp::ClientCapabilities::default(),
+        client_info: None,
+        initialization_options: Some(json!({"protocolVersion": 0})),
+        locale: None,
+        process_id: None,
+        root_path: None,
+        root_uri: None,
+        trace: None,
+        workspace_folders: None,
+    };
+
+    let result = server.initialize(params).await.unwrap();
+    let protocol: ProtocolCapabilities = serde_json::from_value(
+        result.capabilities.experimental.unwrap()["fluxProtocol"]
+            .clone(),
+    )
+    .unwrap();
+
+    assert_eq!(0, protocol.version);
+    assert!(protocol
+        .commands
+        .contains(&"fluxComposition/initialize".to_string()));
+    assert_eq!(
+        vec![
+            "flux/analysisStatus".to_string(),
+            "flux/updateSchema".to_string(),
+            "flux/updateSecrets".to_string(),
+        ],
+        protocol.notifications
+    );
+    assert_eq!(
+        vec![
+            "textDocument/inlineValue".to_string(),
+            "flux/pipelineHeaders".to_string(),
+        ],
+        protocol.requests
+    );
+}
+
+/// Capabilities are turned off by the embedder and by the client, and the requests of
+/// those turned off get nothing.
+#[test]
+async fn test_initialize_disabled_capabilities() {
+    let server = create_server().with_disabled_capabilities(
+        DisabledCapabilities {
+            folding: true,
+            ..DisabledCapabilities::default()
+        },
+    );
+
+    let params = lsp::InitializeParams {
+        capabilities: lsp::ClientCapabilities::default(),
+        initialization_options: Some(json!({
+            "disabledCapabilities": {"executeCommands": true}
+        })),
+        ..lsp::InitializeParams::default()
+    };
+
+    let result = server.initialize(params).await.unwrap();
+    let capabilities = result.capabilities;
+    assert!(capabilities.folding_range_provider.is_none());
+    assert!(capabilities.execute_command_provider.is_none());
+    assert!(capabilities.code_lens_provider.is_none());
+    assert!(capabilities.semantic_tokens_provider.is_some());
+    assert!(capabilities.code_action_provider.is_some());
+    let protocol: ProtocolCapabilities = serde_json::from_value(
+        capabilities.experimental.unwrap()["fluxProtocol"].clone(),
+    )
+    .unwrap();
+    assert!(protocol.commands.is_empty());
+
+    open_file(&server, "x = {\n    a: 1,\n}\n".to_string(), None)
+        .await;
+    let folding = server
+        .folding_range(lsp::FoldingRangeParams {
+            text_document: lsp::TextDocumentIdentifier::new(
+                lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+            ),
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: lsp::PartialResultParams {
+                partial_result_token: None,
+            },
+        })
+        .await
+        .unwrap();
+    assert_eq!(None, folding);
+    assert!(server
+        .execute_command(lsp::ExecuteCommandParams {
+            command: "getCommandSchemas".into(),
+            arguments: vec![],
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+        })
+        .await
+        .is_err());
+}
+
 #[test]
 async fn test_shutdown() {
     let server = create_server();
@@ -95,6 +203,20 @@ async fn test_shutdown() {
     server.shutdown().await.unwrap();
 }
 
+/// The shutdown flag is only set once a `shutdown` request is handled, so hosts can
+/// pick the right exit code when the client sends `exit`.
+#[test]
+async fn test_shutdown_flag() {
+    let server = create_server();
+    let flag = server.shutdown_flag();
+
+    assert!(!flag.load(Ordering::SeqCst));
+
+    server.shutdown().await.unwrap();
+
+    assert!(flag.load(Ordering::SeqCst));
+}
+
 #[test]
 async fn test_did_open() {
     let server = create_server();
@@ -144,6 +266,42 @@ async fn test_did_change() {
     assert_eq!(r#"from(bucket: "bucket")"#, contents);
 }
 
+/// An edit leaving the contents of a document as they were, e.g. from a client
+/// syncing a document it didn't change, reuses the analysis of its package rather
+/// than analyzing it again.
+#[test]
+async fn test_did_change_unchanged_reuses_analysis() {
+    let contents = r#"from(bucket: "bucket") |> first()"#;
+    let server = create_server();
+    open_file(&server, contents.to_string(), None).await;
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let generation = server.store.generation(&uri);
+
+    let edit =
+        |version, text: &str| lsp::DidChangeTextDocumentParams {
+            text_document: lsp::VersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version,
+            },
+            content_changes: vec![
+                lsp::TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: text.to_string(),
+                },
+            ],
+        };
+
+    server.did_change(edit(2, contents)).await;
+    assert_eq!(generation, server.store.generation(&uri));
+    assert!(server.store.is_analyzed(&uri));
+
+    server
+        .did_change(edit(3, r#"from(bucket: "sensors")"#))
+        .await;
+    assert_eq!(generation + 1, server.store.generation(&uri));
+}
+
 /// When a `textDocument/didChange` presents a file change for a file
 /// using composition, the updated file gets saved on the stateful composition.
 #[test]
@@ -152,7 +310,7 @@ async fn test_did_change_updates_composition_file() {
     open_file(&server, "".to_string(), None).await;
 
     let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
-    match server.state.lock() {
+    match server.state.write() {
         Ok(mut state) => {
             let ast = flux::parser::parse_string("".to_string(), "");
             let composition = composition::Composition::new(
@@ -197,7 +355,7 @@ async fn test_did_change_updates_composition_file() {
 
     server.did_change(params).await;
 
-    match server.state.lock() {
+    match server.state.write() {
         Ok(mut state) => {
             assert!(state.get_mut_composition(&uri).is_some());
             assert_eq!(
@@ -222,7 +380,7 @@ async fn test_did_change_vacates_composition() {
     )
     .await;
 
-    match server.state.lock() {
+    match server.state.write() {
         Ok(mut state) => {
             let ast = flux::parser::parse_string(
                 "".to_string(),
@@ -264,7 +422,7 @@ from(bucket: "bucket")
 
     server.did_change(params).await;
 
-    match server.state.lock() {
+    match server.state.write() {
         Ok(mut state) => {
             let key = lsp::Url::parse("file:///home/user/file.flux")
                 .unwrap();
@@ -286,7 +444,7 @@ async fn test_did_change_bad_ast_doesnt_vacate_composition() {
     )
     .await;
 
-    match server.state.lock() {
+    match server.state.write() {
         Ok(mut state) => {
             let ast = flux::parser::parse_string(
                 "".to_string(),
@@ -328,7 +486,7 @@ from(bucket: "bucket")
 
     server.did_change(params).await;
 
-    match server.state.lock() {
+    match server.state.write() {
         Ok(mut state) => {
             let key = lsp::Url::parse("file:///home/user/file.flux")
                 .unwrap();
@@ -338,6 +496,137 @@ from(bucket: "bucket")
     };
 }
 
+/// While typing, the composition statement may briefly stop matching. The composition
+/// is only dropped if it stays missing for several changes.
+#[test]
+async fn test_did_change_missing_statement_vacates_composition_eventually(
+) {
+    let server = create_server();
+    open_file(
+        &server,
+        r#"from(bucket: "bucket") |> first()"#.to_string(),
+        None,
+    )
+    .await;
+
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    match server.state.write() {
+        Ok(mut state) => {
+            let ast = flux::parser::parse_string(
+                "".to_string(),
+                &r#"from(bucket: "bucket") |> first()"#,
+            );
+            let composition = composition::Composition::new(
+                ast,
+                "bucket".to_string(),
+                None,
+                vec![],
+                vec![],
+            );
+            state.set_composition(uri.clone(), composition);
+        }
+        Err(err) => panic!("{}", err),
+    }
+
+    for version in 1..=3 {
+        let params = lsp::DidChangeTextDocumentParams {
+            text_document: lsp::VersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version,
+            },
+            content_changes: vec![
+                lsp::TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: format!("x = {}", version),
+                },
+            ],
+        };
+
+        server.did_change(params).await;
+
+        let exists = server
+            .state
+            .write()
+            .unwrap()
+            .get_mut_composition(&uri)
+            .is_some();
+        assert_eq!(version < 3, exists, "version {}", version);
+    }
+}
+
+/// A change made while the composition is being resolved for an earlier one is left
+/// to the task resolving it, rather than resolving the composition again.
+#[test]
+async fn test_did_change_stale_version_doesnt_resolve_composition() {
+    let server = create_server();
+    open_file(&server, "".to_string(), None).await;
+
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let original = match server.state.write() {
+        Ok(mut state) => {
+            let ast = flux::parser::parse_string("".to_string(), "");
+            let composition = composition::Composition::new(
+                ast,
+                "bucket".to_string(),
+                Some("myMeasurement".to_string()),
+                vec![],
+                vec![],
+            );
+            let original = composition.to_string();
+            state.set_composition(uri.clone(), composition);
+            state.queue_composition_resolution(&uri, 5);
+            original
+        }
+        Err(err) => panic!("{}", err),
+    };
+
+    let params = lsp::DidChangeTextDocumentParams {
+        text_document: lsp::VersionedTextDocumentIdentifier {
+            uri: uri.clone(),
+            version: 4,
+        },
+        content_changes: vec![lsp::TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: r#"from(bucket: "bucket")
+    |> range(start: -30m)
+    |> filter(fn: (r) => r._measurement == "myMeasurement")
+    |> first()
+"#
+            .to_string(),
+        }],
+    };
+
+    server.did_change(params).await;
+
+    assert_eq!(
+        original,
+        server
+            .state
+            .write()
+            .unwrap()
+            .get_mut_composition(&uri)
+            .unwrap()
+            .to_string()
+    );
+}
+
+/// The changes queued while the composition of a document is being resolved are
+/// resolved together, and the task resolving it is done once none are left.
+#[test]
+async fn test_composition_resolution_queue() {
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let mut state = LspServerState::default();
+
+    assert!(state.queue_composition_resolution(&uri, 1));
+    assert!(!state.queue_composition_resolution(&uri, 3));
+    assert!(!state.queue_composition_resolution(&uri, 2));
+    assert!(state.take_composition_resolution(&uri));
+    assert!(!state.take_composition_resolution(&uri));
+    assert!(state.queue_composition_resolution(&uri, 4));
+}
+
 #[test]
 async fn test_did_change_multiple() {
     let server = create_server();
@@ -399,6 +688,84 @@ async fn test_did_close() {
         .is_err());
 }
 
+/// Records the events of documents, with the number of statements of their files.
+#[derive(Default)]
+struct RecordingObserver {
+    events: std::sync::Mutex<Vec<(&'static str, usize)>>,
+}
+
+impl DocumentObserver for RecordingObserver {
+    fn opened(&self, _uri: &lsp::Url, file: &ast::File) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(("opened", file.body.len()));
+    }
+
+    fn changed(&self, _uri: &lsp::Url, file: &ast::File) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(("changed", file.body.len()));
+    }
+
+    fn closed(&self, _uri: &lsp::Url) {
+        self.events.lock().unwrap().push(("closed", 0));
+    }
+}
+
+#[test]
+async fn test_document_observer() {
+    let observer = Arc::new(RecordingObserver::default());
+    let server = create_server().with_observer(observer.clone());
+    open_file(&server, "a = 1".to_string(), None).await;
+
+    server
+        .did_change(lsp::DidChangeTextDocumentParams {
+            text_document: lsp::VersionedTextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+                version: 2,
+            },
+            content_changes: vec![
+                lsp::TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: "a = 1\nb = 2".to_string(),
+                },
+            ],
+        })
+        .await;
+    server
+        .did_close(lsp::DidCloseTextDocumentParams {
+            text_document: lsp::TextDocumentIdentifier::new(
+                lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+            ),
+        })
+        .await;
+
+    assert_eq!(
+        vec![("opened", 1), ("changed", 2), ("closed", 0)],
+        *observer.events.lock().unwrap()
+    );
+}
+
+/// Documents are kept in the store registered by the embedder.
+#[test]
+async fn test_custom_document_store() {
+    let documents = Arc::new(MemoryStore::default());
+    let server = create_server().with_store(documents.clone());
+    open_file(&server, "a = 1".to_string(), None).await;
+
+    assert_eq!(
+        Some("a = 1".to_string()),
+        documents.get(
+            &lsp::Url::parse("file:///home/user/file.flux").unwrap()
+        )
+    );
+}
+
 // If the file hasn't been opened on the server get, return an error.
 #[test]
 async fn test_signature_help_not_opened() {
@@ -726,88 +1093,254 @@ errorCounts
     assert_eq!(vec![expected], result);
 }
 
+/// A client can't pick the executable tests are run with when the embedder chose it,
+/// as the binary does for clients connecting over a socket.
+#[cfg(feature = "cmd")]
 #[test]
-async fn test_folding_not_opened() {
-    let server = create_server();
-
-    let params = lsp::FoldingRangeParams {
-        text_document: lsp::TextDocumentIdentifier {
-            uri: lsp::Url::parse("file:///home/user/file.flux")
-                .unwrap(),
-        },
-        work_done_progress_params: lsp::WorkDoneProgressParams {
-            work_done_token: None,
-        },
-        partial_result_params: lsp::PartialResultParams {
-            partial_result_token: None,
-        },
-    };
-
-    let result = server.folding_range(params).await;
+async fn test_flux_command_setting_ignored_when_fixed() {
+    let server = create_server().with_flux_command("flux");
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"fluxCommand": "/bin/sh"}}),
+        })
+        .await;
 
-    assert!(result.is_err());
+    assert_eq!("flux", server.read_state().flux_command());
 }
 
+/// The `fluxCommand` setting picks the executable tests are run with otherwise.
+#[cfg(feature = "cmd")]
 #[test]
-async fn test_folding() {
-    let fluxscript = r#"import "strings"
-env = "prod01-us-west-2"
+async fn test_flux_command_setting() {
+    let server = create_server();
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"fluxCommand": "/opt/flux"}}),
+        })
+        .await;
 
-errorCounts = from(bucket:"kube-infra/monthly")
-    |> range(start: -3d)
-    |> filter(fn: (r) => r._measurement == "query_log" and
-                         r.error != "" and
-                         r._field == "responseSize" and
-                         r.env == env)
-    |> group(columns:["env", "error"])
-    |> count()
-    |> group(columns:["env", "_stop", "_start"])
+    assert_eq!("/opt/flux", server.read_state().flux_command());
+}
 
-errorCounts
-    |> filter(fn: (r) => strings.containsStr(v: r.error, substr: "AppendMappedRecordWithNulls"))"#;
+/// Pipelines are broken into one stage per line with the `formatStagePerLine` setting.
+#[test]
+async fn test_formatting_stage_per_line() {
+    let fluxscript = r#"from(bucket: "a") |> range(start: -1h) |> mean()
+"#;
     let server = create_server();
     open_file(&server, fluxscript.to_string(), None).await;
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"formatStagePerLine": true}}),
+        })
+        .await;
 
-    let params = lsp::FoldingRangeParams {
+    let params = lsp::DocumentFormattingParams {
         text_document: lsp::TextDocumentIdentifier {
             uri: lsp::Url::parse("file:///home/user/file.flux")
                 .unwrap(),
         },
+        options: lsp::FormattingOptions {
+            tab_size: 0,
+            insert_spaces: false,
+            properties:
+                HashMap::<String, lsp::FormattingProperty>::new(),
+            trim_trailing_whitespace: None,
+            insert_final_newline: None,
+            trim_final_newlines: None,
+        },
         work_done_progress_params: lsp::WorkDoneProgressParams {
             work_done_token: None,
         },
-        partial_result_params: lsp::PartialResultParams {
-            partial_result_token: None,
-        },
     };
+    let result = server.formatting(params).await.unwrap().unwrap();
 
-    let result = server.folding_range(params).await.unwrap().unwrap();
-
-    let expected = vec![
-        lsp::FoldingRange {
-            start_line: 6,
-            start_character: Some(26),
-            end_line: 9,
-            end_character: Some(38),
-            kind: Some(lsp::FoldingRangeKind::Region),
-        },
-        lsp::FoldingRange {
-            start_line: 15,
-            start_character: Some(26),
-            end_line: 15,
-            end_character: Some(96),
-            kind: Some(lsp::FoldingRangeKind::Region),
-        },
-    ];
-
-    assert_eq!(expected, result);
+    assert_eq!(
+        r#"from(bucket: "a")
+    |> range(start: -1h)
+    |> mean()
+"#,
+        result[0].new_text
+    );
 }
 
+/// The indentation of the formatter is redone as the client's options ask.
 #[test]
-async fn test_document_symbol_not_opened() {
+async fn test_formatting_indentation_options() {
+    let fluxscript = r#"from(bucket: "a")
+|> range(start: -1h)
+"#;
     let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
 
-    let params = lsp::DocumentSymbolParams {
+    let params =
+        |tab_size, insert_spaces| lsp::DocumentFormattingParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+            },
+            options: lsp::FormattingOptions {
+                tab_size,
+                insert_spaces,
+                properties:
+                    HashMap::<String, lsp::FormattingProperty>::new(),
+                trim_trailing_whitespace: None,
+                insert_final_newline: Some(true),
+                trim_final_newlines: Some(true),
+            },
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+        };
+
+    let result =
+        server.formatting(params(2, true)).await.unwrap().unwrap();
+    assert_eq!(
+        "from(bucket: \"a\")\n  |> range(start: -1h)\n",
+        result[0].new_text
+    );
+    let result =
+        server.formatting(params(4, false)).await.unwrap().unwrap();
+    assert_eq!(
+        "from(bucket: \"a\")\n\t|> range(start: -1h)\n",
+        result[0].new_text
+    );
+}
+
+/// Documents are formatted before explicit saves once `formatOnSave` is enabled.
+#[test]
+async fn test_will_save_wait_until_format_on_save() {
+    let fluxscript = r#"from(bucket:"a")|>range(start:-1h)"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = |reason| lsp::WillSaveTextDocumentParams {
+        text_document: lsp::TextDocumentIdentifier {
+            uri: lsp::Url::parse("file:///home/user/file.flux")
+                .unwrap(),
+        },
+        reason,
+    };
+    assert_eq!(
+        None,
+        server
+            .will_save_wait_until(params(
+                lsp::TextDocumentSaveReason::MANUAL
+            ))
+            .await
+            .unwrap()
+    );
+
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"formatOnSave": true}}),
+        })
+        .await;
+
+    let edits = server
+        .will_save_wait_until(params(
+            lsp::TextDocumentSaveReason::MANUAL,
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        r#"from(bucket: "a") |> range(start: -1h)"#,
+        edits[0].new_text.trim_end()
+    );
+    assert_eq!(
+        None,
+        server
+            .will_save_wait_until(params(
+                lsp::TextDocumentSaveReason::AFTER_DELAY
+            ))
+            .await
+            .unwrap()
+    );
+}
+
+#[test]
+async fn test_folding_not_opened() {
+    let server = create_server();
+
+    let params = lsp::FoldingRangeParams {
+        text_document: lsp::TextDocumentIdentifier {
+            uri: lsp::Url::parse("file:///home/user/file.flux")
+                .unwrap(),
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
+
+    let result = server.folding_range(params).await;
+
+    assert!(result.is_err());
+}
+
+#[test]
+async fn test_folding() {
+    let fluxscript = r#"import "strings"
+env = "prod01-us-west-2"
+
+errorCounts = from(bucket:"kube-infra/monthly")
+    |> range(start: -3d)
+    |> filter(fn: (r) => r._measurement == "query_log" and
+                         r.error != "" and
+                         r._field == "responseSize" and
+                         r.env == env)
+    |> group(columns:["env", "error"])
+    |> count()
+    |> group(columns:["env", "_stop", "_start"])
+
+errorCounts
+    |> filter(fn: (r) => strings.containsStr(v: r.error, substr: "AppendMappedRecordWithNulls"))"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::FoldingRangeParams {
+        text_document: lsp::TextDocumentIdentifier {
+            uri: lsp::Url::parse("file:///home/user/file.flux")
+                .unwrap(),
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
+
+    let result = server.folding_range(params).await.unwrap().unwrap();
+
+    let expected = vec![
+        lsp::FoldingRange {
+            start_line: 5,
+            start_character: Some(25),
+            end_line: 8,
+            end_character: Some(37),
+            kind: Some(lsp::FoldingRangeKind::Region),
+        },
+        lsp::FoldingRange {
+            start_line: 14,
+            start_character: Some(25),
+            end_line: 14,
+            end_character: Some(95),
+            kind: Some(lsp::FoldingRangeKind::Region),
+        },
+    ];
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+async fn test_document_symbol_not_opened() {
+    let server = create_server();
+
+    let params = lsp::DocumentSymbolParams {
         text_document: lsp::TextDocumentIdentifier {
             uri: lsp::Url::parse("file:///home/user/file.flux")
                 .unwrap(),
@@ -918,6 +1451,183 @@ errorCounts
     }
 }
 
+/// Options are outlined with the properties of their record, and builtins as
+/// interfaces.
+#[test]
+async fn test_document_symbol_option_and_builtin() {
+    let fluxscript = r#"option task = {name: "downsample", every: 1h}
+option app = {db: {host: "localhost"}}
+
+builtin double : (v: int) => int
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::DocumentSymbolParams {
+        text_document: lsp::TextDocumentIdentifier {
+            uri: lsp::Url::parse("file:///home/user/file.flux")
+                .unwrap(),
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
+    let symbol_response =
+        server.document_symbol(params).await.unwrap().unwrap();
+
+    match symbol_response {
+        lsp::DocumentSymbolResponse::Flat(symbols) => {
+            assert_eq!(
+                vec![
+                    ("task", lsp::SymbolKind::PROPERTY, None),
+                    ("name", lsp::SymbolKind::FIELD, Some("task")),
+                    ("every", lsp::SymbolKind::FIELD, Some("task")),
+                    ("app", lsp::SymbolKind::PROPERTY, None),
+                    ("db", lsp::SymbolKind::FIELD, Some("app")),
+                    ("host", lsp::SymbolKind::FIELD, Some("db")),
+                    ("double", lsp::SymbolKind::INTERFACE, None),
+                ],
+                symbols
+                    .iter()
+                    .map(|symbol| (
+                        symbol.name.as_str(),
+                        symbol.kind,
+                        symbol.container_name.as_deref()
+                    ))
+                    .collect::<Vec<_>>()
+            );
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Clients showing symbols as a hierarchy get the constructs enclosing each symbol,
+/// spanning the whole construct and selecting its name.
+#[test]
+async fn test_document_symbol_hierarchy() {
+    let fluxscript = r#"f = (tables=<-) => tables
+    |> filter(fn: (r) => r._value > 0)
+
+from(bucket: "b")
+    |> range(start: -1h)
+    |> f()
+"#;
+    let server = create_server();
+    server
+        .initialize(lsp::InitializeParams {
+            capabilities: lsp::ClientCapabilities {
+                text_document: Some(
+                    lsp::TextDocumentClientCapabilities {
+                        document_symbol: Some(
+                            lsp::DocumentSymbolClientCapabilities {
+                                hierarchical_document_symbol_support:
+                                    Some(true),
+                                ..Default::default()
+                            },
+                        ),
+                        ..Default::default()
+                    },
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::DocumentSymbolParams {
+        text_document: lsp::TextDocumentIdentifier {
+            uri: lsp::Url::parse("file:///home/user/file.flux")
+                .unwrap(),
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
+    let symbols = match server
+        .document_symbol(params)
+        .await
+        .unwrap()
+        .unwrap()
+    {
+        lsp::DocumentSymbolResponse::Nested(symbols) => symbols,
+        _ => unreachable!(),
+    };
+
+    fn outline(
+        symbols: &[lsp::DocumentSymbol],
+        depth: usize,
+    ) -> Vec<(usize, String, lsp::SymbolKind)> {
+        symbols
+            .iter()
+            .flat_map(|symbol| {
+                std::iter::once((
+                    depth,
+                    symbol.name.clone(),
+                    symbol.kind,
+                ))
+                .chain(outline(
+                    symbol.children.as_deref().unwrap_or_default(),
+                    depth + 1,
+                ))
+            })
+            .collect()
+    }
+    assert_eq!(
+        vec![
+            (0, "f".to_string(), lsp::SymbolKind::FUNCTION),
+            (1, "tables".to_string(), lsp::SymbolKind::VARIABLE),
+            (1, "filter".to_string(), lsp::SymbolKind::FUNCTION),
+            (
+                0,
+                "from |> range |> f".to_string(),
+                lsp::SymbolKind::OPERATOR
+            ),
+            (1, "from".to_string(), lsp::SymbolKind::FUNCTION),
+            (1, "range".to_string(), lsp::SymbolKind::FUNCTION),
+            (1, "f".to_string(), lsp::SymbolKind::FUNCTION),
+        ],
+        outline(&symbols, 0)
+    );
+
+    assert_eq!(
+        lsp::Range::new(
+            lsp::Position::new(0, 0),
+            lsp::Position::new(1, 38)
+        ),
+        symbols[0].range
+    );
+    assert_eq!(
+        lsp::Range::new(
+            lsp::Position::new(0, 0),
+            lsp::Position::new(0, 1)
+        ),
+        symbols[0].selection_range
+    );
+    let filter = &symbols[0].children.as_ref().unwrap()[1];
+    assert_eq!(
+        lsp::Range::new(
+            lsp::Position::new(1, 7),
+            lsp::Position::new(1, 38)
+        ),
+        filter.range
+    );
+    assert_eq!(
+        lsp::Range::new(
+            lsp::Position::new(1, 7),
+            lsp::Position::new(1, 13)
+        ),
+        filter.selection_range
+    );
+}
+
 #[test]
 async fn test_goto_definition_not_opened() {
     let server = create_server();
@@ -1004,6 +1714,63 @@ errorCounts
     assert_eq!(expected, result);
 }
 
+/// Going to the definition of a member property jumps to the property in the object
+/// literal, following nested objects.
+#[test]
+async fn test_goto_definition_member_property() {
+    let fluxscript = r#"o = {x: 1, y: 2, inner: {z: 3}}
+o.y
+o.inner.z
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let goto_definition = |position: lsp::Position| {
+        server.goto_definition(lsp::GotoDefinitionParams {
+            text_document_position_params:
+                lsp::TextDocumentPositionParams::new(
+                    lsp::TextDocumentIdentifier::new(
+                        lsp::Url::parse(
+                            "file:///home/user/file.flux",
+                        )
+                        .unwrap(),
+                    ),
+                    position,
+                ),
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: lsp::PartialResultParams {
+                partial_result_token: None,
+            },
+        })
+    };
+    let location = |start: u32, end: u32| {
+        Some(lsp::GotoDefinitionResponse::Scalar(lsp::Location {
+            uri: lsp::Url::parse("file:///home/user/file.flux")
+                .unwrap(),
+            range: lsp::Range {
+                start: lsp::Position {
+                    line: 0,
+                    character: start,
+                },
+                end: lsp::Position {
+                    line: 0,
+                    character: end,
+                },
+            },
+        }))
+    };
+
+    let result =
+        goto_definition(lsp::Position::new(1, 2)).await.unwrap();
+    assert_eq!(location(11, 12), result);
+
+    let result =
+        goto_definition(lsp::Position::new(2, 8)).await.unwrap();
+    assert_eq!(location(25, 26), result);
+}
+
 #[test]
 async fn test_goto_definition_builtin() {
     let fluxscript = r#"
@@ -1144,12 +1911,12 @@ errorCounts
             new_text: "environment".to_string(),
             range: lsp::Range {
                 start: lsp::Position {
-                    line: 1,
-                    character: 0,
+                    line: 8,
+                    character: 34,
                 },
                 end: lsp::Position {
-                    line: 1,
-                    character: 3,
+                    line: 8,
+                    character: 37,
                 },
             },
         },
@@ -1157,12 +1924,12 @@ errorCounts
             new_text: "environment".to_string(),
             range: lsp::Range {
                 start: lsp::Position {
-                    line: 8,
-                    character: 34,
+                    line: 1,
+                    character: 0,
                 },
                 end: lsp::Position {
-                    line: 8,
-                    character: 37,
+                    line: 1,
+                    character: 3,
                 },
             },
         },
@@ -1183,20 +1950,236 @@ errorCounts
     assert_eq!(expected, result);
 }
 
+/// Renaming a record property updates both `r.name` and `r["name"]` accesses. A name
+/// that isn't a valid identifier turns `r.name` into a string access.
 #[test]
-async fn test_references() {
-    let fluxscript = r#"import "strings"
-env = "prod01-us-west-2"
-
-errorCounts = from(bucket:"kube-infra/monthly")
-    |> range(start: -3d)
-    |> filter(fn: (r) => r._measurement == "query_log" and
-                         r.error != "" and
-                         r._field == "responseSize" and
-                         r.env == env)
-    |> group(columns:["env", "error"])
-    |> count()
-    |> group(columns:["env", "_stop", "_start"])
+async fn test_rename_record_property() {
+    let fluxscript = r#"from(bucket: "b")
+    |> filter(fn: (r) => r.my_tag == "a")
+    |> map(fn: (r) => ({r with x: r["my_tag"]}))"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let params = lsp::RenameParams {
+        text_document_position: lsp::TextDocumentPositionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: uri.clone(),
+            },
+            position: lsp::Position {
+                line: 2,
+                character: 38,
+            },
+        },
+        new_name: "my tag".to_string(),
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    };
+
+    let result = server.rename(params).await.unwrap().unwrap();
+
+    let mut edits = result.changes.unwrap().remove(&uri).unwrap();
+    edits.sort_by_key(|edit| edit.range.start);
+    assert_eq!(
+        vec![
+            lsp::TextEdit {
+                new_text: r#"["my tag"]"#.to_string(),
+                range: lsp::Range {
+                    start: lsp::Position {
+                        line: 1,
+                        character: 26,
+                    },
+                    end: lsp::Position {
+                        line: 1,
+                        character: 33,
+                    },
+                },
+            },
+            lsp::TextEdit {
+                new_text: r#""my tag""#.to_string(),
+                range: lsp::Range {
+                    start: lsp::Position {
+                        line: 2,
+                        character: 36,
+                    },
+                    end: lsp::Position {
+                        line: 2,
+                        character: 44,
+                    },
+                },
+            },
+        ],
+        edits
+    );
+}
+
+/// Renaming a definition at the top level of a file renames its references in the
+/// other files of the package.
+#[test]
+async fn test_rename_across_package_files() {
+    let server = create_server();
+    open_file(
+        &server,
+        r#"threshold = 10"#.to_string(),
+        Some("file:///path/to/vars.flux"),
+    )
+    .await;
+    open_file(
+        &server,
+        r#"from(bucket: "b")
+    |> filter(fn: (r) => r._value > threshold)"#
+            .to_string(),
+        Some("file:///path/to/script.flux"),
+    )
+    .await;
+
+    let params = lsp::RenameParams {
+        text_document_position: lsp::TextDocumentPositionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///path/to/script.flux")
+                    .unwrap(),
+            },
+            position: lsp::Position {
+                line: 1,
+                character: 38,
+            },
+        },
+        new_name: "limit".to_string(),
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    };
+
+    let result = server.rename(params).await.unwrap().unwrap();
+
+    let edit = |line, start, end| lsp::TextEdit {
+        new_text: "limit".to_string(),
+        range: lsp::Range {
+            start: lsp::Position {
+                line,
+                character: start,
+            },
+            end: lsp::Position {
+                line,
+                character: end,
+            },
+        },
+    };
+    assert_eq!(
+        HashMap::from([
+            (
+                lsp::Url::parse("file:///path/to/vars.flux").unwrap(),
+                vec![edit(0, 0, 9)],
+            ),
+            (
+                lsp::Url::parse("file:///path/to/script.flux")
+                    .unwrap(),
+                vec![edit(1, 36, 45)],
+            ),
+        ]),
+        result.changes.unwrap()
+    );
+}
+
+/// A parameter shadowing a definition at the top level of the package is renamed
+/// within its function only, and the definition renamed outside of that function.
+#[test]
+async fn test_rename_shadowed_parameter() {
+    let server = create_server();
+    open_file(
+        &server,
+        r#"x = 10"#.to_string(),
+        Some("file:///path/to/vars.flux"),
+    )
+    .await;
+    open_file(
+        &server,
+        r#"double = (x) => x * 2
+y = x + 1"#
+            .to_string(),
+        Some("file:///path/to/script.flux"),
+    )
+    .await;
+
+    let rename = |line, character| {
+        let params = lsp::RenameParams {
+            text_document_position: lsp::TextDocumentPositionParams {
+                text_document: lsp::TextDocumentIdentifier {
+                    uri: lsp::Url::parse(
+                        "file:///path/to/script.flux",
+                    )
+                    .unwrap(),
+                },
+                position: lsp::Position { line, character },
+            },
+            new_name: "z".to_string(),
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+        };
+        let server = &server;
+        async move {
+            server
+                .rename(params)
+                .await
+                .unwrap()
+                .unwrap()
+                .changes
+                .unwrap()
+        }
+    };
+    let edit = |line, start, end| lsp::TextEdit {
+        new_text: "z".to_string(),
+        range: lsp::Range {
+            start: lsp::Position {
+                line,
+                character: start,
+            },
+            end: lsp::Position {
+                line,
+                character: end,
+            },
+        },
+    };
+
+    assert_eq!(
+        HashMap::from([(
+            lsp::Url::parse("file:///path/to/script.flux").unwrap(),
+            vec![edit(0, 10, 11), edit(0, 16, 17)],
+        )]),
+        rename(0, 16).await
+    );
+    assert_eq!(
+        HashMap::from([
+            (
+                lsp::Url::parse("file:///path/to/vars.flux").unwrap(),
+                vec![edit(0, 0, 1)],
+            ),
+            (
+                lsp::Url::parse("file:///path/to/script.flux")
+                    .unwrap(),
+                vec![edit(1, 4, 5)],
+            ),
+        ]),
+        rename(1, 4).await
+    );
+}
+
+#[test]
+async fn test_references() {
+    let fluxscript = r#"import "strings"
+env = "prod01-us-west-2"
+
+errorCounts = from(bucket:"kube-infra/monthly")
+    |> range(start: -3d)
+    |> filter(fn: (r) => r._measurement == "query_log" and
+                         r.error != "" and
+                         r._field == "responseSize" and
+                         r.env == env)
+    |> group(columns:["env", "error"])
+    |> count()
+    |> group(columns:["env", "_stop", "_start"])
 
 errorCounts
     |> filter(fn: (r) => strings.containsStr(v: r.error, substr: "AppendMappedRecordWithNulls"))"#;
@@ -1269,6 +2252,128 @@ errorCounts
     assert_eq!(expected, result);
 }
 
+#[test]
+async fn test_moniker() {
+    let fluxscript = r#"import "strings"
+
+x = strings.title(v: "a")
+y = length(arr: [1])
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let moniker = |line, character| {
+        let params = lsp::MonikerParams {
+            text_document_position_params:
+                lsp::TextDocumentPositionParams {
+                    text_document: lsp::TextDocumentIdentifier {
+                        uri: lsp::Url::parse(
+                            "file:///home/user/file.flux",
+                        )
+                        .unwrap(),
+                    },
+                    position: lsp::Position::new(line, character),
+                },
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: lsp::PartialResultParams {
+                partial_result_token: None,
+            },
+        };
+        let server = &server;
+        async move {
+            server.moniker(params).await.unwrap().map(|monikers| {
+                monikers
+                    .into_iter()
+                    .map(|moniker| {
+                        format!(
+                            "{}:{}",
+                            moniker.scheme, moniker.identifier
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+        }
+    };
+
+    assert_eq!(
+        Some(vec!["flux:stdlib/strings#title".to_string()]),
+        moniker(2, 14).await
+    );
+    assert_eq!(
+        Some(vec!["flux:stdlib/universe#length".to_string()]),
+        moniker(3, 6).await
+    );
+    // Variables of the script have no moniker.
+    assert_eq!(None, moniker(2, 0).await);
+}
+
+/// References of a record property include both `r.name` and `r["name"]` accesses.
+#[test]
+async fn test_references_record_property() {
+    let fluxscript = r#"from(bucket: "b")
+    |> filter(fn: (r) => r.my_tag == "a")
+    |> map(fn: (r) => ({r with x: r["my_tag"]}))"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let params = lsp::ReferenceParams {
+        text_document_position: lsp::TextDocumentPositionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: uri.clone(),
+            },
+            position: lsp::Position {
+                line: 1,
+                character: 28,
+            },
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+        context: lsp::ReferenceContext {
+            include_declaration: true,
+        },
+    };
+
+    let mut result =
+        server.references(params).await.unwrap().unwrap();
+    result.sort_by_key(|location| location.range.start);
+
+    assert_eq!(
+        vec![
+            lsp::Range {
+                start: lsp::Position {
+                    line: 1,
+                    character: 27,
+                },
+                end: lsp::Position {
+                    line: 1,
+                    character: 33,
+                },
+            },
+            lsp::Range {
+                start: lsp::Position {
+                    line: 2,
+                    character: 36,
+                },
+                end: lsp::Position {
+                    line: 2,
+                    character: 44,
+                },
+            },
+        ],
+        result
+            .iter()
+            .map(|location| location.range)
+            .collect::<Vec<lsp::Range>>()
+    );
+}
+
 #[test]
 async fn test_references_duplicates() {
     let fluxscript = r#"
@@ -1415,54 +2520,185 @@ errorCounts
     assert_eq!(expected, result);
 }
 
-fn hover_params(pos: lsp::Position) -> lsp::HoverParams {
-    lsp::HoverParams {
-        text_document_position_params:
-            lsp::TextDocumentPositionParams::new(
-                lsp::TextDocumentIdentifier::new(
-                    lsp::Url::parse("file:///home/user/file.flux")
-                        .unwrap(),
-                ),
-                pos,
-            ),
-        work_done_progress_params: lsp::WorkDoneProgressParams {
-            work_done_token: None,
-        },
-    }
-}
-
+/// On the name of a function called by a pipeline stage, only the stages of the same
+/// pipeline calling that function are highlighted.
 #[test]
-async fn test_hover() {
-    let fluxscript = r#"x = 1
-x + 1
-"#;
+async fn test_document_highlight_pipeline_stages() {
+    let fluxscript = r#"errorCounts = from(bucket:"kube-infra/monthly")
+    |> range(start: -3d)
+    |> filter(fn: (r) => r._measurement == "query_log")
+    |> group(columns:["env", "error"])
+    |> count()
+    |> group(columns:["env", "_stop", "_start"])
+
+errorCounts
+    |> filter(fn: (r) => r.env == "prod")
+    |> group()"#;
     let server = create_server();
     open_file(&server, fluxscript.to_string(), None).await;
 
-    let params = hover_params(lsp::Position::new(1, 1));
-
-    let result = server.hover(params).await.unwrap();
+    let highlights = |line, character| {
+        let params = lsp::DocumentHighlightParams {
+            text_document_position_params:
+                lsp::TextDocumentPositionParams {
+                    text_document: lsp::TextDocumentIdentifier {
+                        uri: lsp::Url::parse(
+                            "file:///home/user/file.flux",
+                        )
+                        .unwrap(),
+                    },
+                    position: lsp::Position { line, character },
+                },
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: lsp::PartialResultParams {
+                partial_result_token: None,
+            },
+        };
+        let server = &server;
+        async move {
+            server
+                .document_highlight(params)
+                .await
+                .unwrap()
+                .unwrap()
+                .iter()
+                .map(|highlight| {
+                    (
+                        highlight.range.start.line,
+                        highlight.range.start.character,
+                        highlight.range.end.character,
+                    )
+                })
+                .collect::<Vec<(u32, u32, u32)>>()
+        }
+    };
 
-    assert_eq!(
-        result,
-        Some(lsp::Hover {
-            contents: lsp::HoverContents::Scalar(
-                lsp::MarkedString::String("int".to_string())
-            ),
-            range: None,
-        })
-    );
+    assert_eq!(vec![(3, 7, 12), (5, 7, 12)], highlights(3, 9).await);
+    assert_eq!(vec![(2, 7, 13)], highlights(2, 9).await);
+    assert_eq!(vec![(8, 7, 13)], highlights(8, 9).await);
 }
 
+/// On a measurement name, the other places the same measurement is written are
+/// highlighted.
 #[test]
-async fn test_hover_with_markdown() {
-    let fluxscript = r#"x = 1
-x + 1
-"#;
+async fn test_document_highlight_measurement() {
+    let fluxscript = r#"from(bucket: "telegraf")
+    |> range(start: -1h)
+    |> filter(fn: (r) => r._measurement == "cpu")
+    |> set(key: "_measurement", value: "cpu_1h")
+    |> to(bucket: "downsampled")
+
+from(bucket: "downsampled")
+    |> range(start: -1d)
+    |> filter(fn: (r) => r._measurement == "cpu_1h" and r.host == "cpu")"#;
     let server = create_server();
-    let params = lsp::InitializeParams {
-        capabilities: lsp::ClientCapabilities {
-            workspace: None,
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::DocumentHighlightParams {
+        text_document_position_params:
+            lsp::TextDocumentPositionParams {
+                text_document: lsp::TextDocumentIdentifier {
+                    uri: lsp::Url::parse(
+                        "file:///home/user/file.flux",
+                    )
+                    .unwrap(),
+                },
+                position: lsp::Position {
+                    line: 3,
+                    character: 40,
+                },
+            },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
+
+    let highlights =
+        server.document_highlight(params).await.unwrap().unwrap();
+
+    assert_eq!(
+        vec![
+            lsp::Range {
+                start: lsp::Position {
+                    line: 3,
+                    character: 39,
+                },
+                end: lsp::Position {
+                    line: 3,
+                    character: 47,
+                },
+            },
+            lsp::Range {
+                start: lsp::Position {
+                    line: 8,
+                    character: 43,
+                },
+                end: lsp::Position {
+                    line: 8,
+                    character: 51,
+                },
+            },
+        ],
+        highlights
+            .into_iter()
+            .map(|highlight| highlight.range)
+            .collect::<Vec<lsp::Range>>()
+    );
+}
+
+fn hover_params(pos: lsp::Position) -> lsp::HoverParams {
+    lsp::HoverParams {
+        text_document_position_params:
+            lsp::TextDocumentPositionParams::new(
+                lsp::TextDocumentIdentifier::new(
+                    lsp::Url::parse("file:///home/user/file.flux")
+                        .unwrap(),
+                ),
+                pos,
+            ),
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    }
+}
+
+#[test]
+async fn test_hover() {
+    let fluxscript = r#"x = 1
+x + 1
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = hover_params(lsp::Position::new(1, 1));
+
+    let result = server.hover(params).await.unwrap();
+
+    assert_eq!(
+        result,
+        Some(lsp::Hover {
+            contents: lsp::HoverContents::Scalar(
+                lsp::MarkedString::String("int = 1".to_string())
+            ),
+            range: None,
+        })
+    );
+}
+
+#[test]
+async fn test_hover_with_markdown() {
+    let fluxscript = r#"x = 1
+x + 1
+"#;
+    let server = create_server();
+    let params = lsp::InitializeParams {
+        capabilities: lsp::ClientCapabilities {
+            workspace: None,
             text_document: Some(
                 lsp::TextDocumentClientCapabilities {
                     hover: Some(lsp::HoverClientCapabilities {
@@ -1501,7 +2737,7 @@ x + 1
             contents: lsp::HoverContents::Markup(
                 lsp::MarkupContent {
                     kind: lsp::MarkupKind::Markdown,
-                    value: String::from("```flux\nint\n```")
+                    value: String::from("```flux\nint = 1\n```")
                 }
             ),
             range: None,
@@ -1528,7 +2764,9 @@ option option_ = 123
         result,
         Some(lsp::Hover {
             contents: lsp::HoverContents::Scalar(
-                lsp::MarkedString::String("string".to_string())
+                lsp::MarkedString::String(
+                    "string = \"asd\"".to_string()
+                )
             ),
             range: None,
         })
@@ -1567,6 +2805,257 @@ option option_ = 123
     );
 }
 
+/// Hovering a pipeline assignment shows which stages are estimated to run in storage.
+#[test]
+async fn test_hover_pushdown_boundary() {
+    let fluxscript = r#"data = from(bucket: "a")
+    |> range(start: -1h)
+    |> map(fn: (r) => ({r with _value: 1.0}))
+    |> sum()
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let result = server
+        .hover(hover_params(lsp::Position::new(0, 1)))
+        .await
+        .unwrap()
+        .unwrap();
+
+    match result.contents {
+        lsp::HoverContents::Scalar(lsp::MarkedString::String(
+            value,
+        )) => {
+            assert!(value.ends_with(
+                "Estimated pushdown:\n  from: storage\n  range: storage\n  map: memory\n  sum: memory"
+            ), "{}", value)
+        }
+        contents => {
+            panic!("unexpected hover contents {:?}", contents)
+        }
+    }
+}
+
+/// Fields of the task option are explained, along with whether their value is valid.
+#[test]
+async fn test_hover_task_option_field() {
+    let fluxscript = r#"option task = {name: "downsample", every: 0h, offset: 5m}
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let hover = |character| {
+        let server = &server;
+        async move {
+            match server
+                .hover(hover_params(lsp::Position::new(0, character)))
+                .await
+                .unwrap()
+                .unwrap()
+                .contents
+            {
+                lsp::HoverContents::Scalar(
+                    lsp::MarkedString::String(value),
+                ) => value,
+                contents => {
+                    panic!("unexpected hover contents {:?}", contents)
+                }
+            }
+        }
+    };
+
+    let every = hover(37).await;
+    assert!(
+        every.starts_with(
+            "duration\n\nTask option every:\nHow often the task runs"
+        ),
+        "{}",
+        every
+    );
+    assert!(
+        every.ends_with(
+            "Invalid: `every` must be a duration longer than zero."
+        ),
+        "{}",
+        every
+    );
+    assert!(hover(47).await.ends_with("Valid."));
+}
+
+/// Hovering an import alias, in the import or as the root of a member, describes the
+/// package it refers to.
+#[test]
+async fn test_hover_import_alias() {
+    let fluxscript = r#"import s "strings"
+
+s.trimSpace(v: " a ")
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    for position in
+        [lsp::Position::new(0, 7), lsp::Position::new(2, 0)]
+    {
+        let contents = match server
+            .hover(hover_params(position))
+            .await
+            .unwrap()
+            .unwrap()
+            .contents
+        {
+            lsp::HoverContents::Scalar(
+                lsp::MarkedString::String(value),
+            ) => value,
+            contents => {
+                panic!("unexpected hover contents {:?}", contents)
+            }
+        };
+        let count = contents
+            .strip_prefix("alias for strings \u{2014} contains ")
+            .and_then(|rest| rest.strip_suffix(" functions"))
+            .unwrap_or_else(|| panic!("{}", contents));
+        assert!(count.parse::<usize>().unwrap() > 1);
+    }
+}
+
+/// Variables assigned from constant expressions show their computed value.
+#[test]
+async fn test_hover_constant_value() {
+    let fluxscript = r#"minutes = 10
+cutoff = minutes * 60
+cutoff + 1
+// ^
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let expected = Some(lsp::Hover {
+        contents: lsp::HoverContents::Scalar(
+            lsp::MarkedString::String("int = 600".to_string()),
+        ),
+        range: None,
+    });
+
+    let result = server
+        .hover(hover_params(lsp::Position::new(1, 1)))
+        .await
+        .unwrap();
+    assert_eq!(expected, result);
+
+    let result = server
+        .hover(hover_params(position_of(fluxscript)))
+        .await
+        .unwrap();
+    assert_eq!(expected, result);
+}
+
+/// Values are not computed for variables that depend on something other than constants,
+/// nor for names that may be shadowed inside of functions.
+#[test]
+async fn test_hover_constant_value_not_constant() {
+    let fluxscript = r#"import "strings"
+
+x = strings.toUpper(v: "a") + "b"
+y = 1
+f = (y) => y + 1
+       // ^
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let result = server
+        .hover(hover_params(lsp::Position::new(2, 1)))
+        .await
+        .unwrap();
+    assert_eq!(
+        Some(lsp::Hover {
+            contents: lsp::HoverContents::Scalar(
+                lsp::MarkedString::String("string".to_string()),
+            ),
+            range: None,
+        }),
+        result
+    );
+
+    let result = server
+        .hover(hover_params(position_of(fluxscript)))
+        .await
+        .unwrap();
+    assert_eq!(
+        Some(lsp::Hover {
+            contents: lsp::HoverContents::Scalar(
+                lsp::MarkedString::String("int".to_string()),
+            ),
+            range: None,
+        }),
+        result
+    );
+}
+
+/// Hovering over a pipe forward operator shows the columns flowing into the stage.
+#[test]
+async fn test_hover_pipe_input_schema() {
+    let fluxscript = r#"import "array"
+
+array.from(rows: [{a: 1, b: "x"}]) |> filter(fn: (r) => r.a > 0)
+                                // ^
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let result = server
+        .hover(hover_params(position_of(fluxscript)))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        Some(lsp::Hover {
+            contents: lsp::HoverContents::Scalar(
+                lsp::MarkedString::String(
+                    "Input columns:\n  a: int\n  b: string"
+                        .to_string()
+                ),
+            ),
+            range: None,
+        }),
+        result
+    );
+}
+
+/// Hovering over the function of a pipeline stage shows its type and the columns
+/// flowing into the stage.
+#[test]
+async fn test_hover_pipe_stage_function() {
+    let fluxscript = r#"import "array"
+
+array.from(rows: [{a: 1, b: "x"}]) |> filter(fn: (r) => r.a > 0)
+                                    // ^
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let result = server
+        .hover(hover_params(position_of(fluxscript)))
+        .await
+        .unwrap()
+        .unwrap();
+
+    match result.contents {
+        lsp::HoverContents::Scalar(lsp::MarkedString::String(
+            value,
+        )) => {
+            assert!(
+                value.ends_with(
+                    "\n\nInput columns:\n  a: int\n  b: string"
+                ),
+                "{}",
+                value
+            );
+        }
+        other => panic!("unexpected hover contents {:?}", other),
+    }
+}
+
 #[test]
 async fn test_hover_argument() {
     let fluxscript = r#"
@@ -1658,7 +3147,7 @@ x = 1
         result,
         Some(lsp::Hover {
             contents: lsp::HoverContents::Scalar(
-                lsp::MarkedString::String("int".to_string())
+                lsp::MarkedString::String("int = 1".to_string())
             ),
             range: None,
         })
@@ -1744,15 +3233,52 @@ sql.
     };
 }
 
-/// When completing package member names, support import aliases.
+/// Completion documentation is rendered as markdown when the client supports it.
+#[cfg(feature = "docs")]
 #[test]
-async fn test_package_completion_with_alias() {
-    let fluxscript = r#"import sekwel "sql"
+async fn test_package_completion_with_markdown() {
+    let fluxscript = r#"import "sql"
 
-sekwel.
-   // ^
+sql.
+// ^
 "#;
     let server = create_server();
+    server
+        .initialize(lsp::InitializeParams {
+            capabilities: lsp::ClientCapabilities {
+                text_document: Some(
+                    lsp::TextDocumentClientCapabilities {
+                        completion: Some(
+                            lsp::CompletionClientCapabilities {
+                                completion_item: Some(
+                                    lsp::CompletionItemCapability {
+                                        documentation_format: Some(
+                                            vec![
+                                            lsp::MarkupKind::Markdown,
+                                        ],
+                                        ),
+                                        ..Default::default()
+                                    },
+                                ),
+                                ..Default::default()
+                            },
+                        ),
+                        ..Default::default()
+                    },
+                ),
+                ..Default::default()
+            },
+            client_info: None,
+            initialization_options: None,
+            locale: None,
+            process_id: None,
+            root_path: None,
+            root_uri: None,
+            trace: None,
+            workspace_folders: None,
+        })
+        .await
+        .unwrap();
     open_file(&server, fluxscript.to_string(), None).await;
 
     let params = lsp::CompletionParams {
@@ -1776,15 +3302,160 @@ sekwel.
         }),
     };
 
-    let result =
-        server.completion(params.clone()).await.unwrap().unwrap();
+    let result = server.completion(params).await.unwrap().unwrap();
 
-    let expected_labels: Vec<String> = vec!["to", "from"]
-        .into_iter()
-        .map(|x| x.into())
-        .collect::<Vec<String>>();
+    let items = match result {
+        lsp::CompletionResponse::List(l) => l.items,
+        _ => unreachable!(),
+    };
+    let from =
+        items.iter().find(|item| item.label == "from").unwrap();
+    match &from.documentation {
+        Some(lsp::Documentation::MarkupContent(content)) => {
+            assert_eq!(lsp::MarkupKind::Markdown, content.kind);
+            assert!(content.value.starts_with("```flux\nfrom("));
+            assert!(content
+                .value
+                .contains("- `query` (required): `string`"));
+            assert!(content.value.ends_with(
+                "[View documentation](https://docs.influxdata.com/flux/v0.x/stdlib/sql/from/)"
+            ));
+        }
+        other => {
+            panic!("expected markdown documentation, got {:?}", other)
+        }
+    }
+}
 
-    match result {
+/// Deprecated functions are tagged for clients supporting the tag, and functions are
+/// committed by opening their call.
+#[test]
+async fn test_package_completion_deprecated_tag() {
+    let fluxscript = r#"import "influxdata/influxdb/v1"
+
+v1.
+// ^
+"#;
+    let completion = |tag_support: bool| async move {
+        let server = create_server();
+        server
+            .initialize(lsp::InitializeParams {
+                capabilities: lsp::ClientCapabilities {
+                    text_document: Some(
+                        lsp::TextDocumentClientCapabilities {
+                            completion: Some(
+                                lsp::CompletionClientCapabilities {
+                                    completion_item: Some(
+                                        lsp::CompletionItemCapability {
+                                            tag_support: tag_support
+                                                .then(|| lsp::TagSupport {
+                                                    value_set: vec![lsp::CompletionItemTag::DEPRECATED],
+                                                }),
+                                            ..Default::default()
+                                        },
+                                    ),
+                                    ..Default::default()
+                                },
+                            ),
+                            ..Default::default()
+                        },
+                    ),
+                    ..Default::default()
+                },
+                ..lsp::InitializeParams::default()
+            })
+            .await
+            .unwrap();
+        open_file(&server, fluxscript.to_string(), None).await;
+
+        let params = lsp::CompletionParams {
+            text_document_position: lsp::TextDocumentPositionParams {
+                text_document: lsp::TextDocumentIdentifier {
+                    uri: lsp::Url::parse(
+                        "file:///home/user/file.flux",
+                    )
+                    .unwrap(),
+                },
+                position: position_of(fluxscript),
+            },
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: lsp::PartialResultParams {
+                partial_result_token: None,
+            },
+            context: Some(lsp::CompletionContext {
+                trigger_kind:
+                    lsp::CompletionTriggerKind::TRIGGER_CHARACTER,
+                trigger_character: Some(".".to_string()),
+            }),
+        };
+        match server.completion(params).await.unwrap().unwrap() {
+            lsp::CompletionResponse::List(l) => l.items,
+            _ => unreachable!(),
+        }
+    };
+
+    let items = completion(true).await;
+    let tag_values =
+        items.iter().find(|item| item.label == "tagValues").unwrap();
+    assert_eq!(
+        Some(vec![lsp::CompletionItemTag::DEPRECATED]),
+        tag_values.tags
+    );
+    assert_eq!(
+        Some(vec!["(".to_string()]),
+        tag_values.commit_characters
+    );
+    let databases =
+        items.iter().find(|item| item.label == "databases").unwrap();
+    assert_eq!(None, databases.tags);
+
+    let items = completion(false).await;
+    assert!(items.iter().all(|item| item.tags.is_none()));
+}
+
+/// When completing package member names, support import aliases.
+#[test]
+async fn test_package_completion_with_alias() {
+    let fluxscript = r#"import sekwel "sql"
+
+sekwel.
+   // ^
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::CompletionParams {
+        text_document_position: lsp::TextDocumentPositionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+            },
+            position: position_of(fluxscript),
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+        context: Some(lsp::CompletionContext {
+            trigger_kind:
+                lsp::CompletionTriggerKind::TRIGGER_CHARACTER,
+            trigger_character: Some(".".to_string()),
+        }),
+    };
+
+    let result =
+        server.completion(params.clone()).await.unwrap().unwrap();
+
+    let expected_labels: Vec<String> = vec!["to", "from"]
+        .into_iter()
+        .map(|x| x.into())
+        .collect::<Vec<String>>();
+
+    match result {
         lsp::CompletionResponse::List(l) => {
             assert_eq!(
                 expected_labels,
@@ -1798,6 +3469,72 @@ sekwel.
     };
 }
 
+/// Aliases of nested packages resolve to the package, while the original name of an
+/// aliased package doesn't.
+#[test]
+async fn test_package_completion_with_nested_alias() {
+    let server = create_server();
+    let complete = |fluxscript: &'static str| {
+        let server = &server;
+        async move {
+            open_file(server, fluxscript.to_string(), None).await;
+            server
+                .completion(lsp::CompletionParams {
+                    text_document_position: lsp::TextDocumentPositionParams {
+                        text_document: lsp::TextDocumentIdentifier {
+                            uri: lsp::Url::parse(
+                                "file:///home/user/file.flux",
+                            )
+                            .unwrap(),
+                        },
+                        position: position_of(fluxscript),
+                    },
+                    work_done_progress_params: lsp::WorkDoneProgressParams {
+                        work_done_token: None,
+                    },
+                    partial_result_params: lsp::PartialResultParams {
+                        partial_result_token: None,
+                    },
+                    context: Some(lsp::CompletionContext {
+                        trigger_kind:
+                            lsp::CompletionTriggerKind::TRIGGER_CHARACTER,
+                        trigger_character: Some(".".to_string()),
+                    }),
+                })
+                .await
+                .unwrap()
+        }
+    };
+
+    let result = complete(
+        r#"import smo "contrib/anaisdg/statsmodels"
+
+smo.
+// ^
+"#,
+    )
+    .await;
+    match result {
+        Some(lsp::CompletionResponse::List(l)) => {
+            assert!(l
+                .items
+                .iter()
+                .any(|item| item.label == "linearRegression"));
+        }
+        other => panic!("unexpected completion {:?}", other),
+    }
+
+    let result = complete(
+        r#"import s "strings"
+
+strings.
+    // ^
+"#,
+    )
+    .await;
+    assert_eq!(None, result);
+}
+
 #[test]
 async fn test_import_completion() {
     let fluxscript = r#"
@@ -1936,6 +3673,54 @@ x = 1
     };
 }
 
+/// Packages the `target` platform doesn't provide aren't completed.
+#[test]
+async fn test_import_completion_on_platform() {
+    let fluxscript = r#"
+import "
+    // ^
+
+x = 1
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"target": "cloud"}}),
+        })
+        .await;
+
+    let params = lsp::CompletionParams {
+        text_document_position: lsp::TextDocumentPositionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+            },
+            position: position_of(fluxscript),
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+        context: None,
+    };
+
+    let labels: Vec<String> =
+        match server.completion(params).await.unwrap().unwrap() {
+            lsp::CompletionResponse::List(l) => l.items,
+            lsp::CompletionResponse::Array(items) => items,
+        }
+        .into_iter()
+        .map(|item| item.label)
+        .collect();
+
+    assert!(labels.contains(&"\"csv\"".to_string()));
+    assert!(!labels.contains(&"\"socket\"".to_string()));
+    assert!(!labels.contains(&"\"experimental/iox\"".to_string()));
+}
+
 #[test]
 async fn test_variable_completion() {
     let fluxscript = r#"import "strings"
@@ -2000,6 +3785,7 @@ errorCounts
 
     let want: BTreeSet<&str> = vec![
         "buckets",
+        "cal",
         "cardinality",
         "chandeMomentumOscillator",
         "columns",
@@ -2011,6 +3797,7 @@ errorCounts
         "contrib/bonitoo-io/victorops",
         "contrib/chobbs/discord",
         "contrib/qxip/clickhouse",
+        "cool",
         "count",
         "cov",
         "covariance",
@@ -2055,6 +3842,66 @@ errorCounts
     );
 }
 
+/// Parameters and variables of the function being completed in rank first, then the
+/// variables of the file declared before it.
+#[test]
+async fn test_scoped_variable_completion() {
+    let fluxscript = r#"offset = 10
+scale = (factor) => {
+    other = 2
+
+    return o
+}
+outer = 1
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::CompletionParams {
+        text_document_position: lsp::TextDocumentPositionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+            },
+            position: lsp::Position::new(4, 12),
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+        context: None,
+    };
+
+    let result = server.completion(params).await.unwrap().unwrap();
+
+    let items = match result {
+        lsp::CompletionResponse::List(l) => l.items,
+        _ => unreachable!(),
+    };
+    let mut items: Vec<(&str, &str)> = items
+        .iter()
+        .map(|item| {
+            (
+                item.sort_text.as_deref().unwrap_or(&item.label),
+                item.label.as_str(),
+            )
+        })
+        .collect();
+    items.sort_unstable();
+
+    assert_eq!(
+        vec![
+            ("0factor", "factor"),
+            ("0other", "other"),
+            ("1offset", "offset")
+        ],
+        items[..3]
+    );
+    assert!(!items.iter().any(|(_, label)| *label == "outer"));
+}
+
 #[test]
 async fn test_option_object_members_completion() {
     let fluxscript = r#"import "strings"
@@ -2124,6 +3971,67 @@ ab = 10
     assert_eq!(expected, labels);
 }
 
+/// Variables and records assigned after the position being completed are undefined at
+/// it, so they aren't offered.
+#[test]
+async fn test_completion_excludes_later_variables() {
+    let server = create_server();
+    let labels = |fluxscript: &'static str| {
+        let server = &server;
+        async move {
+            open_file(server, fluxscript.to_string(), None).await;
+            let params = lsp::CompletionParams {
+                text_document_position:
+                    lsp::TextDocumentPositionParams {
+                        text_document: lsp::TextDocumentIdentifier {
+                            uri: lsp::Url::parse(
+                                "file:///home/user/file.flux",
+                            )
+                            .unwrap(),
+                        },
+                        position: position_of(fluxscript),
+                    },
+                work_done_progress_params:
+                    lsp::WorkDoneProgressParams {
+                        work_done_token: None,
+                    },
+                partial_result_params: lsp::PartialResultParams {
+                    partial_result_token: None,
+                },
+                context: None,
+            };
+            match server.completion(params).await.unwrap() {
+                Some(lsp::CompletionResponse::List(l)) => l
+                    .items
+                    .into_iter()
+                    .map(|item| item.label)
+                    .collect::<Vec<String>>(),
+                _ => vec![],
+            }
+        }
+    };
+
+    let identifiers = labels(
+        r#"value1 = 1
+valu
+// ^
+value2 = 2
+"#,
+    )
+    .await;
+    assert!(identifiers.contains(&"value1".to_string()));
+    assert!(!identifiers.contains(&"value2".to_string()));
+
+    let members = labels(
+        r#"settings.
+     // ^
+settings = {high: 2}
+"#,
+    )
+    .await;
+    assert!(!members.contains(&"high (self)".to_string()));
+}
+
 /// Function parameter snippets are returned with the snippet syntax
 /// for completion on a a function already written, e.g. from an
 /// automated process.
@@ -2289,6 +4197,7 @@ ab = 10
           "difference",
           "distinct",
           "duration",
+          "env",
           "experimental",
           "experimental/date/boundaries",
           "experimental/dynamic",
@@ -2568,6 +4477,50 @@ x = 1
     assert_eq!(expected, labels);
 }
 
+/// Parameters are completed while the next argument is being typed, though the call
+/// isn't closed yet.
+#[test]
+async fn test_param_completion_trailing_comma() {
+    let fluxscript = "from(bucket: \"a\")\n    |> filter(fn: (r) => r._measurement == \"cpu\", \n";
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::CompletionParams {
+        text_document_position: lsp::TextDocumentPositionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+            },
+            position: lsp::Position {
+                line: 1,
+                character: 50,
+            },
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+        context: Some(lsp::CompletionContext {
+            trigger_kind: lsp::CompletionTriggerKind::INVOKED,
+            trigger_character: None,
+        }),
+    };
+
+    let result = server.completion(params).await.unwrap().unwrap();
+
+    let items = match result {
+        lsp::CompletionResponse::List(l) => l.items,
+        _ => unreachable!(),
+    };
+    let labels: Vec<&str> =
+        items.iter().map(|item| item.label.as_str()).collect();
+
+    assert!(labels.contains(&"onEmpty"), "{:?}", labels);
+    assert!(!labels.contains(&"fn"), "{:?}", labels);
+}
+
 #[test]
 async fn test_options_completion() {
     let fluxscript = r#"import "strings"
@@ -2726,6 +4679,7 @@ errorCounts
         "system",
         "tableFind",
         "tail",
+        "task",
         "testing",
         "testing/expect",
         "time",
@@ -2810,6 +4764,85 @@ async fn test_folding_range_invalid() {
     assert_eq!(result, Ok(None));
 }
 
+/// The symbols of every document opened are searched, including closed files, but
+/// not documents that aren't files.
+#[test]
+async fn test_workspace_symbol() {
+    let server = create_server();
+    open_file(
+        &server,
+        "movingAverage = (tables=<-) => tables\n".to_string(),
+        Some("file:///home/user/lib/functions.flux"),
+    )
+    .await;
+    open_file(
+        &server,
+        "maxValue = 10\n".to_string(),
+        Some("file:///home/user/queries/query.flux"),
+    )
+    .await;
+    open_file(
+        &server,
+        "meanAverage = 1\n".to_string(),
+        Some("untitled:Untitled-1"),
+    )
+    .await;
+    for uri in [
+        "file:///home/user/lib/functions.flux",
+        "untitled:Untitled-1",
+    ] {
+        server
+            .did_close(lsp::DidCloseTextDocumentParams {
+                text_document: lsp::TextDocumentIdentifier::new(
+                    lsp::Url::parse(uri).unwrap(),
+                ),
+            })
+            .await;
+    }
+
+    let server = &server;
+    let symbols = move |query: &str| {
+        let query = query.to_string();
+        async move {
+            server
+                .symbol(lsp::WorkspaceSymbolParams {
+                    query,
+                    work_done_progress_params:
+                        lsp::WorkDoneProgressParams {
+                            work_done_token: None,
+                        },
+                    partial_result_params: lsp::PartialResultParams {
+                        partial_result_token: None,
+                    },
+                })
+                .await
+                .unwrap()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|symbol| {
+                    (symbol.name, symbol.location.uri.to_string())
+                })
+                .collect::<Vec<_>>()
+        }
+    };
+
+    assert_eq!(
+        vec![(
+            "movingAverage".to_string(),
+            "file:///home/user/lib/functions.flux".to_string()
+        )],
+        symbols("mavg").await
+    );
+    assert_eq!(
+        vec![(
+            "maxValue".to_string(),
+            "file:///home/user/queries/query.flux".to_string()
+        )],
+        symbols("maxv").await
+    );
+    assert!(symbols("meanAverage").await.is_empty());
+}
+
 #[test]
 async fn test_document_symbol_invalid() {
     let fluxscript = r#"bork |>"#;
@@ -3051,7 +5084,10 @@ from(bucket: "bucket")
               "detail": "(v:A) -> bytes",
               "sortText": "encode",
               "filterText": "encode",
-              "insertTextFormat": 2
+              "insertTextFormat": 2,
+              "commitCharacters": [
+                "("
+              ]
             }
           ]
         }"#]]
@@ -3324,51 +5360,35 @@ async fn test_code_action_import_insertion() {
     .assert_eq(&serde_json::to_string_pretty(&result).unwrap());
 }
 
-// When inserting a package import, don't clobber the package statement at the beginning.
+/// Assignments shadowing a prelude function can be renamed, along with their references.
 #[test]
-async fn test_code_action_import_insertion_with_package() {
-    let fluxscript = "package anPackage\n\nsql";
+async fn test_code_action_prelude_shadowing() {
+    let fluxscript = r#"sort = 5
+sorted = sort + 1"#;
     let server = create_server();
     open_file(&server, fluxscript.to_string(), None).await;
 
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let diagnostics =
+        server.compute_diagnostics(&uri).remove(&uri).unwrap();
+    let diagnostic = diagnostics
+        .iter()
+        .find(|diagnostic| {
+            diagnostic.code
+                == Some(lsp::NumberOrString::String(
+                    "prelude-shadowing".into(),
+                ))
+        })
+        .unwrap()
+        .clone();
+
     let params = lsp::CodeActionParams {
-        text_document: lsp::TextDocumentIdentifier {
-            uri: lsp::Url::parse("file:///home/user/file.flux")
-                .unwrap(),
-        },
+        text_document: lsp::TextDocumentIdentifier { uri },
         context: lsp::CodeActionContext {
-            diagnostics: vec![lsp::Diagnostic {
-                code: None,
-                code_description: None,
-                data: None,
-                related_information: None,
-                severity: Some(lsp::DiagnosticSeverity::ERROR),
-                source: Some("flux".into()),
-                tags: None,
-                message: "undefined identifier sql".into(),
-                range: lsp::Range {
-                    start: lsp::Position {
-                        line: 2,
-                        character: 0,
-                    },
-                    end: lsp::Position {
-                        line: 2,
-                        character: 0,
-                    },
-                },
-            }],
+            diagnostics: vec![diagnostic.clone()],
             only: None,
         },
-        range: lsp::Range {
-            start: lsp::Position {
-                line: 2,
-                character: 2,
-            },
-            end: lsp::Position {
-                line: 2,
-                character: 2,
-            },
-        },
+        range: diagnostic.range,
         work_done_progress_params: lsp::WorkDoneProgressParams {
             work_done_token: None,
         },
@@ -3377,84 +5397,65 @@ async fn test_code_action_import_insertion_with_package() {
         },
     };
 
-    let result = server.code_action(params).await.unwrap();
+    let result = server.code_action(params).await.unwrap().unwrap();
 
-    expect_test::expect![[r#"
-            [
-              {
-                "title": "Import `sql`",
-                "kind": "quickfix",
-                "edit": {
-                  "changes": {
-                    "file:///home/user/file.flux": [
-                      {
-                        "range": {
-                          "start": {
-                            "line": 1,
-                            "character": 0
-                          },
-                          "end": {
-                            "line": 1,
-                            "character": 0
-                          }
-                        },
-                        "newText": "import \"sql\"\n"
-                      }
-                    ]
-                  }
-                },
-                "isPreferred": true
-              }
-            ]"#]]
-    .assert_eq(&serde_json::to_string_pretty(&result).unwrap());
+    let action = match &result[..] {
+        [lsp::CodeActionOrCommand::CodeAction(action)] => action,
+        _ => panic!(
+            "expected a single code action, found {:?}",
+            result
+        ),
+    };
+    assert_eq!("Rename `sort` to `mySort`", action.title);
+    let edits =
+        &action.edit.as_ref().unwrap().changes.as_ref().unwrap()
+            [&lsp::Url::parse("file:///home/user/file.flux")
+                .unwrap()];
+    assert_eq!(
+        vec![
+            (lsp::Position::new(1, 9), lsp::Position::new(1, 13)),
+            (lsp::Position::new(0, 0), lsp::Position::new(0, 4)),
+        ],
+        edits
+            .iter()
+            .map(|edit| (edit.range.start, edit.range.end))
+            .collect::<Vec<_>>()
+    );
+    assert!(edits.iter().all(|edit| edit.new_text == "mySort"));
 }
 
-/// If the identifier matches multiple potential imports, multiple code
-/// actions should be offered to the user.
+/// Regular expressions only matching a literal are replaced with an equality.
 #[test]
-async fn test_code_action_import_insertion_multiple_actions() {
-    let fluxscript = r#"array"#;
+async fn test_code_action_literal_regex() {
+    let fluxscript = r#"from(bucket: "a")
+    |> range(start: -1h)
+    |> filter(fn: (r) => r._measurement =~ /^cpu$/)"#;
     let server = create_server();
     open_file(&server, fluxscript.to_string(), None).await;
 
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let diagnostics =
+        server.compute_diagnostics(&uri).remove(&uri).unwrap();
+    let diagnostic = diagnostics
+        .iter()
+        .find(|diagnostic| {
+            diagnostic.code
+                == Some(lsp::NumberOrString::String(
+                    "literal-regex".into(),
+                ))
+        })
+        .unwrap()
+        .clone();
+
     let params = lsp::CodeActionParams {
         text_document: lsp::TextDocumentIdentifier {
-            uri: lsp::Url::parse("file:///home/user/file.flux")
-                .unwrap(),
+            uri: uri.clone(),
         },
         context: lsp::CodeActionContext {
-            diagnostics: vec![lsp::Diagnostic {
-                code: None,
-                code_description: None,
-                data: None,
-                related_information: None,
-                severity: Some(lsp::DiagnosticSeverity::ERROR),
-                source: Some("flux".into()),
-                tags: None,
-                message: "undefined identifier array".into(),
-                range: lsp::Range {
-                    start: lsp::Position {
-                        line: 0,
-                        character: 0,
-                    },
-                    end: lsp::Position {
-                        line: 0,
-                        character: 0,
-                    },
-                },
-            }],
+            diagnostics: vec![diagnostic.clone()],
             only: None,
         },
-        range: lsp::Range {
-            start: lsp::Position {
-                line: 0,
-                character: 4,
-            },
-            end: lsp::Position {
-                line: 0,
-                character: 4,
-            },
-        },
+        range: diagnostic.range,
         work_done_progress_params: lsp::WorkDoneProgressParams {
             work_done_token: None,
         },
@@ -3463,107 +5464,72 @@ async fn test_code_action_import_insertion_multiple_actions() {
         },
     };
 
-    let result = server.code_action(params).await.unwrap();
+    let result = server.code_action(params).await.unwrap().unwrap();
 
-    expect_test::expect![[r#"
-            [
-              {
-                "title": "Import `array`",
-                "kind": "quickfix",
-                "edit": {
-                  "changes": {
-                    "file:///home/user/file.flux": [
-                      {
-                        "range": {
-                          "start": {
-                            "line": 0,
-                            "character": 0
-                          },
-                          "end": {
-                            "line": 0,
-                            "character": 0
-                          }
-                        },
-                        "newText": "import \"array\"\n"
-                      }
-                    ]
-                  }
-                },
-                "isPreferred": true
-              },
-              {
-                "title": "Import `experimental/array`",
-                "kind": "quickfix",
-                "edit": {
-                  "changes": {
-                    "file:///home/user/file.flux": [
-                      {
-                        "range": {
-                          "start": {
-                            "line": 0,
-                            "character": 0
-                          },
-                          "end": {
-                            "line": 0,
-                            "character": 0
-                          }
-                        },
-                        "newText": "import \"experimental/array\"\n"
-                      }
-                    ]
-                  }
-                },
-                "isPreferred": true
-              }
-            ]"#]]
-    .assert_eq(&serde_json::to_string_pretty(&result).unwrap());
+    let action = match &result[..] {
+        [lsp::CodeActionOrCommand::CodeAction(action)] => action,
+        _ => panic!(
+            "expected a single code action, found {:?}",
+            result
+        ),
+    };
+    assert_eq!("Replace with `== \"cpu\"`", action.title);
+    assert_eq!(
+        vec![lsp::TextEdit {
+            range: lsp::Range {
+                start: lsp::Position::new(2, 39),
+                end: lsp::Position::new(2, 50),
+            },
+            new_text: " == \"cpu\"".into(),
+        }],
+        action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri]
+    );
 }
 
-/// If the import requires a full path, that the action suggests the full path.
+/// Calls of functions deprecated by the `deprecatedFunctions` setting are reported,
+/// and renamed to their replacement.
 #[test]
-async fn test_code_action_import_insertion_full_path() {
-    let fluxscript = r#"schema"#;
+async fn test_code_action_deprecated_function() {
+    let fluxscript = r#"import "strings"
+
+strings.title(v: "a")"#;
     let server = create_server();
     open_file(&server, fluxscript.to_string(), None).await;
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"deprecatedFunctions": [
+                {"package": "strings", "name": "title", "replacement": "toTitle"},
+            ]}}),
+        })
+        .await;
+
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let diagnostics =
+        server.compute_diagnostics(&uri).remove(&uri).unwrap();
+    let diagnostic = diagnostics
+        .iter()
+        .find(|diagnostic| {
+            diagnostic.code
+                == Some(lsp::NumberOrString::String(
+                    "deprecated-function".into(),
+                ))
+        })
+        .unwrap()
+        .clone();
+    assert_eq!(
+        "strings.title is deprecated, use strings.toTitle instead.",
+        diagnostic.message
+    );
 
     let params = lsp::CodeActionParams {
         text_document: lsp::TextDocumentIdentifier {
-            uri: lsp::Url::parse("file:///home/user/file.flux")
-                .unwrap(),
+            uri: uri.clone(),
         },
         context: lsp::CodeActionContext {
-            diagnostics: vec![lsp::Diagnostic {
-                code: None,
-                code_description: None,
-                data: None,
-                related_information: None,
-                severity: Some(lsp::DiagnosticSeverity::ERROR),
-                source: Some("flux".into()),
-                tags: None,
-                message: "undefined identifier schema".into(),
-                range: lsp::Range {
-                    start: lsp::Position {
-                        line: 0,
-                        character: 0,
-                    },
-                    end: lsp::Position {
-                        line: 0,
-                        character: 0,
-                    },
-                },
-            }],
+            diagnostics: vec![diagnostic.clone()],
             only: None,
         },
-        range: lsp::Range {
-            start: lsp::Position {
-                line: 0,
-                character: 5,
-            },
-            end: lsp::Position {
-                line: 0,
-                character: 5,
-            },
-        },
+        range: diagnostic.range,
         work_done_progress_params: lsp::WorkDoneProgressParams {
             work_done_token: None,
         },
@@ -3572,165 +5538,2804 @@ async fn test_code_action_import_insertion_full_path() {
         },
     };
 
-    let result = server.code_action(params).await.unwrap();
+    let result = server.code_action(params).await.unwrap().unwrap();
 
-    expect_test::expect![[r#"
-            [
-              {
-                "title": "Import `influxdata/influxdb/schema`",
-                "kind": "quickfix",
-                "edit": {
-                  "changes": {
-                    "file:///home/user/file.flux": [
-                      {
-                        "range": {
-                          "start": {
-                            "line": 0,
-                            "character": 0
-                          },
-                          "end": {
-                            "line": 0,
-                            "character": 0
-                          }
-                        },
-                        "newText": "import \"influxdata/influxdb/schema\"\n"
-                      }
-                    ]
-                  }
-                },
-                "isPreferred": true
-              }
-            ]"#]]
-        .assert_eq(&serde_json::to_string_pretty(&result).unwrap());
+    let action = match &result[..] {
+        [lsp::CodeActionOrCommand::CodeAction(action)] => action,
+        _ => panic!(
+            "expected a single code action, found {:?}",
+            result
+        ),
+    };
+    assert_eq!("Replace `title` with `toTitle`", action.title);
+    assert_eq!(
+        vec![lsp::TextEdit {
+            range: lsp::Range {
+                start: lsp::Position::new(2, 8),
+                end: lsp::Position::new(2, 13),
+            },
+            new_text: "toTitle".into(),
+        }],
+        action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri]
+    );
 }
 
+/// Unused parameters can be removed or prefixed with `_`, along with the arguments
+/// passed as them.
 #[test]
-async fn compute_diagnostics_multi_file() {
+async fn test_code_action_unused_parameter() {
+    let fluxscript = r#"f = (x, y) => y
+f(x: 1, y: 2)"#;
     let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
 
-    let filename: String = "file:///path/to/script.flux".into();
-    let fluxscript = r#"from(bucket: "my-bucket")
-|> range(start: -100d)
-|> filter(fn: (r) => r.anTag == v.a)"#;
-    open_file(&server, fluxscript.into(), Some(&filename)).await;
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let diagnostics =
+        server.compute_diagnostics(&uri).remove(&uri).unwrap();
+    let diagnostic = diagnostics
+        .iter()
+        .find(|diagnostic| {
+            diagnostic.code
+                == Some(lsp::NumberOrString::String(
+                    "unused-parameter".into(),
+                ))
+        })
+        .unwrap()
+        .clone();
 
-    let diagnostics = server
-        .compute_diagnostics(&lsp::Url::parse(&filename).unwrap());
+    let params = lsp::CodeActionParams {
+        text_document: lsp::TextDocumentIdentifier {
+            uri: uri.clone(),
+        },
+        context: lsp::CodeActionContext {
+            diagnostics: vec![diagnostic.clone()],
+            only: None,
+        },
+        range: diagnostic.range,
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
 
+    let result = server.code_action(params).await.unwrap().unwrap();
+
+    let edits = |action: &lsp::CodeActionOrCommand| match action {
+        lsp::CodeActionOrCommand::CodeAction(action) => (
+            action.title.clone(),
+            action.edit.as_ref().unwrap().changes.as_ref().unwrap()
+                [&uri]
+                .iter()
+                .map(|edit| {
+                    (
+                        edit.range.start,
+                        edit.range.end,
+                        edit.new_text.clone(),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        ),
+        _ => panic!("expected a code action, found {:?}", action),
+    };
     assert_eq!(
-        HashMap::from([(
-            lsp::Url::parse("file:///path/to/script.flux").unwrap(),
-            vec![lsp::Diagnostic {
-                code: None,
-                code_description: None,
-                data: None,
-                message: "undefined identifier v".into(),
-                range: lsp::Range {
-                    start: lsp::Position {
-                        line: 2,
-                        character: 32
-                    },
-                    end: lsp::Position {
-                        line: 2,
-                        character: 33
-                    },
-                },
-                related_information: None,
-                severity: Some(lsp::DiagnosticSeverity::ERROR),
-                source: Some("flux".into()),
-                tags: None,
-            }]
-        ),]),
-        diagnostics
+        vec![
+            (
+                "Remove the parameter `x`".to_string(),
+                vec![
+                    (
+                        lsp::Position::new(1, 2),
+                        lsp::Position::new(1, 8),
+                        "".to_string()
+                    ),
+                    (
+                        lsp::Position::new(0, 5),
+                        lsp::Position::new(0, 8),
+                        "".to_string()
+                    ),
+                ]
+            ),
+            (
+                "Rename the parameter `x` to `_x`".to_string(),
+                vec![
+                    (
+                        lsp::Position::new(1, 2),
+                        lsp::Position::new(1, 2),
+                        "_".to_string()
+                    ),
+                    (
+                        lsp::Position::new(0, 5),
+                        lsp::Position::new(0, 5),
+                        "_".to_string()
+                    ),
+                ]
+            ),
+        ],
+        result.iter().map(edits).collect::<Vec<_>>()
     );
+}
 
-    open_file(
-        &server,
-        r#"v = {a: "b"}"#.to_string(),
-        Some("file:///path/to/an_vars.flux"),
-    )
-    .await;
+/// Imports with colliding names can be given an alias.
+/// A result without a name, in a script with several results, is named with a `yield`.
+#[test]
+async fn test_code_action_unnamed_result() {
+    let fluxscript = r#"from(bucket: "a") |> range(start: -1h)
+from(bucket: "b")
+    |> range(start: -1h)
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
 
-    let diagnostics_again = server
-        .compute_diagnostics(&lsp::Url::parse(&filename).unwrap());
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let diagnostics =
+        server.compute_diagnostics(&uri).remove(&uri).unwrap();
+    let diagnostic = diagnostics
+        .iter()
+        .filter(|diagnostic| {
+            diagnostic.code
+                == Some(lsp::NumberOrString::String(
+                    "unnamed-result".into(),
+                ))
+        })
+        .last()
+        .unwrap()
+        .clone();
 
-    let expected: HashMap<lsp::Url, Vec<lsp::Diagnostic>> = HashMap::from([
-        (lsp::Url::parse("file:///path/to/script.flux").unwrap(), vec![]),
-        (lsp::Url::parse("file:///path/to/an_vars.flux").unwrap(), vec![lsp::Diagnostic {
+    let params = lsp::CodeActionParams {
+        text_document: lsp::TextDocumentIdentifier {
+            uri: uri.clone(),
+        },
+        context: lsp::CodeActionContext {
+            diagnostics: vec![diagnostic.clone()],
+            only: None,
+        },
+        range: diagnostic.range,
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
+
+    let result = server.code_action(params).await.unwrap().unwrap();
+
+    let action = match &result[..] {
+        [lsp::CodeActionOrCommand::CodeAction(action)] => action,
+        _ => {
+            panic!("expected a single code action, got {:?}", result)
+        }
+    };
+    assert_eq!("Name the result `result_2`", action.title);
+    assert_eq!(
+        vec![lsp::TextEdit {
             range: lsp::Range {
                 start: lsp::Position {
-                    line: 0, character: 0,
+                    line: 2,
+                    character: 24
                 },
                 end: lsp::Position {
-                    line: 0, character: 1,
-                }
+                    line: 2,
+                    character: 24
+                },
             },
-            severity: Some(lsp::DiagnosticSeverity::WARNING),
-            message: "Avoid using `v` as an identifier name. In some InfluxDB contexts, it may be provided at runtime.".to_string(),
-            ..lsp::Diagnostic::default()
-        }]),
-    ]);
+            new_text: "\n    |> yield(name: \"result_2\")".into(),
+        }],
+        action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri]
+    );
+}
 
-    // We are interested in the error from the original file being fixed, not in
-    // the lints introduced by the new file.
-    assert_eq!(expected, diagnostics_again);
+/// The opt-in `aggregate-window-create-empty` lint only runs once enabled, and its
+/// quick fix sets `createEmpty: false`.
+#[test]
+async fn test_code_action_aggregate_window_create_empty() {
+    let fluxscript = r#"option task = {name: "downsample", every: 1h}
+
+from(bucket: "a")
+    |> range(start: -task.every)
+    |> aggregateWindow(every: 5m, fn: mean)
+    |> to(bucket: "b")
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let code = Some(lsp::NumberOrString::String(
+        "aggregate-window-create-empty".into(),
+    ));
+    let diagnostics =
+        server.compute_diagnostics(&uri).remove(&uri).unwrap();
+    assert!(diagnostics
+        .iter()
+        .all(|diagnostic| diagnostic.code != code));
+
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"optInLints": ["aggregate-window-create-empty"]}}),
+        })
+        .await;
+    let diagnostics =
+        server.compute_diagnostics(&uri).remove(&uri).unwrap();
+    let diagnostic = diagnostics
+        .iter()
+        .find(|diagnostic| diagnostic.code == code)
+        .unwrap()
+        .clone();
+
+    let params = lsp::CodeActionParams {
+        text_document: lsp::TextDocumentIdentifier {
+            uri: uri.clone(),
+        },
+        context: lsp::CodeActionContext {
+            diagnostics: vec![diagnostic.clone()],
+            only: None,
+        },
+        range: diagnostic.range,
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
+
+    let result = server.code_action(params).await.unwrap().unwrap();
+
+    let action = match &result[..] {
+        [lsp::CodeActionOrCommand::CodeAction(action)] => action,
+        _ => {
+            panic!("expected a single code action, got {:?}", result)
+        }
+    };
+    assert_eq!(
+        vec![lsp::TextEdit {
+            range: lsp::Range {
+                start: lsp::Position {
+                    line: 4,
+                    character: 42
+                },
+                end: lsp::Position {
+                    line: 4,
+                    character: 42
+                },
+            },
+            new_text: ", createEmpty: false".into(),
+        }],
+        action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri]
+    );
 }
 
 #[test]
-async fn compute_diagnostics_non_errors() {
+async fn test_code_action_import_collision() {
+    let fluxscript = r#"import "influxdata/influxdb/schema"
+import "influxdata/influxdb/v1/schema"
+"#;
     let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
 
-    let filename: String = "file:///path/to/script.flux".into();
-    let fluxscript = r#"import "experimental"
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let diagnostics =
+        server.compute_diagnostics(&uri).remove(&uri).unwrap();
+    let diagnostic = diagnostics
+        .iter()
+        .find(|diagnostic| {
+            diagnostic.code
+                == Some(lsp::NumberOrString::String(
+                    "import-collision".into(),
+                ))
+        })
+        .unwrap()
+        .clone();
 
-from(bucket: "my-bucket")
-|> range(start: -100d)
-|> filter(fn: (r) => r.value == "b")
-|> experimental.to(bucket: "out-bucket", org: "abc123", host: "https://myhost.example.com", token: "123abc")"#;
-    open_file(&server, fluxscript.into(), Some(&filename)).await;
+    let params = lsp::CodeActionParams {
+        text_document: lsp::TextDocumentIdentifier { uri },
+        context: lsp::CodeActionContext {
+            diagnostics: vec![diagnostic.clone()],
+            only: None,
+        },
+        range: diagnostic.range,
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
 
-    let diagnostics_again = server
-        .compute_diagnostics(&lsp::Url::parse(&filename).unwrap());
+    let result = server.code_action(params).await.unwrap();
 
-    assert!(!diagnostics_again.is_empty());
+    expect_test::expect![[r#"
+        [
+          {
+            "title": "Import `influxdata/influxdb/v1/schema` as `v1Schema`",
+            "kind": "quickfix",
+            "diagnostics": [
+              {
+                "range": {
+                  "start": {
+                    "line": 1,
+                    "character": 0
+                  },
+                  "end": {
+                    "line": 1,
+                    "character": 37
+                  }
+                },
+                "severity": 2,
+                "code": "import-collision",
+                "message": "The import of `influxdata/influxdb/v1/schema` is named `schema`, like the import of `influxdata/influxdb/schema`. Add an alias to tell them apart."
+              }
+            ],
+            "edit": {
+              "changes": {
+                "file:///home/user/file.flux": [
+                  {
+                    "range": {
+                      "start": {
+                        "line": 1,
+                        "character": 7
+                      },
+                      "end": {
+                        "line": 1,
+                        "character": 7
+                      }
+                    },
+                    "newText": "v1Schema "
+                  }
+                ]
+              }
+            },
+            "isPreferred": true
+          }
+        ]"#]]
+    .assert_eq(&serde_json::to_string_pretty(&result).unwrap());
 }
 
-// All commands require key/value pairs as params, not positional
-/// arguments.
 #[test]
-async fn execute_command_too_many_args() {
+async fn test_code_action_duplicate_import() {
+    let fluxscript = r#"import "strings"
+import "array"
+import "strings"
+
+strings.toUpper(v: "a")
+"#;
     let server = create_server();
-    let params = lsp::ExecuteCommandParams {
-        command: "notActuallyAValidCommand".into(),
-        arguments: vec![
-            serde_json::value::to_value("arg1").unwrap(),
-            serde_json::value::to_value("arg2").unwrap(),
-        ],
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let diagnostic = lsp::Diagnostic {
+        range: lsp::Range {
+            start: lsp::Position {
+                line: 2,
+                character: 0,
+            },
+            end: lsp::Position {
+                line: 2,
+                character: 16,
+            },
+        },
+        severity: Some(lsp::DiagnosticSeverity::WARNING),
+        code: Some(lsp::NumberOrString::String(
+            "duplicate-import".into(),
+        )),
+        message: "`strings` is already imported.".into(),
+        tags: Some(vec![lsp::DiagnosticTag::UNNECESSARY]),
+        ..lsp::Diagnostic::default()
+    };
+
+    let params = lsp::CodeActionParams {
+        text_document: lsp::TextDocumentIdentifier { uri },
+        context: lsp::CodeActionContext {
+            diagnostics: vec![diagnostic.clone()],
+            only: None,
+        },
+        range: diagnostic.range,
         work_done_progress_params: lsp::WorkDoneProgressParams {
             work_done_token: None,
         },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
+
+    let result = server.code_action(params).await.unwrap().unwrap();
+
+    let action = match &result[0] {
+        lsp::CodeActionOrCommand::CodeAction(action) => action,
+        _ => panic!("expected a code action"),
+    };
+    assert_eq!(
+        "Remove the duplicate import of `strings`",
+        action.title
+    );
+    assert_eq!(
+        vec![lsp::TextEdit {
+            range: lsp::Range {
+                start: lsp::Position {
+                    line: 2,
+                    character: 0
+                },
+                end: lsp::Position {
+                    line: 3,
+                    character: 0
+                },
+            },
+            new_text: "".into(),
+        }],
+        action.edit.as_ref().unwrap().changes.as_ref().unwrap()
+            [&lsp::Url::parse("file:///home/user/file.flux")
+                .unwrap()]
+    );
+}
+
+// When inserting a package import, don't clobber the package statement at the beginning.
+#[test]
+async fn test_code_action_import_insertion_with_package() {
+    let fluxscript = "package anPackage\n\nsql";
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::CodeActionParams {
+        text_document: lsp::TextDocumentIdentifier {
+            uri: lsp::Url::parse("file:///home/user/file.flux")
+                .unwrap(),
+        },
+        context: lsp::CodeActionContext {
+            diagnostics: vec![lsp::Diagnostic {
+                code: None,
+                code_description: None,
+                data: None,
+                related_information: None,
+                severity: Some(lsp::DiagnosticSeverity::ERROR),
+                source: Some("flux".into()),
+                tags: None,
+                message: "undefined identifier sql".into(),
+                range: lsp::Range {
+                    start: lsp::Position {
+                        line: 2,
+                        character: 0,
+                    },
+                    end: lsp::Position {
+                        line: 2,
+                        character: 0,
+                    },
+                },
+            }],
+            only: None,
+        },
+        range: lsp::Range {
+            start: lsp::Position {
+                line: 2,
+                character: 2,
+            },
+            end: lsp::Position {
+                line: 2,
+                character: 2,
+            },
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
+
+    let result = server.code_action(params).await.unwrap();
+
+    expect_test::expect![[r#"
+            [
+              {
+                "title": "Import `sql`",
+                "kind": "quickfix",
+                "edit": {
+                  "changes": {
+                    "file:///home/user/file.flux": [
+                      {
+                        "range": {
+                          "start": {
+                            "line": 1,
+                            "character": 0
+                          },
+                          "end": {
+                            "line": 1,
+                            "character": 0
+                          }
+                        },
+                        "newText": "import \"sql\"\n"
+                      }
+                    ]
+                  }
+                },
+                "isPreferred": true
+              }
+            ]"#]]
+    .assert_eq(&serde_json::to_string_pretty(&result).unwrap());
+}
+
+/// Imports are inserted in order, below header comments and above the comments of
+/// the statement they precede.
+#[test]
+async fn test_code_action_import_insertion_position() {
+    for (fluxscript, line, new_text) in [
+        // After a shebang and header comments.
+        (
+            "#!/usr/bin/env flux\n// Copyright\n\nsql",
+            3,
+            "import \"sql\"\n",
+        ),
+        // Above the comment documenting the first statement.
+        ("// The query\nsql", 0, "import \"sql\"\n"),
+        // Between sorted imports, with the comment above the next one.
+        (
+            "import \"array\"\n// Strings\nimport \"strings\"\n\nsql",
+            1,
+            "import \"sql\"\n",
+        ),
+        // After the last import, which ends the file.
+        ("import \"array\"\nsql", 1, "import \"sql\"\n"),
+    ] {
+        let server = create_server();
+        open_file(&server, fluxscript.to_string(), None).await;
+        let sql_line = fluxscript.lines().count() as u32 - 1;
+
+        let params = lsp::CodeActionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+            },
+            context: lsp::CodeActionContext {
+                diagnostics: vec![lsp::Diagnostic {
+                    severity: Some(lsp::DiagnosticSeverity::ERROR),
+                    source: Some("flux".into()),
+                    message: "undefined identifier sql".into(),
+                    range: lsp::Range {
+                        start: lsp::Position::new(sql_line, 0),
+                        end: lsp::Position::new(sql_line, 3),
+                    },
+                    ..lsp::Diagnostic::default()
+                }],
+                only: None,
+            },
+            range: lsp::Range {
+                start: lsp::Position::new(sql_line, 0),
+                end: lsp::Position::new(sql_line, 3),
+            },
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: lsp::PartialResultParams {
+                partial_result_token: None,
+            },
+        };
+
+        let result =
+            server.code_action(params).await.unwrap().unwrap();
+        let edit = match &result[0] {
+            lsp::CodeActionOrCommand::CodeAction(action) => action
+                .edit
+                .as_ref()
+                .unwrap()
+                .changes
+                .as_ref()
+                .unwrap()[&lsp::Url::parse(
+                "file:///home/user/file.flux",
+            )
+            .unwrap()][0]
+                .clone(),
+            _ => unreachable!(),
+        };
+
+        assert_eq!(
+            lsp::TextEdit {
+                range: lsp::Range {
+                    start: lsp::Position::new(line, 0),
+                    end: lsp::Position::new(line, 0),
+                },
+                new_text: new_text.into(),
+            },
+            edit,
+            "{}",
+            fluxscript
+        );
+    }
+}
+
+/// If the identifier matches multiple potential imports, multiple code
+/// actions should be offered to the user.
+#[test]
+async fn test_code_action_import_insertion_multiple_actions() {
+    let fluxscript = r#"array"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::CodeActionParams {
+        text_document: lsp::TextDocumentIdentifier {
+            uri: lsp::Url::parse("file:///home/user/file.flux")
+                .unwrap(),
+        },
+        context: lsp::CodeActionContext {
+            diagnostics: vec![lsp::Diagnostic {
+                code: None,
+                code_description: None,
+                data: None,
+                related_information: None,
+                severity: Some(lsp::DiagnosticSeverity::ERROR),
+                source: Some("flux".into()),
+                tags: None,
+                message: "undefined identifier array".into(),
+                range: lsp::Range {
+                    start: lsp::Position {
+                        line: 0,
+                        character: 0,
+                    },
+                    end: lsp::Position {
+                        line: 0,
+                        character: 0,
+                    },
+                },
+            }],
+            only: None,
+        },
+        range: lsp::Range {
+            start: lsp::Position {
+                line: 0,
+                character: 4,
+            },
+            end: lsp::Position {
+                line: 0,
+                character: 4,
+            },
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
+
+    let result = server.code_action(params).await.unwrap();
+
+    expect_test::expect![[r#"
+            [
+              {
+                "title": "Import `array`",
+                "kind": "quickfix",
+                "edit": {
+                  "changes": {
+                    "file:///home/user/file.flux": [
+                      {
+                        "range": {
+                          "start": {
+                            "line": 0,
+                            "character": 0
+                          },
+                          "end": {
+                            "line": 0,
+                            "character": 0
+                          }
+                        },
+                        "newText": "import \"array\"\n"
+                      }
+                    ]
+                  }
+                },
+                "isPreferred": true
+              },
+              {
+                "title": "Import `experimental/array`",
+                "kind": "quickfix",
+                "edit": {
+                  "changes": {
+                    "file:///home/user/file.flux": [
+                      {
+                        "range": {
+                          "start": {
+                            "line": 0,
+                            "character": 0
+                          },
+                          "end": {
+                            "line": 0,
+                            "character": 0
+                          }
+                        },
+                        "newText": "import \"experimental/array\"\n"
+                      }
+                    ]
+                  }
+                },
+                "isPreferred": true
+              }
+            ]"#]]
+    .assert_eq(&serde_json::to_string_pretty(&result).unwrap());
+}
+
+/// The pipelines of the statement at the cursor are toggled between one line and one
+/// stage per line, without touching the rest of the document.
+#[test]
+async fn test_code_action_toggle_pipeline() {
+    let fluxscript = r#"x = from(bucket: "a")   |>   range(start: -1h)
+data = from(bucket: "a") |> range(start: -1h) |> mean()
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = |only| lsp::CodeActionParams {
+        text_document: lsp::TextDocumentIdentifier {
+            uri: lsp::Url::parse("file:///home/user/file.flux")
+                .unwrap(),
+        },
+        context: lsp::CodeActionContext {
+            diagnostics: vec![],
+            only,
+        },
+        range: lsp::Range {
+            start: lsp::Position::new(1, 3),
+            end: lsp::Position::new(1, 3),
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
+
+    let result =
+        server.code_action(params(None)).await.unwrap().unwrap();
+    let action = match &result[..] {
+        [lsp::CodeActionOrCommand::CodeAction(action)] => action,
+        _ => unreachable!(),
+    };
+    assert_eq!(
+        "Put each stage of the pipeline on its own line",
+        action.title
+    );
+    assert_eq!(
+        vec![lsp::TextEdit {
+            range: lsp::Range {
+                start: lsp::Position::new(1, 0),
+                end: lsp::Position::new(1, 55),
+            },
+            new_text: r#"data = from(bucket: "a")
+    |> range(start: -1h)
+    |> mean()"#
+                .to_string(),
+        }],
+        action.edit.as_ref().unwrap().changes.as_ref().unwrap()
+            [&lsp::Url::parse("file:///home/user/file.flux")
+                .unwrap()]
+    );
+
+    assert_eq!(
+        None,
+        server
+            .code_action(params(Some(vec![
+                lsp::CodeActionKind::QUICKFIX
+            ])))
+            .await
+            .unwrap()
+    );
+}
+
+/// A package whose name is already taken is imported with an alias, and the undefined
+/// identifier renamed to it.
+#[test]
+async fn test_code_action_import_insertion_alias() {
+    let fluxscript = r#"import "array"
+
+arra.from(rows: [{a: 1}])"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let params = lsp::CodeActionParams {
+        text_document: lsp::TextDocumentIdentifier {
+            uri: uri.clone(),
+        },
+        context: lsp::CodeActionContext {
+            diagnostics: vec![lsp::Diagnostic {
+                range: lsp::Range {
+                    start: lsp::Position::new(2, 0),
+                    end: lsp::Position::new(2, 4),
+                },
+                severity: Some(lsp::DiagnosticSeverity::ERROR),
+                source: Some("flux".into()),
+                message: "undefined identifier arra".into(),
+                ..lsp::Diagnostic::default()
+            }],
+            only: None,
+        },
+        range: lsp::Range {
+            start: lsp::Position::new(2, 2),
+            end: lsp::Position::new(2, 2),
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
+
+    let result = server.code_action(params).await.unwrap().unwrap();
+
+    let action = result
+        .iter()
+        .find_map(|action| match action {
+            lsp::CodeActionOrCommand::CodeAction(action)
+                if action.title
+                    == "Import `experimental/array` as `experimentalArray`" =>
+            {
+                Some(action)
+            }
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(
+        vec![
+            lsp::TextEdit {
+                range: lsp::Range {
+                    start: lsp::Position::new(2, 0),
+                    end: lsp::Position::new(2, 4),
+                },
+                new_text: "experimentalArray".into(),
+            },
+            lsp::TextEdit {
+                range: lsp::Range {
+                    start: lsp::Position::new(1, 0),
+                    end: lsp::Position::new(1, 0),
+                },
+                new_text:
+                    "import experimentalArray \"experimental/array\"\n"
+                        .into(),
+            },
+        ],
+        action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri]
+    );
+    assert!(!result.iter().any(|action| matches!(
+        action,
+        lsp::CodeActionOrCommand::CodeAction(action) if action.title == "Import `array`"
+    )));
+}
+
+/// If the import requires a full path, that the action suggests the full path.
+#[test]
+async fn test_code_action_import_insertion_full_path() {
+    let fluxscript = r#"schema"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::CodeActionParams {
+        text_document: lsp::TextDocumentIdentifier {
+            uri: lsp::Url::parse("file:///home/user/file.flux")
+                .unwrap(),
+        },
+        context: lsp::CodeActionContext {
+            diagnostics: vec![lsp::Diagnostic {
+                code: None,
+                code_description: None,
+                data: None,
+                related_information: None,
+                severity: Some(lsp::DiagnosticSeverity::ERROR),
+                source: Some("flux".into()),
+                tags: None,
+                message: "undefined identifier schema".into(),
+                range: lsp::Range {
+                    start: lsp::Position {
+                        line: 0,
+                        character: 0,
+                    },
+                    end: lsp::Position {
+                        line: 0,
+                        character: 0,
+                    },
+                },
+            }],
+            only: None,
+        },
+        range: lsp::Range {
+            start: lsp::Position {
+                line: 0,
+                character: 5,
+            },
+            end: lsp::Position {
+                line: 0,
+                character: 5,
+            },
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
+
+    let result = server.code_action(params).await.unwrap();
+
+    expect_test::expect![[r#"
+            [
+              {
+                "title": "Import `influxdata/influxdb/schema`",
+                "kind": "quickfix",
+                "edit": {
+                  "changes": {
+                    "file:///home/user/file.flux": [
+                      {
+                        "range": {
+                          "start": {
+                            "line": 0,
+                            "character": 0
+                          },
+                          "end": {
+                            "line": 0,
+                            "character": 0
+                          }
+                        },
+                        "newText": "import \"influxdata/influxdb/schema\"\n"
+                      }
+                    ]
+                  }
+                },
+                "isPreferred": true
+              }
+            ]"#]]
+        .assert_eq(&serde_json::to_string_pretty(&result).unwrap());
+}
+
+#[test]
+async fn compute_diagnostics_multi_file() {
+    let server = create_server();
+
+    let filename: String = "file:///path/to/script.flux".into();
+    let fluxscript = r#"from(bucket: "my-bucket")
+|> range(start: -100d)
+|> filter(fn: (r) => r.anTag == v.a)"#;
+    open_file(&server, fluxscript.into(), Some(&filename)).await;
+
+    let diagnostics = server
+        .compute_diagnostics(&lsp::Url::parse(&filename).unwrap());
+
+    assert_eq!(
+        HashMap::from([(
+            lsp::Url::parse("file:///path/to/script.flux").unwrap(),
+            vec![lsp::Diagnostic {
+                code: Some(lsp::NumberOrString::String(
+                    "type-error".into()
+                )),
+                code_description: None,
+                data: None,
+                message: "undefined identifier v".into(),
+                range: lsp::Range {
+                    start: lsp::Position {
+                        line: 2,
+                        character: 32
+                    },
+                    end: lsp::Position {
+                        line: 2,
+                        character: 33
+                    },
+                },
+                related_information: None,
+                severity: Some(lsp::DiagnosticSeverity::ERROR),
+                source: Some("flux".into()),
+                tags: None,
+            }]
+        ),]),
+        diagnostics
+    );
+
+    open_file(
+        &server,
+        r#"v = {a: "b"}"#.to_string(),
+        Some("file:///path/to/an_vars.flux"),
+    )
+    .await;
+
+    let diagnostics_again = server
+        .compute_diagnostics(&lsp::Url::parse(&filename).unwrap());
+
+    let expected: HashMap<lsp::Url, Vec<lsp::Diagnostic>> = HashMap::from([
+        (lsp::Url::parse("file:///path/to/script.flux").unwrap(), vec![]),
+        (lsp::Url::parse("file:///path/to/an_vars.flux").unwrap(), vec![lsp::Diagnostic {
+            range: lsp::Range {
+                start: lsp::Position {
+                    line: 0, character: 0,
+                },
+                end: lsp::Position {
+                    line: 0, character: 1,
+                }
+            },
+            severity: Some(lsp::DiagnosticSeverity::WARNING),
+            code: Some(lsp::NumberOrString::String("influxdb-identifier".into())),
+            message: "Avoid using `v` as an identifier name. In some InfluxDB contexts, it may be provided at runtime.".to_string(),
+            ..lsp::Diagnostic::default()
+        }]),
+    ]);
+
+    // We are interested in the error from the original file being fixed, not in
+    // the lints introduced by the new file.
+    assert_eq!(expected, diagnostics_again);
+}
+
+/// Diagnostics are only re-published for files whose diagnostics changed since
+/// they were last published.
+#[test]
+async fn changed_diagnostics_skips_unchanged_files() {
+    let server = create_server();
+
+    let filename: String = "file:///path/to/script.flux".into();
+    open_file(
+        &server,
+        r#"from(bucket: "my-bucket") |> range(start: v.a)"#.into(),
+        Some(&filename),
+    )
+    .await;
+    open_file(
+        &server,
+        r#"x = 1"#.into(),
+        Some("file:///path/to/other.flux"),
+    )
+    .await;
+    let url = lsp::Url::parse(&filename).unwrap();
+
+    let first =
+        server.changed_diagnostics(server.compute_diagnostics(&url));
+    assert_eq!(2, first.len());
+
+    let second =
+        server.changed_diagnostics(server.compute_diagnostics(&url));
+    assert!(second.is_empty());
+
+    open_file(
+        &server,
+        r#"from(bucket: "my-bucket") |> range(start: -1h)"#.into(),
+        Some(&filename),
+    )
+    .await;
+
+    let third =
+        server.changed_diagnostics(server.compute_diagnostics(&url));
+    assert_eq!(HashMap::from([(url, vec![])]), third);
+}
+
+/// Missing record labels are reported on the property access, along with the
+/// labels that are available.
+#[test]
+async fn compute_diagnostics_missing_label() {
+    let server = create_server();
+
+    let filename: String = "file:///path/to/script.flux".into();
+    let fluxscript = r#"import "array"
+
+array.from(rows: [{a: 1, b: 2}]) |> filter(fn: (r) => r.c == 1)"#;
+    open_file(&server, fluxscript.into(), Some(&filename)).await;
+
+    let url = lsp::Url::parse(&filename).unwrap();
+    let diagnostics = server.compute_diagnostics(&url);
+
+    let diagnostic = &diagnostics[&url][0];
+    assert_eq!(
+        lsp::Range {
+            start: lsp::Position {
+                line: 2,
+                character: 54,
+            },
+            end: lsp::Position {
+                line: 2,
+                character: 57,
+            },
+        },
+        diagnostic.range
+    );
+    assert!(
+        diagnostic.message.ends_with("(available labels: a, b)"),
+        "{}",
+        diagnostic.message
+    );
+}
+
+/// Diagnostics beyond `maxDiagnosticsPerFile` are dropped, and replaced by a note
+/// about the truncation.
+#[test]
+async fn compute_diagnostics_max_per_file() {
+    let server = create_server();
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"maxDiagnosticsPerFile": 2}}),
+        })
+        .await;
+
+    let filename: String = "file:///path/to/script.flux".into();
+    let fluxscript = r#"a = x
+b = y
+c = z"#;
+    open_file(&server, fluxscript.into(), Some(&filename)).await;
+
+    let url = lsp::Url::parse(&filename).unwrap();
+    let diagnostics = server.compute_diagnostics(&url);
+
+    let diagnostics = &diagnostics[&url];
+    assert_eq!(3, diagnostics.len());
+    assert_eq!(
+        vec![
+            Some(lsp::DiagnosticSeverity::ERROR),
+            Some(lsp::DiagnosticSeverity::ERROR),
+            Some(lsp::DiagnosticSeverity::INFORMATION),
+        ],
+        diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.severity)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        "Showing 2 of 3 diagnostics for this file",
+        diagnostics[2].message
+    );
+}
+
+/// Errors from flux's checks, e.g. a reassigned variable, are only reported in strict
+/// analysis.
+#[test]
+async fn compute_diagnostics_strict_analysis() {
+    let server = create_server();
+
+    let filename: String = "file:///path/to/script.flux".into();
+    let fluxscript = r#"a = 1
+a = 2"#;
+    open_file(&server, fluxscript.into(), Some(&filename)).await;
+    let url = lsp::Url::parse(&filename).unwrap();
+
+    let diagnostics = server.compute_diagnostics(&url);
+    assert_eq!(1, diagnostics[&url].len());
+    assert_eq!(
+        Some(lsp::DiagnosticSeverity::ERROR),
+        diagnostics[&url][0].severity
+    );
+
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"strictAnalysis": false}}),
+        })
+        .await;
+
+    let diagnostics = server.compute_diagnostics(&url);
+    assert!(diagnostics[&url].is_empty(), "{:?}", diagnostics[&url]);
+}
+
+/// `flux-lsp:ignore-next-line` comments suppress the diagnostics with the given codes
+/// on the next line. Once every error is suppressed, lints are reported instead.
+#[test]
+async fn compute_diagnostics_ignore_next_line() {
+    let server = create_server();
+
+    let filename: String = "file:///path/to/script.flux".into();
+    let fluxscript = r#"// flux-lsp:ignore-next-line type-error
+a = x
+// flux-lsp:ignore-next-line camel-case
+my_b = 1
+my_c = 2"#;
+    open_file(&server, fluxscript.into(), Some(&filename)).await;
+    let url = lsp::Url::parse(&filename).unwrap();
+
+    let diagnostics = server.compute_diagnostics(&url);
+
+    assert_eq!(
+        vec![(
+            4,
+            Some(lsp::NumberOrString::String("camel-case".into()))
+        )],
+        diagnostics[&url]
+            .iter()
+            .map(|diagnostic| (
+                diagnostic.range.start.line,
+                diagnostic.code.clone()
+            ))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+async fn compute_diagnostics_non_errors() {
+    let server = create_server();
+
+    let filename: String = "file:///path/to/script.flux".into();
+    let fluxscript = r#"import "experimental"
+
+from(bucket: "my-bucket")
+|> range(start: -100d)
+|> filter(fn: (r) => r.value == "b")
+|> experimental.to(bucket: "out-bucket", org: "abc123", host: "https://myhost.example.com", token: "123abc")"#;
+    open_file(&server, fluxscript.into(), Some(&filename)).await;
+
+    let diagnostics_again = server
+        .compute_diagnostics(&lsp::Url::parse(&filename).unwrap());
+
+    assert!(!diagnostics_again.is_empty());
+}
+
+// All commands require key/value pairs as params, not positional
+/// arguments.
+#[test]
+async fn execute_command_too_many_args() {
+    let server = create_server();
+    let params = lsp::ExecuteCommandParams {
+        command: "notActuallyAValidCommand".into(),
+        arguments: vec![
+            serde_json::value::to_value("arg1").unwrap(),
+            serde_json::value::to_value("arg2").unwrap(),
+        ],
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    };
+
+    let result = server.execute_command(params).await;
+
+    assert!(result.is_err());
+}
+
+// Attempting to execute a command that doesn't exist results in an Err response.
+#[test]
+async fn execute_command_unknown_command() {
+    let server = create_server();
+    let params = lsp::ExecuteCommandParams {
+        command: "notActuallyAValidCommand".into(),
+        arguments: vec![serde_json::json!({"foo": "bar"})],
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    };
+
+    let result = server.execute_command(params).await;
+
+    assert!(result.is_err());
+}
+
+#[test]
+async fn execute_command_get_snippets() {
+    let server = create_server();
+    let params = lsp::ExecuteCommandParams {
+        command: "getSnippets".into(),
+        arguments: vec![],
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    };
+
+    let result: Vec<serde_json::Value> = serde_json::from_value(
+        server.execute_command(params).await.unwrap().unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec![
+            "downsampleTask",
+            "thresholdAlert",
+            "pivotFields",
+            "lastValue"
+        ],
+        result
+            .iter()
+            .map(|snippet| snippet["prefix"].as_str().unwrap())
+            .collect::<Vec<&str>>()
+    );
+    assert!(result.iter().all(|snippet| snippet["body"].is_string()
+        && snippet["name"].is_string()
+        && snippet["description"].is_string()));
+}
+
+#[test]
+async fn execute_command_get_lint_rules() {
+    let server = create_server();
+    let params = lsp::ExecuteCommandParams {
+        command: "getLintRules".into(),
+        arguments: vec![],
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    };
+
+    let result: Vec<serde_json::Value> = serde_json::from_value(
+        server.execute_command(params).await.unwrap().unwrap(),
+    )
+    .unwrap();
+
+    let rule = result
+        .iter()
+        .find(|rule| rule["id"] == "unused-parameter")
+        .unwrap();
+    assert_eq!(
+        json!({
+            "id": "unused-parameter",
+            "description": "Function parameters that are never used.",
+            "defaultSeverity": "hint",
+            "configurable": true,
+            "optIn": false,
+            "quickfix": true,
+        }),
+        *rule
+    );
+    let opt_in: Vec<&str> = result
+        .iter()
+        .filter(|rule| rule["optIn"] == true)
+        .map(|rule| rule["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(vec!["aggregate-window-create-empty"], opt_in);
+}
+
+#[test]
+async fn execute_command_get_command_schemas() {
+    let server = create_server();
+    let params = lsp::ExecuteCommandParams {
+        command: "getCommandSchemas".into(),
+        arguments: vec![],
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    };
+
+    let result =
+        server.execute_command(params).await.unwrap().unwrap();
+
+    assert_eq!(
+        json!({
+            "type": "object",
+            "properties": {
+                "bucket": {"type": "string"},
+                "newName": {"type": "string"},
+            },
+            "required": ["bucket", "newName"],
+        }),
+        result["renameBucket"]
+    );
+    assert_eq!(json!({"type": "null"}), result["getSnippets"]);
+    assert_eq!(
+        json!(["textDocument", "position", "stage", "direction"]),
+        result["movePipelineStage"]["required"]
+    );
+}
+
+/// Every missing and unknown field of the params of a command is reported.
+#[test]
+async fn execute_command_mismatched_params() {
+    let server = create_server();
+    let params = lsp::ExecuteCommandParams {
+        command: "renameBucket".into(),
+        arguments: vec![
+            json!({"bucket": "a", "name": "b", "force": true}),
+        ],
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    };
+
+    let err = server.execute_command(params).await.unwrap_err();
+
+    assert_eq!(lspower::jsonrpc::ErrorCode::InvalidParams, err.code);
+    assert_eq!(
+        "Invalid params for renameBucket: missing fields `newName`; unknown fields `force`, `name`",
+        err.message
+    );
+    assert_eq!(
+        Some(
+            json!({"missing": ["newName"], "unknown": ["force", "name"]})
+        ),
+        err.data
+    );
+}
+
+fn move_pipeline_stage_params(
+    position: lsp::Position,
+    stage: usize,
+    direction: &str,
+) -> lsp::ExecuteCommandParams {
+    lsp::ExecuteCommandParams {
+        command: "movePipelineStage".into(),
+        arguments: vec![serde_json::json!({
+            "textDocument": {"uri": "file:///home/user/file.flux"},
+            "position": position,
+            "stage": stage,
+            "direction": direction,
+        })],
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    }
+}
+
+#[test]
+async fn execute_command_move_pipeline_stage() {
+    let fluxscript = r#"from(bucket: "a")
+    |> range(start: -1h)
+    |> filter(fn: (r) => r._measurement == "cpu")
+    |> filter(fn: (r) => r._field == "usage")
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let result: lsp::WorkspaceEdit = serde_json::from_value(
+        server
+            .execute_command(move_pipeline_stage_params(
+                lsp::Position::new(1, 8),
+                3,
+                "up",
+            ))
+            .await
+            .unwrap()
+            .unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        lsp::WorkspaceEdit {
+            changes: Some(HashMap::from([(
+                lsp::Url::parse("file:///home/user/file.flux").unwrap(),
+                vec![
+                    lsp::TextEdit {
+                        range: lsp::Range {
+                            start: lsp::Position::new(3, 7),
+                            end: lsp::Position::new(3, 45),
+                        },
+                        new_text:
+                            r#"filter(fn: (r) => r._measurement == "cpu")"#
+                                .into(),
+                    },
+                    lsp::TextEdit {
+                        range: lsp::Range {
+                            start: lsp::Position::new(2, 7),
+                            end: lsp::Position::new(2, 49),
+                        },
+                        new_text: r#"filter(fn: (r) => r._field == "usage")"#
+                            .into(),
+                    },
+                ],
+            )])),
+            document_changes: None,
+            change_annotations: None,
+        },
+        result
+    );
+}
+
+/// Moves that the analyzer can't prove safe are refused.
+#[test]
+async fn execute_command_move_pipeline_stage_unsafe() {
+    let fluxscript = r#"from(bucket: "a")
+    |> range(start: -1h)
+    |> filter(fn: (r) => r._measurement == "cpu")
+    |> map(fn: (r) => ({_time: r._time, _value: 1.0}))
+    |> to(bucket: "b")
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    for (stage, direction) in [
+        // The head of the pipeline stays the head.
+        (0, "down"),
+        (1, "up"),
+        // The filter would read a column that `map` drops.
+        (3, "up"),
+        // `to` writes data.
+        (4, "up"),
+        // There is no stage after the last one.
+        (4, "down"),
+    ] {
+        let result = server
+            .execute_command(move_pipeline_stage_params(
+                lsp::Position::new(1, 8),
+                stage,
+                direction,
+            ))
+            .await;
+
+        assert!(result.is_err(), "{} {}", stage, direction);
+    }
+}
+
+#[test]
+async fn execute_command_remove_pipeline_stage() {
+    let fluxscript = r#"from(bucket: "a")
+    |> range(start: -1h)
+    // Only cpu
+    |> filter(fn: (r) => r._measurement == "cpu") // trailing
+    |> sum()
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let expected = lsp::WorkspaceEdit {
+        changes: Some(HashMap::from([(
+            lsp::Url::parse("file:///home/user/file.flux").unwrap(),
+            vec![lsp::TextEdit {
+                range: lsp::Range {
+                    start: lsp::Position::new(1, 24),
+                    end: lsp::Position::new(3, 61),
+                },
+                new_text: "".into(),
+            }],
+        )])),
+        document_changes: None,
+        change_annotations: None,
+    };
+    for arguments in [
+        json!({
+            "textDocument": {"uri": "file:///home/user/file.flux"},
+            "position": {"line": 0, "character": 1},
+            "stage": 2,
+        }),
+        json!({
+            "textDocument": {"uri": "file:///home/user/file.flux"},
+            "range": {
+                "start": {"line": 3, "character": 7},
+                "end": {"line": 3, "character": 49},
+            },
+        }),
+    ] {
+        let params = lsp::ExecuteCommandParams {
+            command: "removePipelineStage".into(),
+            arguments: vec![arguments],
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+        };
+
+        let result: lsp::WorkspaceEdit = serde_json::from_value(
+            server.execute_command(params).await.unwrap().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(expected, result);
+    }
+}
+
+#[test]
+async fn execute_command_set_call_argument() {
+    let fluxscript = r#"from(bucket: "a")
+    |> range(start: -1h)
+    |> aggregateWindow(every: 5m, fn: mean)
+    |> sum()
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    for (arguments, expected) in [
+        // An existing argument of the call at the position.
+        (
+            json!({"position": {"line": 2, "character": 10}, "name": "every", "value": "1h"}),
+            lsp::TextEdit {
+                range: lsp::Range {
+                    start: lsp::Position::new(2, 30),
+                    end: lsp::Position::new(2, 32),
+                },
+                new_text: "1h".into(),
+            },
+        ),
+        // A new argument of a stage.
+        (
+            json!({"position": {"line": 0, "character": 1}, "stage": 2, "name": "createEmpty", "value": "false"}),
+            lsp::TextEdit {
+                range: lsp::Range {
+                    start: lsp::Position::new(2, 42),
+                    end: lsp::Position::new(2, 42),
+                },
+                new_text: ", createEmpty: false".into(),
+            },
+        ),
+        // The first argument of a call without any.
+        (
+            json!({"position": {"line": 0, "character": 1}, "stage": 3, "name": "column", "value": "\"_value\""}),
+            lsp::TextEdit {
+                range: lsp::Range {
+                    start: lsp::Position::new(3, 11),
+                    end: lsp::Position::new(3, 11),
+                },
+                new_text: "column: \"_value\"".into(),
+            },
+        ),
+    ] {
+        let mut arguments = arguments;
+        arguments["textDocument"] =
+            json!({"uri": "file:///home/user/file.flux"});
+        let params = lsp::ExecuteCommandParams {
+            command: "setCallArgument".into(),
+            arguments: vec![arguments],
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+        };
+
+        let result: lsp::WorkspaceEdit = serde_json::from_value(
+            server.execute_command(params).await.unwrap().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            Some(HashMap::from([(
+                lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+                vec![expected],
+            )])),
+            result.changes
+        );
+    }
+}
+
+#[test]
+async fn test_code_lens_testcase() {
+    let fluxscript = r#"import "testing"
+
+testcase addition {
+    testing.assertEqualValues(got: 1 + 1, want: 2)
+}
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::CodeLensParams {
+        text_document: lsp::TextDocumentIdentifier {
+            uri: lsp::Url::parse("file:///home/user/file.flux")
+                .unwrap(),
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
+
+    let lenses = server.code_lens(params).await.unwrap().unwrap();
+
+    assert_eq!(
+        vec![
+            (
+                "Run test",
+                json!({"textDocument": {"uri": "file:///home/user/file.flux"}, "names": ["addition"], "verbose": false})
+            ),
+            (
+                "Debug output",
+                json!({"textDocument": {"uri": "file:///home/user/file.flux"}, "names": ["addition"], "verbose": true})
+            ),
+        ],
+        lenses
+            .iter()
+            .map(|lens| {
+                let command = lens.command.as_ref().unwrap();
+                assert_eq!("runTests", command.command);
+                (
+                    command.title.as_str(),
+                    command.arguments.as_ref().unwrap()[0].clone(),
+                )
+            })
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+async fn execute_command_discover_tests() {
+    let fluxscript = r#"import "testing"
+
+testcase addition {
+    testing.assertEqualValues(got: 1 + 1, want: 2)
+}
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::ExecuteCommandParams {
+        command: "discoverTests".into(),
+        arguments: vec![
+            json!({"textDocument": {"uri": "file:///home/user/file.flux"}}),
+        ],
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    };
+
+    let result: Vec<protocol_ext::TestCase> = serde_json::from_value(
+        server.execute_command(params).await.unwrap().unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(1, result.len());
+    assert_eq!("addition", result[0].name);
+    assert_eq!(
+        lsp::Range {
+            start: lsp::Position::new(2, 0),
+            end: lsp::Position::new(4, 1),
+        },
+        result[0].range
+    );
+}
+
+#[test]
+async fn execute_command_find_bucket_references() {
+    let server = create_server();
+    open_file(
+        &server,
+        r#"from(bucket: "telegraf")
+    |> range(start: -1h)
+    |> to(bucket: "downsampled")
+"#
+        .to_string(),
+        None,
+    )
+    .await;
+    open_file(
+        &server,
+        r#"option task = {name: "downsample", every: 1h}
+
+from(bucket: "sensors")
+    |> range(start: -task.every)
+    |> to(bucket: "telegraf")
+"#
+        .to_string(),
+        Some("file:///home/user/tasks/downsample.flux"),
+    )
+    .await;
+
+    let params = lsp::ExecuteCommandParams {
+        command: "findBucketReferences".into(),
+        arguments: vec![json!({"bucket": "telegraf"})],
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    };
+
+    let result: Vec<lsp::Location> = serde_json::from_value(
+        server.execute_command(params).await.unwrap().unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec![
+            lsp::Location::new(
+                lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+                lsp::Range {
+                    start: lsp::Position::new(0, 13),
+                    end: lsp::Position::new(0, 23),
+                },
+            ),
+            lsp::Location::new(
+                lsp::Url::parse(
+                    "file:///home/user/tasks/downsample.flux"
+                )
+                .unwrap(),
+                lsp::Range {
+                    start: lsp::Position::new(4, 18),
+                    end: lsp::Position::new(4, 28),
+                },
+            ),
+        ],
+        result
+    );
+}
+
+#[test]
+async fn execute_command_rename_bucket() {
+    let server = create_server();
+    open_file(
+        &server,
+        r#"from(bucket: "telegraf")
+    |> range(start: -1h)
+    |> to(bucket: "telegraf")
+"#
+        .to_string(),
+        None,
+    )
+    .await;
+    open_file(
+        &server,
+        r#"from(bucket: "sensors") |> range(start: -1h)"#.to_string(),
+        Some("file:///home/user/sensors.flux"),
+    )
+    .await;
+
+    let params = lsp::ExecuteCommandParams {
+        command: "renameBucket".into(),
+        arguments: vec![
+            json!({"bucket": "telegraf", "newName": "metrics"}),
+        ],
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    };
+
+    let edit: lsp::WorkspaceEdit = serde_json::from_value(
+        server.execute_command(params).await.unwrap().unwrap(),
+    )
+    .unwrap();
+
+    let changes = match edit.document_changes {
+        Some(lsp::DocumentChanges::Edits(changes)) => changes,
+        _ => panic!("expected document edits"),
+    };
+    assert_eq!(1, changes.len());
+    assert_eq!(
+        lsp::Url::parse("file:///home/user/file.flux").unwrap(),
+        changes[0].text_document.uri
+    );
+    assert_eq!(
+        vec![
+            (lsp::Position::new(2, 18), "\"metrics\"".to_string()),
+            (lsp::Position::new(0, 13), "\"metrics\"".to_string()),
+        ],
+        changes[0]
+            .edits
+            .iter()
+            .map(|edit| match edit {
+                lsp::OneOf::Right(edit) => (
+                    edit.text_edit.range.start,
+                    edit.text_edit.new_text.clone()
+                ),
+                lsp::OneOf::Left(_) =>
+                    panic!("expected annotated edits"),
+            })
+            .collect::<Vec<_>>()
+    );
+    let annotations = edit.change_annotations.unwrap();
+    assert_eq!(
+        Some(true),
+        annotations["renameBucket"].needs_confirmation
+    );
+}
+
+/// A `${` in the new name would start an interpolation, and is escaped along with
+/// quotes.
+#[test]
+async fn execute_command_rename_bucket_escaped() {
+    let server = create_server();
+    open_file(
+        &server,
+        r#"from(bucket: "telegraf") |> range(start: -1h)"#
+            .to_string(),
+        None,
+    )
+    .await;
+
+    let params = lsp::ExecuteCommandParams {
+        command: "renameBucket".into(),
+        arguments: vec![json!({
+            "bucket": "telegraf",
+            "newName": "${env}\"metrics\"",
+        })],
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    };
+
+    let edit: lsp::WorkspaceEdit = serde_json::from_value(
+        server.execute_command(params).await.unwrap().unwrap(),
+    )
+    .unwrap();
+
+    let changes = match edit.document_changes {
+        Some(lsp::DocumentChanges::Edits(changes)) => changes,
+        _ => panic!("expected document edits"),
+    };
+    assert_eq!(
+        vec![r#""\${env}\"metrics\"""#.to_string()],
+        changes[0]
+            .edits
+            .iter()
+            .map(|edit| match edit {
+                lsp::OneOf::Right(edit) =>
+                    edit.text_edit.new_text.clone(),
+                lsp::OneOf::Left(_) =>
+                    panic!("expected annotated edits"),
+            })
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+async fn execute_command_debug_document_state() {
+    let server = create_server();
+    open_file(&server, "x = 1\ny =".to_string(), None).await;
+
+    let params = lsp::ExecuteCommandParams {
+        command: "debugDocumentState".into(),
+        arguments: vec![json!({
+            "textDocument": {"uri": "file:///home/user/file.flux"},
+        })],
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    };
+
+    let state: protocol_ext::DocumentState = serde_json::from_value(
+        server.execute_command(params).await.unwrap().unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(16, state.contents_hash.len());
+    assert_eq!(Some(1), state.version);
+    assert!(state.parse_errors >= 1);
+    assert!(state.semantic_errors >= 1);
+    assert_eq!(
+        vec![lsp::Url::parse("file:///home/user/file.flux").unwrap()],
+        state.package_files
+    );
+    // The package is analyzed as it is opened.
+    assert!(state.analysis_cached);
+    assert!(state.composition.is_none());
+    assert!(state.last_analysis_millis.is_some());
+}
+
+/// Only literal values are set, so the edit can't change the meaning of the rest of
+/// the query.
+#[test]
+async fn execute_command_set_call_argument_not_literal() {
+    let fluxscript = r#"from(bucket: "a")
+    |> range(start: -1h)
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::ExecuteCommandParams {
+        command: "setCallArgument".into(),
+        arguments: vec![json!({
+            "textDocument": {"uri": "file:///home/user/file.flux"},
+            "position": {"line": 1, "character": 10},
+            "name": "start",
+            "value": "-1h) |> drop(columns: [\"_value\"]",
+        })],
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    };
+
+    let result = server.execute_command(params).await;
+
+    assert!(result.is_err());
+}
+
+#[test]
+async fn test_pipeline_headers() {
+    let fluxscript = r#"data = from(bucket: "a")
+    |> range(start: -1h)
+    |> mean()
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let result = server
+        .request_else(
+            "flux/pipelineHeaders",
+            Some(json!({
+                "textDocument": {"uri": "file:///home/user/file.flux"},
+            })),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    let headers: Vec<protocol_ext::PipelineHeader> =
+        serde_json::from_value(result).unwrap();
+
+    assert_eq!(
+        vec![protocol_ext::PipelineHeader {
+            range: lsp::Range::new(
+                lsp::Position::new(0, 0),
+                lsp::Position::new(2, 13)
+            ),
+            header: lsp::Range::new(
+                lsp::Position::new(0, 0),
+                lsp::Position::new(0, 24)
+            ),
+        }],
+        headers
+    );
+}
+
+#[test]
+async fn test_inline_values() {
+    let fluxscript = r#"x = 10
+y = x * 2
+double = (v) => v * 2
+z = double(v: y)
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let result = server
+        .request_else(
+            "textDocument/inlineValue",
+            Some(json!({
+                "textDocument": {"uri": "file:///home/user/file.flux"},
+                "range": {
+                    "start": {"line": 0, "character": 0},
+                    "end": {"line": 4, "character": 0},
+                },
+                "context": {
+                    "frameId": 1,
+                    "stoppedLocation": {
+                        "start": {"line": 2, "character": 0},
+                        "end": {"line": 2, "character": 0},
+                    },
+                },
+            })),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    let values: Vec<protocol_ext::InlineValueVariableLookup> =
+        serde_json::from_value(result).unwrap();
+
+    assert_eq!(
+        vec![
+            (lsp::Position::new(0, 0), "x"),
+            (lsp::Position::new(1, 0), "y"),
+            (lsp::Position::new(1, 4), "x"),
+        ],
+        values
+            .iter()
+            .map(|value| (
+                value.range.start,
+                value.variable_name.as_deref().unwrap()
+            ))
+            .collect::<Vec<_>>()
+    );
+}
+
+async fn update_schema(server: &LspServer) {
+    server
+        .request_else(
+            "flux/updateSchema",
+            Some(json!({
+                "buckets": {
+                    "telegraf": {
+                        "measurements": {
+                            "cpu": {"fields": ["usage_user"], "tags": ["host"]},
+                            "mem": {"fields": ["used"]},
+                        },
+                    },
+                },
+            })),
+        )
+        .await
+        .unwrap();
+}
+
+/// Bucket, measurement and field names are completed from the pushed schema.
+#[test]
+async fn test_schema_completion() {
+    let fluxscript = r#"from(bucket: "")
+    |> filter(fn: (r) => r._measurement == "")
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+    update_schema(&server).await;
+    server.write_state().set_buckets(vec!["_monitoring".into()]);
+
+    for (position, expected) in [
+        (lsp::Position::new(0, 14), vec!["_monitoring", "telegraf"]),
+        (lsp::Position::new(1, 44), vec!["cpu", "mem"]),
+    ] {
+        let params = lsp::CompletionParams {
+            text_document_position: lsp::TextDocumentPositionParams {
+                text_document: lsp::TextDocumentIdentifier {
+                    uri: lsp::Url::parse(
+                        "file:///home/user/file.flux",
+                    )
+                    .unwrap(),
+                },
+                position,
+            },
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: lsp::PartialResultParams {
+                partial_result_token: None,
+            },
+            context: None,
+        };
+
+        let result =
+            server.completion(params).await.unwrap().unwrap();
+
+        let items = match result {
+            lsp::CompletionResponse::List(l) => l.items,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            expected,
+            items
+                .iter()
+                .map(|item| item.label.as_str())
+                .collect::<Vec<&str>>()
+        );
+    }
+}
+
+/// Names passed to the functions of the schema package are completed too, narrowed
+/// down to the bucket and measurement passed along with them.
+#[test]
+async fn test_schema_function_completion() {
+    let fluxscript = r#"import "influxdata/influxdb/schema"
+
+schema.measurementTagKeys(bucket: "telegraf", measurement: "")
+schema.measurementTagValues(bucket: "telegraf", measurement: "cpu", tag: "")
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+    update_schema(&server).await;
+
+    for (position, expected) in [
+        (lsp::Position::new(2, 60), vec!["cpu", "mem"]),
+        (lsp::Position::new(3, 74), vec!["host"]),
+    ] {
+        let params = lsp::CompletionParams {
+            text_document_position: lsp::TextDocumentPositionParams {
+                text_document: lsp::TextDocumentIdentifier {
+                    uri: lsp::Url::parse(
+                        "file:///home/user/file.flux",
+                    )
+                    .unwrap(),
+                },
+                position,
+            },
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: lsp::PartialResultParams {
+                partial_result_token: None,
+            },
+            context: None,
+        };
+
+        let result =
+            server.completion(params).await.unwrap().unwrap();
+
+        let items = match result {
+            lsp::CompletionResponse::List(l) => l.items,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            expected,
+            items
+                .iter()
+                .map(|item| item.label.as_str())
+                .collect::<Vec<&str>>()
+        );
+    }
+}
+
+#[test]
+async fn test_schema_hover() {
+    let fluxscript = r#"from(bucket: "telegraf")
+    |> filter(fn: (r) => r._measurement == "cpu")
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+    update_schema(&server).await;
+
+    let result = server
+        .hover(hover_params(lsp::Position::new(1, 45)))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        Some(lsp::Hover {
+            contents: lsp::HoverContents::Scalar(
+                lsp::MarkedString::String(
+                    "string\n\nFields:\n  usage_user\n\nTags:\n  host"
+                        .to_string()
+                )
+            ),
+            range: None,
+        }),
+        result
+    );
+}
+
+#[test]
+async fn test_hover_csv_from() {
+    let fluxscript = r##"import "csv"
+
+data = csv.from(
+    csv: "#datatype,string,long,double,string
+#group,false,false,false,true
+,result,table,_value,host
+,,0,1.5,a
+",
+)
+"##;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let result = server
+        .hover(hover_params(lsp::Position::new(4, 10)))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        Some(lsp::Hover {
+            contents: lsp::HoverContents::Scalar(
+                lsp::MarkedString::String(
+                    "string\n\nCSV table (1 row):\n  result: string\n  table: int\n  _value: float\n  host: string (group key)"
+                        .to_string()
+                )
+            ),
+            range: None,
+        }),
+        result
+    );
+}
+
+/// The key of `secrets.get` is completed from the pushed secret keys.
+#[test]
+async fn test_secret_key_completion() {
+    let fluxscript = r#"import "influxdata/influxdb/secrets"
+
+token = secrets.get(key: "")
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+    server
+        .request_else(
+            "flux/updateSecrets",
+            Some(json!({"keys": ["PASSWORD", "TOKEN"]})),
+        )
+        .await
+        .unwrap();
+
+    let params = lsp::CompletionParams {
+        text_document_position: lsp::TextDocumentPositionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+            },
+            position: lsp::Position::new(2, 26),
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+        context: None,
+    };
+
+    let result = server.completion(params).await.unwrap().unwrap();
+
+    let items = match result {
+        lsp::CompletionResponse::List(l) => l.items,
+        _ => unreachable!(),
+    };
+    assert_eq!(
+        vec!["PASSWORD", "TOKEN"],
+        items
+            .iter()
+            .map(|item| item.label.as_str())
+            .collect::<Vec<&str>>()
+    );
+}
+
+#[test]
+async fn test_timezone_completion() {
+    let fluxscript = r#"import "timezone"
+
+option location = timezone.location(name: "America/New")
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::CompletionParams {
+        text_document_position: lsp::TextDocumentPositionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+            },
+            position: lsp::Position::new(2, 54),
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+        context: None,
+    };
+
+    let result = server.completion(params).await.unwrap().unwrap();
+
+    let items = match result {
+        lsp::CompletionResponse::List(l) => l.items,
+        _ => unreachable!(),
+    };
+    assert_eq!(crate::timezones::TIMEZONES.len(), items.len());
+    let item = items
+        .iter()
+        .find(|item| item.label == "America/New_York")
+        .unwrap();
+    assert_eq!(
+        Some(lsp::CompletionTextEdit::Edit(lsp::TextEdit {
+            range: lsp::Range {
+                start: lsp::Position::new(2, 42),
+                end: lsp::Position::new(2, 55),
+            },
+            new_text: "\"America/New_York\"".into(),
+        })),
+        item.text_edit
+    );
+}
+
+/// The columns of both sides of a join are offered for its `on` columns.
+#[test]
+async fn test_join_on_completion() {
+    let fluxscript = r#"import "array"
+
+left = array.from(rows: [{_time: 2020-01-01T00:00:00Z, host: "a", cpu: 1.0}])
+right = array.from(rows: [{_time: 2020-01-01T00:00:00Z, host: "a", mem: 2}])
+join(tables: {left: left, right: right}, on: [])
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::CompletionParams {
+        text_document_position: lsp::TextDocumentPositionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+            },
+            position: lsp::Position::new(4, 46),
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+        context: None,
+    };
+
+    let result = server.completion(params).await.unwrap().unwrap();
+
+    let items = match result {
+        lsp::CompletionResponse::List(l) => l.items,
+        _ => unreachable!(),
+    };
+    let mut labels: Vec<&str> =
+        items.iter().map(|item| item.label.as_str()).collect();
+    labels.sort_unstable();
+    assert_eq!(vec!["_time", "host"], labels);
+}
+
+/// The columns flowing into `pivot` are offered for its column arguments.
+#[test]
+async fn test_pivot_column_completion() {
+    let fluxscript = r#"import "array"
+
+array.from(rows: [{_time: 2020-01-01T00:00:00Z, _field: "cpu", _value: 1.0}])
+    |> pivot(rowKey: [""], columnKey: ["_field"], valueColumn: "_value")
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    for position in [
+        // In a string of the array.
+        lsp::Position::new(3, 23),
+        // In the string.
+        lsp::Position::new(3, 66),
+    ] {
+        let params = lsp::CompletionParams {
+            text_document_position: lsp::TextDocumentPositionParams {
+                text_document: lsp::TextDocumentIdentifier {
+                    uri: lsp::Url::parse(
+                        "file:///home/user/file.flux",
+                    )
+                    .unwrap(),
+                },
+                position,
+            },
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: lsp::PartialResultParams {
+                partial_result_token: None,
+            },
+            context: None,
+        };
+
+        let result =
+            server.completion(params).await.unwrap().unwrap();
+
+        let items = match result {
+            lsp::CompletionResponse::List(l) => l.items,
+            _ => unreachable!(),
+        };
+        let mut labels: Vec<&str> =
+            items.iter().map(|item| item.label.as_str()).collect();
+        labels.sort_unstable();
+        assert_eq!(vec!["_field", "_time", "_value"], labels);
+    }
+}
+
+/// The columns arguments of functions like `group` are completed with the columns
+/// flowing into them, and those of InfluxDB and the schema when they aren't all known.
+#[test]
+async fn test_columns_argument_completion() {
+    let fluxscript = r#"import "array"
+
+array.from(rows: [{_time: 2020-01-01T00:00:00Z, host: "a", _value: 1.0}])
+    |> keep(columns: ["_time", ""])
+from(bucket: "telegraf")
+    |> range(start: -1h)
+    |> group(columns: ["_field", ""])
+"#;
+    let server = create_server();
+    update_schema(&server).await;
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    for (position, expected) in [
+        (lsp::Position::new(3, 32), vec!["_value", "host"]),
+        (
+            lsp::Position::new(6, 34),
+            vec![
+                "_measurement",
+                "_start",
+                "_stop",
+                "_time",
+                "_value",
+                "host",
+            ],
+        ),
+    ] {
+        let params = lsp::CompletionParams {
+            text_document_position: lsp::TextDocumentPositionParams {
+                text_document: lsp::TextDocumentIdentifier {
+                    uri: lsp::Url::parse(
+                        "file:///home/user/file.flux",
+                    )
+                    .unwrap(),
+                },
+                position,
+            },
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: lsp::PartialResultParams {
+                partial_result_token: None,
+            },
+            context: None,
+        };
+
+        let result =
+            server.completion(params).await.unwrap().unwrap();
+
+        let items = match result {
+            lsp::CompletionResponse::List(l) => l.items,
+            _ => unreachable!(),
+        };
+        let mut labels: Vec<&str> =
+            items.iter().map(|item| item.label.as_str()).collect();
+        labels.sort_unstable();
+        assert_eq!(expected, labels);
+    }
+}
+
+/// Snippets are offered when completing an identifier that is a statement of its own.
+#[test]
+async fn test_snippet_completion() {
+    let fluxscript = r#"down"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::CompletionParams {
+        text_document_position: lsp::TextDocumentPositionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+            },
+            position: lsp::Position {
+                line: 0,
+                character: 4,
+            },
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+        context: Some(lsp::CompletionContext {
+            trigger_kind: lsp::CompletionTriggerKind::INVOKED,
+            trigger_character: None,
+        }),
+    };
+
+    let result = server.completion(params).await.unwrap().unwrap();
+
+    let items = match result {
+        lsp::CompletionResponse::List(l) => l.items,
+        _ => unreachable!(),
+    };
+    let snippet = items
+        .iter()
+        .find(|item| {
+            item.kind == Some(lsp::CompletionItemKind::SNIPPET)
+        })
+        .unwrap();
+    assert_eq!("downsampleTask", snippet.label);
+    assert_eq!(
+        Some(lsp::InsertTextFormat::SNIPPET),
+        snippet.insert_text_format
+    );
+    assert!(snippet
+        .insert_text
+        .as_ref()
+        .unwrap()
+        .starts_with("option task = "));
+}
+
+/// With `apply: false`, composition commands return their edit rather than applying
+/// it, and the composition is still updated.
+#[test]
+async fn execute_command_composition_without_applying() {
+    let server = create_server();
+    open_file(&server, "".to_string(), None).await;
+
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let execute = |command: &str, arguments: serde_json::Value| {
+        server.execute_command(lsp::ExecuteCommandParams {
+            command: command.into(),
+            arguments: vec![arguments],
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+        })
+    };
+    let new_text = |result: Option<serde_json::Value>| {
+        let mut edit: lsp::WorkspaceEdit =
+            serde_json::from_value(result.unwrap()).unwrap();
+        edit.changes.unwrap().remove(&uri).unwrap()[0]
+            .new_text
+            .clone()
+    };
+
+    let result = execute(
+        "fluxComposition/initialize",
+        json!({
+            "textDocument": {"uri": "file:///home/user/file.flux"},
+            "bucket": "bucket",
+            "measurement": "cpu",
+            "apply": false,
+        }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        r#"from(bucket: "bucket")
+    |> range(start: v.timeRangeStart, stop: v.timeRangeStop)
+    |> filter(fn: (r) => r._measurement == "cpu")
+"#,
+        new_text(result)
+    );
+
+    let result = execute(
+        "fluxComposition/addFieldFilter",
+        json!({
+            "textDocument": {"uri": "file:///home/user/file.flux"},
+            "value": "usage",
+            "apply": false,
+        }),
+    )
+    .await
+    .unwrap();
+    assert!(new_text(result).contains(r#"r._field == "usage""#));
+    assert!(server
+        .write_state()
+        .get_mut_composition(&uri)
+        .unwrap()
+        .to_string()
+        .contains(r#"r._field == "usage""#));
+}
+
+/// A client rejecting the edit of a composition is told once that the command failed,
+/// with an `executeCommandFailed` message.
+#[test]
+async fn execute_command_composition_edit_rejected() {
+    use futures::channel::mpsc::UnboundedReceiver;
+    use futures::{SinkExt, StreamExt};
+    use tower_service::Service;
+
+    /// Read the messages of the server up to the one `until` picks, answering those
+    /// shown to the user along the way, as a client whose user dismisses them would,
+    /// and collecting them in `shown`.
+    async fn read_until(
+        service: &mut lspower::LspService,
+        outgoing: &mut UnboundedReceiver<serde_json::Value>,
+        shown: &mut Vec<serde_json::Value>,
+        until: impl Fn(&serde_json::Value) -> bool,
+    ) -> serde_json::Value {
+        loop {
+            let message = outgoing.next().await.unwrap();
+            if message["method"] == "window/showMessage"
+                || message["method"] == "window/showMessageRequest"
+            {
+                shown.push(message["params"]["message"].clone());
+            }
+            if message["method"] == "window/showMessageRequest" {
+                let answer = json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "result": null,
+                });
+                service
+                    .call(serde_json::from_value(answer).unwrap())
+                    .await
+                    .unwrap();
+            }
+            if until(&message) {
+                return message;
+            }
+        }
+    }
+    let open = |uri: &str| -> lspower::jsonrpc::Incoming {
+        serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {"textDocument": {
+                "uri": uri,
+                "languageId": "flux",
+                "version": 1,
+                "text": "",
+            }},
+        }))
+        .unwrap()
     };
 
-    let result = server.execute_command(params).await;
+    let (mut service, mut messages) =
+        lspower::LspService::new(|client| {
+            LspServer::new(Some(client))
+        });
+    // The server waits for its messages to be read, so they are read as they come
+    // and kept until the test looks at them.
+    let (mut sender, mut outgoing) =
+        futures::channel::mpsc::unbounded();
+    async_std::task::spawn(async move {
+        while let Some(message) = messages.next().await {
+            let message = serde_json::to_value(&message).unwrap();
+            if sender.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+    let mut shown = vec![];
+
+    service
+        .call(
+            serde_json::from_value(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": {
+                    "processId": null,
+                    "rootUri": null,
+                    "capabilities": {},
+                },
+            }))
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+    service
+        .call(open("file:///home/user/file.flux"))
+        .await
+        .unwrap();
+    let execute = async_std::task::spawn(
+        service.call(
+            serde_json::from_value(json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "workspace/executeCommand",
+                "params": {
+                    "command": "fluxComposition/initialize",
+                    "arguments": [{
+                        "textDocument": {
+                            "uri": "file:///home/user/file.flux",
+                        },
+                        "bucket": "bucket",
+                    }],
+                },
+            }))
+            .unwrap(),
+        ),
+    );
 
-    assert!(result.is_err());
+    let apply_edit = read_until(
+        &mut service,
+        &mut outgoing,
+        &mut shown,
+        |message| message["method"] == "workspace/applyEdit",
+    )
+    .await;
+    service
+        .call(
+            serde_json::from_value(json!({
+                "jsonrpc": "2.0",
+                "id": apply_edit["id"],
+                "result": {"applied": false, "failureReason": "read only"},
+            }))
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+    read_until(&mut service, &mut outgoing, &mut shown, |message| {
+        message["method"] == "window/showMessageRequest"
+    })
+    .await;
+    execute.await.unwrap();
+    // Messages arrive in the order they are sent, so any other message about the
+    // failure comes before the analysis status of a document opened after it.
+    service
+        .call(open("file:///home/user/other.flux"))
+        .await
+        .unwrap();
+    read_until(&mut service, &mut outgoing, &mut shown, |message| {
+        message["method"] == "flux/analysisStatus"
+            && message["params"]["textDocument"]["uri"]
+                == "file:///home/user/other.flux"
+    })
+    .await;
+
+    assert_eq!(
+        vec![json!("fluxComposition/executeCommandFailed")],
+        shown
+    );
 }
 
-// Attempting to execute a command that doesn't exist results in an Err response.
+/// Composition commands made against an older version of the document than the
+/// latest are rejected.
 #[test]
-async fn execute_command_unknown_command() {
+async fn execute_command_composition_stale_version() {
     let server = create_server();
-    let params = lsp::ExecuteCommandParams {
-        command: "notActuallyAValidCommand".into(),
-        arguments: vec![serde_json::json!({"foo": "bar"})],
-        work_done_progress_params: lsp::WorkDoneProgressParams {
-            work_done_token: None,
-        },
+    open_file(&server, "".to_string(), None).await;
+    server
+        .did_change(lsp::DidChangeTextDocumentParams {
+            text_document: lsp::VersionedTextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+                version: 2,
+            },
+            content_changes: vec![
+                lsp::TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: "".into(),
+                },
+            ],
+        })
+        .await;
+
+    let initialize = |version: i32| {
+        server.execute_command(lsp::ExecuteCommandParams {
+            command: "fluxComposition/initialize".into(),
+            arguments: vec![json!({
+                "textDocument": {"uri": "file:///home/user/file.flux"},
+                "bucket": "bucket",
+                "version": version,
+                "apply": false,
+            })],
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+        })
     };
 
-    let result = server.execute_command(params).await;
-
-    assert!(result.is_err());
+    let err = initialize(1).await.unwrap_err();
+    assert_eq!(
+        lspower::jsonrpc::ErrorCode::ServerError(
+            types::CONTENT_MODIFIED
+        ),
+        err.code
+    );
+    assert!(initialize(2).await.unwrap().is_some());
 }
 
 #[test]
@@ -3873,7 +8478,7 @@ async fn workspace_state_buckets() {
         settings: json!({"settings": {"buckets": ["my-bucket", "your-bucket", "our-bucket"]}})
     }).await;
 
-    let buckets = server.state.lock().unwrap().buckets().clone();
+    let buckets = server.state.read().unwrap().buckets().clone();
 
     let expected: Vec<String> =
         vec!["my-bucket", "your-bucket", "our-bucket"]
@@ -3882,3 +8487,428 @@ async fn workspace_state_buckets() {
             .collect();
     assert_eq!(expected, buckets);
 }
+
+/// A panic while the server state is held doesn't take the server down with it.
+#[test]
+async fn workspace_state_survives_poisoning() {
+    let server = create_server();
+
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(
+        || {
+            let _state = server.state.write().unwrap();
+            panic!("poison the state lock");
+        },
+    ));
+    assert!(server.state.is_poisoned());
+
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"buckets": ["my-bucket"]}}),
+        })
+        .await;
+
+    assert_eq!(
+        vec!["my-bucket".to_string()],
+        *server.read_state().buckets()
+    );
+}
+
+/// Colors are only provided to clients that enable them with the `documentColors`
+/// initialization option.
+#[test]
+async fn test_document_color() {
+    let fluxscript = r##"option dashboard = {colors: ["#22ADF6", "red"]}
+"##;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+    let text_document = lsp::TextDocumentIdentifier {
+        uri: lsp::Url::parse("file:///home/user/file.flux").unwrap(),
+    };
+    let params = || lsp::DocumentColorParams {
+        text_document: text_document.clone(),
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+    };
+
+    assert_eq!(
+        Vec::<lsp::ColorInformation>::new(),
+        server.document_color(params()).await.unwrap()
+    );
+
+    let result = server
+        .initialize(lsp::InitializeParams {
+            capabilities: lsp::ClientCapabilities::default(),
+            client_info: None,
+            initialization_options: Some(
+                json!({"documentColors": true}),
+            ),
+            locale: None,
+            process_id: None,
+            root_path: None,
+            root_uri: None,
+            trace: None,
+            workspace_folders: None,
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        Some(lsp::ColorProviderCapability::Simple(true)),
+        result.capabilities.color_provider
+    );
+
+    let colors = server.document_color(params()).await.unwrap();
+    assert_eq!(
+        vec![lsp::Range {
+            start: lsp::Position {
+                line: 0,
+                character: 30,
+            },
+            end: lsp::Position {
+                line: 0,
+                character: 37,
+            },
+        }],
+        colors.iter().map(|color| color.range).collect::<Vec<_>>()
+    );
+
+    let presentations = server
+        .color_presentation(lsp::ColorPresentationParams {
+            text_document,
+            color: colors[0].color,
+            range: colors[0].range,
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: lsp::PartialResultParams {
+                partial_result_token: None,
+            },
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        vec![Some("#22adf6".to_string())],
+        presentations
+            .iter()
+            .map(|presentation| presentation
+                .text_edit
+                .as_ref()
+                .map(|edit| edit.new_text.clone()))
+            .collect::<Vec<_>>()
+    );
+}
+
+/// `http://` urls are only reported when the `warnInsecureHttp` setting is enabled.
+#[test]
+async fn compute_diagnostics_warn_insecure_http() {
+    let server = create_server();
+
+    let filename: String = "file:///path/to/script.flux".into();
+    let fluxscript = r#"import "http/requests"
+
+requests.get(url: "http://example.com")"#;
+    open_file(&server, fluxscript.into(), Some(&filename)).await;
+    let url = lsp::Url::parse(&filename).unwrap();
+
+    let diagnostics = server.compute_diagnostics(&url);
+    assert!(diagnostics[&url].is_empty(), "{:?}", diagnostics[&url]);
+
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"warnInsecureHttp": true}}),
+        })
+        .await;
+
+    let diagnostics = server.compute_diagnostics(&url);
+    assert_eq!(
+        vec![Some(lsp::NumberOrString::String(
+            "insecure-http".into()
+        ))],
+        diagnostics[&url]
+            .iter()
+            .map(|diagnostic| diagnostic.code.clone())
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+async fn compute_diagnostics_target_version() {
+    let server = create_server();
+
+    let filename: String = "file:///path/to/script.flux".into();
+    let fluxscript = r#"import "array"
+
+array.from(rows: [{a: 1}]) |> display()"#;
+    open_file(&server, fluxscript.into(), Some(&filename)).await;
+    let url = lsp::Url::parse(&filename).unwrap();
+
+    let diagnostics = server.compute_diagnostics(&url);
+    assert!(diagnostics[&url].is_empty(), "{:?}", diagnostics[&url]);
+
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"targetVersion": "2.1"}}),
+        })
+        .await;
+
+    let diagnostics = server.compute_diagnostics(&url);
+    assert_eq!(
+        vec![Some(lsp::NumberOrString::String(
+            "unavailable-function".into()
+        ))],
+        diagnostics[&url]
+            .iter()
+            .map(|diagnostic| diagnostic.code.clone())
+            .collect::<Vec<_>>()
+    );
+
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"targetVersion": "cloud"}}),
+        })
+        .await;
+
+    let diagnostics = server.compute_diagnostics(&url);
+    assert!(diagnostics[&url].is_empty(), "{:?}", diagnostics[&url]);
+}
+
+/// Type errors link to the page explaining them.
+#[cfg(feature = "docs")]
+#[test]
+async fn compute_diagnostics_error_description() {
+    let fluxscript = r#"x = 1 + "a"
+y = z
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let uri = lsp::Url::parse("file:///home/user/file.flux").unwrap();
+    let mut descriptions: Vec<(u32, Option<String>)> = server
+        .compute_diagnostics(&uri)
+        .remove(&uri)
+        .unwrap()
+        .into_iter()
+        .map(|diagnostic| {
+            (
+                diagnostic.range.start.line,
+                diagnostic
+                    .code_description
+                    .map(|description| description.href.to_string()),
+            )
+        })
+        .collect();
+    descriptions.sort();
+
+    assert_eq!(
+        vec![
+            (
+                0,
+                Some(
+                    "https://docs.influxdata.com/flux/v0.x/spec/types/"
+                        .to_string()
+                )
+            ),
+            (1, None),
+        ],
+        descriptions
+    );
+}
+
+/// Once the params of queries are declared with the `queryParams` setting, `params` is
+/// no longer undefined, and its members that aren't declared are reported instead.
+#[test]
+async fn compute_diagnostics_query_params() {
+    let server = create_server();
+
+    let filename: String = "file:///path/to/script.flux".into();
+    let fluxscript = r#"from(bucket: params.bucket)
+    |> range(start: params.strat)"#;
+    open_file(&server, fluxscript.into(), Some(&filename)).await;
+    let url = lsp::Url::parse(&filename).unwrap();
+    let codes = |diagnostics: &HashMap<
+        lsp::Url,
+        Vec<lsp::Diagnostic>,
+    >| {
+        diagnostics[&url]
+            .iter()
+            .map(|diagnostic| {
+                (diagnostic.range.start.line, diagnostic.code.clone())
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let undefined = server.compute_diagnostics(&url);
+    assert!(!undefined[&url].is_empty());
+    assert!(undefined[&url]
+        .iter()
+        .all(|diagnostic| diagnostic.message.contains("params")));
+
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"queryParams": {
+                "bucket": "string",
+                "start": "time",
+            }}}),
+        })
+        .await;
+
+    assert_eq!(
+        vec![(
+            1,
+            Some(lsp::NumberOrString::String(
+                "undeclared-param".into()
+            ))
+        )],
+        codes(&server.compute_diagnostics(&url))
+    );
+}
+
+#[test]
+async fn test_query_params_completion() {
+    let fluxscript = r#"params.
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"queryParams": {
+                "bucket": "string",
+                "start": "time",
+            }}}),
+        })
+        .await;
+
+    let params = lsp::CompletionParams {
+        text_document_position: lsp::TextDocumentPositionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+            },
+            position: lsp::Position::new(0, 7),
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+        context: None,
+    };
+
+    let result = server.completion(params).await.unwrap().unwrap();
+
+    let items = match result {
+        lsp::CompletionResponse::List(l) => l.items,
+        _ => unreachable!(),
+    };
+    assert_eq!(
+        vec![
+            ("bucket", Some("query parameter: string")),
+            ("start", Some("query parameter: time")),
+        ],
+        items
+            .iter()
+            .map(|item| (item.label.as_str(), item.detail.as_deref()))
+            .collect::<Vec<_>>()
+    );
+}
+
+/// Lints have the severity configured with the `lintSeverities` setting, and aren't
+/// reported when turned off.
+#[test]
+async fn compute_diagnostics_lint_severities() {
+    let server = create_server();
+
+    let filename: String = "file:///path/to/script.flux".into();
+    let fluxscript = r#"from(bucket: "telegraf")
+    |> range(start: -1h)
+    |> to(bucket: "telegraf")"#;
+    open_file(&server, fluxscript.into(), Some(&filename)).await;
+    let url = lsp::Url::parse(&filename).unwrap();
+    let severities =
+        |diagnostics: HashMap<lsp::Url, Vec<lsp::Diagnostic>>| {
+            diagnostics[&url]
+                .iter()
+                .map(|diagnostic| {
+                    (diagnostic.code.clone(), diagnostic.severity)
+                })
+                .collect::<Vec<_>>()
+        };
+
+    assert_eq!(
+        vec![(
+            Some(lsp::NumberOrString::String(
+                "same-bucket-write".into()
+            )),
+            Some(lsp::DiagnosticSeverity::WARNING)
+        )],
+        severities(server.compute_diagnostics(&url))
+    );
+
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"lintSeverities": {"same-bucket-write": "error"}}}),
+        })
+        .await;
+    assert_eq!(
+        vec![(
+            Some(lsp::NumberOrString::String(
+                "same-bucket-write".into()
+            )),
+            Some(lsp::DiagnosticSeverity::ERROR)
+        )],
+        severities(server.compute_diagnostics(&url))
+    );
+
+    server
+        .did_change_configuration(lsp::DidChangeConfigurationParams {
+            settings: json!({"settings": {"lintSeverities": {"same-bucket-write": "off"}}}),
+        })
+        .await;
+    assert!(severities(server.compute_diagnostics(&url)).is_empty());
+}
+
+#[test]
+async fn test_http_headers_completion() {
+    let fluxscript = r#"import "http"
+
+http.post(url: "https://example.com", headers: {})
+"#;
+    let server = create_server();
+    open_file(&server, fluxscript.to_string(), None).await;
+
+    let params = lsp::CompletionParams {
+        text_document_position: lsp::TextDocumentPositionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///home/user/file.flux")
+                    .unwrap(),
+            },
+            position: lsp::Position::new(2, 48),
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+        context: None,
+    };
+
+    let result = server.completion(params).await.unwrap().unwrap();
+
+    let items = match result {
+        lsp::CompletionResponse::List(l) => l.items,
+        _ => unreachable!(),
+    };
+    let item = items
+        .iter()
+        .find(|item| item.label == "Content-Type")
+        .unwrap();
+    assert_eq!(
+        Some("\"Content-Type\": "),
+        item.insert_text.as_deref()
+    );
+}
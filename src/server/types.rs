@@ -1,14 +1,32 @@
 use lspower::jsonrpc::{Error, ErrorCode};
 
+/// The error code of requests made against contents the document no longer has, as
+/// the protocol defines it.
+pub const CONTENT_MODIFIED: i64 = -32801;
+
 #[derive(Debug)]
 pub enum LspError {
     InternalError(String),
     LockNotAcquired,
     FileNotFound(String),
     InvalidArguments(Vec<serde_json::value::Value>),
+    /// The params of a command don't match its schema, or can't be parsed.
+    InvalidCommandParams {
+        command: String,
+        missing: Vec<String>,
+        unknown: Vec<String>,
+        reason: Option<String>,
+    },
     InvalidCommand(String),
 
     CompositionNotFound(lspower::lsp::Url),
+    /// A command was made against a version of a document older than the latest.
+    StaleDocumentVersion {
+        uri: lspower::lsp::Url,
+        version: i32,
+        latest: i32,
+    },
+    UnsafeStageMove(String),
 }
 
 impl From<LspError> for Error {
@@ -37,6 +55,51 @@ impl From<LspError> for Error {
                 ),
                 data: None,
             },
+            LspError::InvalidCommandParams {
+                command,
+                missing,
+                unknown,
+                reason,
+            } => {
+                let fields = |fields: &[String]| {
+                    fields
+                        .iter()
+                        .map(|field| format!("`{}`", field))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+                let mut reasons = vec![];
+                if !missing.is_empty() {
+                    reasons.push(format!(
+                        "missing fields {}",
+                        fields(&missing)
+                    ));
+                }
+                if !unknown.is_empty() {
+                    reasons.push(format!(
+                        "unknown fields {}",
+                        fields(&unknown)
+                    ));
+                }
+                reasons.extend(reason);
+                Error {
+                    code: ErrorCode::InvalidParams,
+                    message: format!(
+                        "Invalid params for {}: {}",
+                        command,
+                        reasons.join("; ")
+                    ),
+                    data: if missing.is_empty() && unknown.is_empty()
+                    {
+                        None
+                    } else {
+                        Some(serde_json::json!({
+                            "missing": missing,
+                            "unknown": unknown,
+                        }))
+                    },
+                }
+            }
             LspError::InvalidCommand(command) => Error {
                 code: ErrorCode::InvalidParams,
                 message: format!(
@@ -54,6 +117,26 @@ impl From<LspError> for Error {
                 ),
                 data: None,
             },
+            LspError::StaleDocumentVersion {
+                uri,
+                version,
+                latest,
+            } => Error {
+                code: ErrorCode::ServerError(CONTENT_MODIFIED),
+                message: format!(
+                    "Version {} of {} is stale, the latest is {}",
+                    version, uri, latest
+                ),
+                data: None,
+            },
+            LspError::UnsafeStageMove(reason) => Error {
+                code: ErrorCode::InvalidParams,
+                message: format!(
+                    "Pipeline stage can't be moved: {}",
+                    reason
+                ),
+                data: None,
+            },
         }
     }
 }
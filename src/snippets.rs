@@ -0,0 +1,150 @@
+/// A catalog of snippets for common flux idioms.
+///
+/// Snippets are served to clients, both as completion items and through the
+/// `getSnippets` command, so that every editor integration shares the same catalog.
+/// Bodies use the LSP snippet syntax, so a literal `$` (e.g. string interpolation)
+/// must be escaped as `\$`.
+use lspower::lsp;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Snippet {
+    /// A human readable name for the snippet.
+    pub name: &'static str,
+    /// The text that triggers the snippet when typed.
+    pub prefix: &'static str,
+    pub description: &'static str,
+    pub body: &'static str,
+}
+
+pub(crate) const SNIPPETS: &[Snippet] = &[
+    Snippet {
+        name: "Downsampling task",
+        prefix: "downsampleTask",
+        description: "A task that aggregates data into windows and writes it to another bucket.",
+        body: r#"option task = {name: "${1:downsample}", every: ${2:1h}}
+
+from(bucket: "${3:source}")
+    |> range(start: -task.every)
+    |> filter(fn: (r) => r._measurement == "${4:measurement}")
+    |> aggregateWindow(every: ${5:5m}, fn: ${6:mean}, createEmpty: false)
+    |> to(bucket: "${7:destination}")
+"#,
+    },
+    Snippet {
+        name: "Threshold alert",
+        prefix: "thresholdAlert",
+        description: "Assign a level to each value based on thresholds, and keep only the values that crossed one.",
+        body: r#"from(bucket: "${1:bucket}")
+    |> range(start: -${2:5m})
+    |> filter(fn: (r) => r._measurement == "${3:measurement}" and r._field == "${4:field}")
+    |> map(fn: (r) => ({r with _level: if r._value > ${5:90.0} then "crit" else if r._value > ${6:80.0} then "warn" else "ok"}))
+    |> filter(fn: (r) => r._level != "ok")
+"#,
+    },
+    Snippet {
+        name: "Pivot fields for visualization",
+        prefix: "pivotFields",
+        description: "Turn each field into a column, with one row per timestamp.",
+        body: r#"from(bucket: "${1:bucket}")
+    |> range(start: ${2:-1h})
+    |> filter(fn: (r) => r._measurement == "${3:measurement}")
+    |> pivot(rowKey: ["_time"], columnKey: ["_field"], valueColumn: "_value")
+"#,
+    },
+    Snippet {
+        name: "Last value per series",
+        prefix: "lastValue",
+        description: "The most recent value of each series.",
+        body: r#"from(bucket: "${1:bucket}")
+    |> range(start: ${2:-1h})
+    |> filter(fn: (r) => r._measurement == "${3:measurement}")
+    |> last()
+"#,
+    },
+];
+
+impl Snippet {
+    pub fn completion_item(&self) -> lsp::CompletionItem {
+        lsp::CompletionItem {
+            label: self.prefix.into(),
+            kind: Some(lsp::CompletionItemKind::SNIPPET),
+            detail: Some(self.name.into()),
+            documentation: Some(lsp::Documentation::String(
+                self.description.into(),
+            )),
+            filter_text: Some(self.prefix.into()),
+            insert_text: Some(self.body.into()),
+            insert_text_format: Some(lsp::InsertTextFormat::SNIPPET),
+            sort_text: Some(self.prefix.into()),
+            ..lsp::CompletionItem::default()
+        }
+    }
+}
+
+/// Snippets whose prefix starts with the text typed so far.
+pub(crate) fn matching(
+    typed: &str,
+) -> impl Iterator<Item = &'static Snippet> + '_ {
+    SNIPPETS.iter().filter(move |snippet| {
+        snippet
+            .prefix
+            .to_lowercase()
+            .starts_with(typed.to_lowercase().as_str())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Placeholders are numbered from 1 without gaps, so tabbing through a snippet
+    /// visits every one of them.
+    #[test]
+    fn placeholders_are_sequential() {
+        for snippet in SNIPPETS {
+            let mut numbers: Vec<u32> = snippet
+                .body
+                .split("${")
+                .skip(1)
+                .filter_map(|part| {
+                    part.split(':').next()?.parse().ok()
+                })
+                .collect();
+            numbers.sort_unstable();
+            numbers.dedup();
+
+            assert_eq!(
+                (1..=numbers.len() as u32).collect::<Vec<u32>>(),
+                numbers,
+                "{}",
+                snippet.name
+            );
+        }
+    }
+
+    #[test]
+    fn prefixes_are_unique() {
+        for (i, snippet) in SNIPPETS.iter().enumerate() {
+            assert!(
+                SNIPPETS[i + 1..]
+                    .iter()
+                    .all(|other| other.prefix != snippet.prefix),
+                "{}",
+                snippet.prefix
+            );
+        }
+    }
+
+    #[test]
+    fn matching_is_case_insensitive_prefix() {
+        assert_eq!(
+            vec!["pivotFields"],
+            matching("PIV")
+                .map(|snippet| snippet.prefix)
+                .collect::<Vec<&str>>()
+        );
+        assert_eq!(0, matching("fields").count());
+    }
+}
@@ -0,0 +1,129 @@
+/// Headers of pipelines, for editors pinning them while scrolling
+///
+/// Editors with sticky scroll keep the lines a position is nested in at the top of
+/// the view. Pipelines aren't nested blocks, so the statement a long pipeline starts
+/// with, e.g. `data = from(bucket: "a")`, would scroll out of view along with the
+/// source of the data. Every pipeline spanning several lines is reported along with
+/// its header, the statement up to the end of the head of the pipeline.
+use flux::ast::{self, walk};
+use lspower::lsp;
+
+use crate::convert;
+
+/// The head of a pipeline, i.e. the expression before the first `|>`.
+fn pipeline_head(pipe: &ast::PipeExpr) -> &ast::Expression {
+    let mut head = &pipe.argument;
+    while let ast::Expression::PipeExpr(pipe) = head {
+        head = &pipe.argument;
+    }
+    head
+}
+
+#[derive(Default)]
+struct PipelineHeaderVisitor {
+    headers: Vec<(lsp::Range, lsp::Range)>,
+}
+
+impl PipelineHeaderVisitor {
+    /// Report the pipeline a statement evaluates to, or returns from the function
+    /// it evaluates to.
+    fn push(
+        &mut self,
+        statement: &ast::BaseNode,
+        value: &ast::Expression,
+    ) {
+        let pipe = match value {
+            ast::Expression::PipeExpr(pipe) => pipe,
+            ast::Expression::Function(function) => {
+                match &function.body {
+                    ast::FunctionBody::Expr(
+                        ast::Expression::PipeExpr(pipe),
+                    ) => pipe,
+                    _ => return,
+                }
+            }
+            _ => return,
+        };
+        let range = convert::location_to_range(&statement.location);
+        if range.start.line == range.end.line {
+            return;
+        }
+        let head = convert::location_to_range(
+            &pipeline_head(pipe).base().location,
+        );
+        self.headers.push((
+            range,
+            lsp::Range {
+                start: range.start,
+                end: head.end,
+            },
+        ));
+    }
+}
+
+impl<'a> walk::Visitor<'a> for PipelineHeaderVisitor {
+    fn visit(&mut self, node: walk::Node<'a>) -> bool {
+        match node {
+            walk::Node::VariableAssgn(assignment) => {
+                self.push(&assignment.base, &assignment.init)
+            }
+            walk::Node::MemberAssgn(assignment) => {
+                self.push(&assignment.base, &assignment.init)
+            }
+            walk::Node::ExprStmt(statement) => {
+                self.push(&statement.base, &statement.expression)
+            }
+            walk::Node::ReturnStmt(statement) => {
+                self.push(&statement.base, &statement.argument)
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+/// The pipelines of a file spanning several lines, along with their header, in the
+/// order they are written. Pipelines nested in functions are included.
+pub(crate) fn pipeline_headers(
+    file: &ast::File,
+) -> Vec<(lsp::Range, lsp::Range)> {
+    let mut visitor = PipelineHeaderVisitor::default();
+    walk::walk(&mut visitor, walk::Node::File(file));
+    visitor.headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_of_pipelines() {
+        let fluxscript = r#"data = from(bucket: "a")
+    |> range(start: -1h)
+
+f = (tables=<-) => {
+    return tables
+        |> mean()
+}
+
+from(bucket: "b") |> range(start: -1h)
+"#;
+        let file = flux::parser::parse_string(
+            "script.flux".into(),
+            fluxscript,
+        );
+
+        assert_eq!(
+            vec![(0, 1, 0, 24), (4, 5, 4, 17),],
+            pipeline_headers(&file)
+                .into_iter()
+                .map(|(range, header)| (
+                    range.start.line,
+                    range.end.line,
+                    header.start.line,
+                    header.end.character
+                ))
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,205 @@
+/// Descriptions of the fields of the `task` option
+///
+/// InfluxDB schedules a script as a task by the `task` option, e.g.
+/// `option task = {name: "downsample", every: 1h}`. What each field means for the runs
+/// of the task isn't obvious from its name, and a task with invalid fields is only
+/// rejected once it is created, so hovering a field explains it and checks its value.
+use flux::semantic::nodes::{Expression, ObjectExpr};
+use flux::semantic::walk::Node as WalkNode;
+
+/// The fields of the task option, along with what they mean for its runs.
+const FIELDS: &[(&str, &str)] = &[
+    ("name", "The name of the task, as listed in InfluxDB. It is required."),
+    ("every", "How often the task runs, e.g. `1h` runs it at the start of every hour. Each run queries the period since the previous one, which is `-task.every` in `range`."),
+    ("cron", "When the task runs, as a cron expression of 5 fields, or 6 with the seconds, e.g. `\"0 2 * * *\"` runs it at 2am every day, in UTC."),
+    ("offset", "How long runs are delayed after their scheduled time, e.g. to wait for late data. The period a run queries isn't shifted, only when it runs."),
+    ("concurrency", "How many runs of the task may run at the same time."),
+    ("retry", "How many times a failed run is retried."),
+];
+
+/// A field of the task option, along with what it means and whether its value is
+/// valid.
+pub(crate) struct TaskField<'a> {
+    pub name: &'a str,
+    pub meaning: &'static str,
+    /// Why the value of the field is invalid, if it is.
+    pub invalid: Option<String>,
+}
+
+/// Whether a duration literal is longer than zero.
+fn is_positive_duration(value: &Expression) -> bool {
+    match value {
+        Expression::Duration(lit) => {
+            !lit.value.negative
+                && (lit.value.months > 0 || lit.value.nanoseconds > 0)
+        }
+        _ => false,
+    }
+}
+
+/// Why the value of a field of a task option is invalid, if it is.
+fn invalid_reason(
+    name: &str,
+    value: &Expression,
+    record: &ObjectExpr,
+) -> Option<String> {
+    let is_set = |field: &str| {
+        record
+            .properties
+            .iter()
+            .any(|property| property.key.name == field)
+    };
+    match name {
+        "name" => match value {
+            Expression::StringLit(lit) if lit.value.is_empty() => {
+                Some("the name can't be empty".into())
+            }
+            Expression::StringLit(_) => None,
+            _ => Some("the name must be a string".into()),
+        },
+        "every" | "cron" if is_set("every") && is_set("cron") => {
+            Some("`every` and `cron` can't both be set".into())
+        }
+        "every" if !is_positive_duration(value) => {
+            Some("`every` must be a duration longer than zero".into())
+        }
+        "cron" => match value {
+            Expression::StringLit(lit)
+                if matches!(
+                    lit.value.split_whitespace().count(),
+                    5 | 6
+                ) =>
+            {
+                None
+            }
+            Expression::StringLit(_) => {
+                Some("a cron expression has 5 or 6 fields".into())
+            }
+            _ => Some("`cron` must be a string".into()),
+        },
+        "offset" if !matches!(value, Expression::Duration(_)) => {
+            Some("`offset` must be a duration".into())
+        }
+        "concurrency" | "retry" => match value {
+            Expression::Integer(lit) if lit.value > 0 => None,
+            _ => Some(format!(
+                "`{}` must be an integer greater than zero",
+                name
+            )),
+        },
+        _ => None,
+    }
+}
+
+/// The field of the task option whose name is hovered, given the path to the hovered
+/// node.
+pub(crate) fn hovered_field<'a>(
+    path: &[WalkNode<'a>],
+) -> Option<TaskField<'a>> {
+    let (ident, property, record, assign, option) = match *path {
+        [.., option, assign, record, property, ident] => {
+            (ident, property, record, assign, option)
+        }
+        _ => return None,
+    };
+    let (ident, property, record) = match (ident, property, record) {
+        (
+            WalkNode::Identifier(ident),
+            WalkNode::Property(property),
+            WalkNode::ObjectExpr(record),
+        ) if property.key.name == ident.name => {
+            (ident, property, record)
+        }
+        _ => return None,
+    };
+    match (assign, option) {
+        (
+            WalkNode::VariableAssgn(assign),
+            WalkNode::OptionStmt(_),
+        ) if assign.id.name == "task" => {}
+        _ => return None,
+    }
+    let name = ident.name.as_str();
+    let meaning = FIELDS
+        .iter()
+        .find(|(field, _)| *field == name)
+        .map(|(_, meaning)| *meaning)?;
+    Some(TaskField {
+        name,
+        meaning,
+        invalid: invalid_reason(name, &property.value, record),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::get_salvaged_package;
+
+    fn hover_fields(source: &str) -> Vec<(String, Option<String>)> {
+        let pkg = get_salvaged_package("task.flux", source);
+        let lines: Vec<&str> = source.lines().collect();
+        FIELDS
+            .iter()
+            .filter_map(|(field, _)| {
+                let character =
+                    lines[0].find(&format!("{}:", field))?;
+                let visitor = crate::walk_semantic_package!(
+                    crate::visitors::semantic::NodeFinderVisitor::new(
+                        lspower::lsp::Position::new(
+                            0,
+                            character as u32 + 1
+                        )
+                    ),
+                    pkg
+                );
+                hovered_field(&visitor.path).map(|field| {
+                    (field.name.to_string(), field.invalid)
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fields_of_task_option() {
+        assert_eq!(
+            vec![
+                ("name".to_string(), None),
+                (
+                    "every".to_string(),
+                    Some(
+                        "`every` and `cron` can't both be set".into()
+                    )
+                ),
+                (
+                    "cron".to_string(),
+                    Some(
+                        "`every` and `cron` can't both be set".into()
+                    )
+                ),
+                ("offset".to_string(), None),
+            ],
+            hover_fields(
+                r#"option task = {name: "downsample", every: 1h, cron: "0 * * * *", offset: 5m}"#
+            )
+        );
+        assert_eq!(
+            vec![
+                ("name".to_string(), None),
+                (
+                    "cron".to_string(),
+                    Some(
+                        "a cron expression has 5 or 6 fields".into()
+                    )
+                ),
+            ],
+            hover_fields(
+                r#"option task = {name: "nightly", cron: "0 2 *"}"#
+            )
+        );
+        assert!(hover_fields(
+            r#"option other = {name: "a", every: 1h}"#
+        )
+        .is_empty());
+    }
+}
@@ -0,0 +1,31 @@
+/// Helpers shared by the tests of lints, which run on analyzed packages.
+use flux::semantic::nodes::Package;
+
+/// Analyze a package of a single file, `script.flux`, without errors.
+pub(crate) fn get_package(source: &str) -> Package {
+    let ast_pkg =
+        flux::parser::parse_string("script.flux".into(), source);
+    let mut analyzer = flux::new_semantic_analyzer(
+        flux::semantic::AnalyzerConfig::default(),
+    )
+    .unwrap();
+    let (_, pkg) = analyzer.analyze_ast(&ast_pkg.into()).unwrap();
+    pkg
+}
+
+/// Analyze a package of a single file, using whatever package flux salvages when
+/// it has errors.
+pub(crate) fn get_salvaged_package(
+    filename: &str,
+    source: &str,
+) -> Package {
+    let ast_pkg = flux::parser::parse_string(filename.into(), source);
+    let mut analyzer = flux::new_semantic_analyzer(
+        flux::semantic::AnalyzerConfig::default(),
+    )
+    .unwrap();
+    match analyzer.analyze_ast(&ast_pkg.into()) {
+        Ok((_, pkg)) => pkg,
+        Err(err) => err.value.unwrap().1,
+    }
+}
@@ -0,0 +1,173 @@
+/// Discovery and running of flux tests
+///
+/// Tests are `testcase` blocks, along with the cases run with `testing.run`. They are
+/// found in the AST, as `testcase` blocks aren't part of the semantic graph. Running
+/// them needs the `flux` command line tool, which only native builds can spawn.
+use flux::ast::{self, walk};
+use lspower::lsp;
+
+use crate::convert;
+
+pub(crate) struct Test {
+    pub name: String,
+    pub range: lsp::Range,
+    /// The package a `testcase` extends the tests of.
+    pub extends: Option<String>,
+}
+
+/// The name of the case run by a `testing.run(case: ...)` call.
+fn testing_run_case(call: &ast::CallExpr) -> Option<&str> {
+    let is_run = match &call.callee {
+        ast::Expression::Member(member) => {
+            matches!(&member.object, ast::Expression::Identifier(ident) if ident.name == "testing")
+                && matches!(&member.property, ast::PropertyKey::Identifier(ident) if ident.name == "run")
+        }
+        _ => false,
+    };
+    if !is_run {
+        return None;
+    }
+    let object = match call.arguments.first() {
+        Some(ast::Expression::Object(object)) => object,
+        _ => return None,
+    };
+    object
+        .properties
+        .iter()
+        .find(|property| {
+            matches!(&property.key, ast::PropertyKey::Identifier(ident) if ident.name == "case")
+        })
+        .and_then(|property| match &property.value {
+            Some(ast::Expression::Identifier(ident)) => {
+                Some(ident.name.as_str())
+            }
+            _ => None,
+        })
+}
+
+#[derive(Default)]
+struct TestVisitor {
+    tests: Vec<Test>,
+}
+
+impl<'a> walk::Visitor<'a> for TestVisitor {
+    fn visit(&mut self, node: walk::Node<'a>) -> bool {
+        match node {
+            walk::Node::TestCaseStmt(testcase) => {
+                self.tests.push(Test {
+                    name: testcase.id.name.clone(),
+                    range: convert::location_to_range(
+                        &testcase.base.location,
+                    ),
+                    extends: testcase
+                        .extends
+                        .as_ref()
+                        .map(|lit| lit.value.clone()),
+                });
+            }
+            walk::Node::CallExpr(call) => {
+                if let Some(name) = testing_run_case(call) {
+                    self.tests.push(Test {
+                        name: name.into(),
+                        range: convert::location_to_range(
+                            &call.base.location,
+                        ),
+                        extends: None,
+                    });
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+/// The tests of a file, in the order they are written.
+pub(crate) fn tests(file: &ast::File) -> Vec<Test> {
+    let mut visitor = TestVisitor::default();
+    walk::walk(&mut visitor, walk::Node::File(file));
+    visitor.tests
+}
+
+/// Run a test of the package in a directory with the `flux` command line tool.
+///
+/// Returns whether the test passed, along with the output of the tool, which is
+/// verbose when asked for.
+#[cfg(feature = "cmd")]
+pub(crate) fn run(
+    flux: &str,
+    directory: &std::path::Path,
+    name: &str,
+    verbose: bool,
+) -> (bool, String) {
+    let mut command = std::process::Command::new(flux);
+    command
+        .arg("test")
+        .arg("--path")
+        .arg(directory)
+        .arg("--test")
+        .arg(name);
+    if verbose {
+        command.arg("-v");
+    }
+    match command.output() {
+        Ok(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            (
+                output.status.success(),
+                [stdout.trim(), stderr.trim()]
+                    .iter()
+                    .filter(|text| !text.is_empty())
+                    .copied()
+                    .collect::<Vec<&str>>()
+                    .join("\n"),
+            )
+        }
+        Err(err) => {
+            (false, format!("Could not run {}: {}", flux, err))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discover_tests() {
+        let fluxscript = r#"import "testing"
+
+testcase addition {
+    testing.assertEqualValues(got: 1 + 1, want: 2)
+}
+
+testcase subtraction extends "flux/math_test" {
+    testing.assertEqualValues(got: 2 - 1, want: 1)
+}
+
+multiplication = () => ({input: 2, want: 4, fn: (v) => v * 2})
+testing.run(case: multiplication)
+"#;
+        let file = flux::parser::parse_string(
+            "math_test.flux".into(),
+            fluxscript,
+        );
+
+        assert_eq!(
+            vec![
+                ("addition", 2, None),
+                ("subtraction", 6, Some("flux/math_test")),
+                ("multiplication", 11, None),
+            ],
+            tests(&file)
+                .iter()
+                .map(|test| (
+                    test.name.as_str(),
+                    test.range.start.line,
+                    test.extends.as_deref()
+                ))
+                .collect::<Vec<_>>()
+        );
+    }
+}
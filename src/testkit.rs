@@ -0,0 +1,391 @@
+/// Golden file tests of completion, hover and diagnostics
+///
+/// A fixture is a `.flux` file with `// ^` comments marking cursors, like the server
+/// tests: the cursor is on the line above the comment, just after the character above
+/// the caret. Next to a fixture are golden files of the same name, one for each kind of
+/// result checked. A `.completion` file lists the labels completed at each cursor, a
+/// `.hover` file the hover at each cursor and a `.diagnostics` file the diagnostics of
+/// the fixture.
+///
+/// Only the kinds with a golden file are checked. To add one, create it empty and run
+/// the tests with `UPDATE_EXPECT=1`, which rewrites golden files with the results.
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use lspower::{lsp, LanguageServer};
+
+use crate::LspServer;
+
+const MARKER: &str = "// ^";
+const UPDATE_VARIABLE: &str = "UPDATE_EXPECT";
+
+/// A kind of result checked against a golden file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Check {
+    Completion,
+    Hover,
+    Diagnostics,
+}
+
+impl Check {
+    const ALL: [Check; 3] =
+        [Check::Completion, Check::Hover, Check::Diagnostics];
+
+    /// The extension of the golden files of the check.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Check::Completion => "completion",
+            Check::Hover => "hover",
+            Check::Diagnostics => "diagnostics",
+        }
+    }
+}
+
+pub struct Fixture {
+    pub path: PathBuf,
+    pub source: String,
+    pub cursors: Vec<lsp::Position>,
+}
+
+impl Fixture {
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let source = fs::read_to_string(&path)?;
+        Ok(Self {
+            cursors: cursors(&source),
+            path,
+            source,
+        })
+    }
+
+    /// The url the fixture is opened at.
+    pub fn uri(&self) -> lsp::Url {
+        let name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "fixture.flux".into());
+        lsp::Url::parse("file:///home/user/")
+            .and_then(|base| base.join(&name))
+            .expect("fixture names are valid in urls")
+    }
+
+    /// The golden file of a check.
+    pub fn golden(&self, check: Check) -> PathBuf {
+        self.path.with_extension(check.extension())
+    }
+
+    /// The character typed just before a cursor, when it triggers completion.
+    fn trigger_character(
+        &self,
+        cursor: lsp::Position,
+    ) -> Option<String> {
+        let line = self.source.lines().nth(cursor.line as usize)?;
+        let typed = line
+            .chars()
+            .nth((cursor.character as usize).checked_sub(1)?)?;
+        matches!(typed, '.' | ':' | '(' | ',' | '"')
+            .then(|| typed.to_string())
+    }
+}
+
+/// The positions marked by `// ^` comments.
+fn cursors(source: &str) -> Vec<lsp::Position> {
+    source
+        .lines()
+        .enumerate()
+        .filter_map(|(line, line_str)| {
+            let column = line_str.find(MARKER)?;
+            Some(lsp::Position {
+                // The marker is on the line after the position it marks.
+                line: (line as u32).checked_sub(1)?,
+                character: (line_str[..column].chars().count()
+                    + MARKER.len()) as u32,
+            })
+        })
+        .collect()
+}
+
+/// The heading of the results at a cursor.
+fn heading(cursor: lsp::Position) -> String {
+    format!("-- {}:{} --\n", cursor.line, cursor.character)
+}
+
+fn range(range: &lsp::Range) -> String {
+    format!(
+        "{}:{}-{}:{}",
+        range.start.line,
+        range.start.character,
+        range.end.line,
+        range.end.character
+    )
+}
+
+fn marked_string(marked: &lsp::MarkedString) -> &str {
+    match marked {
+        lsp::MarkedString::String(value) => value,
+        lsp::MarkedString::LanguageString(language) => {
+            &language.value
+        }
+    }
+}
+
+fn hover_contents(contents: &lsp::HoverContents) -> String {
+    match contents {
+        lsp::HoverContents::Scalar(marked) => {
+            marked_string(marked).into()
+        }
+        lsp::HoverContents::Array(marked) => marked
+            .iter()
+            .map(marked_string)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        lsp::HoverContents::Markup(markup) => markup.value.clone(),
+    }
+}
+
+fn severity(
+    severity: Option<lsp::DiagnosticSeverity>,
+) -> &'static str {
+    match severity {
+        Some(lsp::DiagnosticSeverity::ERROR) => "error",
+        Some(lsp::DiagnosticSeverity::WARNING) => "warning",
+        Some(lsp::DiagnosticSeverity::INFORMATION) => "information",
+        Some(lsp::DiagnosticSeverity::HINT) => "hint",
+        _ => "unknown",
+    }
+}
+
+async fn completion(
+    server: &LspServer,
+    fixture: &Fixture,
+    cursor: lsp::Position,
+) -> Vec<String> {
+    let trigger_character = fixture.trigger_character(cursor);
+    let params = lsp::CompletionParams {
+        text_document_position: lsp::TextDocumentPositionParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: fixture.uri(),
+            },
+            position: cursor,
+        },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+        partial_result_params: lsp::PartialResultParams {
+            partial_result_token: None,
+        },
+        context: Some(lsp::CompletionContext {
+            trigger_kind: match trigger_character {
+                Some(_) => {
+                    lsp::CompletionTriggerKind::TRIGGER_CHARACTER
+                }
+                None => lsp::CompletionTriggerKind::INVOKED,
+            },
+            trigger_character,
+        }),
+    };
+    let items = match server.completion(params).await {
+        Ok(Some(lsp::CompletionResponse::Array(items))) => items,
+        Ok(Some(lsp::CompletionResponse::List(list))) => list.items,
+        Ok(None) => vec![],
+        Err(err) => return vec![format!("error: {}", err.message)],
+    };
+    items.into_iter().map(|item| item.label).collect()
+}
+
+async fn hover(
+    server: &LspServer,
+    fixture: &Fixture,
+    cursor: lsp::Position,
+) -> String {
+    let params = lsp::HoverParams {
+        text_document_position_params:
+            lsp::TextDocumentPositionParams {
+                text_document: lsp::TextDocumentIdentifier {
+                    uri: fixture.uri(),
+                },
+                position: cursor,
+            },
+        work_done_progress_params: lsp::WorkDoneProgressParams {
+            work_done_token: None,
+        },
+    };
+    match server.hover(params).await {
+        Ok(Some(hover)) => hover_contents(&hover.contents),
+        Ok(None) => "no hover".into(),
+        Err(err) => format!("error: {}", err.message),
+    }
+}
+
+/// The results of a check of a fixture opened in a server, as written in golden files.
+pub async fn render(
+    server: &LspServer,
+    fixture: &Fixture,
+    check: Check,
+) -> String {
+    let mut rendered = String::new();
+    match check {
+        Check::Completion => {
+            for cursor in &fixture.cursors {
+                rendered.push_str(&heading(*cursor));
+                for label in
+                    completion(server, fixture, *cursor).await
+                {
+                    rendered.push_str(&label);
+                    rendered.push('\n');
+                }
+            }
+        }
+        Check::Hover => {
+            for cursor in &fixture.cursors {
+                rendered.push_str(&heading(*cursor));
+                rendered
+                    .push_str(&hover(server, fixture, *cursor).await);
+                rendered.push('\n');
+            }
+        }
+        Check::Diagnostics => {
+            let uri = fixture.uri();
+            let diagnostics = server
+                .compute_diagnostics(&uri)
+                .remove(&uri)
+                .unwrap_or_default();
+            for diagnostic in diagnostics {
+                let _ = write!(
+                    rendered,
+                    "{} {}",
+                    range(&diagnostic.range),
+                    severity(diagnostic.severity)
+                );
+                match &diagnostic.code {
+                    Some(lsp::NumberOrString::String(code)) => {
+                        let _ = write!(rendered, " {}", code);
+                    }
+                    Some(lsp::NumberOrString::Number(code)) => {
+                        let _ = write!(rendered, " {}", code);
+                    }
+                    None => {}
+                }
+                let _ =
+                    writeln!(rendered, ": {}", diagnostic.message);
+            }
+        }
+    }
+    rendered
+}
+
+/// Check a fixture against its golden files, returning a description of each
+/// mismatch.
+///
+/// Golden files are rewritten instead when `UPDATE_EXPECT` is set.
+pub async fn check(fixture: &Fixture) -> Vec<String> {
+    let update = std::env::var_os(UPDATE_VARIABLE).is_some();
+    let server = LspServer::new(None);
+    server
+        .did_open(lsp::DidOpenTextDocumentParams {
+            text_document: lsp::TextDocumentItem::new(
+                fixture.uri(),
+                "flux".to_string(),
+                1,
+                fixture.source.clone(),
+            ),
+        })
+        .await;
+
+    let mut mismatches = vec![];
+    for check in Check::ALL {
+        let golden = fixture.golden(check);
+        let expected = match fs::read_to_string(&golden) {
+            Ok(expected) => expected,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                continue
+            }
+            Err(err) => {
+                mismatches.push(format!(
+                    "Could not read {}: {}",
+                    golden.display(),
+                    err
+                ));
+                continue;
+            }
+        };
+        let actual = render(&server, fixture, check).await;
+        if actual == expected {
+            continue;
+        }
+        if update {
+            if let Err(err) = fs::write(&golden, &actual) {
+                mismatches.push(format!(
+                    "Could not update {}: {}",
+                    golden.display(),
+                    err
+                ));
+            }
+            continue;
+        }
+        mismatches.push(format!(
+            "{} doesn't match, run with {}=1 to update it\n--- expected\n{}--- actual\n{}",
+            golden.display(),
+            UPDATE_VARIABLE,
+            expected,
+            actual
+        ));
+    }
+    mismatches
+}
+
+/// Check every fixture in a directory, panicking with the mismatches.
+pub async fn check_dir(dir: impl AsRef<Path>) {
+    let dir = dir.as_ref();
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap_or_else(|err| {
+            panic!("Could not read {}: {}", dir.display(), err)
+        })
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .map_or(false, |extension| extension == "flux")
+        })
+        .collect();
+    paths.sort();
+
+    let mut mismatches = vec![];
+    for path in paths {
+        let fixture = Fixture::load(&path).unwrap_or_else(|err| {
+            panic!("Could not read {}: {}", path.display(), err)
+        });
+        mismatches.extend(check(&fixture).await);
+    }
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursors_of_markers() {
+        let source = r#"task.
+ // ^
+x = 1
+y = x
+// ^
+"#;
+
+        assert_eq!(
+            vec![lsp::Position::new(0, 5), lsp::Position::new(3, 4)],
+            cursors(source)
+        );
+    }
+
+    #[async_std::test]
+    async fn fixtures() {
+        check_dir(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures"),
+        )
+        .await;
+    }
+}
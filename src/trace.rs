@@ -0,0 +1,341 @@
+/// Recording of the messages exchanged with a client, for reproducing bugs
+///
+/// A trace is JSON lines, one per message, with the time it was seen in milliseconds
+/// since the epoch, whether it came `in` from the client or went `out` to it, and the
+/// message itself. The command line server records one with `--trace-file`, and the
+/// wasm build hands each line to the callback registered with `onTrace`. Traces can
+/// be fed back through a server with `replay`.
+use std::io::Write;
+use std::sync::{Mutex, PoisonError};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// A message from the client.
+    In,
+    /// A message to the client.
+    Out,
+}
+
+/// A line of a trace.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TraceEntry {
+    pub time: u64,
+    pub direction: Direction,
+    pub message: serde_json::Value,
+}
+
+impl TraceEntry {
+    /// An entry for a message seen now.
+    ///
+    /// Messages that aren't JSON are recorded as a string, so that a trace shows
+    /// whatever a client sent.
+    pub fn new(direction: Direction, message: &[u8]) -> Self {
+        Self {
+            time: now(),
+            direction,
+            message: serde_json::from_slice(message).unwrap_or_else(
+                |_| String::from_utf8_lossy(message).into(),
+            ),
+        }
+    }
+}
+
+/// The time in milliseconds since the epoch.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn now() -> u64 {
+    js_sys::Date::now() as u64
+}
+
+/// A trace written as JSON lines.
+pub struct Trace {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Trace {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Record a message, logging rather than failing when it can't be written.
+    pub fn record(&self, direction: Direction, message: &[u8]) {
+        let entry = TraceEntry::new(direction, message);
+        let mut writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let written = serde_json::to_writer(&mut *writer, &entry)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writer.write_all(b"\n"))
+            .and_then(|_| writer.flush());
+        if let Err(err) = written {
+            log::error!("Failed to write trace: {}", err);
+        }
+    }
+}
+
+/// Splits the bytes of a stream into the messages of the base protocol.
+///
+/// Messages are preceded by headers, of which `Content-Length` gives the length of
+/// the message, and separated from them by an empty line. Some clients don't quite
+/// follow the protocol, so header names are matched whatever their case, and lines
+/// may end with a bare `\n` rather than `\r\n`.
+#[derive(Default)]
+pub struct Framer {
+    buffer: Vec<u8>,
+}
+
+/// The end of the headers at the start of a buffer, and the start of the message
+/// following them, i.e. the position of the empty line and of the line after it.
+fn headers_end(buffer: &[u8]) -> Option<(usize, usize)> {
+    buffer.iter().enumerate().find_map(|(index, byte)| {
+        if *byte != b'\n' {
+            return None;
+        }
+        match &buffer[index + 1..] {
+            [b'\n', ..] => Some((index, index + 2)),
+            [b'\r', b'\n', ..] => Some((index, index + 3)),
+            _ => None,
+        }
+    })
+}
+
+impl Framer {
+    /// Frame a message as the protocol specifies, for readers that expect it to be
+    /// followed to the letter.
+    pub fn frame(message: &[u8]) -> Vec<u8> {
+        let mut framed =
+            format!("Content-Length: {}\r\n\r\n", message.len())
+                .into_bytes();
+        framed.extend_from_slice(message);
+        framed
+    }
+
+    /// Add bytes read from or written to a stream, returning the messages they complete.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        self.buffer.extend_from_slice(bytes);
+        let mut messages = vec![];
+        while let Some((end, start)) = headers_end(&self.buffer) {
+            let headers =
+                String::from_utf8_lossy(&self.buffer[..end]);
+            let length = headers.lines().find_map(|header| {
+                let (name, value) = header.split_once(':')?;
+                if name.trim().eq_ignore_ascii_case("content-length")
+                {
+                    value.trim().parse::<usize>().ok()
+                } else {
+                    None
+                }
+            });
+            let length = match length {
+                Some(length) => length,
+                None => {
+                    // Skip headers that can't be framed, rather than stalling.
+                    log::warn!(
+                        "Skipping headers without a Content-Length: {:?}",
+                        headers.trim()
+                    );
+                    self.buffer.drain(..start);
+                    continue;
+                }
+            };
+            if self.buffer.len() < start + length {
+                break;
+            }
+            messages
+                .push(self.buffer[start..start + length].to_vec());
+            self.buffer.drain(..start + length);
+        }
+        messages
+    }
+}
+
+/// Feed the messages a client sent in a trace through a new server, returning the
+/// responses to its requests.
+///
+/// Responses are in the order of the requests, which is how a test compares them to
+/// the responses recorded in the trace. Notifications sent by the server aren't
+/// returned, as they depend on timing.
+#[cfg(feature = "cmd")]
+pub async fn replay(trace: &str) -> Vec<serde_json::Value> {
+    use tower_service::Service;
+
+    let (mut service, _messages) =
+        lspower::LspService::new(|client| {
+            crate::LspServer::new(Some(client))
+        });
+    let mut responses = vec![];
+    for line in trace.lines().filter(|line| !line.trim().is_empty()) {
+        let entry: TraceEntry = match serde_json::from_str(line) {
+            Ok(entry) => entry,
+            Err(err) => {
+                log::error!(
+                    "Skipping trace line that isn't an entry: {}",
+                    err
+                );
+                continue;
+            }
+        };
+        if entry.direction != Direction::In {
+            continue;
+        }
+        let incoming: lspower::jsonrpc::Incoming =
+            match serde_json::from_value(entry.message) {
+                Ok(incoming) => incoming,
+                Err(err) => {
+                    log::error!(
+                        "Skipping message that isn't JSON-RPC: {}",
+                        err
+                    );
+                    continue;
+                }
+            };
+        match service.call(incoming).await {
+            Ok(Some(response)) => responses.push(
+                serde_json::to_value(&response)
+                    .unwrap_or(serde_json::Value::Null),
+            ),
+            Ok(None) => {}
+            Err(err) => {
+                log::error!("Failed to replay message: {}", err)
+            }
+        }
+    }
+    responses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_of_a_stream() {
+        let mut framer = Framer::default();
+        let first =
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#;
+        let second = r#"{"jsonrpc":"2.0","method":"exit"}"#;
+        let stream = format!(
+            "Content-Length: {}\r\n\r\n{}Content-Type: application/vscode-jsonrpc; charset=utf-8\r\nContent-Length: {}\r\n\r\n{}",
+            first.len(),
+            first,
+            second.len(),
+            second
+        );
+        let (start, end) = stream.as_bytes().split_at(30);
+
+        assert!(framer.push(start).is_empty());
+        assert_eq!(
+            vec![
+                first.as_bytes().to_vec(),
+                second.as_bytes().to_vec()
+            ],
+            framer.push(end)
+        );
+    }
+
+    /// Header names in any case, and lines ending with a bare `\n`, are framed like
+    /// conforming headers.
+    #[test]
+    fn frames_of_a_nonconforming_stream() {
+        let mut framer = Framer::default();
+        let first =
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#;
+        let second = r#"{"jsonrpc":"2.0","method":"exit"}"#;
+        let stream = format!(
+            "content-length: {}\n\n{}CONTENT-LENGTH:{}\r\n\n{}",
+            first.len(),
+            first,
+            second.len(),
+            second
+        );
+
+        assert_eq!(
+            vec![
+                first.as_bytes().to_vec(),
+                second.as_bytes().to_vec()
+            ],
+            framer.push(stream.as_bytes())
+        );
+    }
+
+    /// Headers without a length are skipped, rather than holding up the messages
+    /// after them.
+    #[test]
+    fn frames_after_headers_without_length() {
+        let mut framer = Framer::default();
+        let message = r#"{"jsonrpc":"2.0","method":"exit"}"#;
+        let stream = format!(
+            "Content-Type: application/json\r\n\r\nContent-Length: {}\r\n\r\n{}",
+            message.len(),
+            message
+        );
+
+        assert_eq!(
+            vec![message.as_bytes().to_vec()],
+            framer.push(stream.as_bytes())
+        );
+    }
+
+    #[test]
+    fn entries_of_a_trace() {
+        let entry = TraceEntry::new(
+            Direction::In,
+            br#"{"jsonrpc":"2.0","method":"exit"}"#,
+        );
+
+        assert_eq!(
+            serde_json::json!({"jsonrpc": "2.0", "method": "exit"}),
+            entry.message
+        );
+        let line = serde_json::to_string(&entry).unwrap();
+        assert!(line.contains(r#""direction":"in""#), "{}", line);
+        assert_eq!(
+            serde_json::Value::String("not json".into()),
+            TraceEntry::new(Direction::Out, b"not json").message
+        );
+    }
+
+    #[cfg(feature = "cmd")]
+    #[async_std::test]
+    async fn replay_a_trace() {
+        let trace = r#"{"time":1,"direction":"in","message":{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{}}}}
+{"time":2,"direction":"out","message":{"jsonrpc":"2.0","id":1,"result":{}}}
+{"time":3,"direction":"in","message":{"jsonrpc":"2.0","method":"initialized","params":{}}}
+{"time":4,"direction":"in","message":{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///home/user/file.flux","languageId":"flux","version":1,"text":"x = 1"}}}}
+{"time":5,"direction":"in","message":{"jsonrpc":"2.0","id":2,"method":"textDocument/documentSymbol","params":{"textDocument":{"uri":"file:///home/user/file.flux"}}}}
+{"time":6,"direction":"in","message":{"jsonrpc":"2.0","id":3,"method":"shutdown"}}
+{"time":7,"direction":"out","message":{"jsonrpc":"2.0","id":3,"result":null}}
+"#;
+
+        let responses = replay(trace).await;
+
+        assert_eq!(
+            vec![Some(1), Some(2), Some(3)],
+            responses
+                .iter()
+                .map(|response| response["id"].as_i64())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some("x"),
+            responses[1]["result"][0]["name"].as_str()
+        );
+        assert_eq!(
+            serde_json::json!({"jsonrpc": "2.0", "id": 3, "result": null}),
+            responses[2]
+        );
+    }
+}
@@ -0,0 +1,146 @@
+/// Tolerant reading of the messages clients send to the command line server
+///
+/// The messages of the base protocol are framed by headers that some clients don't
+/// write quite as specified, e.g. with a lowercase `content-length` or lines ending
+/// in a bare `\n`. The server's reader would wait forever for such messages, so the
+/// stream from the client is reframed as the protocol specifies first.
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::Framer;
+
+/// The number of bytes read from the client at once.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// A reader of the messages of a client, reframed as the protocol specifies.
+pub struct Normalized<R> {
+    inner: R,
+    framer: Framer,
+    /// Framed messages not read yet, from `offset` on.
+    pending: Vec<u8>,
+    offset: usize,
+}
+
+impl<R> Normalized<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            framer: Framer::default(),
+            pending: vec![],
+            offset: 0,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Normalized<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.offset < this.pending.len() {
+                let length = buf
+                    .remaining()
+                    .min(this.pending.len() - this.offset);
+                buf.put_slice(
+                    &this.pending[this.offset..this.offset + length],
+                );
+                this.offset += length;
+                if this.offset == this.pending.len() {
+                    this.pending.clear();
+                    this.offset = 0;
+                }
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut chunk = [0; CHUNK_SIZE];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut this.inner).poll_read(cx, &mut chunk)
+            {
+                Poll::Ready(Ok(())) => {
+                    if chunk.filled().is_empty() {
+                        // The client is gone.
+                        return Poll::Ready(Ok(()));
+                    }
+                    for message in this.framer.push(chunk.filled()) {
+                        this.pending.extend_from_slice(
+                            &Framer::frame(&message),
+                        );
+                    }
+                }
+                poll => return poll,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    async fn normalize(stream: &[u8]) -> String {
+        let mut normalized = vec![];
+        Normalized::new(stream)
+            .read_to_end(&mut normalized)
+            .await
+            .unwrap();
+        String::from_utf8(normalized).unwrap()
+    }
+
+    #[async_std::test]
+    async fn conforming_messages() {
+        let message = r#"{"jsonrpc":"2.0","method":"exit"}"#;
+        let stream = format!(
+            "Content-Length: {}\r\nContent-Type: application/vscode-jsonrpc; charset=utf-8\r\n\r\n{}",
+            message.len(),
+            message
+        );
+
+        assert_eq!(
+            format!("Content-Length: 33\r\n\r\n{}", message),
+            normalize(stream.as_bytes()).await
+        );
+    }
+
+    #[async_std::test]
+    async fn nonconforming_messages() {
+        let first = r#"{"jsonrpc":"2.0","id":1,"method":"shutdown"}"#;
+        let second = r#"{"jsonrpc":"2.0","method":"exit"}"#;
+        let stream = format!(
+            "content-length: {}\n\n{}Content-length:{}\r\n\n{}",
+            first.len(),
+            first,
+            second.len(),
+            second
+        );
+
+        assert_eq!(
+            format!(
+                "Content-Length: {}\r\n\r\n{}Content-Length: {}\r\n\r\n{}",
+                first.len(),
+                first,
+                second.len(),
+                second
+            ),
+            normalize(stream.as_bytes()).await
+        );
+    }
+
+    /// A message cut short by the client going away is dropped, rather than passed on
+    /// for the server to wait on the rest of.
+    #[async_std::test]
+    async fn truncated_message() {
+        assert_eq!(
+            "",
+            normalize(b"Content-Length: 100\r\n\r\n{\"jsonrpc\"")
+                .await
+        );
+    }
+}
@@ -0,0 +1,286 @@
+/// Checks of the stdlib functions a script calls against the InfluxDB it targets
+///
+/// Clients configure the InfluxDB they deploy scripts to with the `targetVersion`
+/// setting. Calls of functions introduced in a later version of flux than the target
+/// runs only fail once the script is deployed, so they are reported as it is written.
+///
+/// Likewise, the `target` setting is the platform scripts run on, `oss`, `cloud` or
+/// `serverless`. Packages and arguments that platform doesn't support are reported, as
+/// scripts are often moved from one platform to another.
+use std::collections::HashMap;
+
+use flux::semantic::nodes::{
+    CallExpr, Expression, ImportDeclaration, Package,
+};
+use flux::semantic::walk::Node as WalkNode;
+use lspower::lsp;
+
+use crate::convert;
+use crate::diagnostics::import_name;
+use crate::lang::{self, FluxVersion, Platform};
+
+/// The diagnostic code of calls of functions the target version doesn't provide.
+pub(crate) const UNAVAILABLE_FUNCTION: &str = "unavailable-function";
+/// The diagnostic code of packages and arguments the target platform doesn't support.
+pub(crate) const UNSUPPORTED_ON_PLATFORM: &str =
+    "unsupported-on-platform";
+
+/// Finds calls of the functions of packages, along with the path of their package.
+#[derive(Default)]
+pub(crate) struct StdlibCallVisitor<'a> {
+    /// The paths of the packages imported by the file being walked, by name.
+    imports: HashMap<String, String>,
+    /// The imports of every file.
+    declarations: Vec<&'a ImportDeclaration>,
+    pub(crate) calls: Vec<(String, String, &'a CallExpr)>,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a> for StdlibCallVisitor<'a> {
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        match node {
+            WalkNode::File(file) => {
+                self.declarations.extend(file.imports.iter());
+                self.imports = file
+                    .imports
+                    .iter()
+                    .map(|import| {
+                        (
+                            import_name(import),
+                            import.path.value.clone(),
+                        )
+                    })
+                    .collect();
+            }
+            WalkNode::CallExpr(call) => match &call.callee {
+                Expression::Identifier(ident) => self.calls.push((
+                    "universe".into(),
+                    ident.name.to_string(),
+                    call,
+                )),
+                Expression::Member(member) => {
+                    if let Expression::Identifier(ident) =
+                        &member.object
+                    {
+                        if let Some(path) =
+                            self.imports.get(ident.name.as_str())
+                        {
+                            self.calls.push((
+                                path.clone(),
+                                member.property.to_string(),
+                                call,
+                            ));
+                        }
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        true
+    }
+}
+
+/// Calls of stdlib functions introduced after the version of flux of the target.
+///
+/// Nothing is reported without a target, or for targets that run the latest flux.
+pub(crate) fn unavailable_functions(
+    pkg: &Package,
+    target: Option<&str>,
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    let (target, available): (&str, FluxVersion) = match target
+        .and_then(|target| {
+            lang::target_flux_version(target)
+                .map(|version| (target, version))
+        }) {
+        Some(target) => target,
+        None => return vec![],
+    };
+
+    let visitor = crate::walk_semantic_package!(
+        StdlibCallVisitor::default(),
+        pkg
+    );
+    visitor
+        .calls
+        .into_iter()
+        .filter_map(|(path, name, call)| {
+            let introduced = lang::introduced_in(&path, &name)?;
+            if introduced <= available {
+                return None;
+            }
+            let function = match path.as_str() {
+                "universe" => name,
+                path => format!(
+                    "{}.{}",
+                    path.rsplit('/').next().unwrap_or(path),
+                    name
+                ),
+            };
+            Some((
+                call.loc.file.clone(),
+                lsp::Diagnostic {
+                    range: convert::location_to_range(&call.loc),
+                    severity: Some(lsp::DiagnosticSeverity::WARNING),
+                    code: Some(lsp::NumberOrString::String(
+                        UNAVAILABLE_FUNCTION.into(),
+                    )),
+                    message: format!(
+                        "{} was introduced in flux {}, but the target version {} runs flux {}.",
+                        function, introduced, target, available
+                    ),
+                    ..lsp::Diagnostic::default()
+                },
+            ))
+        })
+        .collect()
+}
+
+fn unsupported_diagnostic(
+    loc: &flux::ast::SourceLocation,
+    message: String,
+) -> (Option<String>, lsp::Diagnostic) {
+    (
+        loc.file.clone(),
+        lsp::Diagnostic {
+            range: convert::location_to_range(loc),
+            severity: Some(lsp::DiagnosticSeverity::WARNING),
+            code: Some(lsp::NumberOrString::String(
+                UNSUPPORTED_ON_PLATFORM.into(),
+            )),
+            message,
+            ..lsp::Diagnostic::default()
+        },
+    )
+}
+
+/// Imports of packages and arguments of stdlib functions the target platform doesn't
+/// support.
+pub(crate) fn unsupported_on_platform(
+    pkg: &Package,
+    platform: Option<Platform>,
+) -> Vec<(Option<String>, lsp::Diagnostic)> {
+    let platform = match platform {
+        Some(platform) => platform,
+        None => return vec![],
+    };
+
+    let visitor = crate::walk_semantic_package!(
+        StdlibCallVisitor::default(),
+        pkg
+    );
+    let imports = visitor
+        .declarations
+        .iter()
+        .filter(|import| {
+            !lang::is_available(&import.path.value, Some(platform))
+        })
+        .map(|import| {
+            unsupported_diagnostic(
+                &import.path.loc,
+                format!(
+                    "The \"{}\" package is not available on {}.",
+                    import.path.value,
+                    platform.describe()
+                ),
+            )
+        });
+    let arguments =
+        visitor.calls.iter().flat_map(|(path, name, call)| {
+            call.arguments
+                .iter()
+                .filter(move |argument| {
+                    let value = match &argument.value {
+                        Expression::StringLit(lit) => {
+                            Some(lit.value.as_str())
+                        }
+                        _ => None,
+                    };
+                    lang::is_unsupported_argument(
+                        path,
+                        name,
+                        argument.key.name.as_str(),
+                        value,
+                        platform,
+                    )
+                })
+                .map(move |argument| {
+                    unsupported_diagnostic(
+                        &argument.loc,
+                        format!(
+                            "{}.{} can't be called with this {} on {}.",
+                            path.rsplit('/').next().unwrap_or(path),
+                            name,
+                            argument.key.name,
+                            platform.describe()
+                        ),
+                    )
+                })
+        });
+    imports.chain(arguments).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::get_package;
+
+    #[test]
+    fn functions_unavailable_on_target() {
+        let fluxscript = r#"import "array"
+import j "join"
+
+left = array.from(rows: [{id: 1, a: 1}])
+right = array.from(rows: [{id: 1, b: 2}])
+j.inner(left: left, right: right, on: (l, r) => l.id == r.id, as: (l, r) => ({l with b: r.b}))
+    |> display()
+"#;
+        let package = get_package(fluxscript);
+        let functions = |target| {
+            let mut functions =
+                unavailable_functions(&package, target)
+                    .into_iter()
+                    .map(|(_, diagnostic)| {
+                        (
+                            diagnostic.range.start.line,
+                            diagnostic.message,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+            functions.sort();
+            functions
+        };
+
+        assert_eq!(
+            vec![
+                (5, "join.inner was introduced in flux v0.172, but the target version 2.2 runs flux v0.150.".to_string()),
+                (6, "display was introduced in flux v0.154, but the target version 2.2 runs flux v0.150.".to_string()),
+            ],
+            functions(Some("2.2"))
+        );
+        assert_eq!(4, functions(Some("2.0")).len());
+        assert!(functions(Some("2.4")).is_empty());
+        assert!(functions(Some("cloud")).is_empty());
+        assert!(functions(None).is_empty());
+    }
+
+    #[test]
+    fn usage_unsupported_on_platform() {
+        let fluxscript = r##"import "csv"
+import "socket"
+
+csv.from(file: "/data/metrics.csv")
+csv.from(csv: "#datatype,string,long\n")
+"##;
+        let package = get_package(fluxscript);
+        let lines = |platform| {
+            unsupported_on_platform(&package, platform)
+                .into_iter()
+                .map(|(_, diagnostic)| diagnostic.range.start.line)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec![1, 3], lines(Some(Platform::Cloud)));
+        assert!(lines(Some(Platform::Oss)).is_empty());
+        assert!(lines(None).is_empty());
+    }
+}
@@ -1,12 +1,19 @@
 use flux::ast::walk;
 use lspower::lsp;
 
+use crate::convert;
+
 #[derive(Clone, Debug)]
 pub struct NodeFinderNode<'a> {
     pub node: walk::Node<'a>,
     pub parent: Option<Box<NodeFinderNode<'a>>>,
 }
 
+/// Finds the narrowest node containing a position, along with its enclosing nodes.
+///
+/// A node is only selected if it is no wider than the node found so far, so the
+/// result doesn't depend on the order the nodes happen to be walked in. Nodes of equal
+/// width are nested, so the later, deeper one wins.
 #[derive(Clone)]
 pub struct NodeFinderVisitor<'a> {
     pub node: Option<NodeFinderNode<'a>>,
@@ -27,22 +34,72 @@ impl<'a> walk::Visitor<'a> for NodeFinderVisitor<'a> {
         if let walk::Node::Package(_) = node {
             return true;
         }
-        if crate::lsp::position_in_range(
-            &self.position,
-            &node.base().location.clone().into(),
-        ) {
-            let parent = self.node.clone();
-            if let Some(parent) = parent {
-                self.node = Some(NodeFinderNode {
-                    node,
-                    parent: Some(Box::new(parent)),
-                });
-            } else {
-                self.node =
-                    Some(NodeFinderNode { node, parent: None });
-            }
+        let location = &node.base().location;
+        if convert::is_synthesized(location)
+            || !convert::location_contains(location, &self.position)
+        {
+            return true;
+        }
+
+        let narrower = match &self.node {
+            Some(current) => crate::lsp::range_is_narrower_or_equal(
+                &convert::location_to_range(location),
+                &convert::location_to_range(
+                    &current.node.base().location,
+                ),
+            ),
+            None => true,
+        };
+        if narrower {
+            let parent = self.node.take().map(Box::new);
+            self.node = Some(NodeFinderNode { node, parent });
+        }
+
+        true
+    }
+}
+
+/// The name of a property, whether accessed as `r.name` or `r["name"]`.
+pub fn property_name(key: &flux::ast::PropertyKey) -> &str {
+    match key {
+        flux::ast::PropertyKey::Identifier(ident) => &ident.name,
+        flux::ast::PropertyKey::StringLit(lit) => &lit.value,
+    }
+}
+
+/// Finds the member expressions accessing a property of an identifier.
+///
+/// Both `r.name` and `r["name"]` are found, as they access the same property.
+pub struct PropertyFinderVisitor<'a> {
+    pub object: String,
+    pub property: String,
+    pub members: Vec<&'a flux::ast::MemberExpr>,
+}
+
+impl<'a> PropertyFinderVisitor<'a> {
+    pub fn new(object: String, property: String) -> Self {
+        PropertyFinderVisitor {
+            object,
+            property,
+            members: vec![],
         }
+    }
+}
 
+impl<'a> walk::Visitor<'a> for PropertyFinderVisitor<'a> {
+    fn visit(&mut self, node: walk::Node<'a>) -> bool {
+        if let walk::Node::MemberExpr(member) = node {
+            if let flux::ast::Expression::Identifier(ident) =
+                &member.object
+            {
+                if ident.name == self.object
+                    && property_name(&member.property)
+                        == self.property
+                {
+                    self.members.push(member);
+                }
+            }
+        }
         true
     }
 }
@@ -57,7 +114,9 @@ impl From<&flux::ast::Package> for PackageInfo {
     fn from(pkg: &flux::ast::Package) -> Self {
         Self {
             name: pkg.package.clone(),
-            position: pkg.base.location.start.into(),
+            position: convert::position_to_lsp(
+                &pkg.base.location.start,
+            ),
         }
     }
 }
@@ -247,3 +306,201 @@ impl<'a> walk::Visitor<'a> for SemanticTokenVisitor {
         true
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"import "strings"
+
+data = from(bucket: "b")
+    |> range(start: -1h)
+    |> filter(fn: (r) => r._value > 10 and strings.hasPrefix(v: r.host, prefix: "a"))
+
+f = (x, y=1) => {
+    z = x + y
+
+    return z * 2
+}
+"#;
+
+    #[derive(Default)]
+    struct AllNodesVisitor<'a> {
+        nodes: Vec<walk::Node<'a>>,
+    }
+
+    impl<'a> walk::Visitor<'a> for AllNodesVisitor<'a> {
+        fn visit(&mut self, node: walk::Node<'a>) -> bool {
+            self.nodes.push(node);
+            true
+        }
+    }
+
+    fn find(
+        pkg: &flux::ast::Package,
+        line: u32,
+        character: u32,
+    ) -> Option<NodeFinderNode<'_>> {
+        crate::walk_ast_package!(
+            NodeFinderVisitor::new(lsp::Position { line, character }),
+            pkg
+        )
+        .node
+    }
+
+    fn package() -> flux::ast::Package {
+        flux::parser::parse_string("script.flux".into(), SCRIPT)
+            .into()
+    }
+
+    /// For every position in the script, the node found contains the position and
+    /// no other node containing the position is narrower.
+    #[test]
+    fn finds_narrowest_node_everywhere() {
+        let pkg = package();
+        let nodes =
+            crate::walk_ast_package!(AllNodesVisitor::default(), pkg)
+                .nodes;
+
+        for (line, text) in SCRIPT.lines().enumerate() {
+            for character in 0..=text.len() {
+                let position = lsp::Position {
+                    line: line as u32,
+                    character: character as u32,
+                };
+                let containing: Vec<lsp::Range> = nodes
+                    .iter()
+                    .filter(|node| {
+                        !matches!(node, walk::Node::Package(_))
+                    })
+                    .map(|node| &node.base().location)
+                    .filter(|location| {
+                        !convert::is_synthesized(location)
+                            && convert::location_contains(
+                                location, &position,
+                            )
+                    })
+                    .map(convert::location_to_range)
+                    .collect();
+
+                let found =
+                    find(&pkg, position.line, position.character);
+                match found {
+                    None => assert!(
+                        containing.is_empty(),
+                        "{:?}",
+                        position
+                    ),
+                    Some(found) => {
+                        let range = convert::location_to_range(
+                            &found.node.base().location,
+                        );
+                        assert!(
+                            crate::lsp::position_in_range(
+                                &position, &range
+                            ),
+                            "{:?} not in {:?}",
+                            position,
+                            range
+                        );
+                        for other in containing.iter() {
+                            assert!(
+                                crate::lsp::range_is_narrower_or_equal(
+                                    &range, other
+                                ),
+                                "{:?}: {:?} is narrower than {:?}",
+                                position,
+                                other,
+                                range
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Every found node's parent chain only holds nodes enclosing it.
+    #[test]
+    fn parents_enclose_node() {
+        let pkg = package();
+        for (line, text) in SCRIPT.lines().enumerate() {
+            for character in 0..=text.len() {
+                let mut node =
+                    find(&pkg, line as u32, character as u32);
+                while let Some(current) = node {
+                    let range = convert::location_to_range(
+                        &current.node.base().location,
+                    );
+                    if let Some(parent) = &current.parent {
+                        let parent_range = convert::location_to_range(
+                            &parent.node.base().location,
+                        );
+                        assert!(parent_range.start <= range.start);
+                        assert!(range.end <= parent_range.end);
+                    }
+                    node = current.parent.map(|parent| *parent);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn finds_pipe_operator() {
+        let pkg = package();
+
+        let node = find(&pkg, 3, 5).unwrap();
+
+        assert!(matches!(node.node, walk::Node::PipeExpr(_)));
+    }
+
+    #[test]
+    fn finds_member_property() {
+        let pkg = package();
+
+        let node = find(&pkg, 4, 30).unwrap();
+
+        match node.node {
+            walk::Node::Identifier(ident) => {
+                assert_eq!("_value", ident.name)
+            }
+            other => panic!("expected identifier, found {:?}", other),
+        }
+        assert!(matches!(
+            node.parent.unwrap().node,
+            walk::Node::MemberExpr(_)
+        ));
+    }
+
+    #[test]
+    fn finds_string_literal() {
+        let pkg = package();
+
+        let node = find(&pkg, 2, 21).unwrap();
+
+        assert!(matches!(node.node, walk::Node::StringLit(_)));
+    }
+
+    #[test]
+    fn finds_identifier_at_end() {
+        let pkg = package();
+
+        // Right after the `z` of `return z * 2`
+        let node = find(&pkg, 9, 12).unwrap();
+
+        match node.node {
+            walk::Node::Identifier(ident) => {
+                assert_eq!("z", ident.name)
+            }
+            other => panic!("expected identifier, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn finds_nothing_past_the_end() {
+        let pkg = package();
+
+        assert!(find(&pkg, 42, 0).is_none());
+    }
+}
@@ -1,20 +1,14 @@
 use flux::ast::SourceLocation;
-use flux::semantic::nodes::Expression;
+use flux::semantic::nodes::{Expression, Symbol};
 use flux::semantic::types::MonoType;
 use flux::semantic::walk::{Node, Visitor};
 use lspower::lsp;
 
 use crate::completion::CompletionFunction;
+use crate::convert;
 
 fn defined_after(loc: &SourceLocation, pos: lsp::Position) -> bool {
-    if loc.start.line > pos.line + 1
-        || (loc.start.line == pos.line + 1
-            && loc.start.column > pos.character + 1)
-    {
-        return true;
-    }
-
-    false
+    convert::starts_after(loc, &pos)
 }
 
 pub struct FunctionFinderVisitor {
@@ -77,13 +71,26 @@ pub struct ObjectFunction {
     pub function: CompletionFunction,
 }
 
-#[derive(Default)]
 pub struct ObjectFunctionFinderVisitor {
+    pub pos: lsp::Position,
     pub results: Vec<ObjectFunction>,
 }
 
+impl ObjectFunctionFinderVisitor {
+    pub fn new(pos: lsp::Position) -> Self {
+        ObjectFunctionFinderVisitor {
+            pos,
+            results: vec![],
+        }
+    }
+}
+
 impl<'a> Visitor<'a> for ObjectFunctionFinderVisitor {
     fn visit(&mut self, node: Node<'a>) -> bool {
+        if defined_after(node.loc(), self.pos) {
+            return false;
+        }
+
         match node {
             Node::VariableAssgn(assignment) => {
                 let object_name = &assignment.id.name;
@@ -142,3 +149,109 @@ impl<'a> Visitor<'a> for ObjectFunctionFinderVisitor {
         true
     }
 }
+
+/// Where a variable in scope at a position is declared, from the nearest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum VariableScope {
+    /// A parameter or variable of a function the position is in.
+    Local,
+    /// A variable of the file.
+    Global,
+}
+
+/// Finds the variables in scope at a position of a file: the parameters and variables
+/// of the functions it is in, and the variables of the file declared before it.
+///
+/// Variables are found outermost first, so a variable shadowing another comes after it.
+pub struct ScopedVariableFinderVisitor<'a> {
+    pos: lsp::Position,
+    file: Option<String>,
+    /// The functions the position is in, outermost first.
+    functions: Vec<&'a SourceLocation>,
+    pub variables: Vec<(&'a Symbol, Option<MonoType>, VariableScope)>,
+}
+
+impl<'a> ScopedVariableFinderVisitor<'a> {
+    pub fn new(pos: lsp::Position, file: Option<String>) -> Self {
+        Self {
+            pos,
+            file,
+            functions: vec![],
+            variables: vec![],
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for ScopedVariableFinderVisitor<'a> {
+    fn visit(&mut self, node: Node<'a>) -> bool {
+        match node {
+            Node::File(file) => {
+                return file.loc.file == self.file;
+            }
+            Node::FunctionExpr(function) => {
+                // The parameters and variables of other functions aren't in scope.
+                if !convert::location_contains(
+                    &function.loc,
+                    &self.pos,
+                ) {
+                    return false;
+                }
+                self.functions.push(&function.loc);
+                for param in &function.params {
+                    let typ = match &function.typ {
+                        MonoType::Fun(fun) => fun
+                            .req
+                            .get(param.key.name.as_str())
+                            .cloned()
+                            .or_else(|| {
+                                fun.opt
+                                    .get(param.key.name.as_str())
+                                    .map(|arg| arg.typ.clone())
+                            })
+                            .or_else(|| {
+                                fun.pipe
+                                    .as_ref()
+                                    .filter(|pipe| {
+                                        pipe.k
+                                            == param.key.name.as_str()
+                                    })
+                                    .map(|pipe| pipe.v.clone())
+                            }),
+                        _ => None,
+                    };
+                    self.variables.push((
+                        &param.key.name,
+                        typ,
+                        VariableScope::Local,
+                    ));
+                }
+            }
+            // Variables declared after the position, including the one being declared
+            // at it, aren't in scope.
+            Node::VariableAssgn(assignment)
+                if defined_after(&assignment.loc, self.pos)
+                    || convert::location_contains(
+                        &assignment.loc,
+                        &self.pos,
+                    ) => {}
+            Node::VariableAssgn(assignment) => {
+                let start =
+                    convert::position_to_lsp(&assignment.loc.start);
+                let scope = if self.functions.iter().any(|function| {
+                    convert::location_contains(function, &start)
+                }) {
+                    VariableScope::Local
+                } else {
+                    VariableScope::Global
+                };
+                self.variables.push((
+                    &assignment.id.name,
+                    Some(assignment.init.type_of()),
+                    scope,
+                ));
+            }
+            _ => {}
+        }
+        true
+    }
+}
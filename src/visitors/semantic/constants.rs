@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use flux::ast::{LogicalOperator, Operator};
+use flux::semantic::nodes::Expression;
+use flux::semantic::walk::{Node, Visitor};
+
+/// The value of an expression that could be computed without running the script.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConstantValue {
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+}
+
+impl fmt::Display for ConstantValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConstantValue::Int(value) => write!(f, "{}", value),
+            // Make sure floats always look like floats, e.g. `2.0` rather than `2`.
+            ConstantValue::Float(value) => write!(f, "{:?}", value),
+            ConstantValue::String(value) => write!(f, "{:?}", value),
+            ConstantValue::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Evaluate an expression built from literals, operators and known constants.
+///
+/// Anything that would require actually running flux (calls, member access, etc.)
+/// or that would fail at runtime (overflow, division by zero) evaluates to `None`.
+pub fn evaluate(
+    expr: &Expression,
+    constants: &HashMap<String, ConstantValue>,
+) -> Option<ConstantValue> {
+    use ConstantValue::{Bool, Float, Int, String};
+
+    match expr {
+        Expression::Integer(lit) => Some(Int(lit.value)),
+        Expression::Float(lit) => Some(Float(lit.value)),
+        Expression::StringLit(lit) => Some(String(lit.value.clone())),
+        Expression::Boolean(lit) => Some(Bool(lit.value)),
+        Expression::Identifier(ident) => {
+            constants.get(ident.name.as_str()).cloned()
+        }
+        Expression::Unary(unary) => {
+            match (
+                &unary.operator,
+                evaluate(&unary.argument, constants)?,
+            ) {
+                (Operator::SubtractionOperator, Int(value)) => {
+                    value.checked_neg().map(Int)
+                }
+                (Operator::SubtractionOperator, Float(value)) => {
+                    Some(Float(-value))
+                }
+                (
+                    Operator::AdditionOperator,
+                    value @ (Int(_) | Float(_)),
+                ) => Some(value),
+                (Operator::NotOperator, Bool(value)) => {
+                    Some(Bool(!value))
+                }
+                _ => None,
+            }
+        }
+        Expression::Logical(logical) => {
+            match (
+                &logical.operator,
+                evaluate(&logical.left, constants)?,
+                evaluate(&logical.right, constants)?,
+            ) {
+                (
+                    LogicalOperator::AndOperator,
+                    Bool(left),
+                    Bool(right),
+                ) => Some(Bool(left && right)),
+                (
+                    LogicalOperator::OrOperator,
+                    Bool(left),
+                    Bool(right),
+                ) => Some(Bool(left || right)),
+                _ => None,
+            }
+        }
+        Expression::Binary(binary) => evaluate_binary(
+            &binary.operator,
+            evaluate(&binary.left, constants)?,
+            evaluate(&binary.right, constants)?,
+        ),
+        _ => None,
+    }
+}
+
+fn evaluate_binary(
+    operator: &Operator,
+    left: ConstantValue,
+    right: ConstantValue,
+) -> Option<ConstantValue> {
+    use ConstantValue::{Bool, Float, Int, String};
+
+    let value = match (operator, left, right) {
+        (Operator::AdditionOperator, Int(l), Int(r)) => {
+            Int(l.checked_add(r)?)
+        }
+        (Operator::SubtractionOperator, Int(l), Int(r)) => {
+            Int(l.checked_sub(r)?)
+        }
+        (Operator::MultiplicationOperator, Int(l), Int(r)) => {
+            Int(l.checked_mul(r)?)
+        }
+        (Operator::DivisionOperator, Int(l), Int(r)) => {
+            Int(l.checked_div(r)?)
+        }
+        (Operator::ModuloOperator, Int(l), Int(r)) => {
+            Int(l.checked_rem(r)?)
+        }
+        (Operator::PowerOperator, Int(l), Int(r)) => {
+            Int(l.checked_pow(u32::try_from(r).ok()?)?)
+        }
+
+        (Operator::AdditionOperator, Float(l), Float(r)) => {
+            Float(l + r)
+        }
+        (Operator::SubtractionOperator, Float(l), Float(r)) => {
+            Float(l - r)
+        }
+        (Operator::MultiplicationOperator, Float(l), Float(r)) => {
+            Float(l * r)
+        }
+        (Operator::DivisionOperator, Float(l), Float(r)) => {
+            Float(l / r)
+        }
+        (Operator::ModuloOperator, Float(l), Float(r)) => {
+            Float(l % r)
+        }
+        (Operator::PowerOperator, Float(l), Float(r)) => {
+            Float(l.powf(r))
+        }
+
+        (Operator::AdditionOperator, String(l), String(r)) => {
+            String(l + &r)
+        }
+
+        (Operator::EqualOperator, l, r) => Bool(l == r),
+        (Operator::NotEqualOperator, l, r) => Bool(l != r),
+        (Operator::LessThanOperator, Int(l), Int(r)) => Bool(l < r),
+        (Operator::LessThanEqualOperator, Int(l), Int(r)) => {
+            Bool(l <= r)
+        }
+        (Operator::GreaterThanOperator, Int(l), Int(r)) => {
+            Bool(l > r)
+        }
+        (Operator::GreaterThanEqualOperator, Int(l), Int(r)) => {
+            Bool(l >= r)
+        }
+        (Operator::LessThanOperator, Float(l), Float(r)) => {
+            Bool(l < r)
+        }
+        (Operator::LessThanEqualOperator, Float(l), Float(r)) => {
+            Bool(l <= r)
+        }
+        (Operator::GreaterThanOperator, Float(l), Float(r)) => {
+            Bool(l > r)
+        }
+        (Operator::GreaterThanEqualOperator, Float(l), Float(r)) => {
+            Bool(l >= r)
+        }
+        _ => return None,
+    };
+
+    match value {
+        Float(value) if !value.is_finite() => None,
+        value => Some(value),
+    }
+}
+
+/// Compute the values of package level variables assigned from constant expressions.
+///
+/// Only top level assignments are considered. Variables inside of functions depend on
+/// their arguments and may shadow package level names, so they are never folded.
+#[derive(Default)]
+pub struct ConstantEvaluatorVisitor {
+    pub constants: HashMap<String, ConstantValue>,
+}
+
+impl<'a> Visitor<'a> for ConstantEvaluatorVisitor {
+    fn visit(&mut self, node: Node<'a>) -> bool {
+        match node {
+            Node::Package(_) | Node::File(_) => true,
+            Node::VariableAssgn(assignment) => {
+                let name = assignment.id.name.as_str().to_string();
+                match evaluate(&assignment.init, &self.constants) {
+                    Some(value) => {
+                        self.constants.insert(name, value);
+                    }
+                    None => {
+                        // A later non-constant assignment replaces the name.
+                        self.constants.remove(&name);
+                    }
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+}
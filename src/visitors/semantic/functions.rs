@@ -6,6 +6,8 @@ use flux::semantic::types::MonoType;
 use flux::semantic::walk::{Node, Visitor};
 use lspower::lsp;
 
+use crate::convert;
+
 pub struct FunctionInfo {
     pub name: String,
     pub package_name: String,
@@ -54,14 +56,7 @@ fn is_before_position(
     loc: &SourceLocation,
     pos: lsp::Position,
 ) -> bool {
-    if loc.start.line > pos.line + 1
-        || (loc.start.line == pos.line + 1
-            && loc.start.column > pos.character + 1)
-    {
-        return false;
-    }
-
-    true
+    !convert::starts_after(loc, &pos)
 }
 
 impl<'a> Visitor<'a> for FunctionFinderVisitor {
@@ -1,6 +1,11 @@
+use flux::semantic::nodes::{
+    Expression, FunctionParameter, VariableAssgn,
+};
 use flux::semantic::walk::Node as WalkNode;
 use lspower::lsp;
 
+use crate::{convert, diagnostics};
+
 pub struct ExperimentalDiagnosticVisitor {
     namespaces: Vec<String>,
     pub diagnostics: Vec<(Option<String>, lsp::Diagnostic)>,
@@ -54,8 +59,9 @@ impl<'a> flux::semantic::walk::Visitor<'a>
                     flux::semantic::nodes::Expression::Identifier(id) => {
                         if self.namespaces.contains(&format!("{}", id.name)) {
                             self.diagnostics.push((expr.loc.file.clone(), lsp::Diagnostic {
-                                range: expr.loc.clone().into(),
+                                range: convert::location_to_range(&expr.loc),
                                 severity: Some(lsp::DiagnosticSeverity::HINT),
+                                code: Some(lsp::NumberOrString::String(diagnostics::EXPERIMENTAL.into())),
                                 message: "experimental features can change often or be deleted/moved. Use with caution.".into(),
                                 ..lsp::Diagnostic::default()
                             }));
@@ -65,8 +71,9 @@ impl<'a> flux::semantic::walk::Visitor<'a>
                         if let flux::semantic::nodes::Expression::Identifier(id) = &member.object {
                             if self.namespaces.contains(&format!("{}", id.name)) {
                                 self.diagnostics.push((expr.loc.file.clone(), lsp::Diagnostic {
-                                    range: expr.loc.clone().into(),
+                                    range: convert::location_to_range(&expr.loc),
                                     severity: Some(lsp::DiagnosticSeverity::HINT),
+                                    code: Some(lsp::NumberOrString::String(diagnostics::EXPERIMENTAL.into())),
                                     message: "experimental features can change often or be deleted/moved. Use with caution.".into(),
                                     ..lsp::Diagnostic::default()
                                 }));
@@ -128,8 +135,9 @@ impl<'a> flux::semantic::walk::Visitor<'a>
                     flux::semantic::nodes::Expression::Identifier(id) => {
                         if self.namespaces.contains(&format!("{}", id.name)) {
                             self.diagnostics.push((id.loc.file.clone(), lsp::Diagnostic {
-                                range: expr.loc.clone().into(),
+                                range: convert::location_to_range(&expr.loc),
                                 severity: Some(lsp::DiagnosticSeverity::HINT),
+                                code: Some(lsp::NumberOrString::String(diagnostics::CONTRIB.into())),
                                 message: "contrib packages are user-contributed, and do not carry with them the same compatibility guarantees as the standard library. Use with caution.".into(),
                                 ..lsp::Diagnostic::default()
                             }));
@@ -139,8 +147,9 @@ impl<'a> flux::semantic::walk::Visitor<'a>
                         if let flux::semantic::nodes::Expression::Identifier(id) = &member.object {
                             if self.namespaces.contains(&id.name.to_string()) {
                                 self.diagnostics.push((id.loc.file.clone(), lsp::Diagnostic {
-                                    range: expr.loc.clone().into(),
+                                    range: convert::location_to_range(&expr.loc),
                                     severity: Some(lsp::DiagnosticSeverity::HINT),
+                                    code: Some(lsp::NumberOrString::String(diagnostics::CONTRIB.into())),
                                     message: "contrib packages are user-contributed, and do not carry with them the same compatibility guarantees as the standard library. Use with caution.".into(),
                                     ..lsp::Diagnostic::default()
                                 }));
@@ -177,8 +186,9 @@ impl<'a> flux::semantic::walk::Visitor<'a>
         if let WalkNode::VariableAssgn(assign) = node {
             if self.names.contains(&assign.id.name.to_string()) {
                 self.diagnostics.push((assign.loc.file.clone(), lsp::Diagnostic {
-                    range: assign.id.loc.clone().into(),
+                    range: convert::location_to_range(&assign.id.loc),
                     severity: Some(lsp::DiagnosticSeverity::WARNING),
+                    code: Some(lsp::NumberOrString::String(diagnostics::INFLUXDB_IDENTIFIER.into())),
                     message: format!("Avoid using `{}` as an identifier name. In some InfluxDB contexts, it may be provided at runtime.", assign.id.name),
                     ..lsp::Diagnostic::default()
                 }));
@@ -187,3 +197,75 @@ impl<'a> flux::semantic::walk::Visitor<'a>
         true
     }
 }
+
+/// Finds whether an identifier is used, e.g. a parameter in the body of its function.
+struct UsageVisitor<'a> {
+    name: &'a str,
+    used: bool,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a> for UsageVisitor<'_> {
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        if let WalkNode::IdentifierExpr(ident) = node {
+            if ident.name == self.name {
+                self.used = true;
+            }
+        }
+        !self.used
+    }
+}
+
+/// Finds the parameters of functions assigned to variables that their body never uses.
+///
+/// Pipe parameters, the conventional row parameter `r` and parameters prefixed with
+/// `_` are left alone, as a signature requires them whether they are used or not.
+#[derive(Default)]
+pub struct UnusedParameterVisitor<'a> {
+    /// The names of variables passed as arguments, whose function has the signature
+    /// of the parameter it's passed as.
+    pub passed: Vec<&'a str>,
+    pub unused: Vec<(&'a VariableAssgn, &'a FunctionParameter)>,
+}
+
+impl<'a> flux::semantic::walk::Visitor<'a>
+    for UnusedParameterVisitor<'a>
+{
+    fn visit(&mut self, node: WalkNode<'a>) -> bool {
+        match node {
+            WalkNode::VariableAssgn(assign) => {
+                if let Expression::Function(function) = &assign.init {
+                    for param in &function.params {
+                        let name = param.key.name.as_str();
+                        if param.is_pipe
+                            || name == "r"
+                            || name.starts_with('_')
+                        {
+                            continue;
+                        }
+                        let mut visitor =
+                            UsageVisitor { name, used: false };
+                        flux::semantic::walk::walk(
+                            &mut visitor,
+                            WalkNode::Block(&function.body),
+                        );
+                        if !visitor.used {
+                            self.unused.push((assign, param));
+                        }
+                    }
+                }
+            }
+            WalkNode::CallExpr(call) => {
+                self.passed.extend(call.arguments.iter().filter_map(
+                    |argument| match &argument.value {
+                        Expression::Identifier(ident) => {
+                            Some(ident.name.as_str())
+                        }
+                        _ => None,
+                    },
+                ));
+            }
+            _ => {}
+        }
+        true
+    }
+}
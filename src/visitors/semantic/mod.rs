@@ -1,22 +1,28 @@
+use flux::ast::SourceLocation;
 use flux::semantic::{
-    nodes::{Expression, Symbol},
+    nodes::{Block, CallExpr, Expression, FunctionExpr, Symbol},
     walk::{self, Node, Visitor},
 };
 use lspower::lsp;
 
+use crate::convert;
+
 mod completion;
+mod constants;
 mod functions;
 mod lint;
 mod symbols;
 
 pub use completion::{
     FunctionFinderVisitor, ObjectFunctionFinderVisitor,
+    ScopedVariableFinderVisitor, VariableScope,
 };
+pub use constants::{ConstantEvaluatorVisitor, ConstantValue};
 pub use lint::{
     ContribDiagnosticVisitor, ExperimentalDiagnosticVisitor,
-    InfluxDBIdentifierDiagnosticVisitor,
+    InfluxDBIdentifierDiagnosticVisitor, UnusedParameterVisitor,
 };
-pub use symbols::SymbolsVisitor;
+pub use symbols::{document_symbols, SymbolsVisitor};
 
 fn contains_position(node: Node<'_>, pos: lsp::Position) -> bool {
     if let Node::Package(_) = node {
@@ -24,31 +30,16 @@ fn contains_position(node: Node<'_>, pos: lsp::Position) -> bool {
         // a start/end location of 0:0.
         return false;
     }
-    let range: lsp::Range = node.loc().clone().into();
-
-    if pos.line < range.start.line {
-        return false;
-    }
-
-    if pos.line > range.end.line {
-        return false;
-    }
-
-    if pos.line == range.start.line
-        && pos.character < range.start.character
-    {
-        return false;
-    }
-
-    if pos.line == range.end.line
-        && pos.character > range.end.character
-    {
-        return false;
-    }
-
-    true
+    !convert::is_synthesized(node.loc())
+        && convert::location_contains(node.loc(), &pos)
 }
 
+/// Finds the narrowest node containing a position.
+///
+/// Nodes aren't always walked in source order (e.g. the pipe argument of a call is
+/// walked after the call itself), so a node is only selected if it is no wider than
+/// the node found so far. Nodes of equal width are nested, so the later, deeper one
+/// wins. `path` holds the selected nodes, from the outermost to `node`.
 #[derive(Debug)]
 pub struct NodeFinderVisitor<'a> {
     pub node: Option<Node<'a>>,
@@ -58,9 +49,18 @@ pub struct NodeFinderVisitor<'a> {
 
 impl<'a> Visitor<'a> for NodeFinderVisitor<'a> {
     fn visit(&mut self, node: Node<'a>) -> bool {
-        let contains = contains_position(node, self.position);
+        if !contains_position(node, self.position) {
+            return true;
+        }
 
-        if contains {
+        let narrower = match self.node {
+            Some(current) => crate::lsp::range_is_narrower_or_equal(
+                &convert::location_to_range(node.loc()),
+                &convert::location_to_range(current.loc()),
+            ),
+            None => true,
+        };
+        if narrower {
             self.path.push(node);
             self.node = Some(node);
         }
@@ -79,14 +79,44 @@ impl<'a> NodeFinderVisitor<'a> {
     }
 }
 
+/// Whether a function defines a name, as a parameter or in its body. The definition
+/// shadows those of the same name outside of the function.
+pub fn defines_name(function: &FunctionExpr, name: &Symbol) -> bool {
+    if function.params.iter().any(|param| &param.key.name == name) {
+        return true;
+    }
+    let mut block = &function.body;
+    loop {
+        match block {
+            Block::Variable(assignment, next) => {
+                if &assignment.id.name == name {
+                    return true;
+                }
+                block = next;
+            }
+            Block::Expr(_, next) => block = next,
+            Block::Return(_) => return false,
+        }
+    }
+}
+
 pub struct IdentFinderVisitor<'a> {
     pub name: Symbol,
     pub identifiers: Vec<walk::Node<'a>>,
+    /// Whether to skip the functions shadowing the name, whose identifiers refer to
+    /// another definition.
+    skip_shadowed: bool,
 }
 
 impl<'a> Visitor<'a> for IdentFinderVisitor<'a> {
     fn visit(&mut self, node: walk::Node<'a>) -> bool {
         match node {
+            walk::Node::FunctionExpr(function)
+                if self.skip_shadowed
+                    && defines_name(function, &self.name) =>
+            {
+                return false;
+            }
             walk::Node::MemberExpr(m) => {
                 if let Expression::Identifier(i) = &m.object {
                     if i.name == self.name {
@@ -116,6 +146,16 @@ impl<'a> IdentFinderVisitor<'a> {
         IdentFinderVisitor {
             name,
             identifiers: vec![],
+            skip_shadowed: false,
+        }
+    }
+
+    /// Find the identifiers referring to a definition at the top level, skipping the
+    /// functions that shadow it.
+    pub fn unshadowed(name: Symbol) -> IdentFinderVisitor<'a> {
+        IdentFinderVisitor {
+            skip_shadowed: true,
+            ..IdentFinderVisitor::new(name)
         }
     }
 }
@@ -163,6 +203,124 @@ impl<'a> DefinitionFinderVisitor<'a> {
     }
 }
 
+/// Find the piped call expression at a given range.
+///
+/// `a |> f()` becomes a call to `f` with `a` as its pipe argument in the semantic graph,
+/// located at `f()` in the source.
+pub struct PipedCallFinderVisitor<'a> {
+    file: Option<String>,
+    range: lsp::Range,
+    pub call: Option<&'a CallExpr>,
+}
+
+impl<'a> PipedCallFinderVisitor<'a> {
+    pub fn new(location: &SourceLocation) -> Self {
+        Self {
+            file: location.file.clone(),
+            range: convert::location_to_range(location),
+            call: None,
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for PipedCallFinderVisitor<'a> {
+    fn visit(&mut self, node: Node<'a>) -> bool {
+        if self.call.is_some() {
+            return false;
+        }
+        if let Node::CallExpr(call) = node {
+            if call.pipe.is_some()
+                && call.loc.file == self.file
+                && convert::location_to_range(&call.loc) == self.range
+            {
+                self.call = Some(call);
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Finds the call expression at a location of the source.
+pub struct CallFinderVisitor<'a> {
+    file: Option<String>,
+    range: lsp::Range,
+    pub call: Option<&'a CallExpr>,
+}
+
+impl<'a> CallFinderVisitor<'a> {
+    pub fn new(location: &SourceLocation) -> Self {
+        Self {
+            file: location.file.clone(),
+            range: convert::location_to_range(location),
+            call: None,
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for CallFinderVisitor<'a> {
+    fn visit(&mut self, node: Node<'a>) -> bool {
+        if self.call.is_some() {
+            return false;
+        }
+        if let Node::CallExpr(call) = node {
+            if call.loc.file == self.file
+                && convert::location_to_range(&call.loc) == self.range
+            {
+                self.call = Some(call);
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Finds where the variables of a file holding values, rather than functions, are
+/// assigned and read, in the order they are walked.
+pub struct VariableFinderVisitor<'a> {
+    file: Option<String>,
+    names: Vec<&'a Symbol>,
+    pub variables: Vec<(&'a Symbol, &'a SourceLocation)>,
+}
+
+impl<'a> VariableFinderVisitor<'a> {
+    pub fn new(file: Option<String>) -> Self {
+        Self {
+            file,
+            names: vec![],
+            variables: vec![],
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for VariableFinderVisitor<'a> {
+    fn visit(&mut self, node: Node<'a>) -> bool {
+        match node {
+            Node::File(file) => {
+                // Variables of other files can't be in view.
+                return file.loc.file == self.file;
+            }
+            Node::VariableAssgn(assignment)
+                if !matches!(
+                    assignment.init,
+                    Expression::Function(_)
+                ) =>
+            {
+                self.names.push(&assignment.id.name);
+                self.variables
+                    .push((&assignment.id.name, &assignment.id.loc));
+            }
+            Node::IdentifierExpr(ident)
+                if self.names.contains(&&ident.name) =>
+            {
+                self.variables.push((&ident.name, &ident.loc));
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
 #[derive(Default)]
 pub struct FoldFinderVisitor<'a> {
     pub nodes: Vec<Node<'a>>,
@@ -218,17 +376,183 @@ impl<'a> Visitor<'a> for ImportFinderVisitor {
     }
 }
 
-#[derive(Default)]
-pub struct PackageNodeFinderVisitor {
-    pub location: Option<lsp::Range>,
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"import "strings"
+
+data = from(bucket: "b")
+    |> range(start: -1h)
+    |> filter(fn: (r) => r._value > 10 and strings.hasPrefix(v: r.host, prefix: "a"))
+
+f = (x, y=1) => {
+    z = x + y
+
+    return z * 2
 }
+"#;
 
-impl<'a> Visitor<'a> for PackageNodeFinderVisitor {
-    fn visit(&mut self, node: Node<'a>) -> bool {
-        if let Node::PackageClause(n) = node {
-            self.location = Some(n.loc.clone().into());
-            return false;
+    #[derive(Default)]
+    struct AllNodesVisitor<'a> {
+        nodes: Vec<Node<'a>>,
+    }
+
+    impl<'a> Visitor<'a> for AllNodesVisitor<'a> {
+        fn visit(&mut self, node: Node<'a>) -> bool {
+            self.nodes.push(node);
+            true
         }
-        true
+    }
+
+    fn package() -> flux::semantic::nodes::Package {
+        let ast_pkg =
+            flux::parser::parse_string("script.flux".into(), SCRIPT);
+        let mut analyzer = flux::new_semantic_analyzer(
+            flux::semantic::AnalyzerConfig::default(),
+        )
+        .unwrap();
+        let (_, pkg) = analyzer.analyze_ast(&ast_pkg.into()).unwrap();
+        pkg
+    }
+
+    fn find(
+        pkg: &flux::semantic::nodes::Package,
+        line: u32,
+        character: u32,
+    ) -> NodeFinderVisitor<'_> {
+        crate::walk_semantic_package!(
+            NodeFinderVisitor::new(lsp::Position { line, character }),
+            pkg
+        )
+    }
+
+    /// For every position in the script, the node found contains the position, no
+    /// other node containing the position is narrower, and the path ends with it.
+    #[test]
+    fn finds_narrowest_node_everywhere() {
+        let pkg = package();
+        let nodes = crate::walk_semantic_package!(
+            AllNodesVisitor::default(),
+            pkg
+        )
+        .nodes;
+
+        for (line, text) in SCRIPT.lines().enumerate() {
+            for character in 0..=text.len() {
+                let position = lsp::Position {
+                    line: line as u32,
+                    character: character as u32,
+                };
+                let containing: Vec<lsp::Range> = nodes
+                    .iter()
+                    .filter(|node| {
+                        contains_position(**node, position)
+                    })
+                    .map(|node| {
+                        convert::location_to_range(node.loc())
+                    })
+                    .collect();
+
+                let visitor =
+                    find(&pkg, position.line, position.character);
+                match visitor.node {
+                    None => {
+                        assert!(
+                            containing.is_empty(),
+                            "{:?}",
+                            position
+                        );
+                        assert!(visitor.path.is_empty());
+                    }
+                    Some(found) => {
+                        let range =
+                            convert::location_to_range(found.loc());
+                        assert!(
+                            crate::lsp::position_in_range(
+                                &position, &range
+                            ),
+                            "{:?} not in {:?}",
+                            position,
+                            range
+                        );
+                        for other in containing.iter() {
+                            assert!(
+                                crate::lsp::range_is_narrower_or_equal(
+                                    &range, other
+                                ),
+                                "{:?}: {:?} is narrower than {:?}",
+                                position,
+                                other,
+                                range
+                            );
+                        }
+                        assert_eq!(
+                            Some(range),
+                            visitor.path.last().map(|node| {
+                                convert::location_to_range(node.loc())
+                            })
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn finds_member_expression_on_property() {
+        let pkg = package();
+
+        let visitor = find(&pkg, 4, 30);
+
+        match visitor.node.unwrap() {
+            Node::MemberExpr(member) => {
+                assert_eq!("_value", member.property)
+            }
+            other => panic!(
+                "expected member expression, found {:?}",
+                other
+            ),
+        }
+    }
+
+    #[test]
+    fn finds_identifier_expression() {
+        let pkg = package();
+
+        // The `x` of `z = x + y`
+        let visitor = find(&pkg, 7, 8);
+
+        match visitor.node.unwrap() {
+            Node::IdentifierExpr(ident) => {
+                assert_eq!("x", ident.name.as_str())
+            }
+            other => panic!("expected identifier, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn finds_assignment_identifier_with_path() {
+        let pkg = package();
+
+        // The `z` of `z = x + y`
+        let visitor = find(&pkg, 7, 4);
+
+        assert!(matches!(visitor.node, Some(Node::Identifier(_))));
+        assert!(matches!(
+            visitor.path[visitor.path.len() - 2],
+            Node::VariableAssgn(_)
+        ));
+    }
+
+    #[test]
+    fn finds_nothing_past_the_end() {
+        let pkg = package();
+
+        let visitor = find(&pkg, 42, 0);
+
+        assert!(visitor.node.is_none());
+        assert!(visitor.path.is_empty());
     }
 }
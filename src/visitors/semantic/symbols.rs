@@ -4,6 +4,8 @@ use flux::semantic::nodes::{self, Expression};
 use flux::semantic::walk::{Node, Visitor};
 use lspower::lsp;
 
+use crate::convert;
+
 fn parse_variable_assignment(
     uri: lsp::Url,
     node: Node,
@@ -15,7 +17,7 @@ fn parse_variable_assignment(
             name: va.id.name.to_string(),
             location: lsp::Location {
                 uri: uri.clone(),
-                range: node.loc().clone().into(),
+                range: convert::location_to_range(node.loc()),
             },
             tags: None,
             deprecated: None,
@@ -28,7 +30,7 @@ fn parse_variable_assignment(
                 name: param.key.name.to_string(),
                 location: lsp::Location {
                     uri: uri.clone(),
-                    range: param.loc.into(),
+                    range: convert::location_to_range(&param.loc),
                 },
                 tags: None,
                 deprecated: None,
@@ -42,7 +44,7 @@ fn parse_variable_assignment(
             name: va.id.name.to_string(),
             location: lsp::Location {
                 uri,
-                range: node.loc().clone().into(),
+                range: convert::location_to_range(node.loc()),
             },
             tags: None,
             deprecated: None,
@@ -62,7 +64,7 @@ fn parse_call_expression(
                 name: ident.name.to_string(),
                 location: lsp::Location {
                     uri: uri.clone(),
-                    range: c.loc.clone().into(),
+                    range: convert::location_to_range(&c.loc),
                 },
                 tags: None,
                 deprecated: None,
@@ -81,7 +83,7 @@ fn parse_call_expression(
                     name: arg.key.name.to_string(),
                     location: lsp::Location {
                         uri: uri.clone(),
-                        range: arg.loc.into(),
+                        range: convert::location_to_range(&arg.loc),
                     },
                     tags: None,
                     deprecated: None,
@@ -93,7 +95,7 @@ fn parse_call_expression(
                     name: arg.key.name.to_string(),
                     location: lsp::Location {
                         uri: uri.clone(),
-                        range: arg.loc.into(),
+                        range: convert::location_to_range(&arg.loc),
                     },
                     tags: None,
                     deprecated: None,
@@ -116,7 +118,7 @@ fn parse_binary_expression(
                 name: ident.name.to_string(),
                 location: lsp::Location {
                     uri: uri.clone(),
-                    range: ident.loc.into(),
+                    range: convert::location_to_range(&ident.loc),
                 },
                 tags: None,
                 deprecated: None,
@@ -131,7 +133,7 @@ fn parse_binary_expression(
                 name: ident.name.to_string(),
                 location: lsp::Location {
                     uri,
-                    range: ident.loc.into(),
+                    range: convert::location_to_range(&ident.loc),
                 },
                 tags: None,
                 deprecated: None,
@@ -147,6 +149,90 @@ fn parse_binary_expression(
     .collect()
 }
 
+/// The properties of a record, along with those of the records nested in it.
+fn parse_record_properties(
+    uri: &lsp::Url,
+    object: &nodes::ObjectExpr,
+    container: &str,
+) -> Vec<lsp::SymbolInformation> {
+    object
+        .properties
+        .iter()
+        .flat_map(|property| {
+            let name = property.key.name.to_string();
+            let nested = match &property.value {
+                Expression::Object(object) => {
+                    parse_record_properties(uri, object, &name)
+                }
+                _ => vec![],
+            };
+            std::iter::once(lsp::SymbolInformation {
+                kind: lsp::SymbolKind::FIELD,
+                name,
+                location: lsp::Location {
+                    uri: uri.clone(),
+                    range: convert::location_to_range(&property.loc),
+                },
+                tags: None,
+                deprecated: None,
+                container_name: Some(container.into()),
+            })
+            .chain(nested)
+        })
+        .collect()
+}
+
+/// The name of an option, e.g. `task` or `influxdb.defaultHost`, along with the
+/// range of that name and the value the option is set to.
+fn option_assignment(
+    opt: &nodes::OptionStmt,
+) -> (String, lsp::Range, &Expression) {
+    match &opt.assignment {
+        nodes::Assignment::Variable(va) => (
+            va.id.name.to_string(),
+            convert::location_to_range(&va.id.loc),
+            &va.init,
+        ),
+        nodes::Assignment::Member(ma) => (
+            match &ma.member.object {
+                Expression::Identifier(ident) => {
+                    format!("{}.{}", ident.name, ma.member.property)
+                }
+                _ => ma.member.property.to_string(),
+            },
+            convert::location_to_range(&ma.member.loc),
+            &ma.init,
+        ),
+    }
+}
+
+/// The symbol of an option, along with the properties of the record it's set to.
+fn parse_option_statement(
+    uri: lsp::Url,
+    opt: &nodes::OptionStmt,
+) -> Vec<lsp::SymbolInformation> {
+    let (name, _, init) = option_assignment(opt);
+    let properties = match init {
+        Expression::Object(object) => {
+            parse_record_properties(&uri, object, &name)
+        }
+        _ => vec![],
+    };
+    std::iter::once(lsp::SymbolInformation {
+        kind: lsp::SymbolKind::PROPERTY,
+        name,
+        location: lsp::Location {
+            uri,
+            range: convert::location_to_range(&opt.loc),
+        },
+        tags: None,
+        deprecated: None,
+        container_name: None,
+    })
+    .chain(properties)
+    .collect()
+}
+
 pub struct SymbolsVisitor<'a> {
     pub symbols: Vec<lsp::SymbolInformation>,
     pub uri: lsp::Url,
@@ -176,6 +262,27 @@ impl<'a> Visitor<'a> for SymbolsVisitor<'a> {
         self.path.push(node);
 
         match node {
+            // The assignment and values of an option are summed up by its symbols.
+            Node::OptionStmt(opt) => {
+                self.symbols.extend(parse_option_statement(uri, opt));
+                return false;
+            }
+            Node::BuiltinStmt(builtin) => {
+                self.symbols.push(lsp::SymbolInformation {
+                    kind: lsp::SymbolKind::INTERFACE,
+                    name: builtin.id.name.to_string(),
+                    location: lsp::Location {
+                        uri,
+                        range: convert::location_to_range(
+                            &builtin.loc,
+                        ),
+                    },
+                    tags: None,
+                    deprecated: None,
+                    container_name: None,
+                });
+                return false;
+            }
             Node::VariableAssgn(va) => {
                 let list = parse_variable_assignment(uri, node, va);
 
@@ -204,7 +311,9 @@ impl<'a> Visitor<'a> for SymbolsVisitor<'a> {
                         name: source,
                         location: lsp::Location {
                             uri,
-                            range: me.loc.clone().into(),
+                            range: convert::location_to_range(
+                                &me.loc,
+                            ),
                         },
                         tags: None,
                         deprecated: None,
@@ -218,7 +327,7 @@ impl<'a> Visitor<'a> for SymbolsVisitor<'a> {
                     name: num.value.to_string(),
                     location: lsp::Location {
                         uri,
-                        range: num.loc.clone().into(),
+                        range: convert::location_to_range(&num.loc),
                     },
                     tags: None,
                     deprecated: None,
@@ -232,7 +341,7 @@ impl<'a> Visitor<'a> for SymbolsVisitor<'a> {
                     name: num.value.to_string(),
                     location: lsp::Location {
                         uri,
-                        range: num.loc.clone().into(),
+                        range: convert::location_to_range(&num.loc),
                     },
                     tags: None,
                     deprecated: None,
@@ -246,7 +355,7 @@ impl<'a> Visitor<'a> for SymbolsVisitor<'a> {
                     name: d.value.to_string(),
                     location: lsp::Location {
                         uri,
-                        range: d.loc.clone().into(),
+                        range: convert::location_to_range(&d.loc),
                     },
                     tags: None,
                     deprecated: None,
@@ -260,7 +369,7 @@ impl<'a> Visitor<'a> for SymbolsVisitor<'a> {
                     name: b.value.to_string(),
                     location: lsp::Location {
                         uri,
-                        range: b.loc.clone().into(),
+                        range: convert::location_to_range(&b.loc),
                     },
                     tags: None,
                     deprecated: None,
@@ -274,7 +383,7 @@ impl<'a> Visitor<'a> for SymbolsVisitor<'a> {
                     name: s.value.clone(),
                     location: lsp::Location {
                         uri,
-                        range: s.loc.clone().into(),
+                        range: convert::location_to_range(&s.loc),
                     },
                     tags: None,
                     deprecated: None,
@@ -288,7 +397,7 @@ impl<'a> Visitor<'a> for SymbolsVisitor<'a> {
                     name: String::from("[]"),
                     location: lsp::Location {
                         uri,
-                        range: a.loc.clone().into(),
+                        range: convert::location_to_range(&a.loc),
                     },
                     tags: None,
                     deprecated: None,
@@ -300,3 +409,214 @@ impl<'a> Visitor<'a> for SymbolsVisitor<'a> {
         true
     }
 }
+
+fn document_symbol(
+    name: String,
+    kind: lsp::SymbolKind,
+    range: lsp::Range,
+    selection_range: lsp::Range,
+    children: Vec<lsp::DocumentSymbol>,
+) -> lsp::DocumentSymbol {
+    lsp::DocumentSymbol {
+        name,
+        detail: None,
+        kind,
+        tags: None,
+        deprecated: None,
+        range,
+        selection_range,
+        children: if children.is_empty() {
+            None
+        } else {
+            Some(children)
+        },
+    }
+}
+
+/// The name of the function a call calls, e.g. `filter` or `strings.toUpper`.
+fn callee_name(call: &nodes::CallExpr) -> Option<String> {
+    match &call.callee {
+        Expression::Identifier(ident) => Some(ident.name.to_string()),
+        Expression::Member(member) => match &member.object {
+            Expression::Identifier(ident) => {
+                Some(format!("{}.{}", ident.name, member.property))
+            }
+            _ => Some(member.property.to_string()),
+        },
+        _ => None,
+    }
+}
+
+/// The calls of a pipeline, from its first stage to its last.
+fn pipeline_stages(expr: &Expression) -> Vec<&nodes::CallExpr> {
+    match expr {
+        Expression::Call(call) => {
+            let mut stages = match &call.pipe {
+                Some(pipe) => pipeline_stages(pipe),
+                None => vec![],
+            };
+            stages.push(call);
+            stages
+        }
+        _ => vec![],
+    }
+}
+
+/// A symbol for every stage of a pipeline.
+///
+/// A piped call is located at the call itself, so each stage only spans its own
+/// call rather than the pipeline up to it.
+fn stage_symbols(expr: &Expression) -> Vec<lsp::DocumentSymbol> {
+    pipeline_stages(expr)
+        .into_iter()
+        .filter_map(|call| {
+            Some(document_symbol(
+                callee_name(call)?,
+                lsp::SymbolKind::FUNCTION,
+                convert::location_to_range(&call.loc),
+                convert::location_to_range(call.callee.loc()),
+                vec![],
+            ))
+        })
+        .collect()
+}
+
+/// The symbols of the properties of a record, with those of nested records as children.
+fn record_symbols(
+    object: &nodes::ObjectExpr,
+) -> Vec<lsp::DocumentSymbol> {
+    object
+        .properties
+        .iter()
+        .map(|property| {
+            document_symbol(
+                property.key.name.to_string(),
+                lsp::SymbolKind::FIELD,
+                convert::location_to_range(&property.loc),
+                convert::location_to_range(&property.key.loc),
+                match &property.value {
+                    Expression::Object(object) => {
+                        record_symbols(object)
+                    }
+                    _ => vec![],
+                },
+            )
+        })
+        .collect()
+}
+
+/// The symbols nested in the value of an assignment: the parameters and body of a
+/// function, the properties of a record, or the stages of a pipeline.
+fn value_symbols(init: &Expression) -> Vec<lsp::DocumentSymbol> {
+    match init {
+        Expression::Function(function) => {
+            let mut symbols: Vec<lsp::DocumentSymbol> = function
+                .params
+                .iter()
+                .map(|param| {
+                    document_symbol(
+                        param.key.name.to_string(),
+                        lsp::SymbolKind::VARIABLE,
+                        convert::location_to_range(&param.loc),
+                        convert::location_to_range(&param.key.loc),
+                        vec![],
+                    )
+                })
+                .collect();
+            let mut block = &function.body;
+            loop {
+                match block {
+                    nodes::Block::Variable(assignment, next) => {
+                        symbols.push(assignment_symbol(assignment));
+                        block = next;
+                    }
+                    nodes::Block::Expr(statement, next) => {
+                        symbols.extend(stage_symbols(
+                            &statement.expression,
+                        ));
+                        block = next;
+                    }
+                    nodes::Block::Return(statement) => {
+                        symbols.extend(value_symbols(
+                            &statement.argument,
+                        ));
+                        break;
+                    }
+                }
+            }
+            symbols
+        }
+        Expression::Object(object) => record_symbols(object),
+        _ => stage_symbols(init),
+    }
+}
+
+fn assignment_symbol(
+    va: &nodes::VariableAssgn,
+) -> lsp::DocumentSymbol {
+    document_symbol(
+        va.id.name.to_string(),
+        match va.init {
+            Expression::Function(_) => lsp::SymbolKind::FUNCTION,
+            _ => lsp::SymbolKind::VARIABLE,
+        },
+        convert::location_to_range(&va.loc),
+        convert::location_to_range(&va.id.loc),
+        value_symbols(&va.init),
+    )
+}
+
+/// The outline of a file, for clients that show symbols as a hierarchy.
+///
+/// Each symbol spans the whole construct, e.g. an assignment with its value, while its
+/// selection range is the name it's known by. Editors rely on the former to tell which
+/// symbols enclose the cursor, e.g. for breadcrumbs, and on the latter to reveal it.
+/// Pipelines that aren't assigned are named after their stages.
+pub fn document_symbols(
+    file: &nodes::File,
+) -> Vec<lsp::DocumentSymbol> {
+    file.body
+        .iter()
+        .filter_map(|statement| match statement {
+            nodes::Statement::Variable(va) => {
+                Some(assignment_symbol(va))
+            }
+            nodes::Statement::Option(opt) => {
+                let (name, selection_range, init) =
+                    option_assignment(opt);
+                Some(document_symbol(
+                    name,
+                    lsp::SymbolKind::PROPERTY,
+                    convert::location_to_range(&opt.loc),
+                    selection_range,
+                    value_symbols(init),
+                ))
+            }
+            nodes::Statement::Builtin(builtin) => {
+                Some(document_symbol(
+                    builtin.id.name.to_string(),
+                    lsp::SymbolKind::INTERFACE,
+                    convert::location_to_range(&builtin.loc),
+                    convert::location_to_range(&builtin.id.loc),
+                    vec![],
+                ))
+            }
+            nodes::Statement::Expr(statement) => {
+                let stages = stage_symbols(&statement.expression);
+                let first = stages.first()?;
+                Some(document_symbol(
+                    stages
+                        .iter()
+                        .map(|stage| stage.name.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(" |> "),
+                    lsp::SymbolKind::OPERATOR,
+                    convert::location_to_range(&statement.loc),
+                    first.selection_range,
+                    stages.clone(),
+                ))
+            }
+            _ => None,
+        })
+        .collect()
+}
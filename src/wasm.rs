@@ -22,6 +22,15 @@ pub fn initLog() {
     wasm_logger::init(wasm_logger::Config::new(Level::Info));
 }
 
+/// Load the flux stdlib and prepare completion ahead of time.
+///
+/// The stdlib is otherwise loaded when the server first needs it, which makes the
+/// first requests slow. Call this once the page is idle to get that out of the way.
+#[wasm_bindgen]
+pub fn preload() {
+    crate::completion::preload();
+}
+
 /// Parse flux into an AST representation. The AST will be generated regardless
 /// of valid flux. As such, no error handling is needed.
 #[deprecated]
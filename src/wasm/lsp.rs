@@ -1,17 +1,120 @@
+use std::any::Any;
+use std::cell::RefCell;
 use std::mem;
+use std::rc::Rc;
+use std::sync::Once;
 
+use crate::server::protocol_ext::{
+    CrashedNotification, CrashedParams,
+};
+use crate::trace::{Direction, TraceEntry};
 use crate::LspServer;
 use futures::prelude::*;
 use log::Level;
+use lspower::lsp::notification::Notification;
 use lspower::{LspService, MessageStream};
 use tower_service::Service;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::future_to_promise;
 use wasm_logger;
 
+/// The JSON-RPC error code of internal errors.
+const INTERNAL_ERROR: i64 = -32603;
+
+thread_local! {
+    /// The message handlers of every server, told when a server panics.
+    ///
+    /// A panic aborts the WebAssembly instance when unwinding isn't supported, so the
+    /// panic hook is the only place left to tell the host, which can then restart.
+    static CRASH_HANDLERS: RefCell<Vec<js_sys::Function>> = RefCell::new(vec![]);
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+/// Chain a hook telling message handlers about panics to the current panic hook.
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            let notification = serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": CrashedNotification::METHOD,
+                "params": CrashedParams {
+                    message: info.to_string(),
+                },
+            })
+            .to_string();
+            // The handlers may be borrowed if a handler itself panicked.
+            let _ = CRASH_HANDLERS.try_with(|handlers| {
+                if let Ok(handlers) = handlers.try_borrow() {
+                    for handler in handlers.iter() {
+                        let _ = handler.call1(
+                            &JsValue::UNDEFINED,
+                            &notification.as_str().into(),
+                        );
+                    }
+                }
+            });
+        }));
+    });
+}
+
+/// The message of a panic caught while handling a message.
+fn panic_message(err: Box<dyn Any + Send>) -> String {
+    err.downcast::<String>().map(|s| *s).unwrap_or_else(|err| {
+        err.downcast::<&str>()
+            .ok()
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| "Unknown panic occurred".to_string())
+    })
+}
+
+/// The JSON-RPC response to a request whose handling panicked.
+fn internal_error(id: &serde_json::Value, message: &str) -> String {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": INTERNAL_ERROR,
+            "message": format!("Internal error: {}", message),
+        },
+    })
+    .to_string()
+}
+
+/// The handlers given every message to and from the server, as lines of a trace.
+type TraceHandlers = Rc<RefCell<Vec<js_sys::Function>>>;
+
+/// Call the trace handlers with a message.
+fn trace(handlers: &TraceHandlers, direction: Direction, msg: &str) {
+    let handlers = handlers.borrow();
+    if handlers.is_empty() {
+        return;
+    }
+    let line = match serde_json::to_string(&TraceEntry::new(
+        direction,
+        msg.as_bytes(),
+    )) {
+        Ok(line) => line,
+        Err(err) => {
+            log::error!("failed to JSON encode trace entry: {}", err);
+            return;
+        }
+    };
+    for handler in handlers.iter() {
+        if let Err(err) =
+            handler.call1(&JsValue::UNDEFINED, &line.as_str().into())
+        {
+            log::error!("{:?}", err);
+        }
+    }
+}
+
 // MessageProcessor calls handlers for recieved messages.
 struct MessageProcessor {
     handlers: Vec<js_sys::Function>,
+    trace_handlers: TraceHandlers,
     messages: MessageStream,
     running: bool,
 }
@@ -44,6 +147,7 @@ impl MessageProcessor {
         if !self.running {
             panic!("Attempted to fire message handlers while server is not running")
         }
+        trace(&self.trace_handlers, Direction::Out, msg);
         for handler in self.handlers.iter() {
             // Set the context to `undefined` explicitly, so the error
             // message on `this` usage is clear.
@@ -61,6 +165,7 @@ impl MessageProcessor {
 pub struct Lsp {
     processor: Option<MessageProcessor>,
     service: LspService,
+    trace_handlers: TraceHandlers,
 }
 
 impl Default for Lsp {
@@ -69,18 +174,22 @@ impl Default for Lsp {
         console_error_panic_hook::set_once();
 
         wasm_logger::init(wasm_logger::Config::new(Level::Info));
+        install_panic_hook();
 
         let (service, messages) =
             lspower::LspService::new(|client| {
                 LspServer::new(Some(client))
             });
+        let trace_handlers = TraceHandlers::default();
         Lsp {
             processor: Some(MessageProcessor {
                 handlers: vec![],
+                trace_handlers: trace_handlers.clone(),
                 messages,
                 running: false,
             }),
             service,
+            trace_handlers,
         }
     }
 }
@@ -96,13 +205,35 @@ impl Lsp {
     /// All handlers must be attached before server.run is called.
     #[allow(non_snake_case)]
     pub fn onMessage(&mut self, func: js_sys::Function) {
+        CRASH_HANDLERS.with(|handlers| {
+            handlers.borrow_mut().push(func.clone())
+        });
         if let Some(processor) = &mut self.processor {
             processor.on_message(func)
         }
     }
 
+    /// Attach a handler given every message to and from the server, as a line of
+    /// JSON with the time it was seen and its direction, `in` or `out`.
+    ///
+    /// The lines are those the command line server writes with `--trace-file`, so a
+    /// trace from a browser can be replayed the same way.
+    #[allow(non_snake_case)]
+    pub fn onTrace(&mut self, func: js_sys::Function) {
+        self.trace_handlers.borrow_mut().push(func);
+    }
+
     /// Send a message to the server.
+    ///
+    /// A request whose handling panics is answered with an internal error, and the
+    /// message handlers get a `flux/crashed` notification.
     pub fn send(&mut self, msg: String) -> js_sys::Promise {
+        trace(&self.trace_handlers, Direction::In, &msg);
+        let trace_handlers =
+            std::panic::AssertUnwindSafe(self.trace_handlers.clone());
+        let id = serde_json::from_str::<serde_json::Value>(&msg)
+            .ok()
+            .and_then(|value| value.get("id").cloned());
         let message: lspower::jsonrpc::Incoming =
             match serde_json::from_str(&msg) {
                 Ok(value) => value,
@@ -125,6 +256,11 @@ impl Lsp {
                             match serde_json::to_string(&result_inner)
                             {
                                 Ok(msg) => {
+                                    trace(
+                                        &trace_handlers,
+                                        Direction::Out,
+                                        &msg,
+                                    );
                                     // Return message JSON
                                     Ok(JsValue::from(msg))
                                 }
@@ -145,20 +281,16 @@ impl Lsp {
                 }
             }
             .catch_unwind()
-            .unwrap_or_else(|err| {
-                Err(JsValue::from({
-                    err.downcast::<String>()
-                        .map(|s| *s)
-                        .unwrap_or_else(|err| {
-                            err.downcast::<&str>()
-                                .ok()
-                                .map(|s| s.to_string())
-                                .unwrap_or_else(|| {
-                                    "Unknown panic occurred"
-                                        .to_string()
-                                })
-                        })
-                }))
+            .unwrap_or_else(move |err| {
+                let message = panic_message(err);
+                match id {
+                    Some(id) => {
+                        Ok(JsValue::from(internal_error(&id, &message)))
+                    }
+                    // Notifications have no response to carry the error, the
+                    // crashed notification tells about it.
+                    None => Ok(JsValue::UNDEFINED),
+                }
             }),
         )
     }
@@ -190,19 +322,7 @@ impl Lsp {
             }
             .catch_unwind()
             .unwrap_or_else(|err| {
-                Err(JsValue::from({
-                    err.downcast::<String>()
-                        .map(|s| *s)
-                        .unwrap_or_else(|err| {
-                            err.downcast::<&str>()
-                                .ok()
-                                .map(|s| s.to_string())
-                                .unwrap_or_else(|| {
-                                    "Unknown panic occurred"
-                                        .to_string()
-                                })
-                        })
-                }))
+                Err(JsValue::from(panic_message(err)))
             }),
         )
     }